@@ -0,0 +1,82 @@
+/*
+ * C interface to the gramine-sealing-key-provider guest client.
+ *
+ * Link with -lskp_client (libskp_client.so or libskp_client.a). Functions
+ * return SKP_OK or a negative SKP_ERR_* code; skp_last_error() describes the
+ * last failure on the calling thread.
+ */
+#ifndef SKP_CLIENT_H
+#define SKP_CLIENT_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SKP_OK 0
+#define SKP_ERR_INVALID_ARGUMENT (-1)
+#define SKP_ERR_IO (-2)
+#define SKP_ERR_ATTESTATION (-3)
+#define SKP_ERR_PROTOCOL (-4)
+#define SKP_ERR_REFUSED (-5)
+#define SKP_ERR_BINDING (-6)
+#define SKP_ERR_CRYPTO (-7)
+#define SKP_ERR_BUFFER_TOO_SMALL (-8)
+#define SKP_ERR_PANIC (-9)
+
+#define SKP_REPORT_DATA_LEN 64
+
+/* One key request: a fresh keypair and nonce. */
+typedef struct skp_exchange skp_exchange;
+
+/* Measurements a response must come from. Each field is nullable, but at
+ * least one must be set or every response is refused. */
+typedef struct skp_expected_provider {
+    const uint8_t *mr_enclave; /* 32 bytes */
+    const uint8_t *mr_signer;  /* 32 bytes */
+    const uint8_t *mr_td;      /* 48 bytes */
+} skp_expected_provider;
+
+/* Never null; valid until the next call on the same thread. */
+const char *skp_last_error(void);
+
+/* Fetches the collateral provider quotes are verified against from the PCCS
+ * at `url` instead of Intel PCS, for the whole process. Null goes back to
+ * Intel PCS. */
+int skp_set_pccs_url(const char *url);
+
+/* Runs the whole exchange, quoting through /dev/attestation or configfs-tsm,
+ * and verifies the provider quote as skp_exchange_open does. `expected` is
+ * nullable. On SKP_ERR_BUFFER_TOO_SMALL, *key_len is the size needed. */
+int skp_request_key(const char *address, const skp_expected_provider *expected,
+                    uint8_t *key_out, size_t key_cap, size_t *key_len);
+
+/* Step by step, for agents that quote or connect themselves. */
+skp_exchange *skp_exchange_new(void);
+void skp_exchange_free(skp_exchange *exchange);
+
+/* Writes the SKP_REPORT_DATA_LEN bytes the guest quote must carry. */
+int skp_exchange_report_data(const skp_exchange *exchange, uint8_t *report_data);
+
+/* Builds the request body for `quote`; send it after a 4-byte big-endian
+ * length. Free *request with skp_buffer_free(*request, *request_len). */
+int skp_exchange_request(const skp_exchange *exchange, const uint8_t *quote,
+                         size_t quote_len, bool explain, uint8_t **request,
+                         size_t *request_len);
+
+/* Verifies the provider's response body, with the provider quote checked
+ * against collateral from Intel PCS or the PCCS, and decrypts the key. */
+int skp_exchange_open(const skp_exchange *exchange, const uint8_t *response,
+                      size_t response_len, const skp_expected_provider *expected,
+                      uint8_t *key_out, size_t key_cap, size_t *key_len);
+
+void skp_buffer_free(uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SKP_CLIENT_H */
@@ -0,0 +1,455 @@
+//! C ABI for the guest client, declared in `include/skp_client.h`.
+//!
+//! Functions return `SKP_OK` (0) or a negative `SKP_ERR_*` code; the message
+//! of the last failure on the calling thread is available from
+//! `skp_last_error`. Pointers marked nullable in the header may be null, all
+//! others must be valid for the given lengths.
+
+use gramine_sealing_key_client::{
+    attestation, ClientError, DerivedKey, Exchange, ExpectedProvider, PcsCollateral,
+    SealingKeyClient,
+};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+pub const SKP_OK: c_int = 0;
+pub const SKP_ERR_INVALID_ARGUMENT: c_int = -1;
+pub const SKP_ERR_IO: c_int = -2;
+pub const SKP_ERR_ATTESTATION: c_int = -3;
+pub const SKP_ERR_PROTOCOL: c_int = -4;
+pub const SKP_ERR_REFUSED: c_int = -5;
+pub const SKP_ERR_BINDING: c_int = -6;
+pub const SKP_ERR_CRYPTO: c_int = -7;
+pub const SKP_ERR_BUFFER_TOO_SMALL: c_int = -8;
+pub const SKP_ERR_PANIC: c_int = -9;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// PCCS that collateral for provider quotes comes from, if not Intel PCS.
+static PCCS_URL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Measurements a response must come from; each field is nullable, but at
+/// least one must be set.
+#[repr(C)]
+pub struct SkpExpectedProvider {
+    /// 32 bytes
+    pub mr_enclave: *const u8,
+    /// 32 bytes
+    pub mr_signer: *const u8,
+    /// 48 bytes
+    pub mr_td: *const u8,
+}
+
+enum Failure {
+    InvalidArgument(&'static str),
+    BufferTooSmall(usize),
+    Client(ClientError),
+}
+
+impl From<ClientError> for Failure {
+    fn from(e: ClientError) -> Self {
+        Failure::Client(e)
+    }
+}
+
+impl Failure {
+    fn code(&self) -> c_int {
+        match self {
+            Failure::InvalidArgument(_) => SKP_ERR_INVALID_ARGUMENT,
+            Failure::BufferTooSmall(_) => SKP_ERR_BUFFER_TOO_SMALL,
+            Failure::Client(e) => match e {
+                ClientError::ConfigError(_) => SKP_ERR_INVALID_ARGUMENT,
+                ClientError::IOError(_) | ClientError::CollateralError(_) => SKP_ERR_IO,
+                ClientError::AttestationError(_) => SKP_ERR_ATTESTATION,
+                ClientError::ProtocolError(_) | ClientError::JsonError(_) => SKP_ERR_PROTOCOL,
+                ClientError::Refused | ClientError::Rejected(_) => SKP_ERR_REFUSED,
+                ClientError::BindingError(_) => SKP_ERR_BINDING,
+                ClientError::CryptoError(_) | ClientError::KeyEpochChanged { .. } => SKP_ERR_CRYPTO,
+            },
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Failure::InvalidArgument(what) => format!("Invalid argument: {}", what),
+            Failure::BufferTooSmall(needed) => format!("Key buffer needs {} bytes", needed),
+            Failure::Client(e) => e.to_string(),
+        }
+    }
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// Runs `f`, turning its failure or panic into an error code. Panics must not
+/// unwind into C.
+fn call(f: impl FnOnce() -> Result<(), Failure>) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => SKP_OK,
+        Ok(Err(failure)) => {
+            set_last_error(failure.message());
+            failure.code()
+        }
+        Err(_) => {
+            set_last_error("Internal error (panic)".into());
+            SKP_ERR_PANIC
+        }
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Failure> {
+    if data.is_null() {
+        return Err(Failure::InvalidArgument("null buffer"));
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+unsafe fn measurement<const N: usize>(data: *const u8) -> Option<[u8; N]> {
+    if data.is_null() {
+        return None;
+    }
+    let mut value = [0u8; N];
+    value.copy_from_slice(slice::from_raw_parts(data, N));
+    Some(value)
+}
+
+unsafe fn expected(expected: *const SkpExpectedProvider) -> ExpectedProvider {
+    let Some(expected) = expected.as_ref() else {
+        return ExpectedProvider::default();
+    };
+    ExpectedProvider {
+        mr_enclave: measurement(expected.mr_enclave),
+        mr_signer: measurement(expected.mr_signer),
+        mr_td: measurement(expected.mr_td),
+    }
+}
+
+/// Where collateral for verifying provider quotes comes from.
+fn collateral() -> PcsCollateral {
+    let pccs_url = PCCS_URL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    pccs_url
+        .as_deref()
+        .map_or_else(PcsCollateral::default, PcsCollateral::pccs)
+}
+
+/// Copies the key to the caller's buffer, or reports the size it needs.
+unsafe fn write_key(
+    key: &DerivedKey,
+    key_out: *mut u8,
+    key_cap: usize,
+    key_len: *mut usize,
+) -> Result<(), Failure> {
+    if key_out.is_null() || key_len.is_null() {
+        return Err(Failure::InvalidArgument("null key buffer"));
+    }
+    let key = key.expose();
+    *key_len = key.len();
+    if key.len() > key_cap {
+        return Err(Failure::BufferTooSmall(key.len()));
+    }
+    ptr::copy_nonoverlapping(key.as_ptr(), key_out, key.len());
+    Ok(())
+}
+
+/// Message of the last failed call on this thread. Valid until the next call
+/// on the same thread; never null.
+#[no_mangle]
+pub extern "C" fn skp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Fetches the collateral that provider quotes are verified against from the
+/// PCCS at `url` instead of Intel PCS, for every later call in the process.
+/// Null goes back to Intel PCS.
+///
+/// # Safety
+/// `url` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn skp_set_pccs_url(url: *const c_char) -> c_int {
+    call(|| {
+        let url = if url.is_null() {
+            None
+        } else {
+            let url = CStr::from_ptr(url)
+                .to_str()
+                .map_err(|_| Failure::InvalidArgument("PCCS URL is not UTF-8"))?;
+            Some(url.to_string())
+        };
+        *PCCS_URL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = url;
+        Ok(())
+    })
+}
+
+/// Starts a key request with a fresh keypair and nonce. Returns null on
+/// failure.
+#[no_mangle]
+pub extern "C" fn skp_exchange_new() -> *mut Exchange {
+    let mut exchange = ptr::null_mut();
+    call(|| {
+        exchange = Box::into_raw(Box::new(Exchange::new()?));
+        Ok(())
+    });
+    exchange
+}
+
+/// Frees an exchange and wipes its secret key. Accepts null.
+///
+/// # Safety
+/// `exchange` must come from `skp_exchange_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn skp_exchange_free(exchange: *mut Exchange) {
+    if !exchange.is_null() {
+        drop(Box::from_raw(exchange));
+    }
+}
+
+/// Writes the 64 bytes of report data the guest quote must carry.
+///
+/// # Safety
+/// `report_data` must have room for 64 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn skp_exchange_report_data(
+    exchange: *const Exchange,
+    report_data: *mut u8,
+) -> c_int {
+    call(|| {
+        let exchange = exchange
+            .as_ref()
+            .ok_or(Failure::InvalidArgument("null exchange"))?;
+        if report_data.is_null() {
+            return Err(Failure::InvalidArgument("null report data buffer"));
+        }
+        ptr::copy_nonoverlapping(exchange.report_data().as_ptr(), report_data, 64);
+        Ok(())
+    })
+}
+
+/// Builds the request frame body for `quote`. The caller sends it after a
+/// 4-byte big-endian length and frees it with `skp_buffer_free`.
+///
+/// # Safety
+/// `quote` must be valid for `quote_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn skp_exchange_request(
+    exchange: *const Exchange,
+    quote: *const u8,
+    quote_len: usize,
+    explain: bool,
+    request: *mut *mut u8,
+    request_len: *mut usize,
+) -> c_int {
+    call(|| {
+        let exchange = exchange
+            .as_ref()
+            .ok_or(Failure::InvalidArgument("null exchange"))?;
+        if request.is_null() || request_len.is_null() {
+            return Err(Failure::InvalidArgument("null request pointer"));
+        }
+        let body = exchange
+            .request(bytes(quote, quote_len)?, explain)?
+            .into_boxed_slice();
+        *request_len = body.len();
+        *request = Box::into_raw(body).cast();
+        Ok(())
+    })
+}
+
+/// Verifies the provider's response frame body, with the provider quote
+/// checked against collateral from Intel PCS or the PCCS set with
+/// `skp_set_pccs_url`, and decrypts the key into
+/// `key_out`. On `SKP_ERR_BUFFER_TOO_SMALL`, `key_len` holds the size needed.
+///
+/// # Safety
+/// `response` must be valid for `response_len` bytes and `key_out` for
+/// `key_cap`; `expected_provider` is nullable, but without it every
+/// response is refused.
+#[no_mangle]
+pub unsafe extern "C" fn skp_exchange_open(
+    exchange: *const Exchange,
+    response: *const u8,
+    response_len: usize,
+    expected_provider: *const SkpExpectedProvider,
+    key_out: *mut u8,
+    key_cap: usize,
+    key_len: *mut usize,
+) -> c_int {
+    call(|| {
+        let exchange = exchange
+            .as_ref()
+            .ok_or(Failure::InvalidArgument("null exchange"))?;
+        let key = exchange.open(
+            bytes(response, response_len)?,
+            &expected(expected_provider),
+            &collateral(),
+        )?;
+        write_key(&key, key_out, key_cap, key_len)
+    })
+}
+
+/// Runs the whole exchange with the provider at `address` (`host:port`),
+/// quoting through `/dev/attestation` or configfs-tsm. The provider quote is
+/// verified as `skp_exchange_open` does.
+///
+/// # Safety
+/// `address` must be a NUL-terminated string and `key_out` valid for
+/// `key_cap` bytes; `expected_provider` is nullable, but without it every
+/// response is refused.
+#[no_mangle]
+pub unsafe extern "C" fn skp_request_key(
+    address: *const c_char,
+    expected_provider: *const SkpExpectedProvider,
+    key_out: *mut u8,
+    key_cap: usize,
+    key_len: *mut usize,
+) -> c_int {
+    call(|| {
+        if address.is_null() {
+            return Err(Failure::InvalidArgument("null address"));
+        }
+        let address = CStr::from_ptr(address)
+            .to_str()
+            .map_err(|_| Failure::InvalidArgument("address is not UTF-8"))?;
+        let source = attestation::detect()?;
+        let key = SealingKeyClient::new(address)
+            .expect(expected(expected_provider))
+            .collateral(collateral())
+            .request_key(source.as_ref())?;
+        write_key(&key, key_out, key_cap, key_len)
+    })
+}
+
+/// Frees a buffer returned by this library. Accepts null.
+///
+/// # Safety
+/// `data` and `len` must come from the same call of this library.
+#[no_mangle]
+pub unsafe extern "C" fn skp_buffer_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_embeds_the_quote_and_bad_responses_set_the_error() {
+        let exchange = skp_exchange_new();
+        assert!(!exchange.is_null());
+        let mut request = ptr::null_mut();
+        let mut request_len = 0;
+        let mut key = [0u8; 32];
+        let mut key_len = 0;
+
+        unsafe {
+            let status = skp_exchange_request(
+                exchange,
+                b"quote".as_ptr(),
+                5,
+                false,
+                &mut request,
+                &mut request_len,
+            );
+            assert_eq!(status, SKP_OK);
+            let body: serde_json::Value =
+                serde_json::from_slice(slice::from_raw_parts(request, request_len)).unwrap();
+            assert_eq!(body["quote"], serde_json::json!(b"quote"));
+            skp_buffer_free(request, request_len);
+
+            let status = skp_exchange_open(
+                exchange,
+                b"{}".as_ptr(),
+                2,
+                ptr::null(),
+                key.as_mut_ptr(),
+                key.len(),
+                &mut key_len,
+            );
+            assert_eq!(status, SKP_ERR_PROTOCOL);
+            assert!(!CStr::from_ptr(skp_last_error()).to_bytes().is_empty());
+            skp_exchange_free(exchange);
+        }
+    }
+
+    #[test]
+    fn forged_provider_quotes_are_rejected() {
+        use gramine_sealing_key_client::binding::expected_report_data;
+        use gramine_sealing_key_client::protocol::QuoteResponse;
+
+        let exchange = skp_exchange_new();
+        let mut request = ptr::null_mut();
+        let mut request_len = 0;
+        let mut key = [0u8; 32];
+        let mut key_len = 0;
+
+        unsafe {
+            // Nothing listens on port 1 of the loopback address, so no
+            // collateral vouches for the quote
+            let pccs = CString::new("https://127.0.0.1:1").unwrap();
+            assert_eq!(skp_set_pccs_url(pccs.as_ptr()), SKP_OK);
+
+            skp_exchange_request(
+                exchange,
+                b"quote".as_ptr(),
+                5,
+                false,
+                &mut request,
+                &mut request_len,
+            );
+            let body: serde_json::Value =
+                serde_json::from_slice(slice::from_raw_parts(request, request_len)).unwrap();
+            skp_buffer_free(request, request_len);
+            let nonce: Vec<u8> = serde_json::from_value(body["nonce"].clone()).unwrap();
+
+            // A genuine quote made to carry the report data of the response,
+            // which its signature no longer covers
+            let mut response = serde_json::json!({
+                "suite": "x25519-sealedbox",
+                "encrypted_key": [1, 2, 3],
+                "provider_quote": [],
+                "key_confirmation": vec![0u8; 32],
+            });
+            let parsed: QuoteResponse = serde_json::from_value(response.clone()).unwrap();
+            let mut quote = include_bytes!("../../quotes/tdxQuote.txt").to_vec();
+            // The TD report's report data follows the 48-byte header and
+            // 520 bytes of the report
+            quote[568..632].copy_from_slice(&expected_report_data(&parsed, &nonce));
+            response["provider_quote"] = serde_json::json!(quote);
+            let response = serde_json::to_vec(&response).unwrap();
+            // Its MRTD follows the header and 136 bytes of the report
+            let expected = SkpExpectedProvider {
+                mr_enclave: ptr::null(),
+                mr_signer: ptr::null(),
+                mr_td: quote[184..232].as_ptr(),
+            };
+
+            let status = skp_exchange_open(
+                exchange,
+                response.as_ptr(),
+                response.len(),
+                &expected,
+                key.as_mut_ptr(),
+                key.len(),
+                &mut key_len,
+            );
+            assert_eq!(status, SKP_ERR_IO);
+            assert_eq!(key_len, 0);
+            assert_eq!(key, [0; 32]);
+            assert_eq!(skp_set_pccs_url(ptr::null()), SKP_OK);
+            skp_exchange_free(exchange);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! libcryptsetup token plugin for the `gramine-skp` tokens `skp-luks bind`
+//! stores. Installed as `libcryptsetup-token-gramine-skp.so` in
+//! libcryptsetup's token directory, it lets `cryptsetup open --token-only`
+//! and systemd-cryptsetup, which tries every token of a volume without a
+//! key file, fetch the passphrase from the providers in the token.
+//!
+//! The entry points are those of libcryptsetup's external token ABI. They
+//! return 0 or a negative errno, and never unwind into C.
+
+use gramine_sealing_key_client::luks::{self, Token};
+use gramine_sealing_key_client::ClientError;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use zeroize::Zeroize;
+
+const CRYPT_LOG_NORMAL: c_int = 0;
+const CRYPT_LOG_ERROR: c_int = 1;
+
+/// `struct crypt_device`, only ever handled by pointer.
+#[repr(C)]
+pub struct CryptDevice {
+    _private: [u8; 0],
+}
+
+#[link(name = "cryptsetup")]
+extern "C" {
+    fn crypt_token_json_get(cd: *mut CryptDevice, token: c_int, json: *mut *const c_char) -> c_int;
+    fn crypt_log(cd: *mut CryptDevice, level: c_int, msg: *const c_char);
+}
+
+fn log(cd: *mut CryptDevice, level: c_int, message: &str) {
+    if let Ok(message) = CString::new(format!("{}\n", message)) {
+        unsafe { crypt_log(cd, level, message.as_ptr()) };
+    }
+}
+
+/// The errno libcryptsetup expects for `e`. `-EPERM` tells it the token
+/// cannot unlock the volume, so systemd-cryptsetup falls back to asking
+/// for a passphrase.
+fn errno(e: &ClientError) -> c_int {
+    match e {
+        ClientError::ConfigError(_) | ClientError::JsonError(_) => -libc::EINVAL,
+        ClientError::IOError(_) | ClientError::CollateralError(_) => -libc::EIO,
+        ClientError::Refused | ClientError::Rejected(_) | ClientError::KeyEpochChanged { .. } => {
+            -libc::EPERM
+        }
+        _ => -libc::ENOENT,
+    }
+}
+
+fn token_json(cd: *mut CryptDevice, token: c_int) -> Result<Token, c_int> {
+    let mut json = ptr::null();
+    let result = unsafe { crypt_token_json_get(cd, token, &mut json) };
+    if result < 0 {
+        return Err(result);
+    }
+    let json = unsafe { CStr::from_ptr(json) };
+    Token::from_json(json.to_bytes()).map_err(|e| errno(&e))
+}
+
+#[no_mangle]
+pub extern "C" fn cryptsetup_token_version() -> *const c_char {
+    c"0.1.0".as_ptr()
+}
+
+/// Fetches the passphrase into a buffer that `cryptsetup_token_buffer_free`
+/// releases.
+///
+/// # Safety
+/// libcryptsetup's contract for `crypt_token_open_func`.
+#[no_mangle]
+pub unsafe extern "C" fn cryptsetup_token_open(
+    cd: *mut CryptDevice,
+    token: c_int,
+    buffer: *mut *mut c_char,
+    buffer_len: *mut usize,
+    _usrptr: *mut c_void,
+) -> c_int {
+    let opened = panic::catch_unwind(AssertUnwindSafe(|| {
+        let token = token_json(cd, token)?;
+        let passphrase = luks::bound_passphrase(&token).map_err(|e| {
+            log(cd, CRYPT_LOG_ERROR, &format!("gramine-skp: {}", e));
+            errno(&e)
+        })?;
+        let copy = unsafe { libc::malloc(passphrase.len()) } as *mut c_char;
+        if copy.is_null() {
+            return Err(-libc::ENOMEM);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(passphrase.as_ptr() as *const c_char, copy, passphrase.len());
+            *buffer = copy;
+            *buffer_len = passphrase.len();
+        }
+        Ok(())
+    }));
+    match opened {
+        Ok(Ok(())) => 0,
+        Ok(Err(errno)) => errno,
+        Err(_) => -libc::EINVAL,
+    }
+}
+
+/// The token takes no PIN; a PIN given anyway is ignored.
+///
+/// # Safety
+/// libcryptsetup's contract for `crypt_token_open_pin_func`.
+#[no_mangle]
+pub unsafe extern "C" fn cryptsetup_token_open_pin(
+    cd: *mut CryptDevice,
+    token: c_int,
+    _pin: *const c_char,
+    _pin_size: usize,
+    buffer: *mut *mut c_char,
+    buffer_len: *mut usize,
+    usrptr: *mut c_void,
+) -> c_int {
+    cryptsetup_token_open(cd, token, buffer, buffer_len, usrptr)
+}
+
+/// # Safety
+/// `buffer` and `buffer_len` must come from `cryptsetup_token_open`.
+#[no_mangle]
+pub unsafe extern "C" fn cryptsetup_token_buffer_free(buffer: *mut c_void, buffer_len: usize) {
+    if buffer.is_null() {
+        return;
+    }
+    std::slice::from_raw_parts_mut(buffer as *mut u8, buffer_len).zeroize();
+    libc::free(buffer);
+}
+
+/// Checks a token before `cryptsetup token import` stores it.
+///
+/// # Safety
+/// `json` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cryptsetup_token_validate(
+    cd: *mut CryptDevice,
+    json: *const c_char,
+) -> c_int {
+    let json = CStr::from_ptr(json).to_bytes();
+    match panic::catch_unwind(|| Token::from_json(json)) {
+        Ok(Ok(token)) if !token.providers.is_empty() => 0,
+        Ok(Ok(_)) => {
+            log(cd, CRYPT_LOG_ERROR, "gramine-skp: token names no provider");
+            -libc::EINVAL
+        }
+        Ok(Err(e)) => {
+            log(cd, CRYPT_LOG_ERROR, &format!("gramine-skp: {}", e));
+            -libc::EINVAL
+        }
+        Err(_) => -libc::EINVAL,
+    }
+}
+
+/// Adds the token's fields to `cryptsetup luksDump`.
+///
+/// # Safety
+/// `json` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cryptsetup_token_dump(cd: *mut CryptDevice, json: *const c_char) {
+    let json = CStr::from_ptr(json).to_bytes();
+    let Ok(Ok(token)) = panic::catch_unwind(|| Token::from_json(json)) else {
+        return;
+    };
+    for line in dump_lines(&token) {
+        log(cd, CRYPT_LOG_NORMAL, &line);
+    }
+}
+
+fn dump_lines(token: &Token) -> Vec<String> {
+    let fields = [
+        ("providers", Some(token.providers.join(", "))),
+        ("context", Some(token.context.clone())),
+        ("key id", Some(token.key_id.clone())),
+        ("mrenclave", token.expect_mrenclave.clone()),
+        ("mrsigner", token.expect_mrsigner.clone()),
+        ("mrtd", token.expect_mrtd.clone()),
+    ];
+    fields
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("\t{:<12}{}", format!("{}:", name), value?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_lists_the_token_fields() {
+        let token = Token {
+            token_type: luks::TOKEN_TYPE.into(),
+            keyslots: vec!["1".into()],
+            providers: vec!["kp1.local:3443".into(), "kp2.local:3443".into()],
+            context: "luks:uuid".into(),
+            key_id: "00".repeat(16),
+            expect_mrenclave: None,
+            expect_mrsigner: None,
+            expect_mrtd: Some("aa".repeat(48)),
+        };
+
+        let lines = dump_lines(&token);
+
+        assert_eq!(lines[0], "\tproviders:  kp1.local:3443, kp2.local:3443");
+        assert_eq!(lines[3], format!("\tmrtd:       {}", "aa".repeat(48)));
+        assert_eq!(errno(&ClientError::Refused), -libc::EPERM);
+    }
+}
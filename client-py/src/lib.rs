@@ -0,0 +1,167 @@
+//! Python bindings for the guest client, built with maturin as the
+//! `gramine_sealing_key_client` module.
+
+use gramine_sealing_key_client::{
+    attestation, ClientError, DerivedKey, Exchange as ClientExchange, ExpectedProvider,
+    PcsCollateral, SealingKeyClient,
+};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::time::Duration;
+
+create_exception!(gramine_sealing_key_client, SealingKeyError, PyException);
+create_exception!(gramine_sealing_key_client, RefusedError, SealingKeyError);
+
+fn to_py(e: ClientError) -> PyErr {
+    match e {
+        ClientError::Refused | ClientError::Rejected(_) => RefusedError::new_err(e.to_string()),
+        e => SealingKeyError::new_err(e.to_string()),
+    }
+}
+
+fn measurement<const N: usize>(name: &str, value: Option<&[u8]>) -> PyResult<Option<[u8; N]>> {
+    value
+        .map(|value| {
+            value
+                .try_into()
+                .map_err(|_| PyValueError::new_err(format!("{} must be {} bytes", name, N)))
+        })
+        .transpose()
+}
+
+fn expected(
+    mr_enclave: Option<&[u8]>,
+    mr_signer: Option<&[u8]>,
+    mr_td: Option<&[u8]>,
+) -> PyResult<ExpectedProvider> {
+    Ok(ExpectedProvider {
+        mr_enclave: measurement("mr_enclave", mr_enclave)?,
+        mr_signer: measurement("mr_signer", mr_signer)?,
+        mr_td: measurement("mr_td", mr_td)?,
+    })
+}
+
+/// Collateral from the PCCS at `pccs_url`, or Intel PCS without one.
+fn collateral(pccs_url: Option<&str>) -> PcsCollateral {
+    pccs_url.map_or_else(PcsCollateral::default, PcsCollateral::pccs)
+}
+
+/// The key, or its subkey for `context`, as bytes.
+fn key_bytes<'py>(
+    py: Python<'py>,
+    key: &DerivedKey,
+    context: Option<&[u8]>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    match context {
+        Some(context) => Ok(PyBytes::new_bound(
+            py,
+            &key.subkey(context).map_err(to_py)?[..],
+        )),
+        None => Ok(PyBytes::new_bound(py, key.expose())),
+    }
+}
+
+/// One key request, for callers that quote and talk to the provider
+/// themselves.
+#[pyclass]
+struct Exchange {
+    inner: ClientExchange,
+}
+
+#[pymethods]
+impl Exchange {
+    #[new]
+    fn new() -> PyResult<Self> {
+        Ok(Self {
+            inner: ClientExchange::new().map_err(to_py)?,
+        })
+    }
+
+    /// The 64 bytes of report data the guest quote must carry.
+    fn report_data<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.inner.report_data())
+    }
+
+    /// The request frame body for `quote`, to send after a 4-byte big-endian
+    /// length.
+    #[pyo3(signature = (quote, explain = false))]
+    fn request<'py>(
+        &self,
+        py: Python<'py>,
+        quote: &[u8],
+        explain: bool,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let body = self.inner.request(quote, explain).map_err(to_py)?;
+        Ok(PyBytes::new_bound(py, &body))
+    }
+
+    /// Verifies the provider's response frame body, with the provider quote
+    /// checked against collateral from the PCCS at `pccs_url` or Intel PCS,
+    /// and returns the key.
+    #[pyo3(signature = (response, mr_enclave = None, mr_signer = None, mr_td = None, context = None, pccs_url = None))]
+    #[allow(clippy::too_many_arguments)]
+    fn open<'py>(
+        &self,
+        py: Python<'py>,
+        response: &[u8],
+        mr_enclave: Option<&[u8]>,
+        mr_signer: Option<&[u8]>,
+        mr_td: Option<&[u8]>,
+        context: Option<&[u8]>,
+        pccs_url: Option<&str>,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let expected = expected(mr_enclave, mr_signer, mr_td)?;
+        let collateral = collateral(pccs_url);
+        // Fetching collateral blocks on the network
+        let key = py
+            .allow_threads(|| self.inner.open(response, &expected, &collateral))
+            .map_err(to_py)?;
+        key_bytes(py, &key, context)
+    }
+}
+
+/// Runs the whole exchange with the provider at `address` (`host:port`),
+/// quoting through /dev/attestation or configfs-tsm, and returns the key.
+#[pyfunction]
+#[pyo3(signature = (address, mr_enclave = None, mr_signer = None, mr_td = None, context = None, timeout = 30.0, pccs_url = None))]
+#[allow(clippy::too_many_arguments)]
+fn request_key<'py>(
+    py: Python<'py>,
+    address: &str,
+    mr_enclave: Option<&[u8]>,
+    mr_signer: Option<&[u8]>,
+    mr_td: Option<&[u8]>,
+    context: Option<&[u8]>,
+    timeout: f64,
+    pccs_url: Option<&str>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let client = SealingKeyClient::new(address)
+        .expect(expected(mr_enclave, mr_signer, mr_td)?)
+        .collateral(collateral(pccs_url))
+        .timeout(
+            Duration::try_from_secs_f64(timeout)
+                .map_err(|_| PyValueError::new_err("timeout must be a positive number"))?,
+        );
+    // The exchange blocks on the network; let other Python threads run
+    let key = py
+        .allow_threads(|| {
+            let source = attestation::detect()?;
+            client.request_key(source.as_ref())
+        })
+        .map_err(to_py)?;
+    key_bytes(py, &key, context)
+}
+
+#[pymodule]
+fn gramine_sealing_key_client(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Exchange>()?;
+    m.add_function(wrap_pyfunction!(request_key, m)?)?;
+    m.add(
+        "SealingKeyError",
+        m.py().get_type_bound::<SealingKeyError>(),
+    )?;
+    m.add("RefusedError", m.py().get_type_bound::<RefusedError>())?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! WebAssembly bindings for the protocol core, built with
+//! `wasm-pack build client-wasm`. They cover one exchange without its
+//! transport: browsers cannot open the provider's TCP port, so the caller
+//! relays the frames, for example through a WebSocket bridge, and the quote
+//! comes from the TD being provisioned.
+
+use gramine_sealing_key_client::{ClientError, Exchange, ExpectedProvider, FetchedCollateral};
+use wasm_bindgen::prelude::*;
+
+fn to_js(e: ClientError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// One key request.
+#[wasm_bindgen(js_name = Exchange)]
+pub struct WasmExchange {
+    inner: Exchange,
+}
+
+#[wasm_bindgen(js_class = Exchange)]
+impl WasmExchange {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Result<WasmExchange, JsError> {
+        Ok(Self {
+            inner: Exchange::new().map_err(to_js)?,
+        })
+    }
+
+    /// The 64 bytes of report data the quote must carry.
+    #[wasm_bindgen(js_name = reportData)]
+    pub fn report_data(&self) -> Vec<u8> {
+        self.inner.report_data().to_vec()
+    }
+
+    /// The request frame body for `quote`, to send after a 4-byte big-endian
+    /// length.
+    pub fn request(&self, quote: &[u8], explain: bool) -> Result<Vec<u8>, JsError> {
+        self.inner.request(quote, explain).map_err(to_js)
+    }
+
+    /// Verifies the provider's response frame body against the provider
+    /// measurements, given as hex, and the provider quote against
+    /// `collateral`, the JSON the caller fetched from PCS for it, at `now`
+    /// (seconds since the Unix epoch). Returns the key, or its subkey for
+    /// `context`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn open(
+        &self,
+        response: &[u8],
+        collateral: &[u8],
+        now: u64,
+        mr_enclave: Option<String>,
+        mr_signer: Option<String>,
+        mr_td: Option<String>,
+        context: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, JsError> {
+        let expected = ExpectedProvider::from_hex(
+            mr_enclave.as_deref(),
+            mr_signer.as_deref(),
+            mr_td.as_deref(),
+        )
+        .map_err(to_js)?;
+        let collateral = FetchedCollateral::from_json(collateral, now).map_err(to_js)?;
+        let key = self
+            .inner
+            .open(response, &expected, &collateral)
+            .map_err(to_js)?;
+        match context {
+            Some(context) => Ok(key.subkey(&context).map_err(to_js)?.to_vec()),
+            None => Ok(key.expose().to_vec()),
+        }
+    }
+}
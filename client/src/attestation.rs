@@ -0,0 +1,142 @@
+use crate::azure::{self, AzureVtpm};
+use crate::error::ClientError;
+use crate::gcp::{self, ConfidentialSpace};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Gramine's pseudo-filesystem for local and remote attestation.
+pub const GRAMINE_ATTESTATION_DIR: &str = "/dev/attestation";
+/// The Linux TSM reports interface (configfs-tsm) of TDX guests.
+pub const TSM_REPORT_DIR: &str = "/sys/kernel/config/tsm/report";
+
+/// Produces quotes of the guest with caller-chosen report data.
+pub trait QuoteSource {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError>;
+}
+
+/// Quotes through Gramine's `/dev/attestation`: the report data is written
+/// to `user_report_data`, then `quote` is read.
+pub struct GramineAttestation {
+    dir: PathBuf,
+}
+
+impl GramineAttestation {
+    pub fn new() -> Self {
+        Self::at(Path::new(GRAMINE_ATTESTATION_DIR))
+    }
+
+    pub fn at(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+}
+
+impl Default for GramineAttestation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QuoteSource for GramineAttestation {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+        fs::write(self.dir.join("user_report_data"), report_data).map_err(|e| {
+            ClientError::AttestationError(format!(
+                "Cannot write report data to {} ({}); is this running under Gramine?",
+                self.dir.display(),
+                e
+            ))
+        })?;
+        Ok(fs::read(self.dir.join("quote"))?)
+    }
+}
+
+/// Quotes through configfs-tsm, for TD guests that do not run under Gramine.
+pub struct TsmReport {
+    entry: PathBuf,
+}
+
+impl TsmReport {
+    /// Creates this process's report entry under `report_dir`.
+    pub fn open(report_dir: &Path) -> Result<Self, ClientError> {
+        let entry = report_dir.join(format!("sealing-key-client-{}", std::process::id()));
+        match fs::create_dir(&entry) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(ClientError::AttestationError(format!(
+                    "Cannot create TSM report entry {} ({})",
+                    entry.display(),
+                    e
+                )))
+            }
+        }
+        Ok(Self { entry })
+    }
+}
+
+impl QuoteSource for TsmReport {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+        fs::write(self.entry.join("inblob"), report_data)?;
+        let generation = fs::read_to_string(self.entry.join("generation"))?;
+        let quote = fs::read(self.entry.join("outblob"))?;
+        if fs::read_to_string(self.entry.join("generation"))? != generation {
+            return Err(ClientError::AttestationError(
+                "TSM report entry changed while reading the quote".into(),
+            ));
+        }
+        Ok(quote)
+    }
+}
+
+impl Drop for TsmReport {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.entry);
+    }
+}
+
+/// Whichever attestation interface this guest has, preferring Gramine's.
+/// An Azure VM with a vTPM is taken to be an Azure TDX confidential VM,
+/// whose TD has no quote of its own even if configfs-tsm is present; the
+/// same goes for a Confidential Space workload with the launcher's socket.
+pub fn detect() -> Result<Box<dyn QuoteSource + Send + Sync>, ClientError> {
+    if Path::new(GRAMINE_ATTESTATION_DIR)
+        .join("user_report_data")
+        .exists()
+    {
+        return Ok(Box::new(GramineAttestation::new()));
+    }
+    if ConfidentialSpace::is_available() {
+        return Ok(Box::new(ConfidentialSpace::new(gcp::DEFAULT_AUDIENCE)));
+    }
+    if azure::is_available() {
+        return Ok(Box::new(AzureVtpm));
+    }
+    if Path::new(TSM_REPORT_DIR).is_dir() {
+        return Ok(Box::new(TsmReport::open(Path::new(TSM_REPORT_DIR))?));
+    }
+    Err(ClientError::AttestationError(format!(
+        "Neither {} nor {} nor an Azure vTPM or Confidential Space launcher is available",
+        GRAMINE_ATTESTATION_DIR, TSM_REPORT_DIR
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gramine_quote_covers_the_written_report_data() {
+        let dir = std::env::temp_dir().join(format!("attestation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("quote"), b"quote").unwrap();
+
+        let quote = GramineAttestation::at(&dir).quote(&[7; 64]).unwrap();
+        let written = fs::read(dir.join("user_report_data")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(quote, b"quote");
+        assert_eq!(written, [7; 64]);
+    }
+}
@@ -0,0 +1,168 @@
+//! Evidence from Azure TDX confidential VMs, which have no TDX quote of their
+//! own. The report data goes to the vTPM, the paravisor answers with an HCL
+//! report holding a TD report, and IMDS turns that into a quote. A vTPM
+//! quote over the boot PCRs comes along, since the TD's own measurements
+//! only cover the paravisor. Needs `tpm2-tools`.
+
+use crate::attestation::QuoteSource;
+use crate::error::ClientError;
+use crate::tpm::{tpm2, TempFile};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// NV index the paravisor serves the HCL report from.
+const HCL_REPORT_INDEX: &str = "0x01400001";
+/// NV index the HCL report takes its `user-data` from.
+const USER_DATA_INDEX: &str = "0x01400002";
+/// The vTPM's persistent attestation key, `HCLAkPub` in the runtime data.
+const AK_HANDLE: &str = "0x81000003";
+/// The PCRs the vTPM quote covers, and so the PCRs the key depends on.
+pub const VTPM_PCRS: &str = "sha256:0,1,2,3,4,5,6,7";
+const IMDS_ADDR: &str = "169.254.169.254:80";
+const IMDS_QUOTE_PATH: &str = "/acc/tdquote";
+const IMDS_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+/// `IGVM_ATTEST_HEADER` before the hardware report.
+const HCL_HEADER_LEN: usize = 32;
+/// The hardware report slot, sized for an SNP report.
+const HW_REPORT_LEN: usize = 1184;
+const TD_REPORT_LEN: usize = 1024;
+/// `IGVM_REQUEST_DATA` before the runtime data.
+const RUNTIME_HEADER_LEN: usize = 20;
+const SYS_VENDOR: &str = "/sys/class/dmi/id/sys_vendor";
+
+/// Whether this looks like an Azure VM with a vTPM.
+pub fn is_available() -> bool {
+    fs::read_to_string(SYS_VENDOR).is_ok_and(|vendor| vendor.trim() == "Microsoft Corporation")
+        && fs::metadata("/dev/tpmrm0").is_ok()
+}
+
+/// Azure TDX evidence, as the provider takes it in place of a quote.
+pub struct AzureVtpm;
+
+impl QuoteSource for AzureVtpm {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+        if tpm2(
+            &["nvwrite", "-C", "o", "-i", "-", USER_DATA_INDEX],
+            report_data,
+        )
+        .is_err()
+        {
+            tpm2(&["nvdefine", "-C", "o", "-s", "64", USER_DATA_INDEX], &[])?;
+            tpm2(
+                &["nvwrite", "-C", "o", "-i", "-", USER_DATA_INDEX],
+                report_data,
+            )?;
+        }
+        let hcl_report = tpm2(&["nvread", "-C", "o", HCL_REPORT_INDEX], &[])?;
+        let (td_report, runtime_data) = split_hcl_report(&hcl_report)?;
+        let quote = imds_quote(td_report)?;
+
+        let qualifying_data = hex::encode(Sha256::digest(report_data));
+        let attest = TempFile::new("attest");
+        let signature = TempFile::new("signature");
+        tpm2(
+            &[
+                "quote",
+                "-c",
+                AK_HANDLE,
+                "-l",
+                VTPM_PCRS,
+                "-q",
+                &qualifying_data,
+                "-g",
+                "sha256",
+                "-m",
+                attest.path()?,
+                "-s",
+                signature.path()?,
+            ],
+            &[],
+        )?;
+
+        let evidence = json!({
+            "type": "azure-tdx",
+            "quote": STANDARD.encode(quote),
+            "runtime_data": STANDARD.encode(runtime_data),
+            "vtpm_quote": STANDARD.encode(fs::read(&attest.0)?),
+            "vtpm_signature": STANDARD.encode(fs::read(&signature.0)?),
+        });
+        Ok(evidence.to_string().into_bytes())
+    }
+}
+
+/// The TD report and the runtime data of an HCL report.
+fn split_hcl_report(report: &[u8]) -> Result<(&[u8], &[u8]), ClientError> {
+    let malformed = || ClientError::AttestationError("Malformed HCL report".into());
+    let td_report = report
+        .get(HCL_HEADER_LEN..HCL_HEADER_LEN + TD_REPORT_LEN)
+        .ok_or_else(malformed)?;
+    let runtime = report
+        .get(HCL_HEADER_LEN + HW_REPORT_LEN..)
+        .ok_or_else(malformed)?;
+    let size = runtime
+        .get(16..RUNTIME_HEADER_LEN)
+        .map(|size| u32::from_le_bytes(size.try_into().expect("4 bytes")) as usize)
+        .ok_or_else(malformed)?;
+    let runtime_data = runtime
+        .get(RUNTIME_HEADER_LEN..RUNTIME_HEADER_LEN + size)
+        .ok_or_else(malformed)?;
+    Ok((td_report, runtime_data))
+}
+
+/// Has IMDS quote `td_report`.
+fn imds_quote(td_report: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let body = json!({ "report": URL_SAFE_NO_PAD.encode(td_report) }).to_string();
+    let address = IMDS_ADDR.parse().expect("valid address");
+    let mut socket = TcpStream::connect_timeout(&address, IMDS_TIMEOUT)?;
+    socket.set_read_timeout(Some(IMDS_TIMEOUT))?;
+    write!(
+        socket,
+        "POST {} HTTP/1.1\r\nHost: 169.254.169.254\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        IMDS_QUOTE_PATH,
+        body.len(),
+        body
+    )?;
+    let mut response = Vec::new();
+    socket.take(MAX_RESPONSE_LEN).read_to_end(&mut response)?;
+
+    let (status, body): (u16, Value) = crate::vault::parse_response(&response)?;
+    body["quote"]
+        .as_str()
+        .filter(|_| status == 200)
+        .and_then(|quote| URL_SAFE_NO_PAD.decode(quote.trim_end_matches('=')).ok())
+        .ok_or_else(|| {
+            ClientError::AttestationError(format!("IMDS did not quote (status {})", status))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hcl_report_splits_into_td_report_and_runtime_data() {
+        let mut report = vec![0u8; HCL_HEADER_LEN];
+        report.extend_from_slice(&[0xaa; TD_REPORT_LEN]);
+        report.resize(HCL_HEADER_LEN + HW_REPORT_LEN, 0);
+        let runtime_data = br#"{"user-data":"00"}"#;
+        report.extend_from_slice(&[0; 16]);
+        report.extend_from_slice(&(runtime_data.len() as u32).to_le_bytes());
+        report.extend_from_slice(runtime_data);
+        report.extend_from_slice(&[0; 8]);
+
+        let (td_report, runtime) = split_hcl_report(&report).unwrap();
+
+        assert_eq!(td_report, [0xaa; TD_REPORT_LEN]);
+        assert_eq!(runtime, runtime_data);
+        assert!(split_hcl_report(&report[..100]).is_err());
+    }
+}
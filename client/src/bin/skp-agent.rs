@@ -0,0 +1,132 @@
+use clap::Parser;
+use gramine_sealing_key_client::install::{self, KeyringOptions, Target};
+use gramine_sealing_key_client::{
+    attestation, ClientError, ExpectedProvider, PcsCollateral, Retry, SealingKeyClient,
+};
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Reference guest agent: at boot, fetches this TD's key from the first
+/// provider that answers and installs it for the workload.
+#[derive(Parser)]
+#[command(name = "skp-agent", version)]
+struct Args {
+    /// Providers to try, in order
+    #[arg(
+        long = "provider",
+        env = "SKP_PROVIDERS",
+        value_delimiter = ',',
+        required = true,
+        value_name = "HOST:PORT"
+    )]
+    providers: Vec<String>,
+
+    /// Install a subkey for this purpose instead of the key itself
+    #[arg(long, env = "SKP_CONTEXT")]
+    context: Option<String>,
+
+    /// `file:<path>` on a tmpfs, `keyring:<logon|user|encrypted>:<description>`,
+    /// or `credential:<name>` for systemd services to import
+    #[arg(long, env = "SKP_INSTALL", value_name = "TARGET")]
+    install: Target,
+
+    /// Keyring for `keyring:` targets: @u, @s, @us, @p or @t
+    #[arg(long, env = "SKP_KEYRING", default_value = "@u")]
+    keyring: String,
+
+    /// Permission mask for `keyring:` targets, as `keyctl setperm` takes it
+    #[arg(long, env = "SKP_KEY_PERM", value_name = "HEX", value_parser = install::parse_permissions)]
+    key_perm: Option<u32>,
+
+    /// Master key of `keyring:encrypted:` targets, such as `trusted:kmk`
+    #[arg(long, env = "SKP_MASTER_KEY", value_name = "TYPE:DESCRIPTION")]
+    master_key: Option<String>,
+
+    /// Allow `file:` and `credential:` targets on persistent storage
+    #[arg(long)]
+    allow_persistent: bool,
+
+    /// Rounds over all providers before giving up
+    #[arg(long, env = "SKP_ATTEMPTS", default_value_t = 10)]
+    attempts: u32,
+
+    /// Pause after the first failed round, doubled after each further one
+    /// and randomized by up to half
+    #[arg(long, default_value_t = 1, value_name = "SECS")]
+    backoff: u64,
+
+    #[arg(long, default_value_t = 60, value_name = "SECS")]
+    max_backoff: u64,
+
+    /// Per-provider timeout
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    timeout: u64,
+
+    /// Move on from a provider that does not accept the connection after
+    #[arg(long, default_value_t = 5, value_name = "SECS")]
+    connect_timeout: u64,
+
+    #[arg(long, env = "SKP_EXPECT_MRENCLAVE", value_name = "HEX")]
+    expect_mrenclave: Option<String>,
+
+    #[arg(long, env = "SKP_EXPECT_MRSIGNER", value_name = "HEX")]
+    expect_mrsigner: Option<String>,
+
+    #[arg(long, env = "SKP_EXPECT_MRTD", value_name = "HEX")]
+    expect_mrtd: Option<String>,
+
+    /// PCCS to fetch the collateral for the provider quote from, in place of
+    /// Intel PCS
+    #[arg(long, env = "SKP_PCCS_URL", value_name = "URL")]
+    pccs_url: Option<String>,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skp-agent: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), ClientError> {
+    let expected = ExpectedProvider::from_hex(
+        args.expect_mrenclave.as_deref(),
+        args.expect_mrsigner.as_deref(),
+        args.expect_mrtd.as_deref(),
+    )?;
+    let mut client = SealingKeyClient::failover(&args.providers)?
+        .expect(expected)
+        .timeout(Duration::from_secs(args.timeout))
+        .connect_timeout(Duration::from_secs(args.connect_timeout))
+        .retry(Retry {
+            attempts: args.attempts.max(1),
+            initial_backoff: Duration::from_secs(args.backoff),
+            max_backoff: Duration::from_secs(args.max_backoff),
+            ..Retry::default()
+        });
+    if let Some(url) = &args.pccs_url {
+        client = client.collateral(PcsCollateral::pccs(url));
+    }
+
+    let target = args.install.clone().with_keyring_options(KeyringOptions {
+        keyring: args.keyring.clone(),
+        permissions: args.key_perm,
+        master_key: args.master_key.clone(),
+    });
+
+    let source = attestation::detect()?;
+    let key = client.request_key(source.as_ref())?;
+    match &args.context {
+        Some(context) => install::install(
+            &target,
+            &key.subkey(context.as_bytes())?[..],
+            args.allow_persistent,
+        )?,
+        None => install::install(&target, key.expose(), args.allow_persistent)?,
+    }
+    eprintln!("skp-agent: installed key at {:?}", target);
+    Ok(())
+}
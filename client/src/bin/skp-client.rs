@@ -0,0 +1,262 @@
+use base64::Engine;
+use clap::{Parser, ValueEnum};
+use gramine_sealing_key_client::{
+    attestation::{self, QuoteSource},
+    install::{self, KeyringOptions, Target},
+    tpm::TpmAttestation,
+    vault, ClientError, ExpectedProvider, PcsCollateral, Retry, SealingKeyClient,
+};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Fetches this TD's sealing key from a gramine-sealing-key-provider and
+/// prints it, writes it to a file or adds it to a kernel keyring.
+#[derive(Parser)]
+#[command(name = "skp-client", version)]
+struct Args {
+    /// Providers to try, in order, moving on from one that cannot be
+    /// reached
+    #[arg(
+        long = "provider",
+        env = "SKP_PROVIDER",
+        value_delimiter = ',',
+        required_unless_present = "vault_unseal",
+        value_name = "HOST:PORT"
+    )]
+    providers: Vec<String>,
+
+    /// Derive a subkey for this purpose instead of returning the key itself
+    #[arg(long, env = "SKP_CONTEXT")]
+    context: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = Format::Hex)]
+    format: Format,
+
+    /// Write the key to this file (mode 0600) instead of standard output
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Add the key to a kernel keyring as
+    /// `keyring:<logon|user|encrypted>:<description>` instead, so it never
+    /// reaches a file
+    #[arg(long, conflicts_with_all = ["format", "output"], value_name = "TARGET", value_parser = keyring_target)]
+    install: Option<Target>,
+
+    /// Keyring to add the key to: @u, @s, @us, @p or @t
+    #[arg(long, requires = "install", default_value = "@u")]
+    keyring: String,
+
+    /// Permission mask for the key, as `keyctl setperm` takes it
+    #[arg(long, requires = "install", value_name = "HEX", value_parser = install::parse_permissions)]
+    key_perm: Option<u32>,
+
+    /// Master key of an `encrypted` key, such as `trusted:kmk`
+    #[arg(long, requires = "install", value_name = "TYPE:DESCRIPTION")]
+    master_key: Option<String>,
+
+    /// Expected MRENCLAVE of an SGX provider, hex
+    #[arg(long, env = "SKP_EXPECT_MRENCLAVE", value_name = "HEX")]
+    expect_mrenclave: Option<String>,
+
+    /// Expected MRSIGNER of an SGX provider, hex
+    #[arg(long, env = "SKP_EXPECT_MRSIGNER", value_name = "HEX")]
+    expect_mrsigner: Option<String>,
+
+    /// Expected MRTD of a provider running in a TD, hex
+    #[arg(long, env = "SKP_EXPECT_MRTD", value_name = "HEX")]
+    expect_mrtd: Option<String>,
+
+    /// PCCS to fetch the collateral for the provider quote from, in place of
+    /// Intel PCS
+    #[arg(long, env = "SKP_PCCS_URL", value_name = "URL")]
+    pccs_url: Option<String>,
+
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    timeout: u64,
+
+    /// Rounds over all providers before giving up, with exponential backoff
+    /// and jitter between them
+    #[arg(long, env = "SKP_ATTEMPTS", default_value_t = 1)]
+    attempts: u32,
+
+    /// Attest with the TPM instead of a TEE, using the AK whose certificate
+    /// chain (PEM, AK first) is in this file. The provider must allow TPM
+    /// clients.
+    #[arg(long, env = "SKP_TPM_AK_CHAIN", value_name = "PATH")]
+    tpm_ak_chain: Option<PathBuf>,
+
+    /// Persistent handle of the TPM attestation key
+    #[arg(long, requires = "tpm_ak_chain", value_name = "HANDLE")]
+    tpm_ak: Option<String>,
+
+    /// Ask the provider why it refuses the request
+    #[arg(long)]
+    explain: bool,
+
+    /// Tenant of a shared provider to request the key of
+    #[arg(long, env = "SKP_TENANT")]
+    tenant: Option<String>,
+
+    /// Log in to the provider's Vault API at --provider and print shell
+    /// exports of the Vault token and the key its secrets are sealed to,
+    /// instead of the key
+    #[arg(long, conflicts_with_all = ["context", "format", "output", "install", "explain", "tenant"])]
+    vault_login: bool,
+
+    /// Open a secret the Vault API sealed to the login, read as base64 from
+    /// standard input, and print it like a key
+    #[arg(long, requires = "vault_key", conflicts_with_all = ["vault_login", "context", "install"])]
+    vault_unseal: bool,
+
+    /// The login's key, as --vault-login exports it
+    #[arg(
+        long,
+        env = "SKP_VAULT_KEY",
+        hide_env_values = true,
+        value_name = "HEX"
+    )]
+    vault_key: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Raw,
+    Hex,
+    Base64,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skp-client: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn keyring_target(target: &str) -> Result<Target, ClientError> {
+    match target.parse()? {
+        target @ Target::Keyring { .. } => Ok(target),
+        _ => Err(ClientError::ConfigError(format!(
+            "{:?} is not a keyring: target",
+            target
+        ))),
+    }
+}
+
+fn quote_source(args: &Args) -> Result<Box<dyn QuoteSource + Send + Sync>, ClientError> {
+    let Some(chain) = &args.tpm_ak_chain else {
+        return attestation::detect();
+    };
+    let mut tpm = TpmAttestation::new(chain)?;
+    if let Some(handle) = &args.tpm_ak {
+        tpm = tpm.ak_handle(handle);
+    }
+    Ok(Box::new(tpm))
+}
+
+/// Logs in at the first provider that can be reached.
+fn vault_login(args: &Args) -> Result<vault::VaultLogin, ClientError> {
+    let source = quote_source(args)?;
+    let mut last_error = None;
+    for provider in &args.providers {
+        match vault::login(provider, source.as_ref(), Duration::from_secs(args.timeout)) {
+            Ok(login) => return Ok(login),
+            Err(e) if e.is_transient() => last_error = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.expect("clap requires a provider"))
+}
+
+fn run(args: Args) -> Result<(), ClientError> {
+    if args.vault_login {
+        let login = vault_login(&args)?;
+        writeln!(
+            std::io::stdout().lock(),
+            "export VAULT_TOKEN={}\nexport SKP_VAULT_KEY={}",
+            &login.token[..],
+            &login.secret_key_hex()[..]
+        )?;
+        return Ok(());
+    }
+    if args.vault_unseal {
+        let vault_key = args
+            .vault_key
+            .as_deref()
+            .expect("clap requires the vault key");
+        let mut sealed = String::new();
+        std::io::stdin().read_to_string(&mut sealed)?;
+        return write_key(&args, vault::unseal_with_key(vault_key, &sealed)?);
+    }
+
+    let expected = ExpectedProvider::from_hex(
+        args.expect_mrenclave.as_deref(),
+        args.expect_mrsigner.as_deref(),
+        args.expect_mrtd.as_deref(),
+    )?;
+    let source = quote_source(&args)?;
+    let mut client = SealingKeyClient::failover(&args.providers)?;
+    if let Some(tenant) = &args.tenant {
+        client = client.tenant(tenant);
+    }
+    if let Some(url) = &args.pccs_url {
+        client = client.collateral(PcsCollateral::pccs(url));
+    }
+    let key = client
+        .expect(expected)
+        .timeout(Duration::from_secs(args.timeout))
+        .retry(Retry {
+            attempts: args.attempts.max(1),
+            ..Retry::default()
+        })
+        .explain(args.explain)
+        .request_key(source.as_ref())?;
+
+    let key = match &args.context {
+        Some(context) => Zeroizing::new(key.subkey(context.as_bytes())?.to_vec()),
+        None => Zeroizing::new(key.expose().to_vec()),
+    };
+    if let Some(target) = &args.install {
+        let target = target.clone().with_keyring_options(KeyringOptions {
+            keyring: args.keyring.clone(),
+            permissions: args.key_perm,
+            master_key: args.master_key.clone(),
+        });
+        return install::install(&target, &key, false);
+    }
+    write_key(&args, key)
+}
+
+/// Prints `key` in the requested format, or writes it to the output file.
+fn write_key(args: &Args, key: Zeroizing<Vec<u8>>) -> Result<(), ClientError> {
+    let encoded = match args.format {
+        Format::Raw => key,
+        Format::Hex => Zeroizing::new(format!("{}\n", hex::encode(&key[..])).into_bytes()),
+        Format::Base64 => Zeroizing::new(
+            format!(
+                "{}\n",
+                base64::engine::general_purpose::STANDARD.encode(&key[..])
+            )
+            .into_bytes(),
+        ),
+    };
+
+    match &args.output {
+        Some(path) => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(&encoded)?,
+        None => std::io::stdout().lock().write_all(&encoded)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use clap::{Parser, ValueEnum};
+use gramine_sealing_key_client::kubernetes::{self, KeyFiles, Settings};
+use gramine_sealing_key_client::{
+    attestation, ClientError, ExpectedProvider, Retry, SealingKeyClient,
+};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+/// Delivers this pod's keys into a shared in-memory volume, as an init
+/// container or a sidecar. Configured by `skp.gramine.dev/*` annotations
+/// (through the downward API) and `SKP_*` environment variables.
+#[derive(Parser)]
+#[command(name = "skp-k8s", version)]
+struct Args {
+    #[arg(long, env = "SKP_MODE", value_enum, default_value_t = Mode::Init)]
+    mode: Mode,
+
+    /// Shared volume to project the keys into; use an emptyDir with
+    /// `medium: Memory`
+    #[arg(long, env = "SKP_KEY_DIR", default_value = "/run/skp/keys")]
+    key_dir: PathBuf,
+
+    /// Downward API file with the pod's annotations
+    #[arg(long, env = "SKP_ANNOTATIONS", default_value = kubernetes::DEFAULT_ANNOTATIONS_FILE)]
+    annotations: PathBuf,
+
+    /// How often a sidecar fetches the keys again
+    #[arg(long, env = "SKP_REFRESH_SECS", default_value_t = 300)]
+    refresh: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// Fetch the keys once and exit
+    Init,
+    /// Fetch the keys, then keep them current for the life of the pod
+    Sidecar,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skp-k8s: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), ClientError> {
+    let annotations = kubernetes::read_annotations(&args.annotations)?;
+    let settings = Settings::load(&annotations, |var| std::env::var(var).ok())?;
+    let client = SealingKeyClient::failover(&settings.providers)?
+        .expect(ExpectedProvider::from_hex(
+            settings.expect_mrenclave.as_deref(),
+            settings.expect_mrsigner.as_deref(),
+            settings.expect_mrtd.as_deref(),
+        )?)
+        .retry(Retry {
+            attempts: 10,
+            ..Retry::default()
+        });
+    let source = attestation::detect()?;
+
+    let mut current = deliver(&client, source.as_ref(), &settings, &args, None)?;
+    eprintln!(
+        "skp-k8s: projected {} key(s) into {}",
+        current.len(),
+        args.key_dir.display()
+    );
+    if args.mode == Mode::Init {
+        return Ok(());
+    }
+
+    loop {
+        thread::sleep(Duration::from_secs(args.refresh));
+        // Keep serving the keys already projected when a refresh fails
+        match deliver(&client, source.as_ref(), &settings, &args, Some(&current)) {
+            Ok(keys) => current = keys,
+            Err(e) => eprintln!("skp-k8s: refresh failed, keeping current keys: {}", e),
+        }
+    }
+}
+
+/// Fetches the keys and projects them unless they equal `current`. Returns
+/// the keys now in the volume.
+fn deliver(
+    client: &SealingKeyClient,
+    source: &dyn attestation::QuoteSource,
+    settings: &Settings,
+    args: &Args,
+    current: Option<&KeyFiles>,
+) -> Result<KeyFiles, ClientError> {
+    let key = client.request_key(source)?;
+    let keys = kubernetes::key_files(&key, settings)?;
+
+    if current.is_some_and(|current| *current == keys) {
+        return Ok(keys);
+    }
+    if current.is_some() {
+        eprintln!("skp-k8s: keys changed (key epoch change), projecting the new ones");
+    }
+    let files: Vec<(String, &[u8])> = keys
+        .iter()
+        .map(|(file, key)| (file.clone(), key.as_slice()))
+        .collect();
+    kubernetes::project(&args.key_dir, &files)?;
+    Ok(keys)
+}
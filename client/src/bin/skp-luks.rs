@@ -0,0 +1,144 @@
+use clap::{Args, Parser, Subcommand};
+use gramine_sealing_key_client::luks::{self, Token};
+use gramine_sealing_key_client::ClientError;
+use std::io::{Read, Write};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+/// Exit status when the volume was bound to another key epoch, so boot
+/// scripts can fall back to a recovery passphrase instead of retrying.
+const EXIT_KEY_EPOCH_CHANGED: u8 = 3;
+
+/// Unlocks LUKS2 volumes with a key from gramine-sealing-key-provider.
+#[derive(Parser)]
+#[command(name = "skp-luks", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add a keyslot for this TD's key and record how to fetch it
+    Bind(BindArgs),
+    /// Fetch the key and open the volume
+    Unlock(UnlockArgs),
+    /// Print the passphrase of the volume in CRYPTTAB_SOURCE, for a crypttab
+    /// `keyscript=`
+    Keyscript,
+}
+
+#[derive(Args)]
+struct BindArgs {
+    /// LUKS2 device
+    device: String,
+
+    /// Providers to try, in order
+    #[arg(
+        long = "provider",
+        value_delimiter = ',',
+        required = true,
+        value_name = "HOST:PORT"
+    )]
+    providers: Vec<String>,
+
+    /// File holding an existing passphrase of the volume, `-` for stdin
+    #[arg(long, value_name = "PATH")]
+    existing_key_file: String,
+
+    /// Subkey context of the passphrase; `luks:<volume UUID>` by default
+    #[arg(long)]
+    context: Option<String>,
+
+    #[arg(long, value_name = "HEX")]
+    expect_mrenclave: Option<String>,
+
+    #[arg(long, value_name = "HEX")]
+    expect_mrsigner: Option<String>,
+
+    #[arg(long, value_name = "HEX")]
+    expect_mrtd: Option<String>,
+}
+
+#[derive(Args)]
+struct UnlockArgs {
+    /// LUKS2 device
+    device: String,
+
+    /// Mapping name under /dev/mapper
+    name: String,
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Bind(args) => bind(args),
+        Command::Unlock(args) => unlock(&args.device, &args.name),
+        Command::Keyscript => keyscript(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e @ ClientError::KeyEpochChanged { .. }) => {
+            eprintln!(
+                "skp-luks: {}; the TD's measurements or the provider's root key changed, \
+                 unlock with a recovery passphrase and bind again",
+                e
+            );
+            ExitCode::from(EXIT_KEY_EPOCH_CHANGED)
+        }
+        Err(e) => {
+            eprintln!("skp-luks: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bind(args: BindArgs) -> Result<(), ClientError> {
+    let existing = Zeroizing::new(if args.existing_key_file == "-" {
+        let mut existing = Vec::new();
+        std::io::stdin().read_to_end(&mut existing)?;
+        existing
+    } else {
+        std::fs::read(&args.existing_key_file)?
+    });
+    let context = match args.context {
+        Some(context) => context,
+        None => format!("luks:{}", luks::volume_uuid(&args.device)?),
+    };
+    let token = Token {
+        token_type: luks::TOKEN_TYPE.to_string(),
+        keyslots: Vec::new(),
+        providers: args.providers,
+        context,
+        key_id: String::new(),
+        expect_mrenclave: args.expect_mrenclave,
+        expect_mrsigner: args.expect_mrsigner,
+        expect_mrtd: args.expect_mrtd,
+    };
+
+    let passphrase = luks::passphrase(&luks::fetch_key(&token)?, &token.context)?;
+    let token = Token {
+        key_id: luks::key_id(&passphrase[..]),
+        ..token
+    };
+    let slot = luks::bind(&args.device, &existing, &passphrase[..], token)?;
+    eprintln!("skp-luks: bound keyslot {} of {}", slot, args.device);
+    Ok(())
+}
+
+fn unlock(device: &str, name: &str) -> Result<(), ClientError> {
+    let passphrase = bound_passphrase(device)?;
+    luks::open(device, name, &passphrase[..])
+}
+
+fn keyscript() -> Result<(), ClientError> {
+    let device = std::env::var("CRYPTTAB_SOURCE")
+        .map_err(|_| ClientError::ConfigError("CRYPTTAB_SOURCE is not set".into()))?;
+    let passphrase = bound_passphrase(&device)?;
+    std::io::stdout().lock().write_all(&passphrase[..])?;
+    Ok(())
+}
+
+fn bound_passphrase(device: &str) -> Result<Zeroizing<[u8; 32]>, ClientError> {
+    let (_, token) = luks::find_token(device)?;
+    luks::bound_passphrase(&token)
+}
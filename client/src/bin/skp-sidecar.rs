@@ -0,0 +1,138 @@
+use clap::Parser;
+use gramine_sealing_key_client::kubernetes::{self, KeyFiles, Settings};
+use gramine_sealing_key_client::sidecar::{self, WaitTarget};
+use gramine_sealing_key_client::{
+    attestation, ClientError, ExpectedProvider, Retry, SealingKeyClient,
+};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread;
+use std::time::Duration;
+
+/// Delivers keys to a container in a TD, such as a Docker Compose service,
+/// through a shared memory-backed volume. Configured by the `SKP_*`
+/// environment variables skp-k8s reads.
+#[derive(Parser)]
+#[command(name = "skp-sidecar", version)]
+struct Args {
+    /// Shared volume to project the keys into; use a tmpfs volume
+    #[arg(long, env = "SKP_KEY_DIR", default_value = "/run/skp/keys")]
+    key_dir: PathBuf,
+
+    /// Wait for these before fetching: comma-separated absolute paths in a
+    /// shared volume, or host:port addresses that accept connections
+    #[arg(
+        long,
+        env = "SKP_WAIT_FOR",
+        value_delimiter = ',',
+        value_name = "TARGET"
+    )]
+    wait_for: Vec<String>,
+
+    /// Give up waiting after this long; 0 waits forever
+    #[arg(long, env = "SKP_WAIT_TIMEOUT_SECS", default_value_t = 0)]
+    wait_timeout: u64,
+
+    /// How often the keys are fetched again
+    #[arg(long, env = "SKP_REFRESH_SECS", default_value_t = 300)]
+    refresh: u64,
+
+    /// Exit once the keys are delivered instead of keeping them current
+    #[arg(long, env = "SKP_ONCE")]
+    once: bool,
+
+    /// Exit 0 if the keys are ready, 1 if not, for a container health check
+    #[arg(long, conflicts_with_all = ["wait_for", "once"])]
+    healthcheck: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if args.healthcheck {
+        return if sidecar::is_ready(&args.key_dir) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        };
+    }
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skp-sidecar: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), ClientError> {
+    let settings = Settings::load(&BTreeMap::new(), |var| std::env::var(var).ok())?;
+    let targets = args
+        .wait_for
+        .iter()
+        .map(|target| WaitTarget::parse(target))
+        .collect::<Result<Vec<_>, _>>()?;
+    let client = SealingKeyClient::failover(&settings.providers)?
+        .expect(ExpectedProvider::from_hex(
+            settings.expect_mrenclave.as_deref(),
+            settings.expect_mrsigner.as_deref(),
+            settings.expect_mrtd.as_deref(),
+        )?)
+        .retry(Retry {
+            attempts: 10,
+            ..Retry::default()
+        });
+    let source = attestation::detect()?;
+
+    sidecar::clear_ready(&args.key_dir)?;
+    if !targets.is_empty() {
+        eprintln!("skp-sidecar: waiting for {}", args.wait_for.join(", "));
+        let timeout = (args.wait_timeout > 0).then(|| Duration::from_secs(args.wait_timeout));
+        sidecar::wait_for(&targets, timeout)?;
+    }
+
+    let mut current = deliver(&client, source.as_ref(), &settings, &args, None)?;
+    sidecar::mark_ready(&args.key_dir)?;
+    eprintln!(
+        "skp-sidecar: projected {} key(s) into {}",
+        current.len(),
+        args.key_dir.display()
+    );
+    if args.once {
+        return Ok(());
+    }
+
+    loop {
+        thread::sleep(Duration::from_secs(args.refresh));
+        // Keep serving the keys already projected when a refresh fails
+        match deliver(&client, source.as_ref(), &settings, &args, Some(&current)) {
+            Ok(keys) => current = keys,
+            Err(e) => eprintln!("skp-sidecar: refresh failed, keeping current keys: {}", e),
+        }
+    }
+}
+
+/// Fetches the keys and projects them unless they equal `current`. Returns
+/// the keys now in the volume.
+fn deliver(
+    client: &SealingKeyClient,
+    source: &dyn attestation::QuoteSource,
+    settings: &Settings,
+    args: &Args,
+    current: Option<&KeyFiles>,
+) -> Result<KeyFiles, ClientError> {
+    let key = client.request_key(source)?;
+    let keys = kubernetes::key_files(&key, settings)?;
+    if current.is_some_and(|current| *current == keys) {
+        return Ok(keys);
+    }
+    if current.is_some() {
+        eprintln!("skp-sidecar: keys changed (key epoch change), projecting the new ones");
+    }
+    let files: Vec<(String, &[u8])> = keys
+        .iter()
+        .map(|(file, key)| (file.clone(), key.as_slice()))
+        .collect();
+    kubernetes::project(&args.key_dir, &files)?;
+    Ok(keys)
+}
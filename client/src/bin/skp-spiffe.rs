@@ -0,0 +1,149 @@
+use clap::{Args, Parser, Subcommand};
+use gramine_sealing_key_client::install::write_private;
+use gramine_sealing_key_client::spiffe::{self, NodeAttestation};
+use gramine_sealing_key_client::{attestation, ClientError, ExpectedProvider, SealingKeyClient};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Both halves of SPIRE node attestation through gramine-sealing-key-provider,
+/// for node attestor plugins to run: `agent` in the TD, `server` next to the
+/// SPIRE server.
+#[derive(Parser)]
+#[command(name = "skp-spiffe", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the SVID key derived from this TD's key and print the
+    /// attestation answering CHALLENGE
+    Agent {
+        #[command(flatten)]
+        provider: ProviderArgs,
+
+        /// The server plugin's challenge, hex
+        #[arg(long, value_name = "HEX")]
+        challenge: String,
+
+        /// Where to write the SVID key (PKCS#8 PEM, mode 0600)
+        #[arg(long, value_name = "PATH")]
+        key_out: PathBuf,
+    },
+    /// Read an agent's attestation from standard input, have the provider
+    /// verify it, and print the agent ID and selectors
+    Server {
+        #[command(flatten)]
+        provider: ProviderArgs,
+
+        /// The challenge sent to the agent, hex
+        #[arg(long, value_name = "HEX")]
+        challenge: String,
+
+        #[arg(long, env = "SKP_TRUST_DOMAIN")]
+        trust_domain: String,
+    },
+}
+
+#[derive(Args)]
+struct ProviderArgs {
+    /// Providers to try, in order
+    #[arg(
+        long = "provider",
+        env = "SKP_PROVIDERS",
+        value_delimiter = ',',
+        required = true,
+        value_name = "HOST:PORT"
+    )]
+    providers: Vec<String>,
+
+    #[arg(long, env = "SKP_EXPECT_MRENCLAVE", value_name = "HEX")]
+    expect_mrenclave: Option<String>,
+
+    #[arg(long, env = "SKP_EXPECT_MRSIGNER", value_name = "HEX")]
+    expect_mrsigner: Option<String>,
+
+    #[arg(long, env = "SKP_EXPECT_MRTD", value_name = "HEX")]
+    expect_mrtd: Option<String>,
+
+    #[arg(long, default_value_t = 30, value_name = "SECS")]
+    timeout: u64,
+}
+
+impl ProviderArgs {
+    fn client(&self) -> Result<SealingKeyClient, ClientError> {
+        Ok(SealingKeyClient::failover(&self.providers)?
+            .expect(ExpectedProvider::from_hex(
+                self.expect_mrenclave.as_deref(),
+                self.expect_mrsigner.as_deref(),
+                self.expect_mrtd.as_deref(),
+            )?)
+            .timeout(Duration::from_secs(self.timeout)))
+    }
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Agent {
+            provider,
+            challenge,
+            key_out,
+        } => agent(&provider, &challenge, &key_out),
+        Command::Server {
+            provider,
+            challenge,
+            trust_domain,
+        } => server(&provider, &challenge, &trust_domain),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("skp-spiffe: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn agent(provider: &ProviderArgs, challenge: &str, key_out: &Path) -> Result<(), ClientError> {
+    let challenge = decode_challenge(challenge)?;
+    let source = attestation::detect()?;
+    let key = provider.client()?.request_key(source.as_ref())?;
+    let svid_key = spiffe::svid_key(&key)?;
+    write_private(key_out, spiffe::svid_key_pem(&svid_key)?.as_bytes())?;
+
+    let attestation = spiffe::attest(
+        source.as_ref(),
+        &challenge,
+        &spiffe::svid_public_key(&svid_key)?,
+    )?;
+    print_json(&attestation)
+}
+
+fn server(provider: &ProviderArgs, challenge: &str, trust_domain: &str) -> Result<(), ClientError> {
+    let challenge = decode_challenge(challenge)?;
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let attestation: NodeAttestation = serde_json::from_slice(&input)?;
+
+    let response = provider
+        .client()?
+        .attest_node(&attestation.request(&challenge, trust_domain))?;
+    print_json(&serde_json::json!({
+        "spiffe_id": response.spiffe_id,
+        "selectors": response.selectors,
+    }))
+}
+
+fn decode_challenge(challenge: &str) -> Result<Vec<u8>, ClientError> {
+    hex::decode(challenge).map_err(|_| ClientError::ConfigError("The challenge must be hex".into()))
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<(), ClientError> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
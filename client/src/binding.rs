@@ -0,0 +1,384 @@
+use crate::error::ClientError;
+use crate::protocol::QuoteResponse;
+use dcap_qvl::quote::{Quote, Report};
+use dcap_qvl::verify::verify;
+use dcap_qvl::QuoteCollateralV3;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const METADATA_LABEL: &[u8] = b"gramine-sealing-key-provider/response-metadata/v1";
+/// `SGX_FLAGS_DEBUG` in the low byte of an enclave's attributes.
+const SGX_FLAGS_DEBUG: u8 = 0x02;
+/// The DEBUG bit in the low byte of a TD's attributes.
+const TD_ATTRIBUTES_DEBUG: u8 = 0x01;
+
+/// Identity of the provider a response must come from, checked against the
+/// provider quote. At least one measurement must be set: a provider quote is
+/// not checked against an empty one, since any genuine enclave or TD,
+/// including one the host built, would match it.
+#[derive(Clone, Debug, Default)]
+pub struct ExpectedProvider {
+    /// MRENCLAVE of an SGX provider.
+    pub mr_enclave: Option<[u8; 32]>,
+    /// MRSIGNER of an SGX provider.
+    pub mr_signer: Option<[u8; 32]>,
+    /// MRTD of a provider running in a TD.
+    pub mr_td: Option<[u8; 48]>,
+}
+
+impl ExpectedProvider {
+    /// From hex-encoded measurements, as given on a command line.
+    pub fn from_hex(
+        mr_enclave: Option<&str>,
+        mr_signer: Option<&str>,
+        mr_td: Option<&str>,
+    ) -> Result<Self, ClientError> {
+        let expected = Self {
+            mr_enclave: decode_measurement("MRENCLAVE", mr_enclave)?,
+            mr_signer: decode_measurement("MRSIGNER", mr_signer)?,
+            mr_td: decode_measurement("MRTD", mr_td)?,
+        };
+        expected.check()?;
+        Ok(expected)
+    }
+
+    /// Refuses an expectation without any measurement.
+    fn check(&self) -> Result<(), ClientError> {
+        if self.mr_enclave.is_none() && self.mr_signer.is_none() && self.mr_td.is_none() {
+            return Err(ClientError::ConfigError(
+                "No provider measurement expected: give the MRENCLAVE, MRSIGNER or MRTD of the provider".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where the collateral to verify a provider quote's signature chain against
+/// comes from: Intel PCS or a PCCS, fetched by the native client, or the
+/// caller.
+pub trait CollateralSource {
+    fn collateral(&self, quote: &[u8]) -> Result<QuoteCollateralV3, ClientError>;
+
+    /// Seconds since the Unix epoch, at which the collateral must be current.
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// Collateral the caller fetched itself, for example where the client cannot
+/// reach PCS (`wasm32`), with the time to verify at.
+pub struct FetchedCollateral {
+    pub collateral: QuoteCollateralV3,
+    pub now: u64,
+}
+
+impl FetchedCollateral {
+    /// From collateral as PCS serves it, in the JSON form dcap-qvl uses.
+    pub fn from_json(json: &[u8], now: u64) -> Result<Self, ClientError> {
+        Ok(Self {
+            collateral: serde_json::from_slice(json)?,
+            now,
+        })
+    }
+}
+
+impl CollateralSource for FetchedCollateral {
+    fn collateral(&self, _quote: &[u8]) -> Result<QuoteCollateralV3, ClientError> {
+        Ok(self.collateral.clone())
+    }
+
+    fn now(&self) -> u64 {
+        self.now
+    }
+}
+
+fn decode_measurement<const N: usize>(
+    name: &str,
+    value: Option<&str>,
+) -> Result<Option<[u8; N]>, ClientError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(Some)
+        .ok_or_else(|| ClientError::ConfigError(format!("{} must be {} hex bytes", name, N)))
+}
+
+/// Checks that the provider quote's report data is
+/// `SHA-256(encrypted_key) | metadata hash` over the fields the provider
+/// binds for a plain key response, that the quote is from the expected
+/// provider, and that its signature chain verifies against collateral from
+/// `collateral`.
+pub fn verify_response(
+    response: &QuoteResponse,
+    nonce: &[u8],
+    expected: &ExpectedProvider,
+    collateral: &dyn CollateralSource,
+) -> Result<(), ClientError> {
+    check_provider_quote(
+        &response.provider_quote,
+        &expected_report_data(response, nonce),
+        expected,
+        collateral,
+    )
+}
+
+/// Checks that `provider_quote` is from the expected provider, carries
+/// `report_data` and verifies against collateral from `collateral`. A quote
+/// that cannot be verified, for want of collateral or otherwise, is refused,
+/// as is one from a debug enclave or TD, whose memory the host can read.
+pub(crate) fn check_provider_quote(
+    provider_quote: &[u8],
+    report_data: &[u8; 64],
+    expected: &ExpectedProvider,
+    collateral: &dyn CollateralSource,
+) -> Result<(), ClientError> {
+    expected.check()?;
+    let quote = Quote::parse(provider_quote)
+        .map_err(|e| ClientError::BindingError(format!("Cannot parse provider quote: {}", e)))?;
+    let actual: &[u8] = match &quote.report {
+        Report::SgxEnclave(report) => {
+            if expected.mr_enclave.is_none() && expected.mr_signer.is_none() {
+                return Err(ClientError::BindingError(
+                    "provider is an SGX enclave, but no MRENCLAVE or MRSIGNER is expected".into(),
+                ));
+            }
+            if report.attributes[0] & SGX_FLAGS_DEBUG != 0 {
+                return Err(ClientError::BindingError(
+                    "provider is a debug enclave".into(),
+                ));
+            }
+            check_measurement("MRENCLAVE", expected.mr_enclave, &report.mr_enclave)?;
+            check_measurement("MRSIGNER", expected.mr_signer, &report.mr_signer)?;
+            &report.report_data
+        }
+        Report::TD10(report) => {
+            check_td(expected, &report.td_attributes, &report.mr_td)?;
+            &report.report_data
+        }
+        Report::TD15(report) => {
+            check_td(expected, &report.base.td_attributes, &report.base.mr_td)?;
+            &report.base.report_data
+        }
+    };
+
+    if actual != report_data {
+        return Err(ClientError::BindingError(
+            "report data does not match the response".into(),
+        ));
+    }
+
+    let verified = verify(
+        provider_quote,
+        &collateral.collateral(provider_quote)?,
+        collateral.now(),
+    )
+    .map_err(|e| ClientError::BindingError(format!("Provider quote does not verify: {}", e)))?;
+    if verified.status == "Revoked" {
+        return Err(ClientError::BindingError(
+            "Provider platform is revoked".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_td(
+    expected: &ExpectedProvider,
+    td_attributes: &[u8; 8],
+    mr_td: &[u8; 48],
+) -> Result<(), ClientError> {
+    if expected.mr_td.is_none() {
+        return Err(ClientError::BindingError(
+            "provider is a TD, but no MRTD is expected".into(),
+        ));
+    }
+    if td_attributes[0] & TD_ATTRIBUTES_DEBUG != 0 {
+        return Err(ClientError::BindingError("provider is a debug TD".into()));
+    }
+    check_measurement("MRTD", expected.mr_td, mr_td)
+}
+
+fn check_measurement<const N: usize>(
+    name: &str,
+    expected: Option<[u8; N]>,
+    actual: &[u8; N],
+) -> Result<(), ClientError> {
+    match expected {
+        Some(expected) if &expected != actual => Err(ClientError::BindingError(format!(
+            "{} is {}, expected {}",
+            name,
+            hex::encode(actual),
+            hex::encode(expected)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The report data the provider quote must carry for `response`.
+pub fn expected_report_data(response: &QuoteResponse, nonce: &[u8]) -> [u8; 64] {
+    let mut fields: Vec<(&str, &[u8])> = vec![
+        ("nonce", nonce),
+        ("suite", response.suite.as_bytes()),
+        ("key_confirmation", response.key_confirmation.as_slice()),
+    ];
+    for ciphertext in &response.recipient_keys {
+        fields.push(("recipient_key", ciphertext.as_slice()));
+    }
+    if let Some(tcb) = &response.platform_tcb {
+        fields.push(("tcb_status", tcb.status.as_bytes()));
+        for advisory in &tcb.advisory_ids {
+            fields.push(("advisory_id", advisory.as_bytes()));
+        }
+    }
+    if let Some(tenant) = &response.tenant {
+        fields.push(("tenant", tenant.as_bytes()));
+    }
+    bound_report_data(&response.encrypted_key, &fields)
+}
+
+/// `SHA-256(first) | metadata hash`, the metadata hash covering `fields`
+/// the way the provider binds response fields.
+pub(crate) fn bound_report_data(first: &[u8], fields: &[(&str, &[u8])]) -> [u8; 64] {
+    let mut metadata = Sha256::new();
+    metadata.update(METADATA_LABEL);
+    for (name, value) in fields {
+        metadata.update([name.len() as u8]);
+        metadata.update(name.as_bytes());
+        metadata.update((value.len() as u32).to_be_bytes());
+        metadata.update(value);
+    }
+
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(&Sha256::digest(first));
+    report_data[32..].copy_from_slice(&metadata.finalize());
+    report_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PlatformTcb;
+
+    #[test]
+    fn report_data_covers_every_bound_field() {
+        let response = |status: &str| QuoteResponse {
+            suite: "x25519-sealedbox".into(),
+            encrypted_key: b"ciphertext".to_vec(),
+            provider_quote: Vec::new(),
+            recipient_keys: Vec::new(),
+            key_confirmation: vec![1; 32],
+            platform_tcb: Some(PlatformTcb {
+                status: status.into(),
+                advisory_ids: Vec::new(),
+            }),
+            tenant: None,
+        };
+
+        let report_data = expected_report_data(&response("UpToDate"), b"nonce");
+
+        assert_eq!(&report_data[..32], Sha256::digest(b"ciphertext").as_slice());
+        assert_ne!(
+            report_data,
+            expected_report_data(&response("OutOfDate"), b"nonce")
+        );
+        assert_ne!(
+            report_data,
+            expected_report_data(&response("UpToDate"), b"other")
+        );
+    }
+
+    struct Unreachable;
+
+    impl CollateralSource for Unreachable {
+        fn collateral(&self, _quote: &[u8]) -> Result<QuoteCollateralV3, ClientError> {
+            Err(ClientError::CollateralError("unreachable".into()))
+        }
+    }
+
+    /// The sample TDX quote, made to carry `report_data`, with its signature
+    /// no longer covering it, and the MRTD it is from.
+    fn sample_quote(report_data: [u8; 64]) -> (Vec<u8>, ExpectedProvider) {
+        let mut quote = include_bytes!("../../quotes/tdxQuote.txt").to_vec();
+        let Report::TD10(report) = Quote::parse(&quote).unwrap().report else {
+            unreachable!("the sample is a TDX 1.0 quote")
+        };
+        let at = quote
+            .windows(64)
+            .position(|w| w == report.report_data)
+            .unwrap();
+        quote[at..at + 64].copy_from_slice(&report_data);
+        let expected = ExpectedProvider {
+            mr_td: Some(report.mr_td),
+            ..Default::default()
+        };
+        (quote, expected)
+    }
+
+    #[test]
+    fn quotes_that_cannot_be_verified_are_refused() {
+        let (quote, expected) = sample_quote([7; 64]);
+
+        let checked = check_provider_quote(&quote, &[7; 64], &expected, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::CollateralError(_))));
+        let checked = check_provider_quote(&quote, &[8; 64], &expected, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::BindingError(_))));
+    }
+
+    #[test]
+    fn some_measurement_must_be_expected() {
+        assert!(matches!(
+            ExpectedProvider::from_hex(None, None, None),
+            Err(ClientError::ConfigError(_))
+        ));
+        let (quote, _) = sample_quote([7; 64]);
+        let checked = check_provider_quote(&quote, &[7; 64], &Default::default(), &Unreachable);
+        assert!(matches!(checked, Err(ClientError::ConfigError(_))));
+
+        // An SGX expectation does not admit a TD
+        let sgx = ExpectedProvider {
+            mr_signer: Some([1; 32]),
+            ..Default::default()
+        };
+        let checked = check_provider_quote(&quote, &[7; 64], &sgx, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::BindingError(_))));
+    }
+
+    #[test]
+    fn debug_providers_are_refused() {
+        // TD attributes follow the 48-byte header, TEE_TCB_SVN, MRSEAM,
+        // MRSIGNERSEAM and SEAMATTRIBUTES
+        let (mut quote, expected) = sample_quote([7; 64]);
+        quote[168] |= TD_ATTRIBUTES_DEBUG;
+        let checked = check_provider_quote(&quote, &[7; 64], &expected, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::BindingError(e)) if e.contains("debug")));
+
+        let report = |attributes: u8| {
+            // A v3 SGX quote: header, enclave report, then empty
+            // authentication data behind its length
+            let mut quote = vec![0; 48 + 384];
+            quote[0] = 3;
+            quote[2] = 2;
+            quote[48 + 48] = attributes;
+            quote[48 + 64..48 + 96].copy_from_slice(&[3; 32]);
+            quote[48 + 320..].copy_from_slice(&[7; 64]);
+            let auth_data = 64 + 64 + 384 + 64 + 2 + 2 + 4;
+            quote.extend_from_slice(&(auth_data as u32).to_le_bytes());
+            quote.resize(quote.len() + auth_data, 0);
+            quote
+        };
+        let expected = ExpectedProvider {
+            mr_enclave: Some([3; 32]),
+            ..Default::default()
+        };
+        let checked = check_provider_quote(&report(0), &[7; 64], &expected, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::CollateralError(_))));
+        let checked =
+            check_provider_quote(&report(SGX_FLAGS_DEBUG), &[7; 64], &expected, &Unreachable);
+        assert!(matches!(checked, Err(ClientError::BindingError(e)) if e.contains("debug")));
+    }
+}
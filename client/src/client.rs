@@ -0,0 +1,335 @@
+use crate::attestation::QuoteSource;
+use crate::binding::{CollateralSource, ExpectedProvider};
+use crate::collateral::PcsCollateral;
+use crate::error::ClientError;
+use crate::exchange::{DerivedKey, Exchange};
+use crate::protocol::{SpiffeRequest, SpiffeResponse};
+use crate::spiffe;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Provider responses are a few quotes at most.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Kept short so an unreachable provider does not hold up the next one.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a provider that failed is tried after the others.
+const HEALTH_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Requests the guest's sealing key from a provider.
+///
+/// Each request generates a fresh X25519 keypair, commits to its public key
+/// in the first 32 bytes of the report data, quotes the guest, and sends the
+/// quote with a fresh nonce. The response is accepted only if the provider
+/// quote binds it and verifies against collateral from Intel PCS (or a
+/// PCCS), and the key confirmation matches the decrypted key.
+pub struct SealingKeyClient {
+    addresses: Vec<String>,
+    expected: ExpectedProvider,
+    collateral: Box<dyn CollateralSource + Send + Sync>,
+    timeout: Duration,
+    connect_timeout: Duration,
+    explain: bool,
+    tenant: Option<String>,
+    retry: Retry,
+    /// When each provider last could not be reached or failed mid-exchange.
+    failures: Mutex<HashMap<String, Instant>>,
+}
+
+/// How often to go through the providers when none of them answers.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    /// Rounds over all providers, at least one.
+    pub attempts: u32,
+    /// Pause after the first failed round, doubled after each further one.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Pause a random time between half the backoff and all of it, so TDs
+    /// that booted together do not retry in step.
+    pub jitter: bool,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            jitter: true,
+        }
+    }
+}
+
+impl Retry {
+    fn pause(&self, backoff: Duration) -> Duration {
+        if !self.jitter || backoff.is_zero() {
+            return backoff;
+        }
+        backoff.mul_f64(rand::random::<f64>() / 2.0 + 0.5)
+    }
+}
+
+impl SealingKeyClient {
+    /// A client for the provider at `address` (`host:port`).
+    pub fn new(address: &str) -> Self {
+        Self {
+            addresses: vec![address.to_string()],
+            expected: ExpectedProvider::default(),
+            collateral: Box::new(PcsCollateral::default()),
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            explain: false,
+            tenant: None,
+            retry: Retry::default(),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A client that tries `addresses` in order; see [`SealingKeyClient::fallback`].
+    pub fn failover(addresses: &[String]) -> Result<Self, ClientError> {
+        let (first, fallbacks) = addresses
+            .split_first()
+            .ok_or_else(|| ClientError::ConfigError("No provider address given".into()))?;
+        Ok(fallbacks
+            .iter()
+            .fold(Self::new(first), |client, address| client.fallback(address)))
+    }
+
+    /// Also try the provider at `address`, in order, when the ones before it
+    /// are unreachable or fail mid-exchange. A provider that failed within
+    /// the last minute is tried after the others, by this request and later
+    /// ones. A refusal or a response that fails verification is not retried
+    /// anywhere: providers of one fleet share their policy.
+    pub fn fallback(mut self, address: &str) -> Self {
+        self.addresses.push(address.to_string());
+        self
+    }
+
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Only accept responses from a provider with these measurements.
+    pub fn expect(mut self, expected: ExpectedProvider) -> Self {
+        self.expected = expected;
+        self
+    }
+
+    /// Verify provider quotes against collateral from `source` in place of
+    /// Intel PCS.
+    pub fn collateral(mut self, source: impl CollateralSource + Send + Sync + 'static) -> Self {
+        self.collateral = Box::new(source);
+        self
+    }
+
+    /// Gives up on sending or receiving after `timeout`, and on connecting
+    /// after it too if it is shorter than the connect timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gives up on connecting to an address after `timeout`, 5 seconds by
+    /// default.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Ask the provider to say why it refuses a request, as
+    /// [`ClientError::Rejected`], instead of just closing the connection.
+    pub fn explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Request the key of `tenant` from a shared provider.
+    pub fn tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn request_key(&self, source: &dyn QuoteSource) -> Result<DerivedKey, ClientError> {
+        self.with_failover(|address| self.request_from(address, source))
+    }
+
+    /// Has the providers attest a SPIRE agent's quote, for a node attestor
+    /// plugin; see [`crate::spiffe`]. The response is checked against the
+    /// provider quote.
+    pub fn attest_node(&self, request: &SpiffeRequest) -> Result<SpiffeResponse, ClientError> {
+        let frame = serde_json::to_vec(request)?;
+        self.with_failover(|address| {
+            let response: SpiffeResponse = serde_json::from_slice(&self.send(address, &frame)?)?;
+            spiffe::verify_response(&response, request, &self.expected, self.collateral.as_ref())?;
+            Ok(response)
+        })
+    }
+
+    /// Runs `attempt` against each provider, healthy ones first, for as many
+    /// rounds as `retry` allows, until one succeeds or fails for good.
+    fn with_failover<T>(
+        &self,
+        attempt: impl Fn(&str) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut backoff = self.retry.initial_backoff;
+        let mut round = 1;
+        loop {
+            let mut last_error = None;
+            for address in self.by_health() {
+                match attempt(address) {
+                    Ok(result) => {
+                        self.lock_failures().remove(address);
+                        return Ok(result);
+                    }
+                    Err(e) if e.is_transient() => {
+                        self.lock_failures()
+                            .insert(address.to_string(), Instant::now());
+                        last_error = Some(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if round >= self.retry.attempts {
+                return Err(last_error.expect("a client has at least one address"));
+            }
+            thread::sleep(self.retry.pause(backoff));
+            backoff = (backoff * 2).min(self.retry.max_backoff);
+            round += 1;
+        }
+    }
+
+    /// The addresses in order, those that failed recently last.
+    fn by_health(&self) -> Vec<&str> {
+        let failures = self.lock_failures();
+        let mut addresses: Vec<&str> = self.addresses.iter().map(String::as_str).collect();
+        addresses.sort_by_key(|address| {
+            failures
+                .get(*address)
+                .is_some_and(|failed| failed.elapsed() < HEALTH_COOLDOWN)
+        });
+        addresses
+    }
+
+    fn lock_failures(&self) -> std::sync::MutexGuard<'_, HashMap<String, Instant>> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn request_from(
+        &self,
+        address: &str,
+        source: &dyn QuoteSource,
+    ) -> Result<DerivedKey, ClientError> {
+        let mut exchange = Exchange::new()?;
+        if let Some(tenant) = &self.tenant {
+            exchange = exchange.tenant(tenant);
+        }
+        let quote = source.quote(&exchange.report_data())?;
+        let response = self.send(address, &exchange.request(&quote, self.explain)?)?;
+        exchange.open(&response, &self.expected, self.collateral.as_ref())
+    }
+
+    /// Sends one length-prefixed frame to `address` and reads the answer.
+    fn send(&self, address: &str, request: &[u8]) -> Result<Vec<u8>, ClientError> {
+        let mut socket = self.connect(address)?;
+        socket.set_read_timeout(Some(self.timeout))?;
+        socket.set_write_timeout(Some(self.timeout))?;
+
+        socket.write_all(&(request.len() as u32).to_be_bytes())?;
+        socket.write_all(request)?;
+
+        let mut len = [0u8; 4];
+        match socket.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(ClientError::Refused),
+            Err(e) => return Err(ClientError::IOError(e)),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(ClientError::ProtocolError(format!(
+                "Response of {} bytes is too large",
+                len
+            )));
+        }
+        let mut response = vec![0u8; len];
+        socket.read_exact(&mut response)?;
+        Ok(response)
+    }
+
+    /// Connects to the first of the addresses `address` resolves to that
+    /// answers.
+    fn connect(&self, address: &str) -> Result<TcpStream, ClientError> {
+        let timeout = self.connect_timeout.min(self.timeout);
+        let mut last_error = None;
+        for resolved in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&resolved, timeout) {
+                Ok(socket) => return Ok(socket),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => ClientError::IOError(e),
+            None => ClientError::ProtocolError(format!("{} does not resolve", address)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingSource(std::sync::atomic::AtomicUsize);
+
+    impl QuoteSource for CountingSource {
+        fn quote(&self, _report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(b"quote".to_vec())
+        }
+    }
+
+    #[test]
+    fn unreachable_providers_are_retried_in_rounds() {
+        // Nothing listens on port 1 of the loopback address
+        let client = SealingKeyClient::new("127.0.0.1:1")
+            .fallback("127.0.0.1:1")
+            .retry(Retry {
+                attempts: 3,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+                jitter: false,
+            });
+        let source = CountingSource(Default::default());
+
+        let result = client.request_key(&source);
+
+        assert!(matches!(result, Err(ClientError::IOError(_))));
+        assert_eq!(source.0.into_inner(), 6);
+    }
+
+    #[test]
+    fn failed_providers_are_tried_last() {
+        let client = SealingKeyClient::failover(&["kp1".into(), "kp2".into()]).unwrap();
+        let tried = Mutex::new(Vec::new());
+        let attempt = |address: &str| {
+            tried.lock().unwrap().push(address.to_string());
+            match address {
+                "kp1" => Err(ClientError::ProtocolError("down".into())),
+                _ => Ok(()),
+            }
+        };
+
+        client.with_failover(attempt).unwrap();
+        client.with_failover(attempt).unwrap();
+
+        assert_eq!(*tried.lock().unwrap(), ["kp1", "kp2", "kp2"]);
+        let retry = Retry::default();
+        let pause = retry.pause(Duration::from_secs(2));
+        assert!(pause >= Duration::from_secs(1) && pause <= Duration::from_secs(2));
+    }
+}
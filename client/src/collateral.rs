@@ -0,0 +1,45 @@
+//! Collateral for verifying provider quotes, fetched from Intel PCS or a
+//! PCCS for each response.
+
+use crate::binding::CollateralSource;
+use crate::error::ClientError;
+use dcap_qvl::collateral::{get_collateral, get_collateral_from_pcs};
+use dcap_qvl::QuoteCollateralV3;
+
+/// Fetches collateral from Intel PCS, or from a PCCS if one is given.
+#[derive(Clone, Debug, Default)]
+pub struct PcsCollateral {
+    pccs_url: Option<String>,
+}
+
+impl PcsCollateral {
+    /// Fetch from the PCCS at `url` (such as `https://pccs.local:8081`) in
+    /// place of Intel PCS.
+    pub fn pccs(url: &str) -> Self {
+        Self {
+            pccs_url: Some(url.to_string()),
+        }
+    }
+}
+
+impl CollateralSource for PcsCollateral {
+    fn collateral(&self, quote: &[u8]) -> Result<QuoteCollateralV3, ClientError> {
+        // The client is blocking; a runtime lives only as long as one fetch
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let fetched = runtime.block_on(async {
+            match &self.pccs_url {
+                Some(url) => get_collateral(url, quote).await,
+                None => get_collateral_from_pcs(quote).await,
+            }
+        });
+        fetched.map_err(|e| {
+            ClientError::CollateralError(format!(
+                "{}: {}",
+                self.pccs_url.as_deref().unwrap_or("Intel PCS"),
+                e
+            ))
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("Attestation error: {0}")]
+    AttestationError(String),
+
+    #[error("Protocol error: {0}")]
+    ProtocolError(String),
+
+    /// The provider closed the connection instead of answering, which is how
+    /// it refuses a key request.
+    #[error("Provider refused the request")]
+    Refused,
+
+    /// The provider explained why it refused the request. The reason is not
+    /// covered by a provider quote, so it is only a diagnostic.
+    #[error("Provider refused the request: {0}")]
+    Rejected(String),
+
+    /// The collateral to verify the provider quote against could not be
+    /// fetched.
+    #[error("Collateral error: {0}")]
+    CollateralError(String),
+
+    #[error("Provider quote does not vouch for the response: {0}")]
+    BindingError(String),
+
+    /// The key no longer matches what a volume or credential was bound to:
+    /// the TD's measurements or the provider's root key changed.
+    #[error("Key epoch changed: bound to key {bound}, provider now returns key {current}")]
+    KeyEpochChanged { bound: String, current: String },
+
+    #[error("Crypto error: {0}")]
+    CryptoError(String),
+
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+impl ClientError {
+    /// Whether trying again, or trying another provider, may succeed. A
+    /// refusal or a response that fails verification is final.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ClientError::IOError(_)
+                | ClientError::ProtocolError(_)
+                | ClientError::JsonError(_)
+                | ClientError::CollateralError(_)
+        )
+    }
+}
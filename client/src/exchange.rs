@@ -0,0 +1,195 @@
+//! The guest's side of one key exchange, without any transport: what to
+//! quote, the request to send, and how to check and open the response. It
+//! needs neither libsodium nor the OS, so it also builds for `wasm32`.
+
+use crate::binding::{verify_response, CollateralSource, ExpectedProvider};
+use crate::error::ClientError;
+use crate::protocol::{PlatformTcb, QuoteRequest, QuoteResponse, RejectionResponse};
+use crypto_box::{PublicKey, SecretKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const SUITE: &str = "x25519-sealedbox";
+const KEY_CONFIRMATION_LABEL: &[u8] = b"gramine-sealing-key-provider/key-confirmation/v1";
+const CONTEXT_LABEL: &[u8] = b"gramine-sealing-key-client/context/v1";
+const NONCE_LEN: usize = 32;
+
+/// A key received from the provider. The key bytes are wiped on drop.
+pub struct DerivedKey {
+    key: Zeroizing<Vec<u8>>,
+    /// The provider quote that vouched for the response, for callers that
+    /// keep or forward it.
+    pub provider_quote: Vec<u8>,
+    /// TCB level of the provider's platform, when it has SGX attestation.
+    pub platform_tcb: Option<PlatformTcb>,
+    /// Tenant of a shared provider the key was derived for.
+    pub tenant: Option<String>,
+}
+
+impl DerivedKey {
+    #[cfg(test)]
+    pub(crate) fn from_bytes(key: Vec<u8>) -> Self {
+        Self {
+            key: Zeroizing::new(key),
+            provider_quote: Vec::new(),
+            platform_tcb: None,
+            tenant: None,
+        }
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// A 32-byte key for one purpose, so a workload can use its single
+    /// derived key for several things (a disk, a database, a token) without
+    /// reusing it: `HKDF-Expand(key, "gramine-sealing-key-client/context/v1"
+    /// || context, 32)`. The provider is not involved.
+    pub fn subkey(&self, context: &[u8]) -> Result<Zeroizing<[u8; 32]>, ClientError> {
+        let hkdf = Hkdf::<Sha256>::from_prk(&self.key)
+            .map_err(|_| ClientError::CryptoError("Derived key is too short".into()))?;
+        let mut subkey = Zeroizing::new([0u8; 32]);
+        hkdf.expand_multi_info(&[CONTEXT_LABEL, context], &mut subkey[..])
+            .map_err(|e| ClientError::CryptoError(e.to_string()))?;
+        Ok(subkey)
+    }
+}
+
+/// The guest's half of one key request: a fresh X25519 keypair, whose public
+/// key the guest's quote commits to, and the nonce the response must bind.
+/// For callers that quote and talk to the provider themselves; otherwise use
+/// [`SealingKeyClient`].
+pub struct Exchange {
+    public_key: PublicKey,
+    secret_key: SecretKey,
+    nonce: [u8; NONCE_LEN],
+    tenant: Option<String>,
+}
+
+impl Exchange {
+    pub fn new() -> Result<Self, ClientError> {
+        let secret_key = SecretKey::generate(&mut OsRng);
+        let public_key = secret_key.public_key();
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng
+            .try_fill_bytes(&mut nonce)
+            .map_err(|e| ClientError::CryptoError(format!("No randomness: {}", e)))?;
+        Ok(Self {
+            public_key,
+            secret_key,
+            nonce,
+            tenant: None,
+        })
+    }
+
+    /// Ask a shared provider for the key of `tenant`, and accept only a
+    /// response that binds it.
+    pub fn tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// The report data to quote: the public key, then zeros.
+    pub fn report_data(&self) -> [u8; 64] {
+        let mut report_data = [0u8; 64];
+        report_data[..32].copy_from_slice(self.public_key.as_bytes());
+        report_data
+    }
+
+    /// The request frame body for `quote`, which must be over
+    /// [`Exchange::report_data`].
+    pub fn request(&self, quote: &[u8], explain: bool) -> Result<Vec<u8>, ClientError> {
+        Ok(serde_json::to_vec(&QuoteRequest {
+            quote,
+            nonce: &self.nonce,
+            suites: &[SUITE],
+            explain,
+            tenant: self.tenant.as_deref(),
+        })?)
+    }
+
+    /// Checks the provider's response frame body against the provider quote
+    /// and `expected`, verifies the quote against collateral from
+    /// `collateral`, then decrypts the key.
+    pub fn open(
+        &self,
+        response: &[u8],
+        expected: &ExpectedProvider,
+        collateral: &dyn CollateralSource,
+    ) -> Result<DerivedKey, ClientError> {
+        if let Ok(rejection) = serde_json::from_slice::<RejectionResponse>(response) {
+            return Err(ClientError::Rejected(rejection.error));
+        }
+        let response: QuoteResponse = serde_json::from_slice(response)?;
+
+        if response.suite != SUITE {
+            return Err(ClientError::ProtocolError(format!(
+                "Provider answered with suite {}, not {}",
+                response.suite, SUITE
+            )));
+        }
+        if self.tenant.is_some() && response.tenant != self.tenant {
+            return Err(ClientError::BindingError(format!(
+                "Provider derived the key for tenant {:?}, not {:?}",
+                response.tenant, self.tenant
+            )));
+        }
+        verify_response(&response, &self.nonce, expected, collateral)?;
+
+        let key = Zeroizing::new(
+            self.secret_key
+                .unseal(&response.encrypted_key)
+                .map_err(|_| ClientError::CryptoError("Cannot open the encrypted key".into()))?,
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key)
+            .map_err(|e| ClientError::CryptoError(e.to_string()))?;
+        mac.update(KEY_CONFIRMATION_LABEL);
+        mac.verify_slice(&response.key_confirmation).map_err(|_| {
+            ClientError::CryptoError("Key confirmation does not match the decrypted key".into())
+        })?;
+
+        Ok(DerivedKey {
+            key,
+            provider_quote: response.provider_quote,
+            platform_tcb: response.platform_tcb,
+            tenant: response.tenant,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subkeys_are_separated_by_context() {
+        let key = DerivedKey {
+            key: Zeroizing::new(vec![9; 32]),
+            provider_quote: Vec::new(),
+            platform_tcb: None,
+            tenant: None,
+        };
+
+        let disk = key.subkey(b"disk").unwrap();
+
+        assert_eq!(*disk, *key.subkey(b"disk").unwrap());
+        assert_ne!(*disk, *key.subkey(b"database").unwrap());
+        assert_ne!(disk[..], key.expose()[..]);
+    }
+
+    #[test]
+    fn sealed_keys_open_with_the_exchange_key() {
+        let exchange = Exchange::new().unwrap();
+        let public_key =
+            PublicKey::from(<[u8; 32]>::try_from(&exchange.report_data()[..32]).unwrap());
+
+        let sealed = public_key.seal(&mut OsRng, &[7; 32]).unwrap();
+
+        assert_eq!(exchange.secret_key.unseal(&sealed).unwrap(), [7; 32]);
+        assert!(exchange.report_data()[32..].iter().all(|&b| b == 0));
+    }
+}
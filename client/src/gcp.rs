@@ -0,0 +1,102 @@
+//! Attestation tokens for workloads in GCP Confidential Space, which run in
+//! a TD but get no quote: the launcher's TEE server hands out OIDC tokens
+//! from Google's attestation verifier instead. The provider takes a token in
+//! place of a quote.
+
+use crate::attestation::QuoteSource;
+use crate::error::ClientError;
+use serde_json::json;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The launcher's TEE server socket, mounted into the workload container.
+pub const TEE_SERVER_SOCKET: &str = "/run/container_launcher/teeserver.sock";
+/// The audience the provider expects unless configured otherwise.
+pub const DEFAULT_AUDIENCE: &str = "gramine-sealing-key-provider";
+const TOKEN_PATH: &str = "/v1/token";
+const TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+/// Tokens from the Confidential Space launcher. The report data goes into
+/// the token as two 32-byte hex nonces.
+pub struct ConfidentialSpace {
+    socket: PathBuf,
+    audience: String,
+}
+
+impl ConfidentialSpace {
+    pub fn new(audience: &str) -> Self {
+        Self {
+            socket: PathBuf::from(TEE_SERVER_SOCKET),
+            audience: audience.to_string(),
+        }
+    }
+
+    pub fn is_available() -> bool {
+        Path::new(TEE_SERVER_SOCKET).exists()
+    }
+}
+
+impl QuoteSource for ConfidentialSpace {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+        let body = json!({
+            "audience": self.audience,
+            "token_type": "OIDC",
+            "nonces": token_nonces(report_data),
+        })
+        .to_string();
+
+        let mut socket = UnixStream::connect(&self.socket)?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        write!(
+            socket,
+            "POST {} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            TOKEN_PATH,
+            body.len(),
+            body
+        )?;
+        let mut response = Vec::new();
+        socket.take(MAX_RESPONSE_LEN).read_to_end(&mut response)?;
+
+        // HTTP/1.0, so the body is never chunked
+        let response = String::from_utf8_lossy(&response);
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| ClientError::AttestationError("Malformed TEE server response".into()))?;
+        let token = body.trim();
+        if head.split(' ').nth(1) != Some("200") || !token.starts_with("eyJ") {
+            return Err(ClientError::AttestationError(format!(
+                "TEE server did not issue a token: {}",
+                token
+            )));
+        }
+        Ok(token.as_bytes().to_vec())
+    }
+}
+
+/// The report data as the two nonces the provider reads it back from.
+fn token_nonces(report_data: &[u8; 64]) -> [String; 2] {
+    [
+        hex::encode(&report_data[..32]),
+        hex::encode(&report_data[32..]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_data_splits_into_two_nonces() {
+        let mut report_data = [0x11; 64];
+        report_data[32..].fill(0x22);
+
+        let nonces = token_nonces(&report_data);
+
+        assert_eq!(nonces[0], "11".repeat(32));
+        assert_eq!(nonces[1], "22".repeat(32));
+    }
+}
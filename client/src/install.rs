@@ -0,0 +1,276 @@
+use crate::error::ClientError;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+/// The system credential store under `/run`, which systemd searches for
+/// `ImportCredential=` and for `LoadCredential=` without a path.
+pub const CREDSTORE_DIR: &str = "/run/credstore";
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+const RAMFS_MAGIC: i64 = 0x8584_58f6;
+
+/// Where a fetched key is put in the guest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A file on a tmpfs or ramfs, readable only by its owner, so the key
+    /// never reaches persistent storage.
+    File(PathBuf),
+    /// A key in a kernel keyring: `logon` keys (which user space cannot read
+    /// back, for dm-crypt and fscrypt; their description needs a `prefix:`),
+    /// `user` keys, or `encrypted` keys, which the kernel keeps encrypted
+    /// under a master key such as a TPM-sealed `trusted` key.
+    Keyring {
+        key_type: String,
+        description: String,
+        options: KeyringOptions,
+    },
+    /// A systemd credential of this name in [`CREDSTORE_DIR`]. Services
+    /// receive it with `ImportCredential=` or `LoadCredential=`, in their own
+    /// non-swappable credentials directory.
+    Credential(String),
+}
+
+/// Where and how a keyring target's key is added.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyringOptions {
+    /// `@u` (the default), `@s`, `@us`, `@p` or `@t`, as `keyctl` names them.
+    pub keyring: String,
+    /// Permission mask set on the key, as `keyctl setperm` takes it.
+    pub permissions: Option<u32>,
+    /// `<type>:<description>` of the master key of an `encrypted` key, such
+    /// as `trusted:kmk`.
+    pub master_key: Option<String>,
+}
+
+impl Default for KeyringOptions {
+    fn default() -> Self {
+        Self {
+            keyring: "@u".to_string(),
+            permissions: None,
+            master_key: None,
+        }
+    }
+}
+
+impl KeyringOptions {
+    fn keyring_id(&self) -> Result<libc::c_long, ClientError> {
+        let id = match self.keyring.as_str() {
+            "@u" => libc::KEY_SPEC_USER_KEYRING,
+            "@s" => libc::KEY_SPEC_SESSION_KEYRING,
+            "@us" => libc::KEY_SPEC_USER_SESSION_KEYRING,
+            "@p" => libc::KEY_SPEC_PROCESS_KEYRING,
+            "@t" => libc::KEY_SPEC_THREAD_KEYRING,
+            other => {
+                return Err(ClientError::ConfigError(format!(
+                    "Keyring {:?} is not @u, @s, @us, @p or @t",
+                    other
+                )))
+            }
+        };
+        Ok(id.into())
+    }
+}
+
+/// Parses a `keyctl` permission mask, such as `0x3f010000`.
+pub fn parse_permissions(mask: &str) -> Result<u32, ClientError> {
+    let digits = mask.strip_prefix("0x").unwrap_or(mask);
+    u32::from_str_radix(digits, 16).map_err(|_| {
+        ClientError::ConfigError(format!("Key permissions {:?} are not a hex mask", mask))
+    })
+}
+
+impl Target {
+    /// Applies `options` to a keyring target; other targets ignore them.
+    pub fn with_keyring_options(mut self, new_options: KeyringOptions) -> Self {
+        if let Target::Keyring { options, .. } = &mut self {
+            *options = new_options;
+        }
+        self
+    }
+}
+
+impl FromStr for Target {
+    type Err = ClientError;
+
+    /// `file:<path>`, `keyring:<type>:<description>` or `credential:<name>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("file:") {
+            return Ok(Target::File(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("credential:") {
+            if !name.is_empty() && !name.starts_with('.') && !name.contains('/') {
+                return Ok(Target::Credential(name.to_string()));
+            }
+        }
+        if let Some(key) = s.strip_prefix("keyring:") {
+            if let Some((key_type @ ("logon" | "user" | "encrypted"), description)) =
+                key.split_once(':')
+            {
+                if !description.is_empty() {
+                    return Ok(Target::Keyring {
+                        key_type: key_type.to_string(),
+                        description: description.to_string(),
+                        options: KeyringOptions::default(),
+                    });
+                }
+            }
+        }
+        Err(ClientError::ConfigError(format!(
+            "Install target {:?} is not file:<path>, \
+             keyring:<logon|user|encrypted>:<description> or credential:<name>",
+            s
+        )))
+    }
+}
+
+/// Puts `key` at `target`. Files and credentials must be on a RAM-backed
+/// filesystem unless
+/// `allow_persistent` is set.
+pub fn install(target: &Target, key: &[u8], allow_persistent: bool) -> Result<(), ClientError> {
+    match target {
+        Target::File(path) => {
+            if !allow_persistent {
+                check_ram_backed(path)?;
+            }
+            write_private(path, key)
+        }
+        Target::Keyring {
+            key_type,
+            description,
+            options,
+        } => add_key(key_type, description, key, options),
+        Target::Credential(name) => {
+            let dir = Path::new(CREDSTORE_DIR);
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)?;
+            let path = dir.join(name);
+            if !allow_persistent {
+                check_ram_backed(&path)?;
+            }
+            write_private(&path, key)
+        }
+    }
+}
+
+/// Replaces `path` with `data`, mode 0600, without a window in which a
+/// partial key is visible.
+pub fn write_private(path: &Path, data: &[u8]) -> Result<(), ClientError> {
+    let staged = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&staged)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&staged, path)?;
+    Ok(())
+}
+
+fn check_ram_backed(path: &Path) -> Result<(), ClientError> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let dir_c = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|_| ClientError::ConfigError("Install path contains NUL".into()))?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(dir_c.as_ptr(), &mut stat) } != 0 {
+        return Err(ClientError::IOError(std::io::Error::last_os_error()));
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let kind = stat.f_type as i64;
+    if kind != TMPFS_MAGIC && kind != RAMFS_MAGIC {
+        return Err(ClientError::ConfigError(format!(
+            "{} is not on a tmpfs or ramfs; the key would reach persistent storage",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+fn add_key(
+    key_type: &str,
+    description: &str,
+    key: &[u8],
+    options: &KeyringOptions,
+) -> Result<(), ClientError> {
+    let keyring = options.keyring_id()?;
+    let payload = if key_type == "encrypted" {
+        // Needs CONFIG_USER_DECRYPTED_DATA; the kernel only ever hands out
+        // the key encrypted under the master key
+        let master_key = options.master_key.as_deref().ok_or_else(|| {
+            ClientError::ConfigError("Encrypted keys need a master key, such as trusted:kmk".into())
+        })?;
+        let mut payload =
+            Zeroizing::new(format!("new default {} {} ", master_key, key.len()).into_bytes());
+        payload.extend_from_slice(Zeroizing::new(hex::encode(key)).as_bytes());
+        payload
+    } else {
+        Zeroizing::new(key.to_vec())
+    };
+    let key_type_c = CString::new(key_type)
+        .map_err(|_| ClientError::ConfigError("Key type contains NUL".into()))?;
+    let description_c = CString::new(description)
+        .map_err(|_| ClientError::ConfigError("Key description contains NUL".into()))?;
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            key_type_c.as_ptr(),
+            description_c.as_ptr(),
+            payload.as_ptr(),
+            payload.len(),
+            keyring,
+        )
+    };
+    if serial < 0 {
+        return Err(ClientError::IOError(std::io::Error::last_os_error()));
+    }
+    if let Some(permissions) = options.permissions {
+        if unsafe { libc::syscall(libc::SYS_keyctl, libc::KEYCTL_SETPERM, serial, permissions) } < 0
+        {
+            let error = std::io::Error::last_os_error();
+            // Do not leave the key behind with the default permissions
+            unsafe { libc::syscall(libc::SYS_keyctl, libc::KEYCTL_REVOKE, serial) };
+            return Err(ClientError::IOError(error));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_parse_from_their_command_line_form() {
+        assert_eq!(
+            "file:/run/keys/disk".parse::<Target>().unwrap(),
+            Target::File("/run/keys/disk".into())
+        );
+        assert_eq!(
+            "keyring:logon:skp:disk".parse::<Target>().unwrap(),
+            Target::Keyring {
+                key_type: "logon".into(),
+                description: "skp:disk".into(),
+                options: KeyringOptions::default(),
+            }
+        );
+        assert!("keyring:encrypted:skp-disk".parse::<Target>().is_ok());
+        assert_eq!(parse_permissions("0x3f010000").unwrap(), 0x3f01_0000);
+        assert!(parse_permissions("rw").is_err());
+        assert_eq!(
+            "credential:skp-disk".parse::<Target>().unwrap(),
+            Target::Credential("skp-disk".into())
+        );
+        assert!("credential:../etc/x".parse::<Target>().is_err());
+        assert!("keyring:asymmetric:x".parse::<Target>().is_err());
+        assert!("keyring:logon:".parse::<Target>().is_err());
+        assert!("/run/keys/disk".parse::<Target>().is_err());
+    }
+}
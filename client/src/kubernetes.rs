@@ -0,0 +1,223 @@
+//! Init-container and sidecar support: configuration from pod annotations
+//! and environment, and key files laid out like a projected Secret volume.
+//! Containers outside Kubernetes use the same layout, see [`crate::sidecar`].
+//!
+//! Keys are never written to a Kubernetes Secret: that would put them in
+//! etcd, outside the TD. Instead they go to a volume shared with the
+//! workload (an `emptyDir` with `medium: Memory`), in the layout the kubelet
+//! uses for Secret volumes, so workloads that read mounted Secrets work
+//! unchanged.
+
+use crate::error::ClientError;
+use crate::install::write_private;
+use crate::DerivedKey;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{symlink, DirBuilderExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+/// Annotation prefix; e.g. `skp.gramine.dev/providers`.
+pub const ANNOTATION_PREFIX: &str = "skp.gramine.dev/";
+/// Where the downward API volume usually puts the pod's annotations.
+pub const DEFAULT_ANNOTATIONS_FILE: &str = "/etc/podinfo/annotations";
+const DATA_LINK: &str = "..data";
+
+/// File name and contents of each key to project.
+pub type KeyFiles = Vec<(String, Zeroizing<Vec<u8>>)>;
+
+/// What to fetch and where to put it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub providers: Vec<String>,
+    /// File name in the volume to subkey context. With no entries the key
+    /// itself is written to a file named `key`.
+    pub keys: BTreeMap<String, String>,
+    pub expect_mrenclave: Option<String>,
+    pub expect_mrsigner: Option<String>,
+    pub expect_mrtd: Option<String>,
+}
+
+impl Settings {
+    /// From the `skp.gramine.dev/*` annotations, overridden by `SKP_*`
+    /// environment variables:
+    /// - `providers` / `SKP_PROVIDERS`: comma-separated `host:port`
+    /// - `keys` / `SKP_KEYS`: comma-separated `file=context`
+    /// - `expect-mrenclave`, `expect-mrsigner`, `expect-mrtd` /
+    ///   `SKP_EXPECT_*`: hex
+    pub fn load(
+        annotations: &BTreeMap<String, String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ClientError> {
+        let get = |annotation: &str, var: &str| {
+            env(var).or_else(|| {
+                annotations
+                    .get(&format!("{}{}", ANNOTATION_PREFIX, annotation))
+                    .cloned()
+            })
+        };
+
+        let providers: Vec<String> = get("providers", "SKP_PROVIDERS")
+            .map(|list| split_list(&list).map(str::to_string).collect())
+            .unwrap_or_default();
+        if providers.is_empty() {
+            return Err(ClientError::ConfigError(format!(
+                "No providers: set SKP_PROVIDERS or the {}providers annotation",
+                ANNOTATION_PREFIX
+            )));
+        }
+
+        let mut keys = BTreeMap::new();
+        for entry in split_list(&get("keys", "SKP_KEYS").unwrap_or_default()) {
+            let (file, context) = entry.split_once('=').ok_or_else(|| {
+                ClientError::ConfigError(format!("Key {:?} is not file=context", entry))
+            })?;
+            if file.is_empty() || file.starts_with('.') || file.contains('/') {
+                return Err(ClientError::ConfigError(format!(
+                    "Key file name {:?} must be a plain name",
+                    file
+                )));
+            }
+            keys.insert(file.to_string(), context.to_string());
+        }
+
+        Ok(Self {
+            providers,
+            keys,
+            expect_mrenclave: get("expect-mrenclave", "SKP_EXPECT_MRENCLAVE"),
+            expect_mrsigner: get("expect-mrsigner", "SKP_EXPECT_MRSIGNER"),
+            expect_mrtd: get("expect-mrtd", "SKP_EXPECT_MRTD"),
+        })
+    }
+}
+
+/// The files `settings` asks for: a subkey per file, or the key itself as
+/// `key`.
+pub fn key_files(key: &DerivedKey, settings: &Settings) -> Result<KeyFiles, ClientError> {
+    if settings.keys.is_empty() {
+        return Ok(vec![(
+            "key".to_string(),
+            Zeroizing::new(key.expose().to_vec()),
+        )]);
+    }
+    settings
+        .keys
+        .iter()
+        .map(|(file, context)| {
+            let subkey = key.subkey(context.as_bytes())?;
+            Ok((file.clone(), Zeroizing::new(subkey.to_vec())))
+        })
+        .collect()
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+/// Reads a downward API annotations file: one `key="value"` per line, the
+/// value quoted as a Go string. A missing file means no annotations.
+pub fn read_annotations(path: &Path) -> Result<BTreeMap<String, String>, ClientError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(ClientError::IOError(e)),
+    };
+    Ok(parse_annotations(&text))
+}
+
+fn parse_annotations(text: &str) -> BTreeMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            // The Go quoting of plain annotation values is valid JSON
+            let value = serde_json::from_str(value).unwrap_or_else(|_| value.to_string());
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Atomically replaces the files in `dir` the way the kubelet updates a
+/// Secret volume: the files go to a fresh hidden directory, the `..data`
+/// symlink is swapped to it, and each name is a symlink through `..data`.
+/// Readers see either the old set of keys or the new one.
+pub fn project(dir: &Path, files: &[(String, &[u8])]) -> Result<(), ClientError> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let version = format!("..{}", nanos);
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(dir.join(&version))?;
+    for (name, data) in files {
+        write_private(&dir.join(&version).join(name), data)?;
+    }
+
+    let previous = fs::read_link(dir.join(DATA_LINK)).ok();
+    let staged_link = dir.join("..data_tmp");
+    let _ = fs::remove_file(&staged_link);
+    symlink(&version, &staged_link)?;
+    fs::rename(&staged_link, dir.join(DATA_LINK))?;
+
+    for (name, _) in files {
+        let link = dir.join(name);
+        if fs::symlink_metadata(&link).is_err() {
+            symlink(Path::new(DATA_LINK).join(name), &link)?;
+        }
+    }
+    if let Some(previous) = previous {
+        if previous != Path::new(&version) {
+            fs::remove_dir_all(dir.join(previous))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_overrides_annotations() {
+        let annotations = parse_annotations(
+            "kubernetes.io/config.seen=\"2026-01-01\"\n\
+             skp.gramine.dev/providers=\"kp1:3443, kp2:3443\"\n\
+             skp.gramine.dev/keys=\"db.key=database,tls.key=tls\"\n",
+        );
+        let env = |var: &str| (var == "SKP_KEYS").then(|| "disk=luks".to_string());
+
+        let settings = Settings::load(&annotations, env).unwrap();
+
+        assert_eq!(settings.providers, ["kp1:3443", "kp2:3443"]);
+        assert_eq!(
+            settings.keys,
+            BTreeMap::from([("disk".to_string(), "luks".to_string())])
+        );
+        assert!(Settings::load(&BTreeMap::new(), |_| None).is_err());
+    }
+
+    #[test]
+    fn projection_swaps_all_files_at_once() {
+        let dir = std::env::temp_dir().join(format!("skp-projection-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        project(&dir, &[("key".into(), b"old")]).unwrap();
+        project(&dir, &[("key".into(), b"new")]).unwrap();
+        let key = fs::read(dir.join("key")).unwrap();
+        let versions = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("..") && name != DATA_LINK
+            })
+            .count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(key, b"new");
+        assert_eq!(versions, 1);
+    }
+}
@@ -0,0 +1,52 @@
+//! Guest side of the gramine-sealing-key-provider exchange.
+//!
+//! Runs inside a TD (under Gramine or not) and fetches the TD's sealing key:
+//!
+//! ```no_run
+//! use gramine_sealing_key_client::{attestation, SealingKeyClient};
+//!
+//! let source = attestation::detect()?;
+//! let key = SealingKeyClient::new("provider.local:3443").request_key(source.as_ref())?;
+//! # Ok::<(), gramine_sealing_key_client::ClientError>(())
+//! ```
+//!
+//! Everything that needs the OS, from quoting to talking to the provider, is
+//! behind the default `native` feature. Without it the crate is the
+//! protocol core, [`Exchange`], which builds for `wasm32`.
+
+#[cfg(feature = "native")]
+pub mod attestation;
+#[cfg(feature = "native")]
+pub mod azure;
+pub mod binding;
+#[cfg(feature = "native")]
+mod client;
+#[cfg(feature = "native")]
+pub mod collateral;
+mod error;
+mod exchange;
+#[cfg(feature = "native")]
+pub mod gcp;
+#[cfg(feature = "native")]
+pub mod install;
+#[cfg(feature = "native")]
+pub mod kubernetes;
+#[cfg(feature = "native")]
+pub mod luks;
+pub mod protocol;
+#[cfg(feature = "native")]
+pub mod sidecar;
+#[cfg(feature = "native")]
+pub mod spiffe;
+#[cfg(feature = "native")]
+pub mod tpm;
+#[cfg(feature = "native")]
+pub mod vault;
+
+pub use binding::{CollateralSource, ExpectedProvider, FetchedCollateral};
+#[cfg(feature = "native")]
+pub use client::{Retry, SealingKeyClient};
+#[cfg(feature = "native")]
+pub use collateral::PcsCollateral;
+pub use error::ClientError;
+pub use exchange::{DerivedKey, Exchange};
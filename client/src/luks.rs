@@ -0,0 +1,279 @@
+//! LUKS2 volumes unlocked with a provider key, in the manner of a clevis pin.
+//!
+//! Binding adds a keyslot for a subkey of the TD's key and stores a token
+//! saying how to fetch it again. The token also holds a key id, so when the
+//! TD's measurements (or the provider's root key) change, unlocking reports a
+//! key epoch change instead of a wrong passphrase.
+//!
+//! The token is also what the `client-luks-token` libcryptsetup plugin reads,
+//! so systemd-cryptsetup and `cryptsetup open` unlock bound volumes without
+//! a keyscript.
+
+use crate::attestation;
+use crate::binding::ExpectedProvider;
+use crate::client::{Retry, SealingKeyClient};
+use crate::exchange::DerivedKey;
+use crate::error::ClientError;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// LUKS2 token type of this integration.
+pub const TOKEN_TYPE: &str = "gramine-skp";
+const KEY_ID_LABEL: &[u8] = b"gramine-sealing-key-client/luks-key-id/v1";
+
+/// What a bound volume's token records, besides the LUKS2 fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    #[serde(rename = "type")]
+    pub token_type: String,
+    pub keyslots: Vec<String>,
+    pub providers: Vec<String>,
+    /// Subkey context of the passphrase, `luks:<volume UUID>` by default.
+    pub context: String,
+    /// Identifies the key epoch the keyslot was bound to; see [`key_id`].
+    pub key_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_mrenclave: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_mrsigner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_mrtd: Option<String>,
+}
+
+impl Token {
+    /// Parses a token as libcryptsetup stores it.
+    pub fn from_json(json: &[u8]) -> Result<Self, ClientError> {
+        let token: Token = serde_json::from_slice(json)?;
+        if token.token_type != TOKEN_TYPE {
+            return Err(ClientError::ConfigError(format!(
+                "Token type {} is not {}",
+                token.token_type, TOKEN_TYPE
+            )));
+        }
+        Ok(token)
+    }
+}
+
+/// `HMAC-SHA256(passphrase, "gramine-sealing-key-client/luks-key-id/v1")`,
+/// first 16 bytes, hex. Reveals nothing about the passphrase but changes
+/// with it.
+pub fn key_id(passphrase: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(passphrase).expect("HMAC takes any key length");
+    mac.update(KEY_ID_LABEL);
+    hex::encode(&mac.finalize().into_bytes()[..16])
+}
+
+/// The volume passphrase for `context`.
+pub fn passphrase(key: &DerivedKey, context: &str) -> Result<Zeroizing<[u8; 32]>, ClientError> {
+    key.subkey(context.as_bytes())
+}
+
+/// Fails with [`ClientError::KeyEpochChanged`] if `passphrase` is not the one
+/// `token` was bound with.
+pub fn check_epoch(token: &Token, passphrase: &[u8]) -> Result<(), ClientError> {
+    let current = key_id(passphrase);
+    if current != token.key_id {
+        return Err(ClientError::KeyEpochChanged {
+            bound: token.key_id.clone(),
+            current,
+        });
+    }
+    Ok(())
+}
+
+/// Fetches the TD's key from the providers `token` names.
+pub fn fetch_key(token: &Token) -> Result<DerivedKey, ClientError> {
+    let expected = ExpectedProvider::from_hex(
+        token.expect_mrenclave.as_deref(),
+        token.expect_mrsigner.as_deref(),
+        token.expect_mrtd.as_deref(),
+    )?;
+    let client = SealingKeyClient::failover(&token.providers)?
+        .expect(expected)
+        .retry(Retry {
+            attempts: 5,
+            initial_backoff: Duration::from_secs(2),
+            ..Retry::default()
+        });
+    let source = attestation::detect()?;
+    client.request_key(source.as_ref())
+}
+
+/// The passphrase of a volume bound with `token`, checked against the key
+/// epoch it was bound to.
+pub fn bound_passphrase(token: &Token) -> Result<Zeroizing<[u8; 32]>, ClientError> {
+    let passphrase = passphrase(&fetch_key(token)?, &token.context)?;
+    check_epoch(token, &passphrase[..])?;
+    Ok(passphrase)
+}
+
+pub fn volume_uuid(device: &str) -> Result<String, ClientError> {
+    let uuid = cryptsetup(&["luksUUID", device], None)?;
+    Ok(String::from_utf8_lossy(&uuid).trim().to_string())
+}
+
+/// The id and contents of the volume's token of this integration.
+pub fn find_token(device: &str) -> Result<(u32, Token), ClientError> {
+    let dump = cryptsetup(&["luksDump", "--dump-json-metadata", device], None)?;
+    parse_token(&dump)?
+        .ok_or_else(|| ClientError::ConfigError(format!("{} has no {} token", device, TOKEN_TYPE)))
+}
+
+fn parse_token(metadata: &[u8]) -> Result<Option<(u32, Token)>, ClientError> {
+    #[derive(Deserialize)]
+    struct Metadata {
+        #[serde(default)]
+        tokens: BTreeMap<String, serde_json::Value>,
+    }
+    let metadata: Metadata = serde_json::from_slice(metadata)?;
+    for (id, token) in metadata.tokens {
+        if token.get("type").and_then(|t| t.as_str()) != Some(TOKEN_TYPE) {
+            continue;
+        }
+        let id = id
+            .parse()
+            .map_err(|_| ClientError::ProtocolError(format!("Bad LUKS2 token id {:?}", id)))?;
+        return Ok(Some((id, serde_json::from_value(token)?)));
+    }
+    Ok(None)
+}
+
+/// Adds a keyslot for `passphrase`, authorized by `existing` (an existing
+/// passphrase or recovery key), and stores `token` for it. Returns the new
+/// keyslot.
+pub fn bind(
+    device: &str,
+    existing: &[u8],
+    passphrase: &[u8],
+    mut token: Token,
+) -> Result<u32, ClientError> {
+    let before = keyslots(device)?;
+    // luksAddKey takes the existing passphrase on stdin and the new one from
+    // a file; a memfd keeps it off disk
+    let new_key = memfd(passphrase)?;
+    let new_key_path = format!("/proc/self/fd/{}", new_key.as_raw_fd());
+    cryptsetup(
+        &[
+            "luksAddKey",
+            "--batch-mode",
+            "--key-file",
+            "-",
+            device,
+            &new_key_path,
+        ],
+        Some(existing),
+    )?;
+    drop(new_key);
+
+    let slot = keyslots(device)?
+        .difference(&before)
+        .next()
+        .copied()
+        .ok_or_else(|| ClientError::ProtocolError("cryptsetup added no keyslot".into()))?;
+    token.keyslots = vec![slot.to_string()];
+    cryptsetup(
+        &["token", "import", device],
+        Some(&serde_json::to_vec(&token)?),
+    )?;
+    Ok(slot)
+}
+
+fn keyslots(device: &str) -> Result<BTreeSet<u32>, ClientError> {
+    #[derive(Deserialize)]
+    struct Metadata {
+        keyslots: BTreeMap<String, serde_json::Value>,
+    }
+    let dump = cryptsetup(&["luksDump", "--dump-json-metadata", device], None)?;
+    let metadata: Metadata = serde_json::from_slice(&dump)?;
+    Ok(metadata
+        .keyslots
+        .keys()
+        .filter_map(|slot| slot.parse().ok())
+        .collect())
+}
+
+/// An anonymous in-memory file holding `data`, inherited by child processes.
+fn memfd(data: &[u8]) -> Result<File, ClientError> {
+    let fd = unsafe { libc::memfd_create(c"skp-luks".as_ptr(), 0) };
+    if fd < 0 {
+        return Err(ClientError::IOError(std::io::Error::last_os_error()));
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(data)?;
+    Ok(file)
+}
+
+/// Opens `device` as `/dev/mapper/<name>` with `passphrase`.
+pub fn open(device: &str, name: &str, passphrase: &[u8]) -> Result<(), ClientError> {
+    cryptsetup(&["open", "--key-file", "-", device, name], Some(passphrase))?;
+    Ok(())
+}
+
+fn cryptsetup(args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>, ClientError> {
+    let mut child = Command::new("cryptsetup")
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(data), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(data)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(ClientError::ConfigError(format!(
+            "cryptsetup failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_found_and_epoch_changes_are_detected() {
+        let bound = [1u8; 32];
+        let metadata = serde_json::json!({
+            "keyslots": {"0": {}, "1": {}},
+            "tokens": {
+                "0": {"type": "systemd-tpm2", "keyslots": ["0"]},
+                "3": {
+                    "type": TOKEN_TYPE,
+                    "keyslots": ["1"],
+                    "providers": ["kp.local:3443"],
+                    "context": "luks:uuid",
+                    "key_id": key_id(&bound),
+                }
+            }
+        });
+
+        let (id, token) = parse_token(&serde_json::to_vec(&metadata).unwrap())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(id, 3);
+        assert_eq!(token.context, "luks:uuid");
+        assert!(Token::from_json(&serde_json::to_vec(&token).unwrap()).is_ok());
+        assert!(Token::from_json(&serde_json::to_vec(&metadata["tokens"]["0"]).unwrap()).is_err());
+        assert!(check_epoch(&token, &bound).is_ok());
+        assert!(matches!(
+            check_epoch(&token, &[2; 32]),
+            Err(ClientError::KeyEpochChanged { .. })
+        ));
+    }
+}
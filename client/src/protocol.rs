@@ -0,0 +1,59 @@
+//! The subset of the provider's wire format the client speaks. Byte strings
+//! are JSON arrays of numbers, as on the provider side.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct QuoteRequest<'a> {
+    pub quote: &'a [u8],
+    pub nonce: &'a [u8],
+    pub suites: &'a [&'a str],
+    pub explain: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+pub struct QuoteResponse {
+    pub suite: String,
+    pub encrypted_key: Vec<u8>,
+    pub provider_quote: Vec<u8>,
+    #[serde(default)]
+    pub recipient_keys: Vec<Vec<u8>>,
+    pub key_confirmation: Vec<u8>,
+    #[serde(default)]
+    pub platform_tcb: Option<PlatformTcb>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlatformTcb {
+    pub status: String,
+    #[serde(default)]
+    pub advisory_ids: Vec<String>,
+}
+
+/// Sent instead of a key response when a request with `explain` is denied.
+#[derive(Deserialize)]
+pub struct RejectionResponse {
+    pub error: String,
+}
+
+/// `{"op": "spiffe", ...}`: a SPIRE agent's quote, forwarded by a node
+/// attestor plugin.
+#[derive(Serialize)]
+pub struct SpiffeRequest {
+    pub op: &'static str,
+    pub quote: Vec<u8>,
+    pub challenge: Vec<u8>,
+    pub svid_public_key: Vec<u8>,
+    pub trust_domain: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpiffeResponse {
+    pub spiffe_id: String,
+    pub selectors: Vec<String>,
+    pub provider_quote: Vec<u8>,
+}
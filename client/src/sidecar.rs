@@ -0,0 +1,116 @@
+//! Sidecar support for containers outside Kubernetes, such as Docker Compose
+//! services in a TD: waiting for the workload container before fetching,
+//! and a readiness file the workload's `depends_on` health check can test.
+//! Keys go to a shared memory-backed volume in the layout of
+//! [`crate::kubernetes::project`].
+
+use crate::error::ClientError;
+use crate::install::write_private;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Created in the key volume once the keys are there.
+pub const READY_FILE: &str = "..ready";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Something the workload container brings up, which shows that it is
+/// running.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WaitTarget {
+    /// A file or socket in a shared volume.
+    Path(PathBuf),
+    /// A `host:port` that accepts TCP connections.
+    Tcp(String),
+}
+
+impl WaitTarget {
+    /// An absolute path, or `host:port`.
+    pub fn parse(target: &str) -> Result<Self, ClientError> {
+        if target.starts_with('/') {
+            return Ok(WaitTarget::Path(PathBuf::from(target)));
+        }
+        match target.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(WaitTarget::Tcp(target.to_string()))
+            }
+            _ => Err(ClientError::ConfigError(format!(
+                "Wait target {:?} is neither an absolute path nor host:port",
+                target
+            ))),
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        match self {
+            WaitTarget::Path(path) => path.exists(),
+            WaitTarget::Tcp(address) => address
+                .to_socket_addrs()
+                .into_iter()
+                .flatten()
+                .any(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).is_ok()),
+        }
+    }
+}
+
+/// Waits until every target is up, or fails after `timeout` if one is set.
+pub fn wait_for(targets: &[WaitTarget], timeout: Option<Duration>) -> Result<(), ClientError> {
+    let started = Instant::now();
+    for target in targets {
+        while !target.is_up() {
+            if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+                return Err(ClientError::ConfigError(format!(
+                    "Gave up waiting for {:?}",
+                    target
+                )));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
+
+/// Marks the keys in `dir` as ready.
+pub fn mark_ready(dir: &Path) -> Result<(), ClientError> {
+    write_private(&dir.join(READY_FILE), b"")
+}
+
+/// Removes a readiness file left by an earlier run, since a memory-backed
+/// volume outlives a restarted sidecar.
+pub fn clear_ready(dir: &Path) -> Result<(), ClientError> {
+    match fs::remove_file(dir.join(READY_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ClientError::IOError(e)),
+        _ => Ok(()),
+    }
+}
+
+pub fn is_ready(dir: &Path) -> bool {
+    dir.join(READY_FILE).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_are_paths_or_addresses() {
+        assert_eq!(
+            WaitTarget::parse("/run/app/started").unwrap(),
+            WaitTarget::Path(PathBuf::from("/run/app/started"))
+        );
+        assert_eq!(
+            WaitTarget::parse("app:8080").unwrap(),
+            WaitTarget::Tcp("app:8080".into())
+        );
+        assert!(WaitTarget::parse("app").is_err());
+        assert!(WaitTarget::parse("relative/path").is_err());
+        assert!(wait_for(
+            &[WaitTarget::Path("/nonexistent".into())],
+            Some(Duration::ZERO)
+        )
+        .is_err());
+    }
+}
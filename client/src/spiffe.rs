@@ -0,0 +1,151 @@
+//! SPIRE node attestation backed by the provider.
+//!
+//! The agent in the TD derives its SVID key from the TD's key, so the key is
+//! the same after every reboot and only a TD with the same measurements can
+//! hold it. It answers the server plugin's challenge with a quote over the
+//! challenge and the key's public half ([`attest`]). The server plugin
+//! forwards that to a provider ([`crate::SealingKeyClient::attest_node`]),
+//! which verifies the quote like a key request's and names the agent after
+//! its key id.
+
+use crate::attestation::QuoteSource;
+use crate::binding::{bound_report_data, check_provider_quote, CollateralSource, ExpectedProvider};
+use crate::exchange::DerivedKey;
+use crate::error::ClientError;
+use crate::protocol::{SpiffeRequest, SpiffeResponse};
+use p256::pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding};
+use p256::SecretKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+const CHALLENGE_LABEL: &[u8] = b"gramine-sealing-key-provider/spiffe-challenge/v1";
+const SPIFFE_LABEL: &[u8] = b"gramine-sealing-key-provider/spiffe/v1";
+const SVID_KEY_CONTEXT: &[u8] = b"spiffe/svid-key/";
+/// A subkey is out of range for P-256 with probability about 2^-32.
+const MAX_SVID_KEY_TRIES: u8 = 8;
+
+/// The agent's SVID key: the first subkey of `key` under
+/// `"spiffe/svid-key/" || counter` that is a valid P-256 scalar.
+pub fn svid_key(key: &DerivedKey) -> Result<SecretKey, ClientError> {
+    for counter in 0..MAX_SVID_KEY_TRIES {
+        let context = [SVID_KEY_CONTEXT, &[counter]].concat();
+        if let Ok(secret) = SecretKey::from_slice(&key.subkey(&context)?[..]) {
+            return Ok(secret);
+        }
+    }
+    Err(ClientError::CryptoError(
+        "No valid P-256 SVID key from the derived key".into(),
+    ))
+}
+
+/// DER SubjectPublicKeyInfo of `key`, as the quote commits to it.
+pub fn svid_public_key(key: &SecretKey) -> Result<Vec<u8>, ClientError> {
+    Ok(key
+        .public_key()
+        .to_public_key_der()
+        .map_err(|e| ClientError::CryptoError(e.to_string()))?
+        .into_vec())
+}
+
+/// PKCS#8 PEM of `key`, for the agent's key store.
+pub fn svid_key_pem(key: &SecretKey) -> Result<Zeroizing<String>, ClientError> {
+    key.to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| ClientError::CryptoError(e.to_string()))
+}
+
+/// What the agent sends its server plugin in answer to a challenge.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeAttestation {
+    pub quote: Vec<u8>,
+    pub svid_public_key: Vec<u8>,
+}
+
+impl NodeAttestation {
+    /// The provider request for this attestation.
+    pub fn request(self, challenge: &[u8], trust_domain: &str) -> SpiffeRequest {
+        SpiffeRequest {
+            op: "spiffe",
+            quote: self.quote,
+            challenge: challenge.to_vec(),
+            svid_public_key: self.svid_public_key,
+            trust_domain: trust_domain.to_string(),
+        }
+    }
+}
+
+/// Quotes the agent's TD over `challenge` and its SVID public key.
+pub fn attest(
+    source: &dyn QuoteSource,
+    challenge: &[u8],
+    svid_public_key: &[u8],
+) -> Result<NodeAttestation, ClientError> {
+    Ok(NodeAttestation {
+        quote: source.quote(&report_data(challenge, svid_public_key))?,
+        svid_public_key: svid_public_key.to_vec(),
+    })
+}
+
+/// `SHA-256(label || challenge) | SHA-256(svid_public_key)`.
+pub fn report_data(challenge: &[u8], svid_public_key: &[u8]) -> [u8; 64] {
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(
+        &Sha256::new_with_prefix(CHALLENGE_LABEL)
+            .chain_update(challenge)
+            .finalize(),
+    );
+    report_data[32..].copy_from_slice(&Sha256::digest(svid_public_key));
+    report_data
+}
+
+/// Checks that the provider quote binds `response` to `request` and that the
+/// agent ID is in the requested trust domain. As with key responses, the
+/// quote must verify against collateral from `collateral`.
+pub fn verify_response(
+    response: &SpiffeResponse,
+    request: &SpiffeRequest,
+    expected: &ExpectedProvider,
+    collateral: &dyn CollateralSource,
+) -> Result<(), ClientError> {
+    let prefix = format!("spiffe://{}/", request.trust_domain);
+    if !response.spiffe_id.starts_with(&prefix) {
+        return Err(ClientError::ProtocolError(format!(
+            "Agent ID {} is outside trust domain {}",
+            response.spiffe_id, request.trust_domain
+        )));
+    }
+    let mut fields: Vec<(&str, &[u8])> = vec![
+        ("challenge", request.challenge.as_slice()),
+        ("svid_public_key", request.svid_public_key.as_slice()),
+        ("spiffe_id", response.spiffe_id.as_bytes()),
+    ];
+    for selector in &response.selectors {
+        fields.push(("selector", selector.as_bytes()));
+    }
+    check_provider_quote(
+        &response.provider_quote,
+        &bound_report_data(SPIFFE_LABEL, &fields),
+        expected,
+        collateral,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svid_key_is_stable_and_bound_in_report_data() {
+        let key = DerivedKey::from_bytes(vec![7; 32]);
+
+        let first = svid_public_key(&svid_key(&key).unwrap()).unwrap();
+        let again = svid_public_key(&svid_key(&key).unwrap()).unwrap();
+        let other = svid_public_key(&svid_key(&DerivedKey::from_bytes(vec![8; 32])).unwrap());
+
+        assert_eq!(first, again);
+        assert_ne!(first, other.unwrap());
+        let bound = report_data(b"challenge", &first);
+        assert_eq!(&bound[32..], Sha256::digest(&first).as_slice());
+        assert_ne!(bound, report_data(b"other", &first));
+    }
+}
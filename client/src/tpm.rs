@@ -0,0 +1,178 @@
+//! Evidence from VMs without a TEE: a quote from their TPM over its boot
+//! PCRs, signed by an attestation key whose certificate chain the provider
+//! checks. The provider only serves such clients if its policy says so, and
+//! their keys are only as safe as the host they run on. Needs `tpm2-tools`.
+
+use crate::attestation::QuoteSource;
+use crate::error::ClientError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where provisioning tools commonly persist the attestation key.
+pub const DEFAULT_AK_HANDLE: &str = "0x81010002";
+/// The PCRs the quote covers, and so the PCRs the key depends on.
+pub const DEFAULT_PCRS: &str = "sha256:0,1,2,3,4,5,6,7";
+
+/// Quotes from the TPM's attestation key.
+pub struct TpmAttestation {
+    ak_handle: String,
+    /// Base64 DER, AK certificate first.
+    ak_cert_chain: Vec<String>,
+    pcrs: String,
+}
+
+impl TpmAttestation {
+    /// Quotes with the AK at [`DEFAULT_AK_HANDLE`], whose certificate chain
+    /// (PEM, AK certificate first) is in `ak_cert_chain`.
+    pub fn new(ak_cert_chain: &Path) -> Result<Self, ClientError> {
+        let pem = fs::read_to_string(ak_cert_chain)?;
+        let chain = pem_certificates(&pem)?;
+        if chain.is_empty() {
+            return Err(ClientError::ConfigError(format!(
+                "{} holds no certificates",
+                ak_cert_chain.display()
+            )));
+        }
+        Ok(Self {
+            ak_handle: DEFAULT_AK_HANDLE.to_string(),
+            ak_cert_chain: chain,
+            pcrs: DEFAULT_PCRS.to_string(),
+        })
+    }
+
+    pub fn ak_handle(mut self, handle: &str) -> Self {
+        self.ak_handle = handle.to_string();
+        self
+    }
+
+    /// PCR selection in `tpm2_quote -l` syntax.
+    pub fn pcrs(mut self, pcrs: &str) -> Self {
+        self.pcrs = pcrs.to_string();
+        self
+    }
+}
+
+impl QuoteSource for TpmAttestation {
+    fn quote(&self, report_data: &[u8; 64]) -> Result<Vec<u8>, ClientError> {
+        let qualifying_data = hex::encode(Sha256::digest(report_data));
+        let attest = TempFile::new("attest");
+        let signature = TempFile::new("signature");
+        tpm2(
+            &[
+                "quote",
+                "-c",
+                &self.ak_handle,
+                "-l",
+                &self.pcrs,
+                "-q",
+                &qualifying_data,
+                "-g",
+                "sha256",
+                "-m",
+                attest.path()?,
+                "-s",
+                signature.path()?,
+            ],
+            &[],
+        )?;
+
+        let evidence = json!({
+            "type": "tpm",
+            "report_data": STANDARD.encode(report_data),
+            "ak_cert_chain": self.ak_cert_chain,
+            "quote": STANDARD.encode(fs::read(&attest.0)?),
+            "signature": STANDARD.encode(fs::read(&signature.0)?),
+        });
+        Ok(evidence.to_string().into_bytes())
+    }
+}
+
+/// The base64 DER of each certificate in `pem`, in order.
+fn pem_certificates(pem: &str) -> Result<Vec<String>, ClientError> {
+    let mut certificates = Vec::new();
+    let mut current: Option<String> = None;
+    for line in pem.lines().map(str::trim) {
+        if line == "-----BEGIN CERTIFICATE-----" {
+            current = Some(String::new());
+        } else if line == "-----END CERTIFICATE-----" {
+            let body = current.take().unwrap_or_default();
+            STANDARD
+                .decode(&body)
+                .map_err(|e| ClientError::ConfigError(format!("Invalid PEM certificate: {}", e)))?;
+            certificates.push(body);
+        } else if let Some(body) = &mut current {
+            body.push_str(line);
+        }
+    }
+    if current.is_some() {
+        return Err(ClientError::ConfigError(
+            "Unterminated PEM certificate".into(),
+        ));
+    }
+    Ok(certificates)
+}
+
+/// Runs `tpm2_<args[0]>` with the rest of `args`, feeding it `stdin`.
+pub(crate) fn tpm2(args: &[&str], stdin: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let mut child = Command::new(format!("tpm2_{}", args[0]))
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut pipe) = child.stdin.take() {
+        pipe.write_all(stdin)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(ClientError::AttestationError(format!(
+            "tpm2_{} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// A file for `tpm2_quote` to write to, removed when dropped.
+pub(crate) struct TempFile(pub(crate) PathBuf);
+
+impl TempFile {
+    pub(crate) fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("skp-tpm-{}-{}", std::process::id(), name)))
+    }
+
+    pub(crate) fn path(&self) -> Result<&str, ClientError> {
+        self.0
+            .to_str()
+            .ok_or_else(|| ClientError::ConfigError("Temporary directory is not UTF-8".into()))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pem_chain_keeps_certificate_order() {
+        let pem = "junk\n-----BEGIN CERTIFICATE-----\nAAEC\nAw==\n-----END CERTIFICATE-----\n\
+                   -----BEGIN CERTIFICATE-----\nBAUG\n-----END CERTIFICATE-----\n";
+
+        let chain = pem_certificates(pem).unwrap();
+
+        assert_eq!(chain, ["AAECAw==", "BAUG"]);
+        assert!(pem_certificates("-----BEGIN CERTIFICATE-----\nAAEC\n").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Login to the provider's Vault API subset, for applications that already
+//! speak Vault: they get the token this returns as `VAULT_TOKEN` and keep
+//! their Vault code. The login quote commits to a fresh X25519 key; the
+//! token, KV secrets and transit plaintexts come back sealed to it, so only
+//! the holder of the login's secret key can use them.
+
+use crate::attestation::QuoteSource;
+use crate::error::ClientError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crypto_box::{PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+const LOGIN_LABEL: &[u8] = b"gramine-sealing-key-provider/vault-login/v1";
+const LOGIN_PATH: &str = "/v1/auth/tdx/login";
+const NONCE_LEN: usize = 32;
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+/// `SHA-256(label || nonce) | public key`, as the login quote must carry.
+pub fn login_report_data(nonce: &[u8], public_key: &PublicKey) -> [u8; 64] {
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(
+        &Sha256::new_with_prefix(LOGIN_LABEL)
+            .chain_update(nonce)
+            .finalize(),
+    );
+    report_data[32..].copy_from_slice(public_key.as_bytes());
+    report_data
+}
+
+/// A Vault token and the key its secrets are sealed to.
+pub struct VaultLogin {
+    pub token: Zeroizing<String>,
+    secret_key: SecretKey,
+}
+
+impl VaultLogin {
+    /// The secret key, hex, for [`unseal_with_key`] in another process.
+    pub fn secret_key_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode(self.secret_key.to_bytes()))
+    }
+
+    /// Opens a base64 value the provider sealed to this login, such as a KV
+    /// secret's `key`.
+    pub fn unseal(&self, sealed: &str) -> Result<Zeroizing<Vec<u8>>, ClientError> {
+        unseal(&self.secret_key, sealed)
+    }
+}
+
+/// Opens a base64 value sealed to the login whose secret key is
+/// `secret_key`, hex.
+pub fn unseal_with_key(secret_key: &str, sealed: &str) -> Result<Zeroizing<Vec<u8>>, ClientError> {
+    let bytes = Zeroizing::new(
+        hex::decode(secret_key.trim())
+            .map_err(|_| ClientError::ConfigError("Vault key is not hex".into()))?,
+    );
+    let bytes: [u8; 32] = bytes[..]
+        .try_into()
+        .map_err(|_| ClientError::ConfigError("Vault key is not 32 bytes".into()))?;
+    unseal(&SecretKey::from(bytes), sealed)
+}
+
+fn unseal(secret_key: &SecretKey, sealed: &str) -> Result<Zeroizing<Vec<u8>>, ClientError> {
+    let sealed = STANDARD
+        .decode(sealed.trim())
+        .map_err(|_| ClientError::ProtocolError("Sealed value is not base64".into()))?;
+    secret_key
+        .unseal(&sealed)
+        .map(Zeroizing::new)
+        .map_err(|_| ClientError::CryptoError("Cannot open the sealed value".into()))
+}
+
+/// Logs in to the Vault API at `address` (`host:port`) with a fresh quote and
+/// returns the client token with the key to open what it reads. The
+/// connection is plain HTTP, as Vault clients use it with an `http://`
+/// address.
+pub fn login(
+    address: &str,
+    source: &dyn QuoteSource,
+    timeout: Duration,
+) -> Result<VaultLogin, ClientError> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let secret_key = SecretKey::generate(&mut OsRng);
+    let quote = source.quote(&login_report_data(&nonce, &secret_key.public_key()))?;
+    let body =
+        json!({ "quote": STANDARD.encode(quote), "nonce": STANDARD.encode(nonce) }).to_string();
+
+    let resolved = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| ClientError::ProtocolError(format!("{} does not resolve", address)))?;
+    let mut socket = TcpStream::connect_timeout(&resolved, timeout)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+    write!(
+        socket,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        LOGIN_PATH,
+        address,
+        body.len(),
+        body
+    )?;
+
+    let mut response = Vec::new();
+    socket.take(MAX_RESPONSE_LEN).read_to_end(&mut response)?;
+    let (status, body) = parse_response(&response)?;
+    match status {
+        200 => {
+            let sealed = body["auth"]["client_token"]
+                .as_str()
+                .ok_or_else(|| ClientError::ProtocolError("Login response has no token".into()))?;
+            let token = unseal(&secret_key, sealed)?;
+            let token = String::from_utf8(token.to_vec())
+                .map_err(|_| ClientError::ProtocolError("Token is not UTF-8".into()))?;
+            Ok(VaultLogin {
+                token: Zeroizing::new(token),
+                secret_key,
+            })
+        }
+        403 => Err(ClientError::Refused),
+        _ => Err(ClientError::ProtocolError(format!(
+            "Login failed with status {}: {}",
+            status, body["errors"]
+        ))),
+    }
+}
+
+pub(crate) fn parse_response(response: &[u8]) -> Result<(u16, Value), ClientError> {
+    let malformed = || ClientError::ProtocolError("Malformed HTTP response".into());
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let status = std::str::from_utf8(&response[..head_end])
+        .ok()
+        .and_then(|head| head.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    Ok((status, serde_json::from_slice(&response[head_end + 4..])?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_read_from_the_auth_block() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n\
+            {\"auth\":{\"client_token\":\"hvs.abc\"}}";
+
+        let (status, body) = parse_response(response).unwrap();
+
+        assert_eq!(status, 200);
+        assert_eq!(body["auth"]["client_token"], "hvs.abc");
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        let public_key = PublicKey::from([9u8; 32]);
+        assert_eq!(&login_report_data(b"n", &public_key)[32..], &[9u8; 32]);
+    }
+
+    #[test]
+    fn sealed_values_open_only_with_the_login_key() {
+        let secret_key = SecretKey::from([0x11; 32]);
+        let sealed = STANDARD.encode(secret_key.public_key().seal(&mut OsRng, b"subkey").unwrap());
+
+        assert_eq!(
+            &unseal_with_key(&"11".repeat(32), &sealed).unwrap()[..],
+            b"subkey"
+        );
+        assert!(unseal_with_key(&"22".repeat(32), &sealed).is_err());
+        assert!(unseal_with_key("11", &sealed).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::audit::{AuditLog, Query};
+use crate::collateral::CollateralCache;
+use crate::counters::{CounterStore, BOOT_EPOCH};
+use crate::crypto::{constant_time_eq, SecretBytes};
+use crate::error::ProviderError;
+use crate::gramine;
+use crate::logging::{admin_span, new_request_id};
+use crate::protocol::{AdminCommand, AdminRequest, AdminResponse};
+use crate::quote;
+use crate::rejections;
+use crate::resources;
+use crate::server::{bind, read_frame, write_frame};
+use crate::state::ProviderState;
+use crate::webhooks::{self, Event};
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tracing::Instrument;
+
+const MIN_TOKEN_LEN: usize = 16;
+/// Audit log entries returned per `audit_log` request by default, and at
+/// most.
+const AUDIT_EXPORT_LIMIT: usize = 1000;
+const MAX_AUDIT_EXPORT_LIMIT: usize = 10_000;
+/// Entries an `audit_query` returns by default, and at most.
+const AUDIT_QUERY_LIMIT: usize = 100;
+const MAX_AUDIT_QUERY_LIMIT: usize = 1000;
+
+/// Serves the admin API on `addr`, separate from key requests. Every request
+/// must carry `token`. Nothing it returns is secret and every action it
+/// offers but approving releases is one the host could cause anyway, by
+/// cutting PCS access or restarting the provider.
+pub async fn serve(
+    addr: &str,
+    token: SecretBytes,
+    state: Arc<ProviderState>,
+) -> Result<(), ProviderError> {
+    if token.expose().len() < MIN_TOKEN_LEN {
+        return Err(ProviderError::ConfigError(format!(
+            "SEALING_PROVIDER_ADMIN_TOKEN must be at least {} bytes",
+            MIN_TOKEN_LEN
+        )));
+    }
+    let listener = bind(addr).await?;
+    let token = Arc::new(token);
+
+    tokio::spawn(async move {
+        while let Ok((socket, peer_addr)) = listener.accept().await {
+            let span = admin_span(peer_addr, &new_request_id());
+            span.in_scope(|| info!("New admin connection from: {}", peer_addr));
+            let token = Arc::clone(&token);
+            let state = Arc::clone(&state);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = handle_connection(socket, &token, &state).await {
+                        error!("admin connection error from {}: {}", peer_addr, e.chain());
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(
+    mut socket: TcpStream,
+    token: &SecretBytes,
+    state: &ProviderState,
+) -> Result<(), ProviderError> {
+    let Some(frame) = read_frame(&mut socket).await? else {
+        return Ok(());
+    };
+    let request: AdminRequest = serde_json::from_slice(&frame)?;
+
+    let response = if !constant_time_eq(request.token.as_bytes(), token.expose()) {
+        warn!("Rejected admin request with a wrong token");
+        AdminResponse {
+            result: None,
+            error: Some("unauthorized".into()),
+        }
+    } else {
+        info!("Admin request: {:?}", request.command);
+        match execute(&request.command, state).await {
+            Ok(result) => AdminResponse {
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => AdminResponse {
+                result: None,
+                error: Some(e.chain().to_string()),
+            },
+        }
+    };
+    write_frame(&mut socket, &serde_json::to_vec(&response)?).await
+}
+
+async fn execute(command: &AdminCommand, state: &ProviderState) -> Result<Value, ProviderError> {
+    match command {
+        AdminCommand::Policy => {
+            let settings = state.settings();
+            Ok(json!({
+                "policy": settings.policy,
+                "session_idle_timeout_secs": settings.session_idle_timeout.as_secs(),
+            }))
+        }
+        AdminCommand::Caches => Ok(json!({
+            "collateral": state.verifier.collateral().map(CollateralCache::entries),
+            "provider_quote": gramine::own_quote_status().map(|(age, tcb_status)| json!({
+                "age_secs": age.as_secs(),
+                "tcb_status": tcb_status,
+            })),
+            "nonces": state.nonces.remembered(),
+        })),
+        AdminCommand::Counters => {
+            let (generation, counters) = counter_store(state)?.snapshot();
+            Ok(json!({ "generation": generation, "counters": counters }))
+        }
+        AdminCommand::Connections => Ok(json!(state.connections.list())),
+        AdminCommand::Epoch => Ok(json!({ "boot_epoch": counter_store(state)?.get(BOOT_EPOCH) })),
+        // The nonce cache is left alone: flushing it would reopen the replay
+        // window
+        AdminCommand::FlushCaches => {
+            let collateral = state
+                .verifier
+                .collateral()
+                .map_or(0, CollateralCache::flush);
+            gramine::flush_own_quote();
+            Ok(json!({ "collateral_entries": collateral }))
+        }
+        AdminCommand::RotateEpoch => {
+            let epoch = counter_store(state)?.increment(BOOT_EPOCH).await?;
+            info!("Boot epoch rotated to {}", epoch);
+            webhooks::notify(Event::EpochRotated {
+                boot_epoch: epoch,
+                cause: "admin",
+            });
+            Ok(json!({ "boot_epoch": epoch }))
+        }
+        AdminCommand::Rejections => Ok(json!(rejections::snapshot())),
+        AdminCommand::Resources => Ok(json!(resources::sample())),
+        AdminCommand::Approvals => Ok(json!(state.approvals.pending())),
+        AdminCommand::Approve { id } => {
+            state.approvals.decide(id, true, None)?;
+            Ok(json!({ "approved": id }))
+        }
+        AdminCommand::Deny { id, reason } => {
+            state.approvals.decide(id, false, reason.clone())?;
+            Ok(json!({ "denied": id }))
+        }
+        AdminCommand::AuditLog { from, limit } => {
+            let audit = audit_log(state)?;
+            let limit = limit
+                .unwrap_or(AUDIT_EXPORT_LIMIT)
+                .min(MAX_AUDIT_EXPORT_LIMIT);
+            Ok(json!({ "lines": audit.export(*from, limit).await? }))
+        }
+        AdminCommand::AuditQuery {
+            launch_measurement,
+            ppid,
+            key_id,
+            tenant,
+            verdict,
+            since,
+            until,
+            before,
+            limit,
+        } => {
+            let query = Query {
+                launch_measurement: launch_measurement.clone(),
+                ppid: ppid.clone(),
+                key_id: key_id.clone(),
+                tenant: tenant.clone(),
+                verdict: verdict.clone(),
+                since: *since,
+                until: *until,
+                before: *before,
+            };
+            let limit = limit
+                .unwrap_or(AUDIT_QUERY_LIMIT)
+                .min(MAX_AUDIT_QUERY_LIMIT);
+            Ok(json!({ "entries": audit_log(state)?.query(&query, limit).await? }))
+        }
+        AdminCommand::SelfTest => {
+            let check = quote::check_pipeline(&state.master, &state.settings().policy)?;
+            Ok(json!(check))
+        }
+    }
+}
+
+fn counter_store(state: &ProviderState) -> Result<&CounterStore, ProviderError> {
+    state.counters.as_ref().ok_or_else(|| {
+        ProviderError::ConfigError("No counter store is configured (files.counters)".into())
+    })
+}
+
+fn audit_log(state: &ProviderState) -> Result<&AuditLog, ProviderError> {
+    state.audit.as_ref().ok_or_else(|| {
+        ProviderError::ConfigError("No audit log is configured (files.audit_log)".into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+    use crate::padding::ResponsePadding;
+    use crate::policy::Policy;
+    use crate::state::Settings;
+    use crate::verifier::DcapVerifier;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn rotating_the_epoch_advances_the_counter() {
+        sodiumoxide::init().unwrap();
+        let path = std::env::temp_dir().join(format!("admin-counters-{}", std::process::id()));
+        let master = MasterSecret::from_sealing_key(&[7; 16], None).unwrap();
+        let state = ProviderState::new(
+            MasterSecret::from_sealing_key(&[7; 16], None).unwrap(),
+            Box::new(ProviderIdentity::from_master(&master).unwrap()),
+            Settings {
+                policy: Policy::default(),
+                session_idle_timeout: Duration::from_secs(1),
+                padding: ResponsePadding::default(),
+            },
+            Box::new(DcapVerifier::new(CollateralCache::in_memory())),
+            Some(CounterStore::open(&path, &master).unwrap()),
+            None,
+            None,
+        );
+
+        let rotated = execute(&AdminCommand::RotateEpoch, &state).await.unwrap();
+        let epoch = execute(&AdminCommand::Epoch, &state).await.unwrap();
+        let request: AdminRequest =
+            serde_json::from_str(r#"{"token":"t","op":"flush_caches"}"#).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rotated, json!({ "boot_epoch": 1 }));
+        assert_eq!(epoch, rotated);
+        assert!(matches!(request.command, AdminCommand::FlushCaches));
+    }
+}
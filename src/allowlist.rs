@@ -0,0 +1,231 @@
+//! Measurement allowlists: a file, named by the policy, of the TD images a
+//! fleet runs, each an MRTD with optional RTMRs, a name and an expiry date.
+//! The provider reads the file again whenever it changes, so rolling out a
+//! new image means editing the file, without a reload or a redeployment.
+
+use crate::error::ProviderError;
+use crate::evidence::Evidence;
+use crate::watched::Watched;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+const RTMRS: [&str; 4] = ["rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllowlistFile {
+    images: Vec<Image>,
+}
+
+/// One approved image.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Image {
+    /// Shown in traces and logs, e.g. `guest-2026.10`.
+    pub name: String,
+    /// Hex MRTD.
+    pub mrtd: String,
+    /// Hex RTMRs the image must also have; one left out matches any value.
+    #[serde(default)]
+    pub rtmr0: Option<String>,
+    #[serde(default)]
+    pub rtmr1: Option<String>,
+    #[serde(default)]
+    pub rtmr2: Option<String>,
+    #[serde(default)]
+    pub rtmr3: Option<String>,
+    /// `YYYY-MM-DD`: from 00:00 UTC on this day the image is refused.
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(skip)]
+    expires_at: Option<u64>,
+}
+
+impl Image {
+    fn expected(&self) -> [(&'static str, Option<&String>); 5] {
+        [
+            ("mrtd", Some(&self.mrtd)),
+            (RTMRS[0], self.rtmr0.as_ref()),
+            (RTMRS[1], self.rtmr1.as_ref()),
+            (RTMRS[2], self.rtmr2.as_ref()),
+            (RTMRS[3], self.rtmr3.as_ref()),
+        ]
+    }
+
+    fn matches(&self, measured: &[(&'static str, &[u8])]) -> bool {
+        self.expected().into_iter().all(|(name, expected)| {
+            let Some(expected) = expected else {
+                return true;
+            };
+            measured
+                .iter()
+                .find(|(measured_name, _)| *measured_name == name)
+                .is_some_and(|(_, value)| expected.eq_ignore_ascii_case(&hex::encode(value)))
+        })
+    }
+}
+
+/// The images of an allowlist file, as last read.
+#[derive(Debug)]
+pub struct Allowlist {
+    images: Watched<Vec<Image>>,
+}
+
+impl Allowlist {
+    /// Reads the allowlist at `path`, which must be valid.
+    pub fn load(path: &Path) -> Result<Self, ProviderError> {
+        Ok(Self {
+            images: Watched::load(path, read)?,
+        })
+    }
+
+    /// Checks that `evidence` is of an allowed image that has not expired by
+    /// `now` (Unix seconds), and returns the image's name.
+    pub fn check(&self, evidence: &dyn Evidence, now: u64) -> Result<String, ProviderError> {
+        let images = self.images.get();
+        let measured = evidence.reference_measurements();
+        let mut expired = None;
+        for image in images.iter().filter(|image| image.matches(&measured)) {
+            match image.expires_at {
+                Some(expires_at) if expires_at <= now => expired = Some(image),
+                _ => return Ok(image.name.clone()),
+            }
+        }
+        Err(ProviderError::PolicyViolation(match expired {
+            Some(image) => format!(
+                "Image {} expired on {}",
+                image.name,
+                image.expires.as_deref().unwrap_or_default()
+            ),
+            None => format!(
+                "{} measurements match none of the {} allowed images",
+                evidence.kind().name(),
+                images.len()
+            ),
+        }))
+    }
+
+    pub fn count(&self) -> usize {
+        self.images.get().len()
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<Image>, ProviderError> {
+    let file: AllowlistFile = serde_json::from_slice(&fs::read(path)?)?;
+    let mut images = file.images;
+    for image in &mut images {
+        for (name, value) in image.expected() {
+            let Some(value) = value else {
+                continue;
+            };
+            if hex::decode(value).map(|bytes| bytes.len()) != Ok(48) {
+                return Err(ProviderError::ConfigError(format!(
+                    "{} of image {} must be 48 bytes of hex",
+                    name, image.name
+                )));
+            }
+        }
+        if let Some(expires) = &image.expires {
+            image.expires_at = Some(parse_date(expires).ok_or_else(|| {
+                ProviderError::ConfigError(format!(
+                    "Expiry {:?} of image {} is not a YYYY-MM-DD date",
+                    expires, image.name
+                ))
+            })?);
+        }
+    }
+    Ok(images)
+}
+
+/// 00:00 UTC on the `YYYY-MM-DD` date `date`, in Unix seconds.
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since the epoch of a proleptic Gregorian date, with the year
+    // starting in March so the leap day comes last
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::EvidenceKind;
+
+    struct Td(Vec<u8>);
+
+    impl Evidence for Td {
+        fn kind(&self) -> EvidenceKind {
+            EvidenceKind::Tdx
+        }
+        fn measurements(&self) -> &[u8] {
+            &self.0
+        }
+        fn report_data(&self) -> &[u8] {
+            &[]
+        }
+        fn platform_id(&self) -> &[u8] {
+            &[]
+        }
+        fn launch_measurement(&self) -> &[u8] {
+            &self.0[..48]
+        }
+        fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+            let names = ["mrtd", "rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+            names.into_iter().zip(self.0.chunks(48)).collect()
+        }
+    }
+
+    #[test]
+    fn images_match_until_they_expire_and_reload_on_change() {
+        let path = std::env::temp_dir().join(format!("skp-allowlist-{}.json", std::process::id()));
+        let image = |name: &str, mrtd: u8, expires: &str| {
+            serde_json::json!({
+                "name": name,
+                "mrtd": hex::encode([mrtd; 48]),
+                "rtmr1": hex::encode([9; 48]),
+                "expires": expires,
+            })
+        };
+        let write = |images: Vec<serde_json::Value>| {
+            fs::write(&path, serde_json::json!({ "images": images }).to_string()).unwrap()
+        };
+        write(vec![
+            image("old", 1, "2026-01-01"),
+            image("new", 2, "2027-01-01"),
+        ]);
+        let allowlist = Allowlist::load(&path).unwrap();
+        let td = |mrtd: u8| Td([[mrtd; 48], [0; 48], [9; 48], [0; 48], [0; 48]].concat());
+        let now = parse_date("2026-10-16").unwrap();
+
+        assert_eq!(allowlist.check(&td(2), now).unwrap(), "new");
+        assert!(allowlist
+            .check(&td(1), now)
+            .unwrap_err()
+            .to_string()
+            .contains("expired"));
+        assert!(allowlist.check(&td(3), now).is_err());
+        assert_eq!(parse_date("1970-01-02"), Some(86_400));
+        assert_eq!(parse_date("2024-03-01"), Some(1_709_251_200));
+
+        write(vec![image("next", 3, "2027-06-01")]);
+        allowlist.images.expire();
+        assert_eq!(allowlist.check(&td(3), now).unwrap(), "next");
+        fs::remove_file(&path).unwrap();
+    }
+}
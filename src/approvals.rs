@@ -0,0 +1,268 @@
+//! External approval of key releases. The policy's `approval` section names
+//! the launch measurements whose keys are high-value enough that a person or
+//! a change-management system must agree to each release. The provider asks
+//! the policy's approver endpoint, or, without one, holds the request until
+//! an operator approves or denies it over the admin API. A request that gets
+//! no decision in time is refused.
+
+use crate::error::ProviderError;
+use crate::evidence::Evidence;
+use crate::logging::new_request_id;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalPolicy {
+    /// Hex launch measurements (MRTD and so on) whose releases need
+    /// approval.
+    pub launch_measurements: Vec<String>,
+    /// Every release needs approval.
+    pub all: bool,
+    /// HTTPS endpoint asked to approve each release. Without it, releases
+    /// wait for the admin API.
+    pub approver_url: Option<String>,
+    /// How long to wait for a decision before refusing the request.
+    pub timeout_secs: u64,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            launch_measurements: Vec::new(),
+            all: false,
+            approver_url: None,
+            timeout_secs: 60,
+        }
+    }
+}
+
+impl ApprovalPolicy {
+    pub fn requires(&self, launch_measurement: &[u8]) -> bool {
+        let launch = hex::encode(launch_measurement);
+        self.all
+            || self
+                .launch_measurements
+                .iter()
+                .any(|listed| listed.eq_ignore_ascii_case(&launch))
+    }
+
+    /// `approver_url`, or `admin API` for approvals over the admin API.
+    pub fn approver(&self) -> &str {
+        self.approver_url.as_deref().unwrap_or("admin API")
+    }
+}
+
+/// What is asked of an approver, and listed for an operator.
+#[derive(Clone, Debug, Serialize)]
+pub struct ApprovalRequest {
+    pub id: String,
+    pub kind: &'static str,
+    pub launch_measurement: String,
+    pub measurements: String,
+    pub platform_id: String,
+    pub report_data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub requested_at: u64,
+}
+
+/// An approver's answer.
+#[derive(Debug, Deserialize)]
+struct Decision {
+    approved: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+struct Pending {
+    request: ApprovalRequest,
+    decide: oneshot::Sender<Decision>,
+}
+
+/// Releases waiting for an operator.
+#[derive(Default)]
+pub struct Approvals {
+    client: reqwest::Client,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl Approvals {
+    /// Waits for approval of the release for `evidence` if `policy` requires
+    /// one. Returns who approved it, or `None` if no approval was needed.
+    pub async fn approve(
+        &self,
+        policy: &ApprovalPolicy,
+        evidence: &dyn Evidence,
+        tenant: Option<&str>,
+        now: u64,
+    ) -> Result<Option<String>, ProviderError> {
+        if !policy.requires(evidence.launch_measurement()) {
+            return Ok(None);
+        }
+        let request = ApprovalRequest {
+            id: new_request_id(),
+            kind: evidence.kind().name(),
+            launch_measurement: hex::encode(evidence.launch_measurement()),
+            measurements: hex::encode(evidence.measurements()),
+            platform_id: hex::encode(evidence.platform_id()),
+            report_data: hex::encode(evidence.report_data()),
+            tenant: tenant.map(str::to_string),
+            requested_at: now,
+        };
+        let id = request.id.clone();
+        let timeout = Duration::from_secs(policy.timeout_secs);
+        info!(
+            "Release {} for {} awaits approval by {}",
+            id,
+            request.launch_measurement,
+            policy.approver()
+        );
+
+        let decision = match &policy.approver_url {
+            Some(url) => tokio::time::timeout(timeout, self.ask(url, &request)).await,
+            None => {
+                let (decide, decision) = oneshot::channel();
+                self.lock().insert(id.clone(), Pending { request, decide });
+                let decision = tokio::time::timeout(timeout, decision).await;
+                self.lock().remove(&id);
+                decision.map(|decided| {
+                    decided.map_err(|_| {
+                        ProviderError::PolicyViolation("Approval was abandoned".into())
+                    })
+                })
+            }
+        };
+        let decision = decision.map_err(|_| {
+            ProviderError::PolicyViolation(format!(
+                "No approval from {} within {} seconds",
+                policy.approver(),
+                policy.timeout_secs
+            ))
+        })??;
+
+        if !decision.approved {
+            return Err(ProviderError::PolicyViolation(format!(
+                "Release denied by {}: {}",
+                policy.approver(),
+                decision.reason.as_deref().unwrap_or("no reason given")
+            )));
+        }
+        info!("Release {} approved by {}", id, policy.approver());
+        Ok(Some(format!("{} {}", policy.approver(), id)))
+    }
+
+    /// Asks the approver at `url`. Anything but a decision is a refusal.
+    async fn ask(&self, url: &str, request: &ApprovalRequest) -> Result<Decision, ProviderError> {
+        let network_error =
+            |e: reqwest::Error| ProviderError::NetworkError(format!("Approver {}: {}", url, e));
+        let body = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_vec(request)?)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(network_error)?
+            .bytes()
+            .await
+            .map_err(network_error)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Releases waiting for an operator, oldest first.
+    pub fn pending(&self) -> Vec<ApprovalRequest> {
+        let mut pending: Vec<_> = self
+            .lock()
+            .values()
+            .map(|pending| pending.request.clone())
+            .collect();
+        pending.sort_by_key(|request| request.requested_at);
+        pending
+    }
+
+    /// An operator's decision on the release `id`.
+    pub fn decide(
+        &self,
+        id: &str,
+        approved: bool,
+        reason: Option<String>,
+    ) -> Result<(), ProviderError> {
+        let pending = self.lock().remove(id).ok_or_else(|| {
+            ProviderError::ConfigError(format!("No release {} awaits approval", id))
+        })?;
+        if !approved {
+            warn!("Release {} denied over the admin API", id);
+        }
+        pending
+            .decide
+            .send(Decision { approved, reason })
+            .map_err(|_| ProviderError::ConfigError(format!("Release {} timed out", id)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Pending>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::EvidenceKind;
+    use std::sync::Arc;
+
+    struct Td([u8; 48]);
+
+    impl Evidence for Td {
+        fn kind(&self) -> EvidenceKind {
+            EvidenceKind::Tdx
+        }
+        fn measurements(&self) -> &[u8] {
+            &self.0
+        }
+        fn report_data(&self) -> &[u8] {
+            &[]
+        }
+        fn platform_id(&self) -> &[u8] {
+            &[]
+        }
+        fn launch_measurement(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn listed_releases_wait_for_an_operator() {
+        let approvals = Arc::new(Approvals::default());
+        let policy = ApprovalPolicy {
+            launch_measurements: vec![hex::encode([1; 48])],
+            timeout_secs: 5,
+            ..ApprovalPolicy::default()
+        };
+
+        let unlisted = approvals.approve(&policy, &Td([2; 48]), None, 0).await;
+        assert_eq!(unlisted.unwrap(), None);
+
+        let operator = Arc::clone(&approvals);
+        let decided = tokio::spawn(async move {
+            loop {
+                if let Some(request) = operator.pending().pop() {
+                    return operator.decide(&request.id, false, Some("freeze".into()));
+                }
+                tokio::task::yield_now().await;
+            }
+        });
+        let denied = approvals.approve(&policy, &Td([1; 48]), None, 0).await;
+
+        decided.await.unwrap().unwrap();
+        assert!(denied.unwrap_err().to_string().contains("freeze"));
+        assert!(approvals.pending().is_empty());
+    }
+}
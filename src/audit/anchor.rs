@@ -0,0 +1,254 @@
+use super::unix_now;
+use crate::error::ProviderError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// Checkpoints waiting for the anchor thread. One that arrives while these
+/// are queued is dropped; the next checkpoint covers it.
+const QUEUE_LEN: usize = 4;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A signed checkpoint, as posted to the anchor.
+#[derive(Debug, Serialize)]
+pub struct AnchorRequest {
+    pub seq: u64,
+    pub head: String,
+    pub timestamp: u64,
+    pub identity_key: String,
+    pub signature: String,
+}
+
+/// What the anchor answered for the checkpoint at `seq`. The log records it
+/// in its chain, so later entries commit to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub seq: u64,
+    pub head: String,
+    pub url: String,
+    pub timestamp: u64,
+    /// The anchor's answer, as it sent it.
+    pub receipt: Value,
+}
+
+/// Posts checkpoints of the audit log to an external service, such as a
+/// transparency log or timestamping service, from a thread of its own. An
+/// insider who rewrites the log afterwards cannot make it match what the
+/// anchor holds.
+pub struct AuditAnchor {
+    queue: SyncSender<(AnchorRequest, Vec<u8>)>,
+    latest: Arc<Mutex<Option<Receipt>>>,
+}
+
+impl AuditAnchor {
+    pub fn start(url: &str) -> Result<Self, ProviderError> {
+        let (queue, requests) = mpsc::sync_channel(QUEUE_LEN);
+        let latest = Arc::new(Mutex::new(None));
+        let receipts = Arc::clone(&latest);
+        let thread_url = url.to_string();
+        thread::Builder::new()
+            .name("audit-anchor".into())
+            .spawn(move || run(thread_url, requests, receipts))?;
+        info!("Anchoring audit log checkpoints at {}", url);
+        Ok(Self { queue, latest })
+    }
+
+    /// Queues `checkpoint` for the anchor. Never waits for it.
+    pub fn submit(&self, checkpoint: AnchorRequest) {
+        match serde_json::to_vec(&checkpoint) {
+            Ok(body) => {
+                if self.queue.try_send((checkpoint, body)).is_err() {
+                    warn!("Audit anchor queue full, dropping a checkpoint");
+                }
+            }
+            Err(e) => warn!("Failed to encode audit checkpoint: {}", e),
+        }
+    }
+
+    /// The newest receipt not yet recorded in the log.
+    pub fn take(&self) -> Option<Receipt> {
+        lock(&self.latest).take()
+    }
+
+    /// Puts back a receipt taken for an entry that was not written, unless a
+    /// newer one arrived meanwhile.
+    pub fn restore(&self, receipt: Receipt) {
+        lock(&self.latest).get_or_insert(receipt);
+    }
+}
+
+/// Posts queued checkpoints until the provider exits. A failed post is
+/// logged and not retried; the next checkpoint commits to everything before.
+fn run(
+    url: String,
+    requests: Receiver<(AnchorRequest, Vec<u8>)>,
+    latest: Arc<Mutex<Option<Receipt>>>,
+) {
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Cannot create audit anchor client, anchoring is off: {}", e);
+            return;
+        }
+    };
+
+    for (checkpoint, body) in requests {
+        let response = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .and_then(reqwest::blocking::Response::error_for_status)
+            .and_then(reqwest::blocking::Response::bytes)
+            .map_err(|e| e.to_string())
+            .and_then(|body| serde_json::from_slice::<Value>(&body).map_err(|e| e.to_string()));
+        match response {
+            Ok(receipt) => {
+                info!("Audit log anchored at entry {}", checkpoint.seq);
+                *lock(&latest) = Some(Receipt {
+                    seq: checkpoint.seq,
+                    head: checkpoint.head,
+                    url: url.clone(),
+                    timestamp: unix_now(),
+                    receipt,
+                });
+            }
+            Err(e) => warn!(
+                "Anchoring audit entry {} at {} failed: {}",
+                checkpoint.seq, url, e
+            ),
+        }
+    }
+}
+
+fn lock(latest: &Mutex<Option<Receipt>>) -> MutexGuard<'_, Option<Receipt>> {
+    latest
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    fn checkpoint(seq: u64) -> AnchorRequest {
+        AnchorRequest {
+            seq,
+            head: "aa".repeat(32),
+            timestamp: 1,
+            identity_key: "bb".repeat(32),
+            signature: "cc".repeat(64),
+        }
+    }
+
+    fn receipt(seq: u64) -> Receipt {
+        Receipt {
+            seq,
+            head: "aa".repeat(32),
+            url: "https://anchor.example".into(),
+            timestamp: 1,
+            receipt: json!({ "index": seq }),
+        }
+    }
+
+    /// An anchor whose queue nothing drains.
+    fn idle_anchor() -> (AuditAnchor, Receiver<(AnchorRequest, Vec<u8>)>) {
+        let (queue, requests) = mpsc::sync_channel(QUEUE_LEN);
+        let anchor = AuditAnchor {
+            queue,
+            latest: Arc::new(Mutex::new(None)),
+        };
+        (anchor, requests)
+    }
+
+    #[test]
+    fn receipts_are_taken_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/anchor", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let posted: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(posted["seq"], 7);
+
+            let answer = br#"{"index":42}"#;
+            let mut stream = reader.into_inner();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                answer.len()
+            )
+            .unwrap();
+            stream.write_all(answer).unwrap();
+        });
+
+        let anchor = AuditAnchor::start(&url).unwrap();
+        anchor.submit(checkpoint(7));
+        server.join().unwrap();
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let receipt = loop {
+            if let Some(receipt) = anchor.take() {
+                break receipt;
+            }
+            assert!(Instant::now() < deadline, "no receipt from the anchor");
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        assert_eq!((receipt.seq, receipt.url.as_str()), (7, url.as_str()));
+        assert_eq!(receipt.receipt, json!({ "index": 42 }));
+        assert_eq!(anchor.take(), None);
+    }
+
+    #[test]
+    fn restored_receipts_do_not_displace_newer_ones() {
+        let (anchor, _requests) = idle_anchor();
+
+        anchor.restore(receipt(1));
+        assert_eq!(anchor.take(), Some(receipt(1)));
+
+        *lock(&anchor.latest) = Some(receipt(2));
+        anchor.restore(receipt(1));
+        assert_eq!(anchor.take(), Some(receipt(2)));
+    }
+
+    #[test]
+    fn a_full_queue_drops_checkpoints_without_waiting() {
+        let (anchor, requests) = idle_anchor();
+
+        for seq in 0..QUEUE_LEN as u64 + 2 {
+            anchor.submit(checkpoint(seq));
+        }
+
+        let queued: Vec<u64> = requests
+            .try_iter()
+            .map(|(request, _)| request.seq)
+            .collect();
+        assert_eq!(queued, (0..QUEUE_LEN as u64).collect::<Vec<_>>());
+    }
+}
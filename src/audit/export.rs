@@ -0,0 +1,230 @@
+use super::Release;
+use crate::config::ExportFormat;
+use crate::connections;
+use crate::error::ProviderError;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bumped on changes that break consumers; adding fields is not one.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Events waiting for the exporter thread. A slow collector loses events
+/// beyond this rather than holding up key requests.
+const QUEUE_LEN: usize = 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A key request as exported. The release's fields are flattened in.
+#[derive(Debug, Serialize)]
+struct AuditEvent<'a> {
+    schema: u32,
+    event: &'static str,
+    /// `released` or `denied`; the reason is in `verdict`.
+    outcome: &'static str,
+    /// Sequence number in the audit log, when one is kept.
+    audit_seq: Option<u64>,
+    /// Request id of the connection, as in the logs.
+    request_id: Option<String>,
+    /// The provider identity key, hex, which also signs the audit log.
+    provider_key: String,
+    #[serde(flatten)]
+    release: &'a Release,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    File(PathBuf),
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Target {
+    fn parse(target: &str) -> Result<Self, ProviderError> {
+        if let Some(path) = target.strip_prefix("file:") {
+            Ok(Target::File(path.into()))
+        } else if let Some(addr) = target.strip_prefix("tcp:") {
+            Ok(Target::Tcp(addr.to_string()))
+        } else if let Some(path) = target.strip_prefix("unix:") {
+            Ok(Target::Unix(path.into()))
+        } else {
+            Err(ProviderError::ConfigError(format!(
+                "Unknown audit export target {:?}; expected file:<path>, tcp:<host>:<port> \
+                 or unix:<path>",
+                target
+            )))
+        }
+    }
+
+    fn open(&self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            Target::File(path) => {
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            Target::Tcp(addr) => {
+                let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+                })?;
+                Box::new(TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?)
+            }
+            Target::Unix(path) => Box::new(UnixStream::connect(path)?),
+        })
+    }
+}
+
+/// Streams every key release and denial to a SIEM collector, as NDJSON or
+/// CBOR, on a thread of its own.
+///
+/// Unlike the audit log this is best effort: events are dropped, and counted
+/// in a warning, while the collector is unreachable or falling behind. The
+/// audit log stays the record to reconcile against, through `audit_seq`.
+pub struct AuditExport {
+    format: ExportFormat,
+    queue: SyncSender<Vec<u8>>,
+    dropped: AtomicU64,
+}
+
+impl AuditExport {
+    /// `target` is `file:<path>`, `tcp:<host>:<port>` or `unix:<path>`. An
+    /// unreachable collector is retried, so startup does not depend on it.
+    pub fn start(target: &str, format: ExportFormat) -> Result<Self, ProviderError> {
+        let parsed = Target::parse(target)?;
+        let (queue, events) = mpsc::sync_channel(QUEUE_LEN);
+        thread::Builder::new()
+            .name("audit-export".into())
+            .spawn(move || run(parsed, events))?;
+        info!("Exporting audit events to {} as {:?}", target, format);
+        Ok(Self {
+            format,
+            queue,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queues `release` for export without waiting for the collector.
+    pub fn send(&self, release: &Release, audit_seq: Option<u64>, provider_key: &[u8]) {
+        let event = AuditEvent {
+            schema: SCHEMA_VERSION,
+            event: "key_request",
+            outcome: if release.verdict == "released" {
+                "released"
+            } else {
+                "denied"
+            },
+            audit_seq,
+            request_id: connections::current().map(|(request_id, _)| request_id),
+            provider_key: hex::encode(provider_key),
+            release,
+        };
+        let encoded = match encode(&event, self.format) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                warn!("Failed to encode audit event: {}", e.chain());
+                return;
+            }
+        };
+        if self.queue.try_send(encoded).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!("Audit export queue full, {} events dropped so far", dropped);
+        }
+    }
+}
+
+fn encode(event: &AuditEvent<'_>, format: ExportFormat) -> Result<Vec<u8>, ProviderError> {
+    match format {
+        ExportFormat::Ndjson => {
+            let mut line = serde_json::to_vec(event)?;
+            line.push(b'\n');
+            Ok(line)
+        }
+        ExportFormat::Cbor => {
+            let mut item = Vec::new();
+            coset::cbor::ser::into_writer(event, &mut item)
+                .map_err(|e| ProviderError::SerializationError(e.to_string()))?;
+            Ok(item)
+        }
+    }
+}
+
+/// Writes queued events until the provider exits, reconnecting at most
+/// every `RECONNECT_INTERVAL` and dropping events in between.
+fn run(target: Target, events: Receiver<Vec<u8>>) {
+    let mut sink: Option<Box<dyn Write + Send>> = None;
+    let mut last_attempt: Option<Instant> = None;
+    let mut lost = 0u64;
+
+    for event in events {
+        if sink.is_none() && last_attempt.is_none_or(|at| at.elapsed() >= RECONNECT_INTERVAL) {
+            last_attempt = Some(Instant::now());
+            match target.open() {
+                Ok(opened) => {
+                    if lost > 0 {
+                        warn!("Audit export reconnected after dropping {} events", lost);
+                        lost = 0;
+                    }
+                    sink = Some(opened);
+                }
+                Err(e) => warn!("Cannot reach audit export target {:?}: {}", target, e),
+            }
+        }
+        let Some(writer) = sink.as_mut() else {
+            lost += 1;
+            continue;
+        };
+        if let Err(e) = writer.write_all(&event).and_then(|()| writer.flush()) {
+            warn!("Audit export to {:?} failed: {}", target, e);
+            sink = None;
+            lost += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_follow_the_documented_schema() {
+        let release = Release {
+            timestamp: 7,
+            ppid: "00".repeat(16),
+            measurements: "11".repeat(240),
+            report_data: "22".repeat(64),
+            verdict: "denied: PPID mismatch".into(),
+            key_id: None,
+            tenant: None,
+            approval: None,
+            reissued: false,
+            suppressed: 0,
+        };
+        let event = AuditEvent {
+            schema: SCHEMA_VERSION,
+            event: "key_request",
+            outcome: "denied",
+            audit_seq: Some(3),
+            request_id: None,
+            provider_key: "ab".into(),
+            release: &release,
+        };
+
+        let line = encode(&event, ExportFormat::Ndjson).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        let cbor = encode(&event, ExportFormat::Cbor).unwrap();
+        let decoded: serde_json::Value = coset::cbor::de::from_reader(&cbor[..]).unwrap();
+
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(json["schema"], 1);
+        assert_eq!(json["outcome"], "denied");
+        assert_eq!(json["verdict"], "denied: PPID mismatch");
+        assert_eq!(json["audit_seq"], 3);
+        assert!(json.get("key_id").is_none());
+        assert_eq!(decoded, json);
+    }
+}
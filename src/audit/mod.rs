@@ -0,0 +1,968 @@
+use crate::crypto::{backend, Signer};
+use crate::error::ProviderError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod anchor;
+mod export;
+
+pub use anchor::AuditAnchor;
+pub use export::AuditExport;
+
+use anchor::{AnchorRequest, Receipt};
+
+const ENTRY_LABEL: &[u8] = b"gramine-sealing-key-provider/audit-entry/v1";
+const CHECKPOINT_LABEL: &[u8] = b"gramine-sealing-key-provider/audit-checkpoint/v1";
+const ANCHOR_LABEL: &[u8] = b"gramine-sealing-key-provider/audit-anchor/v1";
+/// A checkpoint is signed after this many entries, or on the first entry once
+/// `CHECKPOINT_INTERVAL` has passed since the last one.
+const CHECKPOINT_EVERY: u64 = 100;
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Denials recorded one by one per `DENIAL_WINDOW`. Further ones are only
+/// counted, and the count goes into the next denial that is recorded, so a
+/// flood of bad quotes cannot grow the log without bound.
+const DENIALS_PER_WINDOW: u32 = 60;
+const DENIAL_WINDOW: Duration = Duration::from_secs(60);
+/// Bytes read at a time when a query walks the log from its end.
+const QUERY_BLOCK: u64 = 64 * 1024;
+
+/// One key request that reached the release decision.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub timestamp: u64,
+    /// PPID from the TD quote header, or chip ID of an SNP report, hex.
+    pub ppid: String,
+    /// MRTD followed by RTMR0-3 (or the SNP derivation input), hex.
+    pub measurements: String,
+    /// The TD's report data, which commits to its public key, hex.
+    pub report_data: String,
+    /// `released`, or `denied: <reason>`.
+    pub verdict: String,
+    /// Key id of the released key, hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Tenant the key was derived for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Who approved the release, for one the policy made wait for approval.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<String>,
+    /// The key was released for a re-issuance token, without verifying the
+    /// evidence against collateral.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reissued: bool,
+    /// Denials before this one that were counted but not recorded.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed: u64,
+}
+
+impl Release {
+    fn is_denial(&self) -> bool {
+        self.verdict != "released"
+    }
+}
+
+fn is_zero(count: &u64) -> bool {
+    *count == 0
+}
+
+/// Which entries an audit query returns. Hex fields are compared without
+/// regard to case, and every filter given must match.
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    pub launch_measurement: Option<String>,
+    pub ppid: Option<String>,
+    pub key_id: Option<String>,
+    pub tenant: Option<String>,
+    /// `released` or `denied`.
+    pub verdict: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub before: Option<u64>,
+}
+
+impl Query {
+    fn matches(&self, seq: u64, release: &Release) -> bool {
+        let is = |filter: &Option<String>, value: Option<&str>| match filter {
+            Some(filter) => value.is_some_and(|value| filter.eq_ignore_ascii_case(value)),
+            None => true,
+        };
+        let launch_measurement = self.launch_measurement.as_deref().is_none_or(|filter| {
+            release
+                .measurements
+                .get(..filter.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(filter))
+        });
+        let verdict = self.verdict.as_deref().is_none_or(|verdict| {
+            release
+                .verdict
+                .split(':')
+                .next()
+                .is_some_and(|outcome| outcome == verdict)
+        });
+        launch_measurement
+            && verdict
+            && is(&self.ppid, Some(&release.ppid))
+            && is(&self.key_id, release.key_id.as_deref())
+            && is(&self.tenant, release.tenant.as_deref())
+            && self.since.is_none_or(|since| release.timestamp >= since)
+            && self.until.is_none_or(|until| release.timestamp <= until)
+            && self.before.is_none_or(|before| seq < before)
+    }
+}
+
+/// An entry as an audit query returns it.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub release: Release,
+}
+
+/// Lines of the log. `hash` is
+/// `SHA-256(ENTRY_LABEL || prev_hash || JSON of the release)`, so each entry
+/// commits to all before it; checkpoints sign
+/// `CHECKPOINT_LABEL || seq || head` with the provider identity key. An
+/// anchor records an external anchor's receipt for an earlier checkpoint in
+/// the chain, as `SHA-256(ANCHOR_LABEL || prev_hash || JSON of the receipt)`,
+/// without taking a sequence number.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Line {
+    Release {
+        seq: u64,
+        #[serde(flatten)]
+        release: Release,
+        prev_hash: String,
+        hash: String,
+    },
+    Checkpoint {
+        seq: u64,
+        timestamp: u64,
+        head: String,
+        identity_key: String,
+        signature: String,
+    },
+    Anchor {
+        #[serde(flatten)]
+        receipt: Receipt,
+        prev_hash: String,
+        hash: String,
+    },
+}
+
+struct State {
+    /// Bytes of complete lines in the file.
+    len: u64,
+    seq: u64,
+    head: [u8; 32],
+    unsigned: u64,
+    last_checkpoint: Option<Instant>,
+    /// Denials recorded since `denial_window` began.
+    denials: u32,
+    denial_window: Option<Instant>,
+    /// Denials counted since the last one recorded.
+    suppressed: u64,
+}
+
+impl State {
+    /// Whether a denial is recorded, and if so, how many were only counted
+    /// before it.
+    fn admit_denial(&mut self) -> Option<u64> {
+        let now = Instant::now();
+        let current =
+            matches!(self.denial_window, Some(start) if now.duration_since(start) < DENIAL_WINDOW);
+        if !current {
+            self.denial_window = Some(now);
+            self.denials = 0;
+        }
+        if self.denials >= DENIALS_PER_WINDOW {
+            if self.suppressed == 0 {
+                warn!(
+                    "More than {} denials in {:?}; counting further ones without recording them",
+                    DENIALS_PER_WINDOW, DENIAL_WINDOW
+                );
+            }
+            self.suppressed += 1;
+            return None;
+        }
+        self.denials += 1;
+        Some(std::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Append-only, hash-chained record of every key release and denial.
+///
+/// The file stays readable by the host so operators can audit it; edits,
+/// reordering and removed entries in the middle break the chain and are
+/// refused at startup. Cutting entries off the end is only detectable
+/// against a checkpoint kept elsewhere, so ship checkpoints off the host.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Held by a writer through its writes, so entries land in chain order
+    /// while the writes themselves run off the async threads.
+    file: tokio::sync::Mutex<Arc<File>>,
+    state: Mutex<State>,
+    anchor: Option<AuditAnchor>,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, checking the chain of any existing entries.
+    /// A final line cut short by a crash was never acknowledged, so it is
+    /// cut off.
+    pub fn open(path: &Path) -> Result<Self, ProviderError> {
+        let integrity = |e: &str| {
+            ProviderError::CryptoError(format!(
+                "Audit log {} failed its integrity check: {}",
+                path.display(),
+                e
+            ))
+        };
+        let (len, seq, head) = match fs::read(path) {
+            Ok(mut data) => {
+                let complete = complete_lines(&data).len();
+                if complete < data.len() {
+                    warn!(
+                        "Audit log {} ends in {} bytes of a torn line; truncating them",
+                        path.display(),
+                        data.len() - complete
+                    );
+                    OpenOptions::new()
+                        .write(true)
+                        .open(path)?
+                        .set_len(complete as u64)?;
+                    data.truncate(complete);
+                }
+                let data = String::from_utf8(data).map_err(|_| integrity("not UTF-8"))?;
+                let (seq, head) = verify_chain(&data).map_err(|e| integrity(&e))?;
+                (data.len() as u64, seq, head)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => (0, 0, [0u8; 32]),
+            Err(e) => return Err(ProviderError::IOError(e)),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        info!("Opened audit log {} at entry {}", path.display(), seq);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: tokio::sync::Mutex::new(Arc::new(file)),
+            state: Mutex::new(State {
+                len,
+                seq,
+                head,
+                unsigned: seq,
+                // The first entry after a restart is always checkpointed
+                last_checkpoint: None,
+                denials: 0,
+                denial_window: None,
+                suppressed: 0,
+            }),
+            anchor: None,
+        })
+    }
+
+    /// Also posts every checkpoint to `anchor`, and records its receipts in
+    /// the chain.
+    pub fn with_anchor(mut self, anchor: Option<AuditAnchor>) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Appends `release` and, when due, a signed checkpoint. Returns the
+    /// entry's sequence number once it is on disk, so a release is never sent
+    /// unrecorded, or `None` for a denial that was only counted.
+    pub async fn record(
+        &self,
+        mut release: Release,
+        signer: &dyn Signer,
+    ) -> Result<Option<u64>, ProviderError> {
+        if release.is_denial() {
+            match self.lock().admit_denial() {
+                Some(suppressed) => release.suppressed = suppressed,
+                None => return Ok(None),
+            }
+        }
+
+        let file = self.file.lock().await;
+        let (len, mut head, seq, checkpoint_due) = {
+            let state = self.lock();
+            let recent =
+                matches!(state.last_checkpoint, Some(at) if at.elapsed() < CHECKPOINT_INTERVAL);
+            let due = state.unsigned + 1 >= CHECKPOINT_EVERY || !recent;
+            (state.len, state.head, state.seq + 1, due)
+        };
+
+        // A receipt is only used up once the entry recording it is written
+        let receipt = self.anchor.as_ref().and_then(AuditAnchor::take);
+        let written = async {
+            let mut lines = Vec::new();
+            if let Some(receipt) = &receipt {
+                let hash = anchor_hash(&head, receipt)?;
+                encode(
+                    &mut lines,
+                    &Line::Anchor {
+                        receipt: receipt.clone(),
+                        prev_hash: hex::encode(head),
+                        hash: hex::encode(hash),
+                    },
+                )?;
+                head = hash;
+            }
+            let hash = entry_hash(&head, &release)?;
+            encode(
+                &mut lines,
+                &Line::Release {
+                    seq,
+                    release,
+                    prev_hash: hex::encode(head),
+                    hash: hex::encode(hash),
+                },
+            )?;
+            Ok::<_, ProviderError>((append(&file, len, lines).await?, hash))
+        }
+        .await;
+        let (len, hash) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                if let (Some(anchor), Some(receipt)) = (&self.anchor, receipt) {
+                    anchor.restore(receipt);
+                }
+                return Err(e);
+            }
+        };
+        {
+            let mut state = self.lock();
+            state.len = len;
+            state.seq = seq;
+            state.head = hash;
+            state.unsigned += 1;
+        }
+
+        if checkpoint_due {
+            let signature = signer.sign(&checkpoint_message(seq, &hash))?;
+            let request = AnchorRequest {
+                seq,
+                head: hex::encode(hash),
+                timestamp: unix_now(),
+                identity_key: hex::encode(signer.public_key()),
+                signature: hex::encode(signature),
+            };
+            let mut checkpoint = Vec::new();
+            encode(
+                &mut checkpoint,
+                &Line::Checkpoint {
+                    seq,
+                    timestamp: request.timestamp,
+                    head: request.head.clone(),
+                    identity_key: request.identity_key.clone(),
+                    signature: request.signature.clone(),
+                },
+            )?;
+            match append(&file, len, checkpoint).await {
+                Ok(len) => {
+                    let mut state = self.lock();
+                    state.len = len;
+                    state.unsigned = 0;
+                    state.last_checkpoint = Some(Instant::now());
+                    if let Some(anchor) = &self.anchor {
+                        anchor.submit(request);
+                    }
+                }
+                // The entry itself is recorded; the next one retries
+                Err(e) => warn!(
+                    "Failed to checkpoint {}: {}",
+                    self.path.display(),
+                    e.chain()
+                ),
+            }
+        }
+        Ok(Some(seq))
+    }
+
+    /// Up to `limit` entries from entry `from` on, with the checkpoints and
+    /// anchors between them, as they stand in the file. Saved one per line,
+    /// they can be checked with `verify-audit-log`.
+    pub async fn export(&self, from: u64, limit: usize) -> Result<Vec<Value>, ProviderError> {
+        self.read_recorded(move |file, len| {
+            let mut lines = Vec::new();
+            let mut entries = 0;
+            for line in BufReader::new(file.take(len)).lines() {
+                let line: Value = serde_json::from_str(&line?)?;
+                if line["kind"] == "release" {
+                    if line["seq"].as_u64().unwrap_or(0) < from {
+                        continue;
+                    }
+                    if entries == limit {
+                        break;
+                    }
+                    entries += 1;
+                }
+                if entries > 0 {
+                    lines.push(line);
+                }
+            }
+            Ok(lines)
+        })
+        .await
+    }
+
+    /// The newest `limit` entries `query` matches, newest first. The log is
+    /// read from its end, up to the oldest entry returned.
+    pub async fn query(&self, query: &Query, limit: usize) -> Result<Vec<Entry>, ProviderError> {
+        let query = query.clone();
+        self.read_recorded(move |file, len| {
+            let mut found = Vec::new();
+            let mut lines = LinesBackwards::new(file, len);
+            while found.len() < limit {
+                let Some(line) = lines.next_line()? else {
+                    break;
+                };
+                let Line::Release { seq, release, .. } = serde_json::from_slice::<Line>(&line)?
+                else {
+                    continue;
+                };
+                if query.matches(seq, &release) {
+                    found.push(Entry { seq, release });
+                }
+            }
+            Ok(found)
+        })
+        .await
+    }
+
+    /// Runs `read` on a blocking thread over the file and the length of the
+    /// lines recorded so far. Only the length is taken under the lock, so a
+    /// long read never holds up a release; entries written meanwhile, or cut
+    /// off again after a failed write, are not included.
+    async fn read_recorded<T: Send + 'static>(
+        &self,
+        read: impl FnOnce(File, u64) -> Result<T, ProviderError> + Send + 'static,
+    ) -> Result<T, ProviderError> {
+        let len = self.lock().len;
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || read(File::open(path)?, len))
+            .await
+            .map_err(|e| ProviderError::IOError(io::Error::other(e)))?
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn encode(lines: &mut Vec<u8>, line: &Line) -> Result<(), ProviderError> {
+    serde_json::to_writer(&mut *lines, line)?;
+    lines.push(b'\n');
+    Ok(())
+}
+
+/// Appends `lines` to the `len` bytes in `file` and syncs them on a blocking
+/// thread, returning the new length. A failed write is cut off again, so the
+/// next entry still chains.
+async fn append(file: &Arc<File>, len: u64, lines: Vec<u8>) -> Result<u64, ProviderError> {
+    let file = Arc::clone(file);
+    tokio::task::spawn_blocking(move || {
+        let written = (&*file).write_all(&lines).and_then(|()| file.sync_data());
+        if written.is_err() {
+            let _ = file.set_len(len);
+        }
+        written.map(|()| len + lines.len() as u64)
+    })
+    .await
+    .map_err(|e| ProviderError::IOError(io::Error::other(e)))?
+    .map_err(ProviderError::from)
+}
+
+/// The lines in the first `len` bytes of a file, last first, read a block at
+/// a time from the end.
+struct LinesBackwards {
+    file: File,
+    /// Where the bytes not read yet end.
+    pos: u64,
+    /// Bytes read but not returned yet, up to the end of the last line not
+    /// returned.
+    tail: Vec<u8>,
+}
+
+impl LinesBackwards {
+    fn new(file: File, len: u64) -> Self {
+        Self {
+            file,
+            pos: len,
+            tail: Vec::new(),
+        }
+    }
+
+    fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(newline) = self.tail.iter().rposition(|&b| b == b'\n') {
+                let line = self.tail.split_off(newline + 1);
+                self.tail.truncate(newline);
+                // The newline ending the file
+                if line.is_empty() {
+                    continue;
+                }
+                return Ok(Some(line));
+            }
+            if self.pos == 0 {
+                return Ok(Some(std::mem::take(&mut self.tail)).filter(|line| !line.is_empty()));
+            }
+            let start = self.pos.saturating_sub(QUERY_BLOCK);
+            let mut block = vec![0; (self.pos - start) as usize];
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut block)?;
+            block.append(&mut self.tail);
+            self.tail = block;
+            self.pos = start;
+        }
+    }
+}
+
+/// `data` up to and including its last newline.
+fn complete_lines(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+fn entry_hash(prev: &[u8; 32], release: &Release) -> Result<[u8; 32], ProviderError> {
+    Ok(backend().sha256(&[ENTRY_LABEL, prev, &serde_json::to_vec(release)?]))
+}
+
+fn anchor_hash(prev: &[u8; 32], receipt: &Receipt) -> Result<[u8; 32], ProviderError> {
+    Ok(backend().sha256(&[ANCHOR_LABEL, prev, &serde_json::to_vec(receipt)?]))
+}
+
+fn checkpoint_message(seq: u64, head: &[u8; 32]) -> Vec<u8> {
+    let mut message = CHECKPOINT_LABEL.to_vec();
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(head);
+    message
+}
+
+/// Walks the chain and returns the last sequence number and head hash.
+/// Checkpoint signatures are left to auditors, who know which identity keys
+/// to trust.
+fn verify_chain(data: &str) -> Result<(u64, [u8; 32]), String> {
+    let walk = walk(data, false)?;
+    Ok((walk.seq, walk.head))
+}
+
+/// What an auditor learns from a log, or from part of one exported through
+/// the admin API.
+#[derive(Debug, Serialize)]
+pub struct Verified {
+    /// The first and last entries.
+    pub first: u64,
+    pub last: u64,
+    pub head: String,
+    /// Checkpoints whose signatures verified, and the keys that made them.
+    pub checkpoints: usize,
+    pub identity_keys: Vec<String>,
+    /// Receipts of external anchors, oldest first.
+    pub anchors: Vec<Value>,
+}
+
+/// Checks a log or an export of one for a third party: the chain, and every
+/// checkpoint's signature. With `identity_key`, every checkpoint must be
+/// signed by that key. An export may start at any entry; it is then chained
+/// from that entry's `prev_hash`, which an earlier export or anchor vouches
+/// for.
+pub fn verify_export(data: &str, identity_key: Option<&[u8]>) -> Result<Verified, String> {
+    let walk = walk(data, true)?;
+    let mut identity_keys = Vec::new();
+    for (seq, head, key, signature) in &walk.checkpoints {
+        let key = hex::decode(key).map_err(|e| format!("checkpoint at {}: {}", seq, e))?;
+        let signature =
+            hex::decode(signature).map_err(|e| format!("checkpoint at {}: {}", seq, e))?;
+        if identity_key.is_some_and(|trusted| trusted != key.as_slice()) {
+            return Err(format!(
+                "checkpoint at {} is signed by another key, {}",
+                seq,
+                hex::encode(&key)
+            ));
+        }
+        if !verify_signature(&key, &checkpoint_message(*seq, head), &signature) {
+            return Err(format!("checkpoint at {} has a bad signature", seq));
+        }
+        let key = hex::encode(key);
+        if !identity_keys.contains(&key) {
+            identity_keys.push(key);
+        }
+    }
+    Ok(Verified {
+        first: walk.first,
+        last: walk.seq,
+        head: hex::encode(walk.head),
+        checkpoints: walk.checkpoints.len(),
+        identity_keys,
+        anchors: walk.anchors,
+    })
+}
+
+fn verify_signature(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    use sodiumoxide::crypto::sign;
+    let Some(key) = sign::PublicKey::from_slice(key) else {
+        return false;
+    };
+    if signature.len() != sign::SIGNATUREBYTES {
+        return false;
+    }
+    let signed = [signature, message].concat();
+    sign::verify(&signed, &key).is_ok()
+}
+
+struct Walk {
+    first: u64,
+    seq: u64,
+    head: [u8; 32],
+    /// Sequence number, head, identity key and signature of each checkpoint.
+    checkpoints: Vec<(u64, [u8; 32], String, String)>,
+    anchors: Vec<Value>,
+}
+
+/// Walks the chain from the start of the log or, for a `part`, from the
+/// first line, which must then be an entry.
+fn walk(data: &str, part: bool) -> Result<Walk, String> {
+    let mut seq = 0;
+    let mut head = [0u8; 32];
+    let mut first = None;
+    let mut checkpoints = Vec::new();
+    // Heads of the checkpoints seen, which anchors must name
+    let mut signed = HashMap::new();
+    let mut anchors = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        let line: Line =
+            serde_json::from_str(line).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        if part && number == 0 {
+            let Line::Release {
+                seq: entry_seq,
+                prev_hash,
+                ..
+            } = &line
+            else {
+                return Err("an export must start at an entry".into());
+            };
+            seq = entry_seq.saturating_sub(1);
+            head = decode_hash(prev_hash).ok_or("entry has a malformed prev_hash")?;
+        }
+        match line {
+            Line::Release {
+                seq: entry_seq,
+                release,
+                prev_hash,
+                hash,
+            } => {
+                let expected = entry_hash(&head, &release).map_err(|e| e.to_string())?;
+                if entry_seq != seq + 1
+                    || prev_hash != hex::encode(head)
+                    || hash != hex::encode(expected)
+                {
+                    return Err(format!("entry {} does not chain", entry_seq));
+                }
+                first.get_or_insert(entry_seq);
+                seq = entry_seq;
+                head = expected;
+            }
+            Line::Checkpoint {
+                seq: checkpoint_seq,
+                head: checkpoint_head,
+                identity_key,
+                signature,
+                ..
+            } => {
+                if checkpoint_seq != seq || checkpoint_head != hex::encode(head) {
+                    return Err(format!("checkpoint at {} does not match", checkpoint_seq));
+                }
+                signed.insert(checkpoint_seq, checkpoint_head);
+                checkpoints.push((checkpoint_seq, head, identity_key, signature));
+            }
+            Line::Anchor {
+                receipt,
+                prev_hash,
+                hash,
+            } => {
+                let expected = anchor_hash(&head, &receipt).map_err(|e| e.to_string())?;
+                if prev_hash != hex::encode(head) || hash != hex::encode(expected) {
+                    return Err(format!("anchor for {} does not chain", receipt.seq));
+                }
+                // An export may start after the checkpoint an anchor names
+                let known = signed.get(&receipt.seq);
+                if known.is_some_and(|known| *known != receipt.head)
+                    || (known.is_none() && !(part && receipt.seq < first.unwrap_or(0)))
+                {
+                    return Err(format!("anchor for {} names no checkpoint", receipt.seq));
+                }
+                head = expected;
+                anchors.push(serde_json::to_value(&receipt).map_err(|e| e.to_string())?);
+            }
+        }
+    }
+    Ok(Walk {
+        first: first.unwrap_or(0),
+        seq,
+        head,
+        checkpoints,
+        anchors,
+    })
+}
+
+fn decode_hash(hash: &str) -> Option<[u8; 32]> {
+    hex::decode(hash).ok()?.try_into().ok()
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+
+    fn release(verdict: &str) -> Release {
+        Release {
+            timestamp: 1,
+            ppid: "00".repeat(16),
+            measurements: "11".repeat(240),
+            report_data: "22".repeat(64),
+            verdict: verdict.to_string(),
+            key_id: None,
+            tenant: None,
+            approval: None,
+            reissued: false,
+            suppressed: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_survives_reopen_and_detects_edits() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[1u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record(release("released"), &identity).await.unwrap();
+        log.record(release("denied: PPID mismatch"), &identity)
+            .await
+            .unwrap();
+        drop(log);
+
+        let reopened = AuditLog::open(&path).unwrap();
+        reopened
+            .record(release("released"), &identity)
+            .await
+            .unwrap();
+        drop(reopened);
+
+        let data = fs::read_to_string(&path).unwrap();
+        assert_eq!(verify_chain(&data).unwrap().0, 3);
+        assert!(data.contains("\"kind\":\"checkpoint\""));
+
+        fs::write(&path, data.replacen("denied: PPID mismatch", "released", 1)).unwrap();
+        let tampered = AuditLog::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(tampered.is_err());
+    }
+
+    #[tokio::test]
+    async fn torn_final_lines_are_cut_off() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[4u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-torn-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record(release("released"), &identity).await.unwrap();
+        drop(log);
+        let complete = fs::read(&path).unwrap();
+        let mut torn = complete.clone();
+        torn.extend_from_slice(br#"{"kind":"release","seq":2,"#);
+        fs::write(&path, &torn).unwrap();
+
+        let reopened = AuditLog::open(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        let seq = reopened.record(release("released"), &identity).await;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(seq.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn denial_floods_are_counted_rather_than_recorded() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[5u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-flood-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        for _ in 0..DENIALS_PER_WINDOW {
+            let seq = log.record(release("denied: bad quote"), &identity).await;
+            assert!(seq.unwrap().is_some());
+        }
+        let flooded = log.record(release("denied: bad quote"), &identity).await;
+        let released = log.record(release("released"), &identity).await;
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(flooded.unwrap(), None);
+        assert_eq!(released.unwrap(), Some(DENIALS_PER_WINDOW as u64 + 1));
+    }
+
+    #[tokio::test]
+    async fn exports_verify_against_the_identity_key() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[2u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-export-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        for _ in 0..3 {
+            log.record(release("released"), &identity).await.unwrap();
+        }
+        // Bytes past what was recorded, as from a write still in progress
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not yet recorded\n")
+            .unwrap();
+        let lines = log.export(2, 1).await.unwrap();
+        let full = log.export(1, 10).await.unwrap();
+        fs::remove_file(&path).unwrap();
+        let to_jsonl = |lines: &[Value]| {
+            lines
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect::<String>()
+        };
+
+        assert_eq!(lines.len(), 1);
+        let part = verify_export(&to_jsonl(&lines), Some(identity.public_key())).unwrap();
+        assert_eq!((part.first, part.last), (2, 2));
+        let verified = verify_export(&to_jsonl(&full), Some(identity.public_key())).unwrap();
+        assert_eq!(
+            (verified.first, verified.last, verified.checkpoints),
+            (1, 3, 1)
+        );
+        assert!(verify_export(&to_jsonl(&full), Some(&[0u8; 32])).is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_return_the_newest_matching_entries_first() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[3u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-query-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        for (timestamp, verdict, key_id) in [
+            (10, "released", Some("AA")),
+            (20, "denied: PPID mismatch", None),
+            (30, "released", Some("bb")),
+        ] {
+            let mut entry = release(verdict);
+            entry.timestamp = timestamp;
+            entry.key_id = key_id.map(str::to_string);
+            log.record(entry, &identity).await.unwrap();
+        }
+        // Bytes past what was recorded, as from a write still in progress
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not yet recorded\n")
+            .unwrap();
+        let seqs = |entries: Vec<Entry>| entries.iter().map(|entry| entry.seq).collect::<Vec<_>>();
+
+        let released = Query {
+            verdict: Some("released".into()),
+            ..Query::default()
+        };
+        assert_eq!(seqs(log.query(&released, 10).await.unwrap()), [3, 1]);
+        assert_eq!(seqs(log.query(&released, 1).await.unwrap()), [3]);
+        let denied = Query {
+            verdict: Some("denied".into()),
+            launch_measurement: Some("1111".into()),
+            ..Query::default()
+        };
+        assert_eq!(seqs(log.query(&denied, 10).await.unwrap()), [2]);
+        let by_key = Query {
+            key_id: Some("aa".into()),
+            until: Some(15),
+            ..Query::default()
+        };
+        assert_eq!(seqs(log.query(&by_key, 10).await.unwrap()), [1]);
+        let paged = Query {
+            before: Some(3),
+            since: Some(15),
+            ..Query::default()
+        };
+        assert_eq!(seqs(log.query(&paged, 10).await.unwrap()), [2]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_stop_once_the_limit_is_reached() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[6u8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let path = std::env::temp_dir().join(format!("audit-limit-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let log = AuditLog::open(&path).unwrap();
+        for _ in 0..5 {
+            log.record(release("released"), &identity).await.unwrap();
+        }
+        // A line the read must never reach is garbled, keeping its length
+        // and the log's
+        let data = fs::read_to_string(&path).unwrap();
+        let garbled = |seq: u64| {
+            let entry = format!("\"kind\":\"release\",\"seq\":{},", seq);
+            data.lines()
+                .map(|line| {
+                    let line = if line.contains(&entry) {
+                        "x".repeat(line.len())
+                    } else {
+                        line.to_string()
+                    };
+                    line + "\n"
+                })
+                .collect::<String>()
+        };
+
+        // An export reads from the start, up to the entry after its last
+        fs::write(&path, garbled(5)).unwrap();
+        let oldest = log.export(1, 3).await.unwrap();
+        // A query reads from the end, back to the oldest entry it returns
+        fs::write(&path, garbled(1)).unwrap();
+        let newest = log.query(&Query::default(), 3).await.unwrap();
+        let everything = log.query(&Query::default(), 10).await;
+        fs::remove_file(&path).unwrap();
+
+        let exported: Vec<u64> = oldest
+            .iter()
+            .filter(|line| line["kind"] == "release")
+            .filter_map(|line| line["seq"].as_u64())
+            .collect();
+        assert_eq!(exported, [1, 2, 3]);
+        let seqs: Vec<u64> = newest.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, [4, 3, 2]);
+        assert!(everything.is_err());
+    }
+}
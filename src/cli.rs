@@ -0,0 +1,327 @@
+use crate::audit;
+use crate::collateral::CollateralCache;
+use crate::config::{Config, LogFormat, LoggingConfig, CONFIG_ENV};
+use crate::crypto::{self, compute_key_confirmation, compute_key_id, KdfVersion, MasterSecret};
+use crate::diagnostics;
+use crate::error::ProviderError;
+use crate::gramine;
+use crate::logging;
+use crate::policy::Policy;
+use crate::quote::{self, PckInfo};
+use crate::revocations::RevocationList;
+use crate::verifier;
+use clap::{Args, Parser, Subcommand};
+use dcap_qvl::quote::{Quote, Report};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// MRTD followed by RTMR0-3.
+const MEASUREMENTS_LEN: usize = 5 * 48;
+
+#[derive(Parser)]
+#[command(
+    name = "gramine-sealing-key-provider",
+    version,
+    about = "Derives sealing keys for attested TDX workloads"
+)]
+pub struct Cli {
+    /// TOML configuration file
+    #[arg(long, global = true, env = CONFIG_ENV, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Serve even in a configuration that is unsafe for production
+    #[arg(long = "insecure-i-know", global = true)]
+    pub insecure_i_know: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve key requests (the default)
+    Serve,
+    /// Validate the configuration and policy, then print the effective settings
+    CheckConfig,
+    /// Print the header, measurements and PCK details of a quote
+    InspectQuote(InspectQuoteArgs),
+    /// Check the crypto backend, hardware entropy and the platform's own quote
+    SelfTest,
+    /// Derive a key from a given root key and measurements, for client tests
+    DeriveTestvector(DeriveTestvectorArgs),
+    /// Check an audit log, or entries exported from one, and its checkpoint
+    /// signatures
+    VerifyAuditLog(VerifyAuditLogArgs),
+}
+
+#[derive(Args)]
+pub struct InspectQuoteArgs {
+    /// Quote file, raw or hex-encoded
+    pub path: PathBuf,
+
+    /// Also verify the quote with the configured verifier and collateral source
+    #[arg(long)]
+    pub verify: bool,
+}
+
+#[derive(Args)]
+pub struct DeriveTestvectorArgs {
+    /// Root (sealing) key, hex, at least 16 bytes. Never pass a real one:
+    /// command lines are visible to other users of the host
+    #[arg(long, value_name = "HEX")]
+    pub sealing_key: String,
+
+    /// MRTD followed by RTMR0-3 (240 bytes), hex
+    #[arg(long, value_name = "HEX")]
+    pub measurements: String,
+
+    /// KSS partition appended to the extraction salt, hex
+    #[arg(long, value_name = "HEX")]
+    pub partition: Option<String>,
+
+    /// Key derivation to use, as the policy's `kdf_version` names it
+    #[arg(long, default_value_t = 1, value_name = "1|2")]
+    pub kdf_version: u8,
+}
+
+#[derive(Args)]
+pub struct VerifyAuditLogArgs {
+    /// Audit log, or exported lines saved one per line
+    pub path: PathBuf,
+
+    /// Provider identity key every checkpoint must be signed with, hex
+    #[arg(long, value_name = "HEX")]
+    pub identity_key: Option<String>,
+}
+
+pub fn check_config(config: &Config) -> Result<(), ProviderError> {
+    if let Some(path) = &config.files.policy {
+        Policy::load(path)?;
+    }
+    if let Some(path) = &config.files.revocation_list {
+        RevocationList::load(path)?;
+    }
+    let effective = toml::to_string_pretty(config)
+        .map_err(|e| ProviderError::SerializationError(e.to_string()))?;
+    print!("{}", effective);
+    Ok(())
+}
+
+pub async fn inspect_quote(
+    args: &InspectQuoteArgs,
+    config_path: Option<&Path>,
+) -> Result<(), ProviderError> {
+    let data = read_quote(&args.path)?;
+    let quote = Quote::parse(&data).map_err(|e| ProviderError::quote_decode("quote", e))?;
+
+    let mut output = json!({
+        "version": quote.header.version,
+        "tee_type": format!("{:#x}", quote.header.tee_type),
+        "qe_svn": quote.header.qe_svn,
+        "pce_svn": quote.header.pce_svn,
+        "ppid": hex::encode(&quote.header.user_data[..16]),
+        "report": describe_report(&quote.report),
+    });
+    output["pck"] = match PckInfo::from_quote(&quote) {
+        Ok(pck) => json!({
+            "ppid": hex::encode(&pck.ppid),
+            "fmspc": hex::encode(&pck.fmspc),
+            "sgx_type": pck.sgx_type,
+            "multi_package": pck.is_multi_package(),
+            "platform_instance_id": pck.platform_instance_id.as_ref().map(hex::encode),
+        }),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+
+    if args.verify {
+        let config = Config::load(config_path)?;
+        let collateral = CollateralCache::from_config(&config.collateral)?;
+        let verifier =
+            verifier::by_name(&config.collateral.verifier, collateral, &config.veraison)?;
+        output["verification"] = match verifier.verify(&data).await {
+            Ok(verified) => json!({
+                "verifier": verifier.name(),
+                "tcb_status": verified.tcb_status,
+                "advisory_ids": verified.advisory_ids,
+            }),
+            Err(e) => json!({ "verifier": verifier.name(), "error": e.to_string() }),
+        };
+    }
+
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Quote files are written raw by most tools, but hex is easier to paste.
+fn read_quote(path: &Path) -> Result<Vec<u8>, ProviderError> {
+    let data = fs::read(path)?;
+    match std::str::from_utf8(&data).map(str::trim) {
+        Ok(text) if !text.is_empty() => Ok(hex::decode(text).unwrap_or(data)),
+        _ => Ok(data),
+    }
+}
+
+fn describe_report(report: &Report) -> Value {
+    match report {
+        Report::SgxEnclave(report) => json!({
+            "type": "sgx",
+            "mr_enclave": hex::encode(report.mr_enclave),
+            "mr_signer": hex::encode(report.mr_signer),
+            "isv_prod_id": report.isv_prod_id,
+            "isv_svn": report.isv_svn,
+            "attributes": hex::encode(report.attributes),
+            "report_data": hex::encode(report.report_data),
+        }),
+        Report::TD10(report) => describe_td(report, "td10"),
+        Report::TD15(report) => describe_td(&report.base, "td15"),
+    }
+}
+
+fn describe_td(report: &dcap_qvl::quote::TDReport10, kind: &str) -> Value {
+    json!({
+        "type": kind,
+        "mr_td": hex::encode(report.mr_td),
+        "rtmrs": [report.rt_mr0, report.rt_mr1, report.rt_mr2, report.rt_mr3]
+            .iter()
+            .map(hex::encode)
+            .collect::<Vec<_>>(),
+        "td_attributes": hex::encode(report.td_attributes),
+        "report_data": hex::encode(report.report_data),
+    })
+}
+
+/// Runs the startup checks without serving, and reports each one.
+pub async fn self_test(config: &Config) -> Result<(), ProviderError> {
+    logging::init(&LoggingConfig {
+        format: LogFormat::Text,
+        ..LoggingConfig::default()
+    })?;
+
+    let mut failures = 0;
+    let mut report = |name: &str, result: Result<(), ProviderError>| match result {
+        Ok(()) => println!("ok    {}", name),
+        Err(e) => {
+            println!("FAIL  {}: {}", name, e.chain());
+            failures += 1;
+        }
+    };
+
+    report(
+        "crypto backend",
+        crypto::init_backend(&config.crypto.backend),
+    );
+    report("hardware entropy", crypto::check_hardware_entropy());
+    report("key release pipeline", key_release_pipeline(config));
+
+    let platform = crate::init_platform(config);
+    let attested = platform.is_ok() && gramine::is_attested();
+    report("platform", platform);
+    if attested {
+        let quote_check = async {
+            let collateral = CollateralCache::from_config(&config.collateral)?;
+            let verifier =
+                verifier::by_name(&config.collateral.verifier, collateral, &config.veraison)?;
+            if diagnostics::check_platform(verifier.as_ref()).await {
+                Ok(())
+            } else {
+                Err(ProviderError::QuoteVerificationError(
+                    "see the finding and hint logged above".into(),
+                ))
+            }
+        };
+        report("platform quote", quote_check.await);
+    } else {
+        println!("skip  platform quote: no attestation");
+    }
+
+    match failures {
+        0 => Ok(()),
+        failures => Err(ProviderError::SelfTestFailed(failures)),
+    }
+}
+
+/// Runs a synthetic quote through the key release pipeline under a fixed
+/// root key and the configured policy.
+fn key_release_pipeline(config: &Config) -> Result<(), ProviderError> {
+    let policy = match &config.files.policy {
+        Some(path) => Policy::load(path)?,
+        None => Policy::default(),
+    };
+    let master = MasterSecret::from_sealing_key(&[0x42; 32], None)?;
+    quote::check_pipeline(&master, &policy).map(drop)
+}
+
+pub fn derive_testvector(args: &DeriveTestvectorArgs) -> Result<(), ProviderError> {
+    let decode = |name: &str, value: &str| {
+        hex::decode(value)
+            .map_err(|e| ProviderError::ConfigError(format!("--{} is not hex: {}", name, e)))
+    };
+    let sealing_key = decode("sealing-key", &args.sealing_key)?;
+    let measurements = decode("measurements", &args.measurements)?;
+    let partition = args
+        .partition
+        .as_deref()
+        .map(|partition| decode("partition", partition))
+        .transpose()?;
+    if sealing_key.len() < 16 {
+        return Err(ProviderError::ConfigError(
+            "--sealing-key must be at least 16 bytes".into(),
+        ));
+    }
+    if measurements.len() != MEASUREMENTS_LEN {
+        return Err(ProviderError::ConfigError(format!(
+            "--measurements must be {} bytes (MRTD and RTMR0-3)",
+            MEASUREMENTS_LEN
+        )));
+    }
+
+    let kdf = KdfVersion::try_from(args.kdf_version).map_err(ProviderError::ConfigError)?;
+    let master = MasterSecret::from_sealing_key(&sealing_key, partition.as_deref())?;
+    let derived = master.derive(kdf, &measurements)?;
+    let output = json!({
+        "derived_key": hex::encode(derived.expose()),
+        "key_id": hex::encode(compute_key_id(&derived)),
+        "key_confirmation": hex::encode(compute_key_confirmation(&derived)),
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Checks an audit log offline, so an auditor does not have to trust the
+/// provider that wrote it.
+pub fn verify_audit_log(args: &VerifyAuditLogArgs) -> Result<(), ProviderError> {
+    let identity_key = args
+        .identity_key
+        .as_deref()
+        .map(hex::decode)
+        .transpose()
+        .map_err(|e| ProviderError::ConfigError(format!("--identity-key is not hex: {}", e)))?;
+    let data = fs::read_to_string(&args.path)?;
+    let verified = audit::verify_export(&data, identity_key.as_deref()).map_err(|e| {
+        ProviderError::CryptoError(format!(
+            "Audit log {} failed verification: {}",
+            args.path.display(),
+            e
+        ))
+    })?;
+    println!("{}", serde_json::to_string_pretty(&verified)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_invocation_serves_and_keeps_insecure_flag() {
+        let cli = Cli::try_parse_from(["provider", "--insecure-i-know"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.insecure_i_know);
+
+        let cli =
+            Cli::try_parse_from(["provider", "inspect-quote", "quote.bin", "--verify"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::InspectQuote(ref args)) if args.verify));
+        assert!(Cli::try_parse_from(["provider", "derive-testvector"]).is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::config::CollateralConfig;
+use crate::error::ProviderError;
+use dcap_qvl::collateral::{get_collateral, get_collateral_from_pcs};
+use dcap_qvl::{quote::Quote, QuoteCollateralV3};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// TCB info and CRLs change rarely; an hourly refresh still picks up
+// revocations without a PCS round trip per request
+const DEFAULT_REFRESH_SECS: u64 = 60 * 60;
+/// Platforms kept before the least recently used one is dropped.
+const MAX_ENTRIES: usize = 1024;
+
+/// What the admin API shows of a cache entry; the collateral itself is
+/// public but large.
+#[derive(Debug, Serialize)]
+pub struct CollateralEntry {
+    pub platform: String,
+    pub fetched_at: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedCollateral {
+    fetched_at: u64,
+    collateral: QuoteCollateralV3,
+    /// When a request last used the entry; entries loaded from disk start
+    /// out unused.
+    #[serde(skip)]
+    used_at: u64,
+}
+
+/// Collateral fetched from PCS, keyed by the quoting platform. At most
+/// `MAX_ENTRIES` platforms are kept; the least recently used goes first.
+///
+/// When a directory is configured, entries are also written there so a
+/// restarted provider can serve requests before PCS is reachable again. The
+/// directory is meant to be a Gramine encrypted mount, which keeps the files
+/// confidential and makes tampering show up as a read error; stale or expired
+/// collateral is still rejected by quote verification itself.
+pub struct CollateralCache {
+    dir: Option<PathBuf>,
+    pccs_url: Option<String>,
+    refresh_secs: u64,
+    entries: Mutex<HashMap<String, CachedCollateral>>,
+}
+
+impl CollateralCache {
+    pub fn in_memory() -> Self {
+        Self {
+            dir: None,
+            pccs_url: None,
+            refresh_secs: DEFAULT_REFRESH_SECS,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Loads every readable entry from `dir`, creating it if needed.
+    pub fn persistent(dir: &Path) -> Result<Self, ProviderError> {
+        fs::create_dir_all(dir)?;
+
+        let mut entries = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            match fs::read(&path)
+                .map_err(ProviderError::from)
+                .and_then(|data| Ok(serde_json::from_slice::<CachedCollateral>(&data)?))
+            {
+                Ok(cached) => {
+                    entries.insert(key.to_string(), cached);
+                }
+                Err(e) => warn!(
+                    "Ignoring cached collateral {}: {}",
+                    path.display(),
+                    e.chain()
+                ),
+            }
+        }
+        info!(
+            "Loaded {} cached collateral entries from {}",
+            entries.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            dir: Some(dir.to_path_buf()),
+            pccs_url: None,
+            refresh_secs: DEFAULT_REFRESH_SECS,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// The cache described by the `[collateral]` settings. Point `cache_dir`
+    /// at a Gramine encrypted mount to survive restarts.
+    pub fn from_config(config: &CollateralConfig) -> Result<Self, ProviderError> {
+        let cache = match &config.cache_dir {
+            Some(dir) => Self::persistent(dir)?,
+            None => Self::in_memory(),
+        };
+        Ok(cache.with_source(config.pccs_url.clone(), config.refresh_secs))
+    }
+
+    /// Fetches from `pccs_url` instead of Intel PCS when set, and refetches
+    /// entries older than `refresh_secs`.
+    pub fn with_source(mut self, pccs_url: Option<String>, refresh_secs: u64) -> Self {
+        self.pccs_url = pccs_url;
+        self.refresh_secs = refresh_secs;
+        self
+    }
+
+    /// Collateral for `quote`: cached if recently fetched, otherwise fresh
+    /// from PCS (or the PCCS), falling back to the cached copy if it is
+    /// unreachable.
+    pub async fn get(&self, quote: &[u8]) -> Result<QuoteCollateralV3, ProviderError> {
+        let key = platform_key(quote)?;
+        let now = unix_now();
+        let cached = self.lock().get_mut(&key).map(|cached| {
+            cached.used_at = now;
+            cached.clone()
+        });
+
+        if let Some(cached) = &cached {
+            if now.saturating_sub(cached.fetched_at) < self.refresh_secs {
+                debug!("Using cached collateral for platform {}", key);
+                return Ok(cached.collateral.clone());
+            }
+        }
+
+        let fetched = match &self.pccs_url {
+            Some(url) => get_collateral(url, quote).await,
+            None => get_collateral_from_pcs(quote).await,
+        };
+        match fetched {
+            Ok(collateral) => {
+                self.store(
+                    &key,
+                    CachedCollateral {
+                        fetched_at: now,
+                        collateral: collateral.clone(),
+                        used_at: now,
+                    },
+                );
+                Ok(collateral)
+            }
+            Err(e) => match cached {
+                Some(cached) => {
+                    warn!(
+                        "Collateral fetch failed ({}); using collateral cached for platform {}",
+                        e, key
+                    );
+                    Ok(cached.collateral)
+                }
+                None => Err(ProviderError::CollateralError {
+                    endpoint: self.pccs_url.as_deref().unwrap_or("Intel PCS").to_string(),
+                    source: e.into(),
+                }),
+            },
+        }
+    }
+
+    /// Collateral already cached for `quote`, however old, without a fetch.
+    /// Quote verification still rejects it once it has expired.
+    pub fn cached(&self, quote: &[u8]) -> Result<Option<QuoteCollateralV3>, ProviderError> {
+        let key = platform_key(quote)?;
+        Ok(self.lock().get_mut(&key).map(|cached| {
+            cached.used_at = unix_now();
+            cached.collateral.clone()
+        }))
+    }
+
+    pub fn entries(&self) -> Vec<CollateralEntry> {
+        let mut entries: Vec<_> = self
+            .lock()
+            .iter()
+            .map(|(platform, cached)| CollateralEntry {
+                platform: platform.clone(),
+                fetched_at: cached.fetched_at,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.platform.cmp(&b.platform));
+        entries
+    }
+
+    /// Drops every entry, including the persisted copies, so the next
+    /// request for each platform fetches fresh collateral. Returns how many
+    /// entries were dropped.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.lock();
+        for key in entries.keys() {
+            self.remove_file(key);
+        }
+        let flushed = entries.len();
+        entries.clear();
+        info!("Flushed {} collateral entries", flushed);
+        flushed
+    }
+
+    fn store(&self, key: &str, cached: CachedCollateral) {
+        let mut entries = self.lock();
+        while entries.len() >= MAX_ENTRIES && !entries.contains_key(key) {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.used_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+            self.remove_file(&oldest);
+            debug!("Evicted collateral for platform {}", oldest);
+        }
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", key));
+            if let Err(e) = serde_json::to_vec(&cached)
+                .map_err(ProviderError::from)
+                .and_then(|data| Ok(fs::write(&path, data)?))
+            {
+                warn!(
+                    "Failed to persist collateral to {}: {}",
+                    path.display(),
+                    e.chain()
+                );
+            }
+        }
+        entries.insert(key.to_string(), cached);
+    }
+
+    fn remove_file(&self, key: &str) {
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.json", key));
+            if let Err(e) = fs::remove_file(&path) {
+                warn!(
+                    "Failed to remove cached collateral {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedCollateral>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Collateral depends only on the TEE type and the platform's FMSPC and PCK
+/// CA, which the PCCS also looks it up by, so those make the key. They come
+/// from the PCK certificate in the quote; the header's user data is not
+/// covered by anything and could name any platform.
+fn platform_key(quote: &[u8]) -> Result<String, ProviderError> {
+    let quote = Quote::parse(quote).map_err(|e| ProviderError::quote_decode("quote", e))?;
+    let fmspc = quote
+        .fmspc()
+        .map_err(|e| ProviderError::quote_decode("PCK certificate FMSPC", e))?;
+    let ca = quote
+        .ca()
+        .map_err(|e| ProviderError::quote_decode("PCK certificate issuer", e))?;
+    Ok(format!(
+        "{:08x}-{}-{}",
+        quote.header.tee_type,
+        ca,
+        hex::encode(fmspc)
+    ))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistent_cache_skips_unreadable_entries() {
+        let dir = std::env::temp_dir().join(format!("collateral-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("corrupt.json"), b"not json").unwrap();
+
+        let cache = CollateralCache::persistent(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(cache.lock().is_empty());
+    }
+
+    #[test]
+    fn platforms_are_keyed_by_their_pck_certificate() {
+        let quote = include_bytes!("../quotes/tdxQuote.txt");
+        let key = platform_key(quote).unwrap();
+        assert!(key.starts_with("00000081-"));
+
+        // The header's user data is not signed by anything
+        let mut forged = quote.to_vec();
+        forged[28..48].fill(0xff);
+        assert_eq!(platform_key(&forged).unwrap(), key);
+    }
+}
@@ -0,0 +1,1168 @@
+use crate::error::ProviderError;
+use crate::gramine::{AttestationPaths, DEFAULT_ATTESTATION_DIR, DEFAULT_TSM_REPORT_DIR};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Names the TOML configuration file, like `--config`. Without either, every
+/// setting starts from its default.
+pub const CONFIG_ENV: &str = "SEALING_PROVIDER_CONFIG";
+
+/// Provider settings. Loaded from the TOML file given by `--config` or
+/// `SEALING_PROVIDER_CONFIG`, then overridden by the environment variables
+/// listed in [`Config::apply_env`]. Secrets (the sealing key source and the
+/// PKCS#11 PIN) are not part of it; they stay in `secrets`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub platform: PlatformConfig,
+    pub collateral: CollateralConfig,
+    pub crypto: CryptoConfig,
+    pub files: FilesConfig,
+    pub remote_policy: RemotePolicyConfig,
+    pub logging: LoggingConfig,
+    pub audit_export: AuditExportConfig,
+    pub webhooks: WebhookConfig,
+    pub sev_snp: SevSnpConfig,
+    pub azure_tdx: AzureTdxConfig,
+    pub gcp: GcpConfig,
+    pub ita: ItaConfig,
+    pub maa: MaaConfig,
+    pub tpm: TpmConfig,
+    pub cca: CcaConfig,
+    pub veraison: VeraisonConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: String,
+    /// RA-TLS listener, served in addition to `addr`.
+    pub ratls_addr: Option<String>,
+    pub session_idle_timeout_secs: u64,
+    /// Plain HTTP listener for the Prometheus metrics, off by default.
+    pub metrics_addr: Option<String>,
+    /// Admin API listener, off by default. Needs an admin token.
+    pub admin_addr: Option<String>,
+    /// Key Broker Service protocol listener for confidential containers
+    /// guests, off by default.
+    pub kbs_addr: Option<String>,
+    /// Vault API subset listener for TDs logging in with a quote, off by
+    /// default.
+    pub vault_addr: Option<String>,
+    /// Answer no request sooner than this; 0 is off.
+    pub min_response_ms: u64,
+    /// Pad response bodies to fixed size buckets.
+    pub pad_response_sizes: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:3443".to_string(),
+            ratls_addr: None,
+            session_idle_timeout_secs: 300,
+            metrics_addr: None,
+            admin_addr: None,
+            kbs_addr: None,
+            vault_addr: None,
+            min_response_ms: 0,
+            pad_response_sizes: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Enclave,
+    Td,
+    Standalone,
+}
+
+impl FromStr for Mode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value {
+            "enclave" => Ok(Self::Enclave),
+            "td" => Ok(Self::Td),
+            "standalone" => Ok(Self::Standalone),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlatformConfig {
+    pub mode: Mode,
+    pub tsm_report_dir: PathBuf,
+    /// Host service that turns local reports into quotes.
+    pub quote_service: Option<String>,
+    pub quote_refresh_secs: u64,
+    /// Runs without attestation (with the insecure flag) using this key file.
+    pub insecure_simulated_sealing_key: Option<PathBuf>,
+    pub kss_derivation: bool,
+    /// Encrypted mounts and trusted files: a `file:` root key elsewhere is
+    /// an insecure configuration.
+    pub protected_paths: Vec<PathBuf>,
+    /// Gramine's attestation pseudo-files, for newer Gramine layouts or test
+    /// harnesses. The single files default to their place under the
+    /// directory.
+    pub attestation_dir: PathBuf,
+    pub attestation_quote: Option<PathBuf>,
+    pub attestation_user_report_data: Option<PathBuf>,
+    pub attestation_mrenclave_key: Option<PathBuf>,
+    pub attestation_mrsigner_key: Option<PathBuf>,
+}
+
+impl Default for PlatformConfig {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            tsm_report_dir: PathBuf::from(DEFAULT_TSM_REPORT_DIR),
+            quote_service: None,
+            quote_refresh_secs: 10 * 60,
+            insecure_simulated_sealing_key: None,
+            kss_derivation: false,
+            protected_paths: Vec::new(),
+            attestation_dir: PathBuf::from(DEFAULT_ATTESTATION_DIR),
+            attestation_quote: None,
+            attestation_user_report_data: None,
+            attestation_mrenclave_key: None,
+            attestation_mrsigner_key: None,
+        }
+    }
+}
+
+impl PlatformConfig {
+    /// Where the enclave finds Gramine's attestation files.
+    pub fn attestation_paths(&self) -> AttestationPaths {
+        let mut paths = AttestationPaths::under(&self.attestation_dir);
+        let overrides = [
+            (&self.attestation_quote, &mut paths.quote),
+            (
+                &self.attestation_user_report_data,
+                &mut paths.user_report_data,
+            ),
+            (&self.attestation_mrenclave_key, &mut paths.mrenclave_key),
+            (&self.attestation_mrsigner_key, &mut paths.mrsigner_key),
+        ];
+        for (setting, path) in overrides {
+            if let Some(setting) = setting {
+                *path = setting.clone();
+            }
+        }
+        paths
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollateralConfig {
+    /// PCCS to fetch collateral from instead of Intel PCS.
+    pub pccs_url: Option<String>,
+    pub refresh_secs: u64,
+    /// Persistent cache, meant to be a Gramine encrypted mount.
+    pub cache_dir: Option<PathBuf>,
+    pub verifier: String,
+}
+
+impl Default for CollateralConfig {
+    fn default() -> Self {
+        Self {
+            pccs_url: None,
+            refresh_secs: 60 * 60,
+            cache_dir: None,
+            verifier: "dcap".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CryptoConfig {
+    pub backend: String,
+    pub pkcs11_module: Option<PathBuf>,
+    pub pkcs11_token: Option<String>,
+    pub pkcs11_key_label: Option<String>,
+}
+
+impl Default for CryptoConfig {
+    fn default() -> Self {
+        Self {
+            backend: "native".to_string(),
+            pkcs11_module: None,
+            pkcs11_token: None,
+            pkcs11_key_label: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    pub policy: Option<PathBuf>,
+    pub counters: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub svn_record_dir: Option<PathBuf>,
+    /// Sealed record of session states, so clients can learn that a restart
+    /// ended their session.
+    pub session_journal: Option<PathBuf>,
+    /// Launch measurements, key IDs and public keys refused whatever the
+    /// policy says. Read again whenever it changes.
+    pub revocation_list: Option<PathBuf>,
+}
+
+/// A policy fetched as a signed bundle instead of read from `files.policy`,
+/// so a fleet of providers can share one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemotePolicyConfig {
+    /// Serves the bundle; off when unset.
+    pub url: Option<String>,
+    /// The operator's public key (PEM, P-256) bundles must be signed with.
+    pub signing_key: Option<PathBuf>,
+    /// How often the bundle is fetched again; 0 only fetches it at startup
+    /// and on SIGHUP.
+    pub refresh_secs: u64,
+    /// Bundles with a lower version are refused, even at startup.
+    pub min_version: u64,
+}
+
+impl Default for RemotePolicyConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            signing_key: None,
+            refresh_secs: 300,
+            min_version: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value {
+            "json" => Ok(Self::Json),
+            "text" => Ok(Self::Text),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    #[default]
+    Stdout,
+    Stderr,
+    /// A size-rotated file, e.g. on a mount listed in `sgx.allowed_files`.
+    File,
+    Syslog,
+}
+
+impl FromStr for LogSink {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value {
+            "stdout" => Ok(Self::Stdout),
+            "stderr" => Ok(Self::Stderr),
+            "file" => Ok(Self::File),
+            "syslog" => Ok(Self::Syslog),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub sink: LogSink,
+    /// Log file for the `file` sink; rotated to `<file>.1` .. `<file>.<max_files>`.
+    pub file: Option<PathBuf>,
+    pub max_file_bytes: u64,
+    pub max_files: u32,
+    /// `udp:<host>:<port>` or `unix:<path>` for the `syslog` sink.
+    pub syslog: Option<String>,
+    /// Key requests taking longer are logged with their phase timings; 0
+    /// turns the warning off.
+    pub slow_request_ms: u64,
+    /// Log one in this many client connections in full; the others only log
+    /// warnings and errors.
+    pub sample_requests: u32,
+    /// The same for admin connections.
+    pub sample_admin: u32,
+    /// Informational lines written per second at most; 0 is no limit.
+    pub max_lines_per_sec: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            sink: LogSink::default(),
+            file: None,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+            syslog: None,
+            slow_request_ms: 1000,
+            sample_requests: 1,
+            sample_admin: 1,
+            max_lines_per_sec: 0,
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn slow_request_threshold(&self) -> Option<Duration> {
+        match self.slow_request_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One JSON object per line.
+    #[default]
+    Ndjson,
+    /// A CBOR sequence (RFC 8742), one map per event.
+    Cbor,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, ()> {
+        match value {
+            "ndjson" => Ok(Self::Ndjson),
+            "cbor" => Ok(Self::Cbor),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditExportConfig {
+    /// `file:<path>`, `tcp:<host>:<port>` or `unix:<path>`; off when unset.
+    pub target: Option<String>,
+    pub format: ExportFormat,
+    /// Receives each audit log checkpoint as a JSON POST, and answers with
+    /// a receipt the log records; off when unset.
+    pub anchor_url: Option<String>,
+}
+
+/// Security-relevant events a webhook can be sent for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A key request was refused by the measurement policy.
+    PolicyViolation,
+    /// `ppid_mismatch_threshold` requests from another platform arrived
+    /// within `ppid_mismatch_window_secs`.
+    PpidMismatches,
+    /// The boot epoch advanced, on startup or through the admin API.
+    EpochRotated,
+    /// A `dev-mode` build started.
+    DevModeStartup,
+    /// An identity got its key `alert_after` times for one context.
+    ReleaseThreshold,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Receives a JSON POST per event; off when unset.
+    pub url: Option<String>,
+    pub events: Vec<WebhookEvent>,
+    pub ppid_mismatch_threshold: u32,
+    pub ppid_mismatch_window_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            events: vec![
+                WebhookEvent::PolicyViolation,
+                WebhookEvent::PpidMismatches,
+                WebhookEvent::EpochRotated,
+                WebhookEvent::DevModeStartup,
+                WebhookEvent::ReleaseThreshold,
+            ],
+            ppid_mismatch_threshold: 5,
+            ppid_mismatch_window_secs: 300,
+        }
+    }
+}
+
+/// AMD SEV-SNP guests, served next to TDX guests when enabled. Which of
+/// them get keys is up to the `sev_snp` section of the policy.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SevSnpConfig {
+    pub enabled: bool,
+    /// Processor family of the hosts, as the KDS names it: `Milan` or `Genoa`.
+    pub product: String,
+    /// The ASK and ARK certificates (PEM) AMD publishes for `product`.
+    pub cert_chain: Option<PathBuf>,
+    /// SHA-256 of the ARK's DER encoding, in hex. The chain file is only
+    /// trusted if its root is this one.
+    pub ark_sha256: Option<String>,
+    pub kds_url: String,
+}
+
+impl Default for SevSnpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            product: "Milan".to_string(),
+            cert_chain: None,
+            ark_sha256: None,
+            kds_url: "https://kdsintf.amd.com".to_string(),
+        }
+    }
+}
+
+/// TDX guests on Azure confidential VMs, which attest through the paravisor
+/// and IMDS rather than with a quote of their own.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AzureTdxConfig {
+    pub enabled: bool,
+    /// Refuse evidence without a vTPM quote, whose PCRs are all that tells
+    /// guests on the same paravisor apart.
+    pub require_vtpm: bool,
+}
+
+impl Default for AzureTdxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            require_vtpm: true,
+        }
+    }
+}
+
+/// GCP Confidential Space attestation tokens, accepted in place of quotes
+/// when enabled. Which workloads get keys is up to the `gcp` section of the
+/// policy.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcpConfig {
+    pub enabled: bool,
+    /// The audience workloads request their tokens for.
+    pub audience: String,
+    pub issuer: String,
+    /// Where the issuer publishes its signing keys.
+    pub jwks_url: String,
+}
+
+impl Default for GcpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            audience: "gramine-sealing-key-provider".to_string(),
+            issuer: "https://confidentialcomputing.googleapis.com".to_string(),
+            jwks_url: "https://www.googleapis.com/service_accounts/v1/metadata/jwk/\
+                       signer@confidentialspace-sign.iam.gserviceaccount.com"
+                .to_string(),
+        }
+    }
+}
+
+/// Intel Trust Authority attestation tokens, accepted in place of quotes
+/// when enabled. Which TDs get keys is up to the `ita` section of the policy.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ItaConfig {
+    pub enabled: bool,
+    pub issuer: String,
+    /// Where Trust Authority publishes its signing keys.
+    pub jwks_url: String,
+}
+
+impl Default for ItaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: "Intel Trust Authority".to_string(),
+            jwks_url: "https://portal.trustauthority.intel.com/certs".to_string(),
+        }
+    }
+}
+
+/// Microsoft Azure Attestation tokens for TDX confidential VMs, accepted in
+/// place of quotes when enabled. Which TDs get keys is up to the `maa`
+/// section of the policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaaConfig {
+    pub enabled: bool,
+    /// The attestation instance whose tokens are trusted, such as
+    /// `https://myprovider.eus.attest.azure.net`. It is the tokens' issuer
+    /// and publishes its signing keys at `/certs`.
+    pub instance: Option<String>,
+}
+
+/// The Veraison service quotes are sent to when `collateral.verifier` is
+/// `veraison`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VeraisonConfig {
+    /// Base URL of the verification service, such as
+    /// `https://veraison.example:8443`.
+    pub url: Option<String>,
+    /// The public key (PEM, P-256 or P-384) the service signs EARs with.
+    pub ear_key: Option<PathBuf>,
+    /// Media types the service's TDX and SGX schemes take quotes as.
+    pub tdx_media_type: String,
+    pub sgx_media_type: String,
+    /// Accept quotes the service appraises as `warning`, not only
+    /// `affirming`.
+    pub accept_warning: bool,
+}
+
+impl Default for VeraisonConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            ear_key: None,
+            tdx_media_type: "application/vnd.intel.tdx-quote".to_string(),
+            sgx_media_type: "application/vnd.intel.sgx-quote".to_string(),
+            accept_warning: false,
+        }
+    }
+}
+
+/// VMs without a TEE, attesting with a quote from their TPM. Even when
+/// enabled, none of them get keys unless the `tpm` section of the policy
+/// allows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TpmConfig {
+    pub enabled: bool,
+    /// The CA certificates (PEM) that issue the clients' AK certificates.
+    pub ak_ca_certs: Option<PathBuf>,
+}
+
+/// ARM CCA realms, served when enabled in a build with the `cca` feature.
+/// Which of them get keys is up to the `cca` section of the policy.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CcaConfig {
+    pub enabled: bool,
+    /// The attestation public keys (PEM, P-384) of the platforms realms may
+    /// run on.
+    pub cpaks: Option<PathBuf>,
+}
+
+impl Config {
+    /// Reads `path`, if given, applies environment overrides and validates
+    /// the result.
+    pub fn load(path: Option<&Path>) -> Result<Self, ProviderError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ProviderError> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            ProviderError::ConfigError(format!("Cannot read config {}: {}", path.display(), e))
+        })?;
+        Self::parse(&data).map_err(|e| {
+            ProviderError::ConfigError(format!("Invalid config {}: {}", path.display(), e))
+        })
+    }
+
+    fn parse(data: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(data)
+    }
+
+    /// The environment variables the provider has always read take
+    /// precedence over the file, so existing manifests keep working.
+    fn apply_env(&mut self) -> Result<(), ProviderError> {
+        self.apply_overrides(&Overrides(|name: &str| env::var(name).ok()))
+    }
+
+    fn apply_overrides<F>(&mut self, vars: &Overrides<F>) -> Result<(), ProviderError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let server = &mut self.server;
+        vars.set("SEALING_PROVIDER_ADDR", &mut server.addr)?;
+        vars.set_opt("SEALING_PROVIDER_RATLS_ADDR", &mut server.ratls_addr)?;
+        vars.set(
+            "SEALING_PROVIDER_SESSION_IDLE_TIMEOUT_SECS",
+            &mut server.session_idle_timeout_secs,
+        )?;
+        vars.set_opt("SEALING_PROVIDER_METRICS_ADDR", &mut server.metrics_addr)?;
+        vars.set_opt("SEALING_PROVIDER_ADMIN_ADDR", &mut server.admin_addr)?;
+        vars.set_opt("SEALING_PROVIDER_KBS_ADDR", &mut server.kbs_addr)?;
+        vars.set_opt("SEALING_PROVIDER_VAULT_ADDR", &mut server.vault_addr)?;
+        vars.set(
+            "SEALING_PROVIDER_MIN_RESPONSE_MS",
+            &mut server.min_response_ms,
+        )?;
+        vars.set_flag(
+            "SEALING_PROVIDER_PAD_RESPONSE_SIZES",
+            &mut server.pad_response_sizes,
+        )?;
+
+        let platform = &mut self.platform;
+        vars.set("SEALING_PROVIDER_MODE", &mut platform.mode)?;
+        vars.set(
+            "SEALING_PROVIDER_TSM_REPORT_DIR",
+            &mut platform.tsm_report_dir,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_QUOTE_SERVICE",
+            &mut platform.quote_service,
+        )?;
+        vars.set(
+            "SEALING_PROVIDER_QUOTE_REFRESH_SECS",
+            &mut platform.quote_refresh_secs,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_INSECURE_SIMULATED_SEALING_KEY",
+            &mut platform.insecure_simulated_sealing_key,
+        )?;
+        vars.set_flag(
+            "SEALING_PROVIDER_KSS_DERIVATION",
+            &mut platform.kss_derivation,
+        )?;
+        vars.set(
+            "SEALING_PROVIDER_ATTESTATION_DIR",
+            &mut platform.attestation_dir,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_ATTESTATION_QUOTE",
+            &mut platform.attestation_quote,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_ATTESTATION_USER_REPORT_DATA",
+            &mut platform.attestation_user_report_data,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_ATTESTATION_MRENCLAVE_KEY",
+            &mut platform.attestation_mrenclave_key,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_ATTESTATION_MRSIGNER_KEY",
+            &mut platform.attestation_mrsigner_key,
+        )?;
+
+        let collateral = &mut self.collateral;
+        vars.set_opt("SEALING_PROVIDER_PCCS_URL", &mut collateral.pccs_url)?;
+        vars.set(
+            "SEALING_PROVIDER_COLLATERAL_REFRESH_SECS",
+            &mut collateral.refresh_secs,
+        )?;
+        vars.set_opt("SEALING_PROVIDER_COLLATERAL_DIR", &mut collateral.cache_dir)?;
+        vars.set("SEALING_PROVIDER_VERIFIER", &mut collateral.verifier)?;
+
+        let crypto = &mut self.crypto;
+        vars.set("SEALING_PROVIDER_CRYPTO_BACKEND", &mut crypto.backend)?;
+        vars.set_opt("SEALING_PROVIDER_PKCS11_MODULE", &mut crypto.pkcs11_module)?;
+        vars.set_opt("SEALING_PROVIDER_PKCS11_TOKEN", &mut crypto.pkcs11_token)?;
+        vars.set_opt(
+            "SEALING_PROVIDER_PKCS11_KEY_LABEL",
+            &mut crypto.pkcs11_key_label,
+        )?;
+
+        let files = &mut self.files;
+        vars.set_opt("SEALING_PROVIDER_POLICY", &mut files.policy)?;
+        vars.set_opt("SEALING_PROVIDER_COUNTER_FILE", &mut files.counters)?;
+        vars.set_opt("SEALING_PROVIDER_AUDIT_LOG", &mut files.audit_log)?;
+        vars.set_opt("SEALING_PROVIDER_SVN_RECORD_DIR", &mut files.svn_record_dir)?;
+        vars.set_opt(
+            "SEALING_PROVIDER_SESSION_JOURNAL",
+            &mut files.session_journal,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_REVOCATION_LIST",
+            &mut files.revocation_list,
+        )?;
+
+        let logging = &mut self.logging;
+        vars.set("SEALING_PROVIDER_LOG_FORMAT", &mut logging.format)?;
+        vars.set("SEALING_PROVIDER_LOG_SINK", &mut logging.sink)?;
+        vars.set_opt("SEALING_PROVIDER_LOG_FILE", &mut logging.file)?;
+        vars.set_opt("SEALING_PROVIDER_LOG_SYSLOG", &mut logging.syslog)?;
+        vars.set(
+            "SEALING_PROVIDER_SLOW_REQUEST_MS",
+            &mut logging.slow_request_ms,
+        )?;
+        vars.set(
+            "SEALING_PROVIDER_LOG_SAMPLE_REQUESTS",
+            &mut logging.sample_requests,
+        )?;
+        vars.set(
+            "SEALING_PROVIDER_LOG_MAX_LINES_PER_SEC",
+            &mut logging.max_lines_per_sec,
+        )?;
+
+        let audit_export = &mut self.audit_export;
+        vars.set_opt("SEALING_PROVIDER_AUDIT_EXPORT", &mut audit_export.target)?;
+        vars.set(
+            "SEALING_PROVIDER_AUDIT_EXPORT_FORMAT",
+            &mut audit_export.format,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_AUDIT_ANCHOR_URL",
+            &mut audit_export.anchor_url,
+        )?;
+
+        let webhooks = &mut self.webhooks;
+        vars.set_opt("SEALING_PROVIDER_WEBHOOK_URL", &mut webhooks.url)?;
+        vars.set(
+            "SEALING_PROVIDER_WEBHOOK_PPID_MISMATCH_THRESHOLD",
+            &mut webhooks.ppid_mismatch_threshold,
+        )?;
+        vars.set(
+            "SEALING_PROVIDER_WEBHOOK_PPID_MISMATCH_WINDOW_SECS",
+            &mut webhooks.ppid_mismatch_window_secs,
+        )?;
+
+        let sev_snp = &mut self.sev_snp;
+        vars.set_flag("SEALING_PROVIDER_SEV_SNP", &mut sev_snp.enabled)?;
+        vars.set("SEALING_PROVIDER_SEV_SNP_PRODUCT", &mut sev_snp.product)?;
+        vars.set_opt(
+            "SEALING_PROVIDER_SEV_SNP_CERT_CHAIN",
+            &mut sev_snp.cert_chain,
+        )?;
+        vars.set_opt(
+            "SEALING_PROVIDER_SEV_SNP_ARK_SHA256",
+            &mut sev_snp.ark_sha256,
+        )?;
+        vars.set("SEALING_PROVIDER_SEV_SNP_KDS_URL", &mut sev_snp.kds_url)?;
+
+        let azure_tdx = &mut self.azure_tdx;
+        vars.set_flag("SEALING_PROVIDER_AZURE_TDX", &mut azure_tdx.enabled)?;
+        vars.set_flag(
+            "SEALING_PROVIDER_AZURE_TDX_REQUIRE_VTPM",
+            &mut azure_tdx.require_vtpm,
+        )?;
+
+        let gcp = &mut self.gcp;
+        vars.set_flag("SEALING_PROVIDER_GCP", &mut gcp.enabled)?;
+        vars.set("SEALING_PROVIDER_GCP_AUDIENCE", &mut gcp.audience)?;
+        vars.set("SEALING_PROVIDER_GCP_ISSUER", &mut gcp.issuer)?;
+        vars.set("SEALING_PROVIDER_GCP_JWKS_URL", &mut gcp.jwks_url)?;
+
+        let ita = &mut self.ita;
+        vars.set_flag("SEALING_PROVIDER_ITA", &mut ita.enabled)?;
+        vars.set("SEALING_PROVIDER_ITA_ISSUER", &mut ita.issuer)?;
+        vars.set("SEALING_PROVIDER_ITA_JWKS_URL", &mut ita.jwks_url)?;
+
+        let maa = &mut self.maa;
+        vars.set_flag("SEALING_PROVIDER_MAA", &mut maa.enabled)?;
+        vars.set_opt("SEALING_PROVIDER_MAA_INSTANCE", &mut maa.instance)?;
+
+        let tpm = &mut self.tpm;
+        vars.set_flag("SEALING_PROVIDER_TPM", &mut tpm.enabled)?;
+        vars.set_opt("SEALING_PROVIDER_TPM_AK_CA_CERTS", &mut tpm.ak_ca_certs)?;
+
+        let cca = &mut self.cca;
+        vars.set_flag("SEALING_PROVIDER_CCA", &mut cca.enabled)?;
+        vars.set_opt("SEALING_PROVIDER_CCA_CPAKS", &mut cca.cpaks)?;
+
+        let veraison = &mut self.veraison;
+        vars.set_opt("SEALING_PROVIDER_VERAISON_URL", &mut veraison.url)?;
+        vars.set_opt("SEALING_PROVIDER_VERAISON_EAR_KEY", &mut veraison.ear_key)?;
+
+        let remote_policy = &mut self.remote_policy;
+        vars.set_opt("SEALING_PROVIDER_POLICY_URL", &mut remote_policy.url)?;
+        vars.set_opt(
+            "SEALING_PROVIDER_POLICY_SIGNING_KEY",
+            &mut remote_policy.signing_key,
+        )
+    }
+
+    /// Catches settings that would otherwise only fail on first use.
+    pub fn validate(&self) -> Result<(), ProviderError> {
+        let invalid = |msg: &str| Err(ProviderError::ConfigError(msg.to_string()));
+
+        if self.server.addr.is_empty() {
+            return invalid("server.addr must not be empty");
+        }
+        let listeners: Vec<&String> = [
+            Some(&self.server.addr),
+            self.server.ratls_addr.as_ref(),
+            self.server.metrics_addr.as_ref(),
+            self.server.admin_addr.as_ref(),
+            self.server.kbs_addr.as_ref(),
+            self.server.vault_addr.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if (1..listeners.len()).any(|i| listeners[..i].contains(&listeners[i])) {
+            return invalid(
+                "server.addr, ratls_addr, metrics_addr, admin_addr, kbs_addr and vault_addr must \
+                 all differ",
+            );
+        }
+        if self.server.session_idle_timeout_secs == 0 {
+            return invalid("server.session_idle_timeout_secs must be positive");
+        }
+        if self.platform.quote_refresh_secs == 0 {
+            return invalid("platform.quote_refresh_secs must be positive");
+        }
+        if self.platform.mode != Mode::Enclave
+            && (self.platform.quote_service.is_some()
+                || self.platform.insecure_simulated_sealing_key.is_some())
+        {
+            return invalid(
+                "platform.quote_service and platform.insecure_simulated_sealing_key only \
+                 apply in enclave mode",
+            );
+        }
+        let platform = &self.platform;
+        let attestation_files = [
+            &platform.attestation_quote,
+            &platform.attestation_user_report_data,
+            &platform.attestation_mrenclave_key,
+            &platform.attestation_mrsigner_key,
+        ];
+        if platform.attestation_dir.as_os_str().is_empty()
+            || attestation_files
+                .iter()
+                .any(|path| path.as_deref() == Some(Path::new("")))
+        {
+            return invalid("platform.attestation_dir and attestation_* must not be empty");
+        }
+        if platform.mode != Mode::Enclave
+            && (platform.attestation_dir != Path::new(DEFAULT_ATTESTATION_DIR)
+                || attestation_files.iter().any(|path| path.is_some()))
+        {
+            return invalid(
+                "platform.attestation_dir and attestation_* only apply in enclave mode",
+            );
+        }
+        if !self
+            .platform
+            .protected_paths
+            .iter()
+            .all(|path| is_plain_absolute(path))
+        {
+            return invalid("platform.protected_paths must be absolute paths without ..");
+        }
+        if self.collateral.refresh_secs == 0 {
+            return invalid("collateral.refresh_secs must be positive");
+        }
+        if let Some(url) = &self.collateral.pccs_url {
+            if !url.starts_with("https://") {
+                return invalid("collateral.pccs_url must be an https:// URL");
+            }
+        }
+        if self.crypto.pkcs11_module.is_some()
+            && (self.crypto.pkcs11_token.is_none() || self.crypto.pkcs11_key_label.is_none())
+        {
+            return invalid(
+                "crypto.pkcs11_token and crypto.pkcs11_key_label are required with a PKCS#11 \
+                 module",
+            );
+        }
+        match self.logging.sink {
+            LogSink::File if self.logging.file.is_none() => {
+                return invalid("logging.file is required with the file sink")
+            }
+            LogSink::Syslog if self.logging.syslog.is_none() => {
+                return invalid("logging.syslog is required with the syslog sink")
+            }
+            _ => {}
+        }
+        if self.logging.max_file_bytes == 0 || self.logging.max_files == 0 {
+            return invalid("logging.max_file_bytes and logging.max_files must be positive");
+        }
+        if self.logging.sample_requests == 0 || self.logging.sample_admin == 0 {
+            return invalid("logging.sample_requests and logging.sample_admin must be positive");
+        }
+        if let Some(target) = &self.audit_export.target {
+            if !["file:", "tcp:", "unix:"]
+                .iter()
+                .any(|scheme| target.starts_with(scheme))
+            {
+                return invalid(
+                    "audit_export.target must be file:<path>, tcp:<host>:<port> or unix:<path>",
+                );
+            }
+        }
+        if let Some(url) = &self.remote_policy.url {
+            if !url.starts_with("https://") {
+                return invalid("remote_policy.url must be an https:// URL");
+            }
+            if self.remote_policy.signing_key.is_none() {
+                return invalid("remote_policy.signing_key is required with remote_policy.url");
+            }
+            if self.files.policy.is_some() {
+                return invalid("files.policy and remote_policy.url cannot both be set");
+            }
+        }
+        if let Some(url) = &self.audit_export.anchor_url {
+            if !url.starts_with("https://") {
+                return invalid("audit_export.anchor_url must be an https:// URL");
+            }
+            if self.files.audit_log.is_none() {
+                return invalid("audit_export.anchor_url needs files.audit_log");
+            }
+        }
+        if let Some(url) = &self.webhooks.url {
+            if !url.starts_with("https://") {
+                return invalid("webhooks.url must be an https:// URL");
+            }
+        }
+        if self.webhooks.ppid_mismatch_threshold == 0
+            || self.webhooks.ppid_mismatch_window_secs == 0
+        {
+            return invalid(
+                "webhooks.ppid_mismatch_threshold and ppid_mismatch_window_secs must be positive",
+            );
+        }
+        if self.sev_snp.enabled {
+            if self.sev_snp.cert_chain.is_none() {
+                return invalid("sev_snp.cert_chain is required when sev_snp is enabled");
+            }
+            match &self.sev_snp.ark_sha256 {
+                Some(digest)
+                    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) => {}
+                _ => return invalid("sev_snp.ark_sha256 must be the ARK's SHA-256 in hex"),
+            }
+            if !["Milan", "Genoa"].contains(&self.sev_snp.product.as_str()) {
+                return invalid("sev_snp.product must be Milan or Genoa");
+            }
+            if !self.sev_snp.kds_url.starts_with("https://") {
+                return invalid("sev_snp.kds_url must be an https:// URL");
+            }
+        }
+        if self.gcp.enabled {
+            if self.gcp.audience.is_empty() {
+                return invalid("gcp.audience must not be empty");
+            }
+            if !self.gcp.jwks_url.starts_with("https://") {
+                return invalid("gcp.jwks_url must be an https:// URL");
+            }
+        }
+        if self.ita.enabled && !self.ita.jwks_url.starts_with("https://") {
+            return invalid("ita.jwks_url must be an https:// URL");
+        }
+        if self.maa.enabled {
+            match &self.maa.instance {
+                Some(instance) if instance.starts_with("https://") => {}
+                _ => return invalid("maa.instance must be an https:// URL when maa is enabled"),
+            }
+        }
+        if self.tpm.enabled && self.tpm.ak_ca_certs.is_none() {
+            return invalid("tpm.ak_ca_certs is required when tpm is enabled");
+        }
+        if self.collateral.verifier == "veraison" {
+            match &self.veraison.url {
+                Some(url) if url.starts_with("https://") => {}
+                _ => return invalid("veraison.url must be an https:// URL"),
+            }
+            if self.veraison.ear_key.is_none() {
+                return invalid("veraison.ear_key is required with the veraison verifier");
+            }
+        }
+        if self.cca.enabled {
+            if !cfg!(feature = "cca") {
+                return invalid(
+                    "cca is enabled but the provider was built without the `cca` feature",
+                );
+            }
+            if self.cca.cpaks.is_none() {
+                return invalid("cca.cpaks is required when cca is enabled");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn session_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.session_idle_timeout_secs)
+    }
+
+    pub fn quote_refresh(&self) -> Duration {
+        Duration::from_secs(self.platform.quote_refresh_secs)
+    }
+}
+
+/// Where overrides come from: the process environment, or in tests a map
+/// that tests running in parallel do not share.
+struct Overrides<F>(F);
+
+impl<F: Fn(&str) -> Option<String>> Overrides<F> {
+    fn set<T: FromStr>(&self, name: &str, field: &mut T) -> Result<(), ProviderError> {
+        if let Some(value) = (self.0)(name) {
+            *field = parse(name, &value)?;
+        }
+        Ok(())
+    }
+
+    fn set_opt<T: FromStr>(&self, name: &str, field: &mut Option<T>) -> Result<(), ProviderError> {
+        if let Some(value) = (self.0)(name) {
+            *field = Some(parse(name, &value)?);
+        }
+        Ok(())
+    }
+
+    /// `1`/`true` or `0`/`false`; the provider has always accepted `=1`.
+    fn set_flag(&self, name: &str, field: &mut bool) -> Result<(), ProviderError> {
+        match (self.0)(name).as_deref() {
+            Some("1") | Some("true") => *field = true,
+            Some("0") | Some("false") => *field = false,
+            Some(other) => {
+                return Err(ProviderError::ConfigError(format!(
+                    "{} must be 1 or 0, got {:?}",
+                    name, other
+                )))
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is absolute and has no `..`, so comparing it by prefix
+/// means what it says.
+pub fn is_plain_absolute(path: &Path) -> bool {
+    path.is_absolute()
+        && path
+            .components()
+            .all(|component| component != Component::ParentDir)
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, ProviderError> {
+    value.parse().map_err(|_| {
+        ProviderError::ConfigError(format!("{} has an invalid value {:?}", name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn file_sets_sections_and_keeps_defaults() {
+        let config = Config::parse(
+            r#"
+            [server]
+            addr = "127.0.0.1:4000"
+
+            [collateral]
+            pccs_url = "https://pccs.example:8081/sgx/certification/v4/"
+
+            [files]
+            policy = "/policy.json"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.addr, "127.0.0.1:4000");
+        assert_eq!(config.server.session_idle_timeout_secs, 300);
+        assert_eq!(config.platform.mode, Mode::Enclave);
+        assert_eq!(config.files.policy, Some(PathBuf::from("/policy.json")));
+        assert!(config.validate().is_ok());
+
+        assert!(Config::parse("[server]\nport = 1").is_err());
+        let mut relative_protected = config.clone();
+        relative_protected.platform.protected_paths = vec![PathBuf::from("secrets")];
+        assert!(relative_protected.validate().is_err());
+        let mut plain_pccs = config;
+        plain_pccs.collateral.pccs_url = Some("http://pccs.example".into());
+        assert!(plain_pccs.validate().is_err());
+    }
+
+    #[test]
+    fn environment_overrides_and_rejects_bad_values() {
+        // Not the process environment, which tests running in parallel share
+        let overrides = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            let mut config = Config::default();
+            config
+                .apply_overrides(&Overrides(|name: &str| vars.get(name).cloned()))
+                .map(|()| config)
+        };
+
+        let config = overrides(&[
+            ("SEALING_PROVIDER_SESSION_IDLE_TIMEOUT_SECS", "42"),
+            ("SEALING_PROVIDER_PAD_RESPONSE_SIZES", "1"),
+            ("SEALING_PROVIDER_METRICS_ADDR", "127.0.0.1:9100"),
+        ])
+        .unwrap();
+        assert_eq!(config.server.session_idle_timeout_secs, 42);
+        assert!(config.server.pad_response_sizes);
+        assert_eq!(
+            config.server.metrics_addr.as_deref(),
+            Some("127.0.0.1:9100")
+        );
+        assert_eq!(config.server.addr, Config::default().server.addr);
+
+        assert!(matches!(
+            overrides(&[("SEALING_PROVIDER_MODE", "enclav")]),
+            Err(ProviderError::ConfigError(_))
+        ));
+        assert!(overrides(&[("SEALING_PROVIDER_PAD_RESPONSE_SIZES", "yes")]).is_err());
+    }
+
+    #[test]
+    fn attestation_files_move_with_the_platform_config() {
+        let config = Config::parse(
+            r#"
+            [platform]
+            attestation_dir = "/run/attestation"
+            attestation_quote = "/run/quote"
+            "#,
+        )
+        .unwrap();
+        let paths = config.platform.attestation_paths();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(paths.quote, Path::new("/run/quote"));
+        assert_eq!(
+            paths.user_report_data,
+            Path::new("/run/attestation/user_report_data")
+        );
+        assert_eq!(
+            Config::default().platform.attestation_paths(),
+            AttestationPaths::default()
+        );
+
+        let mut td = config.clone();
+        td.platform.mode = Mode::Td;
+        assert!(td.validate().is_err());
+        let mut empty = config;
+        empty.platform.attestation_mrsigner_key = Some(PathBuf::new());
+        assert!(empty.validate().is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+type Active = Arc<Mutex<BTreeMap<u64, Activity>>>;
+
+tokio::task_local! {
+    static CURRENT: Tracked;
+}
+
+/// Client connections currently being served and what each is doing, for
+/// diagnosing hangs.
+#[derive(Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    active: Active,
+}
+
+struct Activity {
+    request_id: String,
+    peer: SocketAddr,
+    connected_at: Instant,
+    phase: &'static str,
+    phase_since: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    pub request_id: String,
+    pub peer: String,
+    pub phase: &'static str,
+    pub connected_ms: u64,
+    pub phase_ms: u64,
+}
+
+/// The current task's entry, removed when the connection's future finishes
+/// or is dropped.
+struct Tracked {
+    id: u64,
+    active: Active,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        lock(&self.active).remove(&self.id);
+    }
+}
+
+impl Connections {
+    /// Runs `connection` as the one from `peer`, so [`enter_phase`] calls
+    /// made while serving it are attributed to it.
+    pub async fn track<F: Future>(
+        &self,
+        request_id: String,
+        peer: SocketAddr,
+        connection: F,
+    ) -> F::Output {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        lock(&self.active).insert(
+            id,
+            Activity {
+                request_id,
+                peer,
+                connected_at: now,
+                phase: "connected",
+                phase_since: now,
+            },
+        );
+        let tracked = Tracked {
+            id,
+            active: Arc::clone(&self.active),
+        };
+        CURRENT.scope(tracked, connection).await
+    }
+
+    /// Every tracked connection, oldest first.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        lock(&self.active)
+            .values()
+            .map(|activity| ConnectionInfo {
+                request_id: activity.request_id.clone(),
+                peer: activity.peer.to_string(),
+                phase: activity.phase,
+                connected_ms: activity.connected_at.elapsed().as_millis() as u64,
+                phase_ms: activity.phase_since.elapsed().as_millis() as u64,
+            })
+            .collect()
+    }
+}
+
+/// Records that the connection served by the current task moved on to
+/// `phase`. Does nothing outside a tracked connection.
+pub fn enter_phase(phase: &'static str) {
+    let _ = CURRENT.try_with(|tracked| {
+        if let Some(activity) = lock(&tracked.active).get_mut(&tracked.id) {
+            activity.phase = phase;
+            activity.phase_since = Instant::now();
+        }
+    });
+}
+
+/// Request ID and phase of the connection served by the current task, for
+/// crash reports. Never blocks, as the caller may hold the lock already.
+pub fn current() -> Option<(String, &'static str)> {
+    CURRENT
+        .try_with(|tracked| {
+            let active = tracked.active.try_lock().ok()?;
+            let activity = active.get(&tracked.id)?;
+            Some((activity.request_id.clone(), activity.phase))
+        })
+        .ok()
+        .flatten()
+}
+
+fn lock(active: &Active) -> MutexGuard<'_, BTreeMap<u64, Activity>> {
+    active
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_are_tracked_until_the_connection_ends() {
+        let connections = Connections::default();
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let during = runtime.block_on(connections.track("abc".into(), peer, async {
+            enter_phase("verify_quote");
+            connections.list()
+        }));
+        enter_phase("ignored");
+
+        assert_eq!(during.len(), 1);
+        assert_eq!(during[0].request_id, "abc");
+        assert_eq!(during[0].phase, "verify_quote");
+        assert!(connections.list().is_empty());
+    }
+}
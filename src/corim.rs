@@ -0,0 +1,276 @@
+//! Reference values from CoRIM documents (draft-ietf-rats-corim), so
+//! operators can use the reference values their supply chain already
+//! publishes instead of writing measurements into the policy by hand.
+//!
+//! Only the reference triples of CoMID tags are read. The class `model` of a
+//! triple's environment, if any, names the evidence kind it applies to
+//! (`tdx`, `sev-snp`, ...). Each measurement names what it measures with a
+//! text `mkey` (`mrtd`, `rtmr0`, ...; none means the launch measurement) and
+//! lists the accepted digests. Evidence matches a triple when every one of
+//! its measurements matches, and passes when it matches any triple.
+
+use crate::error::ProviderError;
+use crate::evidence::Evidence;
+use coset::cbor::value::Value;
+use std::fs;
+use std::path::Path;
+
+const TAG_COSE_SIGN1: u64 = 18;
+const TAG_CORIM: u64 = 501;
+const TAG_COMID: u64 = 506;
+
+const CORIM_ID: i128 = 0;
+const CORIM_TAGS: i128 = 1;
+const COMID_TRIPLES: i128 = 4;
+const TRIPLES_REFERENCE: i128 = 0;
+const ENVIRONMENT_CLASS: i128 = 0;
+const CLASS_MODEL: i128 = 2;
+const MEASUREMENT_KEY: i128 = 0;
+const MEASUREMENT_VALUES: i128 = 1;
+const VALUES_DIGESTS: i128 = 2;
+
+/// What a measurement without `mkey` is compared with.
+pub const LAUNCH_MEASUREMENT: &str = "launch_measurement";
+
+#[derive(Debug, Default)]
+pub struct ReferenceValues {
+    triples: Vec<Triple>,
+}
+
+#[derive(Debug)]
+struct Triple {
+    /// The CoRIM the triple comes from, for error messages.
+    source: String,
+    kind: Option<String>,
+    measurements: Vec<(String, Vec<Vec<u8>>)>,
+}
+
+impl ReferenceValues {
+    /// Reads a CoRIM, signed or not, from `path`. A signature is not
+    /// checked: the file is trusted like the policy that names it.
+    pub fn load(path: &Path) -> Result<Self, ProviderError> {
+        let data = fs::read(path)?;
+        Self::parse(&data).map_err(|e| {
+            ProviderError::PolicyViolation(format!("Invalid CoRIM {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let value: Value = coset::cbor::de::from_reader(data).map_err(|e| e.to_string())?;
+        let corim = match value {
+            Value::Tag(TAG_COSE_SIGN1, sign1) => {
+                let payload = sign1
+                    .as_array()
+                    .and_then(|sign1| sign1.get(2))
+                    .and_then(Value::as_bytes)
+                    .ok_or("signed CoRIM has no payload")?;
+                return Self::parse(payload);
+            }
+            Value::Tag(TAG_CORIM, corim) => corim,
+            _ => return Err("not a CoRIM (tag 501)".into()),
+        };
+        let corim = corim.as_map().ok_or("CoRIM is not a map")?;
+        let source = match get(corim, CORIM_ID) {
+            Some(Value::Text(id)) => id.clone(),
+            Some(Value::Bytes(id)) => hex::encode(id),
+            _ => "unnamed".to_string(),
+        };
+
+        let mut triples = Vec::new();
+        let tags = get(corim, CORIM_TAGS)
+            .and_then(Value::as_array)
+            .ok_or("CoRIM has no tags")?;
+        for tag in tags {
+            let comid = match tag {
+                Value::Tag(TAG_COMID, comid) => match &**comid {
+                    Value::Bytes(bytes) => {
+                        coset::cbor::de::from_reader(&bytes[..]).map_err(|e| e.to_string())?
+                    }
+                    comid => comid.clone(),
+                },
+                // CoSWID and CoTL tags carry no reference values
+                _ => continue,
+            };
+            let comid = comid.as_map().ok_or("CoMID is not a map")?;
+            let reference = get(comid, COMID_TRIPLES)
+                .and_then(Value::as_map)
+                .and_then(|triples| get(triples, TRIPLES_REFERENCE))
+                .and_then(Value::as_array);
+            for triple in reference.into_iter().flatten() {
+                triples.push(parse_triple(triple, &source)?);
+            }
+        }
+        Ok(Self { triples })
+    }
+
+    pub fn extend(&mut self, other: ReferenceValues) {
+        self.triples.extend(other.triples);
+    }
+
+    pub fn len(&self) -> usize {
+        self.triples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triples.is_empty()
+    }
+
+    /// Checks that `evidence` matches one of the reference triples.
+    pub fn check(&self, evidence: &dyn Evidence) -> Result<(), ProviderError> {
+        let kind = evidence.kind().name();
+        let measured = evidence.reference_measurements();
+        let matched = self
+            .triples
+            .iter()
+            .filter(|triple| triple.kind.is_none() || triple.kind.as_deref() == Some(kind))
+            .find(|triple| {
+                triple.measurements.iter().all(|(name, digests)| {
+                    measured
+                        .iter()
+                        .find(|(measured_name, _)| *measured_name == name.as_str())
+                        .is_some_and(|(_, value)| digests.iter().any(|d| d == value))
+                })
+            });
+        match matched {
+            Some(triple) => {
+                log::debug!("Evidence matches reference values from {}", triple.source);
+                Ok(())
+            }
+            None => Err(ProviderError::PolicyViolation(format!(
+                "{} measurements match none of the {} reference values",
+                kind,
+                self.triples.len()
+            ))),
+        }
+    }
+}
+
+fn parse_triple(triple: &Value, source: &str) -> Result<Triple, String> {
+    let [environment, measurements] = triple
+        .as_array()
+        .map(Vec::as_slice)
+        .and_then(|triple| <&[Value; 2]>::try_from(triple).ok())
+        .ok_or("reference triple is not a pair")?;
+    let kind = environment
+        .as_map()
+        .and_then(|environment| get(environment, ENVIRONMENT_CLASS))
+        .and_then(Value::as_map)
+        .and_then(|class| get(class, CLASS_MODEL))
+        .and_then(Value::as_text)
+        .cloned();
+
+    let mut parsed = Vec::new();
+    for measurement in measurements
+        .as_array()
+        .ok_or("reference triple has no measurements")?
+    {
+        let measurement = measurement.as_map().ok_or("measurement is not a map")?;
+        let name = match get(measurement, MEASUREMENT_KEY) {
+            None => LAUNCH_MEASUREMENT.to_string(),
+            Some(Value::Text(name)) => name.clone(),
+            Some(_) => return Err("only text mkeys are supported".into()),
+        };
+        let digests = get(measurement, MEASUREMENT_VALUES)
+            .and_then(Value::as_map)
+            .and_then(|values| get(values, VALUES_DIGESTS))
+            .and_then(Value::as_array)
+            .ok_or_else(|| format!("measurement {} has no digests", name))?
+            .iter()
+            .map(|digest| {
+                // [algorithm, value]; the value's length gives the algorithm away
+                digest
+                    .as_array()
+                    .and_then(|digest| digest.get(1))
+                    .and_then(Value::as_bytes)
+                    .cloned()
+                    .ok_or_else(|| format!("malformed digest for {}", name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        parsed.push((name, digests));
+    }
+    Ok(Triple {
+        source: source.to_string(),
+        kind,
+        measurements: parsed,
+    })
+}
+
+fn get(map: &[(Value, Value)], key: i128) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|k| i128::from(k) == key))
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::TdxEvidence;
+
+    fn int(key: i128) -> Value {
+        Value::Integer(key.try_into().unwrap())
+    }
+
+    fn corim(model: &str, mkey: &str, digest: &[u8]) -> Vec<u8> {
+        let measurement = Value::Map(vec![
+            (int(MEASUREMENT_KEY), Value::Text(mkey.into())),
+            (
+                int(MEASUREMENT_VALUES),
+                Value::Map(vec![(
+                    int(VALUES_DIGESTS),
+                    Value::Array(vec![Value::Array(vec![
+                        int(7),
+                        Value::Bytes(digest.to_vec()),
+                    ])]),
+                )]),
+            ),
+        ]);
+        let environment = Value::Map(vec![(
+            int(ENVIRONMENT_CLASS),
+            Value::Map(vec![(int(CLASS_MODEL), Value::Text(model.into()))]),
+        )]);
+        let comid = Value::Map(vec![(
+            int(COMID_TRIPLES),
+            Value::Map(vec![(
+                int(TRIPLES_REFERENCE),
+                Value::Array(vec![Value::Array(vec![
+                    environment,
+                    Value::Array(vec![measurement]),
+                ])]),
+            )]),
+        )]);
+        let mut comid_bytes = Vec::new();
+        coset::cbor::ser::into_writer(&comid, &mut comid_bytes).unwrap();
+        let corim = Value::Tag(
+            TAG_CORIM,
+            Box::new(Value::Map(vec![
+                (int(CORIM_ID), Value::Text("test".into())),
+                (
+                    int(CORIM_TAGS),
+                    Value::Array(vec![Value::Tag(
+                        TAG_COMID,
+                        Box::new(Value::Bytes(comid_bytes)),
+                    )]),
+                ),
+            ])),
+        );
+        let mut bytes = Vec::new();
+        coset::cbor::ser::into_writer(&corim, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn quote_must_match_a_reference_mrtd() {
+        let quote = include_bytes!("../quotes/tdxQuote.txt");
+        let evidence = TdxEvidence::parse(quote).unwrap();
+        let mrtd = evidence.measurements()[..48].to_vec();
+
+        let matching = ReferenceValues::parse(&corim("tdx", "mrtd", &mrtd)).unwrap();
+        assert_eq!(matching.len(), 1);
+        assert!(matching.check(&evidence).is_ok());
+
+        let other = ReferenceValues::parse(&corim("tdx", "mrtd", &[0; 48])).unwrap();
+        assert!(other.check(&evidence).is_err());
+        let snp_only = ReferenceValues::parse(&corim("sev-snp", "mrtd", &mrtd)).unwrap();
+        assert!(snp_only.check(&evidence).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use crate::crypto::{backend, constant_time_eq, MasterSecret, SecretBytes};
+use crate::error::ProviderError;
+use crate::gramine;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const COUNTER_KEY_LABEL: &[u8] = b"gramine-sealing-key-provider/counters/v1";
+
+/// Incremented on every provider start.
+pub const BOOT_EPOCH: &str = "boot_epoch";
+
+/// The highest remote policy bundle version applied.
+pub const POLICY_VERSION: &str = "policy_version";
+
+/// Longest counter name in bytes; the MAC frames each name with a one-byte
+/// length.
+pub const MAX_NAME_LEN: usize = u8::MAX as usize;
+
+/// On-disk form of one generation of the store. `mac` is
+/// `HMAC(key, prev_mac || generation || counters)`, so each generation
+/// commits to the one it replaced.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    generation: u64,
+    counters: BTreeMap<String, u64>,
+    prev_mac: String,
+    mac: String,
+}
+
+struct State {
+    generation: u64,
+    counters: BTreeMap<String, u64>,
+    mac: [u8; 32],
+}
+
+/// Named counters that only ever go up, persisted across restarts.
+///
+/// The file is meant to live on a Gramine encrypted mount; the HMAC chain
+/// (keyed from the master secret) additionally rejects edits and records
+/// from another provider. Updates are written to a temporary file, synced and
+/// renamed over the old one, so a crash leaves either the old or the new
+/// generation. Restoring an entire older file is not detected, so every
+/// counter, including [`POLICY_VERSION`] and the release counts, can be set
+/// back by a host that also restarts the provider.
+///
+/// Updates write the file on a blocking thread.
+pub struct CounterStore {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    key: SecretBytes,
+    state: Mutex<State>,
+}
+
+impl CounterStore {
+    pub fn open(path: &Path, master: &MasterSecret) -> Result<Self, ProviderError> {
+        let key = master.expand(COUNTER_KEY_LABEL)?;
+        let state = match fs::read(path) {
+            Ok(data) => {
+                let record: Record = serde_json::from_slice(&data)?;
+                check_names(&record.counters)?;
+                let prev_mac = decode_mac(&record.prev_mac)?;
+                let expected = record_mac(&key, &prev_mac, record.generation, &record.counters);
+                if !constant_time_eq(&expected, &decode_mac(&record.mac)?) {
+                    return Err(ProviderError::CryptoError(format!(
+                        "Counter store {} failed its integrity check",
+                        path.display()
+                    )));
+                }
+                State {
+                    generation: record.generation,
+                    counters: record.counters,
+                    mac: expected,
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => State {
+                generation: 0,
+                counters: BTreeMap::new(),
+                mac: [0u8; 32],
+            },
+            Err(e) => return Err(ProviderError::IOError(e)),
+        };
+        info!(
+            "Opened counter store {} at generation {}",
+            path.display(),
+            state.generation
+        );
+
+        Ok(Self {
+            shared: Arc::new(Shared {
+                path: path.to_path_buf(),
+                key,
+                state: Mutex::new(state),
+            }),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> u64 {
+        self.shared.lock().counters.get(name).copied().unwrap_or(0)
+    }
+
+    /// The current generation and every counter.
+    pub fn snapshot(&self) -> (u64, BTreeMap<String, u64>) {
+        let state = self.shared.lock();
+        (state.generation, state.counters.clone())
+    }
+
+    /// Adds one to `name` and returns the new value once it is durable.
+    pub async fn increment(&self, name: &str) -> Result<u64, ProviderError> {
+        let name = name.to_string();
+        self.update(move |shared| shared.increment(&name)).await
+    }
+
+    /// Raises `name` to `value` unless it is already there. Returns the
+    /// counter's value once it is durable.
+    pub async fn raise(&self, name: &str, value: u64) -> Result<u64, ProviderError> {
+        let name = name.to_string();
+        self.update(move |shared| shared.raise(&name, value)).await
+    }
+
+    /// Adds one to each of `limits`' counters in one generation, unless one
+    /// of them has reached its maximum (0 is none). Returns the new values
+    /// once they are durable, or `None` and changes nothing.
+    pub async fn increment_within(
+        &self,
+        limits: Vec<(String, u64)>,
+    ) -> Result<Option<Vec<u64>>, ProviderError> {
+        self.update(move |shared| shared.increment_within(&limits))
+            .await
+    }
+
+    async fn update<T: Send + 'static>(
+        &self,
+        update: impl FnOnce(&Shared) -> Result<T, ProviderError> + Send + 'static,
+    ) -> Result<T, ProviderError> {
+        let shared = Arc::clone(&self.shared);
+        gramine::unblock(move || update(&shared)).await
+    }
+}
+
+impl Shared {
+    fn increment(&self, name: &str) -> Result<u64, ProviderError> {
+        let mut state = self.lock();
+        let value = state
+            .counters
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or_else(|| ProviderError::CryptoError(format!("Counter {} overflowed", name)))?;
+
+        let mut counters = state.counters.clone();
+        counters.insert(name.to_string(), value);
+        self.commit(&mut state, counters)?;
+        debug!("Counter {} advanced to {}", name, value);
+        Ok(value)
+    }
+
+    fn raise(&self, name: &str, value: u64) -> Result<u64, ProviderError> {
+        let mut state = self.lock();
+        let current = state.counters.get(name).copied().unwrap_or(0);
+        if value <= current {
+            return Ok(current);
+        }
+
+        let mut counters = state.counters.clone();
+        counters.insert(name.to_string(), value);
+        self.commit(&mut state, counters)?;
+        debug!("Counter {} raised to {}", name, value);
+        Ok(value)
+    }
+
+    fn increment_within(
+        &self,
+        limits: &[(String, u64)],
+    ) -> Result<Option<Vec<u64>>, ProviderError> {
+        let mut state = self.lock();
+        let mut counters = state.counters.clone();
+        let mut values = Vec::with_capacity(limits.len());
+        for (name, max) in limits {
+            let value = counters.get(name).copied().unwrap_or(0);
+            if *max > 0 && value >= *max {
+                return Ok(None);
+            }
+            let value = value.checked_add(1).ok_or_else(|| {
+                ProviderError::CryptoError(format!("Counter {} overflowed", name))
+            })?;
+            counters.insert(name.clone(), value);
+            values.push(value);
+        }
+        self.commit(&mut state, counters)?;
+        Ok(Some(values))
+    }
+
+    fn commit(
+        &self,
+        state: &mut State,
+        counters: BTreeMap<String, u64>,
+    ) -> Result<(), ProviderError> {
+        check_names(&counters)?;
+        let generation = state.generation + 1;
+        let mac = record_mac(&self.key, &state.mac, generation, &counters);
+        let record = Record {
+            generation,
+            counters,
+            prev_mac: hex::encode(state.mac),
+            mac: hex::encode(mac),
+        };
+
+        let staged = self.path.with_extension("tmp");
+        let mut file = File::create(&staged)?;
+        file.write_all(&serde_json::to_vec(&record)?)?;
+        file.sync_all()?;
+        fs::rename(&staged, &self.path)?;
+
+        state.generation = generation;
+        state.counters = record.counters;
+        state.mac = mac;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn check_names(counters: &BTreeMap<String, u64>) -> Result<(), ProviderError> {
+    match counters.keys().find(|name| name.len() > MAX_NAME_LEN) {
+        Some(name) => Err(ProviderError::ConfigError(format!(
+            "Counter name {} is longer than {} bytes",
+            name, MAX_NAME_LEN
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn record_mac(
+    key: &SecretBytes,
+    prev_mac: &[u8],
+    generation: u64,
+    counters: &BTreeMap<String, u64>,
+) -> [u8; 32] {
+    let mut encoded = Vec::new();
+    for (name, value) in counters {
+        encoded.push(name.len() as u8);
+        encoded.extend_from_slice(name.as_bytes());
+        encoded.extend_from_slice(&value.to_be_bytes());
+    }
+    backend().hmac_sha256(
+        key.expose(),
+        &[prev_mac, &generation.to_be_bytes(), &encoded],
+    )
+}
+
+fn decode_mac(encoded: &str) -> Result<[u8; 32], ProviderError> {
+    hex::decode(encoded)
+        .ok()
+        .and_then(|mac| mac.try_into().ok())
+        .ok_or_else(|| ProviderError::SerializationError("Malformed counter store MAC".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master(byte: u8) -> MasterSecret {
+        sodiumoxide::init().unwrap();
+        MasterSecret::from_sealing_key(&[byte; 16], None).unwrap()
+    }
+
+    fn store_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("counters-{}-{}.json", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn counters_persist_and_only_increase() {
+        let path = store_path("persist");
+        let store = CounterStore::open(&path, &master(1)).unwrap();
+        assert_eq!(store.increment("epoch").await.unwrap(), 1);
+        assert_eq!(store.increment("epoch").await.unwrap(), 2);
+        drop(store);
+
+        let reopened = CounterStore::open(&path, &master(1)).unwrap();
+        let value = reopened.get("epoch");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(value, 2);
+        assert_eq!(reopened.get("other"), 0);
+    }
+
+    #[tokio::test]
+    async fn raising_never_lowers_a_counter() {
+        let path = store_path("raise");
+        let store = CounterStore::open(&path, &master(1)).unwrap();
+        assert_eq!(store.raise("version", 5).await.unwrap(), 5);
+        assert_eq!(store.raise("version", 3).await.unwrap(), 5);
+        drop(store);
+
+        let reopened = CounterStore::open(&path, &master(1)).unwrap();
+        let value = reopened.get("version");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(value, 5);
+    }
+
+    #[tokio::test]
+    async fn names_too_long_to_frame_are_refused() {
+        let path = store_path("long-names");
+        let store = CounterStore::open(&path, &master(1)).unwrap();
+        let longest = "n".repeat(MAX_NAME_LEN);
+        assert_eq!(store.increment(&longest).await.unwrap(), 1);
+        let too_long = store.increment(&"n".repeat(MAX_NAME_LEN + 1)).await;
+        let (generation, _) = store.snapshot();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(too_long, Err(ProviderError::ConfigError(_))));
+        assert_eq!(generation, 1);
+    }
+
+    #[tokio::test]
+    async fn tampered_or_foreign_stores_are_rejected() {
+        let path = store_path("tamper");
+        CounterStore::open(&path, &master(1))
+            .unwrap()
+            .increment("epoch")
+            .await
+            .unwrap();
+
+        let foreign = CounterStore::open(&path, &master(2));
+        let edited = fs::read_to_string(&path)
+            .unwrap()
+            .replace("\"epoch\":1", "\"epoch\":0");
+        fs::write(&path, edited).unwrap();
+        let tampered = CounterStore::open(&path, &master(1));
+        fs::remove_file(&path).unwrap();
+
+        assert!(foreign.is_err());
+        assert!(tampered.is_err());
+    }
+}
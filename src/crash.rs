@@ -0,0 +1,85 @@
+use crate::connections;
+use crate::crypto;
+use serde::Serialize;
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::io::Write;
+use std::panic::PanicHookInfo;
+
+/// What is printed about a panic, as one JSON line on stderr.
+#[derive(Serialize)]
+struct CrashReport {
+    event: &'static str,
+    thread: Option<String>,
+    location: Option<String>,
+    /// Only literal messages; formatted ones are withheld.
+    message: Option<&'static str>,
+    request_id: Option<String>,
+    phase: Option<&'static str>,
+    guarded_keys_wiped: usize,
+    backtrace: Option<String>,
+}
+
+/// Replaces the default panic hook. A panic then prints a crash report and
+/// aborts, instead of the default message and an unwind that tokio would
+/// catch and survive.
+///
+/// The default hook prints the panic message, and `assert_eq!`, `expect` on
+/// a `Result` and `{:?}` in a `panic!` put runtime values into it, which in a
+/// request handler can be key material. The report keeps only messages that
+/// are string literals. Guarded keys are wiped before aborting, and the
+/// backtrace, when `RUST_BACKTRACE` enables it, holds addresses and symbols
+/// only.
+pub fn install() {
+    std::panic::set_hook(Box::new(|info| {
+        let report = report(info);
+        // Locks taken by the logger may be held by the panicking thread, so
+        // write to stderr directly
+        let mut stderr = std::io::stderr().lock();
+        if let Ok(line) = serde_json::to_string(&report) {
+            let _ = writeln!(stderr, "{}", line);
+        }
+        let _ = stderr.flush();
+        std::process::abort();
+    }));
+}
+
+fn report(info: &PanicHookInfo<'_>) -> CrashReport {
+    let (request_id, phase) = connections::current().unzip();
+    let backtrace = Backtrace::capture();
+    CrashReport {
+        event: "panic",
+        thread: std::thread::current().name().map(str::to_string),
+        location: info
+            .location()
+            .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())),
+        message: literal_message(info.payload()),
+        request_id,
+        phase,
+        guarded_keys_wiped: crypto::wipe_guarded_keys(),
+        backtrace: (backtrace.status() == BacktraceStatus::Captured).then(|| backtrace.to_string()),
+    }
+}
+
+/// The panic message if it is a string literal, which cannot carry runtime
+/// data. `panic!` with format arguments produces a `String` instead.
+fn literal_message(payload: &(dyn Any + Send)) -> Option<&'static str> {
+    payload.downcast_ref::<&'static str>().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatted_messages_are_withheld() {
+        let literal: Box<dyn Any + Send> = Box::new("invariant violated");
+        let formatted: Box<dyn Any + Send> = Box::new(format!("key {:?}", [0x41u8; 4]));
+
+        assert_eq!(
+            literal_message(literal.as_ref()),
+            Some("invariant violated")
+        );
+        assert_eq!(literal_message(formatted.as_ref()), None);
+    }
+}
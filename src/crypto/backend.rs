@@ -0,0 +1,402 @@
+use super::entropy::MixedRng;
+use crate::error::ProviderError;
+use log::info;
+use rsa::RsaPublicKey;
+use std::sync::OnceLock;
+
+pub const SHA256_LEN: usize = 32;
+pub const AEAD_KEY_LEN: usize = 32;
+pub const AEAD_NONCE_LEN: usize = 12;
+
+/// Provider of the symmetric primitives (hashing, MACs, AEADs) and of RSA-OAEP
+/// key transport. HKDF is built on top of `hmac_sha256` so key derivation
+/// goes through the same backend.
+pub trait CryptoBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether this backend runs in a validated FIPS module. Suites whose
+    /// symmetric layer is not routed through the backend are then disabled.
+    fn is_fips(&self) -> bool;
+
+    fn sha256(&self, parts: &[&[u8]]) -> [u8; SHA256_LEN];
+
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; SHA256_LEN];
+
+    /// Encrypts `in_out` in place and appends the 16-byte tag.
+    fn aes256gcm_seal(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError>;
+
+    /// Verifies and strips the trailing 16-byte tag, decrypting in place.
+    fn aes256gcm_open(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError>;
+
+    /// Encrypts `in_out` in place and appends the 16-byte tag (RFC 8439).
+    fn chacha20poly1305_seal(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError>;
+
+    /// RSA-OAEP with SHA-256, MGF1-SHA-256 and an empty label.
+    fn rsa_oaep_sha256_encrypt(
+        &self,
+        public_key: &RsaPublicKey,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, ProviderError>;
+}
+
+static BACKEND: OnceLock<Box<dyn CryptoBackend>> = OnceLock::new();
+
+/// Selects the backend by name (`native` or `fips`). Must run before any
+/// other crypto operation; the native backend is used if it never runs.
+pub fn init_backend(name: &str) -> Result<(), ProviderError> {
+    let selected: Box<dyn CryptoBackend> = match name {
+        "native" => Box::new(NativeBackend),
+        #[cfg(feature = "fips")]
+        "fips" => Box::new(fips::AwsLcFipsBackend::new()?),
+        #[cfg(not(feature = "fips"))]
+        "fips" => {
+            return Err(ProviderError::CryptoError(
+                "FIPS backend requested but the provider was built without the `fips` feature"
+                    .into(),
+            ))
+        }
+        other => {
+            return Err(ProviderError::CryptoError(format!(
+                "Unknown crypto backend: {}",
+                other
+            )))
+        }
+    };
+
+    let selected_name = selected.name();
+    BACKEND
+        .set(selected)
+        .map_err(|_| ProviderError::CryptoError("Crypto backend already initialized".into()))?;
+    info!("Using {} crypto backend", selected_name);
+    Ok(())
+}
+
+pub fn backend() -> &'static dyn CryptoBackend {
+    BACKEND.get_or_init(|| Box::new(NativeBackend)).as_ref()
+}
+
+/// HKDF-Extract (RFC 5869) with HMAC-SHA256 from the active backend.
+pub fn hkdf_extract(salt: &[u8], ikm: &[u8]) -> [u8; SHA256_LEN] {
+    backend().hmac_sha256(salt, &[ikm])
+}
+
+/// HKDF-Expand (RFC 5869) with HMAC-SHA256 from the active backend.
+pub fn hkdf_expand(prk: &[u8], info: &[u8], out: &mut [u8]) -> Result<(), ProviderError> {
+    if out.len() > 255 * SHA256_LEN {
+        return Err(ProviderError::CryptoError(
+            "HKDF output length too large".into(),
+        ));
+    }
+
+    // T(i) is kept in one place and wiped there once the output is filled
+    let mut block = zeroize::Zeroizing::new([0u8; SHA256_LEN]);
+    for (index, chunk) in out.chunks_mut(SHA256_LEN).enumerate() {
+        let counter = [(index + 1) as u8];
+        *block = if index == 0 {
+            backend().hmac_sha256(prk, &[info, &counter])
+        } else {
+            backend().hmac_sha256(prk, &[&block[..], info, &counter])
+        };
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+    Ok(())
+}
+
+/// RustCrypto hashes/MACs/AES-GCM/RSA and libsodium's ChaCha20-Poly1305.
+pub struct NativeBackend;
+
+impl CryptoBackend for NativeBackend {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn is_fips(&self) -> bool {
+        false
+    }
+
+    fn sha256(&self, parts: &[&[u8]]) -> [u8; SHA256_LEN] {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+
+    fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; SHA256_LEN] {
+        use hmac::{Hmac, Mac};
+
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key)
+            .expect("HMAC-SHA256 accepts keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().into()
+    }
+
+    fn aes256gcm_seal(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError> {
+        use aes_gcm::aead::{AeadInPlace, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| ProviderError::CryptoError("Invalid A256GCM key length".into()))?;
+        let tag = cipher
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), aad, in_out)
+            .map_err(|_| ProviderError::CryptoError("A256GCM encryption failed".into()))?;
+        in_out.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    fn aes256gcm_open(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError> {
+        use aes_gcm::aead::{AeadInPlace, KeyInit};
+        use aes_gcm::{Aes256Gcm, Nonce};
+
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| ProviderError::CryptoError("Invalid A256GCM key length".into()))?;
+        cipher
+            .decrypt_in_place(Nonce::from_slice(nonce), aad, in_out)
+            .map_err(|_| ProviderError::CryptoError("A256GCM decryption failed".into()))
+    }
+
+    fn chacha20poly1305_seal(
+        &self,
+        key: &[u8; AEAD_KEY_LEN],
+        nonce: &[u8; AEAD_NONCE_LEN],
+        aad: &[u8],
+        in_out: &mut Vec<u8>,
+    ) -> Result<(), ProviderError> {
+        use sodiumoxide::crypto::aead::chacha20poly1305_ietf::{seal, Key, Nonce};
+
+        let sealed = seal(in_out, Some(aad), &Nonce(*nonce), &Key(*key));
+        in_out.clear();
+        in_out.extend_from_slice(&sealed);
+        Ok(())
+    }
+
+    fn rsa_oaep_sha256_encrypt(
+        &self,
+        public_key: &RsaPublicKey,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, ProviderError> {
+        use rsa::Oaep;
+        use sha2::Sha256;
+
+        let mut rng = MixedRng::new();
+        let encrypted = public_key
+            .encrypt(&mut rng, Oaep::new::<Sha256>(), plaintext)
+            .map_err(|e| {
+                ProviderError::CryptoError(format!("RSA-OAEP encryption failed: {}", e))
+            })?;
+        rng.finish()?;
+        Ok(encrypted)
+    }
+}
+
+#[cfg(feature = "fips")]
+mod fips {
+    use super::{CryptoBackend, AEAD_KEY_LEN, AEAD_NONCE_LEN, SHA256_LEN};
+    use crate::error::ProviderError;
+    use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, CHACHA20_POLY1305};
+    use aws_lc_rs::rsa::{OaepPublicEncryptingKey, PublicEncryptingKey, OAEP_SHA256_MGF1SHA256};
+    use aws_lc_rs::{digest, hmac};
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::RsaPublicKey;
+
+    /// aws-lc-rs built against the FIPS-validated AWS-LC module.
+    pub struct AwsLcFipsBackend;
+
+    impl AwsLcFipsBackend {
+        pub fn new() -> Result<Self, ProviderError> {
+            aws_lc_rs::try_fips_mode().map_err(|e| {
+                ProviderError::CryptoError(format!("AWS-LC is not in FIPS mode: {}", e))
+            })?;
+            Ok(Self)
+        }
+
+        fn seal(
+            algorithm: &'static aws_lc_rs::aead::Algorithm,
+            key: &[u8; AEAD_KEY_LEN],
+            nonce: &[u8; AEAD_NONCE_LEN],
+            aad: &[u8],
+            in_out: &mut Vec<u8>,
+        ) -> Result<(), ProviderError> {
+            let key = UnboundKey::new(algorithm, key)
+                .map_err(|_| ProviderError::CryptoError("Invalid AEAD key".into()))?;
+            LessSafeKey::new(key)
+                .seal_in_place_append_tag(
+                    Nonce::assume_unique_for_key(*nonce),
+                    Aad::from(aad),
+                    in_out,
+                )
+                .map_err(|_| ProviderError::CryptoError("AEAD encryption failed".into()))
+        }
+    }
+
+    impl CryptoBackend for AwsLcFipsBackend {
+        fn name(&self) -> &'static str {
+            "aws-lc-fips"
+        }
+
+        fn is_fips(&self) -> bool {
+            true
+        }
+
+        fn sha256(&self, parts: &[&[u8]]) -> [u8; SHA256_LEN] {
+            let mut context = digest::Context::new(&digest::SHA256);
+            for part in parts {
+                context.update(part);
+            }
+            let mut out = [0u8; SHA256_LEN];
+            out.copy_from_slice(context.finish().as_ref());
+            out
+        }
+
+        fn hmac_sha256(&self, key: &[u8], parts: &[&[u8]]) -> [u8; SHA256_LEN] {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+            let mut context = hmac::Context::with_key(&key);
+            for part in parts {
+                context.update(part);
+            }
+            let mut out = [0u8; SHA256_LEN];
+            out.copy_from_slice(context.sign().as_ref());
+            out
+        }
+
+        fn aes256gcm_seal(
+            &self,
+            key: &[u8; AEAD_KEY_LEN],
+            nonce: &[u8; AEAD_NONCE_LEN],
+            aad: &[u8],
+            in_out: &mut Vec<u8>,
+        ) -> Result<(), ProviderError> {
+            Self::seal(&AES_256_GCM, key, nonce, aad, in_out)
+        }
+
+        fn aes256gcm_open(
+            &self,
+            key: &[u8; AEAD_KEY_LEN],
+            nonce: &[u8; AEAD_NONCE_LEN],
+            aad: &[u8],
+            in_out: &mut Vec<u8>,
+        ) -> Result<(), ProviderError> {
+            let key = UnboundKey::new(&AES_256_GCM, key)
+                .map_err(|_| ProviderError::CryptoError("Invalid AEAD key".into()))?;
+            let plaintext_len = LessSafeKey::new(key)
+                .open_in_place(Nonce::assume_unique_for_key(*nonce), Aad::from(aad), in_out)
+                .map_err(|_| ProviderError::CryptoError("AEAD decryption failed".into()))?
+                .len();
+            in_out.truncate(plaintext_len);
+            Ok(())
+        }
+
+        fn chacha20poly1305_seal(
+            &self,
+            key: &[u8; AEAD_KEY_LEN],
+            nonce: &[u8; AEAD_NONCE_LEN],
+            aad: &[u8],
+            in_out: &mut Vec<u8>,
+        ) -> Result<(), ProviderError> {
+            Self::seal(&CHACHA20_POLY1305, key, nonce, aad, in_out)
+        }
+
+        fn rsa_oaep_sha256_encrypt(
+            &self,
+            public_key: &RsaPublicKey,
+            plaintext: &[u8],
+        ) -> Result<Vec<u8>, ProviderError> {
+            let der = public_key.to_public_key_der().map_err(|e| {
+                ProviderError::CryptoError(format!("Cannot encode RSA public key: {}", e))
+            })?;
+            let key = PublicEncryptingKey::from_der(der.as_bytes()).map_err(|e| {
+                ProviderError::CryptoError(format!("Invalid RSA public key: {}", e))
+            })?;
+            let key = OaepPublicEncryptingKey::new(key)
+                .map_err(|_| ProviderError::CryptoError("Invalid RSA-OAEP key".into()))?;
+            let mut ciphertext = vec![0u8; key.ciphertext_size()];
+            let len = key
+                .encrypt(&OAEP_SHA256_MGF1SHA256, plaintext, &mut ciphertext, None)
+                .map_err(|_| ProviderError::CryptoError("RSA-OAEP encryption failed".into()))?
+                .len();
+            ciphertext.truncate(len);
+            Ok(ciphertext)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 5869, Appendix A.1
+    #[test]
+    fn hkdf_rfc5869_case_1() {
+        let ikm = [0x0bu8; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+
+        let prk = hkdf_extract(&salt, &ikm);
+        assert_eq!(
+            hex::encode(prk),
+            "077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"
+        );
+
+        let mut okm = [0u8; 42];
+        hkdf_expand(&prk, &info, &mut okm).unwrap();
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        );
+    }
+
+    #[cfg(feature = "fips")]
+    #[test]
+    fn fips_backend_serves_rsa_recipients() {
+        use super::super::envelope::Suite;
+        use rsa::{Oaep, RsaPrivateKey};
+
+        // The suite an RSA recipient gets is offered under this backend
+        assert!(Suite::RsaOaepSha256.is_fips_approved());
+
+        let backend = fips::AwsLcFipsBackend::new().unwrap();
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 3072).unwrap();
+        let ciphertext = backend
+            .rsa_oaep_sha256_encrypt(&private_key.to_public_key(), &[7; 32])
+            .unwrap();
+
+        let plaintext = private_key
+            .decrypt(Oaep::new::<sha2::Sha256>(), &ciphertext)
+            .unwrap();
+        assert_eq!(plaintext, [7; 32]);
+    }
+}
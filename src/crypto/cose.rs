@@ -0,0 +1,182 @@
+use super::backend::{backend, hkdf_expand, hkdf_extract, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+use super::entropy;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use coset::cbor::value::Value;
+use coset::{iana, CborSerializable, CoseEncrypt0Builder, HeaderBuilder};
+use log::{debug, info};
+use sodiumoxide::crypto::box_::{self, PublicKey};
+use sodiumoxide::crypto::scalarmult::curve25519::{scalarmult, GroupElement, Scalar};
+use zeroize::Zeroizing;
+
+const COSE_KDF_LABEL: &[u8] = b"gramine-sealing-key-provider/cose-encrypt0/v1";
+
+/// Encrypts the derived key as a COSE_Encrypt0 object (RFC 9052).
+///
+/// The content key comes from an ephemeral-static X25519 exchange with the
+/// recipient, expanded with HKDF-SHA256 over
+/// `label || ephemeral_public || recipient_public`. The ephemeral public key is
+/// carried as a COSE_Key in the unprotected `ephemeral key` (-1) header, the
+/// derived key id in `kid`, and the content is ChaCha20/Poly1305 (alg 24).
+pub fn encrypt_key_cose(
+    derived_key: &SecretBytes,
+    key_id: &[u8],
+    public_key: &PublicKey,
+) -> Result<Vec<u8>, ProviderError> {
+    info!("Encrypting derived key as COSE_Encrypt0");
+
+    let (ephemeral_public, ephemeral_secret) = entropy::gen_keypair()?;
+    let shared = scalarmult(&Scalar(ephemeral_secret.0), &GroupElement(public_key.0))
+        .map_err(|_| ProviderError::CryptoError("X25519 produced a low-order point".into()))?;
+    let shared = Zeroizing::new(shared.0);
+
+    let mut info = Vec::with_capacity(COSE_KDF_LABEL.len() + 2 * box_::PUBLICKEYBYTES);
+    info.extend_from_slice(COSE_KDF_LABEL);
+    info.extend_from_slice(&ephemeral_public.0);
+    info.extend_from_slice(&public_key.0);
+
+    let prk = Zeroizing::new(hkdf_extract(&[], &shared[..]));
+    let mut content_key = Zeroizing::new([0u8; AEAD_KEY_LEN]);
+    hkdf_expand(&prk[..], &info, &mut content_key[..])?;
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    entropy::fill(&mut nonce)?;
+
+    let ephemeral_key = Value::Map(vec![
+        (
+            Value::from(iana::KeyParameter::Kty as i64),
+            Value::from(iana::KeyType::OKP as i64),
+        ),
+        (
+            Value::from(iana::OkpKeyParameter::Crv as i64),
+            Value::from(iana::EllipticCurve::X25519 as i64),
+        ),
+        (
+            Value::from(iana::OkpKeyParameter::X as i64),
+            Value::Bytes(ephemeral_public.0.to_vec()),
+        ),
+    ]);
+
+    let protected = HeaderBuilder::new()
+        .algorithm(iana::Algorithm::ChaCha20Poly1305)
+        .build();
+    let unprotected = HeaderBuilder::new()
+        .key_id(key_id.to_vec())
+        .iv(nonce.to_vec())
+        .value(
+            iana::HeaderAlgorithmParameter::EphemeralKey as i64,
+            ephemeral_key,
+        )
+        .build();
+
+    let encrypted = CoseEncrypt0Builder::new()
+        .protected(protected)
+        .unprotected(unprotected)
+        .try_create_ciphertext(derived_key.expose(), &[], |plaintext, aad| {
+            let mut ciphertext = plaintext.to_vec();
+            backend().chacha20poly1305_seal(&content_key, &nonce, aad, &mut ciphertext)?;
+            Ok::<_, ProviderError>(ciphertext)
+        })?
+        .build()
+        .to_vec()
+        .map_err(|e| ProviderError::SerializationError(format!("COSE encoding failed: {:?}", e)))?;
+
+    debug!("COSE_Encrypt0 length: {} bytes", encrypted.len());
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::init_sodium;
+    use coset::{CoseEncrypt0, Label, RegisteredLabelWithPrivate};
+    use sodiumoxide::crypto::aead::chacha20poly1305_ietf::{self, Key, Nonce};
+    use sodiumoxide::crypto::box_::SecretKey;
+
+    /// Decrypts like a recipient would, from the headers alone.
+    fn decrypt(
+        encrypted: &CoseEncrypt0,
+        public_key: &PublicKey,
+        secret_key: &SecretKey,
+    ) -> Option<Vec<u8>> {
+        let ephemeral_key = encrypted
+            .unprotected
+            .rest
+            .iter()
+            .find_map(|(label, value)| {
+                (*label == Label::Int(iana::HeaderAlgorithmParameter::EphemeralKey as i64))
+                    .then_some(value)
+            })?;
+        let Value::Map(ephemeral_key) = ephemeral_key else {
+            return None;
+        };
+        let x = ephemeral_key.iter().find_map(|(label, value)| {
+            (*label == Value::from(iana::OkpKeyParameter::X as i64)).then(|| value.as_bytes())
+        })??;
+        let ephemeral_public = PublicKey::from_slice(x)?;
+
+        let shared = scalarmult(&Scalar(secret_key.0), &GroupElement(ephemeral_public.0)).ok()?;
+        let mut info = COSE_KDF_LABEL.to_vec();
+        info.extend_from_slice(&ephemeral_public.0);
+        info.extend_from_slice(&public_key.0);
+        let mut content_key = [0u8; AEAD_KEY_LEN];
+        hkdf_expand(&hkdf_extract(&[], &shared.0), &info, &mut content_key).unwrap();
+        let nonce = Nonce::from_slice(&encrypted.unprotected.iv)?;
+
+        encrypted
+            .decrypt(&[], |ciphertext, aad| {
+                chacha20poly1305_ietf::open(ciphertext, Some(aad), &nonce, &Key(content_key))
+            })
+            .ok()
+    }
+
+    #[test]
+    fn encrypt0_decrypts_with_the_recipient_key() {
+        init_sodium().unwrap();
+        let (public_key, secret_key) = box_::gen_keypair();
+        let derived_key = SecretBytes::new(vec![0x42; 32]);
+
+        let encoded = encrypt_key_cose(&derived_key, b"key id", &public_key).unwrap();
+        let encrypted = CoseEncrypt0::from_slice(&encoded).unwrap();
+
+        assert_eq!(
+            encrypted.protected.header.alg,
+            Some(RegisteredLabelWithPrivate::Assigned(
+                iana::Algorithm::ChaCha20Poly1305
+            ))
+        );
+        assert_eq!(encrypted.unprotected.key_id, b"key id");
+        assert_eq!(encrypted.unprotected.iv.len(), AEAD_NONCE_LEN);
+        assert_eq!(
+            decrypt(&encrypted, &public_key, &secret_key).unwrap(),
+            derived_key.expose()
+        );
+        let (other_public, other_secret) = box_::gen_keypair();
+        assert!(decrypt(&encrypted, &other_public, &other_secret).is_none());
+    }
+
+    #[test]
+    fn tampered_encrypt0_does_not_decrypt() {
+        init_sodium().unwrap();
+        let (public_key, secret_key) = box_::gen_keypair();
+        let derived_key = SecretBytes::new(vec![0x42; 32]);
+        let encoded = encrypt_key_cose(&derived_key, b"key id", &public_key).unwrap();
+        let tampered = |tamper: fn(&mut CoseEncrypt0)| {
+            let mut encrypted = CoseEncrypt0::from_slice(&encoded).unwrap();
+            tamper(&mut encrypted);
+            // Through the wire format, as a recipient would get it
+            let encrypted = CoseEncrypt0::from_slice(&encrypted.to_vec().unwrap()).unwrap();
+            decrypt(&encrypted, &public_key, &secret_key)
+        };
+
+        assert!(tampered(|_| {}).is_some());
+        assert!(tampered(|encrypted| encrypted.ciphertext.as_mut().unwrap()[0] ^= 1).is_none());
+        assert!(tampered(|encrypted| encrypted.unprotected.iv[0] ^= 1).is_none());
+        // The protected header is authenticated
+        assert!(tampered(|encrypted| {
+            encrypted.protected.original_data = None;
+            encrypted.protected.header.content_type =
+                Some(coset::ContentType::Text("application/octet-stream".into()));
+        })
+        .is_none());
+    }
+}
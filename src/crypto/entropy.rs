@@ -0,0 +1,247 @@
+use super::backend::{hkdf_expand, hkdf_extract};
+use crate::error::ProviderError;
+use log::{info, warn};
+use rand::{CryptoRng, RngCore};
+use sodiumoxide::crypto::box_::{self, PublicKey, SecretKey};
+use sodiumoxide::randombytes::randombytes_into;
+use zeroize::Zeroizing;
+
+const MIX_LABEL: &[u8] = b"gramine-sealing-key-provider/entropy/v1";
+const SOURCE_LEN: usize = 32;
+// Intel recommends 10 RDRAND retries; RDSEED underflows more readily
+const RDRAND_RETRIES: usize = 10;
+const RDSEED_RETRIES: usize = 100;
+const SELF_TEST_WORDS: usize = 64;
+/// Whether the CPU may have RDSEED/RDRAND at all.
+const HARDWARE_RNG: bool = cfg!(target_arch = "x86_64");
+
+/// Fills `out` from both libsodium's RNG (which ultimately depends on what the
+/// host provides) and the CPU's RDSEED/RDRAND, combined with HKDF. The output
+/// is unpredictable as long as either source is. Off x86_64 there is no
+/// hardware source, and libsodium's RNG is mixed in alone.
+pub fn fill(out: &mut [u8]) -> Result<(), ProviderError> {
+    fill_with(out, hardware_word)
+}
+
+/// [`fill`] with `hardware` drawing the hardware source's words.
+fn fill_with(
+    out: &mut [u8],
+    mut hardware: impl FnMut() -> Option<u64>,
+) -> Result<(), ProviderError> {
+    let mut input = Zeroizing::new([0u8; 2 * SOURCE_LEN]);
+    randombytes_into(&mut input[..SOURCE_LEN]);
+    if HARDWARE_RNG {
+        for chunk in input[SOURCE_LEN..].chunks_mut(8) {
+            let word = hardware().ok_or_else(|| {
+                ProviderError::CryptoError("Hardware random number generator failed".into())
+            })?;
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    let prk = Zeroizing::new(hkdf_extract(MIX_LABEL, &input[..]));
+    hkdf_expand(&prk[..], &[], out)
+}
+
+/// Ephemeral X25519 keypair seeded from the mixed RNG.
+pub fn gen_keypair() -> Result<(PublicKey, SecretKey), ProviderError> {
+    let mut seed = Zeroizing::new([0u8; box_::SEEDBYTES]);
+    fill(&mut seed[..])?;
+    Ok(box_::keypair_from_seed(&box_::Seed(*seed)))
+}
+
+/// Startup health check of the hardware source: it must be present, and a run
+/// of outputs must contain no stuck values (all-zero/all-one words are already
+/// rejected per draw) or consecutive repeats.
+pub fn check_hardware_entropy() -> Result<(), ProviderError> {
+    if !HARDWARE_RNG {
+        warn!("No hardware random number generator on this architecture; using the OS RNG alone");
+        return Ok(());
+    }
+
+    check_words(hardware_word)?;
+    let mut probe = [0u8; SOURCE_LEN];
+    fill(&mut probe)?;
+
+    info!("Hardware entropy source passed startup health checks");
+    Ok(())
+}
+
+/// The run of outputs [`check_hardware_entropy`] looks at, drawn from
+/// `hardware`.
+fn check_words(mut hardware: impl FnMut() -> Option<u64>) -> Result<(), ProviderError> {
+    let mut previous = None;
+    for _ in 0..SELF_TEST_WORDS {
+        let word = hardware().ok_or_else(|| {
+            ProviderError::CryptoError("Hardware random number generator unavailable".into())
+        })?;
+        if previous == Some(word) {
+            return Err(ProviderError::CryptoError(
+                "Hardware random number generator repeated its output".into(),
+            ));
+        }
+        previous = Some(word);
+    }
+    Ok(())
+}
+
+/// `rand` adapter over `fill`, for crates that take an RNG (RSA-OAEP padding).
+/// `RngCore::fill_bytes` cannot fail, so a failure of `fill` is kept for
+/// [`MixedRng::finish`], and the output until then comes from libsodium's
+/// RNG alone. Callers discard what they made with it if `finish` fails.
+pub struct MixedRng {
+    failure: Option<ProviderError>,
+    hardware: fn() -> Option<u64>,
+}
+
+impl Default for MixedRng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MixedRng {
+    pub fn new() -> Self {
+        Self {
+            failure: None,
+            hardware: hardware_word,
+        }
+    }
+
+    /// The first failure to draw from the mixed RNG, if there was one.
+    pub fn finish(self) -> Result<(), ProviderError> {
+        self.failure.map_or(Ok(()), Err)
+    }
+}
+
+impl RngCore for MixedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if let Err(e) = fill_with(dest, self.hardware) {
+            randombytes_into(dest);
+            self.failure.get_or_insert(e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        fill_with(dest, self.hardware).map_err(rand::Error::new)
+    }
+}
+
+impl CryptoRng for MixedRng {}
+
+/// One 64-bit word from RDSEED, falling back to RDRAND. Values that a failed
+/// or stuck generator typically returns are treated as failures.
+#[cfg(target_arch = "x86_64")]
+fn hardware_word() -> Option<u64> {
+    use std::arch::x86_64::{_rdrand64_step, _rdseed64_step};
+
+    let plausible = |word: u64| word != 0 && word != u64::MAX;
+
+    if is_x86_feature_detected!("rdseed") {
+        for _ in 0..RDSEED_RETRIES {
+            let mut word = 0u64;
+            // SAFETY: RDSEED support was detected above
+            if unsafe { _rdseed64_step(&mut word) } == 1 && plausible(word) {
+                return Some(word);
+            }
+            std::hint::spin_loop();
+        }
+    }
+
+    if is_x86_feature_detected!("rdrand") {
+        for _ in 0..RDRAND_RETRIES {
+            let mut word = 0u64;
+            // SAFETY: RDRAND support was detected above
+            if unsafe { _rdrand64_step(&mut word) } == 1 && plausible(word) {
+                return Some(word);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn hardware_word() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A hardware source that works, whatever the CPU has.
+    fn working() -> Option<u64> {
+        Some(rand::random::<u64>() | 1)
+    }
+
+    fn failing() -> Option<u64> {
+        None
+    }
+
+    #[test]
+    fn fills_differ_and_need_the_hardware_source() {
+        sodiumoxide::init().unwrap();
+        let mut first = [0u8; 100];
+        let mut second = [0u8; 100];
+        fill_with(&mut first, working).unwrap();
+        fill_with(&mut second, working).unwrap();
+
+        assert_ne!(first, second);
+        assert_ne!(first, [0u8; 100]);
+        // Off x86_64 the hardware source is never asked
+        assert_eq!(fill_with(&mut first, failing).is_err(), HARDWARE_RNG);
+    }
+
+    #[test]
+    fn mixed_rng_keeps_its_first_failure() {
+        sodiumoxide::init().unwrap();
+        let mut rng = MixedRng {
+            failure: None,
+            hardware: working,
+        };
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        assert!(rng.try_fill_bytes(&mut bytes).is_ok());
+        assert!(rng.finish().is_ok());
+
+        if !HARDWARE_RNG {
+            return;
+        }
+        let mut rng = MixedRng {
+            failure: None,
+            hardware: failing,
+        };
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        // Still filled, from libsodium's RNG
+        assert_ne!(bytes, [0u8; 32]);
+        assert!(rng.try_fill_bytes(&mut bytes).is_err());
+        assert!(matches!(rng.finish(), Err(ProviderError::CryptoError(_))));
+    }
+
+    #[test]
+    fn absent_or_stuck_hardware_sources_fail_the_health_check() {
+        let mut word = 0u64;
+        let counting = || {
+            word += 1;
+            Some(word)
+        };
+
+        assert!(check_words(counting).is_ok());
+        assert!(check_words(failing).is_err());
+        assert!(check_words(|| Some(0x5a5a_5a5a_5a5a_5a5a)).is_err());
+    }
+}
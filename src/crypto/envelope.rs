@@ -0,0 +1,165 @@
+use super::backend::backend;
+use super::secret::SecretBytes;
+use log::debug;
+
+pub const ENVELOPE_VERSION: u8 = 1;
+pub const KEY_ID_LEN: usize = 16;
+
+const KEY_ID_LABEL: &[u8] = b"gramine-sealing-key-provider/key-id/v1";
+
+/// Encryption scheme used to protect the derived key. The numeric value is the
+/// identifier carried in the envelope and must never be reassigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum Suite {
+    X25519SealedBox = 1,
+    RsaOaepSha256 = 2,
+    CoseEncrypt0 = 3,
+    JweEcdhEsA256Gcm = 4,
+    X25519SecretStream = 5,
+    Tpm2Import = 6,
+}
+
+impl Suite {
+    /// Provider preference for negotiation, strongest first. Authenticated
+    /// standard containers that name the key rank above the bare sealed box.
+    pub const PREFERENCE: [Suite; 6] = [
+        Suite::CoseEncrypt0,
+        Suite::JweEcdhEsA256Gcm,
+        Suite::X25519SecretStream,
+        Suite::X25519SealedBox,
+        Suite::RsaOaepSha256,
+        Suite::Tpm2Import,
+    ];
+
+    pub fn id(self) -> u16 {
+        self as u16
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Suite::X25519SealedBox => "x25519-sealedbox",
+            Suite::RsaOaepSha256 => "rsa-oaep-sha256",
+            Suite::CoseEncrypt0 => "cose-encrypt0",
+            Suite::JweEcdhEsA256Gcm => "jwe-ecdh-es-a256gcm",
+            Suite::X25519SecretStream => "x25519-secretstream",
+            Suite::Tpm2Import => "tpm2-import",
+        }
+    }
+
+    /// Whether the whole suite, key transport or agreement included, runs
+    /// approved algorithms inside the crypto backend. Only such suites are
+    /// offered with a FIPS backend. RSA-OAEP qualifies, as the backend runs
+    /// it; the JWE and COSE suites agree their keys with X25519, the sealed
+    /// box and secretstream use XSalsa20/XChaCha20, and the TPM2 import
+    /// wrapper does its AES-CFB outside the backend.
+    pub fn is_fips_approved(self) -> bool {
+        match self {
+            Suite::RsaOaepSha256 => true,
+            Suite::X25519SealedBox
+            | Suite::CoseEncrypt0
+            | Suite::JweEcdhEsA256Gcm
+            | Suite::X25519SecretStream
+            | Suite::Tpm2Import => false,
+        }
+    }
+}
+
+/// Picks the strongest suite that the client offered (by name) and that is
+/// usable for the recipient (`usable`). Unknown names are ignored so clients
+/// can list suites newer than this provider.
+pub fn negotiate_suite(offered: &[String], usable: &[Suite]) -> Option<Suite> {
+    Suite::PREFERENCE
+        .into_iter()
+        .find(|suite| usable.contains(suite) && offered.iter().any(|name| name == suite.name()))
+}
+
+/// Self-describing wrapper around an encrypted key.
+///
+/// Wire layout (all integers big-endian):
+/// `version: u8 | suite: u16 | key_id_len: u8 | key_id | ciphertext_len: u32 | ciphertext`
+#[derive(Debug)]
+pub struct KeyEnvelope {
+    pub suite: Suite,
+    pub key_id: [u8; KEY_ID_LEN],
+    pub ciphertext: Vec<u8>,
+}
+
+impl KeyEnvelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(8 + KEY_ID_LEN + self.ciphertext.len());
+        out.push(ENVELOPE_VERSION);
+        out.extend_from_slice(&self.suite.id().to_be_bytes());
+        out.push(KEY_ID_LEN as u8);
+        out.extend_from_slice(&self.key_id);
+        out.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.ciphertext);
+
+        debug!(
+            "Encoded key envelope v{} ({}), {} bytes",
+            ENVELOPE_VERSION,
+            self.suite.name(),
+            out.len()
+        );
+        out
+    }
+}
+
+/// Non-secret identifier of a derived key, so clients can tell which key a
+/// ciphertext carries without decrypting it.
+pub fn compute_key_id(derived_key: &SecretBytes) -> [u8; KEY_ID_LEN] {
+    let digest = backend().sha256(&[KEY_ID_LABEL, derived_key.expose()]);
+
+    let mut key_id = [0u8; KEY_ID_LEN];
+    key_id.copy_from_slice(&digest[..KEY_ID_LEN]);
+    key_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_layout() {
+        let envelope = KeyEnvelope {
+            suite: Suite::RsaOaepSha256,
+            key_id: [7u8; KEY_ID_LEN],
+            ciphertext: vec![1, 2, 3],
+        };
+        let encoded = envelope.encode();
+
+        assert_eq!(encoded[0], ENVELOPE_VERSION);
+        assert_eq!(&encoded[1..3], &[0, 2]);
+        assert_eq!(encoded[3] as usize, KEY_ID_LEN);
+        assert_eq!(&encoded[4..4 + KEY_ID_LEN], &[7u8; KEY_ID_LEN]);
+        assert_eq!(&encoded[4 + KEY_ID_LEN..8 + KEY_ID_LEN], &[0, 0, 0, 3]);
+        assert_eq!(&encoded[8 + KEY_ID_LEN..], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn negotiation_prefers_strongest_mutual_suite() {
+        let offered = vec![
+            "x25519-sealedbox".to_string(),
+            "hpke-from-the-future".to_string(),
+            "jwe-ecdh-es-a256gcm".to_string(),
+        ];
+        let usable = [
+            Suite::X25519SealedBox,
+            Suite::CoseEncrypt0,
+            Suite::JweEcdhEsA256Gcm,
+        ];
+        assert_eq!(
+            negotiate_suite(&offered, &usable),
+            Some(Suite::JweEcdhEsA256Gcm)
+        );
+        assert_eq!(negotiate_suite(&offered, &[Suite::RsaOaepSha256]), None);
+    }
+
+    #[test]
+    fn key_id_is_deterministic_and_key_specific() {
+        let one = SecretBytes::new(vec![1u8; 32]);
+        let two = SecretBytes::new(vec![2u8; 32]);
+        assert_eq!(compute_key_id(&one), compute_key_id(&one));
+        assert_ne!(compute_key_id(&one), compute_key_id(&two));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::ProviderError;
+use std::fmt;
+use std::ptr::NonNull;
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+/// Address and length of every live guarded key, for [`wipe_guarded_keys`].
+static LIVE: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+/// Secret bytes held in libsodium's guarded heap: the pages are mlock'ed,
+/// surrounded by guard pages, kept PROT_NONE while not in use and wiped on
+/// free. Used for the sealing root so it never sits in ordinary heap memory.
+pub struct GuardedKey {
+    ptr: NonNull<u8>,
+    len: usize,
+    // Protection is toggled per access; serialize accesses so one caller
+    // cannot revoke access while another is still reading.
+    access: Mutex<()>,
+}
+
+// The allocation is owned exclusively and every access goes through `access`.
+unsafe impl Send for GuardedKey {}
+unsafe impl Sync for GuardedKey {}
+
+impl GuardedKey {
+    pub fn from_slice(data: &[u8]) -> Result<Self, ProviderError> {
+        if data.is_empty() {
+            return Err(ProviderError::CryptoError(
+                "Refusing to allocate an empty guarded key".into(),
+            ));
+        }
+
+        let raw = unsafe { libsodium_sys::sodium_malloc(data.len()) } as *mut u8;
+        let ptr = NonNull::new(raw).ok_or_else(|| {
+            ProviderError::CryptoError("Failed to allocate guarded memory".into())
+        })?;
+
+        let result = unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.as_ptr(), data.len());
+            libsodium_sys::sodium_mprotect_noaccess(ptr.as_ptr() as *mut _)
+        };
+        if result != 0 {
+            unsafe { libsodium_sys::sodium_free(ptr.as_ptr() as *mut _) }
+            return Err(ProviderError::CryptoError(
+                "Failed to protect guarded memory".into(),
+            ));
+        }
+
+        live().push((ptr.as_ptr() as usize, data.len()));
+        Ok(Self {
+            ptr,
+            len: data.len(),
+            access: Mutex::new(()),
+        })
+    }
+
+    /// Makes the key readable for the duration of `f` only.
+    pub fn with_bytes<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        let _guard = self
+            .access
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let _readable = ReadableWindow::open(self.ptr);
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) };
+        f(bytes)
+    }
+}
+
+// Restores PROT_NONE even if the closure passed to `with_bytes` panics.
+// Either change failing panics: the key could not be read, or would be left
+// readable.
+struct ReadableWindow(NonNull<u8>);
+
+impl ReadableWindow {
+    fn open(ptr: NonNull<u8>) -> Self {
+        let result = unsafe { libsodium_sys::sodium_mprotect_readonly(ptr.as_ptr() as *mut _) };
+        assert_eq!(result, 0, "Failed to make a guarded key readable");
+        Self(ptr)
+    }
+}
+
+impl Drop for ReadableWindow {
+    fn drop(&mut self) {
+        let result = unsafe { libsodium_sys::sodium_mprotect_noaccess(self.0.as_ptr() as *mut _) };
+        assert_eq!(result, 0, "Failed to make a guarded key inaccessible again");
+    }
+}
+
+impl fmt::Debug for GuardedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("GuardedKey([REDACTED])")
+    }
+}
+
+impl Drop for GuardedKey {
+    fn drop(&mut self) {
+        let addr = self.ptr.as_ptr() as usize;
+        live().retain(|&(live, _)| live != addr);
+        // sodium_free wipes, munlocks and unmaps; it handles PROT_NONE pages.
+        unsafe { libsodium_sys::sodium_free(self.ptr.as_ptr() as *mut _) }
+    }
+}
+
+/// Overwrites every live guarded key with zeros, for a process that is
+/// about to abort. The keys are unusable afterwards. Best effort: if the
+/// registry is locked by the panicking thread, nothing is wiped.
+pub fn wipe_guarded_keys() -> usize {
+    let live = match LIVE.try_lock() {
+        Ok(live) => live,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => return 0,
+    };
+    live.iter().filter(|&&(addr, len)| wipe(addr, len)).count()
+}
+
+/// Zeroes the guarded key at `addr`, unless it cannot be made writable.
+fn wipe(addr: usize, len: usize) -> bool {
+    let ptr = addr as *mut u8;
+    unsafe {
+        if libsodium_sys::sodium_mprotect_readwrite(ptr as *mut _) != 0 {
+            return false;
+        }
+        libsodium_sys::sodium_memzero(ptr as *mut _, len);
+        libsodium_sys::sodium_mprotect_noaccess(ptr as *mut _);
+    }
+    true
+}
+
+fn live() -> MutexGuard<'static, Vec<(usize, usize)>> {
+    LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_live(addr: usize) -> bool {
+        live().iter().any(|&(live, _)| live == addr)
+    }
+
+    #[test]
+    fn keys_read_back_as_stored() {
+        sodiumoxide::init().unwrap();
+        let key = GuardedKey::from_slice(b"0123456789abcdef").unwrap();
+
+        let copied = key.with_bytes(|bytes| bytes.to_vec());
+        let again = key.with_bytes(|bytes| bytes.to_vec());
+
+        assert_eq!(copied, b"0123456789abcdef");
+        assert_eq!(again, copied);
+        assert!(GuardedKey::from_slice(&[]).is_err());
+    }
+
+    #[test]
+    fn wiped_keys_read_as_zeros_and_dropped_keys_are_forgotten() {
+        sodiumoxide::init().unwrap();
+        let key = GuardedKey::from_slice(&[0xa5; 32]).unwrap();
+        let addr = key.ptr.as_ptr() as usize;
+        assert!(is_live(addr));
+
+        // Only this key: wiping every live one would break other tests
+        assert!(wipe(addr, key.len));
+        key.with_bytes(|bytes| assert_eq!(bytes, [0u8; 32]));
+
+        drop(key);
+        assert!(!is_live(addr));
+    }
+}
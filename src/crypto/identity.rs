@@ -0,0 +1,51 @@
+use super::master::MasterSecret;
+use crate::error::ProviderError;
+use log::info;
+use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Seed};
+
+const IDENTITY_LABEL: &[u8] = b"gramine-sealing-key-provider/identity/v1";
+
+/// Ed25519 signing identity used for transcript signatures. Implementations
+/// may keep the private key outside the enclave (see `Pkcs11Signer`), but the
+/// public key is always bound into the provider quote when it is used.
+pub trait Signer: Send + Sync {
+    fn public_key(&self) -> &[u8];
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, ProviderError>;
+}
+
+/// Ed25519 signing identity of this provider instance, derived from the master
+/// secret so it is stable across restarts of the same enclave on the same
+/// platform. Clients learn and trust the public key through the provider
+/// quote, which binds it whenever it is used.
+pub struct ProviderIdentity {
+    public_key: PublicKey,
+    secret_key: SecretKey,
+}
+
+impl ProviderIdentity {
+    pub fn from_master(master: &MasterSecret) -> Result<Self, ProviderError> {
+        let seed = master.expand(IDENTITY_LABEL)?;
+        let seed = Seed::from_slice(seed.expose())
+            .ok_or_else(|| ProviderError::CryptoError("Invalid identity seed length".into()))?;
+        let (public_key, secret_key) = sign::keypair_from_seed(&seed);
+
+        info!("Provider identity key: {}", hex::encode(public_key.0));
+        Ok(Self {
+            public_key,
+            secret_key,
+        })
+    }
+}
+
+impl Signer for ProviderIdentity {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key.0
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        Ok(sign::sign_detached(message, &self.secret_key)
+            .as_ref()
+            .to_vec())
+    }
+}
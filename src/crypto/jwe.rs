@@ -0,0 +1,151 @@
+use super::backend::{backend, AEAD_NONCE_LEN};
+use super::entropy::{self, MixedRng};
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use log::{debug, info};
+use rsa::{Oaep, RsaPublicKey};
+use serde::Serialize;
+use sha2::Sha256;
+use sodiumoxide::crypto::box_::{self, PublicKey};
+use sodiumoxide::crypto::scalarmult::curve25519::{scalarmult, GroupElement, Scalar};
+use zeroize::Zeroizing;
+
+const CONTENT_ENCRYPTION: &str = "A256GCM";
+const CEK_BITS: u32 = 256;
+const TAG_LEN: usize = 16;
+
+/// Encrypts the derived key as a compact JWE (RFC 7516) using direct key
+/// agreement: `alg` is ECDH-ES with an ephemeral X25519 key (RFC 8037) and
+/// `enc` is A256GCM. The derived key id is carried in `kid`.
+pub fn encrypt_key_jwe(
+    derived_key: &SecretBytes,
+    key_id: &[u8],
+    public_key: &PublicKey,
+) -> Result<Vec<u8>, ProviderError> {
+    info!("Encrypting derived key as compact JWE");
+
+    let (ephemeral_public, ephemeral_secret) = entropy::gen_keypair()?;
+    let shared = scalarmult(&Scalar(ephemeral_secret.0), &GroupElement(public_key.0))
+        .map_err(|_| ProviderError::CryptoError("X25519 produced a low-order point".into()))?;
+    let shared = Zeroizing::new(shared.0);
+    let content_key = concat_kdf(&shared[..]);
+
+    let header = serde_json::json!({
+        "alg": "ECDH-ES",
+        "enc": CONTENT_ENCRYPTION,
+        "kid": URL_SAFE_NO_PAD.encode(key_id),
+        "epk": {
+            "kty": "OKP",
+            "crv": "X25519",
+            "x": URL_SAFE_NO_PAD.encode(ephemeral_public.0),
+        },
+    });
+    let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+
+    let mut iv = [0u8; AEAD_NONCE_LEN];
+    entropy::fill(&mut iv)?;
+
+    let mut ciphertext = derived_key.expose().to_vec();
+    backend().aes256gcm_seal(&content_key, &iv, protected.as_bytes(), &mut ciphertext)?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LEN);
+
+    // Direct key agreement: the JWE Encrypted Key part is empty
+    let compact = format!(
+        "{}..{}.{}.{}",
+        protected,
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(ciphertext),
+        URL_SAFE_NO_PAD.encode(tag)
+    );
+
+    debug!("Compact JWE length: {} bytes", compact.len());
+    Ok(compact.into_bytes())
+}
+
+/// A JWE in the flattened JSON serialization (RFC 7516 §7.2.2), without the
+/// unprotected headers.
+#[derive(Debug, Serialize)]
+pub struct FlattenedJwe {
+    pub protected: String,
+    pub encrypted_key: String,
+    pub iv: String,
+    pub ciphertext: String,
+    pub tag: String,
+}
+
+/// Encrypts `plaintext` with a fresh A256GCM content key wrapped for an RSA
+/// recipient. `alg` is recorded in the header as given; the content key is
+/// always wrapped with OAEP over SHA-256, which is what the confidential
+/// containers guest components expect for both `RSA-OAEP` and
+/// `RSA-OAEP-256`.
+pub fn encrypt_jwe_rsa(
+    plaintext: &[u8],
+    alg: &str,
+    public_key: &RsaPublicKey,
+) -> Result<FlattenedJwe, ProviderError> {
+    info!("Encrypting resource as flattened JWE ({})", alg);
+
+    let mut content_key = Zeroizing::new([0u8; 32]);
+    entropy::fill(&mut content_key[..])?;
+    let mut rng = MixedRng::new();
+    let encrypted_key = public_key
+        .encrypt(&mut rng, Oaep::new::<Sha256>(), &content_key[..])
+        .map_err(|e| ProviderError::CryptoError(format!("RSA-OAEP encryption failed: {}", e)))?;
+    rng.finish()?;
+
+    let header = serde_json::json!({ "alg": alg, "enc": CONTENT_ENCRYPTION });
+    let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?);
+
+    let mut iv = [0u8; AEAD_NONCE_LEN];
+    entropy::fill(&mut iv)?;
+
+    let mut ciphertext = plaintext.to_vec();
+    backend().aes256gcm_seal(&content_key, &iv, protected.as_bytes(), &mut ciphertext)?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LEN);
+
+    Ok(FlattenedJwe {
+        protected,
+        encrypted_key: URL_SAFE_NO_PAD.encode(encrypted_key),
+        iv: URL_SAFE_NO_PAD.encode(iv),
+        ciphertext: URL_SAFE_NO_PAD.encode(ciphertext),
+        tag: URL_SAFE_NO_PAD.encode(tag),
+    })
+}
+
+/// Single-round Concat KDF (NIST SP 800-56A) as profiled by RFC 7518 §4.6.2,
+/// with empty PartyUInfo/PartyVInfo.
+fn concat_kdf(shared_secret: &[u8]) -> Zeroizing<[u8; 32]> {
+    Zeroizing::new(backend().sha256(&[
+        &1u32.to_be_bytes(),
+        shared_secret,
+        &(CONTENT_ENCRYPTION.len() as u32).to_be_bytes(),
+        CONTENT_ENCRYPTION.as_bytes(),
+        &0u32.to_be_bytes(),
+        &0u32.to_be_bytes(),
+        &CEK_BITS.to_be_bytes(),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    // RFC 7518 Appendix C uses P-256 with apu/apv; with empty party info the
+    // OtherInfo is fixed, so check the layout against a hand-built input.
+    #[test]
+    fn concat_kdf_layout() {
+        let z = [0x42u8; 32];
+        let mut input = Vec::new();
+        input.extend_from_slice(&[0, 0, 0, 1]);
+        input.extend_from_slice(&z);
+        input.extend_from_slice(&[0, 0, 0, 7]);
+        input.extend_from_slice(b"A256GCM");
+        input.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        input.extend_from_slice(&[0, 0, 1, 0]);
+
+        assert_eq!(&concat_kdf(&z)[..], Sha256::digest(input).as_slice());
+    }
+}
@@ -0,0 +1,80 @@
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+
+// struct fscrypt_provisioning_key_payload (include/uapi/linux/fscrypt.h)
+const FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER: u32 = 2;
+
+/// Payload layouts the guest kernel keyring accepts as-is, so the decrypted
+/// key can go straight into `add_key(2)` / `keyctl padd`. `trusted` keys are
+/// not among them: their blobs are sealed by the guest's TPM over material
+/// the kernel generates, which nothing outside the guest can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelKeyFormat {
+    /// Raw key bytes, for `user` and `logon` keys (e.g. dm-crypt's
+    /// `:32:logon:<desc>` key references).
+    User,
+    /// `fscrypt-provisioning` key for fscrypt v2 policies: a little-endian
+    /// `type: u32 | reserved: u32` header followed by the raw key.
+    FscryptProvisioning,
+}
+
+impl KernelKeyFormat {
+    pub fn from_name(name: &str) -> Result<Self, ProviderError> {
+        match name {
+            "user" => Ok(Self::User),
+            "fscrypt-provisioning" => Ok(Self::FscryptProvisioning),
+            "trusted" => Err(ProviderError::SerializationError(
+                "Kernel trusted keys are sealed by the guest's TPM; load the key as an \
+                 encrypted key under a trusted master key instead"
+                    .into(),
+            )),
+            other => Err(ProviderError::SerializationError(format!(
+                "Unknown kernel key format: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::FscryptProvisioning => "fscrypt-provisioning",
+        }
+    }
+
+    pub fn encode(self, key: &SecretBytes) -> SecretBytes {
+        match self {
+            Self::User => SecretBytes::new(key.expose().to_vec()),
+            Self::FscryptProvisioning => {
+                let mut payload = Vec::with_capacity(8 + key.expose().len());
+                payload.extend_from_slice(&FSCRYPT_KEY_SPEC_TYPE_IDENTIFIER.to_le_bytes());
+                payload.extend_from_slice(&0u32.to_le_bytes());
+                payload.extend_from_slice(key.expose());
+                SecretBytes::new(payload)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fscrypt_payload_has_identifier_header() {
+        let key = SecretBytes::new(vec![0xaa; 32]);
+        let payload = KernelKeyFormat::FscryptProvisioning.encode(&key);
+
+        assert_eq!(&payload.expose()[..8], &[2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&payload.expose()[8..], key.expose());
+    }
+
+    #[test]
+    fn formats_are_named_and_trusted_keys_are_refused() {
+        for format in [KernelKeyFormat::User, KernelKeyFormat::FscryptProvisioning] {
+            assert_eq!(KernelKeyFormat::from_name(format.name()).unwrap(), format);
+        }
+        assert!(KernelKeyFormat::from_name("trusted").is_err());
+        assert!(KernelKeyFormat::from_name("encrypted").is_err());
+    }
+}
@@ -1,26 +1,30 @@
+use super::backend::backend;
+use super::entropy;
+use super::secret::{Redacted, SecretBytes};
 use crate::error::ProviderError;
 use log::{debug, info};
-use sha2::{Digest, Sha256};
-use sodiumoxide::crypto::sealedbox;
 use sodiumoxide::crypto::box_::{self, PublicKey};
+use sodiumoxide::crypto::generichash;
 
 // Initialize sodium at program start
 pub fn init_sodium() -> Result<(), ProviderError> {
     sodiumoxide::init().map_err(|_| ProviderError::CryptoError("Failed to initialize sodium".into()))
 }
 
-pub fn derive_key(sealing_key: &[u8], measurements: &[u8]) -> Vec<u8> {
-    info!("Deriving key from measurements");
-    debug!("Sealing key length: {} bytes", sealing_key.len());
-    debug!("Measurements length: {} bytes", measurements.len());
+/// Compares two byte strings in constant time (for equal lengths), so the host
+/// cannot learn how many leading bytes matched from response timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    sodiumoxide::utils::memcmp(a, b)
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(sealing_key);
-    hasher.update(measurements);
-    let derived = hasher.finalize().to_vec();
+const KEY_CONFIRMATION_LABEL: &[u8] = b"gramine-sealing-key-provider/key-confirmation/v1";
 
-    debug!("Derived key length: {} bytes", derived.len());
-    derived
+/// HMAC-SHA256 over a fixed label under the derived key. Returned in the clear
+/// so a client can check it decrypted the right key before using it.
+pub fn compute_key_confirmation(derived_key: &SecretBytes) -> Vec<u8> {
+    backend()
+        .hmac_sha256(derived_key.expose(), &[KEY_CONFIRMATION_LABEL])
+        .to_vec()
 }
 
 pub fn extract_public_key(report_data: &[u8]) -> Result<PublicKey, ProviderError> {
@@ -36,14 +40,61 @@ pub fn extract_public_key(report_data: &[u8]) -> Result<PublicKey, ProviderError
         .ok_or_else(|| ProviderError::PublicKeyError("Invalid public key format".into()))
 }
 
-pub fn encrypt_key(derived_key: &[u8], public_key: &PublicKey) -> Result<Vec<u8>, ProviderError> {
+pub fn encrypt_key(
+    derived_key: &SecretBytes,
+    public_key: &PublicKey,
+) -> Result<Vec<u8>, ProviderError> {
     info!("Encrypting derived key using sealed box");
-    debug!("Input key length: {} bytes", derived_key.len());
-    
-    let encrypted = sealedbox::seal(derived_key, public_key);
+
+    let encrypted = seal_box(derived_key.expose(), public_key)?;
 
     debug!("Encrypted data length: {} bytes", encrypted.len());
-    debug!("Encrypted data (hex): {}", hex::encode(&encrypted));
+    debug!("Encrypted data: {}", Redacted(&encrypted));
     
     Ok(encrypted)
 }
+
+/// Byte-compatible with libsodium's `crypto_box_seal` (`ephemeral_pk ||
+/// crypto_box(m, nonce = BLAKE2b-192(ephemeral_pk || pk))`), but the
+/// ephemeral key comes from the mixed entropy source.
+pub(super) fn seal_box(plaintext: &[u8], public_key: &PublicKey) -> Result<Vec<u8>, ProviderError> {
+    let (ephemeral_public, ephemeral_secret) = entropy::gen_keypair()?;
+
+    let hash_error = || ProviderError::CryptoError("Sealed box nonce derivation failed".into());
+    let mut state =
+        generichash::State::new(Some(box_::NONCEBYTES), None).map_err(|_| hash_error())?;
+    state
+        .update(&ephemeral_public.0)
+        .map_err(|_| hash_error())?;
+    state.update(&public_key.0).map_err(|_| hash_error())?;
+    let digest = state.finalize().map_err(|_| hash_error())?;
+    let nonce = box_::Nonce::from_slice(digest.as_ref()).ok_or_else(hash_error)?;
+
+    let mut sealed = ephemeral_public.0.to_vec();
+    sealed.extend_from_slice(&box_::seal(
+        plaintext,
+        &nonce,
+        public_key,
+        &ephemeral_secret,
+    ));
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sodiumoxide::crypto::sealedbox;
+
+    #[test]
+    fn seal_box_opens_with_libsodium() {
+        init_sodium().unwrap();
+        let (public_key, secret_key) = box_::gen_keypair();
+
+        let sealed = seal_box(b"derived key", &public_key).unwrap();
+        assert_eq!(sealed.len(), b"derived key".len() + sealedbox::SEALBYTES);
+        assert_eq!(
+            sealedbox::open(&sealed, &public_key, &secret_key).unwrap(),
+            b"derived key"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use super::backend::{hkdf_expand, hkdf_extract};
+use super::guarded::GuardedKey;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+const MASTER_SALT: &[u8] = b"gramine-sealing-key-provider/master/v2";
+const DERIVE_LABEL: &[u8] = b"gramine-sealing-key-provider/derive/v2";
+const TENANT_ROOT_LABEL: &[u8] = b"gramine-sealing-key-provider/tenant-root/v1";
+const DERIVED_KEY_LEN: usize = 32;
+
+/// How workload keys are derived. Keys already released must stay the same,
+/// so the original derivation stays the default and HKDF is opted into per
+/// policy.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum KdfVersion {
+    /// `SHA-256(sealing key || measurements)`.
+    #[default]
+    V1,
+    /// `HKDF-Expand(master, DERIVE_LABEL || measurements, 32)`.
+    V2,
+}
+
+impl TryFrom<u8> for KdfVersion {
+    type Error = String;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            other => Err(format!("unknown kdf_version {}, expected 1 or 2", other)),
+        }
+    }
+}
+
+impl From<KdfVersion> for u8 {
+    fn from(version: KdfVersion) -> Self {
+        match version {
+            KdfVersion::V1 => 1,
+            KdfVersion::V2 => 2,
+        }
+    }
+}
+
+/// HKDF pseudorandom key extracted from the SGX sealing key at startup, and
+/// the sealing key itself for [`KdfVersion::V1`]. Both are held in guarded
+/// memory only; every per-request key is derived from here.
+pub struct MasterSecret {
+    prk: GuardedKey,
+    sealing_key: GuardedKey,
+    partitioned: bool,
+}
+
+impl MasterSecret {
+    /// `partition` (e.g. KSS identity fields) is appended to the salt, so each
+    /// partition gets an unrelated master secret from the same sealing key.
+    pub fn from_sealing_key(
+        sealing_key: &[u8],
+        partition: Option<&[u8]>,
+    ) -> Result<Self, ProviderError> {
+        info!("Deriving master secret from sealing key");
+        let mut salt = MASTER_SALT.to_vec();
+        if let Some(partition) = partition {
+            salt.extend_from_slice(partition);
+        }
+        let mut prk = hkdf_extract(&salt, sealing_key);
+        let guarded = GuardedKey::from_slice(&prk);
+        prk.zeroize();
+
+        Ok(Self {
+            prk: guarded?,
+            sealing_key: GuardedKey::from_slice(sealing_key)?,
+            partitioned: partition.is_some(),
+        })
+    }
+
+    /// Whether the master secret was derived with a partition.
+    pub fn is_partitioned(&self) -> bool {
+        self.partitioned
+    }
+
+    /// Derives the per-workload key for the given measurements with `kdf`.
+    /// A partitioned master secret only derives with HKDF, since the original
+    /// derivation never saw the partition.
+    pub fn derive(
+        &self,
+        kdf: KdfVersion,
+        measurements: &[u8],
+    ) -> Result<SecretBytes, ProviderError> {
+        info!("Deriving key from measurements");
+        debug!("Measurements length: {} bytes", measurements.len());
+        match kdf {
+            KdfVersion::V1 if self.partitioned => Err(ProviderError::ConfigError(
+                "KSS partitioning needs kdf_version 2 in the policy".into(),
+            )),
+            KdfVersion::V1 => Ok(self.sealing_key.with_bytes(|sealing_key| {
+                let mut hasher = WipedSha256(Sha256::new());
+                hasher.0.update(sealing_key);
+                hasher.0.update(measurements);
+                let digest = Zeroizing::new(<[u8; 32]>::from(hasher.0.finalize_reset()));
+                SecretBytes::new(digest.to_vec())
+            })),
+            KdfVersion::V2 => self.expand(&derive_info(measurements)),
+        }
+    }
+
+    /// Derives the per-workload key in `tenant`'s namespace, or in the
+    /// provider's own one without a tenant. A tenant's keys come from a root
+    /// of its own, `HKDF-Expand(prk, TENANT_ROOT_LABEL || tenant)`, so no two
+    /// tenants ever share a key, even for the same measurements. Tenants came
+    /// after HKDF, so their keys never depend on `kdf`.
+    pub fn derive_for(
+        &self,
+        kdf: KdfVersion,
+        tenant: Option<&str>,
+        measurements: &[u8],
+    ) -> Result<SecretBytes, ProviderError> {
+        let Some(tenant) = tenant else {
+            return self.derive(kdf, measurements);
+        };
+        info!("Deriving key from measurements for tenant {}", tenant);
+        let root = self.expand(&[TENANT_ROOT_LABEL, tenant.as_bytes()].concat())?;
+        let mut derived = vec![0u8; DERIVED_KEY_LEN];
+        hkdf_expand(root.expose(), &derive_info(measurements), &mut derived)?;
+        Ok(SecretBytes::new(derived))
+    }
+
+    /// Expands a 32-byte secret for an arbitrary, caller-labelled purpose
+    /// (provider identity keys and the like). Labels must not start with the
+    /// per-workload derivation label.
+    pub fn expand(&self, info: &[u8]) -> Result<SecretBytes, ProviderError> {
+        self.prk.with_bytes(|prk| {
+            let mut derived = vec![0u8; DERIVED_KEY_LEN];
+            hkdf_expand(prk, info, &mut derived)?;
+            Ok(SecretBytes::new(derived))
+        })
+    }
+}
+
+fn derive_info(measurements: &[u8]) -> Vec<u8> {
+    let mut info = Vec::with_capacity(DERIVE_LABEL.len() + measurements.len());
+    info.extend_from_slice(DERIVE_LABEL);
+    info.extend_from_slice(measurements);
+    info
+}
+
+/// A SHA-256 state that has absorbed the sealing key, overwritten when it
+/// goes out of scope; `sha2` leaves its buffer and chaining state behind.
+struct WipedSha256(Sha256);
+
+impl Drop for WipedSha256 {
+    fn drop(&mut self) {
+        // The state is plain integers and bytes, for which zeros are valid
+        unsafe { zeroize::zeroize_flat_type(&mut self.0) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn v1_derivation_matches_keys_released_before_hkdf() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[0x42; 16], None).unwrap();
+        let measurements = [0x11; 240];
+
+        // SHA-256(0x42 * 16 || 0x11 * 240), as the provider released it
+        let v1 = master.derive(KdfVersion::V1, &measurements).unwrap();
+        assert_eq!(
+            hex::encode(v1.expose()),
+            "ad41a68ae4d738c0735527cc13e7b702687f113d61bd0274255fb82d4ddaaa4e"
+        );
+        assert_ne!(
+            v1.expose(),
+            master
+                .derive(KdfVersion::V2, &measurements)
+                .unwrap()
+                .expose()
+        );
+
+        let partitioned = MasterSecret::from_sealing_key(&[0x42; 16], Some(b"kss")).unwrap();
+        assert!(partitioned.derive(KdfVersion::V1, &measurements).is_err());
+    }
+}
@@ -1,3 +1,41 @@
+mod backend;
+mod cose;
+mod entropy;
+mod envelope;
+mod guarded;
+mod identity;
+mod jwe;
+mod kernel;
 mod keys;
+mod master;
+mod oaep;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+#[cfg(feature = "ratls")]
+mod ratls;
+mod secret;
+mod session;
+mod stream;
+mod tpm2;
 
-pub use keys::{derive_key, encrypt_key, extract_public_key, init_sodium};
+pub use backend::{backend, init_backend};
+pub use cose::encrypt_key_cose;
+pub use entropy::{check_hardware_entropy, fill as fill_random};
+pub use envelope::{compute_key_id, negotiate_suite, KeyEnvelope, Suite};
+pub use guarded::{wipe_guarded_keys, GuardedKey};
+pub use identity::{ProviderIdentity, Signer};
+pub use jwe::{encrypt_jwe_rsa, encrypt_key_jwe, FlattenedJwe};
+pub use kernel::KernelKeyFormat;
+pub use keys::{
+    compute_key_confirmation, constant_time_eq, encrypt_key, extract_public_key, init_sodium,
+};
+pub use master::{KdfVersion, MasterSecret};
+pub use oaep::{encrypt_key_rsa, extract_rsa_public_key};
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11Signer;
+#[cfg(feature = "ratls")]
+pub use ratls::{mint_certificate, AttestedCertificate};
+pub use secret::{Redacted, SecretBytes};
+pub use session::{unwrap_with_key, wrap_with_key, SessionChannel};
+pub use stream::encrypt_stream;
+pub use tpm2::{encrypt_key_tpm2, extract_tpm2_parent};
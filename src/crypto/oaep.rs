@@ -0,0 +1,127 @@
+use super::backend::backend;
+use super::keys::constant_time_eq;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use log::{debug, info};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+
+// Only RSA-3072 is accepted; smaller moduli are below our security target and
+// larger ones buy nothing for wrapping a 32-byte key.
+const RSA_MODULUS_BITS: usize = 3072;
+
+/// Parses the recipient's RSA public key and checks that its SHA-256 digest is
+/// what the TD committed to in the first 32 bytes of its report data.
+pub fn extract_rsa_public_key(
+    report_data: &[u8],
+    public_key_der: &[u8],
+) -> Result<RsaPublicKey, ProviderError> {
+    debug!("Extracting RSA public key bound in report data");
+
+    if report_data.len() < 32 {
+        return Err(ProviderError::PublicKeyError(
+            "Report data too short. Expected 32 bytes".into(),
+        ));
+    }
+
+    let digest = backend().sha256(&[public_key_der]);
+    if !constant_time_eq(&digest, &report_data[..32]) {
+        return Err(ProviderError::PublicKeyError(
+            "RSA public key hash does not match report data".into(),
+        ));
+    }
+
+    let public_key = RsaPublicKey::from_public_key_der(public_key_der)
+        .map_err(|e| ProviderError::PublicKeyError(format!("Invalid RSA public key: {}", e)))?;
+
+    if public_key.n().bits() != RSA_MODULUS_BITS {
+        return Err(ProviderError::PublicKeyError(format!(
+            "RSA public key must be {} bits, got {}",
+            RSA_MODULUS_BITS,
+            public_key.n().bits()
+        )));
+    }
+
+    Ok(public_key)
+}
+
+pub fn encrypt_key_rsa(
+    derived_key: &SecretBytes,
+    public_key: &RsaPublicKey,
+) -> Result<Vec<u8>, ProviderError> {
+    info!("Encrypting derived key using RSA-OAEP");
+
+    let encrypted = backend().rsa_oaep_sha256_encrypt(public_key, derived_key.expose())?;
+
+    debug!("Encrypted data length: {} bytes", encrypted.len());
+    Ok(encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::{Oaep, RsaPrivateKey};
+    use sha2::{Sha256, Sha384};
+    use std::sync::OnceLock;
+
+    /// An RSA-3072 recipient; generating one is slow, so tests share it.
+    fn recipient() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::thread_rng(), RSA_MODULUS_BITS).unwrap())
+    }
+
+    /// The DER public key of `key` and report data committing to it.
+    fn bound(key: &RsaPrivateKey) -> (Vec<u8>, [u8; 64]) {
+        let der = key.to_public_key().to_public_key_der().unwrap().into_vec();
+        let mut report_data = [0u8; 64];
+        report_data[..32].copy_from_slice(&backend().sha256(&[&der]));
+        (der, report_data)
+    }
+
+    #[test]
+    fn keys_round_trip_to_a_bound_recipient() {
+        let (der, report_data) = bound(recipient());
+        let public_key = extract_rsa_public_key(&report_data, &der).unwrap();
+        let derived_key = SecretBytes::new(vec![0x5a; 32]);
+
+        let encrypted = encrypt_key_rsa(&derived_key, &public_key).unwrap();
+        let decrypted = recipient()
+            .decrypt(Oaep::new::<Sha256>(), &encrypted)
+            .unwrap();
+
+        assert_eq!(encrypted.len(), RSA_MODULUS_BITS / 8);
+        assert_eq!(decrypted, derived_key.expose());
+    }
+
+    #[test]
+    fn keys_of_other_sizes_or_not_bound_are_refused() {
+        let small = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let (der, report_data) = bound(&small);
+        assert!(matches!(
+            extract_rsa_public_key(&report_data, &der),
+            Err(ProviderError::PublicKeyError(_))
+        ));
+
+        let (der, mut report_data) = bound(recipient());
+        report_data[0] ^= 1;
+        assert!(extract_rsa_public_key(&report_data, &der).is_err());
+        assert!(extract_rsa_public_key(&report_data[..31], &der).is_err());
+    }
+
+    #[test]
+    fn other_labels_or_hashes_do_not_decrypt() {
+        let public_key = recipient().to_public_key();
+        let encrypted = encrypt_key_rsa(&SecretBytes::new(vec![0x5a; 32]), &public_key).unwrap();
+
+        let labelled = recipient().decrypt(
+            Oaep::new_with_label::<Sha256, _>("gramine-sealing-key-provider"),
+            &encrypted,
+        );
+        let other_hash = recipient().decrypt(Oaep::new::<Sha384>(), &encrypted);
+
+        assert!(labelled.is_err());
+        assert!(other_hash.is_err());
+    }
+}
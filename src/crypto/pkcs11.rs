@@ -0,0 +1,202 @@
+use super::identity::Signer;
+use crate::error::ProviderError;
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use log::info;
+use std::path::Path;
+use std::sync::Mutex;
+
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+/// Pure EdDSA over the message, which is what an Ed25519 key signs.
+const SIGN_MECHANISM: Mechanism<'static> = Mechanism::Eddsa;
+
+/// Ed25519 (CKM_EDDSA) signing key held in an HSM, reached through a PKCS#11
+/// module loaded into the enclave. Only transcript digests leave the enclave;
+/// key derivation never touches the HSM.
+pub struct Pkcs11Signer {
+    // PKCS#11 sessions must not be used from several threads at once
+    session: Mutex<Session>,
+    key: ObjectHandle,
+    public_key: Vec<u8>,
+    // Keeps the module loaded for the lifetime of the session
+    _context: Pkcs11,
+}
+
+impl Pkcs11Signer {
+    pub fn open(
+        module: &Path,
+        token_label: &str,
+        key_label: &str,
+        pin: &str,
+    ) -> Result<Self, ProviderError> {
+        info!(
+            "Opening PKCS#11 token '{}' via {}",
+            token_label,
+            module.display()
+        );
+
+        let context = Pkcs11::new(module).map_err(pkcs11_error)?;
+        context
+            .initialize(CInitializeArgs::OsThreads)
+            .map_err(pkcs11_error)?;
+
+        let slot = context
+            .get_slots_with_token()
+            .map_err(pkcs11_error)?
+            .into_iter()
+            .find(|slot| {
+                context
+                    .get_token_info(*slot)
+                    .map(|token| token.label() == token_label)
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| {
+                ProviderError::CryptoError(format!("PKCS#11 token '{}' not found", token_label))
+            })?;
+
+        let session = context.open_ro_session(slot).map_err(pkcs11_error)?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(pin.to_string())))
+            .map_err(pkcs11_error)?;
+
+        let key = find_object(&session, ObjectClass::PRIVATE_KEY, key_label)?;
+        let public_handle = find_object(&session, ObjectClass::PUBLIC_KEY, key_label)?;
+        let public_key = read_public_key(&session, public_handle)?;
+
+        info!(
+            "Provider identity key (PKCS#11): {}",
+            hex::encode(&public_key)
+        );
+        Ok(Self {
+            session: Mutex::new(session),
+            key,
+            public_key,
+            _context: context,
+        })
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        let session = self
+            .session
+            .lock()
+            .map_err(|_| ProviderError::CryptoError("PKCS#11 session lock poisoned".into()))?;
+        session
+            .sign(&SIGN_MECHANISM, self.key, message)
+            .map_err(pkcs11_error)
+    }
+}
+
+fn find_object(
+    session: &Session,
+    class: ObjectClass,
+    label: &str,
+) -> Result<ObjectHandle, ProviderError> {
+    session
+        .find_objects(&key_template(class, label))
+        .map_err(pkcs11_error)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            ProviderError::CryptoError(format!("PKCS#11 key '{}' ({}) not found", label, class))
+        })
+}
+
+/// The attributes that find the `class` half of the key pair `label`.
+fn key_template(class: ObjectClass, label: &str) -> Vec<Attribute> {
+    vec![
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ]
+}
+
+fn read_public_key(session: &Session, handle: ObjectHandle) -> Result<Vec<u8>, ProviderError> {
+    let attributes = session
+        .get_attributes(handle, &[AttributeType::EcPoint])
+        .map_err(pkcs11_error)?;
+    let point = attributes
+        .into_iter()
+        .find_map(|attribute| match attribute {
+            Attribute::EcPoint(point) => Some(point),
+            _ => None,
+        })
+        .ok_or_else(|| ProviderError::CryptoError("PKCS#11 public key has no EC point".into()))?;
+    ed25519_point(&point)
+}
+
+/// CKA_EC_POINT of an Ed25519 key is the raw point, or (per PKCS#11 3.0) the
+/// point wrapped in a DER OCTET STRING; accept both.
+fn ed25519_point(point: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    match point {
+        [0x04, len, key @ ..]
+            if *len as usize == ED25519_PUBLIC_KEY_LEN && key.len() == ED25519_PUBLIC_KEY_LEN =>
+        {
+            Ok(key.to_vec())
+        }
+        key if key.len() == ED25519_PUBLIC_KEY_LEN => Ok(key.to_vec()),
+        _ => Err(ProviderError::CryptoError(
+            "PKCS#11 key is not an Ed25519 key".into(),
+        )),
+    }
+}
+
+fn pkcs11_error(e: cryptoki::error::Error) -> ProviderError {
+    ProviderError::CryptoError(format!("PKCS#11 error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptoki::mechanism::MechanismType;
+
+    #[test]
+    fn ec_points_are_read_raw_or_wrapped() {
+        let key = [7u8; ED25519_PUBLIC_KEY_LEN];
+        let mut wrapped = vec![0x04, ED25519_PUBLIC_KEY_LEN as u8];
+        wrapped.extend_from_slice(&key);
+
+        assert_eq!(ed25519_point(&key).unwrap(), key);
+        assert_eq!(ed25519_point(&wrapped).unwrap(), key);
+        // A wrapped point of the wrong length, and an uncompressed P-256 point
+        assert!(ed25519_point(&wrapped[..wrapped.len() - 1]).is_err());
+        let mut p256 = vec![0x04];
+        p256.extend_from_slice(&[1; 64]);
+        assert!(matches!(
+            ed25519_point(&p256),
+            Err(ProviderError::CryptoError(_))
+        ));
+    }
+
+    #[test]
+    fn keys_are_found_by_class_and_label_and_sign_eddsa() {
+        let template = key_template(ObjectClass::PRIVATE_KEY, "identity");
+        assert!(matches!(
+            template.as_slice(),
+            [Attribute::Class(class), Attribute::Label(label)]
+                if *class == ObjectClass::PRIVATE_KEY && label == b"identity"
+        ));
+        assert_eq!(SIGN_MECHANISM.mechanism_type(), MechanismType::EDDSA);
+    }
+
+    #[test]
+    fn a_missing_module_is_a_crypto_error() {
+        let opened = Pkcs11Signer::open(
+            Path::new("/nonexistent/libpkcs11.so"),
+            "provider",
+            "identity",
+            "1234",
+        );
+        assert!(matches!(
+            opened,
+            Err(ProviderError::CryptoError(message)) if message.starts_with("PKCS#11 error")
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use super::backend::backend;
+use super::entropy;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use log::info;
+use p256::pkcs8::EncodePrivateKey;
+use rcgen::{CertificateParams, CustomExtension, DnType, KeyPair};
+use zeroize::Zeroizing;
+
+/// Extension carrying the raw SGX quote, as emitted and checked by Gramine's
+/// `ra_tls_attest`/`ra_tls_verify` libraries.
+const SGX_QUOTE_OID: &[u64] = &[1, 2, 840, 113741, 1, 13, 1];
+const CERT_COMMON_NAME: &str = "gramine-sealing-key-provider";
+
+/// Self-signed RA-TLS certificate and its PKCS#8 private key. The key never
+/// leaves enclave memory.
+pub struct AttestedCertificate {
+    pub cert_der: Vec<u8>,
+    pub key_der: SecretBytes,
+}
+
+/// Mints an RA-TLS certificate the way Gramine's `ra_tls_attest` does: a
+/// fresh ECDSA P-256 key whose SubjectPublicKeyInfo hash fills the first 32
+/// bytes of the report data of a quote embedded in the certificate. Any RA-TLS
+/// verifier can then check the quote and that it vouches for the TLS key.
+pub fn mint_certificate(
+    get_quote: impl FnOnce(&[u8]) -> Result<Vec<u8>, ProviderError>,
+) -> Result<AttestedCertificate, ProviderError> {
+    let secret = loop {
+        let mut scalar = Zeroizing::new([0u8; 32]);
+        entropy::fill(&mut scalar[..])?;
+        // Out-of-range scalars are astronomically unlikely; draw again
+        if let Ok(secret) = p256::SecretKey::from_slice(&scalar[..]) {
+            break secret;
+        }
+    };
+    let pkcs8 = secret
+        .to_pkcs8_der()
+        .map_err(|e| ProviderError::CryptoError(format!("Encoding TLS key failed: {}", e)))?;
+    let key_pair = KeyPair::try_from(pkcs8.as_bytes()).map_err(rcgen_error)?;
+
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(&backend().sha256(&[&key_pair.public_key_der()]));
+    let quote = get_quote(&report_data)?;
+
+    let mut params = CertificateParams::new(Vec::<String>::new()).map_err(rcgen_error)?;
+    params
+        .distinguished_name
+        .push(DnType::CommonName, CERT_COMMON_NAME);
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(SGX_QUOTE_OID, quote));
+    let cert = params.self_signed(&key_pair).map_err(rcgen_error)?;
+
+    info!("Minted RA-TLS certificate");
+    Ok(AttestedCertificate {
+        cert_der: cert.der().to_vec(),
+        key_der: SecretBytes::new(pkcs8.as_bytes().to_vec()),
+    })
+}
+
+fn rcgen_error(e: rcgen::Error) -> ProviderError {
+    ProviderError::CryptoError(format!("RA-TLS certificate generation failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_embeds_quote_over_its_key() {
+        let mut bound = Vec::new();
+        let cert = mint_certificate(|report_data| {
+            bound = report_data.to_vec();
+            Ok(b"quote".to_vec())
+        })
+        .unwrap();
+
+        let key_pair = KeyPair::try_from(cert.key_der.expose()).unwrap();
+        assert_eq!(
+            &bound[..32],
+            &backend().sha256(&[&key_pair.public_key_der()])[..]
+        );
+        assert!(bound[32..].iter().all(|&byte| byte == 0));
+        assert!(cert
+            .cert_der
+            .windows(b"quote".len())
+            .any(|window| window == b"quote"));
+    }
+}
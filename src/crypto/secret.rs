@@ -0,0 +1,63 @@
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// Secret key material. Zeroized on drop, and its `Debug`/`Display` output
+/// never includes the contents (or the length), so it cannot end up in logs
+/// by accident. Callers must go through `expose` to read the bytes.
+pub struct SecretBytes(Zeroizing<Vec<u8>>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretBytes([REDACTED])")
+    }
+}
+
+impl fmt::Display for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+/// Wraps non-secret but sensitive data (quotes, ciphertexts, platform IDs)
+/// for logging: only the length is printed.
+pub struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[REDACTED; {} bytes]", self.0.len())
+    }
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_bytes_never_formats_contents() {
+        let secret = SecretBytes::new(vec![0x41; 8]);
+        assert_eq!(format!("{:?}", secret), "SecretBytes([REDACTED])");
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(secret.expose(), &[0x41; 8]);
+    }
+
+    #[test]
+    fn redacted_prints_length_only() {
+        assert_eq!(format!("{}", Redacted(&[1, 2, 3])), "[REDACTED; 3 bytes]");
+    }
+}
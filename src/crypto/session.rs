@@ -0,0 +1,238 @@
+use super::backend::{backend, hkdf_expand, hkdf_extract, AEAD_KEY_LEN, AEAD_NONCE_LEN};
+use super::entropy;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use sodiumoxide::crypto::box_::{PublicKey, SecretKey};
+use sodiumoxide::crypto::scalarmult::curve25519::{scalarmult, GroupElement, Scalar};
+use std::fmt;
+use zeroize::Zeroizing;
+
+const SESSION_SALT: &[u8] = b"gramine-sealing-key-provider/session/v1";
+const CLIENT_TO_PROVIDER_LABEL: &[u8] = b"gramine-sealing-key-provider/session/c2p/v1";
+const PROVIDER_TO_CLIENT_LABEL: &[u8] = b"gramine-sealing-key-provider/session/p2c/v1";
+const WRAP_LABEL: &[u8] = b"gramine-sealing-key-provider/session/wrap/v1";
+
+/// Provider end of an encrypted session established after a quote exchange.
+///
+/// Both sides contribute an ephemeral X25519 key: the client's is committed to
+/// in its TD quote and the provider's in the provider quote, so the channel is
+/// bound to both attested identities. Each direction has its own AES-256-GCM
+/// key; nonces are `0u32 || sequence: u64` (big-endian), so messages must be
+/// processed strictly in order and cannot be replayed or reflected.
+pub struct SessionChannel {
+    send_key: Zeroizing<[u8; AEAD_KEY_LEN]>,
+    recv_key: Zeroizing<[u8; AEAD_KEY_LEN]>,
+    send_seq: u64,
+    recv_seq: u64,
+}
+
+impl SessionChannel {
+    /// Runs the provider side of the exchange. Returns the channel and the
+    /// provider's ephemeral public key, which must be bound into the provider
+    /// quote.
+    pub fn establish(
+        client_key: &[u8],
+        tdx_quote: &[u8],
+    ) -> Result<(Self, PublicKey), ProviderError> {
+        let client_key = PublicKey::from_slice(client_key)
+            .ok_or_else(|| ProviderError::PublicKeyError("Invalid session public key".into()))?;
+        let (provider_key, provider_secret) = entropy::gen_keypair()?;
+
+        let shared = x25519(&provider_secret, &client_key)?;
+        let (client_to_provider, provider_to_client) =
+            directional_keys(&shared[..], &client_key, &provider_key, tdx_quote)?;
+        Ok((
+            Self::from_keys(provider_to_client, client_to_provider),
+            provider_key,
+        ))
+    }
+
+    fn from_keys(
+        send_key: Zeroizing<[u8; AEAD_KEY_LEN]>,
+        recv_key: Zeroizing<[u8; AEAD_KEY_LEN]>,
+    ) -> Self {
+        Self {
+            send_key,
+            recv_key,
+            send_seq: 0,
+            recv_seq: 0,
+        }
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        let nonce = sequence_nonce(self.send_seq)?;
+        let mut message = plaintext.to_vec();
+        backend().aes256gcm_seal(&self.send_key, &nonce, &[], &mut message)?;
+        self.send_seq += 1;
+        Ok(message)
+    }
+
+    pub fn open(&mut self, ciphertext: &[u8]) -> Result<SecretBytes, ProviderError> {
+        let nonce = sequence_nonce(self.recv_seq)?;
+        let mut message = ciphertext.to_vec();
+        backend().aes256gcm_open(&self.recv_key, &nonce, &[], &mut message)?;
+        self.recv_seq += 1;
+        Ok(SecretBytes::new(message))
+    }
+}
+
+impl fmt::Debug for SessionChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionChannel")
+            .field("send_seq", &self.send_seq)
+            .field("recv_seq", &self.recv_seq)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wraps `plaintext` under a key expanded from the workload key:
+/// `nonce (12) || AES-256-GCM ciphertext || tag`.
+pub fn wrap_with_key(
+    derived_key: &SecretBytes,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, ProviderError> {
+    let key = wrap_key(derived_key)?;
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    entropy::fill(&mut nonce)?;
+
+    let mut ciphertext = plaintext.to_vec();
+    backend().aes256gcm_seal(&key, &nonce, aad, &mut ciphertext)?;
+
+    let mut wrapped = nonce.to_vec();
+    wrapped.extend_from_slice(&ciphertext);
+    Ok(wrapped)
+}
+
+/// Reverses `wrap_with_key`; fails if the blob was wrapped for another
+/// workload or the associated data differs.
+pub fn unwrap_with_key(
+    derived_key: &SecretBytes,
+    aad: &[u8],
+    wrapped: &[u8],
+) -> Result<SecretBytes, ProviderError> {
+    if wrapped.len() < AEAD_NONCE_LEN {
+        return Err(ProviderError::CryptoError("Wrapped blob too short".into()));
+    }
+    let key = wrap_key(derived_key)?;
+    let (nonce, ciphertext) = wrapped.split_at(AEAD_NONCE_LEN);
+    let nonce: [u8; AEAD_NONCE_LEN] = nonce.try_into().expect("split at nonce length");
+
+    let mut plaintext = ciphertext.to_vec();
+    backend().aes256gcm_open(&key, &nonce, aad, &mut plaintext)?;
+    Ok(SecretBytes::new(plaintext))
+}
+
+fn wrap_key(derived_key: &SecretBytes) -> Result<Zeroizing<[u8; AEAD_KEY_LEN]>, ProviderError> {
+    let mut key = Zeroizing::new([0u8; AEAD_KEY_LEN]);
+    hkdf_expand(derived_key.expose(), WRAP_LABEL, &mut key[..])?;
+    Ok(key)
+}
+
+type DirectionalKeys = (Zeroizing<[u8; AEAD_KEY_LEN]>, Zeroizing<[u8; AEAD_KEY_LEN]>);
+
+fn x25519(secret: &SecretKey, public: &PublicKey) -> Result<Zeroizing<[u8; 32]>, ProviderError> {
+    let shared = scalarmult(&Scalar(secret.0), &GroupElement(public.0))
+        .map_err(|_| ProviderError::CryptoError("X25519 produced a low-order point".into()))?;
+    Ok(Zeroizing::new(shared.0))
+}
+
+/// `(client_to_provider, provider_to_client)` keys: HKDF over the X25519
+/// shared secret, with both public keys and the hash of the client's quote as
+/// context.
+fn directional_keys(
+    shared: &[u8],
+    client_public: &PublicKey,
+    provider_public: &PublicKey,
+    tdx_quote: &[u8],
+) -> Result<DirectionalKeys, ProviderError> {
+    let prk = Zeroizing::new(hkdf_extract(SESSION_SALT, shared));
+
+    let mut context = Vec::with_capacity(3 * 32);
+    context.extend_from_slice(&client_public.0);
+    context.extend_from_slice(&provider_public.0);
+    context.extend_from_slice(&backend().sha256(&[tdx_quote]));
+
+    let expand = |label: &[u8]| -> Result<Zeroizing<[u8; AEAD_KEY_LEN]>, ProviderError> {
+        let mut info = label.to_vec();
+        info.extend_from_slice(&context);
+        let mut key = Zeroizing::new([0u8; AEAD_KEY_LEN]);
+        hkdf_expand(&prk[..], &info, &mut key[..])?;
+        Ok(key)
+    };
+    Ok((
+        expand(CLIENT_TO_PROVIDER_LABEL)?,
+        expand(PROVIDER_TO_CLIENT_LABEL)?,
+    ))
+}
+
+fn sequence_nonce(seq: u64) -> Result<[u8; AEAD_NONCE_LEN], ProviderError> {
+    if seq == u64::MAX {
+        return Err(ProviderError::CryptoError(
+            "Session sequence exhausted".into(),
+        ));
+    }
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    Ok(nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sodiumoxide::crypto::box_;
+
+    fn client_end(
+        client: &(PublicKey, SecretKey),
+        provider_key: &PublicKey,
+        tdx_quote: &[u8],
+    ) -> SessionChannel {
+        let shared = x25519(&client.1, provider_key).unwrap();
+        let (client_to_provider, provider_to_client) =
+            directional_keys(&shared[..], &client.0, provider_key, tdx_quote).unwrap();
+        SessionChannel::from_keys(client_to_provider, provider_to_client)
+    }
+
+    #[test]
+    fn messages_flow_in_order_only() {
+        sodiumoxide::init().unwrap();
+        let client = box_::gen_keypair();
+        let (mut provider, provider_key) =
+            SessionChannel::establish(&client.0 .0, b"quote").unwrap();
+        let mut client = client_end(&client, &provider_key, b"quote");
+
+        let first = client.seal(b"first").unwrap();
+        let second = client.seal(b"second").unwrap();
+        assert!(provider.open(&second).is_err());
+
+        assert_eq!(provider.open(&first).unwrap().expose(), b"first");
+        assert!(provider.open(&first).is_err());
+
+        let reply = provider.seal(b"reply").unwrap();
+        assert_eq!(client.open(&reply).unwrap().expose(), b"reply");
+    }
+
+    #[test]
+    fn session_is_bound_to_the_client_quote() {
+        sodiumoxide::init().unwrap();
+        let client = box_::gen_keypair();
+        let (mut provider, provider_key) =
+            SessionChannel::establish(&client.0 .0, b"quote").unwrap();
+        let mut client = client_end(&client, &provider_key, b"other quote");
+
+        assert!(provider.open(&client.seal(b"hello").unwrap()).is_err());
+    }
+
+    #[test]
+    fn wrap_round_trip_checks_key_and_aad() {
+        let key = SecretBytes::new(vec![7u8; 32]);
+        let wrapped = wrap_with_key(&key, b"aad", b"payload").unwrap();
+
+        assert_eq!(
+            unwrap_with_key(&key, b"aad", &wrapped).unwrap().expose(),
+            b"payload"
+        );
+        assert!(unwrap_with_key(&key, b"other", &wrapped).is_err());
+        assert!(unwrap_with_key(&SecretBytes::new(vec![8u8; 32]), b"aad", &wrapped).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use super::entropy;
+use super::keys::seal_box;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use log::{debug, info};
+use sodiumoxide::crypto::box_::PublicKey;
+use sodiumoxide::crypto::secretstream::{self, Stream, Tag};
+use zeroize::Zeroizing;
+
+pub const STREAM_VERSION: u8 = 1;
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+/// Encrypts an arbitrarily large payload for an X25519 recipient using
+/// libsodium's secretstream (XChaCha20-Poly1305, STREAM-style chunking with
+/// an explicit final tag, so truncation and reordering are detected).
+///
+/// A fresh stream key is sealed to the recipient. Wire layout (big-endian):
+/// `version: u8 | sealed_key_len: u16 | sealed_key | header (24) |
+/// (chunk_len: u32 | chunk)*`, where every chunk is at most
+/// `STREAM_CHUNK_LEN` bytes of plaintext and the last one carries the final
+/// tag.
+pub fn encrypt_stream(
+    plaintext: &SecretBytes,
+    public_key: &PublicKey,
+) -> Result<Vec<u8>, ProviderError> {
+    info!("Encrypting payload with chunked secretstream");
+
+    let mut key_bytes = Zeroizing::new([0u8; secretstream::KEYBYTES]);
+    entropy::fill(&mut key_bytes[..])?;
+    let stream_key = secretstream::Key(*key_bytes);
+    let sealed_key = seal_box(&stream_key.0, public_key)?;
+    let (mut stream, header) = Stream::init_push(&stream_key)
+        .map_err(|_| ProviderError::CryptoError("Failed to initialize secretstream".into()))?;
+
+    let payload = plaintext.expose();
+    let mut out = Vec::with_capacity(3 + sealed_key.len() + header.0.len() + payload.len());
+    out.push(STREAM_VERSION);
+    out.extend_from_slice(&(sealed_key.len() as u16).to_be_bytes());
+    out.extend_from_slice(&sealed_key);
+    out.extend_from_slice(&header.0);
+
+    // An empty payload still gets a (final) chunk so the stream is terminated
+    let mut chunks = payload.chunks(STREAM_CHUNK_LEN).peekable();
+    if chunks.peek().is_none() {
+        push_chunk(&mut stream, &mut out, &[], Tag::Final)?;
+    }
+    while let Some(chunk) = chunks.next() {
+        let tag = if chunks.peek().is_some() {
+            Tag::Message
+        } else {
+            Tag::Final
+        };
+        push_chunk(&mut stream, &mut out, chunk, tag)?;
+    }
+
+    debug!("Stream ciphertext length: {} bytes", out.len());
+    Ok(out)
+}
+
+fn push_chunk(
+    stream: &mut Stream<secretstream::Push>,
+    out: &mut Vec<u8>,
+    chunk: &[u8],
+    tag: Tag,
+) -> Result<(), ProviderError> {
+    let ciphertext = stream
+        .push(chunk, None, tag)
+        .map_err(|_| ProviderError::CryptoError("secretstream push failed".into()))?;
+    out.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+    out.extend_from_slice(&ciphertext);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::init_sodium;
+    use sodiumoxide::crypto::box_::{self, SecretKey};
+    use sodiumoxide::crypto::sealedbox;
+    use std::ops::Range;
+
+    /// Where the header ends and each chunk lies, by the wire layout.
+    fn layout(ciphertext: &[u8]) -> (Range<usize>, Vec<Range<usize>>) {
+        let sealed_key_len = u16::from_be_bytes([ciphertext[1], ciphertext[2]]) as usize;
+        let header_start = 3 + sealed_key_len;
+        let header = header_start..header_start + secretstream::HEADERBYTES;
+        let mut chunks = Vec::new();
+        let mut offset = header.end;
+        while offset < ciphertext.len() {
+            let len = u32::from_be_bytes(ciphertext[offset..offset + 4].try_into().unwrap());
+            chunks.push(offset..offset + 4 + len as usize);
+            offset += 4 + len as usize;
+        }
+        (header, chunks)
+    }
+
+    /// Decrypts like a recipient would: every chunk in order, the last and
+    /// only the last one final.
+    fn decrypt(
+        ciphertext: &[u8],
+        public_key: &PublicKey,
+        secret_key: &SecretKey,
+    ) -> Option<Vec<u8>> {
+        assert_eq!(ciphertext[0], STREAM_VERSION);
+        let (header, chunks) = layout(ciphertext);
+        let key = sealedbox::open(&ciphertext[3..header.start], public_key, secret_key).ok()?;
+        let key = secretstream::Key::from_slice(&key)?;
+        let header = secretstream::Header::from_slice(&ciphertext[header])?;
+        let mut stream = Stream::init_pull(&header, &key).ok()?;
+
+        let mut plaintext = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (message, tag) = stream
+                .pull(&ciphertext[chunk.start + 4..chunk.end], None)
+                .ok()?;
+            if (tag == Tag::Final) != (i == chunks.len() - 1) {
+                return None;
+            }
+            plaintext.extend(message);
+        }
+        stream.is_finalized().then_some(plaintext)
+    }
+
+    #[test]
+    fn payloads_round_trip_in_chunks() {
+        init_sodium().unwrap();
+        let (public_key, secret_key) = box_::gen_keypair();
+
+        for len in [0, 1, STREAM_CHUNK_LEN, 2 * STREAM_CHUNK_LEN + 17] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let ciphertext =
+                encrypt_stream(&SecretBytes::new(payload.clone()), &public_key).unwrap();
+            let (_, chunks) = layout(&ciphertext);
+            assert_eq!(chunks.len(), len.div_ceil(STREAM_CHUNK_LEN).max(1));
+            assert_eq!(
+                decrypt(&ciphertext, &public_key, &secret_key).unwrap(),
+                payload
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_reordered_or_tampered_streams_do_not_decrypt() {
+        init_sodium().unwrap();
+        let (public_key, secret_key) = box_::gen_keypair();
+        let payload = SecretBytes::new(vec![0x5a; 3 * STREAM_CHUNK_LEN]);
+        let ciphertext = encrypt_stream(&payload, &public_key).unwrap();
+        let (_, chunks) = layout(&ciphertext);
+        assert_eq!(chunks.len(), 3);
+        assert!(decrypt(&ciphertext, &public_key, &secret_key).is_some());
+
+        // Without its final chunk
+        let truncated = &ciphertext[..chunks[2].start];
+        assert!(decrypt(truncated, &public_key, &secret_key).is_none());
+
+        // The first two chunks swapped
+        let mut reordered = ciphertext[..chunks[0].start].to_vec();
+        reordered.extend_from_slice(&ciphertext[chunks[1].clone()]);
+        reordered.extend_from_slice(&ciphertext[chunks[0].clone()]);
+        reordered.extend_from_slice(&ciphertext[chunks[2].clone()]);
+        assert!(decrypt(&reordered, &public_key, &secret_key).is_none());
+
+        let mut tampered = ciphertext.clone();
+        tampered[chunks[1].start + 100] ^= 1;
+        assert!(decrypt(&tampered, &public_key, &secret_key).is_none());
+
+        // Only the intended recipient can open the stream key
+        let (other_public, other_secret) = box_::gen_keypair();
+        assert!(decrypt(&ciphertext, &other_public, &other_secret).is_none());
+    }
+}
@@ -0,0 +1,353 @@
+use super::backend::backend;
+use super::entropy::{self, MixedRng};
+use super::keys::constant_time_eq;
+use super::secret::SecretBytes;
+use crate::error::ProviderError;
+use aes::{Aes128, Aes256};
+use cfb_mode::cipher::{AsyncStreamCipher, KeyIvInit};
+use log::{debug, info};
+use rsa::{BigUint, Oaep, RsaPublicKey};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const TPM_ALG_RSA: u16 = 0x0001;
+const TPM_ALG_AES: u16 = 0x0006;
+const TPM_ALG_KEYEDHASH: u16 = 0x0008;
+const TPM_ALG_SHA256: u16 = 0x000B;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_CFB: u16 = 0x0043;
+
+const TPMA_OBJECT_USER_WITH_AUTH: u32 = 1 << 6;
+const TPMA_OBJECT_RESTRICTED: u32 = 1 << 16;
+const TPMA_OBJECT_DECRYPT: u32 = 1 << 17;
+
+const SHA256_DIGEST_LEN: usize = 32;
+const MIN_PARENT_RSA_BITS: usize = 2048;
+
+/// RSA storage key (typically the vTPM's SRK) that the derived key is
+/// duplicated to. Only SHA-256 name algorithms and AES-CFB inner symmetric
+/// definitions are supported, which covers the standard templates.
+pub struct Tpm2Parent {
+    public_key: RsaPublicKey,
+    sym_key_bits: usize,
+}
+
+/// Parses the parent's `TPM2B_PUBLIC` and checks that its SHA-256 digest is
+/// what the TD committed to in the first 32 bytes of its report data.
+pub fn extract_tpm2_parent(
+    report_data: &[u8],
+    parent_public: &[u8],
+) -> Result<Tpm2Parent, ProviderError> {
+    debug!("Extracting TPM2 parent key bound in report data");
+
+    if report_data.len() < SHA256_DIGEST_LEN {
+        return Err(ProviderError::PublicKeyError(
+            "Report data too short. Expected 32 bytes".into(),
+        ));
+    }
+    let digest = backend().sha256(&[parent_public]);
+    if !constant_time_eq(&digest, &report_data[..SHA256_DIGEST_LEN]) {
+        return Err(ProviderError::PublicKeyError(
+            "TPM2 parent key hash does not match report data".into(),
+        ));
+    }
+
+    let mut reader = Reader(parent_public);
+    let public = reader.tpm2b()?;
+    reader.finish()?;
+    parse_rsa_storage_key(public)
+}
+
+/// Wraps the derived key as a sealed data object that only the parent TPM can
+/// load (TPM2_Duplicate outer wrapper, no inner wrapper). The output is
+/// `TPM2B_PUBLIC | TPM2B_PRIVATE | TPM2B_ENCRYPTED_SECRET`, i.e. the
+/// `objectPublic`, `duplicate` and `inSymSeed` arguments of TPM2_Import; the
+/// imported object is then loaded and unsealed inside the guest.
+pub fn encrypt_key_tpm2(
+    derived_key: &SecretBytes,
+    parent: &Tpm2Parent,
+) -> Result<Vec<u8>, ProviderError> {
+    info!("Wrapping derived key for TPM2 import");
+
+    // Sealed data object; fixedTPM/fixedParent must be clear for import
+    let mut obfuscation = Zeroizing::new([0u8; SHA256_DIGEST_LEN]);
+    entropy::fill(&mut obfuscation[..])?;
+    let unique = backend().sha256(&[&obfuscation[..], derived_key.expose()]);
+
+    let mut public = Vec::new();
+    put_u16(&mut public, TPM_ALG_KEYEDHASH);
+    put_u16(&mut public, TPM_ALG_SHA256);
+    public.extend_from_slice(&TPMA_OBJECT_USER_WITH_AUTH.to_be_bytes());
+    put_tpm2b(&mut public, &[])?; // authPolicy
+    put_u16(&mut public, TPM_ALG_NULL); // keyed hash scheme
+    put_tpm2b(&mut public, &unique)?;
+
+    let mut name = TPM_ALG_SHA256.to_be_bytes().to_vec();
+    name.extend_from_slice(&backend().sha256(&[&public]));
+
+    let mut sensitive = Zeroizing::new(Vec::new());
+    put_u16(&mut sensitive, TPM_ALG_KEYEDHASH);
+    put_tpm2b(&mut sensitive, &[])?; // authValue
+    put_tpm2b(&mut sensitive, &obfuscation[..])?;
+    put_tpm2b(&mut sensitive, derived_key.expose())?;
+    let mut encrypted_sensitive = Vec::with_capacity(2 + sensitive.len());
+    put_tpm2b(&mut encrypted_sensitive, &sensitive)?;
+
+    // Outer wrapper keyed by a seed that only the parent can decrypt
+    let mut seed = Zeroizing::new([0u8; SHA256_DIGEST_LEN]);
+    entropy::fill(&mut seed[..])?;
+    let mut rng = MixedRng::new();
+    let encrypted_seed = parent
+        .public_key
+        .encrypt(
+            &mut rng,
+            Oaep::new_with_label::<Sha256, _>("DUPLICATE\0"),
+            &seed[..],
+        )
+        .map_err(|e| ProviderError::CryptoError(format!("TPM2 seed encryption failed: {}", e)))?;
+    rng.finish()?;
+
+    let sym_key = kdfa(&seed[..], b"STORAGE", &name, &[], parent.sym_key_bits);
+    let iv = [0u8; 16];
+    match parent.sym_key_bits {
+        128 => cfb_mode::Encryptor::<Aes128>::new_from_slices(&sym_key, &iv)
+            .map_err(|_| ProviderError::CryptoError("Invalid TPM2 storage key".into()))?
+            .encrypt(&mut encrypted_sensitive),
+        _ => cfb_mode::Encryptor::<Aes256>::new_from_slices(&sym_key, &iv)
+            .map_err(|_| ProviderError::CryptoError("Invalid TPM2 storage key".into()))?
+            .encrypt(&mut encrypted_sensitive),
+    }
+
+    let hmac_key = kdfa(&seed[..], b"INTEGRITY", &[], &[], 8 * SHA256_DIGEST_LEN);
+    let outer_hmac = backend().hmac_sha256(&hmac_key, &[&encrypted_sensitive, &name]);
+
+    let mut duplicate = Vec::with_capacity(2 + SHA256_DIGEST_LEN + encrypted_sensitive.len());
+    put_tpm2b(&mut duplicate, &outer_hmac)?;
+    duplicate.extend_from_slice(&encrypted_sensitive);
+
+    let mut out = Vec::new();
+    put_tpm2b(&mut out, &public)?;
+    put_tpm2b(&mut out, &duplicate)?;
+    put_tpm2b(&mut out, &encrypted_seed)?;
+
+    debug!("TPM2 import blob length: {} bytes", out.len());
+    Ok(out)
+}
+
+/// KDFa (TPM 2.0 Part 1, 11.4.10.2) with HMAC-SHA256.
+fn kdfa(
+    key: &[u8],
+    label: &[u8],
+    context_u: &[u8],
+    context_v: &[u8],
+    bits: usize,
+) -> Zeroizing<Vec<u8>> {
+    let bytes = bits.div_ceil(8);
+    let mut out = Zeroizing::new(Vec::with_capacity(bytes + SHA256_DIGEST_LEN));
+    let mut counter: u32 = 1;
+    while out.len() < bytes {
+        let block = backend().hmac_sha256(
+            key,
+            &[
+                &counter.to_be_bytes(),
+                label,
+                &[0],
+                context_u,
+                context_v,
+                &(bits as u32).to_be_bytes(),
+            ],
+        );
+        out.extend_from_slice(&block);
+        counter += 1;
+    }
+    out.truncate(bytes);
+    out
+}
+
+fn parse_rsa_storage_key(public: &[u8]) -> Result<Tpm2Parent, ProviderError> {
+    let mut reader = Reader(public);
+    if reader.u16()? != TPM_ALG_RSA {
+        return Err(unsupported("parent must be an RSA key"));
+    }
+    if reader.u16()? != TPM_ALG_SHA256 {
+        return Err(unsupported("parent name algorithm must be SHA-256"));
+    }
+    let attributes = reader.u32()?;
+    if attributes & (TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT)
+        != (TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT)
+    {
+        return Err(unsupported("parent must be a restricted decryption key"));
+    }
+    reader.tpm2b()?; // authPolicy
+
+    if reader.u16()? != TPM_ALG_AES {
+        return Err(unsupported("parent symmetric algorithm must be AES"));
+    }
+    let sym_key_bits = reader.u16()? as usize;
+    if !matches!(sym_key_bits, 128 | 256) {
+        return Err(unsupported("parent AES key size must be 128 or 256 bits"));
+    }
+    if reader.u16()? != TPM_ALG_CFB {
+        return Err(unsupported("parent symmetric mode must be CFB"));
+    }
+    if reader.u16()? != TPM_ALG_NULL {
+        return Err(unsupported("parent RSA scheme must be NULL"));
+    }
+    let key_bits = reader.u16()? as usize;
+    let exponent = match reader.u32()? {
+        0 => 65537,
+        exponent => exponent,
+    };
+    let modulus = reader.tpm2b()?;
+    reader.finish()?;
+
+    if key_bits < MIN_PARENT_RSA_BITS || modulus.len() * 8 != key_bits {
+        return Err(unsupported("parent RSA key must be at least 2048 bits"));
+    }
+    let public_key = RsaPublicKey::new(BigUint::from_bytes_be(modulus), BigUint::from(exponent))
+        .map_err(|e| ProviderError::PublicKeyError(format!("Invalid TPM2 parent: {}", e)))?;
+
+    Ok(Tpm2Parent {
+        public_key,
+        sym_key_bits,
+    })
+}
+
+fn unsupported(reason: &str) -> ProviderError {
+    ProviderError::PublicKeyError(format!("Unsupported TPM2 parent: {}", reason))
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_tpm2b(out: &mut Vec<u8>, data: &[u8]) -> Result<(), ProviderError> {
+    let len = u16::try_from(data.len())
+        .map_err(|_| ProviderError::SerializationError("TPM2B field too large".into()))?;
+    put_u16(out, len);
+    out.extend_from_slice(data);
+    Ok(())
+}
+
+/// Big-endian TPM structure reader.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProviderError> {
+        if self.0.len() < len {
+            return Err(ProviderError::PublicKeyError(
+                "Truncated TPM2 structure".into(),
+            ));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, ProviderError> {
+        Ok(u16::from_be_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProviderError> {
+        Ok(u32::from_be_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn tpm2b(&mut self) -> Result<&'a [u8], ProviderError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn finish(&self) -> Result<(), ProviderError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(ProviderError::PublicKeyError(
+                "Trailing bytes after TPM2 structure".into(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::traits::PublicKeyParts;
+    use rsa::RsaPrivateKey;
+
+    fn srk_template(key: &RsaPublicKey) -> Vec<u8> {
+        let mut public = Vec::new();
+        put_u16(&mut public, TPM_ALG_RSA);
+        put_u16(&mut public, TPM_ALG_SHA256);
+        public.extend_from_slice(&(TPMA_OBJECT_RESTRICTED | TPMA_OBJECT_DECRYPT).to_be_bytes());
+        put_tpm2b(&mut public, &[]).unwrap();
+        put_u16(&mut public, TPM_ALG_AES);
+        put_u16(&mut public, 128);
+        put_u16(&mut public, TPM_ALG_CFB);
+        put_u16(&mut public, TPM_ALG_NULL);
+        put_u16(&mut public, 2048);
+        public.extend_from_slice(&0u32.to_be_bytes());
+        put_tpm2b(&mut public, &key.n().to_bytes_be()).unwrap();
+
+        let mut tpm2b = Vec::new();
+        put_tpm2b(&mut tpm2b, &public).unwrap();
+        tpm2b
+    }
+
+    // Performs the TPM side of TPM2_Import + TPM2_Unseal in software
+    #[test]
+    fn import_blob_unwraps_with_parent_key() {
+        sodiumoxide::init().unwrap();
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let parent_public = srk_template(&private_key.to_public_key());
+        let report_data = backend().sha256(&[&parent_public]);
+
+        let parent = extract_tpm2_parent(&report_data, &parent_public).unwrap();
+        let derived_key = SecretBytes::new(vec![0x5a; 32]);
+        let blob = encrypt_key_tpm2(&derived_key, &parent).unwrap();
+
+        let mut reader = Reader(&blob);
+        let public = reader.tpm2b().unwrap();
+        let duplicate = reader.tpm2b().unwrap();
+        let encrypted_seed = reader.tpm2b().unwrap();
+        reader.finish().unwrap();
+
+        let seed = private_key
+            .decrypt(
+                Oaep::new_with_label::<Sha256, _>("DUPLICATE\0"),
+                encrypted_seed,
+            )
+            .unwrap();
+        let mut name = TPM_ALG_SHA256.to_be_bytes().to_vec();
+        name.extend_from_slice(&backend().sha256(&[public]));
+
+        let mut reader = Reader(duplicate);
+        let outer_hmac = reader.tpm2b().unwrap();
+        let encrypted_sensitive = reader.0;
+        let hmac_key = kdfa(&seed, b"INTEGRITY", &[], &[], 256);
+        assert_eq!(
+            outer_hmac,
+            backend().hmac_sha256(&hmac_key, &[encrypted_sensitive, &name])
+        );
+
+        let sym_key = kdfa(&seed, b"STORAGE", &name, &[], 128);
+        let mut sensitive = encrypted_sensitive.to_vec();
+        cfb_mode::Decryptor::<Aes128>::new_from_slices(&sym_key, &[0u8; 16])
+            .unwrap()
+            .decrypt(&mut sensitive);
+
+        let mut reader = Reader(&sensitive);
+        let mut sensitive = Reader(reader.tpm2b().unwrap());
+        assert_eq!(sensitive.u16().unwrap(), TPM_ALG_KEYEDHASH);
+        assert!(sensitive.tpm2b().unwrap().is_empty());
+        let obfuscation = sensitive.tpm2b().unwrap();
+        assert_eq!(sensitive.tpm2b().unwrap(), derived_key.expose());
+
+        // The public area commits to the sealed data
+        let unique = backend().sha256(&[obfuscation, derived_key.expose()]);
+        assert!(public.ends_with(&unique));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::error::ProviderError;
+use crate::gramine;
+use crate::verifier::Verifier;
+use dcap_qvl::quote::Quote;
+use log::{error, info, warn};
+
+/// A platform problem found at startup, with what the operator can do about
+/// it.
+#[derive(Debug)]
+struct Finding {
+    problem: String,
+    remedy: &'static str,
+}
+
+/// Generates and verifies the provider's own quote once at startup, and
+/// explains registration and collateral problems in terms of what to fix.
+/// Nothing here is fatal to the server: PCS may come back, and the collateral
+/// cache may already hold what is needed. Returns whether the check passed.
+pub async fn check_platform(verifier: &dyn Verifier) -> bool {
+    match probe(verifier).await {
+        Ok(status) => {
+            info!("Platform check passed (TCB status {})", status);
+            if let Some(hint) = tcb_hint(&status) {
+                warn!("Platform TCB is {}: {}", status, hint);
+            }
+            true
+        }
+        Err(finding) => {
+            error!("Platform check failed: {}", finding.problem);
+            error!("Hint: {}", finding.remedy);
+            false
+        }
+    }
+}
+
+async fn probe(verifier: &dyn Verifier) -> Result<String, Finding> {
+    let quote = gramine::own_quote().map_err(|e| Finding {
+        problem: format!("the enclave could not generate a quote ({})", e.chain()),
+        remedy: "check that aesmd (SGX) or the QGS (TDX) is running on the host, and that \
+                 the DCAP quote provider library is installed and its \
+                 /etc/sgx_default_qcnl.conf points at a reachable PCCS",
+    })?;
+    check_certification(&quote)?;
+
+    let verified = verifier.verify(&quote).await.map_err(classify)?;
+    Ok(verified.tcb_status)
+}
+
+/// A quote without a PCK certificate chain means the quote provider library
+/// could not get PCK certificates for this platform.
+fn check_certification(quote: &[u8]) -> Result<(), Finding> {
+    let quote = Quote::parse(quote).map_err(|_| Finding {
+        problem: "the enclave's own quote could not be parsed".into(),
+        remedy: "check that the host's DCAP quoting libraries match the installed SGX driver \
+                 and PSW versions",
+    })?;
+    quote.raw_cert_chain().map_err(|e| Finding {
+        problem: format!("the quote carries no PCK certificate chain ({})", e),
+        remedy: "the platform is probably not registered, or the PCCS holds no PCK \
+                 certificates for it; register it with the PCK Cert ID Retrieval Tool or \
+                 multi-package registration, and check that the PCCS can reach Intel PCS",
+    })?;
+    if let Ok(fmspc) = quote.fmspc() {
+        info!("Platform FMSPC {}", hex::encode(fmspc));
+    }
+    Ok(())
+}
+
+fn classify(e: ProviderError) -> Finding {
+    match e {
+        ProviderError::CollateralError { .. } | ProviderError::NetworkError(_) => Finding {
+            problem: format!("collateral could not be fetched ({})", e.chain()),
+            remedy: "check outbound HTTPS to Intel PCS (or the configured PCCS) and any proxy \
+                     settings; a 404 for this FMSPC means the platform is not registered",
+        },
+        ProviderError::QuoteVerificationError(_) => Finding {
+            problem: format!(
+                "the provider's own quote failed verification ({})",
+                e.chain()
+            ),
+            remedy: "the collateral may be stale or the platform TCB revoked; if a PCCS \
+                     caches collateral, refresh it, and apply pending BIOS and microcode \
+                     updates",
+        },
+        _ => Finding {
+            problem: format!(
+                "the provider's own quote could not be verified ({})",
+                e.chain()
+            ),
+            remedy: "run with RUST_LOG=debug for details",
+        },
+    }
+}
+
+/// What to do about a TCB status that verifies but is not `UpToDate`.
+fn tcb_hint(status: &str) -> Option<&'static str> {
+    match status {
+        "UpToDate" => None,
+        "SWHardeningNeeded" => Some(
+            "the platform is patched, but the advisories listed in responses need software \
+             mitigations",
+        ),
+        "ConfigurationNeeded" | "ConfigurationAndSWHardeningNeeded" => Some(
+            "a BIOS configuration change (e.g. disabling hyper-threading) is needed to \
+             reach UpToDate",
+        ),
+        "OutOfDate" | "OutOfDateConfigurationNeeded" => Some(
+            "apply the latest BIOS and microcode update, then re-register the platform if \
+             the PCCS caches PCK certificates",
+        ),
+        _ => Some("see Intel's TCB status documentation"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unparsable_quote_is_reported() {
+        let finding = check_certification(b"not a quote").unwrap_err();
+        assert!(finding.problem.contains("could not be parsed"));
+    }
+
+    #[test]
+    fn up_to_date_needs_no_hint() {
+        assert!(tcb_hint("UpToDate").is_none());
+        assert!(tcb_hint("OutOfDate").unwrap().contains("microcode"));
+    }
+}
@@ -1,14 +1,29 @@
+use std::error::Error as StdError;
+use std::fmt;
 use thiserror::Error;
 
+/// The underlying cause of a [`ProviderError`], from a library or a lower
+/// layer.
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Messages describe what failed; the cause stays reachable as the error's
+/// source rather than being formatted in, so log it with [`ProviderError::chain`].
 #[derive(Error, Debug)]
 pub enum ProviderError {
-    #[error("IO error: {0}")]
+    #[error("IO error")]
     IOError(#[from] std::io::Error),
 
     #[error("Quote parsing error: {0}")]
     QuoteParseError(String),
 
-    #[error("Base64 decode error: {0}")]
+    #[error("Failed to parse {what}")]
+    QuoteDecodeError {
+        what: &'static str,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Base64 decode error")]
     Base64Error(#[from] base64::DecodeError),
 
     #[error("PPID mismatch")]
@@ -18,20 +33,49 @@ pub enum ProviderError {
     PublicKeyError(String),
 
     #[error("Quote verification failed")]
-    QuoteVerificationError,
+    QuoteVerificationError(#[source] BoxError),
 
     #[error("DCAP error")]
-    DcapError,
+    DcapError(#[source] Box<ProviderError>),
 
     #[error("Network error: {0}")]
     NetworkError(String),
 
+    #[error("Fetching collateral from {endpoint} failed")]
+    CollateralError {
+        endpoint: String,
+        #[source]
+        source: BoxError,
+    },
+
     #[error("Serialization error: {0}")]
     SerializationError(String),
 
     #[error("Crypto error: {0}")]
     CryptoError(String),
 
+    #[error("Policy violation: {0}")]
+    PolicyViolation(String),
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Invalid request nonce: {0}")]
+    InvalidNonce(String),
+
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("{0} self-test check(s) failed")]
+    SelfTestFailed(usize),
+
+    #[error("Self-test stage {stage} failed")]
+    SelfTestStage {
+        stage: &'static str,
+        #[source]
+        source: Box<ProviderError>,
+    },
+
     #[error("restart required: permission denied {context}")]
     RestartRequired {
         context: String,
@@ -40,8 +84,85 @@ pub enum ProviderError {
     },
 }
 
+impl ProviderError {
+    /// This error followed by its causes, `outer: cause: root cause`.
+    pub fn chain(&self) -> ErrorChain<'_> {
+        ErrorChain(self)
+    }
+
+    /// A stable name for what went wrong, for grouping errors in metrics.
+    /// Errors wrapped by `DcapError` and self-test stages are named by their
+    /// cause.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::IOError(_) => "io",
+            ProviderError::QuoteParseError(_) | ProviderError::QuoteDecodeError { .. } => {
+                "quote_parse"
+            }
+            ProviderError::Base64Error(_) | ProviderError::SerializationError(_) => {
+                "malformed_request"
+            }
+            ProviderError::PPIDMismatch => "ppid_mismatch",
+            ProviderError::PublicKeyError(_) => "public_key",
+            ProviderError::QuoteVerificationError(_) => "quote_verification",
+            ProviderError::DcapError(source) | ProviderError::SelfTestStage { source, .. } => {
+                source.kind()
+            }
+            ProviderError::NetworkError(_) => "network",
+            ProviderError::CollateralError { .. } => "collateral_unavailable",
+            ProviderError::CryptoError(_) => "crypto",
+            ProviderError::PolicyViolation(_) => "policy_violation",
+            ProviderError::RateLimited(_) => "rate_limited",
+            ProviderError::InvalidNonce(_) => "invalid_nonce",
+            ProviderError::ConfigError(_) => "config",
+            ProviderError::SelfTestFailed(_) => "self_test",
+            ProviderError::RestartRequired { .. } => "restart_required",
+        }
+    }
+
+    /// `what` (a quote) did not decode; `source` says why.
+    pub fn quote_decode(what: &'static str, source: impl Into<BoxError>) -> Self {
+        ProviderError::QuoteDecodeError {
+            what,
+            source: source.into(),
+        }
+    }
+}
+
+pub struct ErrorChain<'a>(&'a (dyn StdError + 'static));
+
+impl fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut cause = self.0.source();
+        while let Some(error) = cause {
+            write!(f, ": {}", error)?;
+            cause = error.source();
+        }
+        Ok(())
+    }
+}
+
 impl From<serde_json::Error> for ProviderError {
     fn from(e: serde_json::Error) -> Self {
         ProviderError::SerializationError(e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_shows_every_cause_once() {
+        let io = std::io::Error::new(std::io::ErrorKind::TimedOut, "connect timed out");
+        let error = ProviderError::DcapError(Box::new(ProviderError::CollateralError {
+            endpoint: "https://pccs.example".into(),
+            source: io.into(),
+        }));
+        assert_eq!(
+            error.chain().to_string(),
+            "DCAP error: Fetching collateral from https://pccs.example failed: connect timed out"
+        );
+    }
+}
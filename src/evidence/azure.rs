@@ -0,0 +1,218 @@
+//! Azure TDX confidential VMs. Their TD runs Microsoft's paravisor, so the
+//! guest cannot ask for a quote itself: it writes its report data to the
+//! vTPM, reads back the paravisor's HCL report, and has IMDS turn the TD
+//! report in it into a quote. The quote's report data is the SHA-256 of the
+//! HCL report's runtime data, a JSON document that carries the guest's 64
+//! bytes as `user-data` and the vTPM's attestation key as `HCLAkPub`.
+//!
+//! MRTD and the RTMRs measure the paravisor, not the guest, so two guests on
+//! the same paravisor build only differ in their vTPM PCRs. Those are covered
+//! by an optional vTPM quote, which must be signed by `HCLAkPub` and asked
+//! for with `SHA-256(user-data)` as qualifying data.
+
+use super::tpm::TpmQuote;
+use super::{rsa_jwk_key, Evidence, EvidenceKind, TdxEvidence};
+use crate::crypto::{backend, constant_time_eq};
+use crate::error::ProviderError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rsa::RsaPublicKey;
+use serde::Deserialize;
+
+pub(super) const ENVELOPE_TYPE: &str = "azure-tdx";
+const DERIVE_LABEL: &[u8] = b"azure-tdx/v1";
+const AK_KID: &str = "HCLAkPub";
+
+/// What the guest sends in place of a quote, with every field base64.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    quote: String,
+    runtime_data: String,
+    vtpm_quote: Option<String>,
+    vtpm_signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RuntimeData {
+    keys: Vec<Jwk>,
+    #[serde(rename = "user-data")]
+    user_data: String,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+/// A TDX quote from IMDS with the runtime data it commits to.
+pub struct AzureTdxEvidence {
+    /// The quote itself, as the verifier takes it.
+    pub raw_quote: Vec<u8>,
+    pub tdx: TdxEvidence,
+    report_data: Vec<u8>,
+    ak: RsaPublicKey,
+    vtpm: Option<TpmQuote>,
+    measurements: Vec<u8>,
+}
+
+impl AzureTdxEvidence {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let envelope: Envelope = serde_json::from_slice(bytes)
+            .map_err(|e| ProviderError::quote_decode("Azure TDX evidence", e))?;
+        if envelope.kind != ENVELOPE_TYPE {
+            return Err(ProviderError::QuoteParseError(format!(
+                "Unknown evidence type {}",
+                envelope.kind
+            )));
+        }
+        let decode = |what: &'static str, value: &str| {
+            STANDARD
+                .decode(value)
+                .map_err(|e| ProviderError::quote_decode(what, e))
+        };
+
+        let raw_quote = decode("quote", &envelope.quote)?;
+        let tdx = TdxEvidence::parse(&raw_quote)?;
+        let runtime_data = decode("runtime_data", &envelope.runtime_data)?;
+        let committed = backend().sha256(&[&runtime_data]);
+        if !constant_time_eq(&committed, &tdx.report_data()[..32]) {
+            return Err(ProviderError::QuoteParseError(
+                "Quote does not commit to the runtime data".into(),
+            ));
+        }
+
+        let runtime: RuntimeData = serde_json::from_slice(&runtime_data)
+            .map_err(|e| ProviderError::quote_decode("runtime_data", e))?;
+        let report_data = hex::decode(&runtime.user_data)
+            .ok()
+            .filter(|user_data| user_data.len() == 64)
+            .ok_or_else(|| {
+                ProviderError::QuoteParseError("user-data must be 64 bytes of hex".into())
+            })?;
+        let ak = attestation_key(&runtime.keys)?;
+
+        let vtpm = match (&envelope.vtpm_quote, &envelope.vtpm_signature) {
+            (Some(quote), Some(signature)) => Some(TpmQuote::parse(
+                decode("vtpm_quote", quote)?,
+                &decode("vtpm_signature", signature)?,
+            )?),
+            (None, None) => None,
+            _ => {
+                return Err(ProviderError::QuoteParseError(
+                    "vtpm_quote and vtpm_signature come together".into(),
+                ))
+            }
+        };
+
+        let mut measurements = DERIVE_LABEL.to_vec();
+        measurements.extend_from_slice(tdx.measurements());
+        if let Some(vtpm) = &vtpm {
+            measurements.extend_from_slice(&vtpm.pcr_select);
+            measurements.extend_from_slice(&vtpm.pcr_digest);
+        }
+        Ok(Self {
+            raw_quote,
+            tdx,
+            report_data,
+            ak,
+            vtpm,
+            measurements,
+        })
+    }
+
+    /// Checks the vTPM quote, if there is one, against `HCLAkPub` and the
+    /// user data. Without one, `required` refuses the evidence.
+    pub fn check_vtpm(&self, required: bool) -> Result<bool, ProviderError> {
+        let Some(vtpm) = &self.vtpm else {
+            if required {
+                return Err(ProviderError::PolicyViolation(
+                    "Azure TDX evidence has no vTPM quote".into(),
+                ));
+            }
+            return Ok(false);
+        };
+        vtpm.verify(&self.ak)?;
+        let expected = backend().sha256(&[&self.report_data]);
+        if !constant_time_eq(&expected, &vtpm.extra_data) {
+            return Err(ProviderError::QuoteVerificationError(
+                "vTPM quote is not qualified by the user data".into(),
+            ));
+        }
+        Ok(true)
+    }
+}
+
+impl Evidence for AzureTdxEvidence {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::AzureTdx
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.report_data
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        self.tdx.platform_id()
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        self.tdx.launch_measurement()
+    }
+
+    fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+        let mut named = self.tdx.reference_measurements();
+        if let Some(vtpm) = &self.vtpm {
+            named.push(("pcr_digest", &vtpm.pcr_digest));
+        }
+        named
+    }
+}
+
+/// The RSA key named `HCLAkPub` in the runtime data's JWK set.
+fn attestation_key(keys: &[Jwk]) -> Result<RsaPublicKey, ProviderError> {
+    let jwk = keys
+        .iter()
+        .find(|jwk| jwk.kid == AK_KID && jwk.kty == "RSA")
+        .ok_or_else(|| ProviderError::QuoteParseError("Runtime data has no HCLAkPub".into()))?;
+    match (&jwk.n, &jwk.e) {
+        (Some(n), Some(e)) => rsa_jwk_key(n, e),
+        _ => Err(ProviderError::QuoteParseError("Malformed HCLAkPub".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_must_commit_to_its_runtime_data() {
+        let quote = include_bytes!("../../quotes/tdxQuote.txt");
+        let runtime_data = br#"{"keys":[],"user-data":"00"}"#;
+        let envelope = serde_json::json!({
+            "type": ENVELOPE_TYPE,
+            "quote": STANDARD.encode(quote),
+            "runtime_data": STANDARD.encode(runtime_data),
+        });
+
+        let err = AzureTdxEvidence::parse(envelope.to_string().as_bytes())
+            .err()
+            .unwrap();
+
+        assert!(err.to_string().contains("runtime data"));
+        assert_eq!(
+            EvidenceKind::detect(envelope.to_string().as_bytes()),
+            EvidenceKind::AzureTdx
+        );
+        assert!(attestation_key(&[]).is_err());
+    }
+}
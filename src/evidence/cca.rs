@@ -0,0 +1,391 @@
+//! ARM CCA attestation tokens, as a realm gets them from configfs-tsm: a
+//! CBOR collection (tag 399) of a platform token, signed by the platform's
+//! attestation key (CPAK), and a realm token, signed by a realm attestation
+//! key (RAK) the platform vouches for. Both are COSE_Sign1 with ES384. The
+//! platform token's challenge is the hash of the RAK, which ties the two
+//! together; the realm token's challenge is the 64 bytes the realm chose.
+//!
+//! Only built with the `cca` feature.
+
+use super::{Evidence, EvidenceKind};
+use crate::config::CcaConfig;
+use crate::crypto::constant_time_eq;
+use crate::error::ProviderError;
+use coset::cbor::value::Value;
+use coset::{iana, Algorithm, CborSerializable, CoseKey, CoseSign1, Label, TaggedCborSerializable};
+use log::info;
+use p384::ecdsa::signature::Verifier;
+use p384::ecdsa::{Signature, VerifyingKey};
+use p384::pkcs8::DecodePublicKey;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::fs;
+
+const COLLECTION_TAG: u64 = 399;
+const PLATFORM_TOKEN: i128 = 44234;
+const REALM_TOKEN: i128 = 44241;
+
+const CHALLENGE: i128 = 10;
+const PLATFORM_INSTANCE_ID: i128 = 256;
+const PLATFORM_LIFECYCLE: i128 = 2395;
+const PLATFORM_IMPLEMENTATION_ID: i128 = 2396;
+const REALM_PERSONALIZATION_VALUE: i128 = 44235;
+const REALM_INITIAL_MEASUREMENT: i128 = 44238;
+const REALM_EXTENSIBLE_MEASUREMENTS: i128 = 44239;
+const REALM_PUBLIC_KEY_HASH_ALGO: i128 = 44240;
+const REALM_PUBLIC_KEY: i128 = 44237;
+
+const DERIVE_LABEL: &[u8] = b"cca-realm/v1";
+const REM_COUNT: usize = 4;
+/// PSA security lifecycle states 0x3000-0x30ff are "secured".
+const LIFECYCLE_SECURED: u64 = 0x3000;
+
+/// A parsed, not yet verified, CCA attestation token.
+pub struct CcaToken {
+    platform: CoseSign1,
+    realm: CoseSign1,
+    rak: Vec<u8>,
+    rak_hash_algo: String,
+    platform_challenge: Vec<u8>,
+    challenge: Vec<u8>,
+    instance_id: Vec<u8>,
+    implementation_id: Vec<u8>,
+    lifecycle: u64,
+    rim: Vec<u8>,
+    measurements: Vec<u8>,
+}
+
+impl CcaToken {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let collection: Value = coset::cbor::de::from_reader(bytes)
+            .map_err(|e| ProviderError::quote_decode("CCA token", e))?;
+        let tokens = match collection {
+            Value::Tag(COLLECTION_TAG, inner) => match *inner {
+                Value::Map(tokens) => tokens,
+                _ => return Err(malformed("collection is not a map")),
+            },
+            _ => return Err(malformed("not a token collection")),
+        };
+        let platform = sign1(bytes_claim(&tokens, PLATFORM_TOKEN, "platform token")?)?;
+        let realm = sign1(bytes_claim(&tokens, REALM_TOKEN, "realm token")?)?;
+
+        let claims = payload(&platform)?;
+        let platform_challenge = bytes_claim(&claims, CHALLENGE, "platform challenge")?.to_vec();
+        let instance_id = bytes_claim(&claims, PLATFORM_INSTANCE_ID, "instance ID")?.to_vec();
+        let implementation_id =
+            bytes_claim(&claims, PLATFORM_IMPLEMENTATION_ID, "implementation ID")?.to_vec();
+        let lifecycle = claim(&claims, PLATFORM_LIFECYCLE)
+            .and_then(Value::as_integer)
+            .and_then(|lifecycle| u64::try_from(lifecycle).ok())
+            .ok_or_else(|| malformed("no security lifecycle"))?;
+
+        let claims = payload(&realm)?;
+        let challenge = bytes_claim(&claims, CHALLENGE, "realm challenge")?.to_vec();
+        if challenge.len() != 64 {
+            return Err(malformed("realm challenge must be 64 bytes"));
+        }
+        let rak = bytes_claim(&claims, REALM_PUBLIC_KEY, "realm public key")?.to_vec();
+        let rak_hash_algo = claim(&claims, REALM_PUBLIC_KEY_HASH_ALGO)
+            .and_then(Value::as_text)
+            .ok_or_else(|| malformed("no realm public key hash algorithm"))?
+            .to_string();
+        let rim = bytes_claim(&claims, REALM_INITIAL_MEASUREMENT, "RIM")?.to_vec();
+        let rpv = bytes_claim(&claims, REALM_PERSONALIZATION_VALUE, "RPV")?;
+        let rems = claim(&claims, REALM_EXTENSIBLE_MEASUREMENTS)
+            .and_then(Value::as_array)
+            .filter(|rems| rems.len() == REM_COUNT)
+            .ok_or_else(|| malformed("realm token must carry 4 REMs"))?;
+
+        let mut measurements = DERIVE_LABEL.to_vec();
+        measurements.extend_from_slice(&rim);
+        for rem in rems {
+            let rem = rem
+                .as_bytes()
+                .ok_or_else(|| malformed("REM is not bytes"))?;
+            measurements.extend_from_slice(rem);
+        }
+        measurements.extend_from_slice(rpv);
+
+        Ok(Self {
+            platform,
+            realm,
+            rak,
+            rak_hash_algo,
+            platform_challenge,
+            challenge,
+            instance_id,
+            implementation_id,
+            lifecycle,
+            rim,
+            measurements,
+        })
+    }
+
+    pub fn implementation_id(&self) -> &[u8] {
+        &self.implementation_id
+    }
+
+    /// Whether the platform's security lifecycle is "secured", rather than
+    /// being provisioned or debuggable.
+    pub fn is_secured(&self) -> bool {
+        self.lifecycle & !0xff == LIFECYCLE_SECURED
+    }
+}
+
+impl Evidence for CcaToken {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::CcaToken
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.challenge
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        &self.instance_id
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        &self.rim
+    }
+}
+
+/// Checks CCA tokens against the CPAKs of the platforms the provider trusts.
+pub struct CcaVerifier {
+    cpaks: Vec<VerifyingKey>,
+}
+
+impl CcaVerifier {
+    /// The verifier for the `[cca]` settings, or `None` when realms are not
+    /// served.
+    pub fn from_config(config: &CcaConfig) -> Result<Option<Self>, ProviderError> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let path = config
+            .cpaks
+            .as_ref()
+            .ok_or_else(|| ProviderError::ConfigError("cca.cpaks is required".into()))?;
+        let pem = fs::read_to_string(path)?;
+        let cpaks = pem
+            .split_inclusive("-----END PUBLIC KEY-----")
+            .filter(|block| block.contains("-----BEGIN PUBLIC KEY-----"))
+            .map(|block| {
+                VerifyingKey::from_public_key_pem(block.trim()).map_err(|e| {
+                    ProviderError::ConfigError(format!("Invalid CPAK in {}: {}", path.display(), e))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if cpaks.is_empty() {
+            return Err(ProviderError::ConfigError(
+                "cca.cpaks holds no P-384 public keys".into(),
+            ));
+        }
+        info!("Serving ARM CCA realms on {} platforms", cpaks.len());
+        Ok(Some(Self { cpaks }))
+    }
+
+    /// Checks that a trusted CPAK signed the platform token, that the
+    /// platform token vouches for the RAK, and that the RAK signed the realm
+    /// token.
+    pub fn verify(&self, token: &CcaToken) -> Result<(), ProviderError> {
+        if !self
+            .cpaks
+            .iter()
+            .any(|cpak| verify_sign1(&token.platform, cpak).is_ok())
+        {
+            return Err(invalid("Platform token is not signed by a trusted CPAK"));
+        }
+
+        let rak_hash = match token.rak_hash_algo.as_str() {
+            "sha-256" => Sha256::digest(&token.rak).to_vec(),
+            "sha-384" => Sha384::digest(&token.rak).to_vec(),
+            "sha-512" => Sha512::digest(&token.rak).to_vec(),
+            other => {
+                return Err(invalid(format!(
+                    "Realm public key hash algorithm {} is not supported",
+                    other
+                )))
+            }
+        };
+        if !constant_time_eq(&rak_hash, &token.platform_challenge) {
+            return Err(invalid("Platform token does not vouch for the realm key"));
+        }
+
+        verify_sign1(&token.realm, &realm_key(&token.rak)?)
+            .map_err(|_| invalid("Realm token signature does not verify"))
+    }
+}
+
+/// The RAK, either a SEC1 point or a COSE_Key depending on the RMM version.
+fn realm_key(rak: &[u8]) -> Result<VerifyingKey, ProviderError> {
+    if rak.first() == Some(&0x04) {
+        return VerifyingKey::from_sec1_bytes(rak).map_err(|_| invalid("Invalid realm public key"));
+    }
+    let key = CoseKey::from_slice(rak).map_err(|_| invalid("Invalid realm public key"))?;
+    let param = |label: iana::Ec2KeyParameter| {
+        key.params
+            .iter()
+            .find(|(l, _)| *l == Label::Int(label as i64))
+            .and_then(|(_, value)| value.as_bytes())
+    };
+    let (Some(x), Some(y)) = (
+        param(iana::Ec2KeyParameter::X),
+        param(iana::Ec2KeyParameter::Y),
+    ) else {
+        return Err(invalid("Realm public key is not an EC2 key"));
+    };
+    let mut point = vec![0x04];
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&point).map_err(|_| invalid("Invalid realm public key"))
+}
+
+fn verify_sign1(sign1: &CoseSign1, key: &VerifyingKey) -> Result<(), ProviderError> {
+    if sign1.protected.header.alg != Some(Algorithm::Assigned(iana::Algorithm::ES384)) {
+        return Err(invalid("CCA tokens must be signed with ES384"));
+    }
+    sign1.verify_signature(b"", |signature, data| {
+        let signature = Signature::from_slice(signature).map_err(|_| invalid("Bad signature"))?;
+        key.verify(data, &signature)
+            .map_err(|_| invalid("Bad signature"))
+    })
+}
+
+fn sign1(bytes: &[u8]) -> Result<CoseSign1, ProviderError> {
+    CoseSign1::from_tagged_slice(bytes)
+        .or_else(|_| CoseSign1::from_slice(bytes))
+        .map_err(|e| ProviderError::quote_decode("CCA COSE_Sign1", e))
+}
+
+fn payload(sign1: &CoseSign1) -> Result<Vec<(Value, Value)>, ProviderError> {
+    let payload = sign1
+        .payload
+        .as_deref()
+        .ok_or_else(|| malformed("token has no payload"))?;
+    match coset::cbor::de::from_reader(payload) {
+        Ok(Value::Map(claims)) => Ok(claims),
+        _ => Err(malformed("claims are not a map")),
+    }
+}
+
+fn claim(map: &[(Value, Value)], key: i128) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|k| i128::from(k) == key))
+        .map(|(_, value)| value)
+}
+
+fn bytes_claim<'a>(
+    map: &'a [(Value, Value)],
+    key: i128,
+    what: &str,
+) -> Result<&'a [u8], ProviderError> {
+    claim(map, key)
+        .and_then(Value::as_bytes)
+        .map(Vec::as_slice)
+        .ok_or_else(|| malformed(&format!("no {}", what)))
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+fn malformed(reason: &str) -> ProviderError {
+    ProviderError::QuoteParseError(format!("Malformed CCA token: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::CoseSign1Builder;
+    use p384::ecdsa::signature::Signer;
+    use p384::ecdsa::SigningKey;
+
+    fn signed(claims: Vec<(Value, Value)>, key: &SigningKey) -> Vec<u8> {
+        let mut payload = Vec::new();
+        coset::cbor::ser::into_writer(&Value::Map(claims), &mut payload).unwrap();
+        let protected = coset::HeaderBuilder::new()
+            .algorithm(iana::Algorithm::ES384)
+            .build();
+        CoseSign1Builder::new()
+            .protected(protected)
+            .payload(payload)
+            .create_signature(b"", |data| {
+                let signature: Signature = key.sign(data);
+                signature.to_bytes().to_vec()
+            })
+            .build()
+            .to_tagged_vec()
+            .unwrap()
+    }
+
+    #[test]
+    fn token_verifies_against_its_cpak() {
+        let cpak = SigningKey::random(&mut rand::thread_rng());
+        let rak = SigningKey::random(&mut rand::thread_rng());
+        let rak_public = rak
+            .verifying_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec();
+        let int = |key: i128| Value::Integer(key.try_into().unwrap());
+
+        let platform = signed(
+            vec![
+                (
+                    int(CHALLENGE),
+                    Value::Bytes(Sha384::digest(&rak_public).to_vec()),
+                ),
+                (int(PLATFORM_INSTANCE_ID), Value::Bytes(vec![1; 33])),
+                (int(PLATFORM_IMPLEMENTATION_ID), Value::Bytes(vec![2; 32])),
+                (int(PLATFORM_LIFECYCLE), int(0x3000)),
+            ],
+            &cpak,
+        );
+        let realm = signed(
+            vec![
+                (int(CHALLENGE), Value::Bytes(vec![0x42; 64])),
+                (int(REALM_PUBLIC_KEY), Value::Bytes(rak_public)),
+                (
+                    int(REALM_PUBLIC_KEY_HASH_ALGO),
+                    Value::Text("sha-384".into()),
+                ),
+                (int(REALM_INITIAL_MEASUREMENT), Value::Bytes(vec![3; 48])),
+                (int(REALM_PERSONALIZATION_VALUE), Value::Bytes(vec![0; 64])),
+                (
+                    int(REALM_EXTENSIBLE_MEASUREMENTS),
+                    Value::Array(vec![Value::Bytes(vec![0; 48]); REM_COUNT]),
+                ),
+            ],
+            &rak,
+        );
+        let collection = Value::Tag(
+            COLLECTION_TAG,
+            Box::new(Value::Map(vec![
+                (int(PLATFORM_TOKEN), Value::Bytes(platform)),
+                (int(REALM_TOKEN), Value::Bytes(realm)),
+            ])),
+        );
+        let mut bytes = Vec::new();
+        coset::cbor::ser::into_writer(&collection, &mut bytes).unwrap();
+
+        assert_eq!(EvidenceKind::detect(&bytes), EvidenceKind::CcaToken);
+        let token = CcaToken::parse(&bytes).unwrap();
+        assert_eq!(token.report_data(), [0x42; 64]);
+        assert_eq!(token.launch_measurement(), [3; 48]);
+        assert!(token.is_secured());
+
+        let trusted = CcaVerifier {
+            cpaks: vec![*cpak.verifying_key()],
+        };
+        assert!(trusted.verify(&token).is_ok());
+        let other = SigningKey::random(&mut rand::thread_rng());
+        let untrusted = CcaVerifier {
+            cpaks: vec![*other.verifying_key()],
+        };
+        assert!(untrusted.verify(&token).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Attestation tokens of GCP Confidential Space on TDX machines: OIDC tokens
+//! (RS256 JWTs) that Google's attestation verifier issues after checking the
+//! TD's quote and event log. The provider trusts Google's verdict instead of
+//! the quote, so it checks the token's signature against Google's published
+//! keys and maps its claims onto a measurement: the hardware model, the
+//! software (`CONFIDENTIAL_SPACE`) and the workload's container image.
+//!
+//! The token carries the guest's 64 bytes of report data as two `eat_nonce`
+//! entries of 32 bytes hex each, since a single nonce is capped at 74 bytes.
+
+use super::jwt::{self, JwkSetCache, Jwt};
+use super::{Evidence, EvidenceKind};
+use crate::config::GcpConfig;
+use crate::error::ProviderError;
+use log::info;
+use serde::Deserialize;
+
+const DERIVE_LABEL: &[u8] = b"gcp-tdx/v1";
+const HW_MODEL_TDX: &str = "GCP_INTEL_TDX";
+const DEBUG_DISABLED: &str = "disabled-since-boot";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn as_slice(&self) -> &[String] {
+        match self {
+            OneOrMany::One(value) => std::slice::from_ref(value),
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    aud: OneOrMany,
+    exp: u64,
+    #[serde(default)]
+    nbf: u64,
+    eat_nonce: OneOrMany,
+    hwmodel: String,
+    swname: String,
+    #[serde(default)]
+    dbgstat: String,
+    #[serde(default)]
+    submods: Submods,
+}
+
+#[derive(Default, Deserialize)]
+struct Submods {
+    #[serde(default)]
+    container: Option<Container>,
+    #[serde(default)]
+    gce: Option<Gce>,
+}
+
+#[derive(Deserialize)]
+struct Container {
+    image_digest: String,
+}
+
+#[derive(Deserialize)]
+struct Gce {
+    project_id: String,
+    instance_id: String,
+}
+
+/// A parsed, not yet verified, attestation token.
+pub struct GcpToken {
+    jwt: Jwt,
+    claims: Claims,
+    report_data: Vec<u8>,
+    measurements: Vec<u8>,
+    launch_measurement: Vec<u8>,
+}
+
+impl GcpToken {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let jwt = Jwt::parse(bytes)?;
+        let claims: Claims = jwt.claims()?;
+
+        let report_data = match claims.eat_nonce.as_slice() {
+            [first, second] => hex::decode(format!("{}{}", first, second))
+                .ok()
+                .filter(|report_data| report_data.len() == 64),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            ProviderError::QuoteParseError(
+                "eat_nonce must be the report data as two 32-byte hex nonces".into(),
+            )
+        })?;
+
+        let image_digest = claims
+            .submods
+            .container
+            .as_ref()
+            .map_or("", |container| container.image_digest.as_str());
+        let mut measurements = DERIVE_LABEL.to_vec();
+        for field in [
+            claims.hwmodel.as_str(),
+            claims.swname.as_str(),
+            image_digest,
+        ] {
+            measurements.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            measurements.extend_from_slice(field.as_bytes());
+        }
+        let launch_measurement = image_digest
+            .strip_prefix("sha256:")
+            .and_then(|digest| hex::decode(digest).ok())
+            .unwrap_or_default();
+
+        Ok(Self {
+            jwt,
+            claims,
+            report_data,
+            measurements,
+            launch_measurement,
+        })
+    }
+
+    pub fn hwmodel(&self) -> &str {
+        &self.claims.hwmodel
+    }
+
+    pub fn swname(&self) -> &str {
+        &self.claims.swname
+    }
+
+    pub fn project_id(&self) -> Option<&str> {
+        self.claims
+            .submods
+            .gce
+            .as_ref()
+            .map(|gce| gce.project_id.as_str())
+    }
+
+    pub fn image_digest(&self) -> Option<&str> {
+        self.claims
+            .submods
+            .container
+            .as_ref()
+            .map(|container| container.image_digest.as_str())
+    }
+
+    /// Whether the VM could be debugged at some point since boot.
+    pub fn is_debuggable(&self) -> bool {
+        self.claims.dbgstat != DEBUG_DISABLED
+    }
+}
+
+impl Evidence for GcpToken {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::GcpToken
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.report_data
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        self.claims
+            .submods
+            .gce
+            .as_ref()
+            .map(|gce| gce.instance_id.as_bytes())
+            .unwrap_or_default()
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        &self.launch_measurement
+    }
+}
+
+/// Checks tokens against the issuer's signing keys, which are fetched from
+/// `jwks_url` and kept in memory.
+pub struct GcpVerifier {
+    issuer: String,
+    audience: String,
+    keys: JwkSetCache,
+}
+
+impl GcpVerifier {
+    /// The verifier for the `[gcp]` settings, or `None` when tokens are not
+    /// accepted.
+    pub fn from_config(config: &GcpConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        info!(
+            "Accepting attestation tokens from {} for audience {}",
+            config.issuer, config.audience
+        );
+        Some(Self {
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            keys: JwkSetCache::new(&config.jwks_url),
+        })
+    }
+
+    /// Checks the token's signature, issuer, audience, lifetime and that it
+    /// is for a TDX machine.
+    pub async fn verify(&self, token: &GcpToken) -> Result<(), ProviderError> {
+        let key = self.keys.key(&token.jwt.header.kid).await?;
+        token.jwt.verify(&key, &["RS256"])?;
+
+        let claims = &token.claims;
+        if claims.iss != self.issuer {
+            return Err(invalid(format!(
+                "Token issuer {} is not trusted",
+                claims.iss
+            )));
+        }
+        if !claims.aud.as_slice().contains(&self.audience) {
+            return Err(invalid("Token is for another audience"));
+        }
+        jwt::check_lifetime(claims.exp, claims.nbf)?;
+        if claims.hwmodel != HW_MODEL_TDX {
+            return Err(invalid(format!(
+                "Token is for {}, not a TDX machine",
+                claims.hwmodel
+            )));
+        }
+        info!("Attestation token verified ({})", claims.swname);
+        Ok(())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use serde_json::json;
+
+    #[test]
+    fn claims_map_to_report_data_and_measurements() {
+        let claims = json!({
+            "iss": "https://confidentialcomputing.googleapis.com",
+            "aud": "gramine-sealing-key-provider",
+            "exp": 2,
+            "eat_nonce": ["11".repeat(32), "22".repeat(32)],
+            "hwmodel": HW_MODEL_TDX,
+            "swname": "CONFIDENTIAL_SPACE",
+            "dbgstat": DEBUG_DISABLED,
+            "submods": {
+                "container": { "image_digest": format!("sha256:{}", "ab".repeat(32)) },
+                "gce": { "project_id": "p", "instance_id": "42" },
+            },
+        });
+        let token = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","kid":"k"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string()),
+            URL_SAFE_NO_PAD.encode(b"sig")
+        );
+
+        let parsed = GcpToken::parse(token.as_bytes()).unwrap();
+
+        assert_eq!(
+            EvidenceKind::detect(token.as_bytes()),
+            EvidenceKind::GcpToken
+        );
+        assert_eq!(&parsed.report_data()[..32], &[0x11; 32]);
+        assert_eq!(&parsed.report_data()[32..], &[0x22; 32]);
+        assert_eq!(parsed.launch_measurement(), &[0xab; 32]);
+        assert_eq!(parsed.project_id(), Some("p"));
+        assert!(!parsed.is_debuggable());
+        assert!(parsed.measurements().starts_with(DERIVE_LABEL));
+    }
+}
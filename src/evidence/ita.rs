@@ -0,0 +1,318 @@
+//! Intel Trust Authority attestation tokens: JWTs (PS384) that Trust
+//! Authority issues after appraising a TD's quote. The provider trusts the
+//! service's verdict instead of the quote, so it checks the token's
+//! signature against the keys Trust Authority publishes and takes the TD's
+//! measurements, report data and TCB status from the token's `tdx` claims.
+//!
+//! The measurements are those of the quote, MRTD and RTMR0-3, behind a label
+//! of their own: a TD can get a token from anywhere, so it must not derive
+//! the key it would get by presenting its quote on this platform.
+
+use super::jwt::{self, JwkSetCache, Jwt};
+use super::{decode_hex, Evidence, EvidenceKind};
+use crate::config::ItaConfig;
+use crate::corim::LAUNCH_MEASUREMENT;
+use crate::error::ProviderError;
+use crate::protocol::PlatformTcb;
+use log::info;
+use serde::Deserialize;
+
+const DERIVE_LABEL: &[u8] = b"ita-tdx/v1";
+const MEASUREMENT_LEN: usize = 48;
+/// The claim that holds the appraisal of a TD's quote.
+pub(super) const TDX_CLAIMS: &str = "tdx";
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    exp: u64,
+    #[serde(default)]
+    nbf: u64,
+    tdx: TdxClaims,
+}
+
+#[derive(Deserialize)]
+struct TdxClaims {
+    tdx_mrtd: String,
+    tdx_rtmr0: String,
+    tdx_rtmr1: String,
+    tdx_rtmr2: String,
+    tdx_rtmr3: String,
+    tdx_report_data: String,
+    #[serde(default)]
+    tdx_is_debuggable: bool,
+    attester_tcb_status: String,
+    #[serde(default)]
+    attester_advisory_ids: Vec<String>,
+}
+
+/// A parsed, not yet verified, Trust Authority token.
+pub struct ItaToken {
+    jwt: Jwt,
+    claims: Claims,
+    report_data: Vec<u8>,
+    measurements: Vec<u8>,
+}
+
+impl ItaToken {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let jwt = Jwt::parse(bytes)?;
+        let claims: Claims = jwt.claims()?;
+
+        let tdx = &claims.tdx;
+        let mut measurements = DERIVE_LABEL.to_vec();
+        for (name, value) in [
+            ("tdx_mrtd", &tdx.tdx_mrtd),
+            ("tdx_rtmr0", &tdx.tdx_rtmr0),
+            ("tdx_rtmr1", &tdx.tdx_rtmr1),
+            ("tdx_rtmr2", &tdx.tdx_rtmr2),
+            ("tdx_rtmr3", &tdx.tdx_rtmr3),
+        ] {
+            measurements.extend_from_slice(&decode_hex(name, value, MEASUREMENT_LEN)?);
+        }
+        let report_data = decode_hex("tdx_report_data", &tdx.tdx_report_data, 64)?;
+
+        Ok(Self {
+            jwt,
+            claims,
+            report_data,
+            measurements,
+        })
+    }
+
+    pub fn mrtd(&self) -> &[u8] {
+        &self.measurements[DERIVE_LABEL.len()..][..MEASUREMENT_LEN]
+    }
+
+    /// The TCB status Trust Authority appraised the quote's platform at.
+    pub fn tcb_status(&self) -> &str {
+        &self.claims.tdx.attester_tcb_status
+    }
+
+    pub fn is_debuggable(&self) -> bool {
+        self.claims.tdx.tdx_is_debuggable
+    }
+}
+
+impl Evidence for ItaToken {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::ItaToken
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.report_data
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        // Trust Authority does not pass the PPID on
+        &[]
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        self.mrtd()
+    }
+
+    fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+        let mut named = vec![(LAUNCH_MEASUREMENT, self.launch_measurement())];
+        let names = ["mrtd", "rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+        named.extend(
+            names
+                .into_iter()
+                .zip(self.measurements[DERIVE_LABEL.len()..].chunks(MEASUREMENT_LEN)),
+        );
+        named
+    }
+}
+
+/// Checks tokens against Trust Authority's signing keys, which are fetched
+/// from `jwks_url` and kept in memory.
+pub struct ItaVerifier {
+    issuer: String,
+    keys: JwkSetCache,
+}
+
+impl ItaVerifier {
+    /// The verifier for the `[ita]` settings, or `None` when tokens are not
+    /// accepted.
+    pub fn from_config(config: &ItaConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        info!("Accepting Trust Authority tokens from {}", config.issuer);
+        Some(Self {
+            issuer: config.issuer.clone(),
+            keys: JwkSetCache::new(&config.jwks_url),
+        })
+    }
+
+    /// Checks the token's signature, issuer and lifetime, and returns the
+    /// TCB level Trust Authority found.
+    pub async fn verify(&self, token: &ItaToken) -> Result<PlatformTcb, ProviderError> {
+        let key = self.keys.key(&token.jwt.header.kid).await?;
+        token.jwt.verify(&key, &["PS384", "RS256"])?;
+
+        let claims = &token.claims;
+        if claims.iss != self.issuer {
+            return Err(ProviderError::QuoteVerificationError(
+                format!("Token issuer {} is not trusted", claims.iss).into(),
+            ));
+        }
+        jwt::check_lifetime(claims.exp, claims.nbf)?;
+        info!(
+            "Trust Authority token verified (TCB {})",
+            claims.tdx.attester_tcb_status
+        );
+        Ok(PlatformTcb {
+            status: claims.tdx.attester_tcb_status.clone(),
+            advisory_ids: claims.tdx.attester_advisory_ids.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use rsa::{Pkcs1v15Sign, Pss, RsaPrivateKey};
+    use serde_json::json;
+    use sha2::{Digest, Sha384};
+    use std::collections::HashMap;
+
+    const ISSUER: &str = "https://portal.trustauthority.intel.com";
+
+    fn token(key: &RsaPrivateKey, alg: &str, kid: &str, iss: &str, exp: u64) -> String {
+        let claims = json!({
+            "iss": iss,
+            "exp": exp,
+            "tdx": {
+                "tdx_mrtd": "aa".repeat(48),
+                "tdx_rtmr0": "00".repeat(48),
+                "tdx_rtmr1": "01".repeat(48),
+                "tdx_rtmr2": "02".repeat(48),
+                "tdx_rtmr3": "03".repeat(48),
+                "tdx_report_data": "11".repeat(64),
+                "attester_tcb_status": "UpToDate",
+            },
+        });
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(json!({ "alg": alg, "kid": kid }).to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let digest = Sha384::digest(signed.as_bytes());
+        let signature = match alg {
+            "PS384" => key.sign_with_rng(&mut rand::thread_rng(), Pss::new::<Sha384>(), &digest),
+            _ => key.sign(Pkcs1v15Sign::new::<Sha384>(), &digest),
+        }
+        .unwrap();
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn signed_token(key: &RsaPrivateKey, alg: &str, kid: &str, iss: &str, exp: u64) -> ItaToken {
+        ItaToken::parse(token(key, alg, kid, iss, exp).as_bytes()).unwrap()
+    }
+
+    fn now() -> u64 {
+        crate::audit::unix_now()
+    }
+
+    #[test]
+    fn tdx_claims_map_to_measurements() {
+        let claims = json!({
+            "iss": "Intel Trust Authority",
+            "exp": 2,
+            "tdx": {
+                "tdx_mrtd": "aa".repeat(48),
+                "tdx_rtmr0": "00".repeat(48),
+                "tdx_rtmr1": "01".repeat(48),
+                "tdx_rtmr2": "02".repeat(48),
+                "tdx_rtmr3": "03".repeat(48),
+                "tdx_report_data": "11".repeat(64),
+                "tdx_is_debuggable": false,
+                "attester_tcb_status": "UpToDate",
+            },
+        });
+        let token = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(br#"{"alg":"PS384","kid":"k"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string()),
+            URL_SAFE_NO_PAD.encode(b"sig")
+        );
+
+        let parsed = ItaToken::parse(token.as_bytes()).unwrap();
+
+        assert_eq!(
+            EvidenceKind::detect(token.as_bytes()),
+            EvidenceKind::ItaToken
+        );
+        assert_eq!(parsed.report_data(), &[0x11; 64]);
+        assert_eq!(parsed.mrtd(), &[0xaa; 48]);
+        assert_eq!(parsed.tcb_status(), "UpToDate");
+        assert!(parsed.measurements().starts_with(DERIVE_LABEL));
+        assert_eq!(parsed.reference_measurements()[5], ("rtmr3", &[3; 48][..]));
+    }
+
+    #[tokio::test]
+    async fn only_current_tokens_signed_by_the_issuer_verify() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let verifier = ItaVerifier {
+            issuer: ISSUER.into(),
+            // Nothing listens there, so any fetch would fail differently
+            keys: JwkSetCache::with_keys(
+                "https://127.0.0.1:1/certs",
+                HashMap::from([("k".to_string(), key.to_public_key())]),
+            ),
+        };
+        let rejected = |result: Result<PlatformTcb, ProviderError>| {
+            matches!(result, Err(ProviderError::QuoteVerificationError(_)))
+        };
+        let exp = now() + 300;
+
+        let tcb = verifier
+            .verify(&signed_token(&key, "PS384", "k", ISSUER, exp))
+            .await
+            .unwrap();
+        assert_eq!(tcb.status, "UpToDate");
+
+        let genuine = token(&key, "PS384", "k", ISSUER, exp);
+        let (signed, signature) = genuine.rsplit_once('.').unwrap();
+        let mut signature = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        signature[0] ^= 1;
+        let forged = format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature));
+        let forged = ItaToken::parse(forged.as_bytes()).unwrap();
+        assert!(rejected(verifier.verify(&forged).await));
+        // RS384 verifies as a JWS, but Trust Authority does not use it
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(&key, "RS384", "k", ISSUER, exp))
+                .await
+        ));
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(
+                    &key,
+                    "PS384",
+                    "k",
+                    "https://attacker.example",
+                    exp
+                ))
+                .await
+        ));
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(&key, "PS384", "k", ISSUER, now() - 3600))
+                .await
+        ));
+        // An unknown key right after a fetch is refused without another
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(&key, "PS384", "other", ISSUER, exp))
+                .await
+        ));
+    }
+}
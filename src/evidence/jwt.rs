@@ -0,0 +1,272 @@
+//! What the attestation token kinds have in common: tokens are compact JWS
+//! signed with RSA, by keys their issuer publishes as a JWK set, either as
+//! `n` and `e` or as an `x5c` certificate.
+
+use super::rsa_jwk_key;
+use crate::error::ProviderError;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::debug;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, Pss, RsaPublicKey};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+
+/// Clock skew tolerated on `exp` and `nbf`.
+const LEEWAY_SECS: u64 = 60;
+/// Keys are fetched again this often, or when a token names an unknown one.
+const JWKS_REFRESH: Duration = Duration::from_secs(3600);
+/// Fetches are at least this far apart, so tokens naming made-up keys cannot
+/// make every request a fetch.
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+
+/// Whether `bytes` look like a JWT, whose header starts with `{"`.
+pub(super) fn is_token(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"eyJ")
+}
+
+/// The top-level claims of what looks like a JWT, unverified, to tell the
+/// token kinds apart.
+pub(super) fn peek_claims(bytes: &[u8]) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let claims = std::str::from_utf8(bytes).ok()?.trim().split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).ok()?).ok()
+}
+
+#[derive(Deserialize)]
+pub(super) struct Header {
+    pub alg: String,
+    #[serde(default)]
+    pub kid: String,
+}
+
+/// A JWT split into its parts, not yet verified.
+pub(super) struct Jwt {
+    pub header: Header,
+    claims: Vec<u8>,
+    signed: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Jwt {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let token = std::str::from_utf8(bytes)
+            .map_err(|e| ProviderError::quote_decode("attestation token", e))?
+            .trim();
+        let mut parts = token.split('.');
+        let (Some(header_part), Some(claims_part), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ProviderError::QuoteParseError(
+                "Attestation token is not a JWT".into(),
+            ));
+        };
+        let decode = |what: &'static str, part: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|e| ProviderError::quote_decode(what, e))
+        };
+        let header = serde_json::from_slice(&decode("token header", header_part)?)
+            .map_err(|e| ProviderError::quote_decode("token header", e))?;
+        Ok(Self {
+            header,
+            claims: decode("token claims", claims_part)?,
+            signed: token[..header_part.len() + 1 + claims_part.len()]
+                .as_bytes()
+                .to_vec(),
+            signature: decode("token signature", signature)?,
+        })
+    }
+
+    pub fn claims<T: DeserializeOwned>(&self) -> Result<T, ProviderError> {
+        serde_json::from_slice(&self.claims)
+            .map_err(|e| ProviderError::quote_decode("token claims", e))
+    }
+
+    /// Checks the signature with `key`, if the token is signed with one of
+    /// `algorithms`.
+    pub fn verify(&self, key: &RsaPublicKey, algorithms: &[&str]) -> Result<(), ProviderError> {
+        let alg = self.header.alg.as_str();
+        if !algorithms.contains(&alg) {
+            return Err(invalid(format!(
+                "Token algorithm {} is not one of {}",
+                alg,
+                algorithms.join(", ")
+            )));
+        }
+        let verified = match alg {
+            "RS256" => key.verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(&self.signed),
+                &self.signature,
+            ),
+            "RS384" => key.verify(
+                Pkcs1v15Sign::new::<Sha384>(),
+                &Sha384::digest(&self.signed),
+                &self.signature,
+            ),
+            "PS256" => key.verify(
+                Pss::new::<Sha256>(),
+                &Sha256::digest(&self.signed),
+                &self.signature,
+            ),
+            "PS384" => key.verify(
+                Pss::new::<Sha384>(),
+                &Sha384::digest(&self.signed),
+                &self.signature,
+            ),
+            alg => return Err(invalid(format!("Token algorithm {} is not supported", alg))),
+        };
+        verified.map_err(|_| invalid("Token signature does not verify"))
+    }
+}
+
+/// Checks `exp` and `nbf` against the clock, with some leeway.
+pub(super) fn check_lifetime(exp: u64, nbf: u64) -> Result<(), ProviderError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now > exp + LEEWAY_SECS || nbf > now + LEEWAY_SECS {
+        return Err(invalid("Token is expired or not yet valid"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    n: Option<String>,
+    e: Option<String>,
+    #[serde(default)]
+    x5c: Vec<String>,
+}
+
+impl Jwk {
+    /// The key, if it is an RSA key; other kinds have neither `n` nor an
+    /// RSA certificate.
+    fn rsa_key(&self) -> Option<RsaPublicKey> {
+        if let (Some(n), Some(e)) = (&self.n, &self.e) {
+            return rsa_jwk_key(n, e).ok();
+        }
+        // The signing certificate comes first; whoever publishes the set
+        // vouches for it, so the rest of the chain is not needed
+        let cert = Certificate::from_der(&STANDARD.decode(self.x5c.first()?).ok()?).ok()?;
+        let spki = cert.tbs_certificate.subject_public_key_info.to_der().ok()?;
+        RsaPublicKey::from_public_key_der(&spki).ok()
+    }
+}
+
+/// An issuer's signing keys, fetched from `url` and kept in memory.
+pub(super) struct JwkSetCache {
+    url: String,
+    client: reqwest::Client,
+    keys: Mutex<Keys>,
+}
+
+#[derive(Default)]
+struct Keys {
+    /// When the keys were fetched, and when a fetch was last tried.
+    fetched: Option<Instant>,
+    attempted: Option<Instant>,
+    keys: HashMap<String, RsaPublicKey>,
+}
+
+impl JwkSetCache {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+            keys: Mutex::new(Keys::default()),
+        }
+    }
+
+    /// A cache that starts out with `keys`, as if just fetched.
+    #[cfg(test)]
+    pub fn with_keys(url: &str, keys: HashMap<String, RsaPublicKey>) -> Self {
+        let now = Some(Instant::now());
+        Self {
+            keys: Mutex::new(Keys {
+                fetched: now,
+                attempted: now,
+                keys,
+            }),
+            ..Self::new(url)
+        }
+    }
+
+    /// The key `kid`, from the cache while it is fresh. Otherwise the keys
+    /// are fetched again, unless that was tried within `JWKS_MIN_REFETCH`;
+    /// then a stale key still serves and an unknown one stays unknown.
+    pub async fn key(&self, kid: &str) -> Result<RsaPublicKey, ProviderError> {
+        {
+            let mut keys = self.lock();
+            let fresh = keys
+                .fetched
+                .is_some_and(|fetched| fetched.elapsed() < JWKS_REFRESH);
+            let throttled = keys
+                .attempted
+                .is_some_and(|attempted| attempted.elapsed() < JWKS_MIN_REFETCH);
+            if fresh || throttled {
+                if let Some(key) = keys.keys.get(kid) {
+                    return Ok(key.clone());
+                }
+            }
+            if throttled {
+                return Err(unknown_key(kid));
+            }
+            keys.attempted = Some(Instant::now());
+        }
+
+        debug!("Fetching token signing keys from {}", self.url);
+        let fetch_error = |e: reqwest::Error| ProviderError::CollateralError {
+            endpoint: self.url.clone(),
+            source: e.into(),
+        };
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(fetch_error)?
+            .bytes()
+            .await
+            .map_err(fetch_error)?;
+        let set: JwkSet = serde_json::from_slice(&body)?;
+        let fetched: HashMap<String, RsaPublicKey> = set
+            .keys
+            .iter()
+            .filter_map(|jwk| Some((jwk.kid.clone(), jwk.rsa_key()?)))
+            .collect();
+
+        let mut keys = self.lock();
+        keys.fetched = Some(Instant::now());
+        keys.keys = fetched;
+        keys.keys.get(kid).cloned().ok_or_else(|| unknown_key(kid))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Keys> {
+        self.keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn unknown_key(kid: &str) -> ProviderError {
+    invalid(format!("Token signing key {} is unknown", kid))
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
@@ -0,0 +1,308 @@
+//! Microsoft Azure Attestation tokens for TDX confidential VMs: JWTs (RS256)
+//! that an MAA instance issues after appraising the quote of an Azure TD
+//! and the runtime data it commits to. The provider trusts the instance's
+//! verdict instead of the quote, so it checks the token's signature against
+//! the keys the instance publishes at `/certs` and takes the TD's
+//! measurements and report data from the token's claims.
+//!
+//! As with [`super::AzureTdxEvidence`], MRTD and the RTMRs measure
+//! Microsoft's paravisor rather than the guest. The guest's 64 bytes of
+//! report data are the `user-data` of the runtime data, passed on as the
+//! `x-ms-runtime` claim.
+
+use super::jwt::{self, JwkSetCache, Jwt};
+use super::{decode_hex, Evidence, EvidenceKind};
+use crate::config::MaaConfig;
+use crate::corim::LAUNCH_MEASUREMENT;
+use crate::error::ProviderError;
+use log::info;
+use serde::Deserialize;
+
+const DERIVE_LABEL: &[u8] = b"maa-tdx/v1";
+const MEASUREMENT_LEN: usize = 48;
+/// The claim that names what an MAA token attests.
+pub(super) const ATTESTATION_TYPE: &str = "x-ms-attestation-type";
+const TDX_VM: &str = "tdxvm";
+
+#[derive(Deserialize)]
+struct Claims {
+    iss: String,
+    exp: u64,
+    #[serde(default)]
+    nbf: u64,
+    #[serde(rename = "x-ms-attestation-type")]
+    attestation_type: String,
+    tdx_mrtd: String,
+    tdx_rtmr0: String,
+    tdx_rtmr1: String,
+    tdx_rtmr2: String,
+    tdx_rtmr3: String,
+    #[serde(default)]
+    tdx_td_attributes_debug: bool,
+    #[serde(rename = "x-ms-runtime")]
+    runtime: Runtime,
+}
+
+#[derive(Deserialize)]
+struct Runtime {
+    #[serde(rename = "user-data")]
+    user_data: String,
+    #[serde(rename = "vm-configuration", default)]
+    vm_configuration: Option<VmConfiguration>,
+}
+
+#[derive(Deserialize)]
+struct VmConfiguration {
+    #[serde(rename = "vmUniqueId")]
+    vm_unique_id: String,
+}
+
+/// A parsed, not yet verified, MAA token.
+pub struct MaaToken {
+    jwt: Jwt,
+    claims: Claims,
+    report_data: Vec<u8>,
+    measurements: Vec<u8>,
+}
+
+impl MaaToken {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let jwt = Jwt::parse(bytes)?;
+        let claims: Claims = jwt.claims()?;
+
+        let mut measurements = DERIVE_LABEL.to_vec();
+        for (name, value) in [
+            ("tdx_mrtd", &claims.tdx_mrtd),
+            ("tdx_rtmr0", &claims.tdx_rtmr0),
+            ("tdx_rtmr1", &claims.tdx_rtmr1),
+            ("tdx_rtmr2", &claims.tdx_rtmr2),
+            ("tdx_rtmr3", &claims.tdx_rtmr3),
+        ] {
+            measurements.extend_from_slice(&decode_hex(name, value, MEASUREMENT_LEN)?);
+        }
+        let report_data = decode_hex("user-data", &claims.runtime.user_data, 64)?;
+
+        Ok(Self {
+            jwt,
+            claims,
+            report_data,
+            measurements,
+        })
+    }
+
+    pub fn mrtd(&self) -> &[u8] {
+        &self.measurements[DERIVE_LABEL.len()..][..MEASUREMENT_LEN]
+    }
+
+    pub fn is_debuggable(&self) -> bool {
+        self.claims.tdx_td_attributes_debug
+    }
+}
+
+impl Evidence for MaaToken {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::MaaToken
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.report_data
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        self.claims
+            .runtime
+            .vm_configuration
+            .as_ref()
+            .map(|vm| vm.vm_unique_id.as_bytes())
+            .unwrap_or_default()
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        self.mrtd()
+    }
+
+    fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+        let mut named = vec![(LAUNCH_MEASUREMENT, self.launch_measurement())];
+        let names = ["mrtd", "rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+        named.extend(
+            names
+                .into_iter()
+                .zip(self.measurements[DERIVE_LABEL.len()..].chunks(MEASUREMENT_LEN)),
+        );
+        named
+    }
+}
+
+/// Checks tokens against the signing keys of one MAA instance, which are
+/// fetched from its `/certs` and kept in memory.
+pub struct MaaVerifier {
+    instance: String,
+    keys: JwkSetCache,
+}
+
+impl MaaVerifier {
+    /// The verifier for the `[maa]` settings, or `None` when tokens are not
+    /// accepted.
+    pub fn from_config(config: &MaaConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        // Config::validate has checked that it is set
+        let instance = config
+            .instance
+            .as_deref()
+            .unwrap_or_default()
+            .trim_end_matches('/');
+        info!("Accepting MAA tokens from {}", instance);
+        Some(Self {
+            instance: instance.to_string(),
+            keys: JwkSetCache::new(&format!("{}/certs", instance)),
+        })
+    }
+
+    /// Checks the token's signature, issuer and lifetime, and that it is for
+    /// a TDX confidential VM.
+    pub async fn verify(&self, token: &MaaToken) -> Result<(), ProviderError> {
+        let key = self.keys.key(&token.jwt.header.kid).await?;
+        token.jwt.verify(&key, &["RS256"])?;
+
+        let claims = &token.claims;
+        if claims.iss.trim_end_matches('/') != self.instance {
+            return Err(invalid(format!(
+                "Token issuer {} is not trusted",
+                claims.iss
+            )));
+        }
+        jwt::check_lifetime(claims.exp, claims.nbf)?;
+        if claims.attestation_type != TDX_VM {
+            return Err(invalid(format!(
+                "Token is for {}, not a TDX confidential VM",
+                claims.attestation_type
+            )));
+        }
+        info!("MAA token verified");
+        Ok(())
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use rsa::{Pkcs1v15Sign, RsaPrivateKey};
+    use serde_json::{json, Value};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+
+    const INSTANCE: &str = "https://example.eus.attest.azure.net";
+
+    fn claims(iss: &str, exp: u64, attestation_type: &str) -> Value {
+        json!({
+            "iss": iss,
+            "exp": exp,
+            "x-ms-attestation-type": attestation_type,
+            "tdx_mrtd": "aa".repeat(48),
+            "tdx_rtmr0": "00".repeat(48),
+            "tdx_rtmr1": "01".repeat(48),
+            "tdx_rtmr2": "02".repeat(48),
+            "tdx_rtmr3": "03".repeat(48),
+            "tdx_report_data": "ff".repeat(64),
+            "x-ms-runtime": {
+                "user-data": "11".repeat(64),
+                "vm-configuration": { "vmUniqueId": "vm-1" },
+            },
+        })
+    }
+
+    /// A token for `claims`, signed RS256 with `key` under `kid`.
+    fn signed_token(key: &RsaPrivateKey, kid: &str, claims: &Value) -> MaaToken {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(json!({ "alg": "RS256", "kid": kid }).to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let digest = Sha256::digest(signed.as_bytes());
+        let signature = key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest).unwrap();
+        let token = format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature));
+        MaaToken::parse(token.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn tdx_claims_map_to_measurements() {
+        let token = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(br#"{"alg":"RS256","kid":"k"}"#),
+            URL_SAFE_NO_PAD.encode(claims(INSTANCE, 2, TDX_VM).to_string()),
+            URL_SAFE_NO_PAD.encode(b"sig")
+        );
+
+        let parsed = MaaToken::parse(token.as_bytes()).unwrap();
+
+        assert_eq!(
+            EvidenceKind::detect(token.as_bytes()),
+            EvidenceKind::MaaToken
+        );
+        assert_eq!(parsed.report_data(), &[0x11; 64]);
+        assert_eq!(parsed.mrtd(), &[0xaa; 48]);
+        assert_eq!(parsed.platform_id(), b"vm-1");
+        assert!(!parsed.is_debuggable());
+        assert!(parsed.measurements().starts_with(DERIVE_LABEL));
+    }
+
+    #[tokio::test]
+    async fn only_current_tdx_tokens_from_the_instance_verify() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let verifier = MaaVerifier {
+            instance: INSTANCE.into(),
+            // Nothing listens there, so any fetch would fail differently
+            keys: JwkSetCache::with_keys(
+                "https://127.0.0.1:1/certs",
+                HashMap::from([("k".to_string(), key.to_public_key())]),
+            ),
+        };
+        let rejected = |result: Result<(), ProviderError>| {
+            matches!(result, Err(ProviderError::QuoteVerificationError(_)))
+        };
+        let exp = crate::audit::unix_now() + 300;
+
+        let genuine = claims(&format!("{}/", INSTANCE), exp, TDX_VM);
+        assert!(verifier
+            .verify(&signed_token(&key, "k", &genuine))
+            .await
+            .is_ok());
+
+        let other_key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(&other_key, "k", &genuine))
+                .await
+        ));
+        let elsewhere = claims("https://attacker.example", exp, TDX_VM);
+        assert!(rejected(
+            verifier.verify(&signed_token(&key, "k", &elsewhere)).await
+        ));
+        let expired = claims(INSTANCE, crate::audit::unix_now() - 3600, TDX_VM);
+        assert!(rejected(
+            verifier.verify(&signed_token(&key, "k", &expired)).await
+        ));
+        let sev_snp = claims(INSTANCE, exp, "sevsnpvm");
+        assert!(rejected(
+            verifier.verify(&signed_token(&key, "k", &sev_snp)).await
+        ));
+        // An unknown key right after a fetch is refused without another
+        assert!(rejected(
+            verifier
+                .verify(&signed_token(&key, "other", &genuine))
+                .await
+        ));
+    }
+}
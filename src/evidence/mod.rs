@@ -0,0 +1,186 @@
+//! Attestation evidence from the kinds of TEE guests the provider serves.
+//!
+//! Each kind parses into an [`Evidence`], which is what key derivation,
+//! report data binding, the audit log and the rejection metrics need. How
+//! evidence is verified, and what ties it to a platform the provider trusts,
+//! differs per kind and stays with the request handler: TDX quotes go
+//! through the configured [`crate::verifier::Verifier`] and the PPID check,
+//! SEV-SNP reports through [`SnpVerifier`] and the `sev_snp` policy, Azure
+//! TDX evidence through the verifier and its vTPM quote, GCP attestation
+//! tokens through [`GcpVerifier`] and the `gcp` policy, Intel Trust
+//! Authority tokens through [`ItaVerifier`] and the `ita` policy, Azure
+//! Attestation tokens through [`MaaVerifier`] and the `maa` policy, and TPM
+//! quotes from VMs without a TEE through [`TpmVerifier`] and the `tpm`
+//! policy. ARM CCA
+//! realm tokens go through `CcaVerifier` and the `cca` policy, in builds
+//! with the `cca` feature; other builds recognize and refuse them.
+
+mod azure;
+#[cfg(feature = "cca")]
+mod cca;
+mod gcp;
+mod ita;
+mod jwt;
+mod maa;
+mod snp;
+mod tdx;
+mod tpm;
+
+use crate::corim::LAUNCH_MEASUREMENT;
+use crate::error::ProviderError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rsa::{BigUint, RsaPublicKey};
+use serde::Deserialize;
+
+pub use azure::AzureTdxEvidence;
+#[cfg(feature = "cca")]
+pub use cca::{CcaToken, CcaVerifier};
+pub use gcp::{GcpToken, GcpVerifier};
+pub use ita::{ItaToken, ItaVerifier};
+pub use maa::{MaaToken, MaaVerifier};
+pub use snp::{SnpReport, SnpTcb, SnpVerifier};
+pub use tdx::{extract_measurements, get_report_data, TdxEvidence};
+pub use tpm::{TpmEvidence, TpmVerifier};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvidenceKind {
+    Tdx,
+    SevSnp,
+    AzureTdx,
+    GcpToken,
+    ItaToken,
+    MaaToken,
+    Tpm,
+    CcaToken,
+}
+
+/// CBOR tag 399, the head of every ARM CCA token collection.
+const CCA_COLLECTION_HEAD: [u8; 3] = [0xd9, 0x01, 0x8f];
+
+/// The `type` every JSON evidence envelope names itself with.
+#[derive(Deserialize)]
+struct Tagged<'a> {
+    #[serde(rename = "type", borrow)]
+    kind: &'a str,
+}
+
+impl EvidenceKind {
+    /// Tells the kinds apart by layout: an SNP report is exactly
+    /// [`snp::REPORT_LEN`] bytes and starts with a 32-bit version, where a
+    /// TDX quote starts with a 16-bit version and the attestation key type.
+    /// Azure TDX and TPM evidence are JSON objects, so they start with `{`
+    /// and are told apart by their `type`; GCP, Trust Authority and MAA
+    /// tokens are JWTs, so they start with `eyJ`, and are told apart by
+    /// Trust Authority's `tdx` claims and MAA's `x-ms-attestation-type`; an
+    /// ARM CCA token is a CBOR tag 399.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if snp::is_report(bytes) {
+            EvidenceKind::SevSnp
+        } else if bytes.first() == Some(&b'{') {
+            match serde_json::from_slice::<Tagged>(bytes) {
+                Ok(tagged) if tagged.kind == tpm::ENVELOPE_TYPE => EvidenceKind::Tpm,
+                _ => EvidenceKind::AzureTdx,
+            }
+        } else if jwt::is_token(bytes) {
+            match jwt::peek_claims(bytes) {
+                Some(claims) if claims.contains_key(ita::TDX_CLAIMS) => EvidenceKind::ItaToken,
+                Some(claims) if claims.contains_key(maa::ATTESTATION_TYPE) => {
+                    EvidenceKind::MaaToken
+                }
+                _ => EvidenceKind::GcpToken,
+            }
+        } else if bytes.starts_with(&CCA_COLLECTION_HEAD) {
+            EvidenceKind::CcaToken
+        } else {
+            EvidenceKind::Tdx
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EvidenceKind::Tdx => "tdx",
+            EvidenceKind::SevSnp => "sev-snp",
+            EvidenceKind::AzureTdx => azure::ENVELOPE_TYPE,
+            EvidenceKind::GcpToken => "gcp-token",
+            EvidenceKind::ItaToken => "ita-token",
+            EvidenceKind::MaaToken => "maa-token",
+            EvidenceKind::Tpm => tpm::ENVELOPE_TYPE,
+            EvidenceKind::CcaToken => "cca",
+        }
+    }
+}
+
+/// Parsed, not yet verified, evidence.
+pub trait Evidence: Send + Sync {
+    fn kind(&self) -> EvidenceKind;
+
+    /// Input to key derivation. TDX keeps its original layout (MRTD followed
+    /// by RTMR0-3); every other kind starts with its own label, so two kinds
+    /// never derive the same key.
+    fn measurements(&self) -> &[u8];
+
+    /// The 64 bytes the guest chose, which commit to its public key.
+    fn report_data(&self) -> &[u8];
+
+    /// The hardware it comes from: the PPID of a TDX quote (also on Azure),
+    /// the chip ID of an SNP report, the instance ID in a GCP token, the VM
+    /// ID in an MAA token, a hash of the AK certificate of a TPM quote, the
+    /// platform instance ID in a CCA token. Empty for Trust Authority
+    /// tokens, which do not name it.
+    fn platform_id(&self) -> &[u8];
+
+    /// The measurement that names the guest's image in metrics and
+    /// webhooks: MRTD (also in a Trust Authority or MAA token), the SNP
+    /// launch measurement, the container image digest in a GCP token, the PCR
+    /// digest of a TPM quote, the realm initial measurement in a CCA token.
+    fn launch_measurement(&self) -> &[u8];
+
+    /// What reference values are compared with, by CoRIM `mkey`.
+    fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+        vec![(LAUNCH_MEASUREMENT, self.launch_measurement())]
+    }
+}
+
+/// Parses `bytes` as whichever kind of evidence they are.
+pub fn parse(bytes: &[u8]) -> Result<Box<dyn Evidence>, ProviderError> {
+    Ok(match EvidenceKind::detect(bytes) {
+        EvidenceKind::Tdx => Box::new(TdxEvidence::parse(bytes)?),
+        EvidenceKind::SevSnp => Box::new(SnpReport::parse(bytes)?),
+        EvidenceKind::AzureTdx => Box::new(AzureTdxEvidence::parse(bytes)?),
+        EvidenceKind::GcpToken => Box::new(GcpToken::parse(bytes)?),
+        EvidenceKind::ItaToken => Box::new(ItaToken::parse(bytes)?),
+        EvidenceKind::MaaToken => Box::new(MaaToken::parse(bytes)?),
+        EvidenceKind::Tpm => Box::new(TpmEvidence::parse(bytes)?),
+        #[cfg(feature = "cca")]
+        EvidenceKind::CcaToken => Box::new(CcaToken::parse(bytes)?),
+        #[cfg(not(feature = "cca"))]
+        EvidenceKind::CcaToken => {
+            return Err(ProviderError::QuoteParseError(
+                "ARM CCA tokens need a provider built with the `cca` feature".into(),
+            ))
+        }
+    })
+}
+
+/// An RSA public key from the base64url `n` and `e` of a JWK.
+fn rsa_jwk_key(n: &str, e: &str) -> Result<RsaPublicKey, ProviderError> {
+    let component = |value: &str| {
+        URL_SAFE_NO_PAD
+            .decode(value.trim_end_matches('='))
+            .map(|bytes| BigUint::from_bytes_be(&bytes))
+            .map_err(|e| ProviderError::quote_decode("JWK", e))
+    };
+    RsaPublicKey::new(component(n)?, component(e)?)
+        .map_err(|e| ProviderError::quote_decode("JWK", e))
+}
+
+/// The `len` bytes of the hex claim `name` of a token.
+fn decode_hex(name: &str, value: &str, len: usize) -> Result<Vec<u8>, ProviderError> {
+    hex::decode(value)
+        .ok()
+        .filter(|bytes| bytes.len() == len)
+        .ok_or_else(|| {
+            ProviderError::QuoteParseError(format!("{} must be {} bytes of hex", name, len))
+        })
+}
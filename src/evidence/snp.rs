@@ -0,0 +1,567 @@
+//! AMD SEV-SNP attestation reports (SNP firmware ABI, `ATTESTATION_REPORT`),
+//! verified against the VCEK that AMD's Key Distribution Service issues for
+//! the reporting chip and TCB.
+
+use super::{Evidence, EvidenceKind};
+use crate::config::SevSnpConfig;
+use crate::crypto::backend;
+use crate::error::ProviderError;
+use log::{debug, info, warn};
+use p384::ecdsa::signature::Verifier as _;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier as _;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::Sha384;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::Certificate;
+
+pub const REPORT_LEN: usize = 0x4a0;
+/// The signature covers everything before it.
+const SIGNED_LEN: usize = 0x2a0;
+const REPORT_DATA: std::ops::Range<usize> = 0x50..0x90;
+const MEASUREMENT: std::ops::Range<usize> = 0x90..0xc0;
+const HOST_DATA: std::ops::Range<usize> = 0xc0..0xe0;
+const CHIP_ID: std::ops::Range<usize> = 0x1a0..0x1e0;
+const POLICY_OFFSET: usize = 0x08;
+const SIGNATURE_ALGO_OFFSET: usize = 0x34;
+const FLAGS_OFFSET: usize = 0x48;
+const REPORTED_TCB_OFFSET: usize = 0x180;
+/// ECDSA P-384 with SHA-384.
+const SIGNATURE_ALGO_ECDSA_P384: u32 = 1;
+/// Guest policy bit that lets the host debug the guest.
+const POLICY_DEBUG: u64 = 1 << 19;
+/// Each signature component is a little-endian 72-byte field.
+const SIGNATURE_COMPONENT_LEN: usize = 72;
+const SCALAR_LEN: usize = 48;
+const DERIVE_LABEL: &[u8] = b"sev-snp/v1";
+const OID_HWID: &str = "1.3.6.1.4.1.3704.1.4";
+const OID_BOOTLOADER_SPL: &str = "1.3.6.1.4.1.3704.1.3.1";
+const OID_TEE_SPL: &str = "1.3.6.1.4.1.3704.1.3.2";
+const OID_SNP_SPL: &str = "1.3.6.1.4.1.3704.1.3.3";
+const OID_MICROCODE_SPL: &str = "1.3.6.1.4.1.3704.1.3.8";
+/// How long a chip and TCB the KDS gave no valid VCEK for is not asked
+/// about again.
+const VCEK_RETRY: Duration = Duration::from_secs(300);
+/// Failed lookups remembered before the oldest is forgotten.
+const MAX_FAILURES: usize = 1024;
+
+/// Whether `bytes` have the size and version of an attestation report.
+pub(super) fn is_report(bytes: &[u8]) -> bool {
+    bytes.len() == REPORT_LEN && (2..=5).contains(&read_u32(bytes, 0))
+}
+
+/// An SEV-SNP attestation report.
+pub struct SnpReport {
+    bytes: Vec<u8>,
+    measurements: Vec<u8>,
+}
+
+impl SnpReport {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        if !is_report(bytes) {
+            return Err(ProviderError::QuoteParseError(
+                "Not an SEV-SNP attestation report".into(),
+            ));
+        }
+        if read_u32(bytes, SIGNATURE_ALGO_OFFSET) != SIGNATURE_ALGO_ECDSA_P384 {
+            return Err(ProviderError::QuoteParseError(
+                "SEV-SNP report is not signed with ECDSA P-384".into(),
+            ));
+        }
+        // Bits 2-4 select the signing key; only the VCEK is supported, not a
+        // cloud provider's VLEK
+        if (read_u32(bytes, FLAGS_OFFSET) >> 2) & 0x7 != 0 {
+            return Err(ProviderError::QuoteParseError(
+                "SEV-SNP report is not signed by the VCEK".into(),
+            ));
+        }
+
+        let mut measurements = DERIVE_LABEL.to_vec();
+        measurements.extend_from_slice(&bytes[MEASUREMENT]);
+        measurements.extend_from_slice(&bytes[HOST_DATA]);
+        Ok(Self {
+            bytes: bytes.to_vec(),
+            measurements,
+        })
+    }
+
+    pub fn policy(&self) -> u64 {
+        read_u64(&self.bytes, POLICY_OFFSET)
+    }
+
+    pub fn is_debuggable(&self) -> bool {
+        self.policy() & POLICY_DEBUG != 0
+    }
+
+    pub fn chip_id(&self) -> &[u8] {
+        &self.bytes[CHIP_ID]
+    }
+
+    pub fn reported_tcb(&self) -> SnpTcb {
+        SnpTcb::from_raw(read_u64(&self.bytes, REPORTED_TCB_OFFSET))
+    }
+
+    /// `(r, s)` big-endian, from the report's little-endian fields. The
+    /// bytes past each scalar and the rest of the signature area must be
+    /// zero.
+    fn signature(&self) -> Result<p384::ecdsa::Signature, ProviderError> {
+        let malformed = || invalid("SEV-SNP report signature is malformed");
+        let component = |offset: usize| {
+            let field = &self.bytes[offset..offset + SIGNATURE_COMPONENT_LEN];
+            if field[SCALAR_LEN..].iter().any(|&b| b != 0) {
+                return Err(malformed());
+            }
+            let mut scalar = field[..SCALAR_LEN].to_vec();
+            scalar.reverse();
+            Ok(p384::FieldBytes::clone_from_slice(&scalar))
+        };
+        let r = component(SIGNED_LEN)?;
+        let s = component(SIGNED_LEN + SIGNATURE_COMPONENT_LEN)?;
+        if self.bytes[SIGNED_LEN + 2 * SIGNATURE_COMPONENT_LEN..]
+            .iter()
+            .any(|&b| b != 0)
+        {
+            return Err(malformed());
+        }
+        p384::ecdsa::Signature::from_scalars(r, s).map_err(|_| malformed())
+    }
+}
+
+impl Evidence for SnpReport {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::SevSnp
+    }
+
+    /// `"sev-snp/v1" || MEASUREMENT || HOST_DATA`: the launch digest and what
+    /// the host declared at launch, usually a hash of the guest's
+    /// configuration.
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.bytes[REPORT_DATA]
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        self.chip_id()
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        &self.bytes[MEASUREMENT]
+    }
+}
+
+/// Security patch levels of the SNP firmware components (the Milan and Genoa
+/// `TCB_VERSION` layout).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnpTcb {
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl SnpTcb {
+    fn from_raw(raw: u64) -> Self {
+        let bytes = raw.to_le_bytes();
+        Self {
+            bootloader: bytes[0],
+            tee: bytes[1],
+            snp: bytes[6],
+            microcode: bytes[7],
+        }
+    }
+
+    /// Whether every component is at least `min`'s.
+    pub fn meets(&self, min: &SnpTcb) -> bool {
+        self.bootloader >= min.bootloader
+            && self.tee >= min.tee
+            && self.snp >= min.snp
+            && self.microcode >= min.microcode
+    }
+}
+
+impl fmt::Display for SnpTcb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bootloader {}, tee {}, snp {}, microcode {}",
+            self.bootloader, self.tee, self.snp, self.microcode
+        )
+    }
+}
+
+/// Verifies report signatures up to AMD's root key. The ASK and ARK come
+/// from the `cert_chain` file AMD publishes for the product, checked once at
+/// startup; VCEKs are fetched from the KDS per chip and TCB and kept in
+/// memory, since the KDS rate-limits. So are failed fetches, for
+/// [`VCEK_RETRY`].
+pub struct SnpVerifier {
+    product: String,
+    kds_url: String,
+    ask_key: RsaPublicKey,
+    client: reqwest::Client,
+    vceks: Mutex<Vceks>,
+}
+
+#[derive(Default)]
+struct Vceks {
+    keys: HashMap<String, p384::ecdsa::VerifyingKey>,
+    failed: HashMap<String, Instant>,
+}
+
+impl SnpVerifier {
+    /// The verifier for the `[sev_snp]` settings, or `None` when SNP guests
+    /// are not served.
+    pub fn from_config(config: &SevSnpConfig) -> Result<Option<Self>, ProviderError> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let path = config
+            .cert_chain
+            .as_ref()
+            .ok_or_else(|| ProviderError::ConfigError("sev_snp.cert_chain is required".into()))?;
+        let pem = fs::read(path)?;
+        let ark_sha256 = config
+            .ark_sha256
+            .as_deref()
+            .ok_or_else(|| ProviderError::ConfigError("sev_snp.ark_sha256 is required".into()))?;
+        let verifier = Self::new(&config.product, &config.kds_url, &pem, ark_sha256)?;
+        info!("Serving SEV-SNP guests on {} hosts", config.product);
+        Ok(Some(verifier))
+    }
+
+    /// `cert_chain` is the PEM ASK and ARK, in either order; the ARK's DER
+    /// must hash to `ark_sha256`.
+    pub fn new(
+        product: &str,
+        kds_url: &str,
+        cert_chain: &[u8],
+        ark_sha256: &str,
+    ) -> Result<Self, ProviderError> {
+        let chain = Certificate::load_pem_chain(cert_chain).map_err(|e| {
+            ProviderError::ConfigError(format!("Invalid SEV-SNP certificate chain: {}", e))
+        })?;
+        let is_root =
+            |cert: &Certificate| cert.tbs_certificate.issuer == cert.tbs_certificate.subject;
+        let (Some(ark), Some(ask)) = (
+            chain.iter().find(|cert| is_root(cert)),
+            chain.iter().find(|cert| !is_root(cert)),
+        ) else {
+            return Err(ProviderError::ConfigError(
+                "The SEV-SNP certificate chain must hold the ASK and the ARK".into(),
+            ));
+        };
+        if !ark
+            .tbs_certificate
+            .subject
+            .to_string()
+            .contains(&format!("CN=ARK-{}", product))
+        {
+            return Err(ProviderError::ConfigError(format!(
+                "The SEV-SNP certificate chain is not AMD's for {}",
+                product
+            )));
+        }
+        let ark_der = ark
+            .to_der()
+            .map_err(|e| ProviderError::ConfigError(format!("Invalid AMD ARK: {}", e)))?;
+        if !hex::encode(backend().sha256(&[&ark_der])).eq_ignore_ascii_case(ark_sha256) {
+            return Err(ProviderError::ConfigError(format!(
+                "The SEV-SNP ARK is not the one sev_snp.ark_sha256 pins for {}",
+                product
+            )));
+        }
+        let ark_key = rsa_key(ark)?;
+        verify_rsa_pss(&ark_key, ark)?;
+        verify_rsa_pss(&ark_key, ask)?;
+
+        Ok(Self {
+            product: product.to_string(),
+            kds_url: kds_url.trim_end_matches('/').to_string(),
+            ask_key: rsa_key(ask)?,
+            client: reqwest::Client::new(),
+            vceks: Mutex::default(),
+        })
+    }
+
+    /// Checks the report's signature with the VCEK for its chip and reported
+    /// TCB, and returns that TCB. A malformed signature is refused before
+    /// the KDS is asked for the VCEK.
+    pub async fn verify(&self, report: &SnpReport) -> Result<SnpTcb, ProviderError> {
+        let tcb = report.reported_tcb();
+        let signature = report.signature()?;
+        let vcek = self.vcek(report.chip_id(), &tcb).await?;
+        check_signature(&vcek, report, &signature)?;
+        info!("SEV-SNP report verified (TCB {})", tcb);
+        Ok(tcb)
+    }
+
+    async fn vcek(
+        &self,
+        chip_id: &[u8],
+        tcb: &SnpTcb,
+    ) -> Result<p384::ecdsa::VerifyingKey, ProviderError> {
+        let key = format!("{}-{}", hex::encode(chip_id), tcb);
+        {
+            let vceks = self.lock();
+            if let Some(vcek) = vceks.keys.get(&key) {
+                debug!("Using cached VCEK");
+                return Ok(*vcek);
+            }
+            if let Some(failed_at) = vceks.failed.get(&key) {
+                if failed_at.elapsed() < VCEK_RETRY {
+                    return Err(invalid(
+                        "No valid VCEK for the report's chip and TCB (cached failure)",
+                    ));
+                }
+            }
+        }
+
+        match self.fetch_vcek(chip_id, tcb).await {
+            Ok(vcek) => {
+                let mut vceks = self.lock();
+                vceks.failed.remove(&key);
+                vceks.keys.insert(key, vcek);
+                Ok(vcek)
+            }
+            Err(e) => {
+                warn!(
+                    "No VCEK for chip {}; not asking again for {:?}",
+                    hex::encode(chip_id),
+                    VCEK_RETRY
+                );
+                self.lock().remember_failure(key);
+                Err(e)
+            }
+        }
+    }
+
+    async fn fetch_vcek(
+        &self,
+        chip_id: &[u8],
+        tcb: &SnpTcb,
+    ) -> Result<p384::ecdsa::VerifyingKey, ProviderError> {
+        let url = format!(
+            "{}/vcek/v1/{}/{}?blSPL={}&teeSPL={}&snpSPL={}&ucodeSPL={}",
+            self.kds_url,
+            self.product,
+            hex::encode(chip_id),
+            tcb.bootloader,
+            tcb.tee,
+            tcb.snp,
+            tcb.microcode
+        );
+        debug!("Fetching VCEK from {}", url);
+        let fetch_error = |e: reqwest::Error| ProviderError::CollateralError {
+            endpoint: self.kds_url.clone(),
+            source: e.into(),
+        };
+        let der = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(fetch_error)?
+            .bytes()
+            .await
+            .map_err(fetch_error)?;
+
+        let cert =
+            Certificate::from_der(&der).map_err(|e| invalid(format!("Invalid VCEK: {}", e)))?;
+        verify_rsa_pss(&self.ask_key, &cert)?;
+        check_vcek_names(&cert, chip_id, tcb)?;
+        p384::ecdsa::VerifyingKey::from_sec1_bytes(
+            cert.tbs_certificate
+                .subject_public_key_info
+                .subject_public_key
+                .raw_bytes(),
+        )
+        .map_err(|_| invalid("VCEK is not a P-384 key"))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vceks> {
+        self.vceks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Vceks {
+    fn remember_failure(&mut self, key: String) {
+        self.failed
+            .retain(|_, failed_at| failed_at.elapsed() < VCEK_RETRY);
+        if self.failed.len() >= MAX_FAILURES {
+            let oldest = self
+                .failed
+                .iter()
+                .min_by_key(|(_, failed_at)| **failed_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.failed.remove(&oldest);
+            }
+        }
+        self.failed.insert(key, Instant::now());
+    }
+}
+
+fn check_signature(
+    vcek: &p384::ecdsa::VerifyingKey,
+    report: &SnpReport,
+    signature: &p384::ecdsa::Signature,
+) -> Result<(), ProviderError> {
+    vcek.verify(&report.bytes[..SIGNED_LEN], signature)
+        .map_err(|_| invalid("SEV-SNP report signature does not verify"))
+}
+
+/// The VCEK must be the one for the report's chip and TCB, not merely some
+/// chip's.
+fn check_vcek_names(cert: &Certificate, chip_id: &[u8], tcb: &SnpTcb) -> Result<(), ProviderError> {
+    let expected_spls = [
+        (OID_BOOTLOADER_SPL, tcb.bootloader),
+        (OID_TEE_SPL, tcb.tee),
+        (OID_SNP_SPL, tcb.snp),
+        (OID_MICROCODE_SPL, tcb.microcode),
+    ];
+    let mut hwid_matched = false;
+    let mut spls_matched = 0;
+    for extension in cert.tbs_certificate.extensions.iter().flatten() {
+        let oid = extension.extn_id.to_string();
+        let value = extension.extn_value.as_bytes();
+        if oid == OID_HWID {
+            // Either the raw ID or an OCTET STRING of it, depending on the
+            // KDS version
+            let hwid = match value {
+                [0x04, 0x40, rest @ ..] => rest,
+                raw => raw,
+            };
+            hwid_matched = hwid == chip_id;
+        } else if let Some((_, expected)) = expected_spls.iter().find(|(spl, _)| *spl == oid) {
+            if u8::from_der(value).ok() == Some(*expected) {
+                spls_matched += 1;
+            }
+        }
+    }
+    if !hwid_matched || spls_matched != expected_spls.len() {
+        return Err(invalid("VCEK is not for the report's chip and TCB"));
+    }
+    Ok(())
+}
+
+fn rsa_key(cert: &Certificate) -> Result<RsaPublicKey, ProviderError> {
+    let spki = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| invalid(format!("Invalid AMD certificate key: {}", e)))?;
+    RsaPublicKey::from_public_key_der(&spki)
+        .map_err(|e| invalid(format!("Invalid AMD certificate key: {}", e)))
+}
+
+/// AMD signs its certificates with RSASSA-PSS, SHA-384 and a 48-byte salt.
+fn verify_rsa_pss(issuer: &RsaPublicKey, cert: &Certificate) -> Result<(), ProviderError> {
+    let tbs = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| invalid(format!("Invalid AMD certificate: {}", e)))?;
+    let signature = rsa::pss::Signature::try_from(cert.signature.raw_bytes())
+        .map_err(|_| invalid("Invalid AMD certificate signature"))?;
+    rsa::pss::VerifyingKey::<Sha384>::new(issuer.clone())
+        .verify(&tbs, &signature)
+        .map_err(|_| invalid("AMD certificate signature does not verify"))
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_fields_are_read_at_their_offsets() {
+        let mut bytes = vec![0u8; REPORT_LEN];
+        bytes[0] = 2;
+        bytes[SIGNATURE_ALGO_OFFSET] = 1;
+        bytes[POLICY_OFFSET + 2] = 0x0b; // reserved bit 16, SMT, debug
+        bytes[MEASUREMENT].fill(0xaa);
+        bytes[HOST_DATA].fill(0xbb);
+        bytes[CHIP_ID].fill(0xcc);
+        bytes[REPORTED_TCB_OFFSET..REPORTED_TCB_OFFSET + 8]
+            .copy_from_slice(&[3, 0, 0, 0, 0, 0, 8, 115]);
+
+        let report = SnpReport::parse(&bytes).unwrap();
+
+        assert_eq!(EvidenceKind::detect(&bytes), EvidenceKind::SevSnp);
+        assert!(report.is_debuggable());
+        assert_eq!(report.chip_id(), &[0xcc; 64]);
+        assert_eq!(
+            report.reported_tcb(),
+            SnpTcb {
+                bootloader: 3,
+                tee: 0,
+                snp: 8,
+                microcode: 115
+            }
+        );
+        assert!(report.measurements().starts_with(DERIVE_LABEL));
+        assert_eq!(report.measurements().len(), DERIVE_LABEL.len() + 48 + 32);
+
+        bytes[FLAGS_OFFSET] = 1 << 2; // signed by a VLEK
+        assert!(SnpReport::parse(&bytes).is_err());
+        assert_eq!(EvidenceKind::detect(&bytes[1..]), EvidenceKind::Tdx);
+    }
+
+    #[test]
+    fn reports_verify_only_as_signed_by_the_vcek() {
+        use p384::ecdsa::signature::Signer as _;
+
+        let signing_key = p384::ecdsa::SigningKey::random(&mut rand::thread_rng());
+        let vcek = *signing_key.verifying_key();
+        let mut bytes = vec![0u8; REPORT_LEN];
+        bytes[0] = 2;
+        bytes[SIGNATURE_ALGO_OFFSET] = 1;
+        bytes[MEASUREMENT].fill(0xaa);
+        let signature: p384::ecdsa::Signature = signing_key.sign(&bytes[..SIGNED_LEN]);
+        let (r, s) = signature.split_bytes();
+        for (offset, scalar) in [(SIGNED_LEN, r), (SIGNED_LEN + SIGNATURE_COMPONENT_LEN, s)] {
+            let field = &mut bytes[offset..offset + SCALAR_LEN];
+            field.copy_from_slice(&scalar);
+            field.reverse();
+        }
+        let verify = |bytes: &[u8]| {
+            let report = SnpReport::parse(bytes)?;
+            check_signature(&vcek, &report, &report.signature()?)
+        };
+
+        assert!(verify(&bytes).is_ok());
+        let mut tampered = bytes.clone();
+        tampered[MEASUREMENT.start] ^= 1;
+        assert!(verify(&tampered).is_err());
+        // Stray bytes past a scalar, or after the signature, are malformed
+        let mut padded = bytes.clone();
+        padded[SIGNED_LEN + SCALAR_LEN] = 1;
+        assert!(SnpReport::parse(&padded).unwrap().signature().is_err());
+        let mut padded = bytes;
+        padded[REPORT_LEN - 1] = 1;
+        assert!(SnpReport::parse(&padded).unwrap().signature().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Evidence, EvidenceKind};
+use crate::corim::LAUNCH_MEASUREMENT;
+use crate::error::ProviderError;
+use dcap_qvl::quote::{Quote, Report};
+use log::{debug, error};
+
+/// A TDX quote.
+pub struct TdxEvidence {
+    pub quote: Quote,
+    measurements: Vec<u8>,
+}
+
+impl TdxEvidence {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let quote = Quote::parse(bytes).map_err(|e| ProviderError::quote_decode("quote", e))?;
+        let measurements = extract_measurements(&quote)?;
+        Ok(Self {
+            quote,
+            measurements,
+        })
+    }
+}
+
+impl Evidence for TdxEvidence {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::Tdx
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        // A quote with measurements is a TD quote, which has report data
+        get_report_data(&self.quote).unwrap_or_default()
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        &self.quote.header.user_data[..16]
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        &self.measurements[..48]
+    }
+
+    fn reference_measurements(&self) -> Vec<(&'static str, &[u8])> {
+        let mut named = vec![(LAUNCH_MEASUREMENT, self.launch_measurement())];
+        let names = ["mrtd", "rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+        named.extend(names.into_iter().zip(self.measurements.chunks(48)));
+        named
+    }
+}
+
+pub fn extract_measurements(quote: &Quote) -> Result<Vec<u8>, ProviderError> {
+    let mut measurements = Vec::new();
+
+    match &quote.report {
+        Report::TD10(report) => {
+            debug!("Processing TD10 measurements");
+            measurements.extend_from_slice(&report.mr_td);
+            measurements.extend_from_slice(&report.rt_mr0);
+            measurements.extend_from_slice(&report.rt_mr1);
+            measurements.extend_from_slice(&report.rt_mr2);
+            measurements.extend_from_slice(&report.rt_mr3);
+        }
+        Report::TD15(report) => {
+            debug!("Processing TD15 measurements");
+            measurements.extend_from_slice(&report.base.mr_td);
+            measurements.extend_from_slice(&report.base.rt_mr0);
+            measurements.extend_from_slice(&report.base.rt_mr1);
+            measurements.extend_from_slice(&report.base.rt_mr2);
+            measurements.extend_from_slice(&report.base.rt_mr3);
+        }
+        _ => {
+            error!("Invalid report type for measurements");
+            return Err(ProviderError::QuoteParseError("Not a TDX quote".into()));
+        }
+    }
+
+    debug!("Extracted measurements: {} bytes", measurements.len());
+    Ok(measurements)
+}
+
+pub fn get_report_data(quote: &Quote) -> Result<&[u8], ProviderError> {
+    match &quote.report {
+        Report::TD10(report) => Ok(&report.report_data),
+        Report::TD15(report) => Ok(&report.base.report_data),
+        _ => Err(ProviderError::QuoteParseError("Not a TDX quote".into())),
+    }
+}
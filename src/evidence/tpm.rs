@@ -0,0 +1,552 @@
+//! TPM 2.0 quotes: a `TPMS_ATTEST` of type quote, and the attestation key's
+//! `TPMT_SIGNATURE` over it, as `tpm2_quote -m ... -s ...` writes them.
+//!
+//! Besides backing Azure's vTPM evidence, a quote is evidence of its own for
+//! VMs without a TEE: it comes with the AK's certificate chain, which must
+//! lead to a configured CA, and with the report data it is qualified by.
+//! Such a client is only as trustworthy as its host, which can read its
+//! memory, so it is refused unless the `tpm` policy admits it.
+
+use super::{Evidence, EvidenceKind};
+use crate::config::TpmConfig;
+use crate::crypto::{backend, constant_time_eq};
+use crate::error::ProviderError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::info;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::{BasicConstraints, KeyUsage};
+use x509_cert::Certificate;
+
+const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+const TPM_ALG_RSASSA: u16 = 0x0014;
+const TPM_ALG_SHA256: u16 = 0x000b;
+/// clock, resetCount, restartCount and safe of `TPMS_CLOCK_INFO`.
+const CLOCK_INFO_LEN: usize = 17;
+pub(super) const ENVELOPE_TYPE: &str = "tpm";
+const DERIVE_LABEL: &[u8] = b"tpm/v1";
+const OID_SHA256_WITH_RSA: &str = "1.2.840.113549.1.1.11";
+const OID_SHA384_WITH_RSA: &str = "1.2.840.113549.1.1.12";
+/// Longest AK certificate chain accepted, not counting the CA.
+const MAX_CHAIN_LEN: usize = 4;
+
+/// A parsed, not yet verified, TPM quote.
+pub struct TpmQuote {
+    attest: Vec<u8>,
+    signature: Vec<u8>,
+    /// The qualifying data the quote was asked for.
+    pub extra_data: Vec<u8>,
+    /// The `TPML_PCR_SELECTION` the quote covers, as encoded.
+    pub pcr_select: Vec<u8>,
+    /// Digest of the selected PCR values, in selection order.
+    pub pcr_digest: Vec<u8>,
+}
+
+impl TpmQuote {
+    pub fn parse(attest: Vec<u8>, signature: &[u8]) -> Result<Self, ProviderError> {
+        let mut reader = Reader(&attest);
+        if reader.u32()? != TPM_GENERATED_VALUE || reader.u16()? != TPM_ST_ATTEST_QUOTE {
+            return Err(malformed("not a quote"));
+        }
+        reader.tpm2b()?; // qualifiedSigner
+        let extra_data = reader.tpm2b()?.to_vec();
+        reader.take(CLOCK_INFO_LEN + 8)?; // clockInfo, firmwareVersion
+
+        let selection_start = attest.len() - reader.0.len();
+        for _ in 0..reader.u32()? {
+            reader.u16()?; // hash
+            let size = reader.take(1)?[0] as usize;
+            reader.take(size)?;
+        }
+        let selection_end = attest.len() - reader.0.len();
+        let pcr_digest = reader.tpm2b()?.to_vec();
+        if !reader.0.is_empty() {
+            return Err(malformed("trailing bytes"));
+        }
+
+        let mut reader = Reader(signature);
+        if reader.u16()? != TPM_ALG_RSASSA || reader.u16()? != TPM_ALG_SHA256 {
+            return Err(malformed("signature is not RSASSA with SHA-256"));
+        }
+        let signature = reader.tpm2b()?.to_vec();
+
+        Ok(Self {
+            pcr_select: attest[selection_start..selection_end].to_vec(),
+            attest,
+            signature,
+            extra_data,
+            pcr_digest,
+        })
+    }
+
+    /// Whether PCR `index` of the SHA-256 bank is covered.
+    pub fn selects_sha256(&self, index: u8) -> bool {
+        let mut reader = Reader(&self.pcr_select);
+        let Ok(count) = reader.u32() else {
+            return false;
+        };
+        for _ in 0..count {
+            let (Ok(hash), Ok(size)) = (reader.u16(), reader.take(1)) else {
+                return false;
+            };
+            let Ok(bitmap) = reader.take(size[0] as usize) else {
+                return false;
+            };
+            let byte = bitmap.get(index as usize / 8).copied().unwrap_or(0);
+            if hash == TPM_ALG_SHA256 && byte & (1 << (index % 8)) != 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks the signature against the attestation key `ak`.
+    pub fn verify(&self, ak: &RsaPublicKey) -> Result<(), ProviderError> {
+        let digest = Sha256::digest(&self.attest);
+        ak.verify(Pkcs1v15Sign::new::<Sha256>(), &digest, &self.signature)
+            .map_err(|e| ProviderError::QuoteVerificationError(e.into()))
+    }
+}
+
+/// What a client without a TEE sends in place of a quote, with every field
+/// base64 and the AK certificate first in its chain.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    report_data: String,
+    ak_cert_chain: Vec<String>,
+    quote: String,
+    signature: String,
+}
+
+/// A TPM quote from a client without a TEE.
+pub struct TpmEvidence {
+    quote: TpmQuote,
+    report_data: Vec<u8>,
+    chain: Vec<Certificate>,
+    platform_id: Vec<u8>,
+    measurements: Vec<u8>,
+}
+
+impl TpmEvidence {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ProviderError> {
+        let envelope: Envelope = serde_json::from_slice(bytes)
+            .map_err(|e| ProviderError::quote_decode("TPM evidence", e))?;
+        if envelope.kind != ENVELOPE_TYPE {
+            return Err(ProviderError::QuoteParseError(format!(
+                "Unknown evidence type {}",
+                envelope.kind
+            )));
+        }
+        let decode = |what: &'static str, value: &str| {
+            STANDARD
+                .decode(value)
+                .map_err(|e| ProviderError::quote_decode(what, e))
+        };
+
+        let report_data = decode("report_data", &envelope.report_data)?;
+        if report_data.len() != 64 {
+            return Err(ProviderError::QuoteParseError(
+                "report_data must be 64 bytes".into(),
+            ));
+        }
+        if envelope.ak_cert_chain.is_empty() || envelope.ak_cert_chain.len() > MAX_CHAIN_LEN {
+            return Err(ProviderError::QuoteParseError(format!(
+                "ak_cert_chain must hold 1 to {} certificates",
+                MAX_CHAIN_LEN
+            )));
+        }
+        let ak_der = decode("ak_cert_chain", &envelope.ak_cert_chain[0])?;
+        let chain = envelope
+            .ak_cert_chain
+            .iter()
+            .map(|cert| {
+                Certificate::from_der(&decode("ak_cert_chain", cert)?)
+                    .map_err(|e| ProviderError::quote_decode("ak_cert_chain", e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let quote = TpmQuote::parse(
+            decode("quote", &envelope.quote)?,
+            &decode("signature", &envelope.signature)?,
+        )?;
+
+        let mut measurements = DERIVE_LABEL.to_vec();
+        measurements.extend_from_slice(&quote.pcr_select);
+        measurements.extend_from_slice(&quote.pcr_digest);
+        Ok(Self {
+            platform_id: backend().sha256(&[&ak_der])[..16].to_vec(),
+            quote,
+            report_data,
+            chain,
+            measurements,
+        })
+    }
+
+    pub fn quote(&self) -> &TpmQuote {
+        &self.quote
+    }
+}
+
+impl Evidence for TpmEvidence {
+    fn kind(&self) -> EvidenceKind {
+        EvidenceKind::Tpm
+    }
+
+    fn measurements(&self) -> &[u8] {
+        &self.measurements
+    }
+
+    fn report_data(&self) -> &[u8] {
+        &self.report_data
+    }
+
+    fn platform_id(&self) -> &[u8] {
+        &self.platform_id
+    }
+
+    fn launch_measurement(&self) -> &[u8] {
+        &self.quote.pcr_digest
+    }
+}
+
+/// Checks TPM evidence against the CAs that issue AK certificates.
+pub struct TpmVerifier {
+    cas: Vec<Certificate>,
+}
+
+impl TpmVerifier {
+    /// The verifier for the `[tpm]` settings, or `None` when TPM clients are
+    /// not served.
+    pub fn from_config(config: &TpmConfig) -> Result<Option<Self>, ProviderError> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let path = config
+            .ak_ca_certs
+            .as_ref()
+            .ok_or_else(|| ProviderError::ConfigError("tpm.ak_ca_certs is required".into()))?;
+        let cas = Certificate::load_pem_chain(&fs::read(path)?).map_err(|e| {
+            ProviderError::ConfigError(format!("Invalid AK CA certificates: {}", e))
+        })?;
+        if cas.is_empty() {
+            return Err(ProviderError::ConfigError(
+                "tpm.ak_ca_certs holds no certificates".into(),
+            ));
+        }
+        info!("Serving TPM clients with AKs from {} CAs", cas.len());
+        Ok(Some(Self { cas }))
+    }
+
+    /// Checks that the AK certificate chains to a configured CA, that the AK
+    /// signed the quote, and that the quote is qualified by the report data.
+    pub fn verify(&self, evidence: &TpmEvidence) -> Result<(), ProviderError> {
+        self.verify_chain(&evidence.chain)?;
+        evidence.quote.verify(&rsa_key(&evidence.chain[0])?)?;
+        let expected = backend().sha256(&[&evidence.report_data]);
+        if !constant_time_eq(&expected, &evidence.quote.extra_data) {
+            return Err(invalid("TPM quote is not qualified by the report data"));
+        }
+        Ok(())
+    }
+
+    /// Checks that each certificate of `chain`, AK first, is valid now and
+    /// signed by the next one, or by a configured CA for the last, and that
+    /// each issuer may issue it.
+    fn verify_chain(&self, chain: &[Certificate]) -> Result<(), ProviderError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        for (i, cert) in chain.iter().enumerate() {
+            let validity = &cert.tbs_certificate.validity;
+            if now < validity.not_before.to_unix_duration()
+                || now > validity.not_after.to_unix_duration()
+            {
+                return Err(invalid("AK certificate chain is not valid now"));
+            }
+            let issuer = match chain.get(i + 1) {
+                Some(issuer) => issuer,
+                None => self
+                    .cas
+                    .iter()
+                    .find(|ca| ca.tbs_certificate.subject == cert.tbs_certificate.issuer)
+                    .ok_or_else(|| invalid("AK certificate chain does not lead to a trusted CA"))?,
+            };
+            // The issuer has the i certificates before `cert` below it
+            check_issuer(issuer, i)?;
+            verify_signature(&rsa_key(issuer)?, cert)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `issuer` may sign certificates in a chain where `below` CA
+/// certificates follow it: it must be a CA with the keyCertSign key usage,
+/// and its path length constraint, if any, must allow them.
+fn check_issuer(issuer: &Certificate, below: usize) -> Result<(), ProviderError> {
+    let tbs = &issuer.tbs_certificate;
+    let constraints = tbs
+        .get::<BasicConstraints>()
+        .map_err(|e| invalid(format!("Invalid basic constraints: {}", e)))?
+        .map(|(_, constraints)| constraints)
+        .filter(|constraints| constraints.ca)
+        .ok_or_else(|| invalid("AK certificate issuer is not a CA"))?;
+    if let Some(path_len) = constraints.path_len_constraint {
+        if below > path_len as usize {
+            return Err(invalid(
+                "AK certificate chain is longer than its issuer allows",
+            ));
+        }
+    }
+    let usage = tbs
+        .get::<KeyUsage>()
+        .map_err(|e| invalid(format!("Invalid key usage: {}", e)))?;
+    if !usage.is_some_and(|(_, usage)| usage.key_cert_sign()) {
+        return Err(invalid("AK certificate issuer may not sign certificates"));
+    }
+    Ok(())
+}
+
+fn rsa_key(cert: &Certificate) -> Result<RsaPublicKey, ProviderError> {
+    let spki = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| invalid(format!("Invalid certificate key: {}", e)))?;
+    RsaPublicKey::from_public_key_der(&spki)
+        .map_err(|_| invalid("AK certificates must carry RSA keys"))
+}
+
+fn verify_signature(issuer: &RsaPublicKey, cert: &Certificate) -> Result<(), ProviderError> {
+    let tbs = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|e| invalid(format!("Invalid certificate: {}", e)))?;
+    let signature = cert.signature.raw_bytes();
+    let verified = match cert.signature_algorithm.oid.to_string().as_str() {
+        OID_SHA256_WITH_RSA => issuer.verify(
+            Pkcs1v15Sign::new::<Sha256>(),
+            &Sha256::digest(&tbs),
+            signature,
+        ),
+        OID_SHA384_WITH_RSA => issuer.verify(
+            Pkcs1v15Sign::new::<Sha384>(),
+            &Sha384::digest(&tbs),
+            signature,
+        ),
+        other => {
+            return Err(invalid(format!(
+                "Certificate signature algorithm {} is not supported",
+                other
+            )))
+        }
+    };
+    verified.map_err(|_| invalid("AK certificate signature does not verify"))
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+fn malformed(reason: &str) -> ProviderError {
+    ProviderError::QuoteParseError(format!("Malformed TPM quote: {}", reason))
+}
+
+/// Big-endian TPM structure reader.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProviderError> {
+        if self.0.len() < len {
+            return Err(malformed("truncated"));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, ProviderError> {
+        Ok(u16::from_be_bytes(
+            self.take(2)?.try_into().expect("2 bytes"),
+        ))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProviderError> {
+        Ok(u32::from_be_bytes(
+            self.take(4)?.try_into().expect("4 bytes"),
+        ))
+    }
+
+    fn tpm2b(&mut self) -> Result<&'a [u8], ProviderError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::EncodePublicKey;
+    use rsa::RsaPrivateKey;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use x509_cert::der::asn1::{Any, BitString, OctetString, UtcTime};
+    use x509_cert::der::oid::AssociatedOid;
+    use x509_cert::ext::pkix::KeyUsages;
+    use x509_cert::ext::Extension;
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+    use x509_cert::time::{Time, Validity};
+    use x509_cert::{TbsCertificate, Version};
+
+    #[test]
+    fn signed_quote_parses_and_verifies() {
+        let mut attest = TPM_GENERATED_VALUE.to_be_bytes().to_vec();
+        attest.extend_from_slice(&TPM_ST_ATTEST_QUOTE.to_be_bytes());
+        attest.extend_from_slice(&[0, 2, 0xaa, 0xbb]); // qualifiedSigner
+        attest.extend_from_slice(&[0, 3, 1, 2, 3]); // extraData
+        attest.extend_from_slice(&[0; CLOCK_INFO_LEN + 8]);
+        let selection = [0, 0, 0, 1, 0, 0x0b, 3, 0xff, 0, 0];
+        attest.extend_from_slice(&selection);
+        attest.extend_from_slice(&[0, 32]);
+        attest.extend_from_slice(&[0x5a; 32]);
+
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let digest = Sha256::digest(&attest);
+        let raw = key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest).unwrap();
+        let mut signature = TPM_ALG_RSASSA.to_be_bytes().to_vec();
+        signature.extend_from_slice(&TPM_ALG_SHA256.to_be_bytes());
+        signature.extend_from_slice(&(raw.len() as u16).to_be_bytes());
+        signature.extend_from_slice(&raw);
+
+        let quote = TpmQuote::parse(attest.clone(), &signature).unwrap();
+        assert_eq!(quote.extra_data, [1, 2, 3]);
+        assert_eq!(quote.pcr_select, selection);
+        assert_eq!(quote.pcr_digest, [0x5a; 32]);
+        assert!(quote.selects_sha256(0) && quote.selects_sha256(7));
+        assert!(!quote.selects_sha256(8));
+        assert!(quote.verify(&key.to_public_key()).is_ok());
+
+        attest[6] ^= 1;
+        let tampered = TpmQuote::parse(attest, &signature).unwrap();
+        assert!(tampered.verify(&key.to_public_key()).is_err());
+    }
+
+    /// A certificate for `subject`'s `key`, signed with it as if by
+    /// `issuer`, with the given basic constraints and key usage.
+    fn certificate(
+        subject: &str,
+        issuer: &str,
+        key: &RsaPrivateKey,
+        constraints: Option<BasicConstraints>,
+        usage: Option<KeyUsages>,
+    ) -> Certificate {
+        let extension = |oid, value: Vec<u8>| Extension {
+            extn_id: oid,
+            critical: true,
+            extn_value: OctetString::new(value).unwrap(),
+        };
+        let mut extensions = Vec::new();
+        if let Some(constraints) = constraints {
+            extensions.push(extension(
+                BasicConstraints::OID,
+                constraints.to_der().unwrap(),
+            ));
+        }
+        if let Some(usage) = usage {
+            extensions.push(extension(
+                KeyUsage::OID,
+                KeyUsage(usage.into()).to_der().unwrap(),
+            ));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let time = |at| Time::UtcTime(UtcTime::from_unix_duration(at).unwrap());
+        let algorithm = AlgorithmIdentifierOwned {
+            oid: OID_SHA256_WITH_RSA.parse().unwrap(),
+            parameters: Some(Any::null()),
+        };
+        let public_key = key.to_public_key().to_public_key_der().unwrap();
+        let tbs_certificate = TbsCertificate {
+            version: Version::V3,
+            serial_number: SerialNumber::new(&[1]).unwrap(),
+            signature: algorithm.clone(),
+            issuer: Name::from_str(issuer).unwrap(),
+            validity: Validity {
+                not_before: time(now - Duration::from_secs(3600)),
+                not_after: time(now + Duration::from_secs(3600)),
+            },
+            subject: Name::from_str(subject).unwrap(),
+            subject_public_key_info: SubjectPublicKeyInfoOwned::from_der(public_key.as_bytes())
+                .unwrap(),
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: Some(extensions),
+        };
+        let digest = Sha256::digest(tbs_certificate.to_der().unwrap());
+        let signature = key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest).unwrap();
+        Certificate {
+            tbs_certificate,
+            signature_algorithm: algorithm,
+            signature: BitString::from_bytes(&signature).unwrap(),
+        }
+    }
+
+    #[test]
+    fn only_cas_may_issue_ak_certificates() {
+        // One key for every certificate, so only the constraints tell them
+        // apart
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+        let ca = |path_len| {
+            Some(BasicConstraints {
+                ca: true,
+                path_len_constraint: path_len,
+            })
+        };
+        let cert_sign = Some(KeyUsages::KeyCertSign);
+        let verifier = |root: Certificate| TpmVerifier { cas: vec![root] };
+
+        let root = certificate("CN=root", "CN=root", &key, ca(None), cert_sign);
+        let ak = certificate("CN=ak", "CN=root", &key, None, None);
+        assert!(verifier(root.clone()).verify_chain(&[ak.clone()]).is_ok());
+
+        // An AK certificate signed by another AK's key
+        let leaf_signed = certificate("CN=ak2", "CN=ak", &key, None, None);
+        let chain = [leaf_signed.clone(), ak];
+        assert!(verifier(root.clone()).verify_chain(&chain).is_err());
+        let no_cert_sign = certificate("CN=ak", "CN=root", &key, ca(None), None);
+        let chain = [leaf_signed.clone(), no_cert_sign];
+        assert!(verifier(root.clone()).verify_chain(&chain).is_err());
+
+        let intermediate = certificate("CN=ak", "CN=root", &key, ca(None), cert_sign);
+        let chain = [leaf_signed, intermediate];
+        assert!(verifier(root).verify_chain(&chain).is_ok());
+        let root = certificate("CN=root", "CN=root", &key, ca(Some(0)), cert_sign);
+        assert!(verifier(root).verify_chain(&chain).is_err());
+    }
+
+    #[test]
+    fn envelope_needs_an_ak_certificate() {
+        let envelope = serde_json::json!({
+            "type": ENVELOPE_TYPE,
+            "report_data": STANDARD.encode([0u8; 64]),
+            "ak_cert_chain": [],
+            "quote": "",
+            "signature": "",
+        })
+        .to_string();
+
+        let err = TpmEvidence::parse(envelope.as_bytes()).err().unwrap();
+
+        assert!(err.to_string().contains("ak_cert_chain"));
+        assert_eq!(EvidenceKind::detect(envelope.as_bytes()), EvidenceKind::Tpm);
+    }
+}
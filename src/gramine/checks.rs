@@ -0,0 +1,107 @@
+use super::interface::set_user_report_data;
+use super::paths::paths;
+use crate::error::ProviderError;
+use log::info;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Threads the provider may need alongside the main one (connection handling,
+/// PKCS#11 modules, Gramine's own helpers, which count against the same limit).
+const SPARE_THREADS: usize = 3;
+
+/// Checks the enclave configuration up front, so a manifest mistake is
+/// reported with the setting to fix rather than as an I/O error on the first
+/// request.
+pub fn validate_environment(attestation: &str) -> Result<(), ProviderError> {
+    if attestation != "dcap" {
+        return Err(ProviderError::ConfigError(format!(
+            "Attestation type is \"{}\"; TDX quote verification and PPID matching \
+             require sgx.remote_attestation = \"dcap\" in the manifest, or a quote \
+             service in platform.quote_service",
+            attestation
+        )));
+    }
+    validate_files(false)
+}
+
+/// Like [`validate_environment`] for quotes made by an out-of-process quote
+/// service, which only needs Gramine's local reports.
+pub fn validate_local_attestation() -> Result<(), ProviderError> {
+    validate_files(true)
+}
+
+fn validate_files(local_reports: bool) -> Result<(), ProviderError> {
+    paths().probe(local_reports)?;
+
+    set_user_report_data(&[]).map_err(|e| {
+        ProviderError::ConfigError(format!(
+            "{} is not writable ({}); check that the enclave runs under gramine-sgx \
+             with remote attestation enabled",
+            paths().user_report_data.display(),
+            e
+        ))
+    })?;
+
+    check_threads(SPARE_THREADS)?;
+
+    info!("Enclave environment validated");
+    Ok(())
+}
+
+/// Starts `count` threads that stay alive together; under Gramine, creating
+/// more threads than `sgx.max_threads` allows fails here instead of in the
+/// middle of serving a request.
+fn check_threads(count: usize) -> Result<(), ProviderError> {
+    let (release, parked) = mpsc::channel::<()>();
+    let parked = Arc::new(Mutex::new(parked));
+
+    let mut handles = Vec::with_capacity(count);
+    let mut failure = None;
+    for _ in 0..count {
+        let parked = parked.clone();
+        match thread::Builder::new()
+            .name("thread-probe".into())
+            .spawn(move || {
+                let _ = parked.lock().map(|rx| rx.recv());
+            }) {
+            Ok(handle) => handles.push(handle),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    drop(release);
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    match failure {
+        None => Ok(()),
+        Some(e) => Err(ProviderError::ConfigError(format!(
+            "Could not start {} threads besides the main one ({}); increase \
+             sgx.max_threads in the manifest",
+            count, e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_dcap_attestation_is_accepted() {
+        let result = validate_environment("epid");
+        assert!(
+            matches!(result, Err(ProviderError::ConfigError(ref msg)) if msg.contains("sgx.remote_attestation")),
+            "expected ConfigError naming the manifest setting, got: {result:?}"
+        );
+    }
+
+    #[test]
+    fn thread_probe_succeeds_with_spare_threads() {
+        assert!(check_threads(SPARE_THREADS).is_ok());
+    }
+}
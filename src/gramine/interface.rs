@@ -1,7 +1,13 @@
+use super::paths::paths;
+use super::GramineKey;
+use crate::crypto::GuardedKey;
 use crate::error::ProviderError;
+use crate::sealing::SealingKeySource;
 use log::{debug, error};
 use std::fs;
-use std::sync::Mutex;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use zeroize::Zeroizing;
 
 static ATTESTATION_LOCK: Mutex<()> = Mutex::new(());
 // OS error 13; libc name is EACCES (permission denied).
@@ -24,10 +30,38 @@ fn map_attestation_io_error(context: &str, e: std::io::Error) -> ProviderError {
     }
 }
 
-pub fn get_sealing_key() -> Result<Vec<u8>, ProviderError> {
-    debug!("reading sealing key from Gramine");
-    fs::read("/dev/attestation/keys/_sgx_mrenclave")
-        .map_err(|e| map_attestation_io_error("reading /dev/attestation/keys/_sgx_mrenclave", e))
+/// Gramine's attestation type (`dcap`, `epid`), or `None` when attestation is
+/// unavailable: no attestation pseudo-FS (gramine-direct, plain Linux) or type
+/// `none`.
+pub fn attestation_type() -> Option<String> {
+    let attestation = fs::read_to_string(&paths().attestation_type).ok()?;
+    let attestation = attestation.trim();
+    (!attestation.is_empty() && attestation != "none").then(|| attestation.to_string())
+}
+
+pub struct GramineSealingKey(pub GramineKey);
+
+impl SealingKeySource for GramineSealingKey {
+    fn describe(&self) -> String {
+        format!("Gramine {:?} sealing key", self.0)
+    }
+
+    fn sealing_key(&self) -> Result<GuardedKey, ProviderError> {
+        match self.0 {
+            GramineKey::MrEnclave => read_sealing_key(&paths().mrenclave_key),
+            GramineKey::MrSigner => read_sealing_key(&paths().mrsigner_key),
+        }
+    }
+}
+
+fn read_sealing_key(path: &Path) -> Result<GuardedKey, ProviderError> {
+    debug!("reading sealing key from {}", path.display());
+    let raw_key = fs::read(path)
+        .map(Zeroizing::new)
+        .map_err(|e| map_attestation_io_error(&format!("reading {}", path.display()), e))?;
+
+    // Move the key into guarded memory right away; `raw_key` is wiped on drop.
+    GuardedKey::from_slice(&raw_key)
 }
 
 pub fn set_user_report_data(data: &[u8]) -> Result<(), ProviderError> {
@@ -42,27 +76,49 @@ pub fn set_user_report_data(data: &[u8]) -> Result<(), ProviderError> {
     let mut padded_data = vec![0u8; 64];
     padded_data[..data.len()].copy_from_slice(data);
 
-    fs::write("/dev/attestation/user_report_data", &padded_data)
-        .map_err(|e| map_attestation_io_error("writing /dev/attestation/user_report_data", e))
+    let path = &paths().user_report_data;
+    fs::write(path, &padded_data)
+        .map_err(|e| map_attestation_io_error(&format!("writing {}", path.display()), e))
 }
 
-pub fn get_quote_with_data(user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
-    debug!("setting user report data and getting quote");
-
+fn lock_attestation() -> Result<MutexGuard<'static, ()>, ProviderError> {
     // Serialize /dev/attestation access; Gramine's pseudo-FS is not thread-safe.
-    let _guard = ATTESTATION_LOCK.lock().map_err(|_| {
+    ATTESTATION_LOCK.lock().map_err(|_| {
         ProviderError::IOError(std::io::Error::new(
             std::io::ErrorKind::Other,
             "attestation lock poisoned",
         ))
-    })?;
+    })
+}
+
+pub fn get_quote_with_data(user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    debug!("setting user report data and getting quote");
+    let _guard = lock_attestation()?;
 
     // First set the user report data
     set_user_report_data(user_data)?;
 
     // Then get the quote
-    fs::read("/dev/attestation/quote")
-        .map_err(|e| map_attestation_io_error("reading /dev/attestation/quote", e))
+    let path = &paths().quote;
+    fs::read(path).map_err(|e| map_attestation_io_error(&format!("reading {}", path.display()), e))
+}
+
+/// Local SGX report over `user_data`, targeted at the enclave described by
+/// `target_info` (e.g. a quoting enclave), for quoting outside Gramine.
+pub fn get_report_with_data(
+    target_info: &[u8],
+    user_data: &[u8],
+) -> Result<Vec<u8>, ProviderError> {
+    debug!("setting target info and user report data and getting report");
+    let _guard = lock_attestation()?;
+
+    let path = &paths().target_info;
+    fs::write(path, target_info)
+        .map_err(|e| map_attestation_io_error(&format!("writing {}", path.display()), e))?;
+    set_user_report_data(user_data)?;
+
+    let path = &paths().report;
+    fs::read(path).map_err(|e| map_attestation_io_error(&format!("reading {}", path.display()), e))
 }
 
 #[cfg(test)]
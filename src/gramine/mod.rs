@@ -1,3 +1,248 @@
+mod checks;
 mod interface;
+mod paths;
+mod quote_cache;
+mod quoting;
+mod report;
+mod simulated;
+mod td;
 
-pub use interface::{get_quote_with_data, get_sealing_key};
+use crate::error::ProviderError;
+use crate::sealing::SealingKeySource;
+use interface::GramineSealingKey;
+use log::{info, warn};
+pub use paths::{AttestationPaths, DEFAULT_ATTESTATION_DIR};
+use quote_cache::QuoteCache;
+use quoting::{GramineQuoting, QuoteGenerator, QuoteService};
+use simulated::SimulatedPlatform;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+use td::TdPlatform;
+pub use td::DEFAULT_TSM_REPORT_DIR;
+
+/// What the provider runs on, decided once at startup.
+enum Platform {
+    /// A Gramine SGX enclave with remote attestation.
+    Gramine,
+    /// gramine-direct or plain Linux, faking attestation for local development.
+    Simulated(SimulatedPlatform),
+    /// A normal host daemon with an externally provided root key; responses
+    /// carry no provider quote.
+    Standalone,
+    /// Inside a TDX trust domain, attested by TDX quotes, with an externally
+    /// provided root key.
+    Td(TdPlatform),
+}
+
+static PLATFORM: OnceLock<Platform> = OnceLock::new();
+
+fn set_platform(platform: Platform) -> Result<(), ProviderError> {
+    PLATFORM
+        .set(platform)
+        .map_err(|_| ProviderError::ConfigError("Platform already initialized".into()))
+}
+
+fn platform() -> &'static Platform {
+    PLATFORM.get().unwrap_or(&Platform::Gramine)
+}
+
+/// How quotes are made on the Gramine platform.
+static QUOTING: OnceLock<Box<dyn QuoteGenerator>> = OnceLock::new();
+
+fn quoting() -> &'static dyn QuoteGenerator {
+    QUOTING.get_or_init(|| Box::new(GramineQuoting)).as_ref()
+}
+
+/// Decides at startup whether Gramine attestation is available. Without it
+/// (gramine-direct, plain Linux) the provider only runs when a simulated
+/// sealing key file is explicitly configured, and then never attests.
+///
+/// With a `quote_service` address, quotes come from that host service
+/// instead of Gramine's DCAP path, which then need not be configured.
+pub fn init_platform(
+    paths: AttestationPaths,
+    simulated_key: Option<&Path>,
+    quote_service: Option<String>,
+) -> Result<(), ProviderError> {
+    paths::set_paths(paths)?;
+    if let Some(addr) = quote_service {
+        if simulated_key.is_some() {
+            warn!("Ignoring simulated sealing key: a quote service is configured");
+        }
+        checks::validate_local_attestation()?;
+        let generator = QuoteService::new(addr);
+        info!("Using {}", generator.describe());
+        QUOTING
+            .set(Box::new(generator))
+            .map_err(|_| ProviderError::ConfigError("Quoting already initialized".into()))?;
+        return set_platform(Platform::Gramine);
+    }
+
+    match (interface::attestation_type(), simulated_key) {
+        (Some(attestation), None) => {
+            info!("Gramine attestation available ({})", attestation);
+            checks::validate_environment(&attestation)?;
+            set_platform(Platform::Gramine)
+        }
+        (Some(attestation), Some(_)) => {
+            warn!(
+                "Ignoring simulated sealing key: Gramine attestation is available ({})",
+                attestation
+            );
+            checks::validate_environment(&attestation)?;
+            set_platform(Platform::Gramine)
+        }
+        (None, Some(path)) => {
+            warn!(
+                "INSECURE: no Gramine attestation; using simulated sealing key {} and unattested quotes",
+                path.display()
+            );
+            set_platform(Platform::Simulated(SimulatedPlatform::new(path)))
+        }
+        (None, None) => Err(ProviderError::ConfigError(
+            "Gramine attestation is not available (not running under gramine-sgx, or \
+             sgx.remote_attestation is not set). For local development only, set \
+             platform.insecure_simulated_sealing_key to a key file path"
+                .into(),
+        )),
+    }
+}
+
+/// Runs outside any enclave. Quote verification, policy and the protocol are
+/// unchanged, but the root key must come from a file or KMS and clients get
+/// no provider quote to check.
+pub fn init_standalone() -> Result<(), ProviderError> {
+    warn!("Running in standalone mode: responses are not attested by a provider quote");
+    set_platform(Platform::Standalone)
+}
+
+/// Runs inside a TDX trust domain, quoting through configfs-tsm under
+/// `report_dir`. The root key must come from a file or command.
+pub fn init_td(report_dir: &Path) -> Result<(), ProviderError> {
+    let td = TdPlatform::open(report_dir)?;
+    info!("Running in a TDX trust domain: {}", td.describe());
+    set_platform(Platform::Td(td))
+}
+
+pub use report::{is_debug_enclave, own_kss_identity, own_report, KssIdentity};
+
+pub fn is_simulated() -> bool {
+    matches!(platform(), Platform::Simulated(_))
+}
+
+/// Whether provider quotes come from real TEE hardware (an SGX enclave or a
+/// TDX trust domain).
+pub fn is_attested() -> bool {
+    matches!(platform(), Platform::Gramine | Platform::Td(_))
+}
+
+/// Whether the provider runs in a Gramine SGX enclave, so SGX report fields
+/// (ISV SVN, KSS, the enclave attributes) are available.
+pub fn is_sgx() -> bool {
+    matches!(platform(), Platform::Gramine)
+}
+
+pub fn is_standalone() -> bool {
+    matches!(platform(), Platform::Standalone)
+}
+
+pub fn is_td() -> bool {
+    matches!(platform(), Platform::Td(_))
+}
+
+/// This trust domain's TD report, from its own quote.
+pub fn own_td_report() -> Result<dcap_qvl::quote::TDReport10, ProviderError> {
+    td::td_report(&own_quote()?)
+}
+
+/// Whether this trust domain has the DEBUG attribute.
+pub fn is_debug_td() -> Result<bool, ProviderError> {
+    Ok(td::is_debug_td(&own_td_report()?))
+}
+
+/// Gramine's built-in sealing keys: bound to this exact enclave, or to any
+/// enclave from the same signer (which survives provider upgrades).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GramineKey {
+    MrEnclave,
+    MrSigner,
+}
+
+/// The Gramine sealing key, or the simulated key file on a platform without
+/// attestation.
+pub fn sealing_key_source(key: GramineKey) -> Result<Box<dyn SealingKeySource>, ProviderError> {
+    match platform() {
+        Platform::Gramine => Ok(Box::new(GramineSealingKey(key))),
+        Platform::Simulated(platform) => Ok(Box::new(platform.clone())),
+        Platform::Standalone | Platform::Td(_) => Err(ProviderError::ConfigError(format!(
+            "The {:?} sealing key needs Gramine; standalone and TD modes need a file: or \
+             command: root key",
+            key
+        ))),
+    }
+}
+
+/// Quote over `user_data`; empty in standalone mode.
+pub fn get_quote_with_data(user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    match platform() {
+        Platform::Gramine => quoting().quote(user_data),
+        Platform::Simulated(platform) => platform.quote_with_data(user_data),
+        Platform::Standalone => Ok(Vec::new()),
+        Platform::Td(td) => td.quote(user_data),
+    }
+}
+
+/// [`get_quote_with_data`] for the async request path.
+pub async fn quote_with_data(user_data: Vec<u8>) -> Result<Vec<u8>, ProviderError> {
+    unblock(move || get_quote_with_data(&user_data)).await
+}
+
+/// Runs `quoting` on a blocking thread. Quotes come from the Gramine device,
+/// configfs or the host's quote service, which all block, sometimes for
+/// seconds, and must not hold up the runtime's threads.
+pub async fn unblock<T: Send + 'static>(
+    quoting: impl FnOnce() -> Result<T, ProviderError> + Send + 'static,
+) -> Result<T, ProviderError> {
+    tokio::task::spawn_blocking(quoting)
+        .await
+        .map_err(|e| ProviderError::IOError(std::io::Error::other(e)))?
+}
+
+/// How long the provider's own quote is reused unless its report data or the
+/// platform TCB status changes.
+const DEFAULT_QUOTE_REFRESH: Duration = Duration::from_secs(10 * 60);
+
+static QUOTE_CACHE: QuoteCache = QuoteCache::new();
+static QUOTE_REFRESH: OnceLock<Duration> = OnceLock::new();
+
+/// Sets how long the provider's own quote is reused; zero disables reuse.
+pub fn set_quote_refresh(refresh: Duration) -> Result<(), ProviderError> {
+    QUOTE_REFRESH
+        .set(refresh)
+        .map_err(|_| ProviderError::ConfigError("Quote refresh already set".into()))
+}
+
+/// Quote over empty report data, for PPID checks, the provider's own report
+/// and TCB evaluation. Generated again once the refresh interval passes or
+/// the platform TCB status changes.
+pub fn own_quote() -> Result<Vec<u8>, ProviderError> {
+    let refresh = *QUOTE_REFRESH.get_or_init(|| DEFAULT_QUOTE_REFRESH);
+    QUOTE_CACHE.get(&[], refresh, get_quote_with_data)
+}
+
+/// Age and last evaluated TCB status of the cached [`own_quote`].
+pub fn own_quote_status() -> Option<(Duration, Option<String>)> {
+    QUOTE_CACHE.status()
+}
+
+/// Makes the next [`own_quote`] a fresh one.
+pub fn flush_own_quote() {
+    QUOTE_CACHE.clear();
+}
+
+/// Reports the TCB status evaluated for [`own_quote`], so a changed platform
+/// gets a fresh quote.
+pub fn note_tcb_status(status: &str) {
+    QUOTE_CACHE.note_tcb_status(status);
+}
@@ -0,0 +1,114 @@
+use crate::error::ProviderError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const DEFAULT_ATTESTATION_DIR: &str = "/dev/attestation";
+
+static PATHS: OnceLock<AttestationPaths> = OnceLock::new();
+
+/// Locations of Gramine's attestation pseudo-files. All default to the
+/// standard layout under `/dev/attestation`; each can be moved individually
+/// for newer Gramine layouts or test harnesses (see
+/// `PlatformConfig::attestation_paths`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationPaths {
+    pub attestation_type: PathBuf,
+    pub user_report_data: PathBuf,
+    pub quote: PathBuf,
+    pub target_info: PathBuf,
+    pub report: PathBuf,
+    pub mrenclave_key: PathBuf,
+    pub mrsigner_key: PathBuf,
+}
+
+impl AttestationPaths {
+    pub fn under(dir: &Path) -> Self {
+        Self {
+            attestation_type: dir.join("attestation_type"),
+            user_report_data: dir.join("user_report_data"),
+            quote: dir.join("quote"),
+            target_info: dir.join("target_info"),
+            report: dir.join("report"),
+            mrenclave_key: dir.join("keys/_sgx_mrenclave"),
+            mrsigner_key: dir.join("keys/_sgx_mrsigner"),
+        }
+    }
+
+    /// Checks that every file the provider needs is present, so a wrong
+    /// layout fails at startup rather than on the first request. With
+    /// `local_reports`, quotes are made outside Gramine from local reports, so
+    /// the report files are needed instead of the quote.
+    pub fn probe(&self, local_reports: bool) -> Result<(), ProviderError> {
+        let mut required = vec![
+            (&self.user_report_data, "user report data"),
+            (&self.mrenclave_key, "MRENCLAVE sealing key"),
+            (&self.mrsigner_key, "MRSIGNER sealing key"),
+        ];
+        if local_reports {
+            required.push((&self.target_info, "target info"));
+            required.push((&self.report, "report"));
+        } else {
+            required.push((&self.quote, "quote"));
+        }
+        for (path, what) in required {
+            fs::metadata(path).map_err(|e| {
+                ProviderError::ConfigError(format!(
+                    "Attestation {} file {} is not accessible: {}",
+                    what,
+                    path.display(),
+                    e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for AttestationPaths {
+    fn default() -> Self {
+        Self::under(Path::new(DEFAULT_ATTESTATION_DIR))
+    }
+}
+
+pub fn set_paths(paths: AttestationPaths) -> Result<(), ProviderError> {
+    PATHS
+        .set(paths)
+        .map_err(|_| ProviderError::ConfigError("Attestation paths already set".into()))
+}
+
+pub fn paths() -> &'static AttestationPaths {
+    PATHS.get_or_init(AttestationPaths::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout_is_under_dev_attestation() {
+        let paths = AttestationPaths::default();
+
+        assert_eq!(paths.quote, Path::new("/dev/attestation/quote"));
+        assert_eq!(
+            paths.mrenclave_key,
+            Path::new("/dev/attestation/keys/_sgx_mrenclave")
+        );
+    }
+
+    #[test]
+    fn probe_names_the_missing_file() {
+        let dir = std::env::temp_dir().join(format!("attestation-paths-{}", std::process::id()));
+        fs::create_dir_all(dir.join("keys")).unwrap();
+        fs::write(dir.join("quote"), b"").unwrap();
+        fs::write(dir.join("keys/_sgx_mrenclave"), b"").unwrap();
+
+        let result = AttestationPaths::under(&dir).probe(false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            matches!(result, Err(ProviderError::ConfigError(ref msg)) if msg.contains("user_report_data")),
+            "expected ConfigError naming user_report_data, got: {result:?}"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::error::ProviderError;
+use log::{debug, info};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The last quote generated over a given report data. Quotes bound to a
+/// client nonce are never reused, so a single entry is enough for the ones
+/// that are (PPID checks, the provider's own report, TCB evaluation).
+pub struct QuoteCache {
+    entry: Mutex<Option<CachedQuote>>,
+}
+
+struct CachedQuote {
+    user_data: Vec<u8>,
+    quote: Vec<u8>,
+    generated_at: Instant,
+    tcb_status: Option<String>,
+}
+
+impl QuoteCache {
+    pub const fn new() -> Self {
+        Self {
+            entry: Mutex::new(None),
+        }
+    }
+
+    /// The cached quote if it is over `user_data` and younger than `refresh`,
+    /// otherwise a new one from `generate`.
+    pub fn get(
+        &self,
+        user_data: &[u8],
+        refresh: Duration,
+        generate: impl FnOnce(&[u8]) -> Result<Vec<u8>, ProviderError>,
+    ) -> Result<Vec<u8>, ProviderError> {
+        let mut entry = self.lock();
+        if let Some(cached) = entry.as_ref() {
+            if cached.user_data == user_data && cached.generated_at.elapsed() < refresh {
+                debug!("Reusing cached provider quote");
+                return Ok(cached.quote.clone());
+            }
+        }
+
+        let quote = generate(user_data)?;
+        *entry = Some(CachedQuote {
+            user_data: user_data.to_vec(),
+            quote: quote.clone(),
+            generated_at: Instant::now(),
+            tcb_status: None,
+        });
+        Ok(quote)
+    }
+
+    /// Age and TCB status of the cached quote, if there is one.
+    pub fn status(&self) -> Option<(Duration, Option<String>)> {
+        self.lock()
+            .as_ref()
+            .map(|cached| (cached.generated_at.elapsed(), cached.tcb_status.clone()))
+    }
+
+    /// Drops the cached quote, so the next use generates a new one.
+    pub fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    /// Records the TCB status evaluated for the cached quote. If it differs
+    /// from the status seen before, the platform may have been updated, so the
+    /// quote is dropped and regenerated on next use.
+    pub fn note_tcb_status(&self, status: &str) {
+        let mut entry = self.lock();
+        let Some(cached) = entry.as_mut() else {
+            return;
+        };
+        match &cached.tcb_status {
+            Some(previous) if previous != status => {
+                info!(
+                    "Platform TCB status changed from {} to {}; regenerating provider quote",
+                    previous, status
+                );
+                *entry = None;
+            }
+            Some(_) => {}
+            None => cached.tcb_status = Some(status.to_string()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<CachedQuote>> {
+        self.entry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn quote_is_regenerated_for_new_report_data_or_tcb_status() {
+        let cache = QuoteCache::new();
+        let generated = Cell::new(0);
+        let get = |user_data: &[u8]| {
+            cache
+                .get(user_data, Duration::from_secs(60), |data| {
+                    generated.set(generated.get() + 1);
+                    Ok(data.to_vec())
+                })
+                .unwrap()
+        };
+
+        get(b"");
+        get(b"");
+        assert_eq!(generated.get(), 1);
+
+        get(b"other");
+        assert_eq!(generated.get(), 2);
+
+        cache.note_tcb_status("UpToDate");
+        cache.note_tcb_status("UpToDate");
+        get(b"other");
+        assert_eq!(generated.get(), 2);
+
+        cache.note_tcb_status("OutOfDate");
+        get(b"other");
+        assert_eq!(generated.get(), 3);
+    }
+}
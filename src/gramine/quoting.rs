@@ -0,0 +1,173 @@
+use super::interface;
+use crate::error::ProviderError;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const QUOTE_SERVICE_TIMEOUT: Duration = Duration::from_secs(30);
+// A DCAP quote with its certification data is a few KiB
+const MAX_QUOTE_SERVICE_RESPONSE: usize = 64 * 1024;
+
+/// Produces SGX quotes over the provider's report data.
+pub trait QuoteGenerator: Send + Sync {
+    /// Where quotes come from, for logs.
+    fn describe(&self) -> String;
+
+    /// Quote over `user_data` (at most 64 bytes, zero-padded).
+    fn quote(&self, user_data: &[u8]) -> Result<Vec<u8>, ProviderError>;
+}
+
+/// Gramine's in-enclave DCAP path: `/dev/attestation/quote`.
+pub struct GramineQuoting;
+
+impl QuoteGenerator for GramineQuoting {
+    fn describe(&self) -> String {
+        "Gramine DCAP quoting".to_string()
+    }
+
+    fn quote(&self, user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        interface::get_quote_with_data(user_data)
+    }
+}
+
+/// An out-of-process quote-generation service on the host, such as a shim
+/// around AESM or the DCAP quote-ex library, for enclaves where Gramine's own
+/// DCAP path is unavailable. The enclave only makes a local report targeted
+/// at the service's quoting enclave; the service turns it into a quote.
+///
+/// Requests and responses are JSON in the provider's length-prefixed frames:
+/// `{"op": "target_info"}` returns `{"target_info": [...]}` and
+/// `{"op": "quote", "report": [...]}` returns `{"quote": [...]}`; failures
+/// come back as `{"error": "..."}`.
+///
+/// Calls block on the socket for up to `QUOTE_SERVICE_TIMEOUT`, so the
+/// request path makes them through [`super::unblock`].
+pub struct QuoteService {
+    addr: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ServiceRequest<'a> {
+    TargetInfo,
+    Quote { report: &'a [u8] },
+}
+
+#[derive(Deserialize)]
+struct ServiceResponse {
+    #[serde(default)]
+    target_info: Option<Vec<u8>>,
+    #[serde(default)]
+    quote: Option<Vec<u8>>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl QuoteService {
+    pub fn new(addr: String) -> Self {
+        Self { addr }
+    }
+
+    fn call(&self, request: &ServiceRequest) -> Result<ServiceResponse, ProviderError> {
+        let network = |e: std::io::Error| {
+            ProviderError::NetworkError(format!("Quote service {}: {}", self.addr, e))
+        };
+
+        let mut stream = TcpStream::connect(&self.addr).map_err(network)?;
+        stream
+            .set_read_timeout(Some(QUOTE_SERVICE_TIMEOUT))
+            .map_err(network)?;
+        stream
+            .set_write_timeout(Some(QUOTE_SERVICE_TIMEOUT))
+            .map_err(network)?;
+
+        let body = serde_json::to_vec(request)?;
+        stream
+            .write_all(&(body.len() as u32).to_be_bytes())
+            .and_then(|_| stream.write_all(&body))
+            .map_err(network)?;
+
+        let mut len_buf = [0u8; 4];
+        stream.read_exact(&mut len_buf).map_err(network)?;
+        let len = u32::from_be_bytes(len_buf) as usize;
+        if len > MAX_QUOTE_SERVICE_RESPONSE {
+            return Err(ProviderError::NetworkError(format!(
+                "Quote service {} sent a {} byte response",
+                self.addr, len
+            )));
+        }
+        let mut body = vec![0u8; len];
+        stream.read_exact(&mut body).map_err(network)?;
+
+        let response: ServiceResponse = serde_json::from_slice(&body)?;
+        match response.error {
+            Some(error) => Err(ProviderError::NetworkError(format!(
+                "Quote service {}: {}",
+                self.addr, error
+            ))),
+            None => Ok(response),
+        }
+    }
+}
+
+impl QuoteGenerator for QuoteService {
+    fn describe(&self) -> String {
+        format!("quote service at {}", self.addr)
+    }
+
+    fn quote(&self, user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        // The quoting enclave's identity can change when the host updates it,
+        // so its target info is fetched for every quote
+        let target_info = self
+            .call(&ServiceRequest::TargetInfo)?
+            .target_info
+            .ok_or_else(|| missing(&self.addr, "target_info"))?;
+        let report = interface::get_report_with_data(&target_info, user_data)?;
+
+        debug!("Requesting quote from {}", self.addr);
+        self.call(&ServiceRequest::Quote { report: &report })?
+            .quote
+            .ok_or_else(|| missing(&self.addr, "quote"))
+    }
+}
+
+fn missing(addr: &str, field: &str) -> ProviderError {
+    ProviderError::SerializationError(format!("Quote service {} sent no {}", addr, field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn service_errors_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut len_buf = [0u8; 4];
+            stream.read_exact(&mut len_buf).unwrap();
+            let mut request = vec![0u8; u32::from_be_bytes(len_buf) as usize];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, br#"{"op":"target_info"}"#);
+
+            let response = br#"{"error":"no quoting enclave"}"#;
+            stream
+                .write_all(&(response.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(response).unwrap();
+        });
+
+        let result = QuoteService::new(addr).quote(&[]);
+        server.join().unwrap();
+
+        assert!(
+            matches!(result, Err(ProviderError::NetworkError(ref msg)) if msg.contains("no quoting enclave")),
+            "expected NetworkError from the service, got: {result:?}"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use super::own_quote;
+use crate::error::ProviderError;
+use dcap_qvl::quote::{EnclaveReport, Quote, Report};
+
+// SGX ATTRIBUTES.FLAGS bits 1 and 7
+const SGX_FLAGS_DEBUG: u8 = 0x02;
+const SGX_FLAGS_KSS: u8 = 0x80;
+const KSS_PARTITION_LABEL: &[u8] = b"gramine-sealing-key-provider/kss-partition/v1";
+
+/// The enclave's own SGX report, taken from a quote over empty report data
+/// since that is what verifiers will see.
+pub fn own_report() -> Result<EnclaveReport, ProviderError> {
+    let quote =
+        Quote::parse(&own_quote()?).map_err(|e| ProviderError::quote_decode("own quote", e))?;
+    match quote.report {
+        Report::SgxEnclave(report) => Ok(report),
+        _ => Err(ProviderError::QuoteParseError(
+            "Own quote is not an SGX enclave quote".into(),
+        )),
+    }
+}
+
+/// Whether this enclave was launched with the SGX DEBUG attribute
+/// (`sgx.debug = true`), which lets the host read enclave memory.
+pub fn is_debug_enclave() -> Result<bool, ProviderError> {
+    Ok(own_report()?.attributes[0] & SGX_FLAGS_DEBUG != 0)
+}
+
+/// Key Separation and Sharing fields of an enclave signed with KSS. They are
+/// chosen at signing (ISVFAMILYID, ISVEXTPRODID) or launch (CONFIGID,
+/// CONFIGSVN) time, so one provider build can serve separate fleets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KssIdentity {
+    pub config_id: [u8; 64],
+    pub config_svn: u16,
+    pub isv_family_id: [u8; 16],
+    pub isv_ext_prod_id: [u8; 16],
+}
+
+impl KssIdentity {
+    /// The KSS fields of `report`, or `None` if the enclave was not signed
+    /// with KSS. dcap-qvl leaves them inside the reserved areas of the report
+    /// body, at their architectural offsets.
+    pub fn from_report(report: &EnclaveReport) -> Option<Self> {
+        if report.attributes[0] & SGX_FLAGS_KSS == 0 {
+            return None;
+        }
+
+        let mut identity = Self {
+            config_id: [0u8; 64],
+            config_svn: u16::from_le_bytes([report.reserved4[0], report.reserved4[1]]),
+            isv_family_id: [0u8; 16],
+            isv_ext_prod_id: [0u8; 16],
+        };
+        identity
+            .config_id
+            .copy_from_slice(&report.reserved3[32..96]);
+        identity
+            .isv_family_id
+            .copy_from_slice(&report.reserved4[44..60]);
+        identity
+            .isv_ext_prod_id
+            .copy_from_slice(&report.reserved1[12..28]);
+        Some(identity)
+    }
+
+    /// Bytes that partition the derivation root: a label followed by every
+    /// field at a fixed width.
+    pub fn partition(&self) -> Vec<u8> {
+        let mut partition = KSS_PARTITION_LABEL.to_vec();
+        partition.extend_from_slice(&self.config_id);
+        partition.extend_from_slice(&self.config_svn.to_be_bytes());
+        partition.extend_from_slice(&self.isv_family_id);
+        partition.extend_from_slice(&self.isv_ext_prod_id);
+        partition
+    }
+}
+
+/// KSS identity of this enclave, if it was signed with KSS.
+pub fn own_kss_identity() -> Result<Option<KssIdentity>, ProviderError> {
+    Ok(KssIdentity::from_report(&own_report()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_covers_every_field() {
+        let identity = KssIdentity {
+            config_id: [1u8; 64],
+            config_svn: 1,
+            isv_family_id: [2u8; 16],
+            isv_ext_prod_id: [3u8; 16],
+        };
+        let mut bumped = identity.clone();
+        bumped.config_svn = 2;
+
+        assert!(identity.partition().starts_with(KSS_PARTITION_LABEL));
+        assert_eq!(
+            identity.partition().len(),
+            KSS_PARTITION_LABEL.len() + 64 + 2 + 16 + 16
+        );
+        assert_ne!(identity.partition(), bumped.partition());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::crypto::GuardedKey;
+use crate::error::ProviderError;
+use crate::sealing::SealingKeySource;
+use log::warn;
+use sodiumoxide::randombytes::randombytes_into;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Prefix of simulated quotes, which are this magic followed by the 64 bytes
+/// of report data. They carry no attestation at all.
+pub const SIMULATED_QUOTE_MAGIC: &[u8] = b"gramine-sealing-key-provider/simulated-quote/v1";
+const SIMULATED_KEY_LEN: usize = 16;
+
+/// Stand-in for Gramine's attestation pseudo-files under gramine-direct or
+/// plain Linux. The "sealing key" is an ordinary file, so anything derived
+/// from it is only as secret as that file.
+#[derive(Clone)]
+pub struct SimulatedPlatform {
+    key_path: PathBuf,
+}
+
+impl SimulatedPlatform {
+    pub fn new(key_path: &Path) -> Self {
+        Self {
+            key_path: key_path.to_path_buf(),
+        }
+    }
+
+    pub fn quote_with_data(&self, user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        if user_data.len() > 64 {
+            return Err(ProviderError::CryptoError(
+                "User report data must not exceed 64 bytes".into(),
+            ));
+        }
+
+        let mut quote = SIMULATED_QUOTE_MAGIC.to_vec();
+        quote.extend_from_slice(user_data);
+        quote.resize(SIMULATED_QUOTE_MAGIC.len() + 64, 0);
+        Ok(quote)
+    }
+}
+
+impl SealingKeySource for SimulatedPlatform {
+    fn describe(&self) -> String {
+        format!("simulated sealing key {}", self.key_path.display())
+    }
+
+    /// Loads the simulated sealing key, creating it (mode 0600) on first use.
+    fn sealing_key(&self) -> Result<GuardedKey, ProviderError> {
+        match fs::read(&self.key_path).map(Zeroizing::new) {
+            Ok(key) => return GuardedKey::from_slice(&key),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(ProviderError::IOError(e)),
+        }
+
+        warn!(
+            "Creating simulated sealing key at {}",
+            self.key_path.display()
+        );
+        let mut key = Zeroizing::new([0u8; SIMULATED_KEY_LEN]);
+        randombytes_into(&mut key[..]);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&self.key_path)?
+            .write_all(&key[..])?;
+
+        GuardedKey::from_slice(&key[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_key_is_created_once_and_reused() {
+        sodiumoxide::init().unwrap();
+        let path =
+            std::env::temp_dir().join(format!("simulated-sealing-key-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let platform = SimulatedPlatform::new(&path);
+        let first = platform
+            .sealing_key()
+            .unwrap()
+            .with_bytes(|key| key.to_vec());
+        let second = platform
+            .sealing_key()
+            .unwrap()
+            .with_bytes(|key| key.to_vec());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(first.len(), SIMULATED_KEY_LEN);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn simulated_quote_carries_padded_report_data() {
+        let quote = SimulatedPlatform::new(Path::new("/nonexistent"))
+            .quote_with_data(b"data")
+            .unwrap();
+
+        assert!(quote.starts_with(SIMULATED_QUOTE_MAGIC));
+        assert_eq!(&quote[SIMULATED_QUOTE_MAGIC.len()..][..4], b"data");
+        assert_eq!(quote.len(), SIMULATED_QUOTE_MAGIC.len() + 64);
+    }
+}
@@ -0,0 +1,128 @@
+use super::quoting::QuoteGenerator;
+use crate::error::ProviderError;
+use dcap_qvl::quote::{Quote, Report, TDReport10};
+use log::debug;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The Linux TSM reports interface (configfs-tsm), available in TDX guests
+/// with the `tdx_guest` driver.
+pub const DEFAULT_TSM_REPORT_DIR: &str = "/sys/kernel/config/tsm/report";
+// TDATTRIBUTES bit 0
+const TD_ATTRIBUTES_DEBUG: u8 = 0x01;
+
+/// The provider running inside a TDX trust domain. Quotes are TDX quotes from
+/// configfs-tsm; there is no SGX sealing key, so the root key must come from
+/// a file or command.
+pub struct TdPlatform {
+    entry: PathBuf,
+    // Writing inblob and reading outblob must not interleave
+    lock: Mutex<()>,
+}
+
+impl TdPlatform {
+    /// Creates this process's report entry under `report_dir`.
+    pub fn open(report_dir: &Path) -> Result<Self, ProviderError> {
+        let entry = report_dir.join(format!("sealing-key-provider-{}", std::process::id()));
+        match fs::create_dir(&entry) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(ProviderError::ConfigError(format!(
+                    "Cannot create TSM report entry {} ({}); TD mode needs configfs-tsm \
+                     in a TDX guest",
+                    entry.display(),
+                    e
+                )))
+            }
+        }
+
+        let provider = fs::read_to_string(entry.join("provider")).unwrap_or_default();
+        if provider.trim() != "tdx_guest" {
+            return Err(ProviderError::ConfigError(format!(
+                "TSM report provider is {:?}, not tdx_guest",
+                provider.trim()
+            )));
+        }
+
+        Ok(Self {
+            entry,
+            lock: Mutex::new(()),
+        })
+    }
+}
+
+impl QuoteGenerator for TdPlatform {
+    fn describe(&self) -> String {
+        format!("TDX quotes from {}", self.entry.display())
+    }
+
+    fn quote(&self, user_data: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        if user_data.len() > 64 {
+            return Err(ProviderError::CryptoError(
+                "User report data must not exceed 64 bytes".into(),
+            ));
+        }
+        let mut inblob = [0u8; 64];
+        inblob[..user_data.len()].copy_from_slice(user_data);
+
+        let _guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        debug!("Requesting TD quote from {}", self.entry.display());
+        fs::write(self.entry.join("inblob"), inblob)?;
+        let generation = read_generation(&self.entry)?;
+        let quote = fs::read(self.entry.join("outblob"))?;
+
+        // Another writer to the same entry would bump the generation
+        if read_generation(&self.entry)? != generation {
+            return Err(ProviderError::IOError(std::io::Error::new(
+                ErrorKind::Other,
+                "TSM report entry changed while reading the quote",
+            )));
+        }
+        Ok(quote)
+    }
+}
+
+fn read_generation(entry: &Path) -> Result<String, ProviderError> {
+    Ok(fs::read_to_string(entry.join("generation"))?
+        .trim()
+        .to_string())
+}
+
+/// The TD report from a quote made by this provider.
+pub fn td_report(quote: &[u8]) -> Result<TDReport10, ProviderError> {
+    let quote = Quote::parse(quote).map_err(|e| ProviderError::quote_decode("own quote", e))?;
+    match quote.report {
+        Report::TD10(report) => Ok(report),
+        Report::TD15(report) => Ok(report.base),
+        _ => Err(ProviderError::QuoteParseError(
+            "Own quote is not a TDX quote".into(),
+        )),
+    }
+}
+
+/// Whether the TD runs with the DEBUG attribute, which lets the host read
+/// its memory.
+pub fn is_debug_td(report: &TDReport10) -> bool {
+    report.td_attributes[0] & TD_ATTRIBUTES_DEBUG != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_configfs_tsm_is_a_config_error() {
+        let result = TdPlatform::open(Path::new("/nonexistent/tsm/report"));
+        assert!(
+            matches!(result, Err(ProviderError::ConfigError(ref msg)) if msg.contains("configfs-tsm")),
+            "expected ConfigError naming configfs-tsm, got: {:?}",
+            result.err()
+        );
+    }
+}
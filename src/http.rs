@@ -0,0 +1,209 @@
+//! The little HTTP/1.1 the KBS and Vault front ends need: requests with
+//! `Content-Length` bodies (no chunked encoding), keep-alive, and JSON
+//! responses.
+
+use crate::error::ProviderError;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// KBS evidence carries the CCEL event log as well as the quote.
+const MAX_BODY_LEN: usize = 4 * 1024 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    /// The target without its query string.
+    pub path: String,
+    /// Names in lower case, in the order received.
+    headers: Vec<(String, String)>,
+    pub content_length: usize,
+    pub keep_alive: bool,
+}
+
+impl Request {
+    /// The first header called `name`, which must be in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The cookie called `name`, from any `Cookie` header.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header == "cookie")
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl Response {
+    pub fn json(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            body,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Reads one request, keeping whatever follows it in `buffer`. `None` when
+/// the peer closed the connection between requests.
+pub async fn read_request(
+    socket: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+) -> Result<Option<(Request, Vec<u8>)>, ProviderError> {
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEAD_LEN {
+            return Err(ProviderError::NetworkError("HTTP head too large".into()));
+        }
+        if fill(socket, buffer).await? == 0 {
+            return if buffer.is_empty() {
+                Ok(None)
+            } else {
+                Err(ProviderError::NetworkError(
+                    "Connection closed mid-request".into(),
+                ))
+            };
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let request = parse_head(&head)?;
+    buffer.drain(..head_end + 4);
+
+    while buffer.len() < request.content_length {
+        if fill(socket, buffer).await? == 0 {
+            return Err(ProviderError::NetworkError(
+                "Connection closed mid-request".into(),
+            ));
+        }
+    }
+    let body = buffer.drain(..request.content_length).collect();
+    Ok(Some((request, body)))
+}
+
+async fn fill(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<usize, ProviderError> {
+    let mut chunk = [0u8; 8192];
+    let n = timeout(READ_TIMEOUT, socket.read(&mut chunk))
+        .await
+        .map_err(|_| ProviderError::NetworkError("Timed out reading request".into()))??;
+    buffer.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+fn parse_head(head: &str) -> Result<Request, ProviderError> {
+    let malformed = || ProviderError::NetworkError("Malformed HTTP request".into());
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().ok_or_else(malformed)?.split(' ');
+    let (Some(method), Some(target), Some(version)) = (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) else {
+        return Err(malformed());
+    };
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: target.split('?').next().unwrap_or_default().to_string(),
+        headers: Vec::new(),
+        content_length: 0,
+        keep_alive: version == "HTTP/1.1",
+    };
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+        let name = name.trim().to_ascii_lowercase();
+        let value = value.trim();
+        match name.as_str() {
+            "content-length" => {
+                request.content_length = value.parse().map_err(|_| malformed())?;
+                if request.content_length > MAX_BODY_LEN {
+                    return Err(ProviderError::NetworkError("HTTP body too large".into()));
+                }
+            }
+            "transfer-encoding" => {
+                return Err(ProviderError::NetworkError(
+                    "Chunked requests are not supported".into(),
+                ))
+            }
+            "connection" => request.keep_alive = !value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+        request.headers.push((name, value.to_string()));
+    }
+    Ok(request)
+}
+
+pub async fn write_response(
+    socket: &mut TcpStream,
+    response: &Response,
+    keep_alive: bool,
+) -> Result<(), ProviderError> {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let body = response.body.to_string();
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        response.status,
+        reason,
+        body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str("\r\n");
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_yields_route_headers_and_length() {
+        let request = parse_head(
+            "POST /kbs/v0/attest?x=1 HTTP/1.1\r\nHost: kbs\r\nContent-Length: 42\r\n\
+             Cookie: other=1; kbs-session-id=abc\r\nX-Vault-Token: hvs.1\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/kbs/v0/attest");
+        assert_eq!(request.content_length, 42);
+        assert!(request.keep_alive);
+        assert_eq!(request.cookie("kbs-session-id"), Some("abc"));
+        assert_eq!(request.header("x-vault-token"), Some("hvs.1"));
+        assert!(parse_head("GET / HTTP/1.1\r\nTransfer-Encoding: chunked").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::config::is_plain_absolute;
+use crate::error::ProviderError;
+use crate::gramine;
+use crate::sealing::SealingKeySource;
+use log::warn;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+
+/// Command-line flag that acknowledges running in an insecure configuration.
+pub const INSECURE_FLAG: &str = "--insecure-i-know";
+
+/// Configurations that are fine for development but must never serve
+/// production traffic.
+pub fn insecure_reasons(
+    listen_addr: &str,
+    root_key: &dyn SealingKeySource,
+    protected_paths: &[PathBuf],
+) -> Result<Vec<String>, ProviderError> {
+    let mut reasons = Vec::new();
+
+    if gramine::is_simulated() {
+        reasons.push("no SGX attestation (gramine-direct or plain Linux)".to_string());
+    } else if gramine::is_standalone() {
+        reasons.push(
+            "standalone mode: responses carry no provider quote and PPIDs are not compared"
+                .to_string(),
+        );
+    } else if gramine::is_sgx() && gramine::is_debug_enclave()? {
+        reasons.push("the enclave has the SGX DEBUG attribute (sgx.debug = true)".to_string());
+    } else if gramine::is_td() && gramine::is_debug_td()? {
+        reasons.push("the trust domain has the TDX DEBUG attribute".to_string());
+    }
+
+    // Inside a TEE the command runs where the host cannot see its output
+    if root_key.is_external() && !gramine::is_attested() {
+        reasons.push(format!(
+            "the root key comes from {} outside any TEE, where the host can read or replace it",
+            root_key.describe()
+        ));
+    }
+
+    reasons.extend(unprotected_root_key(root_key, protected_paths));
+
+    if cfg!(feature = "dev-mode") && !is_loopback(listen_addr) {
+        reasons.push(format!(
+            "dev-mode build listening on non-loopback address {}",
+            listen_addr
+        ));
+    }
+
+    Ok(reasons)
+}
+
+/// Refuses to start in an insecure configuration unless `acknowledged`.
+pub fn enforce(reasons: &[String], acknowledged: bool) -> Result<(), ProviderError> {
+    if reasons.is_empty() {
+        return Ok(());
+    }
+
+    if !acknowledged {
+        return Err(ProviderError::ConfigError(format!(
+            "Refusing to start in an insecure configuration: {}. Pass {} to run anyway",
+            reasons.join("; "),
+            INSECURE_FLAG
+        )));
+    }
+
+    for reason in reasons {
+        warn!("INSECURE configuration acknowledged: {}", reason);
+    }
+    Ok(())
+}
+
+/// Why a root key read from a file is insecure, unless the file is on an
+/// encrypted mount or a trusted file. Even in a TEE, the host reads and
+/// writes plain files.
+fn unprotected_root_key(root_key: &dyn SealingKeySource, protected: &[PathBuf]) -> Option<String> {
+    let path = root_key.file()?;
+    if is_protected(path, protected) {
+        return None;
+    }
+    Some(format!(
+        "the root key is read from {}, which is not under platform.protected_paths, where the \
+         host can read or replace it",
+        path.display()
+    ))
+}
+
+/// Whether `path` is one of `protected` or lies under one of them.
+fn is_protected(path: &Path, protected: &[PathBuf]) -> bool {
+    is_plain_absolute(path) && protected.iter().any(|dir| path.starts_with(dir))
+}
+
+/// True only if every address `addr` resolves to is a loopback address.
+fn is_loopback(addr: &str) -> bool {
+    match addr.to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.collect();
+            !addrs.is_empty() && addrs.iter().all(|addr| addr.ip().is_loopback())
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sealing::{CommandSealingKey, FileSealingKey};
+
+    #[test]
+    fn loopback_detection() {
+        assert!(is_loopback("127.0.0.1:3443"));
+        assert!(is_loopback("[::1]:3443"));
+        assert!(!is_loopback("0.0.0.0:3443"));
+        assert!(!is_loopback("not an address"));
+    }
+
+    #[test]
+    fn root_key_files_outside_protected_paths_are_insecure() {
+        let protected = [PathBuf::from("/secrets"), PathBuf::from("/root.key")];
+        let flagged = |path: &str| {
+            unprotected_root_key(&FileSealingKey::new(Path::new(path)), &protected).is_some()
+        };
+
+        assert!(!flagged("/secrets/root.key"));
+        assert!(!flagged("/root.key"));
+        assert!(flagged("/secrets-old/root.key"));
+        assert!(flagged("/secrets/../tmp/root.key"));
+        assert!(flagged("secrets/root.key"));
+        assert!(flagged("/tmp/root.key"));
+        assert!(unprotected_root_key(&CommandSealingKey::new("true"), &[]).is_none());
+    }
+
+    #[test]
+    fn insecure_configuration_requires_acknowledgement() {
+        let reasons = vec!["testing".to_string()];
+
+        assert!(matches!(
+            enforce(&reasons, false),
+            Err(ProviderError::ConfigError(_))
+        ));
+        assert!(enforce(&reasons, true).is_ok());
+        assert!(enforce(&[], false).is_ok());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::audit::unix_now;
+use crate::crypto::{backend, constant_time_eq, MasterSecret, SecretBytes};
+use crate::error::ProviderError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+const JOURNAL_KEY_LABEL: &[u8] = b"gramine-sealing-key-provider/session-journal/v1";
+
+/// Finished sessions are remembered this long, so a client that reconnects
+/// after a restart still gets a definite answer.
+const RETENTION_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Open,
+    /// The client closed it, or it ended with the connection or by idling.
+    Closed,
+    /// It was open when the provider stopped, so its keys are gone.
+    Invalidated,
+    /// Not in the journal, or forgotten after the retention period.
+    Unknown,
+}
+
+impl SessionState {
+    pub fn name(self) -> &'static str {
+        match self {
+            SessionState::Open => "open",
+            SessionState::Closed => "closed",
+            SessionState::Invalidated => "invalidated",
+            SessionState::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    state: SessionState,
+    /// When the session opened, or when it last changed state.
+    updated_at: u64,
+}
+
+/// On-disk form of the journal. `mac` is keyed from the master secret over
+/// the JSON encoding of `sessions`.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    sessions: BTreeMap<String, Entry>,
+    mac: String,
+}
+
+/// Sessions opened by this provider, by the provider's session public key
+/// (hex), persisted so their fate survives a restart.
+///
+/// Session keys live only in enclave memory, so a restart ends every open
+/// session. On open, the journal marks the sessions the previous run left
+/// open as invalidated, and a client that reconnects can ask for the state
+/// of its session instead of waiting on a connection the host may keep
+/// open. Like the counter store, the file belongs on a Gramine encrypted
+/// mount and is replaced atomically on every change.
+pub struct SessionJournal {
+    path: PathBuf,
+    key: SecretBytes,
+    sessions: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl SessionJournal {
+    pub fn open(path: &Path, master: &MasterSecret) -> Result<Self, ProviderError> {
+        let key = master.expand(JOURNAL_KEY_LABEL)?;
+        let mut sessions = match fs::read(path) {
+            Ok(data) => {
+                let record: Record = serde_json::from_slice(&data)?;
+                let expected = journal_mac(&key, &record.sessions)?;
+                let valid =
+                    hex::decode(&record.mac).is_ok_and(|mac| constant_time_eq(&mac, &expected));
+                if !valid {
+                    return Err(ProviderError::CryptoError(format!(
+                        "Session journal {} failed its integrity check",
+                        path.display()
+                    )));
+                }
+                record.sessions
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(ProviderError::IOError(e)),
+        };
+
+        let now = unix_now();
+        let mut invalidated = 0;
+        for entry in sessions.values_mut() {
+            if entry.state == SessionState::Open {
+                entry.state = SessionState::Invalidated;
+                entry.updated_at = now;
+                invalidated += 1;
+            }
+        }
+        sessions.retain(|_, entry| now.saturating_sub(entry.updated_at) < RETENTION_SECS);
+        info!(
+            "Opened session journal {}; {} sessions left open by the previous run are invalidated",
+            path.display(),
+            invalidated
+        );
+
+        let journal = Self {
+            path: path.to_path_buf(),
+            key,
+            sessions: Mutex::new(sessions),
+        };
+        journal.commit(&journal.lock())?;
+        Ok(journal)
+    }
+
+    /// Records that the session with provider key `id` opened.
+    pub fn opened(&self, id: &[u8]) {
+        self.set(id, SessionState::Open);
+    }
+
+    /// Records that the session with provider key `id` ended.
+    pub fn closed(&self, id: &[u8]) {
+        self.set(id, SessionState::Closed);
+    }
+
+    pub fn state(&self, id: &[u8]) -> SessionState {
+        self.lock()
+            .get(&hex::encode(id))
+            .map_or(SessionState::Unknown, |entry| entry.state)
+    }
+
+    /// A failed write is logged rather than failing the session: the worst
+    /// outcome is a session reported as unknown after a restart.
+    fn set(&self, id: &[u8], state: SessionState) {
+        let mut sessions = self.lock();
+        let now = unix_now();
+        sessions.retain(|_, entry| {
+            entry.state == SessionState::Open
+                || now.saturating_sub(entry.updated_at) < RETENTION_SECS
+        });
+        sessions.insert(
+            hex::encode(id),
+            Entry {
+                state,
+                updated_at: now,
+            },
+        );
+        if let Err(e) = self.commit(&sessions) {
+            warn!(
+                "Failed to journal session as {}: {}",
+                state.name(),
+                e.chain()
+            );
+        }
+    }
+
+    fn commit(&self, sessions: &BTreeMap<String, Entry>) -> Result<(), ProviderError> {
+        let record = Record {
+            sessions: sessions.clone(),
+            mac: hex::encode(journal_mac(&self.key, sessions)?),
+        };
+        let staged = self.path.with_extension("tmp");
+        let mut file = File::create(&staged)?;
+        file.write_all(&serde_json::to_vec(&record)?)?;
+        file.sync_all()?;
+        fs::rename(&staged, &self.path)?;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Entry>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn journal_mac(
+    key: &SecretBytes,
+    sessions: &BTreeMap<String, Entry>,
+) -> Result<[u8; 32], ProviderError> {
+    Ok(backend().hmac_sha256(key.expose(), &[&serde_json::to_vec(sessions)?]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_sessions_are_invalidated_by_a_restart() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[3; 16], None).unwrap();
+        let path =
+            std::env::temp_dir().join(format!("session-journal-{}.json", std::process::id()));
+
+        let journal = SessionJournal::open(&path, &master).unwrap();
+        journal.opened(b"left-open");
+        journal.opened(b"finished");
+        journal.closed(b"finished");
+        drop(journal);
+
+        let restarted = SessionJournal::open(&path, &master).unwrap();
+        let other = MasterSecret::from_sealing_key(&[4; 16], None).unwrap();
+        let foreign = SessionJournal::open(&path, &other);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restarted.state(b"left-open"), SessionState::Invalidated);
+        assert_eq!(restarted.state(b"finished"), SessionState::Closed);
+        assert_eq!(restarted.state(b"never"), SessionState::Unknown);
+        assert!(matches!(foreign, Err(ProviderError::CryptoError(_))));
+    }
+}
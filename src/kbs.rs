@@ -0,0 +1,465 @@
+//! Key Broker Service protocol front end, so confidential containers guests
+//! can fetch their key without a separate broker.
+//!
+//! Implements the RCAR handshake of KBS protocol 0.1.x over plain HTTP/1.1:
+//! `POST /kbs/v0/auth` hands out a nonce, `POST /kbs/v0/attest` takes a TDX
+//! quote whose report data commits to that nonce and the guest's RSA key, and
+//! `GET /kbs/v0/resource/default/sealing-key/td` returns the key derived for
+//! the quote's measurements, encrypted to that RSA key. Quotes go through the
+//! same verification, same-platform check, audit and rejection accounting as
+//! key requests. Sessions are tracked by the `kbs-session-id` cookie and end
+//! after `server.session_idle_timeout_secs`.
+//!
+//! There is no resource repository: the only resource is the sealing key.
+
+use crate::crypto::{encrypt_jwe_rsa, FlattenedJwe, SecretBytes};
+use crate::error::ProviderError;
+use crate::evidence::EvidenceKind;
+use crate::http::{read_request, write_response, Request, Response};
+use crate::logging::new_request_id;
+use crate::quote::{self, AttestedQuote};
+use crate::server::bind;
+use crate::state::ProviderState;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::{debug, error, info, warn};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha384};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+
+const SESSION_COOKIE: &str = "kbs-session-id";
+/// Repository, type and tag of the sealing key resource.
+const SEALING_KEY_RESOURCE: &str = "default/sealing-key/td";
+const RESOURCE_PREFIX: &str = "/kbs/v0/resource/";
+const ERROR_TYPE_PREFIX: &str = "https://github.com/confidential-containers/kbs/errors/";
+const TOKEN_ISSUER: &str = "gramine-sealing-key-provider";
+const NONCE_LEN: usize = 32;
+const MIN_RSA_BITS: usize = 2048;
+/// Live sessions held before new ones are refused. Each costs a little
+/// memory and anyone who reaches the port can open one.
+const MAX_SESSIONS: usize = 4096;
+
+/// Serves the KBS protocol on `addr`.
+pub async fn serve(addr: &str, state: Arc<ProviderState>) -> Result<(), ProviderError> {
+    let listener = bind(addr).await?;
+    let kbs = Arc::new(Kbs {
+        state,
+        sessions: Mutex::new(HashMap::new()),
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, peer_addr)) => {
+                    debug!("New KBS connection from: {}", peer_addr);
+                    let kbs = Arc::clone(&kbs);
+                    tokio::spawn(async move {
+                        if let Err(e) = kbs.handle_connection(socket).await {
+                            debug!("KBS connection error from {}: {}", peer_addr, e.chain());
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept KBS connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Kbs {
+    state: Arc<ProviderState>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    nonce: String,
+    expires: Instant,
+    attested: Option<AttestedSession>,
+}
+
+/// What a session may fetch once its evidence verified.
+struct AttestedSession {
+    derived_key: SecretBytes,
+    tee_key: RsaPublicKey,
+    alg: String,
+}
+
+/// `Request` of the handshake.
+#[derive(Deserialize)]
+struct AuthRequest {
+    version: String,
+    tee: String,
+}
+
+/// `Attestation` of the handshake.
+#[derive(Deserialize)]
+struct Attestation {
+    #[serde(rename = "tee-pubkey")]
+    tee_pubkey: Value,
+    /// A JSON object, or a string holding one as older guests send it.
+    #[serde(rename = "tee-evidence")]
+    tee_evidence: Value,
+}
+
+#[derive(Deserialize)]
+struct TeePubKey {
+    kty: String,
+    alg: String,
+    #[serde(alias = "k_mod")]
+    n: String,
+    #[serde(alias = "k_exp")]
+    e: String,
+}
+
+#[derive(Deserialize)]
+struct TdxEvidence {
+    quote: String,
+}
+
+/// A KBS error: `kind` names it the way the reference broker does.
+fn error(status: u16, kind: &str, detail: impl Into<String>) -> Response {
+    Response::json(
+        status,
+        json!({
+            "type": format!("{}{}", ERROR_TYPE_PREFIX, kind),
+            "detail": detail.into(),
+        }),
+    )
+}
+
+impl Kbs {
+    async fn handle_connection(&self, mut socket: TcpStream) -> Result<(), ProviderError> {
+        let mut buffer = Vec::new();
+        loop {
+            let Some((request, body)) = read_request(&mut socket, &mut buffer).await? else {
+                return Ok(());
+            };
+            let response = self.handle(&request, &body).await;
+            write_response(&mut socket, &response, request.keep_alive).await?;
+            if !request.keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn handle(&self, request: &Request, body: &[u8]) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/kbs/v0/auth") => self.auth(body),
+            ("POST", "/kbs/v0/attest") => self.attest(request, body).await,
+            ("GET", path) if path.starts_with(RESOURCE_PREFIX) => {
+                self.resource(request, &path[RESOURCE_PREFIX.len()..])
+            }
+            _ => error(404, "NotFound", "No such endpoint"),
+        }
+    }
+
+    fn auth(&self, body: &[u8]) -> Response {
+        let request: AuthRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return error(400, "InvalidRequest", e.to_string()),
+        };
+        if !request.version.starts_with("0.1.") {
+            return error(
+                400,
+                "ProtocolVersion",
+                format!("Protocol version {} is not supported", request.version),
+            );
+        }
+        if request.tee != "tdx" {
+            return error(
+                400,
+                "UnsupportedTee",
+                format!("TEE {} is not supported, only tdx", request.tee),
+            );
+        }
+
+        let ttl = self.state.settings().session_idle_timeout;
+        let id = format!("{}{}", new_request_id(), new_request_id());
+        let nonce = STANDARD.encode(rand::random::<[u8; NONCE_LEN]>());
+        let mut sessions = self.sessions();
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires > now);
+        // Refusing keeps the handshakes already under way; evicting would let
+        // a flood of new sessions end them
+        if sessions.len() >= MAX_SESSIONS {
+            warn!("Refusing KBS session: {} are open", sessions.len());
+            return error(429, "TooManySessions", "Too many open sessions");
+        }
+        sessions.insert(
+            id.clone(),
+            Session {
+                nonce: nonce.clone(),
+                expires: now + ttl,
+                attested: None,
+            },
+        );
+
+        let mut response = Response::json(200, json!({ "nonce": nonce, "extra-params": "" }));
+        response.headers.push((
+            "Set-Cookie",
+            format!(
+                "{}={}; Max-Age={}; Path=/kbs",
+                SESSION_COOKIE,
+                id,
+                ttl.as_secs()
+            ),
+        ));
+        response
+    }
+
+    async fn attest(&self, request: &Request, body: &[u8]) -> Response {
+        // Each nonce is good for one attestation
+        let nonce = match self.session(request, |session| match session.attested {
+            Some(_) => None,
+            None => Some(session.nonce.clone()),
+        }) {
+            Some(Some(nonce)) => nonce,
+            Some(None) => return error(400, "InvalidRequest", "Session already attested"),
+            None => return error(401, "UnAuthenticated", "No valid session"),
+        };
+
+        let (attestation, tee_key, alg, quote) = match parse_attestation(body) {
+            Ok(parsed) => parsed,
+            Err(e) => return error(400, "InvalidRequest", e.to_string()),
+        };
+        let bind = |report_data: &[u8]| {
+            if binds_runtime_data(report_data, &nonce, &attestation.tee_pubkey) {
+                Ok(())
+            } else {
+                Err(ProviderError::PublicKeyError(
+                    "Report data does not match the nonce and tee-pubkey".into(),
+                ))
+            }
+        };
+        let attested = match quote::attest_quote(&quote, &self.state, bind).await {
+            Ok(attested) => attested,
+            Err(e) => {
+                warn!("KBS attestation rejected: {}", e.chain());
+                return error(401, "AttestationFailed", e.to_string());
+            }
+        };
+        // The session was opened for a tdx TEE
+        if attested.kind != EvidenceKind::Tdx {
+            return error(
+                401,
+                "AttestationFailed",
+                format!("Expected tdx evidence, got {}", attested.kind.name()),
+            );
+        }
+
+        let token = match self.token(&attested, &attestation.tee_pubkey) {
+            Ok(token) => token,
+            Err(e) => {
+                error!("Failed to sign KBS attestation token: {}", e.chain());
+                return error(500, "TokenIssueFailed", e.to_string());
+            }
+        };
+        let stored = self.session(request, |session| {
+            session.attested = Some(AttestedSession {
+                derived_key: attested.derived_key,
+                tee_key,
+                alg,
+            });
+        });
+        if stored.is_none() {
+            return error(401, "UnAuthenticated", "Session expired");
+        }
+        info!(
+            "KBS session attested, key id {}",
+            hex::encode(&attested.key_id)
+        );
+        Response::json(200, json!({ "token": token }))
+    }
+
+    fn resource(&self, request: &Request, path: &str) -> Response {
+        if path != SEALING_KEY_RESOURCE {
+            return error(404, "ResourceNotFound", format!("No resource {}", path));
+        }
+        let jwe = self.session(request, |session| {
+            session.attested.as_ref().map(|attested| {
+                encrypt_jwe_rsa(
+                    attested.derived_key.expose(),
+                    &attested.alg,
+                    &attested.tee_key,
+                )
+            })
+        });
+        match jwe {
+            Some(Some(Ok(jwe))) => Response::json(200, jwe_body(&jwe)),
+            Some(Some(Err(e))) => {
+                error!("Failed to encrypt KBS resource: {}", e.chain());
+                error(500, "ResourceEncryption", e.to_string())
+            }
+            Some(None) | None => error(401, "UnAuthenticated", "Session is not attested"),
+        }
+    }
+
+    /// A JWT over the attestation result, signed EdDSA with the provider
+    /// identity key.
+    fn token(&self, attested: &AttestedQuote, tee_pubkey: &Value) -> Result<String, ProviderError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let ttl = self.state.settings().session_idle_timeout.as_secs();
+        let header = json!({ "alg": "EdDSA", "typ": "JWT" });
+        let claims = json!({
+            "iss": TOKEN_ISSUER,
+            "iat": now,
+            "exp": now + ttl,
+            "tee-pubkey": tee_pubkey,
+            "measurements": hex::encode(&attested.measurements),
+            "key_id": hex::encode(&attested.key_id),
+            "tcb_status": attested.quote_tcb.as_ref().map(|tcb| &tcb.status),
+        });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+        );
+        let signature = self.state.identity.sign(signing_input.as_bytes())?;
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    /// Runs `f` on the request's session if it has a live one.
+    fn session<T>(&self, request: &Request, f: impl FnOnce(&mut Session) -> T) -> Option<T> {
+        let id = request.cookie(SESSION_COOKIE)?;
+        let mut sessions = self.sessions();
+        let session = sessions.get_mut(id)?;
+        if session.expires <= Instant::now() {
+            sessions.remove(id);
+            return None;
+        }
+        Some(f(session))
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The attestation, the guest's RSA key and its `alg`, and the decoded
+/// quote.
+fn parse_attestation(
+    body: &[u8],
+) -> Result<(Attestation, RsaPublicKey, String, Vec<u8>), ProviderError> {
+    let attestation: Attestation = serde_json::from_slice(body)?;
+
+    let key: TeePubKey = serde_json::from_value(attestation.tee_pubkey.clone())?;
+    if key.kty != "RSA" {
+        return Err(ProviderError::PublicKeyError(format!(
+            "tee-pubkey type {} is not supported, only RSA",
+            key.kty
+        )));
+    }
+    if key.alg != "RSA-OAEP" && key.alg != "RSA-OAEP-256" {
+        return Err(ProviderError::PublicKeyError(format!(
+            "tee-pubkey algorithm {} is not supported",
+            key.alg
+        )));
+    }
+    let tee_key = RsaPublicKey::new(
+        BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(&key.n)?),
+        BigUint::from_bytes_be(&URL_SAFE_NO_PAD.decode(&key.e)?),
+    )
+    .map_err(|e| ProviderError::PublicKeyError(format!("Invalid tee-pubkey: {}", e)))?;
+    if tee_key.n().bits() < MIN_RSA_BITS {
+        return Err(ProviderError::PublicKeyError(format!(
+            "tee-pubkey must be at least {} bits",
+            MIN_RSA_BITS
+        )));
+    }
+
+    let evidence: TdxEvidence = match &attestation.tee_evidence {
+        Value::String(evidence) => serde_json::from_str(evidence)?,
+        evidence => serde_json::from_value(evidence.clone())?,
+    };
+    let quote = STANDARD.decode(&evidence.quote)?;
+    Ok((attestation, tee_key, key.alg, quote))
+}
+
+/// Whether the quote's report data starts with the SHA-384 of the runtime
+/// data `{"nonce", "tee-pubkey"}`, serialized with sorted keys as the guest
+/// serializes it.
+fn binds_runtime_data(report_data: &[u8], nonce: &str, tee_pubkey: &Value) -> bool {
+    let runtime_data = json!({ "nonce": nonce, "tee-pubkey": tee_pubkey });
+    let digest = Sha384::digest(runtime_data.to_string().as_bytes());
+    report_data.get(..digest.len()) == Some(digest.as_slice())
+}
+
+fn jwe_body(jwe: &FlattenedJwe) -> Value {
+    serde_json::to_value(jwe).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collateral::CollateralCache;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+    use crate::padding::ResponsePadding;
+    use crate::policy::Policy;
+    use crate::state::Settings;
+    use crate::verifier::DcapVerifier;
+    use std::time::Duration;
+
+    #[test]
+    fn runtime_data_is_hashed_with_sorted_keys() {
+        let tee_pubkey = json!({ "kty": "RSA", "alg": "RSA-OAEP", "n": "AQAB", "e": "AQAB" });
+        let expected = Sha384::digest(
+            br#"{"nonce":"bm9uY2U=","tee-pubkey":{"alg":"RSA-OAEP","e":"AQAB","kty":"RSA","n":"AQAB"}}"#,
+        );
+        let mut report_data = expected.to_vec();
+        report_data.resize(64, 0);
+
+        assert!(binds_runtime_data(&report_data, "bm9uY2U=", &tee_pubkey));
+        assert!(!binds_runtime_data(&report_data, "b3RoZXI=", &tee_pubkey));
+    }
+
+    #[test]
+    fn sessions_are_refused_at_the_cap() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[8; 16], None).unwrap();
+        let state = ProviderState::new(
+            MasterSecret::from_sealing_key(&[8; 16], None).unwrap(),
+            Box::new(ProviderIdentity::from_master(&master).unwrap()),
+            Settings {
+                policy: Policy::default(),
+                session_idle_timeout: Duration::from_secs(60),
+                padding: ResponsePadding::default(),
+            },
+            Box::new(DcapVerifier::new(CollateralCache::in_memory())),
+            None,
+            None,
+            None,
+        );
+        let kbs = Kbs {
+            state: Arc::new(state),
+            sessions: Mutex::new(HashMap::new()),
+        };
+        let auth = br#"{"version": "0.1.0", "tee": "tdx"}"#;
+
+        for _ in 0..MAX_SESSIONS {
+            assert_eq!(kbs.auth(auth).status, 200);
+        }
+        assert_eq!(kbs.auth(auth).status, 429);
+
+        // Expired sessions make room again
+        for session in kbs.sessions().values_mut() {
+            session.expires = Instant::now();
+        }
+        assert_eq!(kbs.auth(auth).status, 200);
+        assert_eq!(kbs.sessions().len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+mod sampling;
+mod sinks;
+
+use crate::config::{LogFormat, LogSink, LoggingConfig};
+use crate::error::ProviderError;
+use crate::metrics::PhaseTimer;
+pub use sampling::dropped as dropped_lines;
+use sampling::Sampler;
+use sinks::{RotatingFile, Syslog};
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use tracing::{info_span, Level, Span, Subscriber};
+use tracing_subscriber::filter::{filter_fn, FilterExt};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// Installs the global subscriber. Lines are JSON by default, or plain text
+/// with `format = "text"`; `RUST_LOG` filters as before. They go to the
+/// configured sink: stdout, stderr, a rotating file or syslog, sampled and
+/// rate limited as configured.
+/// Existing `log` records are forwarded, so they carry the current request
+/// span too. Spans are also exported over OTLP when configured, and the
+/// phases of key requests are timed whatever the filter.
+pub fn init(config: &LoggingConfig) -> Result<(), ProviderError> {
+    let format = config.format;
+    let output = match config.sink {
+        LogSink::Stdout => format_layer(format, true, io::stdout),
+        LogSink::Stderr => format_layer(format, true, io::stderr),
+        LogSink::File => {
+            let path = config.file.as_deref().ok_or_else(|| {
+                ProviderError::ConfigError("logging.file is required with the file sink".into())
+            })?;
+            let file = RotatingFile::open(path, config.max_file_bytes, config.max_files)?;
+            format_layer(format, false, file)
+        }
+        LogSink::Syslog => {
+            let target = config.syslog.as_deref().ok_or_else(|| {
+                ProviderError::ConfigError("logging.syslog is required with the syslog sink".into())
+            })?;
+            format_layer(format, false, Syslog::connect(target)?)
+        }
+    };
+
+    let timer = PhaseTimer::new(config.slow_request_threshold())
+        .with_filter(filter_fn(PhaseTimer::is_timed).with_max_level_hint(Level::INFO));
+
+    let sampler = Sampler::new(
+        config.sample_requests,
+        config.sample_admin,
+        config.max_lines_per_sec,
+    );
+
+    tracing_subscriber::registry()
+        .with(output.with_filter(env_filter().and(sampler)))
+        .with(otlp_layer()?.with_filter(env_filter()))
+        .with(timer)
+        .try_init()
+        .map_err(|e| ProviderError::ConfigError(format!("Cannot initialize logging: {}", e)))
+}
+
+fn env_filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"))
+}
+
+fn format_layer<S, W>(format: LogFormat, ansi: bool, writer: W) -> BoxedLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Text => fmt::layer().with_ansi(ansi).with_writer(writer).boxed(),
+    }
+}
+
+/// Exports spans to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT`, if set.
+fn otlp_layer<S>() -> Result<Option<BoxedLayer<S>>, ProviderError>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Ok(endpoint) = env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::KeyValue;
+        use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .build()
+            .map_err(|e| {
+                ProviderError::ConfigError(format!("Cannot export spans to {}: {}", endpoint, e))
+            })?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "gramine-sealing-key-provider",
+            )]))
+            .build();
+        let tracer = provider.tracer("gramine-sealing-key-provider");
+        opentelemetry::global::set_tracer_provider(provider);
+
+        Ok(Some(
+            tracing_opentelemetry::layer().with_tracer(tracer).boxed(),
+        ))
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        Err(ProviderError::ConfigError(format!(
+            "OTLP endpoint {} configured but the provider was built without the `otel` feature",
+            endpoint
+        )))
+    }
+}
+
+/// Random ID that ties the log lines of one connection together.
+pub fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Span covering one connection from accept to the last response.
+pub fn connection_span(peer_addr: SocketAddr, request_id: &str) -> Span {
+    info_span!("request", id = %request_id, peer = %peer_addr)
+}
+
+/// Like [`connection_span`], for a connection to the admin API, which is
+/// sampled separately.
+pub fn admin_span(peer_addr: SocketAddr, request_id: &str) -> Span {
+    info_span!("admin", id = %request_id, peer = %peer_addr)
+}
@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Informational lines the rate limit dropped, for the metrics.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Marks a connection span as logged in full or not.
+struct Sampled(bool);
+
+/// Thins out the informational lines of busy deployments, so debug-rich
+/// logging does not saturate enclave I/O. Each connection is sampled as a
+/// whole: one in `requests` key request connections (one in `admin` admin
+/// connections) is logged in full, the others only log warnings and errors,
+/// which is where failures end up. On top of that, at most `max_per_sec`
+/// informational lines are written per second. Warnings and errors always
+/// pass.
+pub struct Sampler {
+    requests: u64,
+    admin: u64,
+    seen_requests: AtomicU64,
+    seen_admin: AtomicU64,
+    max_per_sec: u32,
+    window: Mutex<(Instant, u32)>,
+}
+
+impl Sampler {
+    pub fn new(requests: u32, admin: u32, max_per_sec: u32) -> Self {
+        Self {
+            requests: requests.max(1).into(),
+            admin: admin.max(1).into(),
+            seen_requests: AtomicU64::new(0),
+            seen_admin: AtomicU64::new(0),
+            max_per_sec,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    fn always(metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() || *metadata.level() <= Level::WARN
+    }
+
+    /// Whether the next connection of span `name` is logged in full; `None`
+    /// for spans that are not connections.
+    fn sample(&self, name: &str) -> Option<bool> {
+        let (seen, every) = match name {
+            "request" => (&self.seen_requests, self.requests),
+            "admin" => (&self.seen_admin, self.admin),
+            _ => return None,
+        };
+        Some(seen.fetch_add(1, Ordering::Relaxed) % every == 0)
+    }
+
+    fn within_rate(&self, now: Instant) -> bool {
+        if self.max_per_sec == 0 {
+            return true;
+        }
+        let mut window = self.lock();
+        if now.duration_since(window.0) >= Duration::from_secs(1) {
+            *window = (now, 0);
+        }
+        if window.1 >= self.max_per_sec {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        window.1 += 1;
+        true
+    }
+
+    fn lock(&self) -> MutexGuard<'_, (Instant, u32)> {
+        self.window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S> Filter<S> for Sampler
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, metadata: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if Self::always(metadata) {
+            return true;
+        }
+        let sampled_out = cx.lookup_current().is_some_and(|span| {
+            span.scope().any(|span| {
+                span.extensions()
+                    .get::<Sampled>()
+                    .is_some_and(|sampled| !sampled.0)
+            })
+        });
+        !sampled_out && self.within_rate(Instant::now())
+    }
+
+    // Whether an event passes depends on its span and the time, never on the
+    // callsite alone
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if Self::always(metadata) {
+            Interest::always()
+        } else {
+            Interest::sometimes()
+        }
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, cx: Context<'_, S>) {
+        let Some(sampled) = self.sample(attrs.metadata().name()) else {
+            return;
+        };
+        if let Some(span) = cx.span(id) {
+            span.extensions_mut().insert(Sampled(sampled));
+        }
+    }
+}
+
+/// Informational lines dropped by the rate limit since startup.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_sampled_and_lines_capped_per_second() {
+        let sampler = Sampler::new(3, 1, 2);
+        let start = Instant::now();
+
+        let requests: Vec<_> = (0..6).map(|_| sampler.sample("request")).collect();
+        let passed: Vec<_> = [0, 10, 20, 1100]
+            .into_iter()
+            .map(|ms| sampler.within_rate(start + Duration::from_millis(ms)))
+            .collect();
+
+        assert_eq!(requests, [true, false, false, true, false, false].map(Some));
+        assert_eq!(sampler.sample("admin"), Some(true));
+        assert_eq!(sampler.sample("derive_key"), None);
+        assert_eq!(passed, [true, true, false, true]);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::error::ProviderError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+// RFC 5424 facility "system daemons"
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+const SYSLOG_APP_NAME: &str = "gramine-sealing-key-provider";
+
+/// A log file that is renamed to `<path>.1` once it would grow past
+/// `max_bytes`, shifting older files up and dropping `<path>.<max_files>`, so
+/// the logs never take more than about `(max_files + 1) * max_bytes`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: u32,
+    inner: Mutex<OpenFile>,
+}
+
+struct OpenFile {
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, max_files: u32) -> Result<Self, ProviderError> {
+        let file = append(path).map_err(|e| {
+            ProviderError::ConfigError(format!("Cannot open log file {}: {}", path.display(), e))
+        })?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            inner: Mutex::new(OpenFile { file, written }),
+        })
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self, open: &mut OpenFile) -> io::Result<()> {
+        for index in (1..self.max_files).rev() {
+            match fs::rename(self.rotated(index), self.rotated(index + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        open.file = append(&self.path)?;
+        open.written = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Holds the file for one whole event, so lines never interleave.
+pub struct RotatingWriter<'a> {
+    file: &'a RotatingFile,
+    open: MutexGuard<'a, OpenFile>,
+}
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.open.written > 0 && self.open.written + buf.len() as u64 > self.file.max_bytes {
+            self.file.rotate(&mut self.open)?;
+        }
+        let written = self.open.file.write(buf)?;
+        self.open.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.open.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingWriter {
+            file: self,
+            open: self
+                .inner
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        }
+    }
+}
+
+enum SyslogTransport {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+}
+
+/// Sends each event as one RFC 3164 datagram to a syslog daemon, over UDP or
+/// a local datagram socket such as `/dev/log`. The collector stamps the time
+/// and host.
+pub struct Syslog {
+    transport: SyslogTransport,
+    pid: u32,
+}
+
+impl Syslog {
+    /// `target` is `udp:<host>:<port>` or `unix:<path>`.
+    pub fn connect(target: &str) -> Result<Self, ProviderError> {
+        let connect_error = |e: io::Error| {
+            ProviderError::ConfigError(format!("Cannot reach syslog at {}: {}", target, e))
+        };
+        let transport = if let Some(addr) = target.strip_prefix("udp:") {
+            let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(connect_error)?;
+            socket.connect(addr).map_err(connect_error)?;
+            SyslogTransport::Udp(socket)
+        } else if let Some(path) = target.strip_prefix("unix:") {
+            let socket = UnixDatagram::unbound().map_err(connect_error)?;
+            socket.connect(path).map_err(connect_error)?;
+            SyslogTransport::Unix(socket)
+        } else {
+            return Err(ProviderError::ConfigError(format!(
+                "Unknown syslog target {:?}; expected udp:<host>:<port> or unix:<path>",
+                target
+            )));
+        };
+        Ok(Self {
+            transport,
+            pid: std::process::id(),
+        })
+    }
+}
+
+pub struct SyslogWriter<'a> {
+    syslog: &'a Syslog,
+    severity: u8,
+}
+
+impl Write for SyslogWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut message = format!(
+            "<{}>{}[{}]: ",
+            SYSLOG_FACILITY_DAEMON * 8 + self.severity,
+            SYSLOG_APP_NAME,
+            self.syslog.pid
+        )
+        .into_bytes();
+        message.extend_from_slice(buf.strip_suffix(b"\n").unwrap_or(buf));
+        match &self.syslog.transport {
+            SyslogTransport::Udp(socket) => socket.send(&message)?,
+            SyslogTransport::Unix(socket) => socket.send(&message)?,
+        };
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogWriter {
+            syslog: self,
+            severity: severity(&Level::INFO),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogWriter {
+            syslog: self,
+            severity: severity(meta.level()),
+        }
+    }
+}
+
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_rotates_and_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("rotating-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("provider.log");
+
+        let log = RotatingFile::open(&path, 16, 2).unwrap();
+        for line in 0..5 {
+            writeln!(log.make_writer(), "line {:09}", line).unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        let newest = fs::read_to_string(dir.join("provider.log.1")).unwrap();
+        let kept = dir.join("provider.log.2").exists();
+        let dropped = dir.join("provider.log.3").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(current, "line 000000004\n");
+        assert_eq!(newest, "line 000000003\n");
+        assert!(kept && !dropped);
+    }
+
+    #[test]
+    fn syslog_datagrams_carry_priority() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let syslog = Syslog::connect(&format!("udp:{}", collector.local_addr().unwrap())).unwrap();
+
+        let mut writer = syslog.make_writer();
+        writer.severity = severity(&Level::ERROR);
+        writer.write_all(b"collateral fetch failed\n").unwrap();
+
+        let mut buf = [0u8; 256];
+        let len = collector.recv(&mut buf).unwrap();
+        let datagram = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(datagram.starts_with("<27>gramine-sealing-key-provider["));
+        assert!(datagram.ends_with("]: collateral fetch failed"));
+    }
+}
@@ -1,30 +1,357 @@
+mod admin;
+mod allowlist;
+mod approvals;
+mod audit;
+mod cli;
+mod collateral;
+mod config;
+mod connections;
+mod corim;
+mod counters;
+mod crash;
 mod crypto;
+mod diagnostics;
 mod error;
+mod evidence;
 mod gramine;
+mod http;
+mod insecure;
+mod journal;
+mod kbs;
+mod logging;
+mod metrics;
+mod padding;
+mod policy;
+mod protocol;
 mod quote;
+mod ratelimit;
+mod reissue;
+mod rejections;
+mod releases;
+mod reload;
+mod remote_policy;
+mod replay;
+mod resources;
+mod revocations;
+mod rollback;
+mod sealing;
+mod secrets;
 mod server;
+mod session;
+mod state;
+mod tenants;
+mod vault;
+mod verifier;
+mod watched;
+mod webhooks;
+mod windows;
 
+use audit::{AuditAnchor, AuditExport, AuditLog};
+use clap::Parser;
+use cli::{Cli, Command};
+use collateral::CollateralCache;
+use config::{Config, Mode};
+use counters::CounterStore;
+use crypto::{ProviderIdentity, Signer};
 use error::ProviderError;
 use log::info;
 use server::Server;
-use std::env;
+use state::{ProviderState, Settings};
+use std::path::Path;
 
 #[tokio::main]
 async fn main() -> Result<(), ProviderError> {
+    // Before anything can hold key material
+    crash::install();
+
     // Initialize sodium first
     crypto::init_sodium()?;
-    
-    env_logger::init();
+
+    // Gramine passes no arguments unless the manifest allows it, so a bare
+    // invocation serves
+    let cli = Cli::parse();
+    let config_path = cli.config.as_deref();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config_path, cli.insecure_i_know).await,
+        Command::CheckConfig => cli::check_config(&Config::load(config_path)?),
+        Command::InspectQuote(args) => cli::inspect_quote(&args, config_path).await,
+        Command::SelfTest => cli::self_test(&Config::load(config_path)?).await,
+        Command::DeriveTestvector(args) => cli::derive_testvector(&args),
+        Command::VerifyAuditLog(args) => cli::verify_audit_log(&args),
+    }
+}
+
+async fn serve(
+    config_path: Option<&Path>,
+    insecure_acknowledged: bool,
+) -> Result<(), ProviderError> {
+    let config = Config::load(config_path)?;
+    logging::init(&config.logging)?;
     info!("Starting Gramine Sealing Key Provider");
 
+    let addr = config.server.addr.clone();
+
+    // Must be selected before any key material is derived
+    crypto::init_backend(&config.crypto.backend)?;
+
+    webhooks::init(
+        &config.webhooks,
+        secrets::secret_setting("SEALING_PROVIDER_WEBHOOK_SECRET")?,
+    )?;
+
     #[cfg(feature = "dev-mode")]
-    log::warn!("Running in DEVELOPMENT mode - security features are reduced");
+    {
+        log::warn!("Running in DEVELOPMENT mode - security features are reduced");
+        webhooks::notify(webhooks::Event::DevModeStartup);
+    }
 
     #[cfg(not(feature = "dev-mode"))]
     info!("Running in PRODUCTION mode - full security enabled");
 
-    let addr = env::var("SEALING_PROVIDER_ADDR").unwrap_or_else(|_| "0.0.0.0:3443".to_string());
-    
-    let server = Server::new(addr);
+    // The host controls the OS entropy sources, so refuse to run without a
+    // working hardware RNG to mix in
+    crypto::check_hardware_entropy()?;
+
+    init_platform(&config)?;
+    let root_key = sealing::from_env()?;
+    insecure::enforce(
+        &insecure::insecure_reasons(&addr, root_key.as_ref(), &config.platform.protected_paths)?,
+        insecure_acknowledged,
+    )?;
+
+    // Before touching the sealing key, make sure this is not a downgraded build
+    if let Some(dir) = &config.files.svn_record_dir {
+        if gramine::is_sgx() {
+            rollback::check_provider_svn(dir, gramine::own_report()?.isv_svn)?;
+        } else {
+            log::warn!("Skipping provider SVN check outside an SGX enclave");
+        }
+    }
+
+    // Read the sealing key once; only the derived master secret is kept
+    let partition = kss_partition(config.platform.kss_derivation)?;
+    let master = {
+        info!("Deriving master secret from {}", root_key.describe());
+        let sealing_key = root_key.sealing_key()?;
+        sealing_key
+            .with_bytes(|key| crypto::MasterSecret::from_sealing_key(key, partition.as_deref()))?
+    };
+
+    let identity = load_identity(&master, &config.crypto)?;
+
+    let collateral = CollateralCache::from_config(&config.collateral)?;
+
+    // Counts provider starts, so clients can tell when it restarted
+    let counters = match &config.files.counters {
+        Some(path) => {
+            let counters = CounterStore::open(path, &master)?;
+            let epoch = counters.increment(counters::BOOT_EPOCH).await?;
+            info!("Boot epoch {}", epoch);
+            webhooks::notify(webhooks::Event::EpochRotated {
+                boot_epoch: epoch,
+                cause: "startup",
+            });
+            Some(counters)
+        }
+        None => None,
+    };
+
+    // A remote policy must verify before anything is served under it, and
+    // may not be older than one applied before a restart
+    let mut settings = Settings::from_config(&config)?;
+    let remote_policy =
+        remote_policy::RemotePolicy::from_config(&config.remote_policy, counters.as_ref())?;
+    if let Some(remote) = &remote_policy {
+        let (version, policy) = remote.fetch().await?;
+        remote.mark_applied(version, counters.as_ref()).await?;
+        settings.policy = policy;
+    }
+
+    // Hash-chained record of every key release, signed by the identity key
+    // and anchored outside the host if asked
+    let anchor = config
+        .audit_export
+        .anchor_url
+        .as_deref()
+        .map(AuditAnchor::start)
+        .transpose()?;
+    let audit = config
+        .files
+        .audit_log
+        .as_deref()
+        .map(AuditLog::open)
+        .transpose()?
+        .map(|audit| audit.with_anchor(anchor));
+    let export = config
+        .audit_export
+        .target
+        .as_deref()
+        .map(|target| AuditExport::start(target, config.audit_export.format))
+        .transpose()?;
+
+    // Invalidates the sessions a previous run left open
+    let journal = config
+        .files
+        .session_journal
+        .as_deref()
+        .map(|path| journal::SessionJournal::open(path, &master))
+        .transpose()?;
+
+    // Checked on every request, whatever the policy
+    let revocations = config
+        .files
+        .revocation_list
+        .as_deref()
+        .map(revocations::RevocationList::load)
+        .transpose()?;
+
+    let verifier = verifier::by_name(&config.collateral.verifier, collateral, &config.veraison)?;
+    info!("Verifying quotes with {}", verifier.name());
+    if gramine::is_attested() {
+        diagnostics::check_platform(verifier.as_ref()).await;
+    }
+    let snp = evidence::SnpVerifier::from_config(&config.sev_snp)?;
+    let tpm = evidence::TpmVerifier::from_config(&config.tpm)?;
+
+    let state = ProviderState::new(
+        master, identity, settings, verifier, counters, audit, export,
+    )
+    .with_journal(journal)
+    .with_revocations(revocations)
+    .with_remote_policy(remote_policy)
+    .with_snp(snp)
+    .with_azure_tdx(config.azure_tdx.enabled.then(|| config.azure_tdx.clone()))
+    .with_gcp(evidence::GcpVerifier::from_config(&config.gcp))
+    .with_ita(evidence::ItaVerifier::from_config(&config.ita))
+    .with_maa(evidence::MaaVerifier::from_config(&config.maa))
+    .with_tpm(tpm);
+    #[cfg(feature = "cca")]
+    let state = state.with_cca(evidence::CcaVerifier::from_config(&config.cca)?);
+    let server = Server::new(addr, state);
+    let server = with_ratls(server, config.server.ratls_addr.clone())?;
+    if let Some(metrics_addr) = &config.server.metrics_addr {
+        metrics::serve(metrics_addr).await?;
+    }
+    if let Some(admin_addr) = &config.server.admin_addr {
+        let token = secrets::secret_setting("SEALING_PROVIDER_ADMIN_TOKEN")?.ok_or_else(|| {
+            ProviderError::ConfigError(
+                "SEALING_PROVIDER_ADMIN_TOKEN is required with server.admin_addr".into(),
+            )
+        })?;
+        admin::serve(admin_addr, token, server.state()).await?;
+    }
+    if let Some(kbs_addr) = &config.server.kbs_addr {
+        kbs::serve(kbs_addr, server.state()).await?;
+    }
+    if let Some(vault_addr) = &config.server.vault_addr {
+        vault::serve(vault_addr, server.state()).await?;
+    }
+    remote_policy::spawn_refresh(server.state());
+    reload::spawn_on_sighup(server.state(), config_path.map(Path::to_path_buf), config)?;
     server.run().await
 }
+
+/// Selects the attestation platform for `platform.mode`.
+fn init_platform(config: &Config) -> Result<(), ProviderError> {
+    let platform = &config.platform;
+    match platform.mode {
+        Mode::Standalone => gramine::init_standalone()?,
+        Mode::Td => gramine::init_td(&platform.tsm_report_dir)?,
+        Mode::Enclave => {
+            // Running without attestation needs this path as well as the insecure flag
+            gramine::init_platform(
+                platform.attestation_paths(),
+                platform.insecure_simulated_sealing_key.as_deref(),
+                platform.quote_service.clone(),
+            )?;
+        }
+    }
+    gramine::set_quote_refresh(config.quote_refresh())
+}
+
+/// With `kss_derivation` enabled, the provider's KSS identity partitions the
+/// derivation root. The enclave must be signed with KSS.
+fn kss_partition(enabled: bool) -> Result<Option<Vec<u8>>, ProviderError> {
+    if !enabled {
+        return Ok(None);
+    }
+    if !gramine::is_sgx() {
+        return Err(ProviderError::ConfigError("KSS derivation needs an SGX enclave".into()));
+    }
+
+    let identity = gramine::own_kss_identity()?.ok_or_else(|| {
+        ProviderError::ConfigError(
+            "KSS derivation requested but the enclave was not signed with KSS".into(),
+        )
+    })?;
+    info!(
+        "Partitioning derivation root by KSS identity (ISVFAMILYID {}, ISVEXTPRODID {})",
+        hex::encode(identity.isv_family_id),
+        hex::encode(identity.isv_ext_prod_id)
+    );
+    Ok(Some(identity.partition()))
+}
+
+/// Adds the RA-TLS listener when `server.ratls_addr` is set, with a
+/// certificate minted from a fresh quote at startup.
+fn with_ratls(server: Server, addr: Option<String>) -> Result<Server, ProviderError> {
+    let Some(addr) = addr else {
+        return Ok(server);
+    };
+
+    #[cfg(feature = "ratls")]
+    {
+        if !gramine::is_attested() && !gramine::is_simulated() {
+            return Err(ProviderError::ConfigError(
+                "RA-TLS needs an enclave quote and is not available in standalone mode".into(),
+            ));
+        }
+        let cert = crypto::mint_certificate(gramine::get_quote_with_data)?;
+        server.with_ratls(addr, cert)
+    }
+
+    #[cfg(not(feature = "ratls"))]
+    {
+        let _ = server;
+        Err(ProviderError::ConfigError(format!(
+            "RA-TLS listener {} configured but the provider was built without the `ratls` feature",
+            addr
+        )))
+    }
+}
+
+/// The transcript signing key lives in an HSM when a PKCS#11 module is
+/// configured, and is derived from the master secret otherwise.
+fn load_identity(
+    master: &crypto::MasterSecret,
+    crypto: &config::CryptoConfig,
+) -> Result<Box<dyn Signer>, ProviderError> {
+    let Some(module) = &crypto.pkcs11_module else {
+        return Ok(Box::new(ProviderIdentity::from_master(master)?));
+    };
+
+    #[cfg(feature = "pkcs11")]
+    {
+        let pin = secrets::secret_string("SEALING_PROVIDER_PKCS11_PIN")?.ok_or_else(|| {
+            ProviderError::ConfigError(
+                "SEALING_PROVIDER_PKCS11_PIN is required with a PKCS#11 module".into(),
+            )
+        })?;
+        let signer = crypto::Pkcs11Signer::open(
+            module,
+            // Config::validate has checked that both are set
+            crypto.pkcs11_token.as_deref().unwrap_or_default(),
+            crypto.pkcs11_key_label.as_deref().unwrap_or_default(),
+            &pin,
+        )?;
+        Ok(Box::new(signer))
+    }
+
+    #[cfg(not(feature = "pkcs11"))]
+    {
+        Err(ProviderError::ConfigError(format!(
+            "PKCS#11 module {} configured but the provider was built without the `pkcs11` feature",
+            module.display()
+        )))
+    }
+}
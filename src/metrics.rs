@@ -0,0 +1,296 @@
+use crate::error::ProviderError;
+use crate::logging;
+use crate::rejections;
+use crate::resources;
+use crate::server::bind;
+use log::{debug, warn};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tracing::span::{Attributes, Id};
+use tracing::{Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Span covering a whole key request.
+const REQUEST_SPAN: &str = "process_quotes";
+
+/// The timed phases of a key request, each measured by its span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    FetchCollateral,
+    Verify,
+    Derive,
+    Encrypt,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [
+        Phase::FetchCollateral,
+        Phase::Verify,
+        Phase::Derive,
+        Phase::Encrypt,
+    ];
+
+    fn from_span(name: &str) -> Option<Self> {
+        match name {
+            "fetch_collateral" => Some(Phase::FetchCollateral),
+            "dcap_verify" => Some(Phase::Verify),
+            "derive_key" => Some(Phase::Derive),
+            "encrypt_key" => Some(Phase::Encrypt),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::FetchCollateral => "fetch_collateral",
+            Phase::Verify => "verify",
+            Phase::Derive => "derive",
+            Phase::Encrypt => "encrypt",
+        }
+    }
+}
+
+/// A Prometheus histogram. Bucket counts are stored cumulatively.
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let count = self.count.load(Ordering::Relaxed);
+        let buckets = self
+            .buckets
+            .iter()
+            .zip(BUCKETS)
+            .map(|(bucket, bound)| (bound.to_string(), bucket.load(Ordering::Relaxed)))
+            .chain([("+Inf".to_string(), count)]);
+        for (bound, bucket) in buckets {
+            let le = format!("le=\"{}\"", bound);
+            let _ = writeln!(out, "{}_bucket{} {}", name, braces(&[labels, &le]), bucket);
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(&[labels]), sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(&[labels]), count);
+    }
+}
+
+/// `{a,b}` from the non-empty label pairs, or nothing.
+fn braces(labels: &[&str]) -> String {
+    let labels: Vec<&str> = labels.iter().copied().filter(|l| !l.is_empty()).collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+static PHASES: [Histogram; Phase::ALL.len()] = [const { Histogram::new() }; Phase::ALL.len()];
+static REQUESTS: Histogram = Histogram::new();
+static SLOW_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Times the phase spans of every key request into the histograms, and logs
+/// a warning with the breakdown when a request takes longer than
+/// `slow_request`.
+pub struct PhaseTimer {
+    slow_request: Option<Duration>,
+}
+
+impl PhaseTimer {
+    pub fn new(slow_request: Option<Duration>) -> Self {
+        Self { slow_request }
+    }
+
+    /// Lets only the spans this layer times through, so it does not enable
+    /// anything the log filter turned off.
+    pub fn is_timed(meta: &Metadata<'_>) -> bool {
+        meta.is_span() && (meta.name() == REQUEST_SPAN || Phase::from_span(meta.name()).is_some())
+    }
+}
+
+struct Started(Instant);
+
+/// Time spent in each phase so far, kept on the request span.
+#[derive(Clone, Copy, Default)]
+struct PhaseTimes([Duration; Phase::ALL.len()]);
+
+impl<S> Layer<S> for PhaseTimer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        extensions.insert(Started(Instant::now()));
+        if attrs.metadata().name() == REQUEST_SPAN {
+            extensions.insert(PhaseTimes::default());
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(elapsed) = span.extensions().get::<Started>().map(|s| s.0.elapsed()) else {
+            return;
+        };
+
+        if let Some(phase) = Phase::from_span(span.name()) {
+            PHASES[phase as usize].observe(elapsed);
+            if let Some(request) = span.scope().skip(1).find(|s| s.name() == REQUEST_SPAN) {
+                if let Some(times) = request.extensions_mut().get_mut::<PhaseTimes>() {
+                    times.0[phase as usize] += elapsed;
+                }
+            }
+            return;
+        }
+
+        REQUESTS.observe(elapsed);
+        if self
+            .slow_request
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            SLOW_REQUESTS.fetch_add(1, Ordering::Relaxed);
+            let times = span
+                .extensions()
+                .get::<PhaseTimes>()
+                .copied()
+                .unwrap_or_default();
+            let ms = |phase: Phase| times.0[phase as usize].as_millis() as u64;
+            tracing::warn!(
+                total_ms = elapsed.as_millis() as u64,
+                fetch_collateral_ms = ms(Phase::FetchCollateral),
+                verify_ms = ms(Phase::Verify),
+                derive_ms = ms(Phase::Derive),
+                encrypt_ms = ms(Phase::Encrypt),
+                "Slow key request"
+            );
+        }
+    }
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    out.push_str(
+        "# HELP sealing_provider_phase_seconds Time spent in each phase of key requests\n",
+    );
+    out.push_str("# TYPE sealing_provider_phase_seconds histogram\n");
+    for phase in Phase::ALL {
+        let labels = format!("phase=\"{}\"", phase.label());
+        PHASES[phase as usize].render(&mut out, "sealing_provider_phase_seconds", &labels);
+    }
+    out.push_str("# HELP sealing_provider_request_seconds Time to answer key requests\n");
+    out.push_str("# TYPE sealing_provider_request_seconds histogram\n");
+    REQUESTS.render(&mut out, "sealing_provider_request_seconds", "");
+    out.push_str(
+        "# HELP sealing_provider_slow_requests_total Key requests over the slow threshold\n",
+    );
+    out.push_str("# TYPE sealing_provider_slow_requests_total counter\n");
+    let _ = writeln!(
+        out,
+        "sealing_provider_slow_requests_total {}",
+        SLOW_REQUESTS.load(Ordering::Relaxed)
+    );
+    out.push_str(
+        "# HELP sealing_provider_log_lines_dropped_total Log lines dropped by the rate limit\n",
+    );
+    out.push_str("# TYPE sealing_provider_log_lines_dropped_total counter\n");
+    let _ = writeln!(
+        out,
+        "sealing_provider_log_lines_dropped_total {}",
+        logging::dropped_lines()
+    );
+    rejections::render(&mut out);
+    resources::render(&mut out);
+    out
+}
+
+/// Answers every HTTP request on `addr` with the metrics. They hold timings
+/// and counts only, never request contents.
+pub async fn serve(addr: &str) -> Result<(), ProviderError> {
+    let listener = bind(addr).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(respond(socket));
+                }
+                Err(e) => warn!("Failed to accept metrics connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut socket: TcpStream) {
+    // Every path gets the metrics, so the request is only drained
+    let mut request = [0u8; 1024];
+    let _ = timeout(Duration::from_secs(5), socket.read(&mut request)).await;
+
+    let body = render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    if let Err(e) = socket.write_all(response.as_bytes()).await {
+        debug!("Failed to send metrics: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(7));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "t", "phase=\"verify\"");
+        assert!(out.contains("t_bucket{phase=\"verify\",le=\"0.001\"} 0\n"));
+        assert!(out.contains("t_bucket{phase=\"verify\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("t_bucket{phase=\"verify\",le=\"5\"} 1\n"));
+        assert!(out.contains("t_bucket{phase=\"verify\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("t_sum{phase=\"verify\"} 7.003\n"));
+        assert!(out.contains("t_count{phase=\"verify\"} 2\n"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::config::ServerConfig;
+use crate::error::ProviderError;
+use crate::server::write_frame;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWrite;
+use tokio::time::sleep_until;
+
+/// Sizes responses are padded up to; larger ones are padded to a multiple of
+/// the last.
+const SIZE_BUCKETS: [usize; 4] = [1024, 4 * 1024, 16 * 1024, 64 * 1024];
+
+/// Hides what the host could otherwise read off the timing and length of
+/// responses, such as whether a request was denied early by policy or which
+/// suite and recipient kind it used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponsePadding {
+    /// No response, and no connection closed on an error, before this much
+    /// time has passed since the request arrived.
+    pub min_duration: Option<Duration>,
+    /// Pad response bodies with trailing JSON whitespace to the next size
+    /// bucket.
+    pub size_buckets: bool,
+}
+
+impl ResponsePadding {
+    pub fn from_config(server: &ServerConfig) -> Self {
+        Self {
+            min_duration: match server.min_response_ms {
+                0 => None,
+                ms => Some(Duration::from_millis(ms)),
+            },
+            size_buckets: server.pad_response_sizes,
+        }
+    }
+
+    fn pad(&self, body: &mut Vec<u8>) {
+        if !self.size_buckets {
+            return;
+        }
+        let largest = SIZE_BUCKETS[SIZE_BUCKETS.len() - 1];
+        let target = SIZE_BUCKETS
+            .into_iter()
+            .find(|bucket| *bucket >= body.len())
+            .unwrap_or_else(|| body.len().div_ceil(largest) * largest);
+        body.resize(target, b' ');
+    }
+}
+
+/// Answers one request under `padding`, counting from when it arrived.
+pub struct Responder {
+    padding: ResponsePadding,
+    started: Instant,
+}
+
+impl Responder {
+    pub fn new(padding: ResponsePadding) -> Self {
+        Self {
+            padding,
+            started: Instant::now(),
+        }
+    }
+
+    /// Writes `response` as a padded JSON frame once the floor is reached.
+    pub async fn send<S, T>(&self, socket: &mut S, response: &T) -> Result<(), ProviderError>
+    where
+        S: AsyncWrite + Unpin,
+        T: Serialize,
+    {
+        let mut body = serde_json::to_vec(response)?;
+        self.padding.pad(&mut body);
+        self.wait().await;
+        write_frame(socket, &body).await
+    }
+
+    /// Sleeps until the floor is reached, if there is one.
+    pub async fn wait(&self) {
+        if let Some(floor) = self.padding.min_duration {
+            sleep_until((self.started + floor).into()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_grow_to_the_next_bucket_and_stay_json() {
+        let padding = ResponsePadding {
+            min_duration: None,
+            size_buckets: true,
+        };
+        let mut small = br#"{"verdict":"denied"}"#.to_vec();
+        let mut large = vec![b'1'; 70 * 1024];
+
+        padding.pad(&mut small);
+        padding.pad(&mut large);
+
+        assert_eq!(small.len(), 1024);
+        assert_eq!(large.len(), 128 * 1024);
+        let parsed: serde_json::Value = serde_json::from_slice(&small).unwrap();
+        assert_eq!(parsed["verdict"], "denied");
+    }
+}
@@ -0,0 +1,469 @@
+use crate::allowlist::Allowlist;
+use crate::approvals::ApprovalPolicy;
+use crate::corim::ReferenceValues;
+use crate::crypto::KdfVersion;
+use crate::error::ProviderError;
+#[cfg(feature = "cca")]
+use crate::evidence::CcaToken;
+use crate::evidence::{GcpToken, ItaToken, MaaToken, SnpReport, SnpTcb, TpmEvidence};
+use crate::ratelimit::RateLimitPolicy;
+use crate::reissue::ReissuePolicy;
+use crate::releases::ReleaseLimitPolicy;
+use crate::tenants::{self, Tenant};
+use crate::windows::ReleaseWindows;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Operator policy, loaded from a JSON document at startup.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Hex-encoded X25519 public keys that may receive a copy of a derived key
+    /// in addition to the requesting TD (e.g. an escrow service).
+    #[serde(default)]
+    pub allowed_extra_recipients: Vec<String>,
+    /// What to do when the SGX and TDX quotes carry different PPIDs because
+    /// they come from different packages of a multi-package platform.
+    #[serde(default)]
+    pub multi_package: MultiPackagePolicy,
+    /// How workload keys are derived: 1 (the default) as keys have always
+    /// been, or 2 with HKDF. Changing it changes every key it covers.
+    #[serde(default)]
+    pub kdf_version: KdfVersion,
+    /// Which SEV-SNP guests may get keys. They run on other hosts than the
+    /// provider, so there is no PPID to compare; this names the hosts instead.
+    #[serde(default)]
+    pub sev_snp: SnpPolicy,
+    /// Which GCP Confidential Space workloads may get keys.
+    #[serde(default)]
+    pub gcp: GcpPolicy,
+    /// Which TDs may get keys on the strength of an Intel Trust Authority
+    /// token.
+    #[serde(default)]
+    pub ita: ItaPolicy,
+    /// Which Azure TDX confidential VMs may get keys on the strength of an
+    /// MAA token.
+    #[serde(default)]
+    pub maa: MaaPolicy,
+    /// Which VMs without a TEE may get keys on the strength of a TPM quote.
+    /// Their host can read their memory, keys included, so they are kept
+    /// apart from TEE guests: nothing here admits them unless `allow` is set.
+    #[serde(default)]
+    pub tpm: TpmPolicy,
+    /// Which ARM CCA realms may get keys. Only read by builds with the `cca`
+    /// feature.
+    #[serde(default)]
+    pub cca: CcaPolicy,
+    /// CoRIM files whose reference values the measurements of every kind of
+    /// evidence must match, relative to the policy file. Empty skips the
+    /// check.
+    #[serde(default)]
+    pub reference_values: Vec<PathBuf>,
+    /// What `reference_values` hold, read with the policy.
+    #[serde(skip)]
+    pub references: ReferenceValues,
+    /// File of approved MRTD and RTMR sets, relative to the policy file,
+    /// that TD evidence must match. Read again whenever it changes.
+    #[serde(default)]
+    pub measurement_allowlist: Option<PathBuf>,
+    /// What `measurement_allowlist` holds.
+    #[serde(skip)]
+    pub allowlist: Option<Allowlist>,
+    /// When keys may be released, in UTC.
+    #[serde(default)]
+    pub release_windows: ReleaseWindows,
+    /// How often each workload identity may get its key.
+    #[serde(default)]
+    pub rate_limit: RateLimitPolicy,
+    /// How many times in all each workload identity may get its key.
+    #[serde(default)]
+    pub release_limits: ReleaseLimitPolicy,
+    /// Tokens that let a restarting TD get its key again for a while
+    /// without full verification.
+    #[serde(default)]
+    pub reissue: ReissuePolicy,
+    /// Releases that wait for an approver or an operator first.
+    #[serde(default)]
+    pub approval: ApprovalPolicy,
+    /// Tenants sharing this provider, each judged by its own policy and
+    /// given keys from its own namespace. Requests of no tenant are judged
+    /// by this document.
+    #[serde(default)]
+    pub tenants: Vec<Tenant>,
+    /// Refuse requests that belong to no tenant.
+    #[serde(default)]
+    pub require_tenant: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnpPolicy {
+    /// Hex chip IDs of the hosts SNP guests may run on. Empty refuses every
+    /// SNP guest.
+    pub allowed_chip_ids: Vec<String>,
+    /// Lowest reported TCB accepted.
+    pub min_tcb: Option<SnpTcb>,
+    /// Accept guests whose policy lets the host debug them, and so read
+    /// their memory.
+    pub allow_debug: bool,
+}
+
+impl SnpPolicy {
+    pub fn check(&self, report: &SnpReport) -> Result<(), ProviderError> {
+        let chip_id = hex::encode(report.chip_id());
+        if !self
+            .allowed_chip_ids
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&chip_id))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "SEV-SNP chip {} is not permitted",
+                chip_id
+            )));
+        }
+        if let Some(min_tcb) = &self.min_tcb {
+            if !report.reported_tcb().meets(min_tcb) {
+                return Err(ProviderError::PolicyViolation(format!(
+                    "SEV-SNP TCB ({}) is below the minimum ({})",
+                    report.reported_tcb(),
+                    min_tcb
+                )));
+            }
+        }
+        if report.is_debuggable() && !self.allow_debug {
+            return Err(ProviderError::PolicyViolation(
+                "SEV-SNP guest policy allows debugging".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GcpPolicy {
+    /// Projects the workload's VM may belong to. Empty refuses every token.
+    pub allowed_projects: Vec<String>,
+    /// Container image digests (`sha256:...`) accepted. Empty accepts any
+    /// image, which still gets a key of its own.
+    pub allowed_image_digests: Vec<String>,
+    /// Accept VMs that could be debugged since boot.
+    pub allow_debug: bool,
+}
+
+impl GcpPolicy {
+    pub fn check(&self, token: &GcpToken) -> Result<(), ProviderError> {
+        let project = token.project_id().unwrap_or_default();
+        if !self
+            .allowed_projects
+            .iter()
+            .any(|allowed| allowed == project)
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "GCP project {:?} is not permitted",
+                project
+            )));
+        }
+        let image = token.image_digest().unwrap_or_default();
+        if !self.allowed_image_digests.is_empty()
+            && !self
+                .allowed_image_digests
+                .iter()
+                .any(|allowed| allowed == image)
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "Container image {:?} is not permitted",
+                image
+            )));
+        }
+        if token.is_debuggable() && !self.allow_debug {
+            return Err(ProviderError::PolicyViolation(
+                "GCP VM was debuggable since boot".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ItaPolicy {
+    /// Hex MRTDs of the TDs that may get keys. Empty refuses every token.
+    pub allowed_mrtds: Vec<String>,
+    /// TCB statuses Trust Authority may report. Empty accepts `UpToDate`
+    /// only.
+    pub allowed_tcb_statuses: Vec<String>,
+    /// Accept TDs that can be debugged.
+    pub allow_debug: bool,
+}
+
+impl ItaPolicy {
+    pub fn check(&self, token: &ItaToken) -> Result<(), ProviderError> {
+        let mrtd = hex::encode(token.mrtd());
+        if !self
+            .allowed_mrtds
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&mrtd))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "MRTD {} is not permitted",
+                mrtd
+            )));
+        }
+        let status = token.tcb_status();
+        let accepted = if self.allowed_tcb_statuses.is_empty() {
+            status == "UpToDate"
+        } else {
+            self.allowed_tcb_statuses
+                .iter()
+                .any(|allowed| allowed == status)
+        };
+        if !accepted {
+            return Err(ProviderError::PolicyViolation(format!(
+                "Trust Authority TCB status {} is not permitted",
+                status
+            )));
+        }
+        if token.is_debuggable() && !self.allow_debug {
+            return Err(ProviderError::PolicyViolation("TD is debuggable".into()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaaPolicy {
+    /// Hex MRTDs of the paravisor builds TDs may run on. Empty refuses every
+    /// token.
+    pub allowed_mrtds: Vec<String>,
+    /// Accept TDs that can be debugged.
+    pub allow_debug: bool,
+}
+
+impl MaaPolicy {
+    pub fn check(&self, token: &MaaToken) -> Result<(), ProviderError> {
+        let mrtd = hex::encode(token.mrtd());
+        if !self
+            .allowed_mrtds
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&mrtd))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "MRTD {} is not permitted",
+                mrtd
+            )));
+        }
+        if token.is_debuggable() && !self.allow_debug {
+            return Err(ProviderError::PolicyViolation("TD is debuggable".into()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TpmPolicy {
+    /// Serve clients whose only evidence is a TPM quote.
+    pub allow: bool,
+    /// SHA-256 PCRs the quote must cover, e.g. the firmware and boot loader
+    /// PCRs 0-7.
+    pub required_pcrs: Vec<u8>,
+    /// Hex digests of the selected PCRs accepted. Empty accepts any, which
+    /// still gets a key of its own.
+    pub allowed_pcr_digests: Vec<String>,
+}
+
+impl TpmPolicy {
+    pub fn check(&self, evidence: &TpmEvidence) -> Result<(), ProviderError> {
+        if !self.allow {
+            return Err(ProviderError::PolicyViolation(
+                "TPM clients are not permitted".into(),
+            ));
+        }
+        let quote = evidence.quote();
+        if let Some(pcr) = self
+            .required_pcrs
+            .iter()
+            .find(|&&pcr| !quote.selects_sha256(pcr))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "TPM quote does not cover PCR {}",
+                pcr
+            )));
+        }
+        let digest = hex::encode(&quote.pcr_digest);
+        if !self.allowed_pcr_digests.is_empty()
+            && !self
+                .allowed_pcr_digests
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&digest))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "TPM PCR digest {} is not permitted",
+                digest
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CcaPolicy {
+    /// Hex implementation IDs of the platform firmware realms may run on.
+    /// Empty refuses every realm.
+    pub allowed_implementation_ids: Vec<String>,
+    /// Accept platforms whose security lifecycle is not "secured", such as
+    /// ones open to debugging.
+    pub allow_unsecured: bool,
+}
+
+#[cfg(feature = "cca")]
+impl CcaPolicy {
+    pub fn check(&self, token: &CcaToken) -> Result<(), ProviderError> {
+        let implementation_id = hex::encode(token.implementation_id());
+        if !self
+            .allowed_implementation_ids
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&implementation_id))
+        {
+            return Err(ProviderError::PolicyViolation(format!(
+                "CCA platform implementation {} is not permitted",
+                implementation_id
+            )));
+        }
+        if !token.is_secured() && !self.allow_unsecured {
+            return Err(ProviderError::PolicyViolation(
+                "CCA platform is not in the secured lifecycle state".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiPackagePolicy {
+    /// Treat it like any other PPID mismatch.
+    #[default]
+    Reject,
+    /// Accept if both PCK certificates name the same platform instance and
+    /// FMSPC.
+    PlatformInstance,
+}
+
+impl MultiPackagePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            MultiPackagePolicy::Reject => "reject",
+            MultiPackagePolicy::PlatformInstance => "platform_instance",
+        }
+    }
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> Result<Self, ProviderError> {
+        info!("Loading policy from {}", path.display());
+        let data = fs::read(path)?;
+        Self::parse(&data, path.parent().unwrap_or(Path::new(".")))
+    }
+
+    /// Reads a policy document whose CoRIMs, allowlist and tenant policies
+    /// are named relative to `dir`.
+    pub fn parse(data: &[u8], dir: &Path) -> Result<Self, ProviderError> {
+        let mut policy: Policy = serde_json::from_slice(data)?;
+
+        for key in &policy.allowed_extra_recipients {
+            let bytes = hex::decode(key).map_err(|e| {
+                ProviderError::PolicyViolation(format!("Invalid recipient key {}: {}", key, e))
+            })?;
+            if bytes.len() != 32 {
+                return Err(ProviderError::PolicyViolation(format!(
+                    "Recipient key {} must be 32 bytes",
+                    key
+                )));
+            }
+        }
+
+        for chip_id in &policy.sev_snp.allowed_chip_ids {
+            if hex::decode(chip_id).map(|bytes| bytes.len()) != Ok(64) {
+                return Err(ProviderError::PolicyViolation(format!(
+                    "SEV-SNP chip ID {} must be 64 bytes of hex",
+                    chip_id
+                )));
+            }
+        }
+
+        policy.release_windows.validate()?;
+        policy.reissue.validate()?;
+
+        for corim in &policy.reference_values {
+            let references = ReferenceValues::load(&dir.join(corim))?;
+            policy.references.extend(references);
+        }
+        if !policy.reference_values.is_empty() {
+            info!(
+                "Loaded {} reference values from {} CoRIMs",
+                policy.references.len(),
+                policy.reference_values.len()
+            );
+        }
+
+        if let Some(allowlist) = &policy.measurement_allowlist {
+            policy.allowlist = Some(Allowlist::load(&dir.join(allowlist))?);
+        }
+
+        tenants::load(&mut policy.tenants, dir)?;
+        if !policy.tenants.is_empty() {
+            info!("Loaded the policies of {} tenants", policy.tenants.len());
+        }
+
+        debug!(
+            "Policy allows {} extra recipients",
+            policy.allowed_extra_recipients.len()
+        );
+        Ok(policy)
+    }
+
+    pub fn check_extra_recipient(&self, public_key: &[u8]) -> Result<(), ProviderError> {
+        let encoded = hex::encode(public_key);
+        if self
+            .allowed_extra_recipients
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&encoded))
+        {
+            Ok(())
+        } else {
+            Err(ProviderError::PolicyViolation(format!(
+                "Extra recipient {} is not permitted",
+                encoded
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_recipient_must_be_listed() {
+        let policy = Policy {
+            allowed_extra_recipients: vec![hex::encode([0xabu8; 32]).to_uppercase()],
+            ..Policy::default()
+        };
+        assert!(policy.check_extra_recipient(&[0xab; 32]).is_ok());
+        assert!(matches!(
+            policy.check_extra_recipient(&[0xcd; 32]),
+            Err(ProviderError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn default_policy_allows_no_extra_recipients() {
+        assert!(Policy::default().check_extra_recipient(&[0u8; 32]).is_err());
+    }
+}
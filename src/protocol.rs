@@ -0,0 +1,439 @@
+use serde::{Deserialize, Serialize};
+
+/// Version of the request/response protocol spoken by this provider.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Selects the request type from the `op` field of the request body. Requests
+/// without one are quote requests, so existing clients keep working.
+#[derive(Deserialize)]
+pub struct RequestKind {
+    #[serde(default)]
+    pub op: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuoteRequest {
+    pub quote: Vec<u8>,
+    /// DER-encoded RSA-3072 SubjectPublicKeyInfo. When present, the derived key
+    /// is wrapped with RSA-OAEP instead of a sealed box, and the SHA-256 of this
+    /// DER must occupy the first 32 bytes of the quote's report data.
+    #[serde(default)]
+    pub recipient_key: Option<Vec<u8>>,
+    /// `TPM2B_PUBLIC` of an RSA storage key in the guest's vTPM (e.g. the SRK).
+    /// When present, the derived key is returned as a TPM2_Import blob for that
+    /// parent, and the SHA-256 of these bytes must occupy the first 32 bytes of
+    /// the quote's report data.
+    #[serde(default)]
+    pub tpm_parent: Option<Vec<u8>>,
+    /// Return `encrypted_key` as a versioned envelope (format version, suite,
+    /// key id, ciphertext) rather than the bare ciphertext.
+    #[serde(default)]
+    pub envelope: bool,
+    /// Encrypt the derived key as a kernel keyring payload instead of raw
+    /// bytes: `"user"` (for `user`/`logon` keys) or `"fscrypt-provisioning"`.
+    #[serde(default)]
+    pub kernel_key: Option<String>,
+    /// X25519 public keys that should receive the same derived key in addition
+    /// to the TD (e.g. an escrow service). The TD commits to this list by
+    /// placing its hash in bytes 32..64 of the report data, and each key must
+    /// be allowed by the provider policy.
+    #[serde(default)]
+    pub extra_recipients: Vec<Vec<u8>>,
+    /// Fresh client nonce (16..=64 bytes). It is bound into the provider quote
+    /// so responses cannot be replayed to a TD, and reused nonces are rejected.
+    #[serde(default)]
+    pub nonce: Option<Vec<u8>>,
+    /// Response encryption suites the client supports, by name. The provider
+    /// picks the strongest one it also supports; when empty, X25519 recipients
+    /// get a sealed box and RSA recipients RSA-OAEP.
+    #[serde(default)]
+    pub suites: Vec<String>,
+    /// Ask for an Ed25519 signature over the whole exchange, made with the
+    /// provider identity key.
+    #[serde(default)]
+    pub transcript: bool,
+    /// Ephemeral X25519 public key to open an encrypted session with. The TD
+    /// commits to it by placing
+    /// `SHA-256("gramine-sealing-key-provider/session-key/v1" || key)` in bytes
+    /// 32..64 of the report data, so it cannot be combined with
+    /// `extra_recipients`.
+    #[serde(default)]
+    pub session_key: Option<Vec<u8>>,
+    /// Return the policy evaluation trace: in a check response, and in a
+    /// [`RejectionResponse`] when a key request is denied rather than just
+    /// closing the connection.
+    #[serde(default)]
+    pub explain: bool,
+    /// Tenant the request is for. It must list the quote's launch
+    /// measurement; without it, the first tenant that does is used.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// What the key is for, such as `disk`. Releases are counted per
+    /// context for `release_limits`; the key does not depend on it.
+    #[serde(default)]
+    pub context: Option<String>,
+    /// Ask for a re-issuance token with the key, if the policy's `reissue`
+    /// section issues them. Only X25519 recipients get one.
+    #[serde(default)]
+    pub reissue_token: bool,
+    /// A re-issuance token to get the key with instead of verifying the
+    /// quote against collateral.
+    #[serde(default)]
+    pub reissue: Option<ReissueProof>,
+}
+
+/// Proof of holding a re-issuance token, for a quote of a TD with the same
+/// measurements as the one it was issued to.
+#[derive(Serialize, Deserialize)]
+pub struct ReissueProof {
+    /// `SHA-256("gramine-sealing-key-provider/reissue-id/v1" || token)`.
+    pub id: Vec<u8>,
+    /// `HMAC-SHA256(token, "gramine-sealing-key-provider/reissue-proof/v1"
+    /// || quote)`.
+    pub proof: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuoteResponse {
+    /// Name of the suite used for `encrypted_key` and `recipient_keys`.
+    pub suite: String,
+    pub encrypted_key: Vec<u8>,
+    pub provider_quote: Vec<u8>,
+    /// Ciphertexts for `extra_recipients`, in request order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipient_keys: Vec<Vec<u8>>,
+    /// HMAC-SHA256 of "gramine-sealing-key-provider/key-confirmation/v1" under
+    /// the derived key.
+    pub key_confirmation: Vec<u8>,
+    /// Kernel key format of the decrypted payload, echoed from the request and
+    /// bound into the provider quote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel_key: Option<String>,
+    /// Provider identity public key (Ed25519), present when a transcript
+    /// signature was requested. Bound into the provider quote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_key: Option<Vec<u8>>,
+    /// Signature over `"gramine-sealing-key-provider/transcript/v1" ||
+    /// SHA-256(request) || SHA-256(quote) || SHA-256(provider_quote)`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript_signature: Option<Vec<u8>>,
+    /// Provider ephemeral X25519 key for the requested session. Bound into the
+    /// provider quote; session frames follow this response on the connection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_key: Option<Vec<u8>>,
+    /// TCB level of the provider's platform, bound into the provider quote.
+    /// Absent without SGX attestation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_tcb: Option<PlatformTcb>,
+    /// Tenant whose namespace the key comes from, bound into the provider
+    /// quote. Absent for the provider's own namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Single-use re-issuance token, encrypted like `encrypted_key`. Bound
+    /// into the provider quote with its expiry.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reissue_token: Option<Vec<u8>>,
+    /// Unix time after which `reissue_token` is refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reissue_expires_at: Option<u64>,
+}
+
+/// Answer to `{"op": "check", ...}`, a quote request that is judged but never
+/// answered with a key.
+#[derive(Serialize, Deserialize)]
+pub struct CheckResponse {
+    /// `eligible` or `denied`.
+    pub verdict: String,
+    /// Why the request would be denied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// TCB level of the request's quote, once it verified. Absent in dev mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_tcb: Option<PlatformTcb>,
+    /// Suite a key response would use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suite: Option<String>,
+    /// TCB level of the provider's platform. Absent without SGX attestation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_tcb: Option<PlatformTcb>,
+    /// The rules evaluated, in order, when `explain` was set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<PolicyStep>,
+    /// Fresh provider quote over the nonce and every field above.
+    pub provider_quote: Vec<u8>,
+}
+
+/// Sent in place of a key response when a request with `explain` is denied.
+/// It carries no provider quote, so it is only a diagnostic: the host could
+/// forge it.
+#[derive(Serialize, Deserialize)]
+pub struct RejectionResponse {
+    pub error: String,
+    pub trace: Vec<PolicyStep>,
+}
+
+/// One policy rule as evaluated for a request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyStep {
+    /// `nonce`, `tenant`, `quote_verification`, `same_platform`,
+    /// `extra_recipients`, `session_key`, `suite` or `recipient_binding`.
+    pub rule: String,
+    /// What the rule was evaluated on, such as the multi-package policy.
+    pub input: String,
+    /// `pass`, `fail` or `skipped`.
+    pub outcome: String,
+    /// The error on failure, otherwise what the rule found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// `{"op": "info", ...}`: asks the provider to describe itself before any
+/// quote is sent.
+#[derive(Serialize, Deserialize)]
+pub struct InfoRequest {
+    /// Fresh client nonce (16..=64 bytes), bound into the provider quote.
+    pub nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InfoResponse {
+    pub mr_enclave: Vec<u8>,
+    pub mr_signer: Vec<u8>,
+    pub isv_prod_id: u16,
+    pub isv_svn: u16,
+    pub protocol_versions: Vec<u32>,
+    /// Suites this provider can negotiate, by name, in preference order.
+    pub suites: Vec<String>,
+    pub crypto_backend: String,
+    /// Provider identity public key used for transcript signatures.
+    pub identity_key: Vec<u8>,
+    /// Key Separation and Sharing fields, when the provider is signed with
+    /// KSS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kss: Option<KssInfo>,
+    /// Measurements of the trust domain when the provider runs in a TD; the
+    /// SGX measurements above are then zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub td: Option<TdInfo>,
+    /// Whether the KSS fields partition the derivation root, i.e. whether
+    /// providers with different KSS fields derive different keys.
+    #[serde(default)]
+    pub kss_derivation: bool,
+    /// Number of provider starts recorded in the sealed counter store; it
+    /// changes when the provider restarts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_epoch: Option<u64>,
+    /// TCB level of the provider's platform. Absent without SGX attestation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_tcb: Option<PlatformTcb>,
+    /// What the provider binary was built from.
+    #[serde(default)]
+    pub build: BuildInfo,
+    /// Fresh SGX quote over the nonce and every field above except the
+    /// measurements, which it carries itself.
+    pub provider_quote: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Crate version.
+    pub version: String,
+    /// Commit the binary was built from, when the build knew it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+    /// Cargo features compiled in, such as `fips` or `ratls`.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// Outcome of verifying the provider's own quote against Intel's TCB info.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlatformTcb {
+    /// `UpToDate`, `SWHardeningNeeded`, `ConfigurationNeeded`,
+    /// `ConfigurationAndSWHardeningNeeded`, `OutOfDate` or
+    /// `OutOfDateConfigurationNeeded`.
+    pub status: String,
+    /// Intel security advisories (`INTEL-SA-xxxxx`) that apply to the
+    /// platform's TCB level.
+    #[serde(default)]
+    pub advisory_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TdInfo {
+    pub mr_td: Vec<u8>,
+    /// RTMR0-3.
+    pub rtmrs: Vec<Vec<u8>>,
+    pub td_attributes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KssInfo {
+    pub config_id: Vec<u8>,
+    pub config_svn: u16,
+    pub isv_family_id: Vec<u8>,
+    pub isv_ext_prod_id: Vec<u8>,
+}
+
+/// `{"op": "session_status", ...}`: asks what became of a session, for
+/// example after the connection carrying it dropped.
+#[derive(Serialize, Deserialize)]
+pub struct SessionStatusRequest {
+    /// The provider's session public key, from the key response.
+    pub session_key: Vec<u8>,
+    /// Fresh client nonce (16..=64 bytes), bound into the provider quote.
+    pub nonce: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionStatusResponse {
+    /// `open`, `closed`, `invalidated` (a restart ended it) or `unknown`.
+    pub state: String,
+    /// The current boot epoch, when a counter store is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_epoch: Option<u64>,
+    /// Fresh provider quote over the nonce, the session key and the fields
+    /// above.
+    pub provider_quote: Vec<u8>,
+}
+
+/// `{"op": "spiffe", ...}`: attests the TD of a SPIRE agent for a node
+/// attestor plugin, which forwards the agent's quote along with the challenge
+/// it sent the agent.
+#[derive(Serialize, Deserialize)]
+pub struct SpiffeRequest {
+    pub quote: Vec<u8>,
+    /// The plugin's fresh challenge (16..=64 bytes).
+    pub challenge: Vec<u8>,
+    /// DER SubjectPublicKeyInfo of the agent's SVID key.
+    pub svid_public_key: Vec<u8>,
+    /// Trust domain of the SPIRE server, e.g. `example.org`.
+    pub trust_domain: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SpiffeResponse {
+    /// `spiffe://<trust domain>/spire/agent/gramine_tdx/<key id>`.
+    pub spiffe_id: String,
+    /// `mrtd:<hex>`, `rtmr0:<hex>` .. `rtmr3:<hex>`, `key_id:<hex>`, and
+    /// `tcb_status:<status>` when the quote was verified.
+    pub selectors: Vec<String>,
+    /// Fresh provider quote over the request and the fields above.
+    pub provider_quote: Vec<u8>,
+}
+
+/// Operations inside an established session. Each one travels as the
+/// session-encrypted JSON body of a length-prefixed frame.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum SessionRequest {
+    /// Encrypt `plaintext` under a key derived from the workload key.
+    Wrap {
+        plaintext: Vec<u8>,
+        #[serde(default)]
+        aad: Vec<u8>,
+    },
+    /// Decrypt a blob produced by `wrap` for the same workload.
+    Unwrap {
+        wrapped: Vec<u8>,
+        #[serde(default)]
+        aad: Vec<u8>,
+    },
+    Close,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A request on the admin listener, one per connection. `token` must equal
+/// the configured admin token.
+#[derive(Serialize, Deserialize)]
+pub struct AdminRequest {
+    pub token: String,
+    #[serde(flatten)]
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AdminCommand {
+    /// The active policy and session idle timeout.
+    Policy,
+    /// What the collateral, provider quote and nonce caches hold, without
+    /// their contents.
+    Caches,
+    /// The sealed counters and the store's generation.
+    Counters,
+    /// Client connections being served, with the phase each is in and for
+    /// how long.
+    Connections,
+    /// The current boot epoch.
+    Epoch,
+    /// Drop the cached collateral and provider quote.
+    FlushCaches,
+    /// Advance the boot epoch, as a restart would.
+    RotateEpoch,
+    /// Run a synthetic quote through the key release pipeline.
+    SelfTest,
+    /// Failed key requests since startup, by reason and by MRTD.
+    Rejections,
+    /// Memory, file descriptors and tasks in use inside the enclave.
+    Resources,
+    /// Releases waiting for an operator's approval.
+    Approvals,
+    /// Let the waiting release `id` go ahead.
+    Approve { id: String },
+    /// Refuse the waiting release `id`.
+    Deny {
+        id: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Entries of the audit log from `from` on, with their checkpoints and
+    /// anchors, for a third party to verify.
+    AuditLog {
+        #[serde(default)]
+        from: u64,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Audit log entries matching every filter given, newest first.
+    AuditQuery {
+        /// Hex launch measurement (MRTD and so on) the measurements start
+        /// with.
+        #[serde(default)]
+        launch_measurement: Option<String>,
+        #[serde(default)]
+        ppid: Option<String>,
+        #[serde(default)]
+        key_id: Option<String>,
+        #[serde(default)]
+        tenant: Option<String>,
+        /// `released` or `denied`.
+        #[serde(default)]
+        verdict: Option<String>,
+        /// Unix seconds, both inclusive.
+        #[serde(default)]
+        since: Option<u64>,
+        #[serde(default)]
+        until: Option<u64>,
+        /// Only entries before this one, to page back through the log.
+        #[serde(default)]
+        before: Option<u64>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
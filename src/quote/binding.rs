@@ -0,0 +1,66 @@
+use crate::crypto::backend;
+use log::debug;
+
+const METADATA_LABEL: &[u8] = b"gramine-sealing-key-provider/response-metadata/v1";
+
+/// Accumulates the response fields besides `encrypted_key` that the provider
+/// quote vouches for. Each field is hashed as
+/// `name_len: u8 | name | value_len: u32 (big-endian) | value`, in the order
+/// they are added, after a fixed label.
+pub struct ResponseBinding {
+    encoded: Vec<u8>,
+}
+
+impl ResponseBinding {
+    pub fn new() -> Self {
+        Self {
+            encoded: METADATA_LABEL.to_vec(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, value: &[u8]) {
+        self.encoded.push(name.len() as u8);
+        self.encoded.extend_from_slice(name.as_bytes());
+        self.encoded
+            .extend_from_slice(&(value.len() as u32).to_be_bytes());
+        self.encoded.extend_from_slice(value);
+    }
+
+    /// Builds the 64-byte user report data for the final provider quote:
+    /// `SHA-256(encrypted_key) | metadata hash`.
+    pub fn report_data(self, encrypted_key: &[u8]) -> Vec<u8> {
+        let key_hash = backend().sha256(&[encrypted_key]);
+        let metadata_hash = backend().sha256(&[&self.encoded]);
+
+        debug!("Hash of encrypted key: {}", hex::encode(key_hash));
+        debug!("Hash of response metadata: {}", hex::encode(metadata_hash));
+
+        let mut report_data = [0u8; 64];
+        report_data[..32].copy_from_slice(&key_hash);
+        report_data[32..].copy_from_slice(&metadata_hash);
+        report_data.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn first_half_is_hash_of_encrypted_key() {
+        let report_data = ResponseBinding::new().report_data(b"ciphertext");
+        assert_eq!(&report_data[..32], Sha256::digest(b"ciphertext").as_slice());
+    }
+
+    #[test]
+    fn field_boundaries_are_unambiguous() {
+        let mut a = ResponseBinding::new();
+        a.add("x", b"ab");
+        a.add("y", b"c");
+        let mut b = ResponseBinding::new();
+        b.add("x", b"a");
+        b.add("y", b"bc");
+        assert_ne!(a.report_data(b"k")[32..], b.report_data(b"k")[32..]);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::error::ProviderError;
+use crate::protocol::PolicyStep;
+
+/// The policy rules a request went through, in the order they ran. A denied
+/// request's trace ends at the rule that denied it.
+#[derive(Debug, Default)]
+pub struct Trace {
+    steps: Vec<PolicyStep>,
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `rule`, evaluated on `input`, as passed or failed by `result`,
+    /// and hands `result` back.
+    pub fn check<T>(
+        &mut self,
+        rule: &str,
+        input: impl Into<String>,
+        result: Result<T, ProviderError>,
+    ) -> Result<T, ProviderError> {
+        let (outcome, detail) = match &result {
+            Ok(_) => ("pass", None),
+            Err(e) => ("fail", Some(e.chain().to_string())),
+        };
+        self.push(rule, input.into(), outcome, detail);
+        result
+    }
+
+    /// Records `rule` as not applying to this request, because of `why`.
+    pub fn skip(&mut self, rule: &str, why: &str) {
+        self.push(rule, String::new(), "skipped", Some(why.to_string()));
+    }
+
+    /// Adds what the last rule found, for one that passed.
+    pub fn note(&mut self, detail: impl Into<String>) {
+        if let Some(step) = self.steps.last_mut() {
+            step.detail = Some(detail.into());
+        }
+    }
+
+    pub fn steps(&self) -> &[PolicyStep] {
+        &self.steps
+    }
+
+    pub fn into_steps(self) -> Vec<PolicyStep> {
+        self.steps
+    }
+
+    fn push(&mut self, rule: &str, input: String, outcome: &str, detail: Option<String>) {
+        self.steps.push(PolicyStep {
+            rule: rule.to_string(),
+            input,
+            outcome: outcome.to_string(),
+            detail,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_keep_their_order_and_outcome() {
+        let mut trace = Trace::new();
+        let _ = trace.check("nonce", "16 bytes", Ok(()));
+        trace.note("fresh");
+        trace.skip("extra_recipients", "none requested");
+        let denied = trace.check::<()>("same_platform", "reject", Err(ProviderError::PPIDMismatch));
+
+        let steps = trace.into_steps();
+        assert!(denied.is_err());
+        assert_eq!(
+            steps.iter().map(|s| s.outcome.as_str()).collect::<Vec<_>>(),
+            ["pass", "skipped", "fail"]
+        );
+        assert_eq!(steps[0].detail.as_deref(), Some("fresh"));
+        assert_eq!(steps[2].rule, "same_platform");
+        assert!(steps[2].detail.is_some());
+    }
+}
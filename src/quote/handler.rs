@@ -1,58 +1,502 @@
-use crate::crypto::{derive_key, encrypt_key, extract_public_key};
+use super::binding::ResponseBinding;
+use super::explain::Trace;
+use super::pck::PckInfo;
+use super::tcb::{bind_platform_tcb, platform_tcb};
+use crate::audit::{self, Release};
+use crate::connections::enter_phase;
+use crate::crypto::{
+    backend, compute_key_confirmation, compute_key_id, constant_time_eq, encrypt_key,
+    encrypt_key_cose, encrypt_key_jwe, encrypt_key_rsa, encrypt_key_tpm2, encrypt_stream,
+    extract_public_key, extract_rsa_public_key, extract_tpm2_parent, negotiate_suite,
+    KernelKeyFormat, KeyEnvelope, Redacted, SecretBytes, SessionChannel, Suite,
+};
 use crate::error::ProviderError;
-use crate::gramine::{get_quote_with_data, get_sealing_key};
-use dcap_qvl::{
-    collateral::get_collateral_from_pcs,
-    quote::{Quote, Report},
-    verify::verify,
+use crate::evidence::{
+    self, AzureTdxEvidence, Evidence, EvidenceKind, GcpToken, GcpVerifier, ItaToken, ItaVerifier,
+    MaaToken, MaaVerifier, SnpReport, SnpVerifier, TdxEvidence, TpmEvidence, TpmVerifier,
 };
+#[cfg(feature = "cca")]
+use crate::evidence::{CcaToken, CcaVerifier};
+use crate::gramine::{self, quote_with_data};
+use crate::policy::{MultiPackagePolicy, Policy};
+use crate::protocol::{CheckResponse, PlatformTcb, PolicyStep, QuoteRequest, ReissueProof};
+use crate::ratelimit::RateLimitBy;
+use crate::rejections;
+use crate::releases;
+use crate::session::Session;
+use crate::state::ProviderState;
+use crate::tenants::{self, Tenant};
+use crate::webhooks;
+use dcap_qvl::quote::Quote;
 use log::{debug, error, info, warn};
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::time::{SystemTime, UNIX_EPOCH};
+use sodiumoxide::crypto::box_::{self, PublicKey};
+use std::time::Instant;
+use tracing::{info_span, instrument};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub struct ProviderResponse {
+    pub suite: Suite,
     pub encrypted_key: Vec<u8>,
     pub provider_quote: Vec<u8>,
+    pub recipient_keys: Vec<Vec<u8>>,
+    pub key_confirmation: Vec<u8>,
+    pub kernel_key: Option<KernelKeyFormat>,
+    pub session: Option<Session>,
+    pub session_key: Option<Vec<u8>>,
+    pub platform_tcb: Option<PlatformTcb>,
+    pub key_id: Vec<u8>,
+    pub tenant: Option<String>,
+    pub approval: Option<String>,
+    pub reissue_token: Option<Vec<u8>>,
+    pub reissue_expires_at: Option<u64>,
+    pub reissued: bool,
+}
+
+const EXTRA_RECIPIENTS_LABEL: &[u8] = b"gramine-sealing-key-provider/extra-recipients/v1";
+const SESSION_KEY_LABEL: &[u8] = b"gramine-sealing-key-provider/session-key/v1";
+/// Stands in for the encrypted key in the report data of a check response,
+/// so it can never be mistaken for a key response.
+const CHECK_LABEL: &[u8] = b"gramine-sealing-key-provider/check/v1";
+
+#[instrument(skip_all, name = "process_quotes")]
+pub async fn process_quotes(
+    request: &QuoteRequest,
+    state: &ProviderState,
+    trace: &mut Trace,
+) -> Result<ProviderResponse, ProviderError> {
+    let result = release_key(request, state, trace).await;
+    if let Err(e) = &result {
+        rejections::record(e, &request.quote);
+        webhooks::rejected(e, &request.quote);
+    }
+    record_release(&request.quote, state, result).await
+}
+
+/// A quote that verified and comes from this platform, with the key derived
+/// for its measurements. For front ends that bind their own recipient in the
+/// report data, such as KBS, rather than the key request's.
+pub struct AttestedQuote {
+    pub kind: EvidenceKind,
+    pub measurements: Vec<u8>,
+    pub derived_key: SecretBytes,
+    pub key_id: Vec<u8>,
+    pub quote_tcb: Option<PlatformTcb>,
+    pub tenant: Option<String>,
+    pub approval: Option<String>,
+}
+
+/// What the audit log records of a released key.
+trait Released {
+    fn key_id(&self) -> &[u8];
+    fn tenant(&self) -> Option<&str>;
+    fn approval(&self) -> Option<&str>;
+    fn reissued(&self) -> bool;
+}
+
+impl Released for ProviderResponse {
+    fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+    fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+    fn approval(&self) -> Option<&str> {
+        self.approval.as_deref()
+    }
+    fn reissued(&self) -> bool {
+        self.reissued
+    }
+}
+
+impl Released for AttestedQuote {
+    fn key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+    fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+    fn approval(&self) -> Option<&str> {
+        self.approval.as_deref()
+    }
+    fn reissued(&self) -> bool {
+        false
+    }
+}
+
+/// Runs the verification and derivation steps of a key request on `quote`,
+/// and records the outcome like one. `bind` checks the report data once the
+/// quote is known to come from this platform; a quote it fails is denied.
+pub async fn attest_quote(
+    quote: &[u8],
+    state: &ProviderState,
+    bind: impl FnOnce(&[u8]) -> Result<(), ProviderError>,
+) -> Result<AttestedQuote, ProviderError> {
+    let result = verify_and_derive(quote, state, bind).await;
+    if let Err(e) = &result {
+        rejections::record(e, quote);
+        webhooks::rejected(e, quote);
+    }
+    record_release(quote, state, result).await
+}
+
+async fn verify_and_derive(
+    quote: &[u8],
+    state: &ProviderState,
+    bind: impl FnOnce(&[u8]) -> Result<(), ProviderError>,
+) -> Result<AttestedQuote, ProviderError> {
+    let settings = state.settings();
+    let mut trace = Trace::new();
+
+    let (tenant, policy) = trace_tenant(quote, None, &settings.policy, &mut trace)?;
+
+    enter_phase("verify_quote");
+    let mut quote_tcb = None;
+    let (evidence, _) = verify_evidence(quote, state, policy, &mut quote_tcb, &mut trace).await?;
+    bind(evidence.report_data())?;
+    let tenant = tenant.map(|tenant| tenant.id.clone());
+    trace_rate_limit(
+        state,
+        policy,
+        RateLimitBy::LaunchMeasurement,
+        evidence.launch_measurement(),
+        tenant.as_deref(),
+        &mut trace,
+    )?;
+    let approval = trace_approval(
+        state,
+        policy,
+        evidence.as_ref(),
+        tenant.as_deref(),
+        &mut trace,
+    )
+    .await?;
+
+    enter_phase("derive_key");
+    let measurements = evidence.measurements().to_vec();
+    let derived_key = info_span!("derive_key").in_scope(|| {
+        state
+            .master
+            .derive_for(policy.kdf_version, tenant.as_deref(), &measurements)
+    })?;
+    let key_id = compute_key_id(&derived_key);
+    check_key_id(state, &key_id, &mut trace)?;
+    trace_rate_limit(
+        state,
+        policy,
+        RateLimitBy::KeyId,
+        &key_id,
+        tenant.as_deref(),
+        &mut trace,
+    )?;
+    trace_release_count(
+        state,
+        policy,
+        evidence.launch_measurement(),
+        &key_id,
+        tenant.as_deref(),
+        None,
+        &mut trace,
+    )
+    .await?;
+    Ok(AttestedQuote {
+        kind: evidence.kind(),
+        measurements,
+        key_id: key_id.to_vec(),
+        derived_key,
+        quote_tcb,
+        tenant,
+        approval,
+    })
+}
+
+/// Records the outcome of a request whose quote parses, then exports it. A
+/// key is only returned once its release is on disk; a denial is still
+/// returned as denied if recording it fails. A denial the audit log only
+/// counts is not exported either.
+async fn record_release<T: Released>(
+    quote: &[u8],
+    state: &ProviderState,
+    result: Result<T, ProviderError>,
+) -> Result<T, ProviderError> {
+    if state.audit.is_none() && state.export.is_none() {
+        return result;
+    }
+    enter_phase("record_release");
+    let Ok(evidence) = evidence::parse(quote) else {
+        return result;
+    };
+
+    let release = Release {
+        timestamp: audit::unix_now(),
+        ppid: hex::encode(evidence.platform_id()),
+        measurements: hex::encode(evidence.measurements()),
+        report_data: hex::encode(evidence.report_data()),
+        verdict: match &result {
+            Ok(_) => "released".to_string(),
+            Err(e) => format!("denied: {}", e.chain()),
+        },
+        key_id: result.as_ref().ok().map(|t| hex::encode(t.key_id())),
+        tenant: result
+            .as_ref()
+            .ok()
+            .and_then(|t| t.tenant().map(str::to_string)),
+        approval: result
+            .as_ref()
+            .ok()
+            .and_then(|t| t.approval().map(str::to_string)),
+        reissued: result.as_ref().is_ok_and(|t| t.reissued()),
+        suppressed: 0,
+    };
+    let audit_seq = match &state.audit {
+        Some(audit) => match audit.record(release.clone(), state.identity.as_ref()).await {
+            Ok(Some(seq)) => Some(seq),
+            Ok(None) => return result,
+            Err(e) => {
+                error!(
+                    "Failed to record key request in the audit log: {}",
+                    e.chain()
+                );
+                return result.and(Err(e));
+            }
+        },
+        None => None,
+    };
+    if let Some(export) = &state.export {
+        export.send(&release, audit_seq, state.identity.public_key());
+    }
+    result
 }
 
-pub async fn process_quotes(tdx_quote_data: &[u8]) -> Result<ProviderResponse, ProviderError> {
+async fn release_key(
+    request: &QuoteRequest,
+    state: &ProviderState,
+    trace: &mut Trace,
+) -> Result<ProviderResponse, ProviderError> {
+    let tdx_quote_data = request.quote.as_slice();
     info!("Starting quote processing");
     debug!("Input quote length: {} bytes", tdx_quote_data.len());
-    debug!("Input quote (hex): {}", hex::encode(tdx_quote_data));
+    debug!("Input quote: {}", Redacted(tdx_quote_data));
 
-    // 1. Verify TDX quote
-    verify_quote(tdx_quote_data).await.or(Err(ProviderError::DcapError))?;
+    // 0. Reject missing or replayed nonces before doing any work; the nonce
+    // is recorded once the evidence verifies
+    let nonce = check_nonce(request, state, trace)?;
 
-    // 2. Parse TDX quote early
-    let tdx_quote = parse_quote(tdx_quote_data.to_vec())?;
+    // The whole request is judged by one policy, even across a reload, and
+    // by its tenant's policy if it has one
+    let settings = state.settings();
+    let (tenant, policy) = trace_tenant(
+        tdx_quote_data,
+        request.tenant.as_deref(),
+        &settings.policy,
+        trace,
+    )?;
+    let tenant = tenant.map(|tenant| tenant.id.as_str());
 
-    // 3. Get initial provider quote (without encrypted key)
-    info!("Getting initial provider quote for PPID verification");
-    let initial_provider_quote = get_quote_with_data(&[])?; // Empty user data
-    let provider_quote_parsed = parse_quote(initial_provider_quote)?;
+    // A token is only encrypted to an X25519 recipient
+    if request.reissue_token && (request.recipient_key.is_some() || request.tpm_parent.is_some()) {
+        return Err(ProviderError::PolicyViolation(
+            "Reissue tokens are only issued to X25519 recipients".into(),
+        ));
+    }
 
-    // 4. Early PPID verification
-    info!("Performing early PPID verification");
-    verify_ppid_match(&provider_quote_parsed.quote, &tdx_quote.quote)?;
+    // 1-4. Verify the quote (or SNP report) and that it may be served here:
+    // for TDX, an early PPID verification. A TD restarting with a
+    // re-issuance token is verified against cached collateral only
+    enter_phase("verify_quote");
+    let (evidence, platform_tcb) = match &request.reissue {
+        Some(reissue) => {
+            redeem_reissue(tdx_quote_data, reissue, state, policy, tenant, trace).await?
+        }
+        None => verify_evidence(tdx_quote_data, state, policy, &mut None, trace).await?,
+    };
+    record_nonce(nonce, state, trace)?;
 
-    // 5. Only proceed with expensive operations after PPID match
-    let sealing_key = get_sealing_key()?;
-    let measurements = extract_measurements(&tdx_quote.quote)?;
-    let derived_key = derive_key(&sealing_key, &measurements);
+    // 5. Each workload identity gets its key so often, and releases the
+    // policy marks as high-value wait for approval
+    trace_rate_limit(
+        state,
+        policy,
+        RateLimitBy::LaunchMeasurement,
+        evidence.launch_measurement(),
+        tenant,
+        trace,
+    )?;
+    let approval = trace_approval(state, policy, evidence.as_ref(), tenant, trace).await?;
 
-    // 6. Extract public key and encrypt derived key
-    let report_data = get_report_data(&tdx_quote.quote)?;
-    let public_key = extract_public_key(report_data)?;
-    let encrypted_key = encrypt_key(&derived_key, &public_key)?;
+    // 6. Only proceed with expensive operations after PPID match
+    enter_phase("derive_key");
+    let derived_key = info_span!("derive_key").in_scope(|| {
+        state
+            .master
+            .derive_for(policy.kdf_version, tenant, evidence.measurements())
+    })?;
 
-    // Calculate hash of encrypted key
-    let hash = calculate_hash(&encrypted_key);
+    // 7. Extract public key and encrypt derived key
+    let report_data = evidence.report_data();
+    let extra_recipients =
+        trace_extra_recipients(&request.extra_recipients, report_data, policy, trace)?;
+    let session = match request.session_key.as_deref() {
+        Some(client_key) => Some(trace.check(
+            "session_key",
+            "session key commitment in report data",
+            open_session(
+                client_key,
+                &request.extra_recipients,
+                report_data,
+                tdx_quote_data,
+            ),
+        )?),
+        None => {
+            trace.skip("session_key", "no session requested");
+            None
+        }
+    };
+    let suite = trace_suite(request, trace)?;
+    let key_id = compute_key_id(&derived_key);
+    check_key_id(state, &key_id, trace)?;
+    trace_rate_limit(state, policy, RateLimitBy::KeyId, &key_id, tenant, trace)?;
+    trace_release_count(
+        state,
+        policy,
+        evidence.launch_measurement(),
+        &key_id,
+        tenant,
+        request.context.as_deref(),
+        trace,
+    )
+    .await?;
+
+    // A kernel key format only changes the plaintext layout; the key id and
+    // key confirmation still refer to the derived key itself
+    let kernel_key = request
+        .kernel_key
+        .as_deref()
+        .map(KernelKeyFormat::from_name)
+        .transpose()?;
+    let formatted_key;
+    let payload = match kernel_key {
+        Some(format) => {
+            formatted_key = format.encode(&derived_key);
+            &formatted_key
+        }
+        None => &derived_key,
+    };
+
+    enter_phase("encrypt_key");
+    let mut x25519_recipient = None;
+    let encrypt_span = info_span!("encrypt_key", suite = suite.name()).entered();
+    let ciphertext = match (
+        request.recipient_key.as_deref(),
+        request.tpm_parent.as_deref(),
+    ) {
+        (Some(public_key_der), _) => {
+            let public_key = trace.check(
+                "recipient_binding",
+                "SHA-256 of recipient_key in report data",
+                extract_rsa_public_key(report_data, public_key_der),
+            )?;
+            encrypt_key_rsa(payload, &public_key)?
+        }
+        (None, Some(parent_public)) => {
+            let parent = trace.check(
+                "recipient_binding",
+                "SHA-256 of tpm_parent in report data",
+                extract_tpm2_parent(report_data, parent_public),
+            )?;
+            encrypt_key_tpm2(payload, &parent)?
+        }
+        (None, None) => {
+            let public_key = trace.check(
+                "recipient_binding",
+                "X25519 key in report data",
+                extract_public_key(report_data),
+            )?;
+            let ciphertext = encrypt_to_x25519(suite, payload, &key_id, &public_key)?;
+            x25519_recipient = Some(public_key);
+            ciphertext
+        }
+    };
+
+    let wrap = |suite: Suite, ciphertext: Vec<u8>| {
+        if request.envelope {
+            KeyEnvelope {
+                suite,
+                key_id,
+                ciphertext,
+            }
+            .encode()
+        } else {
+            ciphertext
+        }
+    };
+
+    let encrypted_key = wrap(suite, ciphertext);
+
+    // Extra recipients are always X25519 keys
+    let extra_suite = match suite {
+        Suite::RsaOaepSha256 | Suite::Tpm2Import => x25519_suites()[0],
+        x25519_suite => x25519_suite,
+    };
+    if !extra_recipients.is_empty() && backend().is_fips() && !extra_suite.is_fips_approved() {
+        return Err(ProviderError::CryptoError(format!(
+            "Extra recipients need {}, which is not approved under the {} backend",
+            extra_suite.name(),
+            backend().name()
+        )));
+    }
+    let recipient_keys = extra_recipients
+        .iter()
+        .map(|public_key| {
+            encrypt_to_x25519(extra_suite, payload, &key_id, public_key)
+                .map(|ct| wrap(extra_suite, ct))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let reissue = match x25519_recipient {
+        Some(public_key) if request.reissue_token => {
+            trace_reissue_token(request, state, policy, evidence.as_ref(), tenant, trace)?
+                .map(|(token, expires_at)| {
+                    encrypt_to_x25519(suite, &token, &key_id, &public_key)
+                        .map(|ct| (wrap(suite, ct), expires_at))
+                })
+                .transpose()?
+        }
+        _ => None,
+    };
+    drop(encrypt_span);
+
+    let key_confirmation = compute_key_confirmation(&derived_key);
+
+    // Bind the encrypted key and the response metadata to the provider quote
+    let mut binding = ResponseBinding::new();
+    binding.add("nonce", nonce);
+    binding.add("suite", suite.name().as_bytes());
+    binding.add("key_confirmation", &key_confirmation);
+    if request.transcript {
+        binding.add("identity_key", state.identity.public_key());
+    }
+    if let Some((_, session_key)) = &session {
+        binding.add("session_key", &session_key.0);
+    }
+    if let Some(format) = kernel_key {
+        binding.add("kernel_key", format.name().as_bytes());
+    }
+    for ciphertext in &recipient_keys {
+        binding.add("recipient_key", ciphertext);
+    }
+    bind_platform_tcb(&mut binding, platform_tcb.as_ref());
+    if let Some(tenant) = tenant {
+        binding.add("tenant", tenant.as_bytes());
+    }
+    if let Some((token, expires_at)) = &reissue {
+        binding.add("reissue_token", token);
+        binding.add("reissue_expires_at", &expires_at.to_be_bytes());
+    }
+    let provider_report_data = binding.report_data(&encrypted_key);
 
-    // 7. Get final quote with hash in user report data
-    debug!("Getting final quote with hash in report data");
-    let final_provider_quote = get_quote_with_data(&hash)?;
+    // 8. Get final quote with hashes in user report data
+    enter_phase("provider_quote");
+    debug!("Getting final quote with hashes in report data");
+    let final_provider_quote = quote_with_data(provider_report_data).await?;
 
     info!("Successfully processed quote and generated response");
     debug!(
@@ -61,53 +505,943 @@ pub async fn process_quotes(tdx_quote_data: &[u8]) -> Result<ProviderResponse, P
     );
 
     Ok(ProviderResponse {
+        suite,
         encrypted_key,
         provider_quote: final_provider_quote,
+        recipient_keys,
+        key_confirmation,
+        kernel_key,
+        session_key: session.as_ref().map(|(_, key)| key.0.to_vec()),
+        platform_tcb,
+        key_id: key_id.to_vec(),
+        tenant: tenant.map(str::to_string),
+        approval,
+        reissue_expires_at: reissue.as_ref().map(|(_, expires_at)| *expires_at),
+        reissue_token: reissue.map(|(token, _)| token),
+        reissued: request.reissue.is_some(),
+        session: session.map(|(channel, _)| Session {
+            channel,
+            derived_key,
+        }),
     })
 }
 
-fn calculate_hash(encrypted_key: &[u8]) -> Vec<u8> {
-    let mut hasher = Sha256::new();
-    hasher.update(encrypted_key);
-    let hash = hasher.finalize();
+/// Judges a key request as [`process_quotes`] would, up to but not including
+/// key derivation: quote verification, the PPID and multi-package checks,
+/// the extra recipient policy and suite negotiation. Only a missing or
+/// replayed nonce is an error; everything else yields a verdict.
+#[instrument(skip_all, name = "check_request")]
+pub async fn check_request(
+    request: &QuoteRequest,
+    state: &ProviderState,
+) -> Result<CheckResponse, ProviderError> {
+    let mut trace = Trace::new();
+    let nonce = check_nonce(request, state, &mut trace)?;
+    let settings = state.settings();
+
+    let mut quote_tcb = None;
+    let outcome = judge(request, state, &settings.policy, &mut quote_tcb, &mut trace).await;
+    // A denied request leaves its nonce to the client
+    if outcome.is_ok() {
+        record_nonce(nonce, state, &mut trace)?;
+    }
+    let (verdict, reason, suite, platform_tcb) = match outcome {
+        Ok((suite, platform_tcb)) => ("eligible", None, Some(suite), platform_tcb),
+        Err(e) => {
+            info!("Checked request would be denied: {}", e.chain());
+            ("denied", Some(e.chain().to_string()), None, None)
+        }
+    };
+
+    let mut binding = ResponseBinding::new();
+    binding.add("nonce", nonce);
+    binding.add("verdict", verdict.as_bytes());
+    if let Some(reason) = &reason {
+        binding.add("reason", reason.as_bytes());
+    }
+    if let Some(tcb) = &quote_tcb {
+        binding.add("quote_tcb_status", tcb.status.as_bytes());
+        for advisory in &tcb.advisory_ids {
+            binding.add("quote_advisory_id", advisory.as_bytes());
+        }
+    }
+    if let Some(suite) = suite {
+        binding.add("suite", suite.name().as_bytes());
+    }
+    bind_platform_tcb(&mut binding, platform_tcb.as_ref());
+    let trace = if request.explain {
+        bind_trace(&mut binding, trace.steps());
+        trace.into_steps()
+    } else {
+        Vec::new()
+    };
+
+    enter_phase("provider_quote");
+    let provider_quote = quote_with_data(binding.report_data(CHECK_LABEL)).await?;
+    Ok(CheckResponse {
+        verdict: verdict.to_string(),
+        reason,
+        quote_tcb,
+        suite: suite.map(|suite| suite.name().to_string()),
+        platform_tcb,
+        trace,
+        provider_quote,
+    })
+}
+
+/// The checks of [`check_request`]. `quote_tcb` is filled in once the quote
+/// verifies, so it is reported even if a later check fails.
+async fn judge(
+    request: &QuoteRequest,
+    state: &ProviderState,
+    policy: &Policy,
+    quote_tcb: &mut Option<PlatformTcb>,
+    trace: &mut Trace,
+) -> Result<(Suite, Option<PlatformTcb>), ProviderError> {
+    let (_, policy) = trace_tenant(&request.quote, request.tenant.as_deref(), policy, trace)?;
+    enter_phase("verify_quote");
+    let (evidence, platform_tcb) =
+        verify_evidence(&request.quote, state, policy, quote_tcb, trace).await?;
+    trace_extra_recipients(
+        &request.extra_recipients,
+        evidence.report_data(),
+        policy,
+        trace,
+    )?;
+    Ok((trace_suite(request, trace)?, platform_tcb))
+}
+
+/// The request nonce, once it is known not to have been used. It is only
+/// recorded, by [`record_nonce`], once the evidence verifies, so requests
+/// with bad evidence cannot use up other clients' nonces or fill the cache.
+fn check_nonce<'a>(
+    request: &'a QuoteRequest,
+    state: &ProviderState,
+    trace: &mut Trace,
+) -> Result<&'a [u8], ProviderError> {
+    let nonce = request
+        .nonce
+        .as_deref()
+        .ok_or_else(|| ProviderError::InvalidNonce("request nonce is required".into()));
+    let len = nonce.as_ref().map_or(0, |nonce| nonce.len());
+    trace.check(
+        "nonce",
+        format!("{} bytes", len),
+        nonce.and_then(|nonce| state.nonces.check(nonce).map(|()| nonce)),
+    )
+}
+
+/// Records the nonce of a request whose evidence verified. Of concurrent
+/// requests with one nonce, only the first to get here goes on.
+fn record_nonce(
+    nonce: &[u8],
+    state: &ProviderState,
+    trace: &mut Trace,
+) -> Result<(), ProviderError> {
+    trace.check("nonce", "recorded", state.nonces.check_and_insert(nonce))
+}
+
+/// Waits for approval of a release the policy's `approval` section covers,
+/// and returns who gave it.
+async fn trace_approval(
+    state: &ProviderState,
+    policy: &Policy,
+    evidence: &dyn Evidence,
+    tenant: Option<&str>,
+    trace: &mut Trace,
+) -> Result<Option<String>, ProviderError> {
+    if !policy.approval.requires(evidence.launch_measurement()) {
+        return Ok(None);
+    }
+    enter_phase("await_approval");
+    let approval = state
+        .approvals
+        .approve(&policy.approval, evidence, tenant, audit::unix_now())
+        .await;
+    let approval = trace.check("approval", policy.approval.approver(), approval)?;
+    if let Some(approval) = &approval {
+        trace.note(format!("approved by {}", approval));
+    }
+    Ok(approval)
+}
+
+/// Takes a request of `identity` from its allowance, if the policy limits
+/// requests `by` that kind of identity. Tenants' identities are counted
+/// apart.
+fn trace_rate_limit(
+    state: &ProviderState,
+    policy: &Policy,
+    by: RateLimitBy,
+    identity: &[u8],
+    tenant: Option<&str>,
+    trace: &mut Trace,
+) -> Result<(), ProviderError> {
+    let limit = &policy.rate_limit;
+    if !limit.applies(by) {
+        return Ok(());
+    }
+    let identity = match tenant {
+        Some(tenant) => format!("{}/{}", tenant, hex::encode(identity)),
+        None => hex::encode(identity),
+    };
+    trace.check(
+        "rate_limit",
+        format!("{} per {}s", limit.requests, limit.window_secs),
+        state.rate_limits.take(limit, &identity, Instant::now()),
+    )
+}
+
+/// Counts the release of a key to its workload identity for `context`, if
+/// the policy limits or watches how often identities get their keys.
+/// Tenants' identities are counted apart.
+async fn trace_release_count(
+    state: &ProviderState,
+    policy: &Policy,
+    launch_measurement: &[u8],
+    key_id: &[u8],
+    tenant: Option<&str>,
+    context: Option<&str>,
+    trace: &mut Trace,
+) -> Result<(), ProviderError> {
+    if let Some(context) = context {
+        releases::check_context(context)?;
+    }
+    let limits = &policy.release_limits;
+    if !limits.is_active() {
+        return Ok(());
+    }
+    let counters = state.counters.as_ref().ok_or_else(|| {
+        ProviderError::ConfigError("Release limits need a counter store (files.counters)".into())
+    })?;
+    let identity = match limits.by {
+        RateLimitBy::LaunchMeasurement => launch_measurement,
+        RateLimitBy::KeyId => key_id,
+    };
+    let identity = match tenant {
+        Some(tenant) => format!("{}/{}", tenant, hex::encode(identity)),
+        None => hex::encode(identity),
+    };
+    let count = trace.check(
+        "release_limit",
+        format!(
+            "{} per context, {} in all",
+            limits.max_per_context, limits.max_total
+        ),
+        releases::count(counters, limits, &identity, context).await,
+    )?;
+    trace.note(format!(
+        "release {} for context {}",
+        count,
+        context.unwrap_or("-")
+    ));
+    Ok(())
+}
 
-    // Create 64-byte user report data
-    let mut report_data = [0u8; 64];
-    report_data[..32].copy_from_slice(&hash);
+/// Verifies the TDX quote of a TD that restarted with a re-issuance token
+/// against the collateral already cached for its platform, so the release
+/// needs no PCS round trip: the token must be outstanding and issued for the
+/// same measurements, and `reissue` must prove it is held. The quote must
+/// still come from this platform and is checked against the policy as any
+/// verified evidence is.
+async fn redeem_reissue(
+    bytes: &[u8],
+    reissue: &ReissueProof,
+    state: &ProviderState,
+    policy: &Policy,
+    tenant: Option<&str>,
+    trace: &mut Trace,
+) -> Result<(Box<dyn Evidence>, Option<PlatformTcb>), ProviderError> {
+    if policy.reissue.ttl_secs == 0 {
+        return Err(ProviderError::PolicyViolation(
+            "Reissue tokens are not accepted".into(),
+        ));
+    }
+    if EvidenceKind::detect(bytes) != EvidenceKind::Tdx {
+        return Err(ProviderError::PolicyViolation(
+            "Reissue tokens are only redeemed for TDX quotes".into(),
+        ));
+    }
+    let verified =
+        verify_quote_cached(bytes, state).map_err(|e| ProviderError::DcapError(Box::new(e)));
+    let quote_tcb = trace.check("quote_verification", "cached collateral", verified)?;
+    trace.note(match &quote_tcb {
+        Some(tcb) => format!("TCB status {}", tcb.status),
+        None => "not verified in dev mode".to_string(),
+    });
+    let tdx = TdxEvidence::parse(bytes)?;
+    let platform_tcb = trace_same_platform(&tdx.quote, state, policy, trace).await?;
+    // Only a verified quote from this platform uses the token up
+    trace.check(
+        "reissue",
+        format!("token {}", hex::encode(&reissue.id)),
+        state.reissues.redeem(
+            &reissue.id,
+            &reissue.proof,
+            bytes,
+            tdx.measurements(),
+            tenant,
+            audit::unix_now(),
+        ),
+    )?;
+    check_evidence(&tdx, state, policy, trace)?;
+    Ok((Box::new(tdx), platform_tcb))
+}
 
-    debug!("Hash of encrypted key: {}", hex::encode(&hash));
-    report_data.to_vec()
+/// Issues the re-issuance token a request asked for, if the policy issues
+/// them. A key released for a token comes with a new one only if the policy
+/// chains them.
+fn trace_reissue_token(
+    request: &QuoteRequest,
+    state: &ProviderState,
+    policy: &Policy,
+    evidence: &dyn Evidence,
+    tenant: Option<&str>,
+    trace: &mut Trace,
+) -> Result<Option<(SecretBytes, u64)>, ProviderError> {
+    let reissue = &policy.reissue;
+    if reissue.ttl_secs == 0 {
+        trace.skip("reissue", "the policy issues no tokens");
+        return Ok(None);
+    }
+    if evidence.kind() != EvidenceKind::Tdx {
+        trace.skip("reissue", "tokens are only redeemed for TDX quotes");
+        return Ok(None);
+    }
+    if request.reissue.is_some() && !reissue.chain {
+        trace.skip("reissue", "the key was reissued and tokens do not chain");
+        return Ok(None);
+    }
+    let issued = trace.check(
+        "reissue",
+        format!("valid for {}s", reissue.ttl_secs),
+        state
+            .reissues
+            .issue(reissue, evidence.measurements(), tenant, audit::unix_now()),
+    )?;
+    Ok(Some(issued))
 }
 
+/// Refuses a derived key whose ID the revocation list names.
+fn check_key_id(
+    state: &ProviderState,
+    key_id: &[u8],
+    trace: &mut Trace,
+) -> Result<(), ProviderError> {
+    if let Some(revocations) = &state.revocations {
+        trace.check("revocation", "key id", revocations.check_key_id(key_id))?;
+    }
+    Ok(())
+}
+
+/// The tenant of the request for `bytes`, which `requested` may name, and
+/// the policy that judges it. Without tenants there is nothing to trace.
+fn trace_tenant<'a>(
+    bytes: &[u8],
+    requested: Option<&str>,
+    policy: &'a Policy,
+    trace: &mut Trace,
+) -> Result<(Option<&'a Tenant>, &'a Policy), ProviderError> {
+    if policy.tenants.is_empty() && requested.is_none() {
+        return Ok((None, policy));
+    }
+    // The launch measurement only picks the policy; the evidence is verified
+    // under it right after
+    let launch_measurement = evidence::parse(bytes)?.launch_measurement().to_vec();
+    let tenant = trace.check(
+        "tenant",
+        requested.unwrap_or("by launch measurement"),
+        tenants::select(policy, requested, &launch_measurement),
+    )?;
+    trace.note(match tenant {
+        Some(tenant) => format!("tenant {}", tenant.id),
+        None => "no tenant, provider namespace".to_string(),
+    });
+    Ok((tenant, tenant.map_or(policy, |tenant| &tenant.rules)))
+}
+
+/// Verifies `bytes` as whichever kind of evidence they are, then checks them
+/// as [`check_evidence`] does. `quote_tcb` is filled in once the evidence
+/// verifies.
+async fn verify_evidence(
+    bytes: &[u8],
+    state: &ProviderState,
+    policy: &Policy,
+    quote_tcb: &mut Option<PlatformTcb>,
+    trace: &mut Trace,
+) -> Result<(Box<dyn Evidence>, Option<PlatformTcb>), ProviderError> {
+    let (evidence, platform_tcb) = verify_kind(bytes, state, policy, quote_tcb, trace).await?;
+    check_evidence(evidence.as_ref(), state, policy, trace)?;
+    Ok((evidence, platform_tcb))
+}
+
+/// Checks that `evidence` may be served here now and is not revoked, and, if
+/// the policy names CoRIM reference values or a measurement allowlist, that
+/// its measurements match one.
+fn check_evidence(
+    evidence: &dyn Evidence,
+    state: &ProviderState,
+    policy: &Policy,
+    trace: &mut Trace,
+) -> Result<(), ProviderError> {
+    if let Some(revocations) = &state.revocations {
+        trace.check(
+            "revocation",
+            format!("{} revoked entries", revocations.count()),
+            revocations.check_evidence(evidence),
+        )?;
+    }
+    if !policy.release_windows.is_empty() {
+        trace.check(
+            "release_window",
+            format!("clock skew {}s", policy.release_windows.clock_skew_secs),
+            policy.release_windows.check(audit::unix_now()),
+        )?;
+    }
+    if !policy.reference_values.is_empty() {
+        trace.check(
+            "reference_values",
+            format!("{} reference values", policy.references.len()),
+            policy.references.check(evidence),
+        )?;
+    }
+    if let Some(allowlist) = &policy.allowlist {
+        let image = trace.check(
+            "measurement_allowlist",
+            format!("{} allowed images", allowlist.count()),
+            allowlist.check(evidence, audit::unix_now()),
+        )?;
+        trace.note(format!("image {}", image));
+    }
+    Ok(())
+}
+
+/// Verifies evidence the way its kind needs: a TDX quote must come from this
+/// platform, an SNP report from a host the `sev_snp` policy names, Azure TDX
+/// evidence needs its vTPM quote unless `require_vtpm` is off, a GCP token
+/// must be for a workload the `gcp` policy names, Trust Authority and MAA
+/// tokens for a TD the `ita` or `maa` policy names, a TPM quote is refused
+/// unless the `tpm` policy admits clients without a TEE, and a CCA token must
+/// come from a platform with a trusted CPAK that the `cca` policy names.
+async fn verify_kind(
+    bytes: &[u8],
+    state: &ProviderState,
+    policy: &Policy,
+    quote_tcb: &mut Option<PlatformTcb>,
+    trace: &mut Trace,
+) -> Result<(Box<dyn Evidence>, Option<PlatformTcb>), ProviderError> {
+    match EvidenceKind::detect(bytes) {
+        EvidenceKind::Tdx => {
+            *quote_tcb = check_quote(bytes, state, trace).await?;
+            let tdx = TdxEvidence::parse(bytes)?;
+            let platform_tcb = trace_same_platform(&tdx.quote, state, policy, trace).await?;
+            Ok((Box::new(tdx), platform_tcb))
+        }
+        EvidenceKind::SevSnp => {
+            let snp = state.snp.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("SEV-SNP evidence is not enabled".into())
+            })?;
+            let report = SnpReport::parse(bytes)?;
+            let verified = verify_snp_report(&report, snp).await;
+            *quote_tcb = trace.check("quote_verification", "sev-snp", verified)?;
+            trace.note(match &*quote_tcb {
+                Some(tcb) => format!("reported TCB {}", tcb.status),
+                None => "not verified in dev mode".to_string(),
+            });
+            trace.check(
+                "snp_policy",
+                format!("{} allowed chips", policy.sev_snp.allowed_chip_ids.len()),
+                policy.sev_snp.check(&report),
+            )?;
+            Ok((Box::new(report), None))
+        }
+        EvidenceKind::AzureTdx => {
+            let azure_tdx = state.azure_tdx.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("Azure TDX evidence is not enabled".into())
+            })?;
+            let azure = AzureTdxEvidence::parse(bytes)?;
+            *quote_tcb = check_quote(&azure.raw_quote, state, trace).await?;
+            let vtpm = trace.check(
+                "vtpm",
+                format!("require_vtpm {}", azure_tdx.require_vtpm),
+                azure.check_vtpm(azure_tdx.require_vtpm),
+            )?;
+            if !vtpm {
+                trace.note("no vTPM quote, guest PCRs not covered");
+            }
+            trace.skip("same_platform", "Azure TDs run on Azure hosts");
+            Ok((Box::new(azure), None))
+        }
+        EvidenceKind::GcpToken => {
+            let gcp = state.gcp.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("GCP attestation tokens are not enabled".into())
+            })?;
+            let token = GcpToken::parse(bytes)?;
+            let verified = verify_gcp_token(&token, gcp).await;
+            trace.check("quote_verification", "gcp-token", verified)?;
+            trace.note(format!("{} on {}", token.swname(), token.hwmodel()));
+            trace.check(
+                "gcp_policy",
+                format!("{} allowed projects", policy.gcp.allowed_projects.len()),
+                policy.gcp.check(&token),
+            )?;
+            Ok((Box::new(token), None))
+        }
+        EvidenceKind::ItaToken => {
+            let ita = state.ita.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("Trust Authority tokens are not enabled".into())
+            })?;
+            let token = ItaToken::parse(bytes)?;
+            let verified = verify_ita_token(&token, ita).await;
+            *quote_tcb = trace.check("quote_verification", "ita-token", verified)?;
+            trace.note(format!(
+                "Trust Authority reported TCB {}",
+                token.tcb_status()
+            ));
+            trace.check(
+                "ita_policy",
+                format!("{} allowed MRTDs", policy.ita.allowed_mrtds.len()),
+                policy.ita.check(&token),
+            )?;
+            trace.skip(
+                "same_platform",
+                "Trust Authority does not name the platform",
+            );
+            Ok((Box::new(token), None))
+        }
+        EvidenceKind::MaaToken => {
+            let maa = state.maa.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("MAA tokens are not enabled".into())
+            })?;
+            let token = MaaToken::parse(bytes)?;
+            let verified = verify_maa_token(&token, maa).await;
+            trace.check("quote_verification", "maa-token", verified)?;
+            trace.check(
+                "maa_policy",
+                format!("{} allowed MRTDs", policy.maa.allowed_mrtds.len()),
+                policy.maa.check(&token),
+            )?;
+            trace.skip("same_platform", "Azure TDs run on Azure hosts");
+            Ok((Box::new(token), None))
+        }
+        EvidenceKind::Tpm => {
+            let tpm = state.tpm.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("TPM evidence is not enabled".into())
+            })?;
+            let evidence = TpmEvidence::parse(bytes)?;
+            trace.check(
+                "quote_verification",
+                "tpm",
+                verify_tpm_quote(&evidence, tpm),
+            )?;
+            trace.note("not a TEE: the host can read the client's memory");
+            trace.check(
+                "tpm_policy",
+                format!("allow {}", policy.tpm.allow),
+                policy.tpm.check(&evidence),
+            )?;
+            trace.skip("same_platform", "TPM clients run on their own hosts");
+            Ok((Box::new(evidence), None))
+        }
+        #[cfg(feature = "cca")]
+        EvidenceKind::CcaToken => {
+            let cca = state.cca.as_ref().ok_or_else(|| {
+                ProviderError::PolicyViolation("ARM CCA tokens are not enabled".into())
+            })?;
+            let token = CcaToken::parse(bytes)?;
+            trace.check("quote_verification", "cca", verify_cca_token(&token, cca))?;
+            trace.check(
+                "cca_policy",
+                format!(
+                    "{} allowed implementations",
+                    policy.cca.allowed_implementation_ids.len()
+                ),
+                policy.cca.check(&token),
+            )?;
+            trace.skip("same_platform", "realms run on ARM hosts");
+            Ok((Box::new(token), None))
+        }
+        #[cfg(not(feature = "cca"))]
+        EvidenceKind::CcaToken => Err(ProviderError::PolicyViolation(
+            "ARM CCA tokens need a provider built with the `cca` feature".into(),
+        )),
+    }
+}
+
+async fn check_quote(
+    quote: &[u8],
+    state: &ProviderState,
+    trace: &mut Trace,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    let verified = verify_quote(quote, state)
+        .await
+        .map_err(|e| ProviderError::DcapError(Box::new(e)));
+    let quote_tcb = trace.check("quote_verification", state.verifier.name(), verified)?;
+    trace.note(match &quote_tcb {
+        Some(tcb) => format!("TCB status {}", tcb.status),
+        None => "not verified in dev mode".to_string(),
+    });
+    Ok(quote_tcb)
+}
+
+async fn trace_same_platform(
+    tdx_quote: &Quote,
+    state: &ProviderState,
+    policy: &Policy,
+    trace: &mut Trace,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    let checked = check_same_platform(tdx_quote, state, policy.multi_package).await;
+    let platform_tcb = trace.check(
+        "same_platform",
+        format!("multi_package {}", policy.multi_package.name()),
+        checked,
+    )?;
+    if !gramine::is_attested() {
+        trace.note("unattested platform, PPIDs not compared");
+    }
+    Ok(platform_tcb)
+}
+
+fn trace_extra_recipients(
+    extra_recipients: &[Vec<u8>],
+    report_data: &[u8],
+    policy: &Policy,
+    trace: &mut Trace,
+) -> Result<Vec<PublicKey>, ProviderError> {
+    if extra_recipients.is_empty() {
+        trace.skip("extra_recipients", "none requested");
+        return Ok(Vec::new());
+    }
+    trace.check(
+        "extra_recipients",
+        format!(
+            "{} keys against {} allowed",
+            extra_recipients.len(),
+            policy.allowed_extra_recipients.len()
+        ),
+        check_extra_recipients(extra_recipients, report_data, policy),
+    )
+}
+
+fn trace_suite(request: &QuoteRequest, trace: &mut Trace) -> Result<Suite, ProviderError> {
+    let input = if request.suites.is_empty() {
+        "default".to_string()
+    } else {
+        request.suites.join(", ")
+    };
+    let suite = trace.check("suite", input, select_suite(request))?;
+    trace.note(suite.name());
+    Ok(suite)
+}
+
+/// Binds each step as its `rule`, `input`, `outcome` and `detail`, separated
+/// by newlines.
+fn bind_trace(binding: &mut ResponseBinding, steps: &[PolicyStep]) {
+    for step in steps {
+        let encoded = format!(
+            "{}\n{}\n{}\n{}",
+            step.rule,
+            step.input,
+            step.outcome,
+            step.detail.as_deref().unwrap_or_default()
+        );
+        binding.add("trace_step", encoded.as_bytes());
+    }
+}
+
+/// Checks the PPID of `tdx_quote` against an initial provider quote (without
+/// encrypted key), which also gives the platform TCB level. A simulated
+/// platform has no quote to compare against.
+async fn check_same_platform(
+    tdx_quote: &Quote,
+    state: &ProviderState,
+    multi_package: MultiPackagePolicy,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    if !gramine::is_attested() {
+        warn!("Unattested platform: skipping PPID verification");
+        return Ok(None);
+    }
+
+    enter_phase("verify_ppid");
+    info!("Getting initial provider quote for PPID verification");
+    let initial_provider_quote = gramine::unblock(gramine::own_quote).await?; // Empty user data
+
+    // The TCB level only informs the client; failing to get it must not
+    // fail the request
+    let platform_tcb = platform_tcb(&initial_provider_quote, state)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Cannot evaluate the platform TCB: {}", e.chain());
+            None
+        });
+    let provider_quote_parsed = parse_quote(initial_provider_quote)?;
+
+    info!("Performing early PPID verification");
+    verify_ppid_match(&provider_quote_parsed.quote, tdx_quote, multi_package)?;
+    Ok(platform_tcb)
+}
+
+fn select_suite(request: &QuoteRequest) -> Result<Suite, ProviderError> {
+    // RSA and TPM recipients each have exactly one suite
+    let usable = match (&request.recipient_key, &request.tpm_parent) {
+        (Some(_), Some(_)) => {
+            return Err(ProviderError::PublicKeyError(
+                "recipient_key and tpm_parent are mutually exclusive".into(),
+            ))
+        }
+        (Some(_), None) => vec![Suite::RsaOaepSha256],
+        (None, Some(_)) => vec![Suite::Tpm2Import],
+        (None, None) => x25519_suites(),
+    };
+    let usable: Vec<Suite> = usable
+        .into_iter()
+        .filter(|suite| !backend().is_fips() || suite.is_fips_approved())
+        .collect();
+
+    // Clients that predate negotiation get the original behaviour
+    if request.suites.is_empty() {
+        return usable.first().copied().ok_or_else(|| {
+            ProviderError::CryptoError(format!(
+                "No suite for this recipient is approved under the {} backend",
+                backend().name()
+            ))
+        });
+    }
+
+    let suite = negotiate_suite(&request.suites, &usable).ok_or_else(|| {
+        ProviderError::CryptoError(format!(
+            "No mutually supported suite among requested {:?}",
+            request.suites
+        ))
+    })?;
+    info!("Negotiated response suite {}", suite.name());
+    Ok(suite)
+}
+
+/// Suites for an X25519 recipient, the default (for clients that do not
+/// negotiate) first.
+pub(super) fn x25519_suites() -> Vec<Suite> {
+    vec![
+        Suite::X25519SealedBox,
+        Suite::CoseEncrypt0,
+        Suite::JweEcdhEsA256Gcm,
+        Suite::X25519SecretStream,
+    ]
+}
+
+pub(super) fn encrypt_to_x25519(
+    suite: Suite,
+    derived_key: &SecretBytes,
+    key_id: &[u8],
+    public_key: &PublicKey,
+) -> Result<Vec<u8>, ProviderError> {
+    match suite {
+        Suite::X25519SealedBox => encrypt_key(derived_key, public_key),
+        Suite::CoseEncrypt0 => encrypt_key_cose(derived_key, key_id, public_key),
+        Suite::JweEcdhEsA256Gcm => encrypt_key_jwe(derived_key, key_id, public_key),
+        Suite::X25519SecretStream => encrypt_stream(derived_key, public_key),
+        Suite::RsaOaepSha256 | Suite::Tpm2Import => Err(ProviderError::CryptoError(format!(
+            "{} is not an X25519 suite",
+            suite.name()
+        ))),
+    }
+}
+
+fn hash_extra_recipients(extra_recipients: &[Vec<u8>]) -> Vec<u8> {
+    let mut parts: Vec<&[u8]> = vec![EXTRA_RECIPIENTS_LABEL];
+    parts.extend(extra_recipients.iter().map(Vec::as_slice));
+    backend().sha256(&parts).to_vec()
+}
+
+#[instrument(skip_all, name = "evaluate_policy")]
+fn check_extra_recipients(
+    extra_recipients: &[Vec<u8>],
+    report_data: &[u8],
+    policy: &Policy,
+) -> Result<Vec<PublicKey>, ProviderError> {
+    if extra_recipients.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    info!("Checking {} extra recipients", extra_recipients.len());
+
+    // The TD must have committed to exactly this recipient list, otherwise the
+    // host could append its own key to the request.
+    let expected = hash_extra_recipients(extra_recipients);
+    if report_data.len() < 64 || !constant_time_eq(&expected, &report_data[32..64]) {
+        return Err(ProviderError::PolicyViolation(
+            "Extra recipients are not bound in report data".into(),
+        ));
+    }
+
+    extra_recipients
+        .iter()
+        .map(|public_key| {
+            if public_key.len() != box_::PUBLICKEYBYTES {
+                return Err(ProviderError::PublicKeyError(format!(
+                    "Extra recipient key must be {} bytes",
+                    box_::PUBLICKEYBYTES
+                )));
+            }
+            policy.check_extra_recipient(public_key)?;
+            extract_public_key(public_key)
+        })
+        .collect()
+}
+
+/// Checks that the TD committed to its session key and runs the provider side
+/// of the handshake.
+fn open_session(
+    client_key: &[u8],
+    extra_recipients: &[Vec<u8>],
+    report_data: &[u8],
+    tdx_quote: &[u8],
+) -> Result<(SessionChannel, PublicKey), ProviderError> {
+    info!("Opening session");
+
+    // Both commitments would need bytes 32..64 of the report data
+    if !extra_recipients.is_empty() {
+        return Err(ProviderError::PolicyViolation(
+            "Sessions cannot be combined with extra recipients".into(),
+        ));
+    }
+
+    let expected = backend().sha256(&[SESSION_KEY_LABEL, client_key]);
+    if report_data.len() < 64 || !constant_time_eq(&expected, &report_data[32..64]) {
+        return Err(ProviderError::PolicyViolation(
+            "Session key is not bound in report data".into(),
+        ));
+    }
+
+    SessionChannel::establish(client_key, tdx_quote)
+}
+
+#[instrument(skip_all, name = "parse_quote")]
 fn parse_quote(data: Vec<u8>) -> Result<QuoteData, ProviderError> {
-    let quote = Quote::parse(&data)
-        .map_err(|_| ProviderError::QuoteParseError("Failed to parse quote".into()))?;
+    let quote = Quote::parse(&data).map_err(|e| ProviderError::quote_decode("quote", e))?;
 
     Ok(QuoteData { quote })
 }
 
-async fn verify_quote(quote_data: &[u8]) -> Result<(), ProviderError> {
+/// The quote's TCB level, or `None` in dev mode, where it is not verified.
+#[instrument(skip_all, name = "verify_quote")]
+async fn verify_quote(
+    quote_data: &[u8],
+    state: &ProviderState,
+) -> Result<Option<PlatformTcb>, ProviderError> {
     #[cfg(feature = "dev-mode")]
     {
         warn!("Skipping quote verification in dev mode");
+        return Ok(None);
+    }
+
+    debug!("Verifying quote with {}", state.verifier.name());
+    let verified = state.verifier.verify(quote_data).await?;
+
+    info!(
+        "Quote verified successfully (TCB status {})",
+        verified.tcb_status
+    );
+    Ok(Some(PlatformTcb {
+        status: verified.tcb_status,
+        advisory_ids: verified.advisory_ids,
+    }))
+}
+
+/// [`verify_quote`] with the collateral the verifier already caches.
+#[instrument(skip_all, name = "verify_quote")]
+fn verify_quote_cached(
+    quote_data: &[u8],
+    state: &ProviderState,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping quote verification in dev mode");
+        return Ok(None);
+    }
+
+    let verified = state.verifier.verify_cached(quote_data)?;
+    Ok(Some(PlatformTcb {
+        status: verified.tcb_status,
+        advisory_ids: verified.advisory_ids,
+    }))
+}
+
+/// The report's TCB level, or `None` in dev mode, where it is not verified.
+#[instrument(skip_all, name = "verify_quote")]
+async fn verify_snp_report(
+    report: &SnpReport,
+    snp: &SnpVerifier,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping SEV-SNP report verification in dev mode");
+        return Ok(None);
+    }
+
+    let tcb = snp.verify(report).await?;
+    Ok(Some(PlatformTcb {
+        status: tcb.to_string(),
+        advisory_ids: Vec::new(),
+    }))
+}
+
+#[instrument(skip_all, name = "verify_quote")]
+async fn verify_gcp_token(token: &GcpToken, gcp: &GcpVerifier) -> Result<(), ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping attestation token verification in dev mode");
         return Ok(());
     }
 
-    debug!("Verifying quote with DCAP");
+    gcp.verify(token).await
+}
 
-    let collateral = get_collateral_from_pcs(quote_data)
-        .await
-        .map_err(|_| ProviderError::QuoteVerificationError)?;
+/// The TCB level Trust Authority found, or `None` in dev mode, where the
+/// token is not verified.
+#[instrument(skip_all, name = "verify_quote")]
+async fn verify_ita_token(
+    token: &ItaToken,
+    ita: &ItaVerifier,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping Trust Authority token verification in dev mode");
+        return Ok(None);
+    }
 
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    ita.verify(token).await.map(Some)
+}
 
-    verify(quote_data, &collateral, now).map_err(|_| ProviderError::QuoteVerificationError)?;
+#[instrument(skip_all, name = "verify_quote")]
+async fn verify_maa_token(token: &MaaToken, maa: &MaaVerifier) -> Result<(), ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping MAA token verification in dev mode");
+        return Ok(());
+    }
 
-    info!("Quote verified successfully");
-    Ok(())
+    maa.verify(token).await
+}
+
+fn verify_tpm_quote(evidence: &TpmEvidence, tpm: &TpmVerifier) -> Result<(), ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping TPM quote verification in dev mode");
+        return Ok(());
+    }
+
+    tpm.verify(evidence)
+}
+
+#[cfg(feature = "cca")]
+fn verify_cca_token(token: &CcaToken, cca: &CcaVerifier) -> Result<(), ProviderError> {
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping CCA token verification in dev mode");
+        return Ok(());
+    }
+
+    cca.verify(token)
 }
 
 #[derive(Debug)]
@@ -115,15 +1449,18 @@ struct QuoteData {
     quote: Quote,
 }
 
-fn verify_ppid_match(sgx_quote: &Quote, tdx_quote: &Quote) -> Result<(), ProviderError> {
+#[instrument(skip_all, name = "verify_ppid")]
+pub(super) fn verify_ppid_match(
+    sgx_quote: &Quote,
+    tdx_quote: &Quote,
+    multi_package: MultiPackagePolicy,
+) -> Result<(), ProviderError> {
     let sgx_ppid = &sgx_quote.header.user_data[..16];
     let tdx_ppid = &tdx_quote.header.user_data[..16];
 
     info!("Performing PPID verification");
-    debug!("SGX Quote Header: {:?}", sgx_quote.header);
-    debug!("TDX Quote Header: {:?}", tdx_quote.header);
-    debug!("SGX PPID (hex): {}", hex::encode(sgx_ppid));
-    debug!("TDX PPID (hex): {}", hex::encode(tdx_ppid));
+    debug!("SGX PPID: {}", Redacted(sgx_ppid));
+    debug!("TDX PPID: {}", Redacted(tdx_ppid));
 
     #[cfg(feature = "dev-mode")]
     {
@@ -131,51 +1468,53 @@ fn verify_ppid_match(sgx_quote: &Quote, tdx_quote: &Quote) -> Result<(), Provide
         return Ok(());
     }
 
-    if sgx_ppid != tdx_ppid {
-        error!("PPID mismatch between SGX and TDX quotes");
-        error!("SGX PPID: {}", hex::encode(sgx_ppid));
-        error!("TDX PPID: {}", hex::encode(tdx_ppid));
-        return Err(ProviderError::PPIDMismatch);
+    if !constant_time_eq(sgx_ppid, tdx_ppid) {
+        return check_multi_package(sgx_quote, tdx_quote, multi_package);
     }
 
     info!("PPID match confirmed, proceeding with key derivation");
     Ok(())
 }
 
-fn extract_measurements(quote: &Quote) -> Result<Vec<u8>, ProviderError> {
-    let mut measurements = Vec::new();
-
-    match &quote.report {
-        Report::TD10(report) => {
-            debug!("Processing TD10 measurements");
-            measurements.extend_from_slice(&report.mr_td);
-            measurements.extend_from_slice(&report.rt_mr0);
-            measurements.extend_from_slice(&report.rt_mr1);
-            measurements.extend_from_slice(&report.rt_mr2);
-            measurements.extend_from_slice(&report.rt_mr3);
-        }
-        Report::TD15(report) => {
-            debug!("Processing TD15 measurements");
-            measurements.extend_from_slice(&report.base.mr_td);
-            measurements.extend_from_slice(&report.base.rt_mr0);
-            measurements.extend_from_slice(&report.base.rt_mr1);
-            measurements.extend_from_slice(&report.base.rt_mr2);
-            measurements.extend_from_slice(&report.base.rt_mr3);
-        }
+/// On a multi-package platform each package has its own PPID, so the quotes
+/// may differ just because they were made on different packages. The policy
+/// decides whether the PCK certificates' platform instance is enough.
+fn check_multi_package(
+    sgx_quote: &Quote,
+    tdx_quote: &Quote,
+    policy: MultiPackagePolicy,
+) -> Result<(), ProviderError> {
+    let sgx_pck = PckInfo::from_quote(sgx_quote);
+    let tdx_pck = PckInfo::from_quote(tdx_quote);
+    let (sgx_pck, tdx_pck) = match (sgx_pck, tdx_pck) {
+        (Ok(sgx), Ok(tdx)) if sgx.is_multi_package() && tdx.is_multi_package() => (sgx, tdx),
         _ => {
-            error!("Invalid report type for measurements");
-            return Err(ProviderError::QuoteParseError("Not a TDX quote".into()));
+            error!("PPID mismatch between SGX and TDX quotes");
+            return Err(ProviderError::PPIDMismatch);
         }
-    }
+    };
 
-    debug!("Extracted measurements: {} bytes", measurements.len());
-    Ok(measurements)
-}
-
-fn get_report_data(quote: &Quote) -> Result<&[u8], ProviderError> {
-    match &quote.report {
-        Report::TD10(report) => Ok(&report.report_data),
-        Report::TD15(report) => Ok(&report.base.report_data),
-        _ => Err(ProviderError::QuoteParseError("Not a TDX quote".into())),
+    match policy {
+        MultiPackagePolicy::Reject => {
+            error!(
+                "PPID mismatch: quotes come from different packages of a multi-package platform"
+            );
+            Err(ProviderError::PolicyViolation(
+                "SGX and TDX quotes come from different packages of a multi-package platform; \
+                 set multi_package to platform_instance in the policy to accept them"
+                    .into(),
+            ))
+        }
+        MultiPackagePolicy::PlatformInstance => {
+            let same_platform = sgx_pck.platform_instance_id.is_some()
+                && sgx_pck.platform_instance_id == tdx_pck.platform_instance_id
+                && sgx_pck.fmspc == tdx_pck.fmspc;
+            if !same_platform {
+                error!("PPID and platform instance mismatch between SGX and TDX quotes");
+                return Err(ProviderError::PPIDMismatch);
+            }
+            info!("Quotes come from different packages of the same platform instance, proceeding");
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,253 @@
+use super::binding::ResponseBinding;
+use super::tcb::{bind_platform_tcb, platform_tcb};
+use crate::counters::BOOT_EPOCH;
+use crate::crypto::{backend, Suite};
+use crate::error::ProviderError;
+use crate::gramine::{self, quote_with_data, KssIdentity};
+use crate::protocol::{
+    BuildInfo, InfoRequest, InfoResponse, KssInfo, PlatformTcb, TdInfo, PROTOCOL_VERSION,
+};
+use crate::state::ProviderState;
+use dcap_qvl::quote::{Quote, Report};
+use log::{info, warn};
+
+/// Stands in for the encrypted key in the report data of an info quote, so an
+/// info quote can never be mistaken for a key response.
+const INFO_LABEL: &[u8] = b"gramine-sealing-key-provider/info/v1";
+
+/// Describes the provider and attests to the description with a fresh quote.
+///
+/// The quote's report data is `SHA-256(INFO_LABEL) | metadata hash`, with the
+/// nonce, identity key, protocol versions, suites, backend and platform TCB
+/// bound the same way as in a key response.
+pub async fn provider_info(
+    request: &InfoRequest,
+    state: &ProviderState,
+) -> Result<InfoResponse, ProviderError> {
+    state.nonces.check_and_insert(&request.nonce)?;
+
+    // The TCB level comes from the cached unbound quote, since the one
+    // returned has to vouch for it
+    let platform_tcb = if gramine::is_attested() {
+        platform_tcb(&gramine::unblock(gramine::own_quote).await?, state)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Cannot evaluate the platform TCB: {}", e.chain());
+                None
+            })
+    } else {
+        None
+    };
+
+    let (mut response, report_data) = describe(&request.nonce, state, platform_tcb);
+    response.provider_quote = quote_with_data(report_data).await?;
+
+    if !gramine::is_attested() {
+        warn!("Unattested platform: reporting zero enclave measurements");
+    } else if gramine::is_td() {
+        let report = gramine::unblock(gramine::own_td_report).await?;
+        response.td = Some(TdInfo {
+            mr_td: report.mr_td.to_vec(),
+            rtmrs: [report.rt_mr0, report.rt_mr1, report.rt_mr2, report.rt_mr3]
+                .iter()
+                .map(|rtmr| rtmr.to_vec())
+                .collect(),
+            td_attributes: report.td_attributes.to_vec(),
+        });
+    } else {
+        let quote = Quote::parse(&response.provider_quote)
+            .map_err(|e| ProviderError::quote_decode("own quote", e))?;
+        let Report::SgxEnclave(report) = quote.report else {
+            return Err(ProviderError::QuoteParseError(
+                "Own quote is not an SGX enclave quote".into(),
+            ));
+        };
+        response.mr_enclave = report.mr_enclave.to_vec();
+        response.mr_signer = report.mr_signer.to_vec();
+        response.isv_prod_id = report.isv_prod_id;
+        response.isv_svn = report.isv_svn;
+        response.kss = KssIdentity::from_report(&report).map(|kss| KssInfo {
+            config_id: kss.config_id.to_vec(),
+            config_svn: kss.config_svn,
+            isv_family_id: kss.isv_family_id.to_vec(),
+            isv_ext_prod_id: kss.isv_ext_prod_id.to_vec(),
+        });
+    }
+
+    info!("Served provider info");
+    Ok(response)
+}
+
+/// The provider's description, with zero measurements and no quote yet, and
+/// the report data its quote must carry.
+fn describe(
+    nonce: &[u8],
+    state: &ProviderState,
+    platform_tcb: Option<PlatformTcb>,
+) -> (InfoResponse, Vec<u8>) {
+    let protocol_versions = vec![PROTOCOL_VERSION];
+    let suites: Vec<String> = Suite::PREFERENCE
+        .into_iter()
+        .filter(|suite| !backend().is_fips() || suite.is_fips_approved())
+        .map(|suite| suite.name().to_string())
+        .collect();
+    let crypto_backend = backend().name().to_string();
+    let identity_key = state.identity.public_key().to_vec();
+    let boot_epoch = state
+        .counters
+        .as_ref()
+        .map(|counters| counters.get(BOOT_EPOCH));
+
+    let mut binding = ResponseBinding::new();
+    binding.add("nonce", nonce);
+    binding.add("identity_key", &identity_key);
+    for version in &protocol_versions {
+        binding.add("protocol_version", &version.to_be_bytes());
+    }
+    for suite in &suites {
+        binding.add("suite", suite.as_bytes());
+    }
+    binding.add("crypto_backend", crypto_backend.as_bytes());
+    binding.add("kss_derivation", &[state.master.is_partitioned() as u8]);
+    if let Some(epoch) = boot_epoch {
+        binding.add("boot_epoch", &epoch.to_be_bytes());
+    }
+    bind_platform_tcb(&mut binding, platform_tcb.as_ref());
+    let build = build_info();
+    bind_build_info(&mut binding, &build);
+
+    let response = InfoResponse {
+        mr_enclave: vec![0u8; 32],
+        mr_signer: vec![0u8; 32],
+        isv_prod_id: 0,
+        isv_svn: 0,
+        protocol_versions,
+        suites,
+        crypto_backend,
+        identity_key,
+        kss: None,
+        td: None,
+        kss_derivation: state.master.is_partitioned(),
+        boot_epoch,
+        platform_tcb,
+        build,
+        provider_quote: Vec::new(),
+    };
+    (response, binding.report_data(INFO_LABEL))
+}
+
+/// The crate version, the commit from `SEALING_PROVIDER_GIT_COMMIT` at build
+/// time (the Makefile sets it) and the enabled features.
+fn build_info() -> BuildInfo {
+    let features = [
+        ("dev-mode", cfg!(feature = "dev-mode")),
+        ("fips", cfg!(feature = "fips")),
+        ("pkcs11", cfg!(feature = "pkcs11")),
+        ("ratls", cfg!(feature = "ratls")),
+        ("otel", cfg!(feature = "otel")),
+        ("cca", cfg!(feature = "cca")),
+    ];
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_commit: option_env!("SEALING_PROVIDER_GIT_COMMIT")
+            .filter(|commit| !commit.is_empty())
+            .map(str::to_string),
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    }
+}
+
+fn bind_build_info(binding: &mut ResponseBinding, build: &BuildInfo) {
+    binding.add("version", build.version.as_bytes());
+    if let Some(commit) = &build.git_commit {
+        binding.add("git_commit", commit.as_bytes());
+    }
+    for feature in &build.features {
+        binding.add("feature", feature.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collateral::CollateralCache;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+    use crate::padding::ResponsePadding;
+    use crate::policy::Policy;
+    use crate::state::Settings;
+    use crate::verifier::DcapVerifier;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    #[test]
+    fn info_describes_the_provider_and_holds_no_secrets() {
+        sodiumoxide::init().unwrap();
+        let sealing_key = [9u8; 16];
+        let master = MasterSecret::from_sealing_key(&sealing_key, None).unwrap();
+        let identity_seed = master
+            .expand(b"gramine-sealing-key-provider/identity/v1")
+            .unwrap();
+        let state = ProviderState::new(
+            MasterSecret::from_sealing_key(&sealing_key, None).unwrap(),
+            Box::new(ProviderIdentity::from_master(&master).unwrap()),
+            Settings {
+                policy: Policy::default(),
+                session_idle_timeout: Duration::from_secs(60),
+                padding: ResponsePadding::default(),
+            },
+            Box::new(DcapVerifier::new(CollateralCache::in_memory())),
+            None,
+            None,
+            None,
+        );
+
+        let (response, report_data) = describe(&[5; 32], &state, None);
+
+        assert_eq!(response.protocol_versions, [PROTOCOL_VERSION]);
+        assert!(!response.suites.is_empty());
+        assert_eq!(response.crypto_backend, backend().name());
+        assert_eq!(response.identity_key, state.identity.public_key());
+        assert_eq!(response.mr_enclave, [0; 32]);
+        assert_eq!(response.mr_signer, [0; 32]);
+        assert_eq!(response.boot_epoch, None);
+        assert!(!response.kss_derivation);
+        assert_eq!(response.build.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(report_data[..32], backend().sha256(&[INFO_LABEL]));
+
+        // Only these fields, and neither the root key nor the identity's
+        // private seed in any of them
+        let json = serde_json::to_value(&response).unwrap();
+        let mut fields: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        fields.sort_unstable();
+        assert_eq!(
+            fields,
+            [
+                "build",
+                "crypto_backend",
+                "identity_key",
+                "isv_prod_id",
+                "isv_svn",
+                "kss_derivation",
+                "mr_enclave",
+                "mr_signer",
+                "protocol_versions",
+                "provider_quote",
+                "suites",
+            ]
+        );
+        let encoded = json.to_string();
+        for secret in [&sealing_key[..], identity_seed.expose()] {
+            let bytes = Value::from(secret.to_vec()).to_string();
+            assert!(!encoded.contains(bytes.trim_matches(|c| c == '[' || c == ']')));
+            assert!(!encoded.contains(&hex::encode(secret)));
+        }
+    }
+}
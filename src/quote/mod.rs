@@ -1,3 +1,19 @@
+mod binding;
+mod explain;
 mod handler;
+mod info;
+mod pck;
+mod selftest;
+mod spiffe;
+mod status;
+mod tcb;
+mod transcript;
 
-pub use handler::process_quotes;
+pub use explain::Trace;
+pub use handler::{attest_quote, check_request, process_quotes, AttestedQuote};
+pub use info::provider_info;
+pub use pck::PckInfo;
+pub use selftest::{check_pipeline, PipelineCheck};
+pub use spiffe::attest_node;
+pub use status::session_status;
+pub use transcript::sign_transcript;
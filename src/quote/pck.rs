@@ -0,0 +1,227 @@
+use crate::error::ProviderError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use dcap_qvl::quote::Quote;
+use x509_cert::der::asn1::{Any, ObjectIdentifier, OctetString};
+use x509_cert::der::{Decode, SliceReader};
+use x509_cert::Certificate;
+
+/// Intel's SGX extensions in PCK certificates. The fields below are arcs
+/// under it.
+const SGX_EXTENSIONS_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113741.1.13.1");
+const PPID_ARC: u32 = 1;
+const FMSPC_ARC: u32 = 4;
+const SGX_TYPE_ARC: u32 = 5;
+const PLATFORM_INSTANCE_ID_ARC: u32 = 6;
+const CONFIGURATION_ARC: u32 = 7;
+
+// SGX Type: Standard (single package), Scalable, Scalable with integrity
+const SGX_TYPE_STANDARD: u8 = 0;
+
+/// Platform identity from the PCK leaf certificate of a quote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PckInfo {
+    pub ppid: Vec<u8>,
+    pub fmspc: Vec<u8>,
+    pub sgx_type: u8,
+    /// Identifies the whole platform on multi-package platforms; absent on
+    /// single-package ones.
+    pub platform_instance_id: Option<Vec<u8>>,
+    /// Whether the certificate has the platform configuration fields, which
+    /// only the Platform CA, the CA for multi-package platforms, includes.
+    pub has_configuration: bool,
+}
+
+impl PckInfo {
+    /// Whether the certificate belongs to a multi-package platform, where
+    /// each package has its own PPID. The SGX type does not tell: a
+    /// single-socket platform can be Scalable too.
+    pub fn is_multi_package(&self) -> bool {
+        self.platform_instance_id.is_some() || self.has_configuration
+    }
+
+    pub fn from_quote(quote: &Quote) -> Result<Self, ProviderError> {
+        let chain = quote
+            .raw_cert_chain()
+            .map_err(|_| pck_error("quote carries no PCK certificate chain"))?;
+        Self::from_cert(&leaf_der(chain)?)
+    }
+
+    fn from_cert(cert: &[u8]) -> Result<Self, ProviderError> {
+        let cert = Certificate::from_der(cert).map_err(|e| der_error(&e))?;
+        let extension = cert
+            .tbs_certificate
+            .extensions
+            .iter()
+            .flatten()
+            .find(|extension| extension.extn_id == SGX_EXTENSIONS_OID)
+            .ok_or_else(|| pck_error("no SGX extensions in PCK certificate"))?;
+
+        // A SEQUENCE of SEQUENCE { OID, value }
+        let fields =
+            Vec::<Any>::from_der(extension.extn_value.as_bytes()).map_err(|e| der_error(&e))?;
+        let mut info = PckInfo {
+            ppid: Vec::new(),
+            fmspc: Vec::new(),
+            sgx_type: SGX_TYPE_STANDARD,
+            platform_instance_id: None,
+            has_configuration: false,
+        };
+        for field in &fields {
+            let (oid, value) = read_field(field).map_err(|e| der_error(&e))?;
+            let Some(arc) = sgx_arc(&oid) else {
+                continue;
+            };
+            let octets = || {
+                value
+                    .decode_as::<OctetString>()
+                    .map(OctetString::into_bytes)
+                    .map_err(|e| der_error(&e))
+            };
+            match arc {
+                PPID_ARC => info.ppid = octets()?,
+                FMSPC_ARC => info.fmspc = octets()?,
+                SGX_TYPE_ARC => info.sgx_type = value.value().first().copied().unwrap_or_default(),
+                PLATFORM_INSTANCE_ID_ARC => info.platform_instance_id = Some(octets()?),
+                CONFIGURATION_ARC => info.has_configuration = true,
+                _ => {}
+            }
+        }
+
+        if info.ppid.is_empty() || info.fmspc.is_empty() {
+            return Err(pck_error("PCK certificate lacks PPID or FMSPC"));
+        }
+        Ok(info)
+    }
+}
+
+/// The OID and value of one SGX extension field.
+fn read_field(field: &Any) -> x509_cert::der::Result<(ObjectIdentifier, Any)> {
+    let mut reader = SliceReader::new(field.value())?;
+    let oid = ObjectIdentifier::decode(&mut reader)?;
+    let value = Any::decode(&mut reader)?;
+    Ok((oid, value))
+}
+
+/// The arc of a field directly under [`SGX_EXTENSIONS_OID`]; the fields of
+/// the TCB and configuration sequences are nested deeper.
+fn sgx_arc(oid: &ObjectIdentifier) -> Option<u32> {
+    let parent = oid.parent()?;
+    if parent != SGX_EXTENSIONS_OID {
+        return None;
+    }
+    oid.arcs().last()
+}
+
+/// DER of the first certificate in a PEM chain.
+fn leaf_der(chain: &[u8]) -> Result<Vec<u8>, ProviderError> {
+    let chain = std::str::from_utf8(chain).map_err(|_| pck_error("PCK chain is not PEM"))?;
+    let body = chain
+        .split("-----BEGIN CERTIFICATE-----")
+        .nth(1)
+        .and_then(|rest| rest.split("-----END CERTIFICATE-----").next())
+        .ok_or_else(|| pck_error("PCK chain is not PEM"))?;
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(STANDARD.decode(body)?)
+}
+
+fn der_error(e: &x509_cert::der::Error) -> ProviderError {
+    pck_error(&format!("invalid DER in PCK certificate: {}", e))
+}
+
+fn pck_error(msg: &str) -> ProviderError {
+    ProviderError::QuoteParseError(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use x509_cert::der::asn1::BitString;
+    use x509_cert::der::{Encode, Tag};
+    use x509_cert::ext::Extension;
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+    use x509_cert::time::Validity;
+    use x509_cert::{TbsCertificate, Version};
+
+    fn field(arc: u32, value: Any) -> Any {
+        let oid = ObjectIdentifier::from_str(&format!("{}.{}", SGX_EXTENSIONS_OID, arc)).unwrap();
+        let mut contents = oid.to_der().unwrap();
+        contents.extend(value.to_der().unwrap());
+        Any::new(Tag::Sequence, contents).unwrap()
+    }
+
+    fn octets(value: &[u8]) -> Any {
+        Any::new(Tag::OctetString, value).unwrap()
+    }
+
+    /// A PCK certificate, unsigned, with `fields` as its SGX extensions.
+    fn certificate(fields: Vec<Any>) -> Vec<u8> {
+        let algorithm = AlgorithmIdentifierOwned {
+            oid: ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2"),
+            parameters: None,
+        };
+        let name = Name::from_str("CN=Intel SGX PCK Certificate").unwrap();
+        let tbs_certificate = TbsCertificate {
+            version: Version::V3,
+            serial_number: SerialNumber::new(&[1]).unwrap(),
+            signature: algorithm.clone(),
+            issuer: name.clone(),
+            validity: Validity::from_now(std::time::Duration::from_secs(3600)).unwrap(),
+            subject: name,
+            subject_public_key_info: SubjectPublicKeyInfoOwned {
+                algorithm: algorithm.clone(),
+                subject_public_key: BitString::from_bytes(&[4; 65]).unwrap(),
+            },
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: Some(vec![Extension {
+                extn_id: SGX_EXTENSIONS_OID,
+                critical: false,
+                extn_value: OctetString::new(fields.to_der().unwrap()).unwrap(),
+            }]),
+        };
+        Certificate {
+            tbs_certificate,
+            signature_algorithm: algorithm,
+            signature: BitString::from_bytes(&[0; 64]).unwrap(),
+        }
+        .to_der()
+        .unwrap()
+    }
+
+    #[test]
+    fn only_platform_fields_mark_multi_package() {
+        let scalable = vec![
+            field(PPID_ARC, octets(&[1; 16])),
+            field(FMSPC_ARC, octets(&[2; 6])),
+            field(SGX_TYPE_ARC, Any::new(Tag::Enumerated, [1]).unwrap()),
+        ];
+        // A single-socket Scalable platform is still one package
+        let info = PckInfo::from_cert(&certificate(scalable.clone())).unwrap();
+        assert_eq!(info.ppid, [1; 16]);
+        assert_eq!(info.fmspc, [2; 6]);
+        assert_eq!(info.sgx_type, 1);
+        assert!(!info.is_multi_package());
+
+        let mut with_instance_id = scalable.clone();
+        with_instance_id.push(field(PLATFORM_INSTANCE_ID_ARC, octets(&[3; 16])));
+        let info = PckInfo::from_cert(&certificate(with_instance_id)).unwrap();
+        assert_eq!(info.platform_instance_id, Some(vec![3; 16]));
+        assert!(info.is_multi_package());
+
+        let mut with_configuration = scalable;
+        with_configuration.push(field(
+            CONFIGURATION_ARC,
+            Any::new(Tag::Sequence, Vec::new()).unwrap(),
+        ));
+        assert!(PckInfo::from_cert(&certificate(with_configuration))
+            .unwrap()
+            .is_multi_package());
+
+        let no_ppid = vec![field(FMSPC_ARC, octets(&[2; 6]))];
+        assert!(PckInfo::from_cert(&certificate(no_ppid)).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use super::binding::ResponseBinding;
+use super::handler::{attest_quote, AttestedQuote};
+use crate::crypto::{backend, constant_time_eq};
+use crate::error::ProviderError;
+use crate::evidence::EvidenceKind;
+use crate::gramine::quote_with_data;
+use crate::protocol::{SpiffeRequest, SpiffeResponse};
+use crate::state::ProviderState;
+use log::info;
+
+/// Precedes the challenge in the first half of the agent's report data.
+const CHALLENGE_LABEL: &[u8] = b"gramine-sealing-key-provider/spiffe-challenge/v1";
+/// Stands in for the encrypted key in the report data of a SPIFFE response.
+const SPIFFE_LABEL: &[u8] = b"gramine-sealing-key-provider/spiffe/v1";
+/// Path of agent IDs under the trust domain, named like SPIRE's own node
+/// attestors name theirs.
+const AGENT_PATH: &str = "spire/agent/gramine_tdx";
+const MEASUREMENT_NAMES: [&str; 5] = ["mrtd", "rtmr0", "rtmr1", "rtmr2", "rtmr3"];
+const MEASUREMENT_LEN: usize = 48;
+const MAX_TRUST_DOMAIN_LEN: usize = 255;
+
+/// Attests a SPIRE agent's TD for a node attestor plugin. The quote must come
+/// from this platform and carry
+/// `SHA-256(CHALLENGE_LABEL || challenge) | SHA-256(svid_public_key)`, so the
+/// SVID key is held in the TD and the attestation is fresh. The agent ID is
+/// named after the key id, so it is stable across reboots of the same TD and
+/// changes with its measurements.
+pub async fn attest_node(
+    request: &SpiffeRequest,
+    state: &ProviderState,
+) -> Result<SpiffeResponse, ProviderError> {
+    check_trust_domain(&request.trust_domain)?;
+    state.nonces.check_and_insert(&request.challenge)?;
+
+    let expected = node_report_data(&request.challenge, &request.svid_public_key);
+    let attested = attest_quote(&request.quote, state, |report_data| {
+        if constant_time_eq(report_data, &expected) {
+            Ok(())
+        } else {
+            Err(ProviderError::PublicKeyError(
+                "Report data does not bind the challenge and SVID key".into(),
+            ))
+        }
+    })
+    .await?;
+    // Selectors and the agent path name TDX measurements
+    if attested.kind != EvidenceKind::Tdx {
+        return Err(ProviderError::PolicyViolation(format!(
+            "SPIFFE node attestation needs a TDX quote, not {} evidence",
+            attested.kind.name()
+        )));
+    }
+
+    let spiffe_id = format!(
+        "spiffe://{}/{}/{}",
+        request.trust_domain,
+        AGENT_PATH,
+        hex::encode(&attested.key_id)
+    );
+    let selectors = selectors(&attested);
+    info!("SPIRE agent attested as {}", spiffe_id);
+
+    let mut binding = ResponseBinding::new();
+    binding.add("challenge", &request.challenge);
+    binding.add("svid_public_key", &request.svid_public_key);
+    binding.add("spiffe_id", spiffe_id.as_bytes());
+    for selector in &selectors {
+        binding.add("selector", selector.as_bytes());
+    }
+    let provider_quote = quote_with_data(binding.report_data(SPIFFE_LABEL)).await?;
+
+    Ok(SpiffeResponse {
+        spiffe_id,
+        selectors,
+        provider_quote,
+    })
+}
+
+fn node_report_data(challenge: &[u8], svid_public_key: &[u8]) -> [u8; 64] {
+    let mut report_data = [0u8; 64];
+    report_data[..32].copy_from_slice(&backend().sha256(&[CHALLENGE_LABEL, challenge]));
+    report_data[32..].copy_from_slice(&backend().sha256(&[svid_public_key]));
+    report_data
+}
+
+fn selectors(attested: &AttestedQuote) -> Vec<String> {
+    let mut selectors: Vec<String> = MEASUREMENT_NAMES
+        .iter()
+        .zip(attested.measurements.chunks(MEASUREMENT_LEN))
+        .map(|(name, value)| format!("{}:{}", name, hex::encode(value)))
+        .collect();
+    selectors.push(format!("key_id:{}", hex::encode(&attested.key_id)));
+    if let Some(tcb) = &attested.quote_tcb {
+        selectors.push(format!("tcb_status:{}", tcb.status));
+    }
+    selectors
+}
+
+/// A SPIFFE trust domain: lowercase letters, digits, `.`, `-` and `_`.
+fn check_trust_domain(trust_domain: &str) -> Result<(), ProviderError> {
+    let valid = !trust_domain.is_empty()
+        && trust_domain.len() <= MAX_TRUST_DOMAIN_LEN
+        && trust_domain
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || b".-_".contains(&c));
+    if !valid {
+        return Err(ProviderError::SerializationError(format!(
+            "Invalid SPIFFE trust domain {:?}",
+            trust_domain
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretBytes;
+
+    #[test]
+    fn selectors_name_each_measurement() {
+        let attested = AttestedQuote {
+            kind: EvidenceKind::Tdx,
+            measurements: (0..5u8).flat_map(|i| [i; MEASUREMENT_LEN]).collect(),
+            derived_key: SecretBytes::new(vec![0; 32]),
+            key_id: vec![0xab; 8],
+            quote_tcb: None,
+            tenant: None,
+            approval: None,
+        };
+
+        let selectors = selectors(&attested);
+
+        assert_eq!(selectors.len(), 6);
+        assert_eq!(selectors[0], format!("mrtd:{}", "00".repeat(48)));
+        assert_eq!(selectors[4], format!("rtmr3:{}", "04".repeat(48)));
+        assert_eq!(selectors[5], "key_id:abababababababab");
+        assert!(check_trust_domain("example.org").is_ok());
+        assert!(check_trust_domain("Example.org/x").is_err());
+    }
+}
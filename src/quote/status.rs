@@ -0,0 +1,49 @@
+use super::binding::ResponseBinding;
+use crate::counters::BOOT_EPOCH;
+use crate::error::ProviderError;
+use crate::gramine::quote_with_data;
+use crate::journal::SessionState;
+use crate::protocol::{SessionStatusRequest, SessionStatusResponse};
+use crate::state::ProviderState;
+use log::info;
+
+/// Stands in for the encrypted key in the report data of a session status
+/// quote.
+const SESSION_STATUS_LABEL: &[u8] = b"gramine-sealing-key-provider/session-status/v1";
+
+/// Tells a client what became of its session, attested so the host cannot
+/// claim a live session has ended or the reverse. Without a session journal
+/// every session is `unknown`.
+pub async fn session_status(
+    request: &SessionStatusRequest,
+    state: &ProviderState,
+) -> Result<SessionStatusResponse, ProviderError> {
+    state.nonces.check_and_insert(&request.nonce)?;
+
+    let session_state = state
+        .journal
+        .as_ref()
+        .map_or(SessionState::Unknown, |journal| {
+            journal.state(&request.session_key)
+        });
+    let boot_epoch = state
+        .counters
+        .as_ref()
+        .map(|counters| counters.get(BOOT_EPOCH));
+    info!("Session status requested: {}", session_state.name());
+
+    let mut binding = ResponseBinding::new();
+    binding.add("nonce", &request.nonce);
+    binding.add("session_key", &request.session_key);
+    binding.add("state", session_state.name().as_bytes());
+    if let Some(epoch) = boot_epoch {
+        binding.add("boot_epoch", &epoch.to_be_bytes());
+    }
+    let provider_quote = quote_with_data(binding.report_data(SESSION_STATUS_LABEL)).await?;
+
+    Ok(SessionStatusResponse {
+        state: session_state.name().to_string(),
+        boot_epoch,
+        provider_quote,
+    })
+}
@@ -0,0 +1,73 @@
+use super::binding::ResponseBinding;
+use crate::error::ProviderError;
+use crate::gramine;
+use crate::protocol::PlatformTcb;
+use crate::state::ProviderState;
+use log::{info, warn};
+
+/// TCB level of the provider's platform, from verifying `own_quote` (see
+/// [`gramine::own_quote`]) with the configured verifier. `None` without SGX
+/// attestation, where there is no quote to verify.
+pub async fn platform_tcb(
+    own_quote: &[u8],
+    state: &ProviderState,
+) -> Result<Option<PlatformTcb>, ProviderError> {
+    if !gramine::is_attested() {
+        return Ok(None);
+    }
+
+    #[cfg(feature = "dev-mode")]
+    {
+        warn!("Skipping platform TCB evaluation in dev mode");
+        return Ok(None);
+    }
+
+    let verified = state.verifier.verify(own_quote).await?;
+
+    info!("Platform TCB status: {}", verified.tcb_status);
+    gramine::note_tcb_status(&verified.tcb_status);
+    if !verified.advisory_ids.is_empty() {
+        warn!(
+            "Platform TCB advisories: {}",
+            verified.advisory_ids.join(", ")
+        );
+    }
+    Ok(Some(PlatformTcb {
+        status: verified.tcb_status,
+        advisory_ids: verified.advisory_ids,
+    }))
+}
+
+/// Adds the TCB status and one `advisory_id` per advisory to the response
+/// metadata.
+pub fn bind_platform_tcb(binding: &mut ResponseBinding, tcb: Option<&PlatformTcb>) {
+    if let Some(tcb) = tcb {
+        binding.add("tcb_status", tcb.status.as_bytes());
+        for advisory in &tcb.advisory_ids {
+            binding.add("advisory_id", advisory.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advisories_change_the_binding() {
+        let tcb = |advisory_ids: Vec<String>| PlatformTcb {
+            status: "SWHardeningNeeded".into(),
+            advisory_ids,
+        };
+        let report_data = |tcb: Option<PlatformTcb>| {
+            let mut binding = ResponseBinding::new();
+            bind_platform_tcb(&mut binding, tcb.as_ref());
+            binding.report_data(b"k")
+        };
+
+        let without = report_data(tcb(vec![]));
+        let with = report_data(tcb(vec!["INTEL-SA-00615".into()]));
+        assert_ne!(without[32..], with[32..]);
+        assert_ne!(without[32..], report_data(None)[32..]);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::crypto::{backend, Signer};
+use crate::error::ProviderError;
+use log::debug;
+
+const TRANSCRIPT_LABEL: &[u8] = b"gramine-sealing-key-provider/transcript/v1";
+
+/// Signs `label || SHA-256(request) || SHA-256(tdx_quote) || SHA-256(provider_quote)`.
+///
+/// `request` is the raw request envelope as received. The provider quote stands
+/// in for the response: its report data already commits to the encrypted key
+/// and every metadata field, so hashing it covers the whole response.
+pub fn sign_transcript(
+    identity: &dyn Signer,
+    request: &[u8],
+    tdx_quote: &[u8],
+    provider_quote: &[u8],
+) -> Result<Vec<u8>, ProviderError> {
+    debug!("Signing exchange transcript");
+    identity.sign(&transcript(request, tdx_quote, provider_quote))
+}
+
+fn transcript(request: &[u8], tdx_quote: &[u8], provider_quote: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(TRANSCRIPT_LABEL.len() + 3 * 32);
+    message.extend_from_slice(TRANSCRIPT_LABEL);
+    message.extend_from_slice(&backend().sha256(&[request]));
+    message.extend_from_slice(&backend().sha256(&[tdx_quote]));
+    message.extend_from_slice(&backend().sha256(&[provider_quote]));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+    use sodiumoxide::crypto::sign::{self, PublicKey, Signature};
+
+    #[test]
+    fn transcripts_verify_under_the_identity_key_and_only_as_signed() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[8; 16], None).unwrap();
+        let identity = ProviderIdentity::from_master(&master).unwrap();
+        let public_key = PublicKey::from_slice(identity.public_key()).unwrap();
+        let verifies = |signature: &[u8], [request, tdx_quote, provider_quote]: [&[u8]; 3]| {
+            let signature = Signature::from_bytes(signature).unwrap();
+            let message = transcript(request, tdx_quote, provider_quote);
+            sign::verify_detached(&signature, &message, &public_key)
+        };
+
+        let fields: [&[u8]; 3] = [b"request", b"td quote", b"provider quote"];
+        let signature = sign_transcript(&identity, fields[0], fields[1], fields[2]).unwrap();
+
+        assert!(verifies(&signature, fields));
+        for changed in 0..fields.len() {
+            let mut other = fields;
+            other[changed] = b"something else";
+            assert!(!verifies(&signature, other));
+        }
+        let mut tampered = signature.clone();
+        tampered[0] ^= 1;
+        assert!(!verifies(&tampered, fields));
+    }
+}
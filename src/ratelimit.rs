@@ -0,0 +1,130 @@
+//! Rate limits per workload identity rather than per connection. A
+//! misbehaving image can run on many VMs, each with its own address, so the
+//! limit follows the verified launch measurement (or the key ID) of the
+//! requests instead. Only verified evidence is counted, so nobody can use
+//! up another image's allowance.
+
+use crate::error::ProviderError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Identities tracked before idle ones are forgotten.
+const MAX_IDENTITIES: usize = 4096;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBy {
+    /// The MRTD, or the launch measurement of other kinds of evidence.
+    #[default]
+    LaunchMeasurement,
+    /// The key ID of the derived key, so one workload of an image shared by
+    /// several is limited on its own.
+    KeyId,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitPolicy {
+    /// Key releases per identity within `window_secs`; 0 is no limit.
+    pub requests: u32,
+    pub window_secs: u64,
+    pub by: RateLimitBy,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        Self {
+            requests: 0,
+            window_secs: 60,
+            by: RateLimitBy::default(),
+        }
+    }
+}
+
+impl RateLimitPolicy {
+    pub fn applies(&self, by: RateLimitBy) -> bool {
+        self.requests > 0 && self.by == by
+    }
+}
+
+/// A token bucket: `requests` tokens, refilled evenly over the window.
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// The buckets of the identities that made requests.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes one request of `identity` at `now` from its allowance under
+    /// `policy`, or refuses it.
+    pub fn take(
+        &self,
+        policy: &RateLimitPolicy,
+        identity: &str,
+        now: Instant,
+    ) -> Result<(), ProviderError> {
+        let capacity = f64::from(policy.requests);
+        let window = Duration::from_secs(policy.window_secs.max(1));
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.at);
+            (bucket.tokens + capacity * elapsed.as_secs_f64() / window.as_secs_f64()).min(capacity)
+        };
+
+        let mut buckets = self.lock();
+        if buckets.len() >= MAX_IDENTITIES && !buckets.contains_key(identity) {
+            buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+        let bucket = buckets.entry(identity.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            at: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.at = now;
+        if bucket.tokens < 1.0 {
+            return Err(ProviderError::RateLimited(format!(
+                "{} exceeded {} requests per {} seconds",
+                identity, policy.requests, policy.window_secs
+            )));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Bucket>> {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_identity_gets_its_own_allowance() {
+        let limiter = RateLimiter::default();
+        let policy = RateLimitPolicy {
+            requests: 2,
+            window_secs: 60,
+            ..RateLimitPolicy::default()
+        };
+        let start = Instant::now();
+
+        assert!(limiter.take(&policy, "a", start).is_ok());
+        assert!(limiter.take(&policy, "a", start).is_ok());
+        assert!(limiter.take(&policy, "a", start).is_err());
+        assert!(limiter.take(&policy, "b", start).is_ok());
+        // One request's worth comes back after half the window
+        let later = start + Duration::from_secs(30);
+        assert!(limiter.take(&policy, "a", later).is_ok());
+        assert!(limiter.take(&policy, "a", later).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! Grace-period re-issuance. A TD that crashes and restarts soon after it
+//! got its key can get it again without another round trip to PCS: with
+//! its key it gets a short-lived, single-use token, encrypted to it like the
+//! key. After the restart it proves it holds the token with an HMAC over its
+//! new quote, and the provider releases the key again if the quote carries
+//! the same measurements and verifies against the collateral it already
+//! caches for the platform.
+//! Grants are kept in memory, so a restart of the provider ends them.
+
+use crate::crypto::{backend, constant_time_eq, SecretBytes};
+use crate::error::ProviderError;
+use serde::{Deserialize, Serialize};
+use sodiumoxide::randombytes::randombytes_into;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+const ID_LABEL: &[u8] = b"gramine-sealing-key-provider/reissue-id/v1";
+const PROOF_LABEL: &[u8] = b"gramine-sealing-key-provider/reissue-proof/v1";
+const TOKEN_LEN: usize = 32;
+/// Beyond this a restart is not what the token is for.
+const MAX_TTL_SECS: u64 = 3600;
+/// Grants held before new ones are refused.
+const MAX_GRANTS: usize = 4096;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReissuePolicy {
+    /// How long a token may be redeemed after it was issued; 0 issues none.
+    pub ttl_secs: u64,
+    /// A key obtained with a token may come with a new token. Without it,
+    /// every other release needs full verification.
+    pub chain: bool,
+}
+
+impl ReissuePolicy {
+    pub fn validate(&self) -> Result<(), ProviderError> {
+        if self.ttl_secs > MAX_TTL_SECS {
+            return Err(ProviderError::ConfigError(format!(
+                "reissue.ttl_secs must be at most {}",
+                MAX_TTL_SECS
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a token lets its holder get again.
+struct Grant {
+    token: SecretBytes,
+    measurements: Vec<u8>,
+    tenant: Option<String>,
+    expires_at: u64,
+}
+
+/// Tokens issued and not yet redeemed or expired, by ID.
+#[derive(Default)]
+pub struct Reissues {
+    grants: Mutex<HashMap<[u8; 32], Grant>>,
+}
+
+impl Reissues {
+    /// Issues a token for the key of `measurements` in `tenant`'s namespace,
+    /// redeemable until `now + ttl_secs`. Returns it with its expiry.
+    pub fn issue(
+        &self,
+        policy: &ReissuePolicy,
+        measurements: &[u8],
+        tenant: Option<&str>,
+        now: u64,
+    ) -> Result<(SecretBytes, u64), ProviderError> {
+        let mut grants = self.lock();
+        grants.retain(|_, grant| grant.expires_at >= now);
+        if grants.len() >= MAX_GRANTS {
+            return Err(ProviderError::RateLimited(format!(
+                "{} reissue tokens are outstanding",
+                grants.len()
+            )));
+        }
+        let mut token = vec![0u8; TOKEN_LEN];
+        randombytes_into(&mut token);
+        let id = token_id(&token);
+        let expires_at = now + policy.ttl_secs;
+        grants.insert(
+            id,
+            Grant {
+                token: SecretBytes::new(token.clone()),
+                measurements: measurements.to_vec(),
+                tenant: tenant.map(str::to_string),
+                expires_at,
+            },
+        );
+        Ok((SecretBytes::new(token), expires_at))
+    }
+
+    /// Redeems the token `id` for `evidence`, whose measurements are
+    /// `measurements`, if `proof` shows the holder knows it. Once the proof
+    /// verifies the token is used up, whatever the outcome; one that does
+    /// not verify leaves it to its holder.
+    pub fn redeem(
+        &self,
+        id: &[u8],
+        proof: &[u8],
+        evidence: &[u8],
+        measurements: &[u8],
+        tenant: Option<&str>,
+        now: u64,
+    ) -> Result<(), ProviderError> {
+        let invalid = |why: &str| ProviderError::PolicyViolation(format!("Reissue token {}", why));
+        let id: [u8; 32] = id.try_into().map_err(|_| invalid("ID is malformed"))?;
+        let grant = {
+            let mut grants = self.lock();
+            let grant = grants.get(&id).ok_or_else(|| invalid("is unknown"))?;
+            if !constant_time_eq(&proof_for(&grant.token, evidence), proof) {
+                return Err(invalid("proof does not verify"));
+            }
+            grants.remove(&id).ok_or_else(|| invalid("is unknown"))?
+        };
+        if grant.expires_at < now {
+            return Err(invalid("has expired"));
+        }
+        if grant.measurements != measurements || grant.tenant.as_deref() != tenant {
+            return Err(invalid("was issued for other measurements"));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<[u8; 32], Grant>> {
+        self.grants
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The ID a token's holder names it by: `SHA-256(ID_LABEL || token)`.
+fn token_id(token: &[u8]) -> [u8; 32] {
+    backend().sha256(&[ID_LABEL, token])
+}
+
+/// What a token's holder sends to redeem it for `evidence`:
+/// `HMAC-SHA256(token, PROOF_LABEL || evidence)`.
+fn proof_for(token: &SecretBytes, evidence: &[u8]) -> [u8; 32] {
+    backend().hmac_sha256(token.expose(), &[PROOF_LABEL, evidence])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_redeem_once_for_the_same_measurements_until_they_expire() {
+        sodiumoxide::init().unwrap();
+        let reissues = Reissues::default();
+        let policy = ReissuePolicy {
+            ttl_secs: 300,
+            chain: false,
+        };
+        let redeem = |token: &SecretBytes, evidence: &[u8], measurements: &[u8], now| {
+            reissues.redeem(
+                &token_id(token.expose()),
+                &proof_for(token, evidence),
+                evidence,
+                measurements,
+                None,
+                now,
+            )
+        };
+
+        let (token, expires_at) = reissues.issue(&policy, b"td", None, 1000).unwrap();
+        assert_eq!(expires_at, 1300);
+        // A wrong proof leaves the token to its holder
+        assert!(reissues
+            .redeem(
+                &token_id(token.expose()),
+                &[0; 32],
+                b"quote",
+                b"td",
+                None,
+                1000
+            )
+            .is_err());
+        assert!(redeem(&token, b"quote", b"td", 1100).is_ok());
+        assert!(redeem(&token, b"quote", b"td", 1100).is_err());
+
+        let (token, _) = reissues.issue(&policy, b"td", None, 1000).unwrap();
+        assert!(redeem(&token, b"quote", b"other td", 1100).is_err());
+        let (token, _) = reissues.issue(&policy, b"td", None, 1000).unwrap();
+        assert!(redeem(&token, b"quote", b"td", 1301).is_err());
+        let (token, _) = reissues
+            .issue(&policy, b"td", Some("tenant"), 1000)
+            .unwrap();
+        assert!(redeem(&token, b"quote", b"td", 1100).is_err());
+    }
+}
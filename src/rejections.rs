@@ -0,0 +1,101 @@
+use crate::error::ProviderError;
+use crate::evidence;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, MutexGuard};
+
+/// Distinct MRTDs counted before the rest are pooled as `other`, so a client
+/// sending arbitrary quotes cannot grow the table without bound.
+const MAX_MRTDS: usize = 256;
+
+static REJECTIONS: Mutex<Rejections> = Mutex::new(Rejections {
+    by_reason: BTreeMap::new(),
+    by_mrtd: BTreeMap::new(),
+});
+
+/// Failed key requests since the provider started, by the kind of error and
+/// by the MRTD of the quote. Many MRTDs failing for one reason points at the
+/// fleet or the platform; one MRTD failing points at that TD.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Rejections {
+    pub by_reason: BTreeMap<&'static str, u64>,
+    pub by_mrtd: BTreeMap<String, u64>,
+}
+
+/// Counts a key request for `quote` that failed with `error`.
+pub fn record(error: &ProviderError, quote: &[u8]) {
+    let mrtd = mrtd(quote);
+    let mut rejections = lock();
+    *rejections.by_reason.entry(error.kind()).or_default() += 1;
+    let key = if rejections.by_mrtd.len() < MAX_MRTDS || rejections.by_mrtd.contains_key(&mrtd) {
+        mrtd
+    } else {
+        "other".to_string()
+    };
+    *rejections.by_mrtd.entry(key).or_default() += 1;
+}
+
+pub fn snapshot() -> Rejections {
+    lock().clone()
+}
+
+/// The counts in the Prometheus text format.
+pub fn render(out: &mut String) {
+    let rejections = snapshot();
+    out.push_str("# HELP sealing_provider_rejections_total Failed key requests by reason\n");
+    out.push_str("# TYPE sealing_provider_rejections_total counter\n");
+    for (reason, count) in &rejections.by_reason {
+        let _ = writeln!(
+            out,
+            "sealing_provider_rejections_total{{reason=\"{}\"}} {}",
+            reason, count
+        );
+    }
+    out.push_str(
+        "# HELP sealing_provider_rejections_by_mrtd_total Failed key requests by TD MRTD\n",
+    );
+    out.push_str("# TYPE sealing_provider_rejections_by_mrtd_total counter\n");
+    for (mrtd, count) in &rejections.by_mrtd {
+        let _ = writeln!(
+            out,
+            "sealing_provider_rejections_by_mrtd_total{{mrtd=\"{}\"}} {}",
+            mrtd, count
+        );
+    }
+}
+
+/// MRTD of a TD quote or launch measurement of an SNP report, hex, or
+/// `unparsed` for anything else.
+pub fn mrtd(quote: &[u8]) -> String {
+    match evidence::parse(quote) {
+        Ok(evidence) => hex::encode(evidence.launch_measurement()),
+        Err(_) => "unparsed".to_string(),
+    }
+}
+
+fn lock() -> MutexGuard<'static, Rejections> {
+    REJECTIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejections_are_grouped_by_cause() {
+        let wrapped = ProviderError::DcapError(Box::new(ProviderError::PPIDMismatch));
+        record(&wrapped, b"not a quote");
+        record(&ProviderError::PPIDMismatch, b"not a quote");
+
+        let rejections = snapshot();
+        let mut out = String::new();
+        render(&mut out);
+
+        assert_eq!(rejections.by_reason["ppid_mismatch"], 2);
+        assert_eq!(rejections.by_mrtd["unparsed"], 2);
+        assert!(out.contains("sealing_provider_rejections_total{reason=\"ppid_mismatch\"} 2\n"));
+    }
+}
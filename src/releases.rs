@@ -0,0 +1,191 @@
+//! Release counts. Every workload identity normally gets its key about once
+//! per boot, so one that gets it far more often, or far more often for one
+//! purpose, is a strong sign of compromise. The provider counts releases per
+//! identity and per identity and context in the sealed counter store, and
+//! refuses or alerts past the policy's limits. An identity's key is counted
+//! for at most [`MAX_CONTEXTS`] contexts, so it cannot grow the store
+//! without bound.
+//!
+//! The counts are only as durable as the store: a host that puts back an
+//! older copy of its file and restarts the provider sets them back to what
+//! that copy holds, so a maximum bounds releases between rollbacks rather
+//! than over the identity's lifetime. The audit log still records every
+//! release.
+
+use crate::counters::{CounterStore, MAX_NAME_LEN};
+use crate::error::ProviderError;
+use crate::ratelimit::RateLimitBy;
+use crate::webhooks::{self, Event};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+const PREFIX: &str = "release";
+/// Counts the contexts each identity has had its key for.
+const CONTEXTS_PREFIX: &str = "release-contexts";
+/// Longest context a request may name, so counter names stay short.
+pub const MAX_CONTEXT_LEN: usize = 64;
+/// Contexts an identity has its key counted for before it is refused the
+/// key for a new one.
+pub const MAX_CONTEXTS: u64 = 64;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReleaseLimitPolicy {
+    /// The identity counted, as for `rate_limit`.
+    pub by: RateLimitBy,
+    /// Releases per identity and context before further ones are refused;
+    /// 0 is no limit.
+    pub max_per_context: u64,
+    /// Releases per identity over all contexts; 0 is no limit.
+    pub max_total: u64,
+    /// Release count of an identity and context that raises the
+    /// `release_threshold` webhook; 0 is no alert.
+    pub alert_after: u64,
+}
+
+impl ReleaseLimitPolicy {
+    pub fn is_active(&self) -> bool {
+        self.max_per_context > 0 || self.max_total > 0 || self.alert_after > 0
+    }
+}
+
+/// Counts a release to `identity` for `context`, unless it would exceed
+/// a limit, and returns how often the identity has now had its key for
+/// the context.
+pub async fn count(
+    counters: &CounterStore,
+    policy: &ReleaseLimitPolicy,
+    identity: &str,
+    context: Option<&str>,
+) -> Result<u64, ProviderError> {
+    let context = context.unwrap_or("-");
+    check_context(context)?;
+    let total = format!("{}/{}", PREFIX, identity);
+    let per_context = format!("{}/{}", total, context);
+    let contexts = format!("{}/{}", CONTEXTS_PREFIX, identity);
+    if let Some(name) = [&per_context, &contexts]
+        .into_iter()
+        .find(|name| name.len() > MAX_NAME_LEN)
+    {
+        return Err(ProviderError::ConfigError(format!(
+            "Release counter {} has too long a name",
+            name
+        )));
+    }
+
+    let mut limits = vec![
+        (total.clone(), policy.max_total),
+        (per_context.clone(), policy.max_per_context),
+    ];
+    // A context seen for the first time is counted against the identity's
+    // contexts too. Two first releases racing for one context count it twice,
+    // which only errs towards the limit.
+    let new_context = counters.get(&per_context) == 0;
+    if new_context {
+        limits.push((contexts.clone(), MAX_CONTEXTS));
+    }
+    let Some(values) = counters.increment_within(limits).await? else {
+        if new_context && counters.get(&contexts) >= MAX_CONTEXTS {
+            return Err(ProviderError::PolicyViolation(format!(
+                "{} has had its key for the most contexts allowed ({})",
+                identity, MAX_CONTEXTS
+            )));
+        }
+        return Err(ProviderError::PolicyViolation(format!(
+            "{} has had its key the most times allowed ({} for context {}, {} in all)",
+            identity,
+            counters.get(&per_context),
+            context,
+            counters.get(&total)
+        )));
+    };
+    let count = values[1];
+    if policy.alert_after > 0 && count == policy.alert_after {
+        warn!(
+            "{} has had its key {} times for context {}",
+            identity, count, context
+        );
+        webhooks::notify(Event::ReleaseThreshold {
+            identity: identity.to_string(),
+            context: context.to_string(),
+            count,
+        });
+    }
+    Ok(count)
+}
+
+/// Contexts are counter names, so they are kept short and printable.
+pub fn check_context(context: &str) -> Result<(), ProviderError> {
+    let printable = context
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | ':' | '/'));
+    if context.is_empty() || context.len() > MAX_CONTEXT_LEN || !printable {
+        return Err(ProviderError::PolicyViolation(format!(
+            "Context {:?} must be 1 to {} letters, digits, or . _ - : /",
+            context, MAX_CONTEXT_LEN
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::MasterSecret;
+    use std::fs;
+
+    #[tokio::test]
+    async fn releases_past_a_maximum_are_refused_and_not_counted() {
+        let path = std::env::temp_dir().join(format!("releases-{}.json", std::process::id()));
+        let master = MasterSecret::from_sealing_key(&[3; 16], None).unwrap();
+        let counters = CounterStore::open(&path, &master).unwrap();
+        let policy = ReleaseLimitPolicy {
+            max_per_context: 2,
+            max_total: 3,
+            ..ReleaseLimitPolicy::default()
+        };
+
+        assert_eq!(
+            count(&counters, &policy, "td", Some("disk")).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            count(&counters, &policy, "td", Some("disk")).await.unwrap(),
+            2
+        );
+        assert!(count(&counters, &policy, "td", Some("disk")).await.is_err());
+        assert_eq!(count(&counters, &policy, "td", None).await.unwrap(), 1);
+        assert!(count(&counters, &policy, "td", Some("db")).await.is_err());
+        assert_eq!(counters.get("release/td"), 3);
+        assert!(count(&counters, &policy, "other", Some("a b"))
+            .await
+            .is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_contexts_past_the_cap_are_refused() {
+        let path = std::env::temp_dir().join(format!("contexts-{}.json", std::process::id()));
+        let master = MasterSecret::from_sealing_key(&[3; 16], None).unwrap();
+        let counters = CounterStore::open(&path, &master).unwrap();
+        let policy = ReleaseLimitPolicy {
+            alert_after: 100,
+            ..ReleaseLimitPolicy::default()
+        };
+
+        for context in 0..MAX_CONTEXTS {
+            let context = format!("c{}", context);
+            count(&counters, &policy, "td", Some(&context))
+                .await
+                .unwrap();
+        }
+        let new = count(&counters, &policy, "td", Some("one-more")).await;
+        let known = count(&counters, &policy, "td", Some("c0")).await;
+        let other = count(&counters, &policy, "other", Some("one-more")).await;
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(new, Err(ProviderError::PolicyViolation(_))));
+        assert_eq!(known.unwrap(), 2);
+        assert_eq!(other.unwrap(), 1);
+    }
+}
@@ -0,0 +1,87 @@
+use crate::config::Config;
+use crate::error::ProviderError;
+use crate::state::{ProviderState, Settings};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Reloads the configuration on every SIGHUP and applies its reloadable
+/// parts: the policy, the session idle timeout and response padding. A remote
+/// policy is fetched again. A configuration or policy that fails to load is
+/// logged and the running settings are kept.
+pub fn spawn_on_sighup(
+    state: Arc<ProviderState>,
+    config_path: Option<PathBuf>,
+    running: Config,
+) -> Result<(), ProviderError> {
+    let mut hangups = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            match reload(&state, config_path.as_deref(), &running).await {
+                Ok(()) => info!("Configuration reloaded"),
+                Err(e) => error!("Reload failed, keeping the running settings: {}", e.chain()),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn reload(
+    state: &ProviderState,
+    config_path: Option<&Path>,
+    running: &Config,
+) -> Result<(), ProviderError> {
+    let config = Config::load(config_path)?;
+    let mut settings = Settings::from_config(&config)?;
+    let mut remote_version = None;
+    if let Some(remote) = &state.remote_policy {
+        let (version, policy) = remote.fetch().await?;
+        settings.policy = policy;
+        remote_version = Some(version);
+    }
+
+    if fixed_part(&config) != fixed_part(running) {
+        warn!(
+            "Only files.policy, server.session_idle_timeout_secs, server.min_response_ms and \
+             server.pad_response_sizes are reloaded; restart the provider to apply the other \
+             changes"
+        );
+    }
+    if let (Some(remote), Some(version)) = (&state.remote_policy, remote_version) {
+        remote
+            .mark_applied(version, state.counters.as_ref())
+            .await?;
+    }
+    state.replace_settings(settings);
+    Ok(())
+}
+
+/// `config` without the settings a reload applies.
+fn fixed_part(config: &Config) -> Config {
+    let mut fixed = config.clone();
+    fixed.files.policy = None;
+    fixed.server.session_idle_timeout_secs = 0;
+    fixed.server.min_response_ms = 0;
+    fixed.server.pad_response_sizes = false;
+    fixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloadable_settings_are_not_fixed() {
+        let running = Config::default();
+        let mut reloaded = running.clone();
+        reloaded.files.policy = Some(PathBuf::from("/policy.json"));
+        reloaded.server.session_idle_timeout_secs = 60;
+        reloaded.server.min_response_ms = 250;
+        assert_eq!(fixed_part(&reloaded), fixed_part(&running));
+
+        reloaded.server.addr = "127.0.0.1:1".into();
+        assert_ne!(fixed_part(&reloaded), fixed_part(&running));
+    }
+}
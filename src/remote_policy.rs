@@ -0,0 +1,252 @@
+//! Policy pulled from a URL, so a fleet of providers stays in sync without
+//! pushing files to each host. The URL serves a bundle the operator signed
+//! with a P-256 key whose public half is in the configuration; the host
+//! carries the bundle but cannot forge one, and cannot roll the fleet back
+//! to an older one once a newer one was applied. With a counter store the
+//! highest applied version outlives restarts too, but a host that puts back
+//! an older copy of the store's file, along with a restart, lowers it to
+//! what that copy holds; `min_version` still holds.
+
+use crate::config::RemotePolicyConfig;
+use crate::counters::{CounterStore, POLICY_VERSION};
+use crate::error::ProviderError;
+use crate::policy::Policy;
+use crate::state::{ProviderState, Settings};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{error, info, warn};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const BUNDLE_LABEL: &[u8] = b"gramine-sealing-key-provider/policy-bundle/v1";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the URL serves. `signature` is ECDSA P-256 with SHA-256, raw or
+/// DER, over `BUNDLE_LABEL || version (8 bytes, big endian) || policy`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Bundle {
+    version: u64,
+    /// The policy document, base64.
+    policy: String,
+    signature: String,
+}
+
+pub struct RemotePolicy {
+    url: String,
+    key: VerifyingKey,
+    refresh: Duration,
+    /// The version last applied, or `min_version` before that, whichever is
+    /// higher.
+    applied: AtomicU64,
+    client: reqwest::Client,
+}
+
+impl RemotePolicy {
+    /// Starts from the version recorded in `counters`, if it is above
+    /// `min_version`.
+    pub fn from_config(
+        config: &RemotePolicyConfig,
+        counters: Option<&CounterStore>,
+    ) -> Result<Option<Self>, ProviderError> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+        // Config::validate has checked that it is set
+        let key_path = config.signing_key.as_deref().unwrap_or(Path::new(""));
+        let pem = fs::read_to_string(key_path).map_err(|e| {
+            ProviderError::ConfigError(format!(
+                "Cannot read policy signing key {}: {}",
+                key_path.display(),
+                e
+            ))
+        })?;
+        let key = VerifyingKey::from_public_key_pem(&pem).map_err(|_| {
+            ProviderError::ConfigError(format!(
+                "Policy signing key {} is not a P-256 public key",
+                key_path.display()
+            ))
+        })?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| ProviderError::NetworkError(e.to_string()))?;
+        let applied = match counters {
+            Some(counters) => config.min_version.max(counters.get(POLICY_VERSION)),
+            None => {
+                warn!("No counter store: a restart lowers the policy bundle version floor");
+                config.min_version
+            }
+        };
+        Ok(Some(Self {
+            url: url.clone(),
+            key,
+            refresh: Duration::from_secs(config.refresh_secs),
+            applied: AtomicU64::new(applied),
+            client,
+        }))
+    }
+
+    /// Fetches and opens the bundle. Its policy is not applied until
+    /// [`RemotePolicy::mark_applied`] is told.
+    pub async fn fetch(&self) -> Result<(u64, Policy), ProviderError> {
+        info!("Fetching policy from {}", self.url);
+        let network_error = |e: reqwest::Error| {
+            ProviderError::NetworkError(format!("Policy bundle {}: {}", self.url, e))
+        };
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(network_error)?
+            .bytes()
+            .await
+            .map_err(network_error)?;
+        self.open(&body)
+    }
+
+    /// Checks the signature and version of a bundle and reads its policy.
+    /// Relative paths in it are taken from the working directory.
+    fn open(&self, body: &[u8]) -> Result<(u64, Policy), ProviderError> {
+        let bundle: Bundle = serde_json::from_slice(body)?;
+        let invalid = |what: &str| ProviderError::ConfigError(format!("Policy bundle {}", what));
+        let policy = STANDARD
+            .decode(&bundle.policy)
+            .map_err(|_| invalid("policy is not base64"))?;
+        let signature = STANDARD
+            .decode(&bundle.signature)
+            .map_err(|_| invalid("signature is not base64"))?;
+        let signature = Signature::from_slice(&signature)
+            .or_else(|_| Signature::from_der(&signature))
+            .map_err(|_| invalid("signature is malformed"))?;
+        let message = [BUNDLE_LABEL, &bundle.version.to_be_bytes()[..], &policy[..]].concat();
+        self.key
+            .verify(&message, &signature)
+            .map_err(|_| invalid("signature does not verify"))?;
+
+        let applied = self.applied.load(Ordering::Acquire);
+        if bundle.version < applied {
+            return Err(invalid(&format!(
+                "version {} is older than version {}",
+                bundle.version, applied
+            )));
+        }
+        let policy = Policy::parse(&policy, Path::new("."))?;
+        Ok((bundle.version, policy))
+    }
+
+    /// Records that the policy of bundle `version` is about to be put in
+    /// force, so older bundles are refused from now on. With `counters` the
+    /// version is durable before this returns; if it cannot be stored,
+    /// nothing changes and the policy must not be applied.
+    pub async fn mark_applied(
+        &self,
+        version: u64,
+        counters: Option<&CounterStore>,
+    ) -> Result<(), ProviderError> {
+        if let Some(counters) = counters {
+            counters.raise(POLICY_VERSION, version).await?;
+        }
+        self.applied.fetch_max(version, Ordering::AcqRel);
+        info!("Applied policy bundle version {}", version);
+        Ok(())
+    }
+
+    fn is_applied(&self, version: u64) -> bool {
+        version <= self.applied.load(Ordering::Acquire)
+    }
+}
+
+/// Fetches the bundle every `refresh_secs` and applies a newer one. A bundle
+/// that fails to fetch or verify is logged and the running policy is kept.
+pub fn spawn_refresh(state: Arc<ProviderState>) {
+    let Some(remote) = &state.remote_policy else {
+        return;
+    };
+    if remote.refresh.is_zero() {
+        return;
+    }
+    let refresh = remote.refresh;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(refresh).await;
+            let Some(remote) = &state.remote_policy else {
+                return;
+            };
+            match remote.fetch().await {
+                Ok((version, _)) if remote.is_applied(version) => {}
+                Ok((version, policy)) => {
+                    if let Err(e) = remote.mark_applied(version, state.counters.as_ref()).await {
+                        error!(
+                            "Cannot record policy bundle version {}, keeping the running \
+                             policy: {}",
+                            version,
+                            e.chain()
+                        );
+                        continue;
+                    }
+                    let running = state.settings();
+                    state.replace_settings(Settings {
+                        policy,
+                        session_idle_timeout: running.session_idle_timeout,
+                        padding: running.padding,
+                    });
+                }
+                Err(e) => error!(
+                    "Policy refresh failed, keeping the running policy: {}",
+                    e.chain()
+                ),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+
+    fn bundle(key: &SigningKey, version: u64, policy: &[u8]) -> Vec<u8> {
+        let message = [BUNDLE_LABEL, &version.to_be_bytes()[..], policy].concat();
+        let signature: Signature = key.sign(&message);
+        serde_json::to_vec(&serde_json::json!({
+            "version": version,
+            "policy": STANDARD.encode(policy),
+            "signature": STANDARD.encode(signature.to_bytes()),
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_signed_bundles_no_older_than_the_applied_one_open() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let remote = RemotePolicy {
+            url: "https://policy.example".into(),
+            key: VerifyingKey::from(&key),
+            refresh: Duration::ZERO,
+            applied: AtomicU64::new(0),
+            client: reqwest::Client::new(),
+        };
+        let policy = br#"{"allowed_extra_recipients": []}"#;
+
+        let (version, _) = remote.open(&bundle(&key, 3, policy)).unwrap();
+        remote.mark_applied(version, None).await.unwrap();
+        assert!(remote.open(&bundle(&key, 3, policy)).is_ok());
+        assert!(remote.open(&bundle(&key, 2, policy)).is_err());
+
+        let mut forged: serde_json::Value =
+            serde_json::from_slice(&bundle(&key, 4, policy)).unwrap();
+        forged["policy"] = STANDARD.encode(br#"{"tenants": []}"#).into();
+        assert!(remote.open(&serde_json::to_vec(&forged).unwrap()).is_err());
+    }
+}
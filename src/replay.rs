@@ -0,0 +1,153 @@
+use crate::error::ProviderError;
+use log::{debug, warn};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub const MIN_NONCE_LEN: usize = 16;
+pub const MAX_NONCE_LEN: usize = 64;
+
+const NONCE_WINDOW: Duration = Duration::from_secs(10 * 60);
+const NONCE_CAPACITY: usize = 65536;
+
+/// Remembers request nonces seen within the replay window.
+pub struct NonceCache {
+    inner: Mutex<NonceCacheInner>,
+    window: Duration,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct NonceCacheInner {
+    seen: HashSet<Vec<u8>>,
+    order: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl NonceCache {
+    pub fn new() -> Self {
+        Self::with_limits(NONCE_WINDOW, NONCE_CAPACITY)
+    }
+
+    pub fn with_limits(window: Duration, capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(NonceCacheInner::default()),
+            window,
+            capacity,
+        }
+    }
+
+    /// Nonces remembered, including expired ones not yet evicted.
+    pub fn remembered(&self) -> usize {
+        self.lock().order.len()
+    }
+
+    /// Validates the nonce and fails if it was already used within the
+    /// window, without recording it.
+    pub fn check(&self, nonce: &[u8]) -> Result<(), ProviderError> {
+        check_len(nonce)?;
+        self.check_unused(&mut self.lock(), nonce, Instant::now())
+    }
+
+    /// Validates the nonce and records it, failing if it was already used
+    /// within the window.
+    pub fn check_and_insert(&self, nonce: &[u8]) -> Result<(), ProviderError> {
+        check_len(nonce)?;
+        let mut inner = self.lock();
+        let now = Instant::now();
+        self.check_unused(&mut inner, nonce, now)?;
+
+        if inner.order.len() >= self.capacity {
+            warn!("Nonce cache full; evicting oldest entry before its window expired");
+            if let Some((_, evicted)) = inner.order.pop_front() {
+                inner.seen.remove(&evicted);
+            }
+        }
+
+        inner.seen.insert(nonce.to_vec());
+        inner.order.push_back((now, nonce.to_vec()));
+        debug!("Recorded request nonce; {} in window", inner.order.len());
+        Ok(())
+    }
+
+    /// Forgets nonces whose window has passed, then fails if `nonce` is
+    /// still remembered.
+    fn check_unused(
+        &self,
+        inner: &mut NonceCacheInner,
+        nonce: &[u8],
+        now: Instant,
+    ) -> Result<(), ProviderError> {
+        while let Some((seen_at, _)) = inner.order.front() {
+            if now.duration_since(*seen_at) < self.window {
+                break;
+            }
+            if let Some((_, expired)) = inner.order.pop_front() {
+                inner.seen.remove(&expired);
+            }
+        }
+
+        if inner.seen.contains(nonce) {
+            warn!("Replayed request nonce {}", hex::encode(nonce));
+            return Err(ProviderError::InvalidNonce("nonce already used".into()));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NonceCacheInner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn check_len(nonce: &[u8]) -> Result<(), ProviderError> {
+    if nonce.len() < MIN_NONCE_LEN || nonce.len() > MAX_NONCE_LEN {
+        return Err(ProviderError::InvalidNonce(format!(
+            "nonce must be {}..={} bytes, got {}",
+            MIN_NONCE_LEN,
+            MAX_NONCE_LEN,
+            nonce.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_reused_nonce() {
+        let cache = NonceCache::new();
+        assert!(cache.check_and_insert(&[1u8; 16]).is_ok());
+        assert!(matches!(
+            cache.check_and_insert(&[1u8; 16]),
+            Err(ProviderError::InvalidNonce(_))
+        ));
+        assert!(cache.check_and_insert(&[2u8; 16]).is_ok());
+    }
+
+    #[test]
+    fn checking_does_not_record() {
+        let cache = NonceCache::new();
+        assert!(cache.check(&[4u8; 16]).is_ok());
+        assert!(cache.check(&[4u8; 16]).is_ok());
+        assert!(cache.check_and_insert(&[4u8; 16]).is_ok());
+        assert!(cache.check(&[4u8; 16]).is_err());
+        assert!(cache.check(&[0u8; MIN_NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_lengths() {
+        let cache = NonceCache::new();
+        assert!(cache.check_and_insert(&[0u8; MIN_NONCE_LEN - 1]).is_err());
+        assert!(cache.check_and_insert(&[0u8; MAX_NONCE_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn expired_nonces_can_be_reused() {
+        let cache = NonceCache::with_limits(Duration::ZERO, 16);
+        assert!(cache.check_and_insert(&[3u8; 16]).is_ok());
+        assert!(cache.check_and_insert(&[3u8; 16]).is_ok());
+    }
+}
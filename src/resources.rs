@@ -0,0 +1,103 @@
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+
+/// The provider's own resource use, as seen from inside the enclave. The
+/// host sees the enclave as one fixed-size allocation, so it cannot tell
+/// when the enclave heap is close to running out and Gramine aborts.
+/// Each field is `None` where the platform does not report it.
+#[derive(Debug, Default, Serialize)]
+pub struct Resources {
+    /// Memory available to the provider: the enclave size under Gramine,
+    /// the machine's memory outside it.
+    pub memory_total_bytes: Option<u64>,
+    pub memory_free_bytes: Option<u64>,
+    pub open_fds: Option<u64>,
+    /// Tokio tasks not yet finished, one or more per open connection.
+    pub alive_tasks: Option<u64>,
+    pub worker_threads: Option<u64>,
+}
+
+pub fn sample() -> Resources {
+    // Gramine answers /proc/meminfo from its own enclave memory accounting
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let runtime = tokio::runtime::Handle::try_current().ok();
+    Resources {
+        memory_total_bytes: meminfo_bytes(&meminfo, "MemTotal"),
+        memory_free_bytes: meminfo_bytes(&meminfo, "MemAvailable")
+            .or_else(|| meminfo_bytes(&meminfo, "MemFree")),
+        open_fds: fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|entries| entries.count() as u64),
+        alive_tasks: runtime
+            .as_ref()
+            .map(|runtime| runtime.metrics().num_alive_tasks() as u64),
+        worker_threads: runtime
+            .as_ref()
+            .map(|runtime| runtime.metrics().num_workers() as u64),
+    }
+}
+
+/// `field` of `/proc/meminfo`, which is given in kB.
+fn meminfo_bytes(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kb = value
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kb * 1024)
+    })
+}
+
+/// The current values as Prometheus gauges.
+pub fn render(out: &mut String) {
+    let resources = sample();
+    let gauges = [
+        (
+            "memory_total_bytes",
+            "Memory available to the provider",
+            resources.memory_total_bytes,
+        ),
+        (
+            "memory_free_bytes",
+            "Memory still free for the provider",
+            resources.memory_free_bytes,
+        ),
+        ("open_fds", "Open file descriptors", resources.open_fds),
+        (
+            "alive_tasks",
+            "Unfinished async tasks",
+            resources.alive_tasks,
+        ),
+        (
+            "worker_threads",
+            "Async runtime worker threads",
+            resources.worker_threads,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        let Some(value) = value else {
+            continue;
+        };
+        let _ = writeln!(out, "# HELP sealing_provider_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE sealing_provider_{} gauge", name);
+        let _ = writeln!(out, "sealing_provider_{} {}", name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meminfo_fields_are_read_in_bytes() {
+        let meminfo = "MemTotal:        1048576 kB\nMemFree:          524288 kB\n";
+
+        assert_eq!(meminfo_bytes(meminfo, "MemTotal"), Some(1 << 30));
+        assert_eq!(meminfo_bytes(meminfo, "MemFree"), Some(1 << 29));
+        assert_eq!(meminfo_bytes(meminfo, "MemAvailable"), None);
+    }
+}
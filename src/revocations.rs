@@ -0,0 +1,160 @@
+//! The revocation list: TDs that get no key, whatever the policy says. It
+//! names them by launch measurement (a compromised image), by the key ID of
+//! their key (one workload's key), or by the public key in their report data
+//! (one running instance). The file is read again whenever it changes, so an
+//! entry takes effect within seconds.
+
+use crate::error::ProviderError;
+use crate::evidence::Evidence;
+use crate::watched::Watched;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RevocationFile {
+    /// Hex MRTDs, or launch measurements of other kinds of evidence.
+    launch_measurements: Vec<String>,
+    /// Hex key IDs, as in key responses and the audit log.
+    key_ids: Vec<String>,
+    /// Hex X25519 public keys, the first 32 bytes of the report data.
+    public_keys: Vec<String>,
+}
+
+/// The entries of a revocation file, lowercased.
+#[derive(Debug, Default)]
+struct Revoked {
+    launch_measurements: HashSet<String>,
+    key_ids: HashSet<String>,
+    public_keys: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub struct RevocationList {
+    revoked: Watched<Revoked>,
+}
+
+impl RevocationList {
+    pub fn load(path: &Path) -> Result<Self, ProviderError> {
+        Ok(Self {
+            revoked: Watched::load(path, read)?,
+        })
+    }
+
+    /// Refuses evidence whose launch measurement or report data public key
+    /// is revoked. Runs before the key is derived.
+    pub fn check_evidence(&self, evidence: &dyn Evidence) -> Result<(), ProviderError> {
+        let revoked = self.revoked.get();
+        let launch = hex::encode(evidence.launch_measurement());
+        if revoked.launch_measurements.contains(&launch) {
+            return Err(revoked_error("Launch measurement", &launch));
+        }
+        let report_data = evidence.report_data();
+        let public_key = hex::encode(&report_data[..report_data.len().min(32)]);
+        if revoked.public_keys.contains(&public_key) {
+            return Err(revoked_error("Public key", &public_key));
+        }
+        Ok(())
+    }
+
+    /// Refuses a key whose ID is revoked, before it is released.
+    pub fn check_key_id(&self, key_id: &[u8]) -> Result<(), ProviderError> {
+        let key_id = hex::encode(key_id);
+        if self.revoked.get().key_ids.contains(&key_id) {
+            return Err(revoked_error("Key ID", &key_id));
+        }
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        let revoked = self.revoked.get();
+        revoked.launch_measurements.len() + revoked.key_ids.len() + revoked.public_keys.len()
+    }
+}
+
+fn revoked_error(what: &str, value: &str) -> ProviderError {
+    ProviderError::PolicyViolation(format!("{} {} is revoked", what, value))
+}
+
+fn read(path: &Path) -> Result<Revoked, ProviderError> {
+    let file: RevocationFile = serde_json::from_slice(&fs::read(path)?)?;
+    let set = |name: &str, entries: Vec<String>| {
+        entries
+            .into_iter()
+            .map(|entry| match hex::decode(&entry) {
+                Ok(bytes) if !bytes.is_empty() => Ok(entry.to_ascii_lowercase()),
+                _ => Err(ProviderError::ConfigError(format!(
+                    "Revoked {} {:?} is not hex",
+                    name, entry
+                ))),
+            })
+            .collect::<Result<HashSet<_>, _>>()
+    };
+    Ok(Revoked {
+        launch_measurements: set("launch measurement", file.launch_measurements)?,
+        key_ids: set("key ID", file.key_ids)?,
+        public_keys: set("public key", file.public_keys)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evidence::EvidenceKind;
+
+    struct Td {
+        mrtd: [u8; 48],
+        report_data: [u8; 64],
+    }
+
+    impl Evidence for Td {
+        fn kind(&self) -> EvidenceKind {
+            EvidenceKind::Tdx
+        }
+        fn measurements(&self) -> &[u8] {
+            &self.mrtd
+        }
+        fn report_data(&self) -> &[u8] {
+            &self.report_data
+        }
+        fn platform_id(&self) -> &[u8] {
+            &[]
+        }
+        fn launch_measurement(&self) -> &[u8] {
+            &self.mrtd
+        }
+    }
+
+    #[test]
+    fn revoked_images_instances_and_keys_are_refused() {
+        let path = std::env::temp_dir().join(format!("revocations-{}.json", std::process::id()));
+        let revoke = |mrtd: u8, public_key: u8| {
+            let file = serde_json::json!({
+                "launch_measurements": [hex::encode([mrtd; 48]).to_uppercase()],
+                "key_ids": [hex::encode([0xab; 16])],
+                "public_keys": [hex::encode([public_key; 32])],
+            });
+            fs::write(&path, file.to_string()).unwrap();
+        };
+        revoke(1, 2);
+        let revocations = RevocationList::load(&path).unwrap();
+        let td = |mrtd: u8, public_key: u8| Td {
+            mrtd: [mrtd; 48],
+            report_data: [[public_key; 32], [0; 32]].concat().try_into().unwrap(),
+        };
+
+        assert!(revocations.check_evidence(&td(1, 3)).is_err());
+        assert!(revocations.check_evidence(&td(3, 2)).is_err());
+        assert!(revocations.check_evidence(&td(3, 3)).is_ok());
+        assert!(revocations.check_key_id(&[0xab; 16]).is_err());
+        assert!(revocations.check_key_id(&[0xcd; 16]).is_ok());
+
+        revoke(3, 2);
+        revocations.revoked.expire();
+        assert!(revocations.check_evidence(&td(1, 3)).is_ok());
+        assert!(revocations.check_evidence(&td(3, 3)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
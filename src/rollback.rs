@@ -0,0 +1,71 @@
+use crate::error::ProviderError;
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+const MARKER_PREFIX: &str = "svn-";
+
+/// Refuses to run a provider build older than the newest one that ran before.
+///
+/// Every build leaves an `svn-<ISV_SVN>` marker in `dir` and refuses to start
+/// if a marker with a higher SVN exists. Only file names are compared:
+/// Gramine's MRSIGNER key changes with the ISV_SVN, so builds cannot read each
+/// other's encrypted files, but they can all list the directory. The host can
+/// still delete markers, so this stops accidental downgrades and raises the
+/// bar for deliberate ones; it is not a hardware rollback guarantee.
+pub fn check_provider_svn(dir: &Path, current: u16) -> Result<(), ProviderError> {
+    fs::create_dir_all(dir)?;
+
+    let mut highest = None;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some(svn) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(MARKER_PREFIX))
+            .and_then(|svn| svn.parse::<u16>().ok())
+        else {
+            continue;
+        };
+        highest = highest.max(Some(svn));
+    }
+
+    match highest {
+        Some(highest) if current < highest => {
+            return Err(ProviderError::ConfigError(format!(
+                "Provider ISV_SVN {} is lower than the previously seen {}; \
+                 refusing to start a downgraded build",
+                current, highest
+            )))
+        }
+        Some(highest) => info!("Provider ISV_SVN {} (highest seen {})", current, highest),
+        None => warn!("No SVN markers in {}; starting a new record", dir.display()),
+    }
+
+    let marker = dir.join(format!("{}{}", MARKER_PREFIX, current));
+    if !marker.exists() {
+        fs::write(&marker, current.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svn_may_rise_but_not_fall() {
+        let dir = std::env::temp_dir().join(format!("svn-record-{}", std::process::id()));
+
+        let results = [
+            check_provider_svn(&dir, 2).is_ok(),
+            check_provider_svn(&dir, 2).is_ok(),
+            check_provider_svn(&dir, 10).is_ok(),
+            check_provider_svn(&dir, 3).is_ok(),
+        ];
+        let marked = dir.join("svn-10").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results, [true, true, true, false]);
+        assert!(marked);
+    }
+}
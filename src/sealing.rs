@@ -0,0 +1,196 @@
+use crate::crypto::GuardedKey;
+use crate::error::ProviderError;
+use crate::gramine::{self, GramineKey};
+use crate::secrets;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zeroize::Zeroizing;
+
+/// Minimum root key length accepted from non-Gramine sources, matching the
+/// 128-bit keys Gramine hands out.
+const MIN_ROOT_KEY_LEN: usize = 16;
+
+/// Where the root key that the master secret is extracted from comes from.
+/// It is read once at startup; new roots (HSM-wrapped, KMS-fetched) only need
+/// an implementation here.
+pub trait SealingKeySource: Send + Sync {
+    /// Human-readable description for logs.
+    fn describe(&self) -> String;
+
+    fn sealing_key(&self) -> Result<GuardedKey, ProviderError>;
+
+    /// Whether another program hands over the key, so whoever controls that
+    /// program sees it.
+    fn is_external(&self) -> bool {
+        false
+    }
+
+    /// The file the key is read from, if it is read from one.
+    fn file(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Root key stored in a file, e.g. a test key or one placed on a Gramine
+/// encrypted mount by a provisioning step.
+pub struct FileSealingKey {
+    path: PathBuf,
+}
+
+impl FileSealingKey {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl SealingKeySource for FileSealingKey {
+    fn describe(&self) -> String {
+        format!("file {}", self.path.display())
+    }
+
+    fn sealing_key(&self) -> Result<GuardedKey, ProviderError> {
+        debug!("reading root key from {}", self.path.display());
+        let key = fs::read(&self.path).map(Zeroizing::new)?;
+        if key.len() < MIN_ROOT_KEY_LEN {
+            return Err(ProviderError::ConfigError(format!(
+                "Root key in {} is shorter than {} bytes",
+                self.path.display(),
+                MIN_ROOT_KEY_LEN
+            )));
+        }
+        GuardedKey::from_slice(&key)
+    }
+
+    fn file(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Root key printed (as raw bytes) by a shell command, typically a KMS or
+/// secrets-manager CLI that unwraps it. Only usable outside an enclave.
+pub struct CommandSealingKey {
+    command: String,
+}
+
+impl CommandSealingKey {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl SealingKeySource for CommandSealingKey {
+    fn describe(&self) -> String {
+        // The command line may embed credentials; name only the program
+        let program = self.command.split_whitespace().next().unwrap_or_default();
+        format!("command {}", program)
+    }
+
+    fn sealing_key(&self) -> Result<GuardedKey, ProviderError> {
+        debug!("running root key command");
+        let output = Command::new("sh").arg("-c").arg(&self.command).output()?;
+        let key = Zeroizing::new(output.stdout);
+        if !output.status.success() {
+            return Err(ProviderError::ConfigError(format!(
+                "Root key command failed with {}",
+                output.status
+            )));
+        }
+        if key.len() < MIN_ROOT_KEY_LEN {
+            return Err(ProviderError::ConfigError(format!(
+                "Root key command printed fewer than {} bytes",
+                MIN_ROOT_KEY_LEN
+            )));
+        }
+        GuardedKey::from_slice(&key)
+    }
+
+    fn is_external(&self) -> bool {
+        true
+    }
+}
+
+/// Selects the source from `SEALING_PROVIDER_SEALING_KEY`: `mrenclave`
+/// (default), `mrsigner`, `file:<path>` or `command:<shell command>`. It is a
+/// secret setting, since KMS commands may embed credentials.
+pub fn from_env() -> Result<Box<dyn SealingKeySource>, ProviderError> {
+    match secrets::secret_string("SEALING_PROVIDER_SEALING_KEY")? {
+        Some(setting) => parse_source(&setting),
+        None => parse_source("mrenclave"),
+    }
+}
+
+fn parse_source(setting: &str) -> Result<Box<dyn SealingKeySource>, ProviderError> {
+    match setting {
+        "mrenclave" => gramine::sealing_key_source(GramineKey::MrEnclave),
+        "mrsigner" => gramine::sealing_key_source(GramineKey::MrSigner),
+        other => {
+            if let Some(path) = other.strip_prefix("file:").filter(|path| !path.is_empty()) {
+                Ok(Box::new(FileSealingKey::new(Path::new(path))))
+            } else if let Some(command) = other.strip_prefix("command:").filter(|c| !c.is_empty()) {
+                Ok(Box::new(CommandSealingKey::new(command)))
+            } else {
+                Err(ProviderError::ConfigError(format!(
+                    "Unknown sealing key source {:?}; expected mrenclave, mrsigner, \
+                     file:<path> or command:<shell command>",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sources() {
+        assert!(parse_source("file:/run/root.key")
+            .unwrap()
+            .describe()
+            .ends_with("/run/root.key"));
+        assert!(matches!(
+            parse_source("file:"),
+            Err(ProviderError::ConfigError(_))
+        ));
+        assert!(matches!(
+            parse_source("kms"),
+            Err(ProviderError::ConfigError(_))
+        ));
+        assert_eq!(
+            parse_source("command:vault read -field=key secret/root")
+                .unwrap()
+                .describe(),
+            "command vault"
+        );
+    }
+
+    #[test]
+    fn command_source_reads_stdout() {
+        sodiumoxide::init().unwrap();
+        let key = CommandSealingKey::new("printf 0123456789abcdef")
+            .sealing_key()
+            .unwrap();
+
+        key.with_bytes(|bytes| assert_eq!(bytes, b"0123456789abcdef"));
+        assert!(CommandSealingKey::new("false").sealing_key().is_err());
+    }
+
+    #[test]
+    fn file_source_rejects_short_keys() {
+        sodiumoxide::init().unwrap();
+        let path = std::env::temp_dir().join(format!("short-root-key-{}", std::process::id()));
+        fs::write(&path, [0u8; MIN_ROOT_KEY_LEN - 1]).unwrap();
+
+        let result = FileSealingKey::new(&path).sealing_key();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(ProviderError::ConfigError(_))));
+    }
+}
@@ -0,0 +1,83 @@
+use crate::crypto::SecretBytes;
+use crate::error::ProviderError;
+use log::{debug, warn};
+use std::env;
+use std::fs;
+use zeroize::Zeroizing;
+
+/// Reads a sensitive setting. `<name>_FILE` names a file holding the value,
+/// meant to live on a Gramine encrypted mount so it never appears in the
+/// manifest, the environment or on the host in the clear; a single trailing
+/// newline is dropped. A plain `<name>` environment variable is still
+/// accepted, with a warning.
+pub fn secret_setting(name: &str) -> Result<Option<SecretBytes>, ProviderError> {
+    read_secret(name, |var| env::var(var).ok())
+}
+
+/// `secret_setting` with the variables from `var`.
+fn read_secret(
+    name: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<SecretBytes>, ProviderError> {
+    if let Some(path) = var(&format!("{}_FILE", name)) {
+        debug!("reading {} from {}", name, path);
+        let mut value = fs::read(&path).map_err(|e| {
+            ProviderError::ConfigError(format!("Cannot read {} from {}: {}", name, path, e))
+        })?;
+        if value.last() == Some(&b'\n') {
+            value.pop();
+        }
+        return Ok(Some(SecretBytes::new(value)));
+    }
+
+    match var(name) {
+        Some(value) => {
+            warn!(
+                "{} is set in the environment, where the host can read it; prefer {}_FILE",
+                name, name
+            );
+            Ok(Some(SecretBytes::new(value.into_bytes())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// `secret_setting` for values that must be UTF-8.
+pub fn secret_string(name: &str) -> Result<Option<Zeroizing<String>>, ProviderError> {
+    secret_setting(name)?
+        .map(|value| {
+            String::from_utf8(value.expose().to_vec())
+                .map(Zeroizing::new)
+                .map_err(|_| ProviderError::ConfigError(format!("{} is not valid UTF-8", name)))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn file_takes_precedence_and_loses_trailing_newline() {
+        let path = std::env::temp_dir().join(format!("secret-setting-{}", std::process::id()));
+        fs::write(&path, b"from-file\n").unwrap();
+        // Not the process environment, which tests running in parallel share
+        let vars = HashMap::from([
+            ("TOKEN_FILE", path.display().to_string()),
+            ("TOKEN", "from-env".to_string()),
+            ("OTHER", "from-env".to_string()),
+        ]);
+        let var = |name: &str| vars.get(name).cloned();
+
+        let value = read_secret("TOKEN", var).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(value.unwrap().expose(), b"from-file");
+        assert_eq!(
+            read_secret("OTHER", var).unwrap().unwrap().expose(),
+            b"from-env"
+        );
+        assert!(read_secret("UNSET", var).unwrap().is_none());
+    }
+}
@@ -1,109 +1,444 @@
+use crate::connections::enter_phase;
 use crate::error::ProviderError;
-use crate::quote::process_quotes;
+use crate::logging::{connection_span, new_request_id};
+use crate::padding::Responder;
+use crate::protocol::{
+    InfoRequest, QuoteRequest, QuoteResponse, RejectionResponse, RequestKind, SessionRequest,
+    SessionStatusRequest, SpiffeRequest,
+};
+use crate::quote::{
+    attest_node, check_request, process_quotes, provider_info, session_status, sign_transcript,
+    Trace,
+};
+use crate::session::Session;
+use crate::state::ProviderState;
 use log::{debug, error, info};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::process;
-use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+#[cfg(feature = "ratls")]
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::{Instrument, Span};
+use zeroize::{Zeroize, Zeroizing};
 
-#[derive(Serialize, Deserialize)]
-struct QuoteRequest {
-    quote: Vec<u8>,
-}
+/// Largest frame accepted from a peer. Requests are a quote or token and a
+/// few keys; this is far beyond that and keeps a peer from making the
+/// provider allocate what it likes.
+pub const MAX_FRAME: usize = 1024 * 1024;
 
-#[derive(Serialize, Deserialize)]
-struct QuoteResponse {
-    encrypted_key: Vec<u8>,
-    provider_quote: Vec<u8>,
-}
+/// How long a peer has to complete the TLS handshake.
+#[cfg(feature = "ratls")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct Server {
     addr: String,
+    state: Arc<ProviderState>,
+    #[cfg(feature = "ratls")]
+    ratls: Option<(String, TlsAcceptor)>,
 }
 
 impl Server {
-    pub fn new(addr: String) -> Self {
-        Self { addr }
+    pub fn new(addr: String, state: ProviderState) -> Self {
+        Self {
+            addr,
+            state: Arc::new(state),
+            #[cfg(feature = "ratls")]
+            ratls: None,
+        }
+    }
+
+    /// Also serves the protocol over TLS on `addr`, authenticated by an
+    /// RA-TLS certificate minted inside the enclave.
+    #[cfg(feature = "ratls")]
+    pub fn with_ratls(
+        mut self,
+        addr: String,
+        cert: crate::crypto::AttestedCertificate,
+    ) -> Result<Self, ProviderError> {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_der.expose().to_vec()));
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![CertificateDer::from(cert.cert_der)], key)
+            .map_err(|e| {
+                ProviderError::ConfigError(format!("Invalid RA-TLS certificate: {}", e))
+            })?;
+        self.ratls = Some((addr, TlsAcceptor::from(Arc::new(config))));
+        Ok(self)
+    }
+
+    pub fn state(&self) -> Arc<ProviderState> {
+        Arc::clone(&self.state)
     }
 
     pub async fn run(&self) -> Result<(), ProviderError> {
-        let listener = TcpListener::bind(&self.addr).await.map_err(|e| {
-            error!("Failed to bind to {}: {}", self.addr, e);
-            ProviderError::NetworkError(e.to_string())
-        })?;
+        let listener = bind(&self.addr).await?;
+
+        #[cfg(feature = "ratls")]
+        {
+            if let Some((addr, acceptor)) = &self.ratls {
+                let tls_listener = bind(addr).await?;
+                tokio::try_join!(
+                    self.serve_plain(listener),
+                    self.serve_tls(tls_listener, acceptor)
+                )?;
+                return Ok(());
+            }
+        }
+
+        self.serve_plain(listener).await
+    }
+
+    async fn serve_plain(&self, listener: TcpListener) -> Result<(), ProviderError> {
+        while let Ok((socket, peer_addr)) = listener.accept().await {
+            let request_id = new_request_id();
+            let span = connection_span(peer_addr, &request_id);
+            span.in_scope(|| info!("New connection from: {}", peer_addr));
+            spawn_connection(socket, peer_addr, request_id, Arc::clone(&self.state), span);
+        }
 
-        info!("Listening on {}", self.addr);
+        Ok(())
+    }
 
+    #[cfg(feature = "ratls")]
+    async fn serve_tls(
+        &self,
+        listener: TcpListener,
+        acceptor: &TlsAcceptor,
+    ) -> Result<(), ProviderError> {
         while let Ok((socket, peer_addr)) = listener.accept().await {
-            info!("New connection from: {}", peer_addr);
-            
-            tokio::spawn(async move {
-                if let Err(e) = handle_connection(socket).await {
-                    match e {
-                        ProviderError::RestartRequired {
-                            ref context,
-                            ref source,
-                        } => {
-                            error!(
-                                "permission denied {context}: {source}; exiting to trigger restart"
-                            );
-                            process::exit(1);
+            let request_id = new_request_id();
+            let span = connection_span(peer_addr, &request_id);
+            span.in_scope(|| info!("New RA-TLS connection from: {}", peer_addr));
+            let acceptor = acceptor.clone();
+            let state = Arc::clone(&self.state);
+
+            let handshake_span = span.clone();
+            tokio::spawn(
+                async move {
+                    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                        Ok(Ok(stream)) => {
+                            spawn_connection(stream, peer_addr, request_id, state, span)
                         }
-                        _ => error!("connection error from {}: {}", peer_addr, e),
+                        Ok(Err(e)) => error!("TLS handshake with {} failed: {}", peer_addr, e),
+                        Err(_) => error!(
+                            "TLS handshake with {} timed out after {:?}",
+                            peer_addr, HANDSHAKE_TIMEOUT
+                        ),
                     }
                 }
-            });
+                .instrument(handshake_span),
+            );
         }
 
         Ok(())
     }
 }
 
-async fn handle_connection(mut socket: TcpStream) -> Result<(), ProviderError> {
-    // Read request length
-    let mut len_buf = [0u8; 4];
-    socket.read_exact(&mut len_buf).await.map_err(|e| {
-        ProviderError::NetworkError(format!("Failed to read request length: {}", e))
+pub async fn bind(addr: &str) -> Result<TcpListener, ProviderError> {
+    let listener = TcpListener::bind(addr).await.map_err(|e| {
+        error!("Failed to bind to {}: {}", addr, e);
+        ProviderError::NetworkError(e.to_string())
     })?;
 
-    let req_len = u32::from_be_bytes(len_buf) as usize;
-    debug!("Expecting request of {} bytes", req_len);
+    info!("Listening on {}", addr);
+    Ok(listener)
+}
 
-    // Read request data
-    let mut request_data = vec![0u8; req_len];
-    socket.read_exact(&mut request_data).await.map_err(|e| {
-        ProviderError::NetworkError(format!("Failed to read request: {}", e))
-    })?;
+fn spawn_connection<S>(
+    socket: S,
+    peer_addr: SocketAddr,
+    request_id: String,
+    state: Arc<ProviderState>,
+    span: Span,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(
+        async move {
+            let connection = handle_connection(socket, &state);
+            if let Err(e) = state
+                .connections
+                .track(request_id, peer_addr, connection)
+                .await
+            {
+                match e {
+                    ProviderError::RestartRequired {
+                        ref context,
+                        ref source,
+                    } => {
+                        error!("permission denied {context}: {source}; exiting to trigger restart");
+                        process::exit(1);
+                    }
+                    _ => error!("connection error from {}: {}", peer_addr, e.chain()),
+                }
+            }
+        }
+        .instrument(span),
+    );
+}
+
+async fn handle_connection<S>(mut socket: S, state: &ProviderState) -> Result<(), ProviderError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    enter_phase("read_request");
+    let request_data = match read_frame(&mut socket).await? {
+        Some(data) => data,
+        None => {
+            return Err(ProviderError::NetworkError(
+                "Connection closed before request".into(),
+            ))
+        }
+    };
+
+    let responder = Responder::new(state.settings().padding);
+    let served = serve_request(&mut socket, &request_data, state, &responder).await;
+    if served.is_err() {
+        // Closing the connection early would tell the host the request failed
+        responder.wait().await;
+    }
+    served
+}
+
+async fn serve_request<S>(
+    socket: &mut S,
+    request_data: &[u8],
+    state: &ProviderState,
+    responder: &Responder,
+) -> Result<(), ProviderError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let kind: RequestKind = serde_json::from_slice(request_data)?;
+    match kind.op.as_deref() {
+        None | Some("quote") => {}
+        Some("info") => {
+            enter_phase("info");
+            let request: InfoRequest = serde_json::from_slice(request_data)?;
+            let response = provider_info(&request, state).await?;
+            return responder.send(socket, &response).await;
+        }
+        Some("check") => {
+            let request: QuoteRequest = serde_json::from_slice(request_data)?;
+            let response = check_request(&request, state).await?;
+            enter_phase("write_response");
+            return responder.send(socket, &response).await;
+        }
+        Some("spiffe") => {
+            let request: SpiffeRequest = serde_json::from_slice(request_data)?;
+            let response = attest_node(&request, state).await?;
+            enter_phase("write_response");
+            return responder.send(socket, &response).await;
+        }
+        Some("session_status") => {
+            enter_phase("session_status");
+            let request: SessionStatusRequest = serde_json::from_slice(request_data)?;
+            let response = session_status(&request, state).await?;
+            return responder.send(socket, &response).await;
+        }
+        Some(op) => {
+            return Err(ProviderError::SerializationError(format!(
+                "Unknown request op: {}",
+                op
+            )))
+        }
+    }
 
     // Parse request
-    let request: QuoteRequest = serde_json::from_slice(&request_data)?;
+    let request: QuoteRequest = serde_json::from_slice(request_data)?;
     debug!("Received quote of {} bytes", request.quote.len());
 
     // Process quote
-    let provider_response = process_quotes(&request.quote).await?;
-    
-    
+    let mut trace = Trace::new();
+    let mut provider_response = match process_quotes(&request, state, &mut trace).await {
+        Ok(response) => response,
+        Err(e) if request.explain => {
+            enter_phase("write_response");
+            let rejection = RejectionResponse {
+                error: e.chain().to_string(),
+                trace: trace.into_steps(),
+            };
+            responder.send(socket, &rejection).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    let session = provider_response.session.take();
+
     // Prepare response
-    let response = QuoteResponse {
+    let mut response = QuoteResponse {
+        suite: provider_response.suite.name().to_string(),
         encrypted_key: provider_response.encrypted_key,
         provider_quote: provider_response.provider_quote,
+        recipient_keys: provider_response.recipient_keys,
+        key_confirmation: provider_response.key_confirmation,
+        kernel_key: provider_response
+            .kernel_key
+            .map(|format| format.name().to_string()),
+        identity_key: None,
+        transcript_signature: None,
+        session_key: provider_response.session_key,
+        platform_tcb: provider_response.platform_tcb,
+        tenant: provider_response.tenant,
+        reissue_token: provider_response.reissue_token,
+        reissue_expires_at: provider_response.reissue_expires_at,
     };
 
-    let response_data = serde_json::to_vec(&response)?;
+    // Sign the exchange if asked; the identity key is already bound in the quote
+    if request.transcript {
+        response.transcript_signature = Some(sign_transcript(
+            state.identity.as_ref(),
+            request_data,
+            &request.quote,
+            &response.provider_quote,
+        )?);
+        response.identity_key = Some(state.identity.public_key().to_vec());
+    }
+
+    enter_phase("write_response");
+    responder.send(socket, &response).await?;
+    debug!("Response sent successfully");
+
+    let (Some(session), Some(session_key)) = (session, response.session_key) else {
+        return Ok(());
+    };
+    if let Some(journal) = &state.journal {
+        journal.opened(&session_key);
+    }
+    let idle_timeout = state.settings().session_idle_timeout;
+    let served = serve_session(socket, session, idle_timeout).await;
+    if let Some(journal) = &state.journal {
+        journal.closed(&session_key);
+    }
+    served
+}
+
+/// Handles encrypted session frames until the client closes the session or
+/// the connection, or goes idle for `idle_timeout`.
+async fn serve_session<S>(
+    socket: &mut S,
+    mut session: Session,
+    idle_timeout: Duration,
+) -> Result<(), ProviderError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    info!("Session established");
+
+    loop {
+        enter_phase("session_idle");
+        let frame = match timeout(idle_timeout, read_frame(socket)).await {
+            Ok(frame) => frame?,
+            Err(_) => {
+                info!("Session idle timeout");
+                return Ok(());
+            }
+        };
+        let Some(frame) = frame else {
+            info!("Client disconnected from session");
+            return Ok(());
+        };
+
+        enter_phase("session_request");
+        let plaintext = session.channel.open(&frame)?;
+        let request: SessionRequest = serde_json::from_slice(plaintext.expose())?;
+        let Some(mut response) = session.handle(request) else {
+            return Ok(());
+        };
 
+        // An unwrapped secret is in the response, then in its encoding
+        let response_data = Zeroizing::new(serde_json::to_vec(&response)?);
+        if let Some(data) = &mut response.data {
+            data.zeroize();
+        }
+        let sealed = session.channel.seal(&response_data)?;
+        write_frame(socket, &sealed).await?;
+    }
+}
+
+/// Reads one length-prefixed frame of at most [`MAX_FRAME`] bytes; `None` if
+/// the peer closed the connection cleanly before a new frame.
+pub async fn read_frame<S>(socket: &mut S) -> Result<Option<Vec<u8>>, ProviderError>
+where
+    S: AsyncRead + Unpin,
+{
+    // Read request length
+    let mut len_buf = [0u8; 4];
+    match socket.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => {
+            return Err(ProviderError::NetworkError(format!(
+                "Failed to read request length: {}",
+                e
+            )))
+        }
+    }
+
+    let req_len = u32::from_be_bytes(len_buf) as usize;
+    if req_len > MAX_FRAME {
+        return Err(ProviderError::NetworkError(format!(
+            "Request of {} bytes exceeds the {} byte limit",
+            req_len, MAX_FRAME
+        )));
+    }
+    debug!("Expecting request of {} bytes", req_len);
+
+    // Read request data
+    let mut request_data = vec![0u8; req_len];
+    socket
+        .read_exact(&mut request_data)
+        .await
+        .map_err(|e| ProviderError::NetworkError(format!("Failed to read request: {}", e)))?;
+
+    Ok(Some(request_data))
+}
+
+pub async fn write_frame<S>(socket: &mut S, data: &[u8]) -> Result<(), ProviderError>
+where
+    S: AsyncWrite + Unpin,
+{
     // Send response length
     socket
-        .write_all(&(response_data.len() as u32).to_be_bytes())
+        .write_all(&(data.len() as u32).to_be_bytes())
         .await
         .map_err(|e| {
             ProviderError::NetworkError(format!("Failed to send response length: {}", e))
         })?;
 
     // Send response
-    socket.write_all(&response_data).await.map_err(|e| {
-        ProviderError::NetworkError(format!("Failed to send response: {}", e))
-    })?;
+    socket
+        .write_all(data)
+        .await
+        .map_err(|e| ProviderError::NetworkError(format!("Failed to send response: {}", e)))?;
 
-    debug!("Response sent successfully");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_frames_are_refused_before_reading() {
+        let mut frame = (MAX_FRAME as u32 + 1).to_be_bytes().to_vec();
+        frame.extend_from_slice(&[7; 16]);
+        assert!(read_frame(&mut &frame[..]).await.is_err());
+
+        let mut frame = 3u32.to_be_bytes().to_vec();
+        frame.extend_from_slice(b"abc");
+        assert_eq!(read_frame(&mut &frame[..]).await.unwrap().unwrap(), b"abc");
+        assert!(read_frame(&mut &[][..]).await.unwrap().is_none());
+    }
+}
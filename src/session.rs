@@ -0,0 +1,46 @@
+use crate::crypto::{unwrap_with_key, wrap_with_key, SecretBytes, SessionChannel};
+use crate::protocol::{SessionRequest, SessionResponse};
+use log::{debug, info};
+use zeroize::Zeroizing;
+
+/// An established session: the encrypted channel plus the workload key it was
+/// opened for. Operations only ever act on that key.
+#[derive(Debug)]
+pub struct Session {
+    pub channel: SessionChannel,
+    pub derived_key: SecretBytes,
+}
+
+impl Session {
+    /// Executes one decrypted request. `None` means the client closed the
+    /// session.
+    pub fn handle(&self, request: SessionRequest) -> Option<SessionResponse> {
+        let result = match request {
+            SessionRequest::Wrap { plaintext, aad } => {
+                let plaintext = Zeroizing::new(plaintext);
+                debug!("Session wrap of {} bytes", plaintext.len());
+                wrap_with_key(&self.derived_key, &aad, &plaintext)
+            }
+            SessionRequest::Unwrap { wrapped, aad } => {
+                debug!("Session unwrap of {} bytes", wrapped.len());
+                unwrap_with_key(&self.derived_key, &aad, &wrapped)
+                    .map(|plaintext| plaintext.expose().to_vec())
+            }
+            SessionRequest::Close => {
+                info!("Client closed session");
+                return None;
+            }
+        };
+
+        Some(match result {
+            Ok(data) => SessionResponse {
+                data: Some(data),
+                error: None,
+            },
+            Err(e) => SessionResponse {
+                data: None,
+                error: Some(e.to_string()),
+            },
+        })
+    }
+}
@@ -0,0 +1,195 @@
+use crate::approvals::Approvals;
+use crate::audit::{AuditExport, AuditLog};
+use crate::config::{AzureTdxConfig, Config};
+use crate::connections::Connections;
+use crate::counters::CounterStore;
+use crate::crypto::{MasterSecret, Signer};
+use crate::error::ProviderError;
+#[cfg(feature = "cca")]
+use crate::evidence::CcaVerifier;
+use crate::evidence::{GcpVerifier, ItaVerifier, MaaVerifier, SnpVerifier, TpmVerifier};
+use crate::journal::SessionJournal;
+use crate::padding::ResponsePadding;
+use crate::policy::Policy;
+use crate::ratelimit::RateLimiter;
+use crate::reissue::Reissues;
+use crate::remote_policy::RemotePolicy;
+use crate::replay::NonceCache;
+use crate::revocations::RevocationList;
+use crate::verifier::Verifier;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The settings that a reload replaces. A request takes one snapshot and
+/// uses it throughout, so it never sees half of an old and half of a new
+/// policy.
+#[derive(Debug)]
+pub struct Settings {
+    pub policy: Policy,
+    pub session_idle_timeout: Duration,
+    pub padding: ResponsePadding,
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Result<Self, ProviderError> {
+        let policy = match &config.files.policy {
+            Some(path) => Policy::load(path)?,
+            None => Policy::default(),
+        };
+        Ok(Self {
+            policy,
+            session_idle_timeout: config.session_idle_timeout(),
+            padding: ResponsePadding::from_config(&config.server),
+        })
+    }
+}
+
+/// State shared by all connections.
+pub struct ProviderState {
+    pub master: MasterSecret,
+    pub identity: Box<dyn Signer>,
+    pub nonces: NonceCache,
+    pub verifier: Box<dyn Verifier>,
+    pub snp: Option<SnpVerifier>,
+    pub azure_tdx: Option<AzureTdxConfig>,
+    pub gcp: Option<GcpVerifier>,
+    pub ita: Option<ItaVerifier>,
+    pub maa: Option<MaaVerifier>,
+    pub tpm: Option<TpmVerifier>,
+    #[cfg(feature = "cca")]
+    pub cca: Option<CcaVerifier>,
+    pub counters: Option<CounterStore>,
+    pub audit: Option<AuditLog>,
+    pub export: Option<AuditExport>,
+    pub journal: Option<SessionJournal>,
+    pub revocations: Option<RevocationList>,
+    pub approvals: Approvals,
+    pub rate_limits: RateLimiter,
+    pub reissues: Reissues,
+    pub remote_policy: Option<RemotePolicy>,
+    pub connections: Connections,
+    settings: RwLock<Arc<Settings>>,
+}
+
+impl ProviderState {
+    pub fn new(
+        master: MasterSecret,
+        identity: Box<dyn Signer>,
+        settings: Settings,
+        verifier: Box<dyn Verifier>,
+        counters: Option<CounterStore>,
+        audit: Option<AuditLog>,
+        export: Option<AuditExport>,
+    ) -> Self {
+        Self {
+            master,
+            identity,
+            nonces: NonceCache::new(),
+            verifier,
+            snp: None,
+            azure_tdx: None,
+            gcp: None,
+            ita: None,
+            maa: None,
+            tpm: None,
+            #[cfg(feature = "cca")]
+            cca: None,
+            counters,
+            audit,
+            export,
+            journal: None,
+            revocations: None,
+            approvals: Approvals::default(),
+            rate_limits: RateLimiter::default(),
+            reissues: Reissues::default(),
+            remote_policy: None,
+            connections: Connections::default(),
+            settings: RwLock::new(Arc::new(settings)),
+        }
+    }
+
+    /// Journals sessions to `journal`.
+    pub fn with_journal(mut self, journal: Option<SessionJournal>) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// Takes the policy from signed bundles at `remote_policy` rather than
+    /// from `files.policy`.
+    pub fn with_remote_policy(mut self, remote_policy: Option<RemotePolicy>) -> Self {
+        self.remote_policy = remote_policy;
+        self
+    }
+
+    /// Refuses what `revocations` lists.
+    pub fn with_revocations(mut self, revocations: Option<RevocationList>) -> Self {
+        self.revocations = revocations;
+        self
+    }
+
+    /// Verifies SEV-SNP reports with `snp`; without it they are refused.
+    pub fn with_snp(mut self, snp: Option<SnpVerifier>) -> Self {
+        self.snp = snp;
+        self
+    }
+
+    /// Accepts Azure TDX evidence as `azure_tdx` says; without it, it is
+    /// refused.
+    pub fn with_azure_tdx(mut self, azure_tdx: Option<AzureTdxConfig>) -> Self {
+        self.azure_tdx = azure_tdx;
+        self
+    }
+
+    /// Checks GCP attestation tokens with `gcp`; without it they are
+    /// refused.
+    pub fn with_gcp(mut self, gcp: Option<GcpVerifier>) -> Self {
+        self.gcp = gcp;
+        self
+    }
+
+    /// Checks Intel Trust Authority tokens with `ita`; without it they are
+    /// refused.
+    pub fn with_ita(mut self, ita: Option<ItaVerifier>) -> Self {
+        self.ita = ita;
+        self
+    }
+
+    /// Checks MAA tokens with `maa`; without it they are refused.
+    pub fn with_maa(mut self, maa: Option<MaaVerifier>) -> Self {
+        self.maa = maa;
+        self
+    }
+
+    /// Checks TPM quotes from clients without a TEE with `tpm`; without it
+    /// they are refused.
+    pub fn with_tpm(mut self, tpm: Option<TpmVerifier>) -> Self {
+        self.tpm = tpm;
+        self
+    }
+
+    /// Checks ARM CCA tokens with `cca`; without it they are refused.
+    #[cfg(feature = "cca")]
+    pub fn with_cca(mut self, cca: Option<CcaVerifier>) -> Self {
+        self.cca = cca;
+        self
+    }
+
+    /// The current settings.
+    pub fn settings(&self) -> Arc<Settings> {
+        let settings = self
+            .settings
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(&settings)
+    }
+
+    /// Applies `settings` to every request that starts afterwards; requests
+    /// in flight finish with the snapshot they took.
+    pub fn replace_settings(&self, settings: Settings) {
+        let mut current = self
+            .settings
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *current = Arc::new(settings);
+    }
+}
@@ -0,0 +1,168 @@
+//! Tenants of a shared provider. The policy document lists them, each with
+//! an id, a policy document of its own and the launch measurements of its
+//! workloads. A request belongs to the tenant it names, which must list its
+//! launch measurement, or else to the first tenant that lists it. The
+//! tenant's policy judges it, and its key comes from the tenant's own root
+//! ([`crate::crypto::MasterSecret::derive_for`]).
+
+use crate::error::ProviderError;
+use crate::policy::Policy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    /// Letters, digits, `.`, `_` and `-`. Part of every key derivation, so
+    /// renaming a tenant changes all its keys.
+    pub id: String,
+    /// Policy document for the tenant's requests, relative to the policy
+    /// file. It cannot list tenants itself.
+    pub policy: PathBuf,
+    /// Hex launch measurements of the tenant's workloads: MRTD, MRENCLAVE,
+    /// the SNP launch measurement, and so on.
+    pub launch_measurements: Vec<String>,
+    /// What `policy` holds, read with the policy file.
+    #[serde(skip)]
+    pub rules: Policy,
+}
+
+/// Checks the tenants of a policy file in `dir` and reads their policies.
+pub fn load(tenants: &mut [Tenant], dir: &Path) -> Result<(), ProviderError> {
+    let mut ids = HashSet::new();
+    for tenant in tenants {
+        let valid_id = !tenant.id.is_empty()
+            && tenant
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !valid_id {
+            return Err(invalid(format!("Tenant id {:?} is not valid", tenant.id)));
+        }
+        if !ids.insert(tenant.id.clone()) {
+            return Err(invalid(format!("Tenant {} is listed twice", tenant.id)));
+        }
+        if let Some(bad) = tenant
+            .launch_measurements
+            .iter()
+            .find(|measurement| hex::decode(measurement).map_or(true, |bytes| bytes.is_empty()))
+        {
+            return Err(invalid(format!(
+                "Launch measurement {} of tenant {} is not hex",
+                bad, tenant.id
+            )));
+        }
+        tenant.rules = Policy::load(&dir.join(&tenant.policy))?;
+        if !tenant.rules.tenants.is_empty() {
+            return Err(invalid(format!(
+                "The policy of tenant {} lists tenants",
+                tenant.id
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The tenant a request with `launch_measurement` belongs to, or `None` for
+/// the provider's own namespace.
+pub fn select<'a>(
+    policy: &'a Policy,
+    requested: Option<&str>,
+    launch_measurement: &[u8],
+) -> Result<Option<&'a Tenant>, ProviderError> {
+    let launch = hex::encode(launch_measurement);
+    let lists = |tenant: &Tenant| {
+        tenant
+            .launch_measurements
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(&launch))
+    };
+    match requested {
+        Some(id) => {
+            let tenant = policy
+                .tenants
+                .iter()
+                .find(|tenant| tenant.id == id)
+                .ok_or_else(|| invalid(format!("Tenant {} is unknown", id)))?;
+            if !lists(tenant) {
+                return Err(invalid(format!(
+                    "Launch measurement {} is not one of tenant {}'s",
+                    launch, id
+                )));
+            }
+            Ok(Some(tenant))
+        }
+        None => match policy.tenants.iter().find(|tenant| lists(tenant)) {
+            Some(tenant) => Ok(Some(tenant)),
+            None if policy.require_tenant => Err(invalid(format!(
+                "Launch measurement {} belongs to no tenant",
+                launch
+            ))),
+            None => Ok(None),
+        },
+    }
+}
+
+fn invalid(message: String) -> ProviderError {
+    ProviderError::PolicyViolation(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{KdfVersion, MasterSecret};
+
+    fn tenant(id: &str, launch: u8) -> Tenant {
+        Tenant {
+            id: id.into(),
+            policy: PathBuf::from("tenant.json"),
+            launch_measurements: vec![hex::encode([launch; 48])],
+            rules: Policy::default(),
+        }
+    }
+
+    #[test]
+    fn requests_map_to_the_tenant_listing_their_measurement() {
+        let mut policy = Policy {
+            tenants: vec![tenant("acme", 1), tenant("globex", 2)],
+            ..Policy::default()
+        };
+
+        let select_id = |policy: &Policy, requested, launch| {
+            select(policy, requested, &[launch; 48]).map(|tenant| tenant.map(|t| t.id.clone()))
+        };
+
+        assert_eq!(
+            select_id(&policy, None, 2).unwrap().as_deref(),
+            Some("globex")
+        );
+        assert_eq!(
+            select_id(&policy, Some("acme"), 1).unwrap().as_deref(),
+            Some("acme")
+        );
+        assert!(select_id(&policy, Some("acme"), 2).is_err());
+        assert!(select_id(&policy, Some("initech"), 1).is_err());
+        assert_eq!(select_id(&policy, None, 3).unwrap(), None);
+        policy.require_tenant = true;
+        assert!(select_id(&policy, None, 3).is_err());
+
+        let master = MasterSecret::from_sealing_key(&[5; 16], None).unwrap();
+        let own = master.derive_for(KdfVersion::V2, None, b"m").unwrap();
+        let acme = master
+            .derive_for(KdfVersion::V2, Some("acme"), b"m")
+            .unwrap();
+        assert_eq!(
+            own.expose(),
+            master.derive(KdfVersion::V2, b"m").unwrap().expose()
+        );
+        assert_ne!(acme.expose(), own.expose());
+        assert_ne!(
+            acme.expose(),
+            master
+                .derive_for(KdfVersion::V2, Some("globex"), b"m")
+                .unwrap()
+                .expose()
+        );
+    }
+}
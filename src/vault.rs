@@ -0,0 +1,528 @@
+//! A small subset of HashiCorp Vault's HTTP API, so applications in a TD that
+//! already talk to Vault can point `VAULT_ADDR` at the provider instead.
+//!
+//! A TD logs in with `POST /v1/auth/tdx/login`, sending a quote whose report
+//! data is `SHA-256(LOGIN_LABEL || nonce) | X25519 public key`. The quote goes
+//! through the same verification, same-platform check, audit and rejection
+//! accounting as a key request, and the token it gets acts only on the key
+//! derived for its measurements. The listener is plain HTTP, so the token and
+//! everything secret the token reads come back sealed to the TD's key; a
+//! host relaying the login learns nothing it can use:
+//!
+//! - `GET /v1/secret/data/<path>` reads a KV version 2 secret whose `key` is
+//!   the 32-byte subkey for `<path>`, the same as the client's
+//!   `DerivedKey::subkey(<path>)`, sealed to the TD.
+//! - `POST /v1/transit/encrypt/<name>` and `POST /v1/transit/decrypt/<name>`
+//!   wrap and unwrap data as the session `wrap`/`unwrap` operations do, with
+//!   the key name and any `context` as associated data. Decrypted plaintext
+//!   is sealed to the TD.
+//!
+//! Tokens live in memory and end after `server.session_idle_timeout_secs`
+//! without use.
+//!
+//! Stock Vault clients cannot use the sealed token or sealed values as they
+//! come: the application, or `skp-client --vault-login` and
+//! `--vault-unseal`, has to open them with the login key first. Only the
+//! request paths and response layout are Vault's.
+
+use crate::crypto::{
+    backend, constant_time_eq, encrypt_key, fill_random, unwrap_with_key, wrap_with_key,
+    SecretBytes,
+};
+use crate::error::ProviderError;
+use crate::http::{read_request, write_response, Request, Response};
+use crate::logging::new_request_id;
+use crate::quote;
+use crate::server::bind;
+use crate::state::ProviderState;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sodiumoxide::crypto::box_::{PublicKey, PUBLICKEYBYTES};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::net::TcpStream;
+use zeroize::Zeroizing;
+
+/// Precedes the login nonce in the first half of the TD's report data.
+const LOGIN_LABEL: &[u8] = b"gramine-sealing-key-provider/vault-login/v1";
+/// The client's subkey label, so KV secrets match `DerivedKey::subkey`.
+const CONTEXT_LABEL: &[u8] = b"gramine-sealing-key-client/context/v1";
+const TRANSIT_LABEL: &[u8] = b"gramine-sealing-key-provider/vault-transit/v1";
+const CIPHERTEXT_PREFIX: &str = "vault:v1:";
+const TOKEN_PREFIX: &str = "hvs.";
+const TOKEN_LEN: usize = 24;
+const KV_PREFIX: &str = "/v1/secret/data/";
+const ENCRYPT_PREFIX: &str = "/v1/transit/encrypt/";
+const DECRYPT_PREFIX: &str = "/v1/transit/decrypt/";
+/// Where the Vault CLI asks which KV version a mount is.
+const MOUNTS_PREFIX: &str = "/v1/sys/internal/ui/mounts/";
+/// Policy name reported for every token.
+const POLICY: &str = "tdx-workload";
+/// Live tokens held before logins are refused. Each holds a derived key, and
+/// any TD that attests can log in again and again.
+const MAX_TOKENS: usize = 4096;
+
+/// Serves the Vault API subset on `addr`.
+pub async fn serve(addr: &str, state: Arc<ProviderState>) -> Result<(), ProviderError> {
+    let listener = bind(addr).await?;
+    let vault = Arc::new(Vault {
+        state,
+        tokens: Mutex::new(HashMap::new()),
+    });
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, peer_addr)) => {
+                    debug!("New Vault API connection from: {}", peer_addr);
+                    let vault = Arc::clone(&vault);
+                    tokio::spawn(async move {
+                        if let Err(e) = vault.handle_connection(socket).await {
+                            debug!(
+                                "Vault API connection error from {}: {}",
+                                peer_addr,
+                                e.chain()
+                            );
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept Vault API connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Vault {
+    state: Arc<ProviderState>,
+    tokens: Mutex<HashMap<String, Token>>,
+}
+
+struct Token {
+    derived_key: SecretBytes,
+    /// The TD's key from the login quote, which secrets are sealed to.
+    public_key: PublicKey,
+    expires: Instant,
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    quote: String,
+    nonce: String,
+}
+
+#[derive(Deserialize)]
+struct EncryptRequest {
+    plaintext: String,
+    #[serde(default)]
+    context: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DecryptRequest {
+    ciphertext: String,
+    #[serde(default)]
+    context: Option<String>,
+}
+
+/// A Vault error response.
+fn error(status: u16, message: impl Into<String>) -> Response {
+    Response::json(status, json!({ "errors": [message.into()] }))
+}
+
+fn permission_denied() -> Response {
+    error(403, "permission denied")
+}
+
+/// A Vault response carrying `data`.
+fn data(data: Value) -> Response {
+    Response::json(
+        200,
+        json!({
+            "request_id": new_request_id(),
+            "lease_id": "",
+            "renewable": false,
+            "lease_duration": 0,
+            "data": data,
+            "wrap_info": null,
+            "warnings": null,
+            "auth": null,
+        }),
+    )
+}
+
+impl Vault {
+    async fn handle_connection(&self, mut socket: TcpStream) -> Result<(), ProviderError> {
+        let mut buffer = Vec::new();
+        loop {
+            let Some((request, body)) = read_request(&mut socket, &mut buffer).await? else {
+                return Ok(());
+            };
+            let response = self.handle(&request, &body).await;
+            write_response(&mut socket, &response, request.keep_alive).await?;
+            if !request.keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn handle(&self, request: &Request, body: &[u8]) -> Response {
+        let path = request.path.as_str();
+        if (request.method.as_str(), path) == ("POST", "/v1/auth/tdx/login") {
+            return self.login(body).await;
+        }
+
+        // Everything else needs a token
+        let Some((derived_key, public_key)) = self.authorize(request) else {
+            return permission_denied();
+        };
+        match request.method.as_str() {
+            "GET" if path.starts_with(KV_PREFIX) => {
+                read_secret(&derived_key, &public_key, &path[KV_PREFIX.len()..])
+            }
+            "POST" | "PUT" if path.starts_with(ENCRYPT_PREFIX) => {
+                encrypt(&derived_key, &path[ENCRYPT_PREFIX.len()..], body)
+            }
+            "POST" | "PUT" if path.starts_with(DECRYPT_PREFIX) => decrypt(
+                &derived_key,
+                &public_key,
+                &path[DECRYPT_PREFIX.len()..],
+                body,
+            ),
+            "GET" if path == "/v1/auth/token/lookup-self" => self.lookup_self(),
+            "GET" if path.starts_with(MOUNTS_PREFIX) => data(json!({
+                "path": "secret/",
+                "type": "kv",
+                "options": { "version": "2" },
+            })),
+            _ => error(404, "unsupported path"),
+        }
+    }
+
+    async fn login(&self, body: &[u8]) -> Response {
+        let request: LoginRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return error(400, e.to_string()),
+        };
+        let (quote, nonce) = match (
+            STANDARD.decode(&request.quote),
+            STANDARD.decode(&request.nonce),
+        ) {
+            (Ok(quote), Ok(nonce)) => (quote, nonce),
+            _ => return error(400, "quote and nonce must be base64"),
+        };
+        // The nonce is only used up by a login whose quote verifies
+        if let Err(e) = self.state.nonces.check(&nonce) {
+            return error(400, e.to_string());
+        }
+
+        let mut td_key = None;
+        let bind = |report_data: &[u8]| {
+            td_key = Some(login_public_key(report_data, &nonce)?);
+            Ok(())
+        };
+        let attested = match quote::attest_quote(&quote, &self.state, bind).await {
+            Ok(attested) => attested,
+            Err(e) => {
+                warn!("Vault login rejected: {}", e.chain());
+                return permission_denied();
+            }
+        };
+        let Some(public_key) = td_key else {
+            return permission_denied();
+        };
+        if let Err(e) = self.state.nonces.check_and_insert(&nonce) {
+            return error(400, e.to_string());
+        }
+
+        let ttl = self.state.settings().session_idle_timeout;
+        let mut token_bytes = [0u8; TOKEN_LEN];
+        if let Err(e) = fill_random(&mut token_bytes) {
+            return error(500, e.to_string());
+        }
+        let token = format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(token_bytes));
+        let sealed_token =
+            match encrypt_key(&SecretBytes::new(token.clone().into_bytes()), &public_key) {
+                Ok(sealed) => sealed,
+                Err(e) => return error(500, e.to_string()),
+            };
+        let entry = Token {
+            derived_key: attested.derived_key,
+            public_key,
+            expires: Instant::now() + ttl,
+        };
+        if let Err(response) = self.issue(token, entry) {
+            return response;
+        }
+        info!(
+            "Vault token issued, key id {}",
+            hex::encode(&attested.key_id)
+        );
+
+        let mut response = data(Value::Null);
+        response.body["auth"] = json!({
+            "client_token": STANDARD.encode(sealed_token),
+            "accessor": "",
+            "policies": [POLICY],
+            "token_policies": [POLICY],
+            "metadata": { "key_id": hex::encode(&attested.key_id) },
+            "lease_duration": ttl.as_secs(),
+            "renewable": false,
+        });
+        response
+    }
+
+    /// Stores a token, refusing it while `MAX_TOKENS` are live. Refusing
+    /// keeps the tokens already issued; evicting would let a TD that logs in
+    /// repeatedly end other TDs' tokens.
+    fn issue(&self, token: String, entry: Token) -> Result<(), Response> {
+        let mut tokens = self.tokens();
+        let now = Instant::now();
+        tokens.retain(|_, token| token.expires > now);
+        if tokens.len() >= MAX_TOKENS {
+            warn!("Refusing Vault login: {} tokens are live", tokens.len());
+            return Err(error(429, "too many live tokens"));
+        }
+        tokens.insert(token, entry);
+        Ok(())
+    }
+
+    fn lookup_self(&self) -> Response {
+        let ttl = self.state.settings().session_idle_timeout.as_secs();
+        data(json!({
+            "policies": [POLICY],
+            "ttl": ttl,
+            "renewable": false,
+        }))
+    }
+
+    /// The key of the request's token and the TD key it was issued to, if it
+    /// is live, refreshing its expiry.
+    fn authorize(&self, request: &Request) -> Option<(SecretBytes, PublicKey)> {
+        let token = request.header("x-vault-token").or_else(|| {
+            request
+                .header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })?;
+        let mut tokens = self.tokens();
+        let entry = tokens.get_mut(token)?;
+        let now = Instant::now();
+        if entry.expires <= now {
+            tokens.remove(token);
+            return None;
+        }
+        entry.expires = now + self.state.settings().session_idle_timeout;
+        Some((
+            SecretBytes::new(entry.derived_key.expose().to_vec()),
+            entry.public_key,
+        ))
+    }
+
+    fn tokens(&self) -> MutexGuard<'_, HashMap<String, Token>> {
+        self.tokens
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The TD key a login quote's report data carries after
+/// `SHA-256(LOGIN_LABEL || nonce)`.
+fn login_public_key(report_data: &[u8], nonce: &[u8]) -> Result<PublicKey, ProviderError> {
+    let expected = backend().sha256(&[LOGIN_LABEL, nonce]);
+    if report_data.len() < 32 + PUBLICKEYBYTES || !constant_time_eq(&report_data[..32], &expected) {
+        return Err(ProviderError::PublicKeyError(
+            "Report data does not bind the login nonce".into(),
+        ));
+    }
+    PublicKey::from_slice(&report_data[32..32 + PUBLICKEYBYTES])
+        .ok_or_else(|| ProviderError::PublicKeyError("Invalid public key format".into()))
+}
+
+/// `value` sealed to the TD, base64.
+fn seal(value: &[u8], public_key: &PublicKey) -> Result<String, Response> {
+    encrypt_key(&SecretBytes::new(value.to_vec()), public_key)
+        .map(|sealed| STANDARD.encode(sealed))
+        .map_err(|e| error(500, e.to_string()))
+}
+
+fn read_secret(derived_key: &SecretBytes, public_key: &PublicKey, path: &str) -> Response {
+    if path.is_empty() {
+        return error(404, "no secret path");
+    }
+    // HKDF-Expand of one block, as the client computes subkeys
+    let key = Zeroizing::new(backend().hmac_sha256(
+        derived_key.expose(),
+        &[CONTEXT_LABEL, path.as_bytes(), &[1]],
+    ));
+    debug!("Vault KV read of {}", path);
+    let key = match seal(&key[..], public_key) {
+        Ok(sealed) => sealed,
+        Err(response) => return response,
+    };
+    data(json!({
+        "data": { "key": key },
+        "metadata": { "version": 1, "destroyed": false, "deletion_time": "" },
+    }))
+}
+
+fn encrypt(derived_key: &SecretBytes, name: &str, body: &[u8]) -> Response {
+    let request: EncryptRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(400, e.to_string()),
+    };
+    let (plaintext, aad) = match (
+        STANDARD.decode(&request.plaintext),
+        transit_aad(name, request.context.as_deref()),
+    ) {
+        (Ok(plaintext), Ok(aad)) => (plaintext, aad),
+        _ => return error(400, "plaintext and context must be base64"),
+    };
+    match wrap_with_key(derived_key, &aad, &plaintext) {
+        Ok(wrapped) => data(json!({
+            "ciphertext": format!("{}{}", CIPHERTEXT_PREFIX, STANDARD.encode(wrapped)),
+            "key_version": 1,
+        })),
+        Err(e) => error(500, e.to_string()),
+    }
+}
+
+fn decrypt(derived_key: &SecretBytes, public_key: &PublicKey, name: &str, body: &[u8]) -> Response {
+    let request: DecryptRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(400, e.to_string()),
+    };
+    let Some(Ok(wrapped)) = request
+        .ciphertext
+        .strip_prefix(CIPHERTEXT_PREFIX)
+        .map(|wrapped| STANDARD.decode(wrapped))
+    else {
+        return error(400, "invalid ciphertext: no prefix");
+    };
+    let Ok(aad) = transit_aad(name, request.context.as_deref()) else {
+        return error(400, "context must be base64");
+    };
+    match unwrap_with_key(derived_key, &aad, &wrapped) {
+        Ok(plaintext) => match seal(plaintext.expose(), public_key) {
+            Ok(sealed) => data(json!({ "plaintext": sealed })),
+            Err(response) => response,
+        },
+        Err(_) => error(400, "cipher: message authentication failed"),
+    }
+}
+
+/// `TRANSIT_LABEL || len(name) (u32 BE) || name || context`.
+fn transit_aad(name: &str, context: Option<&str>) -> Result<Vec<u8>, base64::DecodeError> {
+    let context = context.map(|c| STANDARD.decode(c)).transpose()?;
+    let mut aad = TRANSIT_LABEL.to_vec();
+    aad.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    aad.extend_from_slice(name.as_bytes());
+    aad.extend_from_slice(context.as_deref().unwrap_or_default());
+    Ok(aad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collateral::CollateralCache;
+    use crate::crypto::{MasterSecret, ProviderIdentity};
+    use crate::padding::ResponsePadding;
+    use crate::policy::Policy;
+    use crate::state::Settings;
+    use crate::verifier::DcapVerifier;
+    use std::time::Duration;
+
+    fn body(response: &Response, field: &str) -> String {
+        response.body["data"][field].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn transit_round_trips_only_under_the_same_name_and_context() {
+        sodiumoxide::init().unwrap();
+        let (public_key, secret_key) = sodiumoxide::crypto::box_::gen_keypair();
+        let key = SecretBytes::new(vec![3; 32]);
+        let plaintext = STANDARD.encode(b"database password");
+        let encrypted = encrypt(
+            &key,
+            "app",
+            json!({ "plaintext": plaintext, "context": "Y3R4" })
+                .to_string()
+                .as_bytes(),
+        );
+        let ciphertext = body(&encrypted, "ciphertext");
+        assert!(ciphertext.starts_with(CIPHERTEXT_PREFIX));
+
+        let decrypt_as = |name: &str, context: &str| {
+            decrypt(
+                &key,
+                &public_key,
+                name,
+                json!({ "ciphertext": ciphertext, "context": context })
+                    .to_string()
+                    .as_bytes(),
+            )
+        };
+        let decrypted = decrypt_as("app", "Y3R4");
+        let sealed = STANDARD.decode(body(&decrypted, "plaintext")).unwrap();
+        let opened =
+            sodiumoxide::crypto::sealedbox::open(&sealed, &public_key, &secret_key).unwrap();
+        assert_eq!(STANDARD.encode(opened), plaintext);
+        assert_eq!(decrypt_as("other", "Y3R4").status, 400);
+        assert_eq!(decrypt_as("app", "b3RoZXI=").status, 400);
+    }
+
+    #[test]
+    fn login_binds_the_nonce_and_the_td_key() {
+        let mut report_data = [7u8; 64];
+        report_data[..32].copy_from_slice(&backend().sha256(&[LOGIN_LABEL, b"nonce"]));
+
+        let public_key = login_public_key(&report_data, b"nonce").unwrap();
+        assert_eq!(public_key.0, [7u8; 32]);
+        assert!(login_public_key(&report_data, b"other").is_err());
+        assert!(login_public_key(&report_data[..48], b"nonce").is_err());
+    }
+
+    #[test]
+    fn logins_are_refused_at_the_cap() {
+        sodiumoxide::init().unwrap();
+        let master = MasterSecret::from_sealing_key(&[8; 16], None).unwrap();
+        let state = ProviderState::new(
+            MasterSecret::from_sealing_key(&[8; 16], None).unwrap(),
+            Box::new(ProviderIdentity::from_master(&master).unwrap()),
+            Settings {
+                policy: Policy::default(),
+                session_idle_timeout: Duration::from_secs(60),
+                padding: ResponsePadding::default(),
+            },
+            Box::new(DcapVerifier::new(CollateralCache::in_memory())),
+            None,
+            None,
+            None,
+        );
+        let vault = Vault {
+            state: Arc::new(state),
+            tokens: Mutex::new(HashMap::new()),
+        };
+        let entry = || Token {
+            derived_key: SecretBytes::new(vec![3; 32]),
+            public_key: PublicKey([7; PUBLICKEYBYTES]),
+            expires: Instant::now() + Duration::from_secs(60),
+        };
+
+        for n in 0..MAX_TOKENS {
+            assert!(vault.issue(format!("hvs.{}", n), entry()).is_ok());
+        }
+        let refused = vault.issue("hvs.more".into(), entry()).unwrap_err();
+        assert_eq!(refused.status, 429);
+
+        // Expired tokens make room again
+        for token in vault.tokens().values_mut() {
+            token.expires = Instant::now();
+        }
+        assert!(vault.issue("hvs.more".into(), entry()).is_ok());
+        assert_eq!(vault.tokens().len(), 1);
+    }
+}
@@ -0,0 +1,120 @@
+mod veraison;
+
+use crate::collateral::CollateralCache;
+use crate::config::VeraisonConfig;
+use crate::error::ProviderError;
+use dcap_qvl::verify::verify;
+use dcap_qvl::QuoteCollateralV3;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info_span, Instrument};
+
+pub use veraison::VeraisonVerifier;
+
+/// Outcome of a successful verification: the quoting platform's TCB level.
+#[derive(Debug, Clone)]
+pub struct VerifiedQuote {
+    pub tcb_status: String,
+    pub advisory_ids: Vec<String>,
+}
+
+pub type VerifyFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<VerifiedQuote, ProviderError>> + Send + 'a>>;
+
+/// Checks that a quote was produced by genuine, not revoked TEE hardware.
+/// Checks on the quote's contents (measurements, report data, PPID) stay with
+/// the caller, so other verifiers (Intel Trust Authority, Azure MAA,
+/// Veraison) only need an implementation here.
+pub trait Verifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn verify<'a>(&'a self, quote: &'a [u8]) -> VerifyFuture<'a>;
+
+    /// Verifies `quote` with collateral the verifier already holds, without
+    /// a round trip to PCS or a remote service.
+    fn verify_cached(&self, _quote: &[u8]) -> Result<VerifiedQuote, ProviderError> {
+        Err(ProviderError::PolicyViolation(format!(
+            "The {} verifier keeps no collateral to verify against",
+            self.name()
+        )))
+    }
+
+    /// The collateral the verifier caches locally, if it does.
+    fn collateral(&self) -> Option<&CollateralCache> {
+        None
+    }
+}
+
+/// Local DCAP verification with dcap-qvl against collateral from Intel PCS.
+pub struct DcapVerifier {
+    collateral: CollateralCache,
+}
+
+impl DcapVerifier {
+    pub fn new(collateral: CollateralCache) -> Self {
+        Self { collateral }
+    }
+}
+
+impl Verifier for DcapVerifier {
+    fn name(&self) -> &'static str {
+        "dcap"
+    }
+
+    fn verify<'a>(&'a self, quote: &'a [u8]) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            let collateral = self
+                .collateral
+                .get(quote)
+                .instrument(info_span!("fetch_collateral"))
+                .await?;
+            dcap_verify(quote, &collateral)
+        })
+    }
+
+    fn verify_cached(&self, quote: &[u8]) -> Result<VerifiedQuote, ProviderError> {
+        let collateral = self.collateral.cached(quote)?.ok_or_else(|| {
+            ProviderError::PolicyViolation("No collateral is cached for the platform".into())
+        })?;
+        dcap_verify(quote, &collateral)
+    }
+
+    fn collateral(&self) -> Option<&CollateralCache> {
+        Some(&self.collateral)
+    }
+}
+
+fn dcap_verify(
+    quote: &[u8],
+    collateral: &QuoteCollateralV3,
+) -> Result<VerifiedQuote, ProviderError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let verified = info_span!("dcap_verify")
+        .in_scope(|| verify(quote, collateral, now))
+        .map_err(|e| ProviderError::QuoteVerificationError(e.into()))?;
+    Ok(VerifiedQuote {
+        tcb_status: verified.status,
+        advisory_ids: verified.advisory_ids,
+    })
+}
+
+/// Selects the verifier configured as `collateral.verifier` (default `dcap`).
+pub fn by_name(
+    name: &str,
+    collateral: CollateralCache,
+    veraison: &VeraisonConfig,
+) -> Result<Box<dyn Verifier>, ProviderError> {
+    match name {
+        "dcap" => Ok(Box::new(DcapVerifier::new(collateral))),
+        "veraison" => Ok(Box::new(VeraisonVerifier::from_config(veraison)?)),
+        other => Err(ProviderError::ConfigError(format!(
+            "Unknown verifier {:?}; this build supports dcap and veraison",
+            other
+        ))),
+    }
+}
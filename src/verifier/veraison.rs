@@ -0,0 +1,332 @@
+//! Verification delegated to a Veraison service through its challenge-response
+//! API: each quote gets a session, and the service answers with an EAR (EAT
+//! Attestation Result), a JWT signed by the service's key. The provider
+//! checks the signature and takes the EAR's status as the quote's TCB level.
+//! Which platforms and TCB levels are acceptable is then the service's
+//! appraisal policy, kept outside the enclave.
+
+use super::{VerifiedQuote, Verifier, VerifyFuture};
+use crate::config::VeraisonConfig;
+use crate::error::ProviderError;
+use base64::engine::general_purpose::{URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use dcap_qvl::quote::{Quote, Report};
+use log::{debug, info};
+use p256::pkcs8::DecodePublicKey;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
+const EAR_PROFILE: &str = "tag:github.com,2023:veraison/ear";
+const SESSION_MEDIA_TYPE: &str = "application/vnd.veraison.challenge-response-session+json";
+/// How often a session still processing the evidence is polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_POLLS: usize = 20;
+
+/// The key the service signs EARs with.
+enum EarKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    Es384(p384::ecdsa::VerifyingKey),
+}
+
+#[derive(Deserialize)]
+struct Session {
+    status: String,
+    result: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EarHeader {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct Ear {
+    eat_profile: String,
+    #[serde(default)]
+    eat_nonce: Option<String>,
+    submods: HashMap<String, Appraisal>,
+}
+
+#[derive(Deserialize)]
+struct Appraisal {
+    #[serde(rename = "ear.status")]
+    status: String,
+}
+
+pub struct VeraisonVerifier {
+    base_url: String,
+    ear_key: EarKey,
+    tdx_media_type: String,
+    sgx_media_type: String,
+    accept_warning: bool,
+    client: reqwest::Client,
+}
+
+impl VeraisonVerifier {
+    pub fn from_config(config: &VeraisonConfig) -> Result<Self, ProviderError> {
+        // Config::validate has checked that both are set
+        let url = config.url.as_deref().unwrap_or_default();
+        let key_path = config
+            .ear_key
+            .as_deref()
+            .unwrap_or(std::path::Path::new(""));
+        let pem = fs::read_to_string(key_path).map_err(|e| {
+            ProviderError::ConfigError(format!("Cannot read EAR key {}: {}", key_path.display(), e))
+        })?;
+        let ear_key = p256::ecdsa::VerifyingKey::from_public_key_pem(&pem)
+            .map(EarKey::Es256)
+            .or_else(|_| p384::ecdsa::VerifyingKey::from_public_key_pem(&pem).map(EarKey::Es384))
+            .map_err(|_| {
+                ProviderError::ConfigError(format!(
+                    "EAR key {} is not a P-256 or P-384 public key",
+                    key_path.display()
+                ))
+            })?;
+        info!("Delegating quote verification to Veraison at {}", url);
+        Ok(Self {
+            base_url: format!("{}/challenge-response/v1/", url.trim_end_matches('/')),
+            ear_key,
+            tdx_media_type: config.tdx_media_type.clone(),
+            sgx_media_type: config.sgx_media_type.clone(),
+            accept_warning: config.accept_warning,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Runs a challenge-response session for `quote` and returns its EAR.
+    async fn appraise(&self, quote: &[u8]) -> Result<(String, String), ProviderError> {
+        let parsed = Quote::parse(quote).map_err(|e| ProviderError::quote_decode("quote", e))?;
+        // The session nonce is the quote's own report data, so a service
+        // that checks the nonce against the evidence finds it there.
+        let (report_data, media_type) = match &parsed.report {
+            Report::SgxEnclave(report) => (&report.report_data[..], &self.sgx_media_type),
+            Report::TD10(report) => (&report.report_data[..], &self.tdx_media_type),
+            Report::TD15(report) => (&report.base.report_data[..], &self.tdx_media_type),
+        };
+        let nonce = URL_SAFE.encode(report_data);
+
+        let fetch_error = |e: reqwest::Error| ProviderError::CollateralError {
+            endpoint: self.base_url.clone(),
+            source: e.into(),
+        };
+        let response = self
+            .client
+            .post(format!("{}newSession?nonce={}", self.base_url, nonce))
+            .header(reqwest::header::ACCEPT, SESSION_MEDIA_TYPE)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(fetch_error)?;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| invalid("Veraison did not name the session"))?;
+        let session_url = reqwest::Url::parse(&self.base_url)
+            .and_then(|base| base.join(location))
+            .map_err(|e| invalid(format!("Invalid session location {}: {}", location, e)))?;
+        debug!("Veraison session {}", session_url);
+
+        let result = self.submit(&session_url, quote, media_type).await;
+        // Sessions expire by themselves, so a failed delete only lingers
+        let _ = self.client.delete(session_url).send().await;
+        Ok((result?, nonce))
+    }
+
+    /// Posts `quote` to the session and waits for the service to appraise it.
+    async fn submit(
+        &self,
+        session_url: &reqwest::Url,
+        quote: &[u8],
+        media_type: &str,
+    ) -> Result<String, ProviderError> {
+        let mut session = self
+            .session(
+                self.client
+                    .post(session_url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, media_type)
+                    .body(quote.to_vec()),
+            )
+            .await?;
+        for _ in 0..MAX_POLLS {
+            if session.status != "processing" {
+                break;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            session = self.session(self.client.get(session_url.clone())).await?;
+        }
+        match (session.status.as_str(), session.result) {
+            ("complete", Some(ear)) => Ok(ear),
+            (status, _) => Err(invalid(format!("Veraison session ended {}", status))),
+        }
+    }
+
+    async fn session(&self, request: reqwest::RequestBuilder) -> Result<Session, ProviderError> {
+        let fetch_error = |e: reqwest::Error| ProviderError::CollateralError {
+            endpoint: self.base_url.clone(),
+            source: e.into(),
+        };
+        let body = request
+            .header(reqwest::header::ACCEPT, SESSION_MEDIA_TYPE)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(fetch_error)?
+            .bytes()
+            .await
+            .map_err(fetch_error)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Checks the EAR's signature and profile, and returns the worst status
+    /// of its appraisals.
+    fn check_ear(&self, ear: &str, nonce: &str) -> Result<String, ProviderError> {
+        let mut parts = ear.trim().split('.');
+        let (Some(header_part), Some(claims_part), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("EAR is not a JWT"));
+        };
+        let decode = |what: &'static str, part: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|e| ProviderError::quote_decode(what, e))
+        };
+        let header: EarHeader = serde_json::from_slice(&decode("EAR header", header_part)?)?;
+        let signature = decode("EAR signature", signature)?;
+        let signed = &ear.trim().as_bytes()[..header_part.len() + 1 + claims_part.len()];
+        let verified = match (&self.ear_key, header.alg.as_str()) {
+            (EarKey::Es256(key), "ES256") => p256::ecdsa::Signature::from_slice(&signature)
+                .and_then(|signature| {
+                    p256::ecdsa::signature::Verifier::verify(key, signed, &signature)
+                }),
+            (EarKey::Es384(key), "ES384") => p384::ecdsa::Signature::from_slice(&signature)
+                .and_then(|signature| {
+                    p384::ecdsa::signature::Verifier::verify(key, signed, &signature)
+                }),
+            (_, alg) => {
+                return Err(invalid(format!(
+                    "EAR algorithm {} does not match the EAR key",
+                    alg
+                )))
+            }
+        };
+        verified.map_err(|_| invalid("EAR signature does not verify"))?;
+
+        let claims: Ear = serde_json::from_slice(&decode("EAR claims", claims_part)?)?;
+        if claims.eat_profile != EAR_PROFILE {
+            return Err(invalid(format!(
+                "EAR profile {} is not supported",
+                claims.eat_profile
+            )));
+        }
+        if let Some(eat_nonce) = &claims.eat_nonce {
+            if eat_nonce.trim_end_matches('=') != nonce.trim_end_matches('=') {
+                return Err(invalid("EAR is for another session"));
+            }
+        }
+        worst_status(
+            claims
+                .submods
+                .values()
+                .map(|appraisal| appraisal.status.as_str()),
+        )
+        .map(str::to_string)
+        .ok_or_else(|| invalid("EAR has no appraisals"))
+    }
+}
+
+impl Verifier for VeraisonVerifier {
+    fn name(&self) -> &'static str {
+        "veraison"
+    }
+
+    fn verify<'a>(&'a self, quote: &'a [u8]) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            let (ear, nonce) = self.appraise(quote).await?;
+            let status = self.check_ear(&ear, &nonce)?;
+            match status.as_str() {
+                "affirming" => {}
+                "warning" if self.accept_warning => {}
+                status => {
+                    return Err(invalid(format!(
+                        "Veraison appraised the quote as {}",
+                        status
+                    )))
+                }
+            }
+            Ok(VerifiedQuote {
+                tcb_status: status,
+                advisory_ids: Vec::new(),
+            })
+        })
+    }
+}
+
+/// The least trustworthy of `statuses`, in EAR's order from `affirming` to
+/// `contraindicated`. Unknown statuses count as `none`.
+fn worst_status<'a>(statuses: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let rank = |status: &str| match status {
+        "affirming" => 0,
+        "warning" => 1,
+        "contraindicated" => 3,
+        _ => 2,
+    };
+    statuses.max_by_key(|status| rank(status))
+}
+
+fn invalid(message: impl Into<String>) -> ProviderError {
+    ProviderError::QuoteVerificationError(message.into().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use serde_json::json;
+
+    #[test]
+    fn ear_must_be_signed_and_affirming() {
+        let key = p256::ecdsa::SigningKey::random(&mut rand::thread_rng());
+        let verifier = VeraisonVerifier {
+            base_url: String::new(),
+            ear_key: EarKey::Es256(*key.verifying_key()),
+            tdx_media_type: String::new(),
+            sgx_media_type: String::new(),
+            accept_warning: false,
+            client: reqwest::Client::new(),
+        };
+        let ear = |status: &str| {
+            let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"ES256"}"#);
+            let claims = URL_SAFE_NO_PAD.encode(
+                json!({
+                    "eat_profile": EAR_PROFILE,
+                    "eat_nonce": "bm9uY2U=",
+                    "submods": {
+                        "platform": { "ear.status": "affirming" },
+                        "td": { "ear.status": status },
+                    },
+                })
+                .to_string(),
+            );
+            let signed = format!("{}.{}", header, claims);
+            let signature: p256::ecdsa::Signature = key.sign(signed.as_bytes());
+            format!(
+                "{}.{}",
+                signed,
+                URL_SAFE_NO_PAD.encode(signature.to_bytes())
+            )
+        };
+
+        assert_eq!(
+            verifier.check_ear(&ear("warning"), "bm9uY2U=").unwrap(),
+            "warning"
+        );
+        assert!(verifier.check_ear(&ear("affirming"), "b3RoZXI=").is_err());
+        let mut tampered = ear("affirming");
+        tampered.replace_range(tampered.len() - 4.., "AAAA");
+        assert!(verifier.check_ear(&tampered, "bm9uY2U=").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Files the provider reads again whenever they change, without a reload:
+//! the measurement allowlist and the revocation list.
+
+use crate::error::ProviderError;
+use log::{error, info};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a read of a file is trusted before its modification time is
+/// looked at again.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Loaded<T> {
+    value: Arc<T>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// What `read` made of the file at `path`, as last read.
+pub struct Watched<T> {
+    path: PathBuf,
+    read: fn(&Path) -> Result<T, ProviderError>,
+    loaded: RwLock<Loaded<T>>,
+}
+
+impl<T> Watched<T> {
+    /// Reads the file at `path` with `read`, which must succeed.
+    pub fn load(
+        path: &Path,
+        read: fn(&Path) -> Result<T, ProviderError>,
+    ) -> Result<Self, ProviderError> {
+        let modified = modified(path);
+        let value = read(path)?;
+        info!("Loaded {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            read,
+            loaded: RwLock::new(Loaded {
+                value: Arc::new(value),
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// The file's contents, read again first if it changed. A file that no
+    /// longer reads is logged and what was read before is kept.
+    pub fn get(&self) -> Arc<T> {
+        {
+            let loaded = self.loaded.read().unwrap_or_else(|p| p.into_inner());
+            if loaded.checked.elapsed() < POLL_INTERVAL {
+                return Arc::clone(&loaded.value);
+            }
+        }
+        let mut loaded = self.loaded.write().unwrap_or_else(|p| p.into_inner());
+        loaded.checked = Instant::now();
+        let modified = modified(&self.path);
+        if modified != loaded.modified {
+            match (self.read)(&self.path) {
+                Ok(value) => {
+                    info!("Reloaded {}", self.path.display());
+                    loaded.value = Arc::new(value);
+                    loaded.modified = modified;
+                }
+                Err(e) => error!(
+                    "Keeping what was read before, {} does not load: {}",
+                    self.path.display(),
+                    e.chain()
+                ),
+            }
+        }
+        Arc::clone(&loaded.value)
+    }
+
+    /// Makes the next [`Watched::get`] look at the file again.
+    #[cfg(test)]
+    pub fn expire(&self) {
+        let mut loaded = self.loaded.write().unwrap();
+        loaded.checked -= POLL_INTERVAL;
+        loaded.modified = None;
+    }
+}
+
+impl<T> fmt::Debug for Watched<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watched").field("path", &self.path).finish()
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,235 @@
+use crate::audit::unix_now;
+use crate::config::{WebhookConfig, WebhookEvent};
+use crate::crypto::{backend, SecretBytes};
+use crate::error::ProviderError;
+use crate::rejections;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Notifications waiting for the sender thread. Beyond this they are dropped
+/// rather than holding up key requests.
+const QUEUE_LEN: usize = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const SIGNATURE_HEADER: &str = "X-Sealing-Provider-Signature";
+
+static WEBHOOKS: OnceLock<Webhooks> = OnceLock::new();
+
+/// What happened, as sent in the `event` field of the payload.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    PolicyViolation {
+        reason: String,
+        mrtd: String,
+    },
+    PpidMismatches {
+        count: usize,
+        window_secs: u64,
+    },
+    EpochRotated {
+        boot_epoch: u64,
+        /// `startup` or `admin`.
+        cause: &'static str,
+    },
+    DevModeStartup,
+    ReleaseThreshold {
+        identity: String,
+        context: String,
+        count: u64,
+    },
+}
+
+impl Event {
+    fn kind(&self) -> WebhookEvent {
+        match self {
+            Event::PolicyViolation { .. } => WebhookEvent::PolicyViolation,
+            Event::PpidMismatches { .. } => WebhookEvent::PpidMismatches,
+            Event::EpochRotated { .. } => WebhookEvent::EpochRotated,
+            Event::DevModeStartup => WebhookEvent::DevModeStartup,
+            Event::ReleaseThreshold { .. } => WebhookEvent::ReleaseThreshold,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    timestamp: u64,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+struct Webhooks {
+    events: Vec<WebhookEvent>,
+    threshold: usize,
+    window: Duration,
+    mismatches: Mutex<VecDeque<Instant>>,
+    queue: SyncSender<Vec<u8>>,
+}
+
+/// Starts sending the events selected in `config` to `config.url`, if set.
+/// With `secret`, each body is signed with HMAC-SHA256 in the
+/// `X-Sealing-Provider-Signature` header so the receiver can tell the
+/// notifications are the provider's.
+pub fn init(config: &WebhookConfig, secret: Option<SecretBytes>) -> Result<(), ProviderError> {
+    let Some(url) = config.url.clone() else {
+        return Ok(());
+    };
+    let (queue, bodies) = mpsc::sync_channel(QUEUE_LEN);
+    thread::Builder::new()
+        .name("webhooks".into())
+        .spawn(move || run(url, secret, bodies))?;
+    info!(
+        "Sending webhooks for {:?} to {}",
+        config.events,
+        config.url.as_deref().unwrap_or_default()
+    );
+
+    let webhooks = Webhooks {
+        events: config.events.clone(),
+        threshold: config.ppid_mismatch_threshold as usize,
+        window: Duration::from_secs(config.ppid_mismatch_window_secs),
+        mismatches: Mutex::new(VecDeque::new()),
+        queue,
+    };
+    WEBHOOKS
+        .set(webhooks)
+        .map_err(|_| ProviderError::ConfigError("Webhooks are already initialized".into()))
+}
+
+/// Sends `event` if webhooks are on and it is one of the selected events.
+/// Never waits for the receiver.
+pub fn notify(event: Event) {
+    let Some(webhooks) = WEBHOOKS.get() else {
+        return;
+    };
+    if !webhooks.events.contains(&event.kind()) {
+        return;
+    }
+    let payload = Payload {
+        timestamp: unix_now(),
+        version: env!("CARGO_PKG_VERSION"),
+        event: &event,
+    };
+    match serde_json::to_vec(&payload) {
+        Ok(body) => {
+            if webhooks.queue.try_send(body).is_err() {
+                warn!(
+                    "Webhook queue full, dropping {:?} notification",
+                    event.kind()
+                );
+            }
+        }
+        Err(e) => warn!("Failed to encode webhook: {}", e),
+    }
+}
+
+/// Notifies about a key request for `quote` refused with `error`, if that is
+/// a policy violation or completes a run of PPID mismatches.
+pub fn rejected(error: &ProviderError, quote: &[u8]) {
+    let Some(webhooks) = WEBHOOKS.get() else {
+        return;
+    };
+    match error {
+        ProviderError::PolicyViolation(reason) => notify(Event::PolicyViolation {
+            reason: reason.clone(),
+            mrtd: rejections::mrtd(quote),
+        }),
+        ProviderError::PPIDMismatch => {
+            if let Some(count) = webhooks.mismatch(Instant::now()) {
+                notify(Event::PpidMismatches {
+                    count,
+                    window_secs: webhooks.window.as_secs(),
+                });
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Webhooks {
+    /// Records a PPID mismatch at `now`. Returns the count once `threshold`
+    /// fall within the window, then starts counting afresh so a sustained
+    /// run alerts once per window rather than once per request.
+    fn mismatch(&self, now: Instant) -> Option<usize> {
+        let mut seen = self.lock();
+        while seen
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= self.window)
+        {
+            seen.pop_front();
+        }
+        seen.push_back(now);
+        if seen.len() < self.threshold {
+            return None;
+        }
+        let count = seen.len();
+        seen.clear();
+        Some(count)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Instant>> {
+        self.mismatches
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Posts queued bodies until the provider exits. Failed deliveries are
+/// logged and not retried; the audit log and metrics remain the record.
+fn run(url: String, secret: Option<SecretBytes>, bodies: Receiver<Vec<u8>>) {
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Cannot create webhook client, webhooks are off: {}", e);
+            return;
+        }
+    };
+
+    for body in bodies {
+        let mut request = client.post(&url).header("Content-Type", "application/json");
+        if let Some(secret) = &secret {
+            let mac = backend().hmac_sha256(secret.expose(), &[&body]);
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", hex::encode(mac)));
+        }
+        match request.body(body).send() {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!("Webhook to {} returned {}", url, response.status()),
+            Err(e) => warn!("Webhook to {} failed: {}", url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppid_mismatches_alert_once_per_run_within_the_window() {
+        let (queue, _bodies) = mpsc::sync_channel(1);
+        let webhooks = Webhooks {
+            events: Vec::new(),
+            threshold: 3,
+            window: Duration::from_secs(60),
+            mismatches: Mutex::new(VecDeque::new()),
+            queue,
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(webhooks.mismatch(at(0)), None);
+        assert_eq!(webhooks.mismatch(at(10)), None);
+        // The first one has aged out by now
+        assert_eq!(webhooks.mismatch(at(65)), None);
+        assert_eq!(webhooks.mismatch(at(66)), Some(3));
+        assert_eq!(webhooks.mismatch(at(67)), None);
+    }
+}
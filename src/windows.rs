@@ -0,0 +1,190 @@
+//! Time windows for key release, for change-controlled environments: keys
+//! are only released during the allowed windows, say maintenance hours, and
+//! never during the denied ones. Times are UTC. The clock may be off by up
+//! to `clock_skew_secs`, so a release is only permitted when every instant
+//! within that margin of the provider's time is.
+
+use crate::error::ProviderError;
+use serde::{Deserialize, Serialize};
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const MINUTES_PER_DAY: u64 = 24 * 60;
+/// Beyond this the margin is a configuration mistake, not skew.
+const MAX_CLOCK_SKEW_SECS: u64 = 3600;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReleaseWindows {
+    /// Windows when keys may be released. Empty allows any time not denied.
+    pub allow: Vec<Window>,
+    /// Windows when keys are never released, even within an allowed one.
+    pub deny: Vec<Window>,
+    /// How far the provider's clock may be off.
+    pub clock_skew_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    /// `mon` to `sun`, the days the window starts on. Empty is every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// `HH:MM`. A window whose end is before its start runs past midnight,
+    /// and one whose end equals its start lasts the whole day.
+    pub start: String,
+    pub end: String,
+    #[serde(skip)]
+    parsed: Option<(u8, u64, u64)>,
+}
+
+impl Window {
+    /// Whether the minute `minute` since the epoch falls in the window.
+    fn contains(&self, minute: u64) -> bool {
+        let Some((days, start, end)) = self.parsed else {
+            return false;
+        };
+        let day = minute / MINUTES_PER_DAY;
+        let time = minute % MINUTES_PER_DAY;
+        let starts_on = |day: u64| days & (1 << weekday(day)) != 0;
+        if start < end {
+            starts_on(day) && (start..end).contains(&time)
+        } else {
+            (starts_on(day) && time >= start) || (day > 0 && starts_on(day - 1) && time < end)
+        }
+    }
+
+    fn describe(&self) -> String {
+        let days = if self.days.is_empty() {
+            String::new()
+        } else {
+            format!(" on {}", self.days.join(","))
+        };
+        format!("{}-{}{}", self.start, self.end, days)
+    }
+}
+
+/// Monday is 0. The epoch was a Thursday.
+fn weekday(day: u64) -> u64 {
+    (day + 3) % 7
+}
+
+impl ReleaseWindows {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Checks the windows as the policy file gives them.
+    pub fn validate(&mut self) -> Result<(), ProviderError> {
+        if self.clock_skew_secs > MAX_CLOCK_SKEW_SECS {
+            return Err(ProviderError::ConfigError(format!(
+                "release_windows.clock_skew_secs must be at most {}",
+                MAX_CLOCK_SKEW_SECS
+            )));
+        }
+        for window in self.allow.iter_mut().chain(self.deny.iter_mut()) {
+            let mut days = 0u8;
+            for day in &window.days {
+                let index = DAYS
+                    .iter()
+                    .position(|name| name.eq_ignore_ascii_case(day))
+                    .ok_or_else(|| invalid(format!("Unknown day {:?}", day)))?;
+                days |= 1 << index;
+            }
+            if window.days.is_empty() {
+                days = 0x7f;
+            }
+            window.parsed = Some((days, parse_time(&window.start)?, parse_time(&window.end)?));
+        }
+        Ok(())
+    }
+
+    /// Checks that a key may be released at `now` (Unix seconds), whichever
+    /// instant within the clock skew the real time is.
+    pub fn check(&self, now: u64) -> Result<(), ProviderError> {
+        let earliest = now.saturating_sub(self.clock_skew_secs);
+        let latest = now + self.clock_skew_secs;
+        // Membership only changes at minute boundaries, so the minutes the
+        // margin touches are all there is to check
+        for minute in earliest / 60..=latest / 60 {
+            if let Some(window) = self.deny.iter().find(|window| window.contains(minute)) {
+                return Err(ProviderError::PolicyViolation(format!(
+                    "Key release is denied during {} UTC",
+                    window.describe()
+                )));
+            }
+            if !self.allow.is_empty() && !self.allow.iter().any(|window| window.contains(minute)) {
+                return Err(ProviderError::PolicyViolation(format!(
+                    "Key release is only allowed during {} UTC{}",
+                    self.allow
+                        .iter()
+                        .map(Window::describe)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    if self.clock_skew_secs > 0 {
+                        format!(", {} seconds clear of the edges", self.clock_skew_secs)
+                    } else {
+                        String::new()
+                    }
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Minutes since midnight of `HH:MM`.
+fn parse_time(time: &str) -> Result<u64, ProviderError> {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        let hours: u64 = hours.parse().ok()?;
+        let minutes: u64 = minutes.parse().ok()?;
+        (hours < 24 && minutes < 60 && time.len() == 5).then_some(hours * 60 + minutes)
+    });
+    parsed.ok_or_else(|| invalid(format!("Time {:?} is not HH:MM", time)))
+}
+
+fn invalid(message: String) -> ProviderError {
+    ProviderError::ConfigError(format!("Invalid release window: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], start: &str, end: &str) -> Window {
+        Window {
+            days: days.iter().map(|day| day.to_string()).collect(),
+            start: start.into(),
+            end: end.into(),
+            parsed: None,
+        }
+    }
+
+    #[test]
+    fn releases_follow_the_windows_with_a_skew_margin() {
+        // Saturday 2026-10-17 00:00 UTC
+        let saturday = 1_792_195_200;
+        let mut windows = ReleaseWindows {
+            allow: vec![window(&["fri", "sat"], "22:00", "06:00")],
+            deny: vec![window(&[], "00:00", "04:00")],
+            clock_skew_secs: 120,
+        };
+        windows.validate().unwrap();
+        let at = |hours: u64, minutes: u64| saturday + hours * 3600 + minutes * 60;
+
+        assert!(windows.check(at(0, 0) - 3600).is_ok());
+        assert!(windows.check(at(5, 0)).is_ok());
+        assert!(windows.check(at(2, 0)).is_err());
+        assert!(windows.check(at(7, 0)).is_err());
+        // Too close to the end of the denied window
+        assert!(windows.check(at(4, 1)).is_err());
+        assert!(windows.check(at(4, 3)).is_ok());
+        // Sunday night is not in the allowed window
+        assert!(windows.check(at(24 + 23, 0)).is_err());
+        assert!(parse_time("24:00").is_err());
+        let mut unknown_day = ReleaseWindows {
+            allow: vec![window(&["someday"], "00:00", "01:00")],
+            ..ReleaseWindows::default()
+        };
+        assert!(unknown_day.validate().is_err());
+    }
+}
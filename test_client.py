@@ -4,6 +4,7 @@ import struct
 import sys
 import hashlib
 import binascii
+import os
 
 class SGXQuoteConstants:
     HEADER_SIZE = 48
@@ -33,32 +34,44 @@ def extract_report_data(quote):
     try:
         # Report data is 64 bytes at offset 320 in the report body
         # Report body starts after the quote header
-        report_data = quote[SGXQuoteConstants.REPORT_DATA_OFFSET:
-                          SGXQuoteConstants.REPORT_DATA_OFFSET + SGXQuoteConstants.REPORT_DATA_SIZE]
-        
-        # First 32 bytes contain our hash
-        return report_data[:32]
+        return quote[SGXQuoteConstants.REPORT_DATA_OFFSET:
+                     SGXQuoteConstants.REPORT_DATA_OFFSET + SGXQuoteConstants.REPORT_DATA_SIZE]
     except Exception as e:
         print(f"Error extracting report data: {e}")
         return None
 
-def verify_response(encrypted_key, provider_quote):
-    """Verify the response by checking hash in quote's report data"""
+def calculate_metadata_hash(fields):
+    """Hash response metadata the same way the provider binds it into its quote"""
+    hasher = hashlib.sha256()
+    hasher.update(b"gramine-sealing-key-provider/response-metadata/v1")
+    for name, value in fields:
+        if isinstance(value, list):
+            value = bytes(value)
+        hasher.update(bytes([len(name)]))
+        hasher.update(name.encode())
+        hasher.update(struct.pack('>I', len(value)))
+        hasher.update(value)
+    return hasher.digest()
+
+def verify_response(encrypted_key, provider_quote, metadata_fields):
+    """Verify the response by checking hashes in quote's report data"""
     try:
         # Calculate hash of encrypted key
         calculated_hash = calculate_hash(encrypted_key)
         hex_print("Calculated hash of encrypted key", calculated_hash)
+        calculated_metadata_hash = calculate_metadata_hash(metadata_fields)
+        hex_print("Calculated hash of response metadata", calculated_metadata_hash)
 
         # Extract report data from quote
-        stored_hash = extract_report_data(provider_quote)
-        if stored_hash is None:
+        report_data = extract_report_data(provider_quote)
+        if report_data is None:
             print("Failed to extract report data from quote")
             return False
-            
-        hex_print("Hash from quote's report data", stored_hash)
+
+        hex_print("Report data from quote", report_data)
 
         # Compare hashes
-        if calculated_hash == stored_hash:
+        if calculated_hash + calculated_metadata_hash == report_data:
             print("\nHash verification: SUCCESS")
             return True
         else:
@@ -79,8 +92,10 @@ def send_quote(host='localhost', port=3443, quote_path='quotes/tdxQuote.txt'):
     hex_print("Input quote", quote_data)
     
     # Create request
+    nonce = os.urandom(32)
     request = {
-        'quote': list(quote_data)  # Convert bytes to list for JSON serialization
+        'quote': list(quote_data),  # Convert bytes to list for JSON serialization
+        'nonce': list(nonce),
     }
     
     # Serialize request
@@ -127,8 +142,16 @@ def send_quote(host='localhost', port=3443, quote_path='quotes/tdxQuote.txt'):
             hex_print("Encrypted key", encrypted_key)
             hex_print("Provider quote", provider_quote)
             
+            metadata_fields = [
+                ('nonce', nonce),
+                ('suite', resp_data['suite'].encode()),
+                ('key_confirmation', resp_data['key_confirmation']),
+            ]
+            for recipient_key in resp_data.get('recipient_keys', []):
+                metadata_fields.append(('recipient_key', recipient_key))
+
             print("\nVerifying response...")
-            verify_response(encrypted_key, provider_quote)
+            verify_response(encrypted_key, provider_quote, metadata_fields)
             
         except json.JSONDecodeError as e:
             print(f"\nJSON Parse Error: {e}")